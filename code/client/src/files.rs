@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+/// A file that is being streamed by the executor
+#[derive(Default)]
+struct Incoming {
+    next_seq: u64,
+    content: String,
+    /// a chunk was missing or out of order
+    corrupted: bool,
+}
+
+/// A file that was received completely
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReceivedFile {
+    pub path: String,
+    pub content: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileError {
+    /// a chunk was missing or arrived out of order
+    MissingChunk,
+    ChecksumMismatch,
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingChunk => f.write_str("a chunk was lost"),
+            Self::ChecksumMismatch => f.write_str("checksum mismatch"),
+        }
+    }
+}
+
+/// Reassembles files streamed with `FileChunk` packets.
+#[derive(Default)]
+pub struct Files {
+    incoming: HashMap<String, Incoming>,
+}
+
+impl Files {
+    /// Add a chunk. Returns the content received so far, so it can be rendered.
+    pub fn chunk(&mut self, path: &str, seq: u64, content: &str) -> &str {
+        let incoming = self.incoming.entry(path.to_string()).or_default();
+
+        if seq == incoming.next_seq {
+            incoming.content.push_str(content);
+            incoming.next_seq += 1;
+        } else {
+            incoming.corrupted = true;
+        }
+
+        &incoming.content
+    }
+
+    /// The file was written completely by the executor.
+    ///
+    /// # Errors
+    /// If the reassembled content does not match the checksum.
+    pub fn written(&mut self, path: &str, checksum: &str) -> Result<ReceivedFile, FileError> {
+        let incoming = self.incoming.remove(path).unwrap_or_default();
+
+        if incoming.corrupted {
+            return Err(FileError::MissingChunk);
+        }
+
+        if protocol::checksum(&incoming.content) != checksum {
+            return Err(FileError::ChecksumMismatch);
+        }
+
+        Ok(ReceivedFile {
+            path: path.to_string(),
+            content: incoming.content,
+        })
+    }
+
+    /// Writing the file failed on the executor.
+    pub fn failed(&mut self, path: &str) {
+        self.incoming.remove(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileError, Files};
+
+    #[test]
+    fn test_reassemble() {
+        let mut files = Files::default();
+
+        assert_eq!(files.chunk("main.rs", 0, "fn main() {\n"), "fn main() {\n");
+        assert_eq!(files.chunk("main.rs", 1, "}"), "fn main() {\n}");
+
+        let checksum = protocol::checksum("fn main() {\n}");
+        let file = files.written("main.rs", &checksum).unwrap();
+        assert_eq!(file.content, "fn main() {\n}");
+    }
+
+    #[test]
+    fn test_missing_chunk() {
+        let mut files = Files::default();
+
+        files.chunk("main.rs", 0, "a\n");
+        files.chunk("main.rs", 2, "c\n");
+
+        let checksum = protocol::checksum("a\nb\nc\n");
+        assert_eq!(
+            files.written("main.rs", &checksum),
+            Err(FileError::MissingChunk)
+        );
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut files = Files::default();
+
+        files.chunk("main.rs", 0, "a\n");
+
+        assert_eq!(
+            files.written("main.rs", &protocol::checksum("b\n")),
+            Err(FileError::ChecksumMismatch)
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! A typed client for the executor protocol.
+//!
+//! [`connect`] returns raw packet channels for frontends that render every packet themselves,
+//! while [`Client`] reassembles streamed questions and files into [`Event`]s.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use collective_client::{Client, Event, Transport};
+//!
+//! let mut client = Client::connect(Transport::WebSocket {
+//!     address: "ws://127.0.0.1:8080".to_string(),
+//!     token: None,
+//! })
+//! .await?;
+//!
+//! client.instruct("Create a calculator")?;
+//!
+//! while let Some(event) = client.next_event().await {
+//!     if let Event::Question(question) = event {
+//!         println!("{question}");
+//!         client.answer("Rust")?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+
+use anyhow::Context;
+use futures::Stream;
+use protocol::{client, server::Server, ClientPacket, Packet, ServerPacket, SessionId};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    files::{FileError, Files, ReceivedFile},
+    streams::{StreamError, Streams},
+};
+
+pub mod files;
+mod session;
+mod stdio;
+pub mod streams;
+mod websocket;
+
+/// How to reach the executor
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// Connect to a websocket server, reconnecting and resuming the session when it drops. With
+    /// `token` if the executor was started with `--token`.
+    WebSocket {
+        address: String,
+        token: Option<String>,
+    },
+    /// Spawn the executor and exchange one JSON packet per line over its stdin and stdout.
+    Stdio { program: String, args: Vec<String> },
+}
+
+/// (tx, rx) for sending packets to and receiving packets from the executor
+pub type Channels = (
+    UnboundedSender<ClientPacket>,
+    UnboundedReceiver<ServerPacket>,
+);
+
+/// Connect to the executor, returning [`Channels`] for sending and receiving packets.
+///
+/// The connection stops once `cancel` is cancelled or `tx` is dropped. `rx` is closed when the
+/// connection is lost for good.
+///
+/// # Errors
+/// If the executor cannot be reached.
+pub async fn connect(transport: Transport, cancel: CancellationToken) -> anyhow::Result<Channels> {
+    match transport {
+        Transport::WebSocket { address, token } => websocket::connect(address, token, cancel).await,
+        Transport::Stdio { program, args } => stdio::connect(&program, &args, cancel),
+    }
+}
+
+/// What happened on the executor, with streams reassembled
+#[derive(Debug)]
+pub enum Event {
+    /// A part of the question that is currently being streamed
+    QuestionDelta(String),
+    /// A question was streamed completely
+    Question(String),
+    /// A streamed file was written and its content matches the checksum
+    File(ReceivedFile),
+    /// A streamed file could not be reassembled
+    FileError { path: String, error: FileError },
+    /// A frame of a question was duplicated or after the end of its stream, it was ignored
+    StreamError(StreamError),
+    /// Any other packet
+    Packet(Server),
+}
+
+/// Turns packets into [`Event`]s.
+#[derive(Default)]
+struct Assembler {
+    session: Option<SessionId>,
+    questions: Streams,
+    files: Files,
+}
+
+impl Assembler {
+    fn feed(&mut self, packet: Server) -> Vec<Event> {
+        match packet {
+            Server::Question { frame } => {
+                let delivered = match self.questions.frame(frame) {
+                    Ok(delivered) => delivered,
+                    Err(e) => return vec![Event::StreamError(e)],
+                };
+
+                let mut events = Vec::new();
+                if !delivered.text.is_empty() {
+                    events.push(Event::QuestionDelta(delivered.text));
+                }
+                if let Some(question) = delivered.complete {
+                    events.push(Event::Question(question.trim().to_string()));
+                }
+                events
+            }
+            Server::FileChunk { path, seq, content } => {
+                self.files.chunk(&path, seq, &content);
+                Vec::new()
+            }
+            Server::FileWritten { path, checksum } => {
+                let event = match self.files.written(&path, &checksum) {
+                    Ok(file) => Event::File(file),
+                    Err(error) => Event::FileError { path, error },
+                };
+                vec![event]
+            }
+            packet => {
+                match &packet {
+                    // stream ids start over with every connection
+                    Server::SessionCreated { id, .. } => {
+                        self.session = Some(*id);
+                        self.questions = Streams::default();
+                    }
+                    Server::Cancelled => self.questions.abandon(),
+                    Server::FileFailed { path, .. } => self.files.failed(path),
+                    _ => {}
+                }
+                vec![Event::Packet(packet)]
+            }
+        }
+    }
+}
+
+pub struct Client {
+    tx: UnboundedSender<ClientPacket>,
+    rx: UnboundedReceiver<ServerPacket>,
+    assembler: Assembler,
+    /// events that were assembled but not returned yet
+    events: VecDeque<Event>,
+    cancel: CancellationToken,
+}
+
+impl Client {
+    /// Connect to the executor and wait until it assigned a session.
+    ///
+    /// # Errors
+    /// If the executor cannot be reached or closes the connection before assigning a session.
+    pub async fn connect(transport: Transport) -> anyhow::Result<Self> {
+        let cancel = CancellationToken::new();
+        let (tx, rx) = connect(transport, cancel.clone()).await?;
+
+        let mut client = Self::from_channels(tx, rx);
+        client.cancel = cancel;
+
+        // the executor sends the session as the first packet
+        while client.assembler.session.is_none() {
+            let packet = client
+                .rx
+                .recv()
+                .await
+                .context("The executor closed the connection")?;
+            let events = client.assembler.feed(packet.data);
+            client.events.extend(events);
+        }
+
+        Ok(client)
+    }
+
+    /// Use channels that are already connected to an executor, e.g. one launched in-process.
+    #[must_use]
+    pub fn from_channels(
+        tx: UnboundedSender<ClientPacket>,
+        rx: UnboundedReceiver<ServerPacket>,
+    ) -> Self {
+        Self {
+            tx,
+            rx,
+            assembler: Assembler::default(),
+            events: VecDeque::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// The session assigned by the executor
+    #[must_use]
+    pub fn session(&self) -> Option<SessionId> {
+        self.assembler.session
+    }
+
+    /// Send any packet.
+    ///
+    /// # Errors
+    /// If the connection was closed.
+    pub fn send(&self, packet: impl Into<client::Client>) -> anyhow::Result<()> {
+        self.tx
+            .send(Packet::client(packet))
+            .ok()
+            .context("The connection was closed")
+    }
+
+    /// Start a question-answer session.
+    ///
+    /// # Errors
+    /// If the connection was closed.
+    pub fn instruct(&self, instruction: impl Into<String>) -> anyhow::Result<()> {
+        self.send(client::Instruction {
+            instruction: instruction.into(),
+        })
+    }
+
+    /// Answer the last question.
+    ///
+    /// # Errors
+    /// If the connection was closed.
+    pub fn answer(&self, answer: impl Into<String>) -> anyhow::Result<()> {
+        self.send(client::Answer {
+            answer: answer.into(),
+        })
+    }
+
+    /// Answer the question `id` of a [`Server::Questions`].
+    ///
+    /// # Errors
+    /// If the connection was closed.
+    pub fn answer_to(&self, id: u64, answer: impl Into<String>) -> anyhow::Result<()> {
+        self.send(client::AnswerTo {
+            id,
+            answer: answer.into(),
+        })
+    }
+
+    /// The next event, or `None` once the connection was closed.
+    pub async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+
+            let packet = self.rx.recv().await?;
+            let events = self.assembler.feed(packet.data);
+            self.events.extend(events);
+        }
+    }
+
+    /// All events until the connection is closed.
+    pub fn events(&mut self) -> impl Stream<Item = Event> + '_ {
+        futures::stream::unfold(self, |client| async move {
+            let event = client.next_event().await?;
+            Some((event, client))
+        })
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use protocol::{server::Server, Packet, StreamFrame};
+
+    use crate::{Client, Event};
+
+    fn question(seq: u64, payload: &str, end: bool) -> Server {
+        Server::Question {
+            frame: StreamFrame {
+                id: 1,
+                seq,
+                payload: payload.to_string(),
+                end,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events() -> anyhow::Result<()> {
+        let (tx, _executor_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (executor_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut client = Client::from_channels(tx, rx);
+
+        let content = "fn main() {}\n";
+        let packets = vec![
+            Server::SessionCreated {
+                id: uuid::Uuid::new_v4(),
+                key: String::new(),
+            },
+            question(0, "What", false),
+            question(1, " language?", false),
+            question(2, "", true),
+            Server::FileChunk {
+                path: "main.rs".to_string(),
+                seq: 0,
+                content: content.to_string(),
+            },
+            Server::FileWritten {
+                path: "main.rs".to_string(),
+                checksum: protocol::checksum(content),
+            },
+        ];
+
+        for packet in packets {
+            executor_tx.send(Packet::new(packet))?;
+        }
+        drop(executor_tx);
+
+        let events: Vec<_> = client.events().collect().await;
+
+        assert!(matches!(&events[..], [
+            Event::Packet(Server::SessionCreated { .. }),
+            Event::QuestionDelta(_),
+            Event::QuestionDelta(_),
+            Event::Question(question),
+            Event::File(file),
+        ] if question == "What language?" && file.content == content));
+
+        assert!(client.session().is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use protocol::{
+    client::{self, Client},
+    server::Server,
+    QuestionItem, SessionId,
+};
+
+use crate::streams::Streams;
+/// Everything the frontend knows about the current question-answer session.
+///
+/// This is what gets replayed to the executor (via [`client::Resume`]) when the websocket
+/// drops and we reconnect.
+#[derive(Default)]
+pub struct Session {
+    /// assigned by the executor with a [`Server::SessionCreated`]
+    id: Option<SessionId>,
+    /// needed to resume the session, from the same [`Server::SessionCreated`]
+    key: String,
+    instruction: Option<String>,
+    questions: Vec<String>,
+    answers: Vec<String>,
+
+    /// the questions that are being streamed
+    streams: Streams,
+    /// questions asked at once that are not all answered yet, with their answers
+    batch: Vec<(QuestionItem, Option<String>)>,
+    /// the sessions instructions replaced, the last one is back once its instruction is reverted
+    replaced: Vec<Replaced>,
+}
+
+/// A session as it was before an instruction replaced it
+#[derive(Default)]
+struct Replaced {
+    instruction: Option<String>,
+    questions: Vec<String>,
+    answers: Vec<String>,
+}
+
+impl Session {
+    /// Record a packet that was successfully sent to the executor.
+    pub fn sent(&mut self, packet: &Client) {
+        match packet {
+            Client::Instruction { instruction } => {
+                let mut replaced = std::mem::take(&mut self.replaced);
+                replaced.push(Replaced {
+                    instruction: self.instruction.take(),
+                    questions: std::mem::take(&mut self.questions),
+                    answers: std::mem::take(&mut self.answers),
+                });
+                *self = Self {
+                    id: self.id,
+                    key: std::mem::take(&mut self.key),
+                    instruction: Some(instruction.clone()),
+                    replaced,
+                    ..Self::default()
+                };
+            }
+            // the executor gives an answer without an id to the first open question of a batch
+            Client::Answer { answer } => {
+                let open = self.batch.iter().find(|(_, answer)| answer.is_none());
+                match open.map(|(item, _)| item.id) {
+                    Some(id) => self.answer_batch(id, answer),
+                    None => self.answers.push(answer.clone()),
+                }
+            }
+            Client::AnswerTo { id, answer } => self.answer_batch(*id, answer),
+            // a question that was streamed completely is discarded by the executor
+            Client::Regenerate if !self.batch.is_empty() => self.batch.clear(),
+            Client::Regenerate => {
+                if self.questions.len() > self.answers.len() {
+                    self.questions.pop();
+                }
+            }
+            Client::Ping
+            | Client::Latency
+            | Client::ListSessions
+            | Client::CloneRepo { .. }
+            | Client::SetWorkspace { .. }
+            | Client::ResolveConflict { .. }
+            | Client::VetoSource { .. }
+            | Client::RevertLast
+            | Client::ExecuteSteps { .. }
+            | Client::Resume { .. }
+            | Client::Cancel
+            | Client::Execute
+            | Client::ListMemory
+            | Client::Remember { .. }
+            | Client::Forget { .. }
+            | Client::Confirm { .. } => {}
+        }
+    }
+
+    /// Answer the question `id` of the batch. Once every question is answered, they become
+    /// questions and answers like streamed ones, as on the executor.
+    fn answer_batch(&mut self, id: u64, answer: &str) {
+        let Some((_, given)) = self.batch.iter_mut().find(|(item, _)| item.id == id) else {
+            return;
+        };
+        *given = Some(answer.to_string());
+
+        if self.batch.iter().all(|(_, answer)| answer.is_some()) {
+            for (item, answer) in std::mem::take(&mut self.batch) {
+                self.questions.push(item.question);
+                self.answers.extend(answer);
+            }
+        }
+    }
+
+    /// Record a packet that was received from the executor.
+    pub fn received(&mut self, packet: &Server) {
+        match packet {
+            Server::Question { frame } => {
+                // frames that cannot be reassembled are reported by the frontend
+                if let Ok(delivered) = self.streams.frame(frame.clone()) {
+                    self.questions.extend(delivered.complete);
+                }
+            }
+            // the executor reverts the packet that triggered the question
+            Server::Cancelled => {
+                self.streams.abandon();
+                if self.questions.is_empty() {
+                    self.instruction = None;
+                } else {
+                    self.answers.pop();
+                }
+            }
+            // stream ids start over with every connection
+            Server::SessionCreated { id, key } => {
+                self.id = Some(*id);
+                self.key.clone_from(key);
+                self.streams = Streams::default();
+            }
+            Server::Questions { items } => {
+                self.batch = items.iter().map(|item| (item.clone(), None)).collect();
+            }
+            Server::Reverted {
+                instruction: true, ..
+            } => {
+                let previous = self.replaced.pop().unwrap_or_default();
+                self.streams.abandon();
+                self.batch.clear();
+                self.instruction = previous.instruction;
+                self.questions = previous.questions;
+                self.answers = previous.answers;
+            }
+            Server::Rejected { .. }
+            | Server::FileChunk { .. }
+            | Server::FileWritten { .. }
+            | Server::FileFailed { .. }
+            | Server::StepStarted { .. }
+            | Server::StepOutput { .. }
+            | Server::StepDiagnosed { .. }
+            | Server::StepFinished { .. }
+            | Server::ExecutionFinished { .. }
+            | Server::Memory { .. }
+            | Server::ConfirmCommand { .. }
+            | Server::WorkspaceInfo { .. }
+            | Server::ShuttingDown { .. }
+            | Server::Notice { .. }
+            | Server::Latency { .. }
+            | Server::Sessions { .. }
+            | Server::Cloned { .. }
+            | Server::WorkspaceReady { .. }
+            | Server::CompileChecked { .. }
+            | Server::Usage { .. }
+            | Server::FileConflict { .. }
+            | Server::SourcePreview { .. }
+            | Server::SecretAnswer
+            | Server::QuestionAlternatives { .. }
+            | Server::FollowUp
+            | Server::DocsFetched { .. }
+            | Server::Reverted { .. }
+            | Server::AnswerFormat { .. }
+            | Server::PlanChunk { .. }
+            | Server::Status { .. }
+            | Server::Error { .. }
+            | Server::Pong => {}
+        }
+    }
+
+    /// The packet to send after reconnecting, if there is a session to resume.
+    ///
+    /// A partially streamed question is dropped; the executor generates it again.
+    ///
+    /// A batch that is not answered completely is dropped as well; the executor asks it again.
+    pub fn resume(&mut self) -> Option<client::Resume> {
+        self.streams.abandon();
+        self.batch.clear();
+        // a new connection has nothing to revert
+        self.replaced.clear();
+
+        let instruction = self.instruction.clone()?;
+
+        Some(client::Resume {
+            // the executor does not know a nil session and rebuilds it from the replay
+            session: self.id.unwrap_or_default(),
+            key: self.key.clone(),
+            instruction,
+            questions: self.questions.clone(),
+            answers: self.answers.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{client::Client, server::Server, QuestionItem, StreamFrame};
+
+    use super::Session;
+
+    fn question(id: u64, seq: u64, payload: &str, end: bool) -> Server {
+        Server::Question {
+            frame: StreamFrame {
+                id,
+                seq,
+                payload: payload.to_string(),
+                end,
+            },
+        }
+    }
+
+    #[test]
+    fn test_no_session() {
+        let mut session = Session::default();
+        assert!(session.resume().is_none());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut session = Session::default();
+
+        session.sent(&Client::Instruction {
+            instruction: "Create a calculator".to_string(),
+        });
+        session.received(&question(1, 0, "What", false));
+        session.received(&question(1, 1, " language?", false));
+        session.received(&question(1, 2, "", true));
+        session.sent(&Client::Answer {
+            answer: "Rust".to_string(),
+        });
+
+        // dropped while streaming the second question
+        session.received(&question(2, 0, "Should", false));
+
+        let resume = session.resume().unwrap();
+        assert_eq!(resume.instruction, "Create a calculator");
+        assert_eq!(resume.questions, vec!["What language?"]);
+        assert_eq!(resume.answers, vec!["Rust"]);
+    }
+
+    #[test]
+    fn test_new_instruction_resets() {
+        let mut session = Session::default();
+        let id = uuid::Uuid::new_v4();
+
+        session.received(&Server::SessionCreated {
+            id,
+            key: "key".to_string(),
+        });
+        session.sent(&Client::Instruction {
+            instruction: "first".to_string(),
+        });
+        session.received(&question(1, 0, "?", true));
+
+        session.sent(&Client::Instruction {
+            instruction: "second".to_string(),
+        });
+        let resume = session.resume().unwrap();
+
+        assert_eq!(resume.session, id);
+        assert_eq!(resume.key, "key");
+        assert_eq!(resume.instruction, "second");
+        assert!(resume.questions.is_empty());
+    }
+
+    #[test]
+    fn test_revert_instruction() {
+        let mut session = Session::default();
+
+        session.sent(&Client::Instruction {
+            instruction: "Create a calculator".to_string(),
+        });
+        session.received(&question(1, 0, "What language?", true));
+        session.sent(&Client::Answer {
+            answer: "Rust".to_string(),
+        });
+        session.sent(&Client::Instruction {
+            instruction: "Delete everything".to_string(),
+        });
+        session.received(&question(2, 0, "Are", false));
+
+        session.received(&Server::Reverted {
+            instruction: true,
+            summary: String::new(),
+        });
+        assert_eq!(session.instruction.as_deref(), Some("Create a calculator"));
+        assert_eq!(session.questions, vec!["What language?"]);
+        assert_eq!(session.answers, vec!["Rust"]);
+
+        // reverting the first instruction ends the session
+        session.sent(&Client::RevertLast);
+        session.received(&Server::Reverted {
+            instruction: true,
+            summary: String::new(),
+        });
+        assert!(session.resume().is_none());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut session = Session::default();
+
+        session.sent(&Client::Instruction {
+            instruction: "Create a calculator".to_string(),
+        });
+        session.received(&question(1, 0, "What language?", true));
+        session.sent(&Client::Answer {
+            answer: "Rust".to_string(),
+        });
+        session.received(&question(2, 0, "Should", false));
+        session.received(&Server::Cancelled);
+
+        let resume = session.resume().unwrap();
+        assert_eq!(resume.questions, vec!["What language?"]);
+        assert!(resume.answers.is_empty());
+
+        // cancelling the first question ends the session
+        session.sent(&Client::Instruction {
+            instruction: "Create a calculator".to_string(),
+        });
+        session.received(&Server::Cancelled);
+        assert!(session.resume().is_none());
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut session = Session::default();
+        let item = |id, question: &str| QuestionItem {
+            id,
+            question: question.to_string(),
+            validator: None,
+        };
+
+        session.sent(&Client::Instruction {
+            instruction: "Create a web server".to_string(),
+        });
+        session.received(&Server::Questions {
+            items: vec![item(0, "Which language?"), item(1, "Which port?")],
+        });
+        session.sent(&Client::AnswerTo {
+            id: 1,
+            answer: "8080".to_string(),
+        });
+
+        // a partially answered batch is asked again
+        assert!(session.resume().unwrap().questions.is_empty());
+
+        session.received(&Server::Questions {
+            items: vec![item(0, "Which language?"), item(1, "Which port?")],
+        });
+        session.sent(&Client::AnswerTo {
+            id: 1,
+            answer: "8080".to_string(),
+        });
+        session.sent(&Client::Answer {
+            answer: "Rust".to_string(),
+        });
+
+        let resume = session.resume().unwrap();
+        assert_eq!(resume.questions, vec!["Which language?", "Which port?"]);
+        assert_eq!(resume.answers, vec!["Rust", "8080"]);
+    }
+}
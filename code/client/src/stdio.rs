@@ -0,0 +1,100 @@
+use std::process::Stdio;
+
+use anyhow::Context;
+use protocol::{client::Client, server::Server, Packet};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::Channels;
+
+/// Spawn `program` and exchange packets as one JSON object per line over its stdin and stdout.
+///
+/// The process is killed when the connection is cancelled or the outgoing channel is closed.
+pub fn connect(
+    program: &str,
+    args: &[String],
+    cancel: CancellationToken,
+) -> anyhow::Result<Channels> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to spawn {program}"))?;
+
+    let mut stdin = child.stdin.take().context("no stdin")?;
+    let mut lines = BufReader::new(child.stdout.take().context("no stdout")?).lines();
+
+    let (tx1, mut rx1) = mpsc::unbounded_channel::<Packet<Client>>();
+    let (tx2, rx2) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        // keep the child alive as long as the connection
+        let _child = child;
+
+        loop {
+            tokio::select! {
+                () = cancel.cancelled() => return,
+                packet = rx1.recv() => {
+                    let Some(packet) = packet else {
+                        return;
+                    };
+                    let Ok(mut line) = serde_json::to_string(&packet) else {
+                        debug!("Failed to serialize packet");
+                        continue;
+                    };
+                    line.push('\n');
+                    if let Err(e) = stdin.write_all(line.as_bytes()).await {
+                        debug!("Failed to write to executor: {e}");
+                        return;
+                    }
+                }
+                line = lines.next_line() => {
+                    let Ok(Some(line)) = line else {
+                        debug!("Executor closed stdout");
+                        return;
+                    };
+                    let Ok(packet) = serde_json::from_str::<Packet<Server>>(&line) else {
+                        debug!("Failed to deserialize packet");
+                        continue;
+                    };
+                    if tx2.send(packet).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok((tx1, rx2))
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{server, Packet};
+    use tokio_util::sync::CancellationToken;
+
+    use super::connect;
+
+    #[tokio::test]
+    async fn test_receive() -> anyhow::Result<()> {
+        let line = serde_json::to_string(&Packet::server(server::Pong))?;
+        let args = vec!["-c".to_string(), format!("echo '{line}'")];
+
+        let (_tx, mut rx) = connect("sh", &args, CancellationToken::new())?;
+
+        let packet = rx.recv().await.unwrap();
+        assert!(packet.data.is_pong());
+
+        // the executor exited
+        assert!(rx.recv().await.is_none());
+
+        Ok(())
+    }
+}
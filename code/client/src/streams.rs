@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+use protocol::StreamFrame;
+
+/// A stream whose last frame was not delivered yet
+#[derive(Default)]
+struct Incoming {
+    next_seq: u64,
+    text: String,
+    /// frames that arrived before a frame preceding them
+    pending: BTreeMap<u64, StreamFrame>,
+}
+
+/// What a frame made available, in order
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Delivered {
+    /// the text of the frames that are now in order, may be empty
+    pub text: String,
+    /// whether `text` starts the stream
+    pub started: bool,
+    /// the text of the whole stream, once its last frame is delivered
+    pub complete: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StreamError {
+    /// the frame was received before
+    Duplicate { id: u64, seq: u64 },
+    /// the stream already ended, or a frame after its end arrived
+    AfterEnd { id: u64, seq: u64 },
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate { id, seq } => {
+                write!(f, "frame {seq} of stream {id} was received twice")
+            }
+            Self::AfterEnd { id, seq } => write!(f, "frame {seq} of stream {id} is after its end"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Reassembles streams of [`StreamFrame`]s, which may be interleaved and out of order.
+#[derive(Default)]
+pub struct Streams {
+    incoming: HashMap<u64, Incoming>,
+    /// the streams that were delivered completely, with their number of frames
+    ended: HashMap<u64, u64>,
+}
+
+impl Streams {
+    /// Add a frame. Returns the text that it and the frames waiting for it add to the stream.
+    ///
+    /// # Errors
+    /// If the frame was received before or is after the end of its stream.
+    pub fn frame(&mut self, frame: StreamFrame) -> Result<Delivered, StreamError> {
+        let StreamFrame { id, seq, .. } = frame;
+
+        if self.ended.get(&id).is_some_and(|&frames| seq >= frames) {
+            return Err(StreamError::AfterEnd { id, seq });
+        }
+        if self.ended.contains_key(&id) {
+            return Err(StreamError::Duplicate { id, seq });
+        }
+
+        let incoming = self.incoming.entry(id).or_default();
+        if seq < incoming.next_seq || incoming.pending.contains_key(&seq) {
+            return Err(StreamError::Duplicate { id, seq });
+        }
+        if let Some((&last, _)) = incoming.pending.iter().find(|(_, frame)| frame.end) {
+            if seq > last {
+                return Err(StreamError::AfterEnd { id, seq });
+            }
+        }
+        incoming.pending.insert(seq, frame);
+
+        let mut delivered = Delivered {
+            started: incoming.next_seq == 0 && seq == 0,
+            ..Delivered::default()
+        };
+
+        while let Some(frame) = incoming.pending.remove(&incoming.next_seq) {
+            incoming.next_seq += 1;
+            incoming.text.push_str(&frame.payload);
+            delivered.text.push_str(&frame.payload);
+
+            if frame.end {
+                let incoming = self.incoming.remove(&id).unwrap_or_default();
+                self.ended.insert(id, incoming.next_seq);
+                delivered.complete = Some(incoming.text);
+                break;
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// The frames that are missing from streams whose later frames arrived, e.g. because a
+    /// packet was dropped.
+    #[must_use]
+    pub fn missing(&self) -> Vec<(u64, Range<u64>)> {
+        let mut missing: Vec<_> = self
+            .incoming
+            .iter()
+            .filter_map(|(&id, incoming)| {
+                let (&next, _) = incoming.pending.first_key_value()?;
+                Some((id, incoming.next_seq..next))
+            })
+            .collect();
+        missing.sort_by_key(|(id, _)| *id);
+        missing
+    }
+
+    /// Forget the streams that did not end, e.g. because the executor cancelled them.
+    pub fn abandon(&mut self) {
+        self.incoming.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::StreamFrame;
+
+    use super::{StreamError, Streams};
+
+    fn frame(id: u64, seq: u64, payload: &str, end: bool) -> StreamFrame {
+        StreamFrame {
+            id,
+            seq,
+            payload: payload.to_string(),
+            end,
+        }
+    }
+
+    #[test]
+    fn test_interleaved() {
+        let mut streams = Streams::default();
+
+        let first = streams.frame(frame(1, 0, "What", false)).unwrap();
+        assert!(first.started);
+        assert_eq!(first.text, "What");
+
+        let other = streams.frame(frame(2, 0, "Which", false)).unwrap();
+        assert!(other.started);
+
+        assert_eq!(streams.frame(frame(1, 1, "?", false)).unwrap().text, "?");
+        let end = streams.frame(frame(1, 2, "", true)).unwrap();
+        assert_eq!(end.complete.as_deref(), Some("What?"));
+        assert!(!end.started);
+
+        let end = streams.frame(frame(2, 1, "?", true)).unwrap();
+        assert_eq!(end.complete.as_deref(), Some("Which?"));
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut streams = Streams::default();
+
+        let early = streams.frame(frame(1, 1, " language?", false)).unwrap();
+        assert!(early.text.is_empty());
+        assert_eq!(streams.missing(), [(1, 0..1)]);
+
+        let delivered = streams.frame(frame(1, 0, "Which", false)).unwrap();
+        assert!(delivered.started);
+        assert_eq!(delivered.text, "Which language?");
+        assert!(streams.missing().is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut streams = Streams::default();
+
+        streams.frame(frame(1, 0, "a", false)).unwrap();
+        assert_eq!(
+            streams.frame(frame(1, 0, "a", false)),
+            Err(StreamError::Duplicate { id: 1, seq: 0 })
+        );
+
+        streams.frame(frame(1, 3, "", true)).unwrap();
+        assert_eq!(
+            streams.frame(frame(1, 4, "b", false)),
+            Err(StreamError::AfterEnd { id: 1, seq: 4 })
+        );
+        assert_eq!(streams.missing(), [(1, 1..3)]);
+
+        streams.frame(frame(1, 1, "b", false)).unwrap();
+        let end = streams.frame(frame(1, 2, "c", false)).unwrap();
+        assert_eq!(end.complete.as_deref(), Some("abc"));
+
+        assert_eq!(
+            streams.frame(frame(1, 1, "b", false)),
+            Err(StreamError::Duplicate { id: 1, seq: 1 })
+        );
+        assert_eq!(
+            streams.frame(frame(1, 4, "d", false)),
+            Err(StreamError::AfterEnd { id: 1, seq: 4 })
+        );
+    }
+}
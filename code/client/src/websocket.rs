@@ -0,0 +1,267 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use futures::{SinkExt, StreamExt};
+use protocol::{
+    client::{self, Client},
+    delivery::{self, Delivered, RETRY_DELAY, SEND_ATTEMPTS},
+    server::Server,
+    Packet,
+};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header::AUTHORIZATION, HeaderValue, StatusCode},
+        Error, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::{session::Session, Channels};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How often we send a [`client::Ping`] to the executor
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// If nothing was received for this long, the connection is considered dead
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times we try to reconnect before giving up
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// The delay before the first reconnect attempt. Doubles on every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+pub async fn connect(
+    address: String,
+    token: Option<String>,
+    cancel: CancellationToken,
+) -> anyhow::Result<Channels> {
+    info!("Connecting to {address} via websocket...");
+
+    let websocket = open(&address, token.as_deref()).await?;
+
+    let (tx1, rx1) = mpsc::unbounded_channel();
+    let (tx2, rx2) = mpsc::unbounded_channel();
+
+    tokio::spawn(run(address, token, websocket, cancel, rx1, tx2));
+
+    Ok((tx1, rx2))
+}
+
+/// Connect to the executor at `address`, authenticating with `token` if given.
+async fn open(address: &str, token: Option<&str>) -> anyhow::Result<WebSocket> {
+    let mut request = address.into_client_request()?;
+    if let Some(token) = token {
+        let value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .context("The token is not a valid header value")?;
+        request.headers_mut().insert(AUTHORIZATION, value);
+    }
+
+    match connect_async(request).await {
+        Ok((websocket, _)) => Ok(websocket),
+        Err(Error::Http(response)) if response.status() == StatusCode::UNAUTHORIZED => {
+            bail!("The executor rejected the connection, pass its token with --token")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Forward packets between the frontend and the executor, reconnecting whenever the websocket
+/// drops.
+///
+/// After reconnecting the session is resumed with a [`client::Resume`] packet, so the executor
+/// can pick up where it left off. Packets that could not be sent are sent after it with their
+/// ids, the executor drops those it received before, see [`delivery`]. If reconnecting fails,
+/// the incoming channel is closed.
+async fn run(
+    address: String,
+    token: Option<String>,
+    mut websocket: WebSocket,
+    cancel: CancellationToken,
+    mut outgoing: mpsc::UnboundedReceiver<Packet<Client>>,
+    incoming: mpsc::UnboundedSender<Packet<Server>>,
+) {
+    let mut session = Session::default();
+
+    // packets that could not be sent, in the order they have to be sent after reconnecting
+    let mut pending = VecDeque::new();
+    // packets of the executor that were passed on, across connections
+    let mut delivered = Delivered::default();
+
+    loop {
+        let res = connection(
+            websocket,
+            &cancel,
+            &mut session,
+            &mut pending,
+            &mut outgoing,
+            &incoming,
+            &mut delivered,
+        )
+        .await;
+
+        match res {
+            Ok(()) => return,
+            Err(e) => debug!("Connection lost: {e:?}. Reconnecting"),
+        }
+
+        let Some(new_websocket) = reconnect(&address, token.as_deref(), &cancel).await else {
+            debug!("Failed to reconnect to {address}. Shutting down");
+            return;
+        };
+
+        websocket = new_websocket;
+
+        if let Some(resume) = session.resume() {
+            pending.push_front(Packet::client(resume));
+        }
+    }
+}
+
+/// Try to reconnect with exponential backoff.
+async fn reconnect(
+    address: &str,
+    token: Option<&str>,
+    cancel: &CancellationToken,
+) -> Option<WebSocket> {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        tokio::select! {
+            () = cancel.cancelled() => return None,
+            () = tokio::time::sleep(backoff) => {}
+        }
+
+        match open(address, token).await {
+            Ok(websocket) => {
+                info!("Reconnected to {address}");
+                return Some(websocket);
+            }
+            Err(e) => debug!("Reconnect attempt {attempt} failed: {e}"),
+        }
+
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    None
+}
+
+/// Drive a single websocket connection until it fails.
+///
+/// Returns `Ok(())` when the frontend is shutting down and `Err` when the connection was lost.
+async fn connection(
+    websocket: WebSocket,
+    cancel: &CancellationToken,
+    session: &mut Session,
+    pending: &mut VecDeque<Packet<Client>>,
+    outgoing: &mut mpsc::UnboundedReceiver<Packet<Client>>,
+    incoming: &mpsc::UnboundedSender<Packet<Server>>,
+    delivered: &mut Delivered,
+) -> anyhow::Result<()> {
+    let (mut write, mut read) = websocket.split();
+
+    while let Some(packet) = pending.pop_front() {
+        if let Err(e) = send(&mut write, &packet).await {
+            pending.push_front(packet);
+            return Err(e);
+        }
+        session.sent(&packet.data);
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_received = Instant::now();
+
+    loop {
+        tokio::select! {
+            () = cancel.cancelled() => return Ok(()),
+            packet = outgoing.recv() => {
+                let Some(packet) = packet else {
+                    return Ok(());
+                };
+                if let Err(e) = send(&mut write, &packet).await {
+                    pending.push_back(packet);
+                    return Err(e);
+                }
+                session.sent(&packet.data);
+            }
+            message = read.next() => {
+                let message = message.context("Connection closed")??;
+                last_received = Instant::now();
+
+                let packet = match message {
+                    Message::Text(text) => text,
+                    Message::Close(_) => bail!("Connection closed by executor"),
+                    _ => continue,
+                };
+
+                let Ok(packet) = serde_json::from_str::<Packet<Server>>(&packet) else {
+                    debug!("Failed to deserialize packet");
+                    continue;
+                };
+                // sent again by the executor after sending it seemed to fail
+                if !delivered.first(packet.id) {
+                    debug!("Dropping packet {} that was received before", packet.id);
+                    continue;
+                }
+
+                session.received(&packet.data);
+
+                if packet.data.is_pong() {
+                    continue;
+                }
+
+                if let Err(e) = incoming.send(packet) {
+                    debug!("Failed to send packet: {}. Shutting down", e);
+                    return Ok(());
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_received.elapsed() > HEARTBEAT_TIMEOUT {
+                    bail!("No packet received for {HEARTBEAT_TIMEOUT:?}");
+                }
+                send(&mut write, &Packet::client(client::Ping)).await?;
+            }
+        }
+    }
+}
+
+/// Send `packet`, again if sending fails transiently.
+async fn send(
+    write: &mut futures::stream::SplitSink<WebSocket, Message>,
+    packet: &Packet<Client>,
+) -> anyhow::Result<()> {
+    let text = serde_json::to_string(packet)?;
+
+    for attempt in 1.. {
+        match write.send(Message::Text(text.clone())).await {
+            Ok(()) => break,
+            Err(e) if attempt < SEND_ATTEMPTS && is_transient(&e) => {
+                debug!("Sending packet {} again: {e}", packet.id);
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the websocket can still send after `e`
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Io(e) => delivery::is_transient(e.kind()),
+        Error::SendQueueFull(_) => true,
+        _ => false,
+    }
+}
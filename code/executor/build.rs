@@ -0,0 +1,22 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    header();
+}
+
+/// Generate the header of the C API in [`executor::ffi`] to `include/collective.h`.
+#[cfg(feature = "ffi")]
+fn header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let dir = std::env::var("CARGO_MANIFEST_DIR").expect("set by cargo");
+    let config = cbindgen::Config::from_file(format!("{dir}/cbindgen.toml"))
+        .expect("cbindgen.toml is valid");
+
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{dir}/src/ffi.rs"))
+        .generate()
+        .expect("the C API can be bound")
+        .write_to_file(format!("{dir}/include/collective.h"));
+}
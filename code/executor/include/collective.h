@@ -0,0 +1,102 @@
+#ifndef COLLECTIVE_H
+#define COLLECTIVE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded
+ */
+#define COLLECTIVE_OK 0
+
+/**
+ * An argument is null or not valid UTF-8
+ */
+#define COLLECTIVE_INVALID_ARGUMENT -1
+
+/**
+ * The executor stopped, the agent has to be freed
+ */
+#define COLLECTIVE_CLOSED -2
+
+/**
+ * An executor running on its own runtime, created with [`collective_agent_new`]
+ */
+typedef struct CollectiveAgent CollectiveAgent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Start an executor. Returns null if it cannot be started, e.g. because `OPENAI_KEY` is not set.
+ */
+struct CollectiveAgent *collective_agent_new(void);
+
+/**
+ * Stop the executor and free the agent.
+ *
+ * # Safety
+ * `agent` must be null or returned by [`collective_agent_new`] and not freed before.
+ */
+void collective_agent_free(struct CollectiveAgent *agent);
+
+/**
+ * Send an instruction, which starts a session.
+ *
+ * # Safety
+ * `agent` must be a live agent, `instruction` a NUL terminated string.
+ */
+int32_t collective_agent_instruct(struct CollectiveAgent *agent, const char *instruction);
+
+/**
+ * Answer the current question.
+ *
+ * # Safety
+ * `agent` must be a live agent, `answer` a NUL terminated string.
+ */
+int32_t collective_agent_answer(struct CollectiveAgent *agent, const char *answer);
+
+/**
+ * Answer the question `id` of a batch of questions.
+ *
+ * # Safety
+ * `agent` must be a live agent, `answer` a NUL terminated string.
+ */
+int32_t collective_agent_answer_to(struct CollectiveAgent *agent, uint64_t id, const char *answer);
+
+/**
+ * Send a packet given as JSON, e.g. `Execute`, which has no function of its own.
+ *
+ * # Safety
+ * `agent` must be a live agent, `json` a NUL terminated string.
+ */
+int32_t collective_agent_send(struct CollectiveAgent *agent, const char *json);
+
+/**
+ * Wait up to `timeout_ms` milliseconds for the next packet of the executor and return it as
+ * JSON. Returns null if none arrived or the executor stopped.
+ *
+ * # Safety
+ * `agent` must be a live agent that no other thread uses during the call.
+ */
+char *collective_agent_poll(struct CollectiveAgent *agent, uint64_t timeout_ms);
+
+/**
+ * Free a string returned by [`collective_agent_poll`].
+ *
+ * # Safety
+ * `s` must be null or returned by [`collective_agent_poll`] and not freed before.
+ */
+void collective_string_free(char *s);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* COLLECTIVE_H */
@@ -0,0 +1,132 @@
+//! The format the answer to a question must have, inferred from the question.
+//!
+//! Only questions that clearly ask for one value get a [`Validator`], so a malformed answer is
+//! caught before the model has to ask again. Anything else can be answered freely.
+//!
+//! Questions asking for a password or a token are secret, see [`is_secret`].
+
+use once_cell::sync::Lazy;
+use protocol::Validator;
+use regex::Regex;
+
+static YES_NO: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\(y/n\)|\byes or no\b").expect("valid regex"));
+
+static PORT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(which|what) port\b|\bport number\b").expect("valid regex"));
+
+static CRATE_NAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(which|what) (crate|package) name\b|\bname (of|for) the (crate|package)\b")
+        .expect("valid regex")
+});
+
+static PATH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(which|what) (path|directory|folder)\b|\bpath (to|of|for)\b")
+        .expect("valid regex")
+});
+
+/// Asks for the value itself, e.g. "What is your crates.io token?", not about one, e.g. "Which
+/// password hashing algorithm?"
+static SECRET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)^(what|please|enter|provide|paste)\b.*\b(password|passphrase|api key|token|secret|private key)( (for|to) [^?]*)?\?\s*$",
+    )
+    .expect("valid regex")
+});
+
+/// The validator for answers to `question`, `None` if it can be answered freely.
+#[must_use]
+pub fn infer(question: &str) -> Option<Validator> {
+    // a question listing alternatives, e.g. "Which port or socket?", has no single format
+    if question.contains(" or ") && !YES_NO.is_match(question) {
+        return None;
+    }
+
+    if YES_NO.is_match(question) {
+        return Some(Validator::Enum {
+            options: ["yes", "no", "y", "n"].map(String::from).to_vec(),
+        });
+    }
+
+    if PORT.is_match(question) {
+        return Some(Validator::Range { min: 1, max: 65535 });
+    }
+
+    if CRATE_NAME.is_match(question) {
+        return Some(Validator::Regex {
+            pattern: "[A-Za-z][A-Za-z0-9_-]{0,63}".to_string(),
+            description: "a crate name (letters, digits, - and _)".to_string(),
+        });
+    }
+
+    if PATH.is_match(question) {
+        return Some(Validator::Regex {
+            pattern: r"\S.*".to_string(),
+            description: "a path".to_string(),
+        });
+    }
+
+    None
+}
+
+/// Whether the answer to `question` is a secret, which is only passed to commands and never shown
+/// to the model.
+#[must_use]
+pub fn is_secret(question: &str) -> bool {
+    SECRET.is_match(question.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Validator;
+
+    use super::{infer, is_secret};
+
+    #[test]
+    fn test_infer() {
+        assert!(matches!(
+            infer("Which port should the server listen on?"),
+            Some(Validator::Range { min: 1, max: 65535 })
+        ));
+        assert!(matches!(
+            infer("Should it support a dark mode? (y/n)"),
+            Some(Validator::Enum { .. })
+        ));
+        assert!(matches!(
+            infer("What is the name of the crate?"),
+            Some(Validator::Regex { .. })
+        ));
+        assert!(matches!(
+            infer("Which directory should the output be written to?"),
+            Some(Validator::Regex { .. })
+        ));
+
+        assert!(infer("Which features do you need?").is_none());
+        assert!(infer("Which port or unix socket should it listen on?").is_none());
+    }
+
+    #[test]
+    fn test_is_secret() {
+        assert!(is_secret("What is your crates.io API token?"));
+        assert!(is_secret(
+            "Please enter the password for the deployment server?"
+        ));
+
+        assert!(!is_secret(
+            "Which password hashing algorithm should be used?"
+        ));
+        assert!(!is_secret("What is the password hashing algorithm?"));
+        assert!(!is_secret("Should the API use token-based authentication?"));
+    }
+
+    #[test]
+    fn test_check() {
+        let crate_name = infer("What crate name should be used?").unwrap();
+        assert!(crate_name.check("serde_json").is_ok());
+        assert!(crate_name.check("my crate").is_err());
+
+        let port = infer("What port number?").unwrap();
+        assert!(port.check("8080").is_ok());
+        assert!(port.check("http://localhost:8080").is_err());
+    }
+}
@@ -0,0 +1,468 @@
+//! Portable archives of sessions, `.collective-session` files, to move a session to another
+//! machine, attach it to a bug report or share it with a teammate.
+//!
+//! An archive is a zip of
+//! - `metadata.json`: the version of the format, the session, when it was exported and the hex
+//!   SHA-256 of every other file, which are checked on import
+//! - `session.json`: the instruction, questions and answers, as saved to the checkpoint, with the
+//!   key to resume the session, so whoever has the archive can resume it once it is imported
+//! - `events.jsonl`: the lines of the [audit log](crate::audit) of the session, if it was kept
+//! - `workspace/`: a snapshot of the working directory, without ignored and hidden files
+//! - `transcript.md`: the interview, for reading
+//!
+//! Sessions are exported from the checkpoint of a stopped executor and imported into it, which
+//! restores them when it starts. Answers to secret questions were never saved, so they are not in
+//! the archive either.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::File,
+    io::{Read, Write},
+    path::{Component, Path},
+};
+
+use anyhow::{bail, ensure, Context};
+use protocol::{trace::now_ms, SessionId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::{session::Checkpoint, SessionManager};
+
+/// The extension of archives
+pub const EXTENSION: &str = "collective-session";
+
+/// The version of the format, changed whenever an older executor could not import an archive
+const SCHEMA: u32 = 1;
+
+const METADATA: &str = "metadata.json";
+const SESSION: &str = "session.json";
+const EVENTS: &str = "events.jsonl";
+const TRANSCRIPT: &str = "transcript.md";
+const WORKSPACE: &str = "workspace/";
+
+/// Larger files of the working directory are left out of the snapshot
+const MAX_FILE_BYTES: u64 = 10 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Metadata {
+    schema: u32,
+    session: SessionId,
+    /// milliseconds since the epoch
+    exported_at: u64,
+    /// the version of the executor that exported it
+    executor: String,
+    /// the hex SHA-256 of every other file, by its path in the archive
+    files: BTreeMap<String, String>,
+}
+
+/// Write session `id` of the checkpoint at `checkpoint` to the archive `to`, with its working
+/// directory, the one it chose or its own in `sessions`, and its events in the audit log at
+/// `audit_log`, if any.
+///
+/// # Errors
+/// - The session is not in the checkpoint
+/// - The checkpoint, the audit log or the working directory cannot be read
+/// - The archive cannot be written
+pub fn export(
+    checkpoint: &Path,
+    id: SessionId,
+    sessions: &SessionManager,
+    audit_log: Option<&Path>,
+    to: &Path,
+) -> anyhow::Result<()> {
+    let session = Checkpoint::load(checkpoint)?
+        .into_iter()
+        .find(|session| session.id == id)
+        .with_context(|| format!("There is no session {id} in {}", checkpoint.display()))?;
+
+    let mut files = BTreeMap::new();
+    files.insert(SESSION.to_string(), serde_json::to_vec_pretty(&session)?);
+    files.insert(TRANSCRIPT.to_string(), transcript(&session).into_bytes());
+    if let Some(audit_log) = audit_log {
+        files.insert(EVENTS.to_string(), events(audit_log, id)?);
+    }
+    let workdir = session
+        .workdir
+        .clone()
+        .unwrap_or_else(|| sessions.workdir(id));
+    if workdir.exists() {
+        snapshot(&workdir, &mut files)?;
+    }
+
+    let metadata = Metadata {
+        schema: SCHEMA,
+        session: id,
+        exported_at: now_ms(),
+        executor: env!("CARGO_PKG_VERSION").to_string(),
+        files: files
+            .iter()
+            .map(|(name, content)| (name.clone(), hash(content)))
+            .collect(),
+    };
+
+    let file = File::create(to).with_context(|| format!("Failed to create {}", to.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    zip.start_file(METADATA, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&metadata)?)?;
+    for (name, content) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&content)?;
+    }
+    zip.finish()
+        .with_context(|| format!("Failed to write {}", to.display()))?;
+
+    Ok(())
+}
+
+/// Add the session of the archive at `from` to the checkpoint at `checkpoint`, and its working
+/// directory to `sessions`. Returns the imported session.
+///
+/// # Errors
+/// - The archive is of another version or was altered or damaged
+/// - The session already exists in the checkpoint or has a working directory
+/// - The checkpoint or the working directory cannot be written
+pub fn import(
+    from: &Path,
+    checkpoint: &Path,
+    sessions: &SessionManager,
+) -> anyhow::Result<SessionId> {
+    let (metadata, mut files) = read(from)?;
+    let id = metadata.session;
+
+    let session = files
+        .remove(SESSION)
+        .context("The archive has no session")?;
+    let mut session: Checkpoint =
+        serde_json::from_slice(&session).context("The session of the archive is not valid")?;
+    ensure!(
+        session.id == id,
+        "The archive is of session {id}, but contains session {}",
+        session.id
+    );
+
+    let mut checkpoints = Checkpoint::load(checkpoint)?;
+    ensure!(
+        checkpoints.iter().all(|existing| existing.id != id),
+        "Session {id} already exists in {}",
+        checkpoint.display()
+    );
+    let workdir = sessions.workdir(id);
+    ensure!(
+        !workdir.exists(),
+        "The working directory {} of session {id} already exists",
+        workdir.display()
+    );
+
+    for (name, content) in &files {
+        let Some(relative) = name.strip_prefix(WORKSPACE) else {
+            continue;
+        };
+        let path = workdir.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    // the files are imported into a working directory of its own
+    session.workdir = None;
+    checkpoints.push(session);
+    Checkpoint::save(checkpoint, &checkpoints)?;
+
+    Ok(id)
+}
+
+/// The metadata and the other files of the archive at `path`, after checking them against it
+fn read(path: &Path) -> anyhow::Result<(Metadata, BTreeMap<String, Vec<u8>>)> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut zip = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a session archive", path.display()))?;
+
+    let metadata: Metadata = {
+        let mut entry = zip
+            .by_name(METADATA)
+            .with_context(|| format!("{} has no {METADATA}", path.display()))?;
+        let mut json = Vec::new();
+        entry.read_to_end(&mut json)?;
+        serde_json::from_slice(&json).context("The metadata of the archive is not valid")?
+    };
+    ensure!(
+        metadata.schema == SCHEMA,
+        "{} is of version {} of the archive format, this executor reads version {SCHEMA}",
+        path.display(),
+        metadata.schema
+    );
+
+    let mut files = BTreeMap::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        if entry.is_dir() || entry.name() == METADATA {
+            continue;
+        }
+        // the names come from whoever made the archive, none may escape the working directory,
+        // not even into another one by going up inside the archive
+        if !Path::new(entry.name())
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("The archive contains the unsafe path {}", entry.name());
+        }
+
+        let name = entry.name().to_string();
+        let Some(expected) = metadata.files.get(&name) else {
+            bail!("The archive contains {name}, which its metadata does not list");
+        };
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        ensure!(
+            hash(&content) == *expected,
+            "{name} in the archive was altered or damaged, its hash does not match"
+        );
+        files.insert(name, content);
+    }
+
+    if let Some(missing) = metadata
+        .files
+        .keys()
+        .find(|name| !files.contains_key(*name))
+    {
+        bail!("The archive lacks {missing}, which its metadata lists");
+    }
+
+    Ok((metadata, files))
+}
+
+/// The hex SHA-256 of `content`
+fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// The lines of the audit log at `path` of session `id`
+fn events(path: &Path, id: SessionId) -> anyhow::Result<Vec<u8>> {
+    let log = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the audit log {}", path.display()))?;
+    let session = id.to_string();
+
+    let mut events = Vec::new();
+    for line in log.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        if event["session"] == session.as_str() {
+            events.extend_from_slice(line.as_bytes());
+            events.push(b'\n');
+        }
+    }
+    Ok(events)
+}
+
+/// Add the files of `workdir` to `files`, under [`WORKSPACE`].
+fn snapshot(workdir: &Path, files: &mut BTreeMap<String, Vec<u8>>) -> anyhow::Result<()> {
+    // a workspace that is not a repository yet can ignore files as well
+    let walk = ignore::WalkBuilder::new(workdir).require_git(false).build();
+
+    for entry in walk.flatten() {
+        let is_file = entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file());
+        if !is_file || entry.metadata().map_or(true, |m| m.len() > MAX_FILE_BYTES) {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(workdir)?;
+        // zips separate with `/` everywhere
+        let name = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = std::fs::read(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        files.insert(format!("{WORKSPACE}{name}"), content);
+    }
+
+    Ok(())
+}
+
+/// The interview of `session` in markdown
+fn transcript(session: &Checkpoint) -> String {
+    let mut transcript = format!(
+        "# Session {}\n\n**Instruction:** {}\n",
+        session.id, session.instruction
+    );
+    for (index, question) in session.questions.iter().enumerate() {
+        let answer = session
+            .answers
+            .get(index)
+            .map_or("(not answered)", String::as_str);
+        let _ = write!(transcript, "\n**Q:** {question}\n\n**A:** {answer}\n");
+    }
+    transcript
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::{export, import, EXTENSION};
+    use crate::{session::Checkpoint, SessionManager};
+
+    #[test]
+    fn test_export_import() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let checkpoint = dir.path().join("sessions.json");
+        let id = uuid::Uuid::new_v4();
+        Checkpoint::save(&checkpoint, &[Checkpoint {
+            id,
+            instruction: "Create a calculator".to_string(),
+            questions: vec!["Which language?".to_string()],
+            answers: vec!["Rust".to_string()],
+            workdir: None,
+            key: None,
+        }])?;
+
+        let sessions = SessionManager::new();
+        let workdir = sessions.workdir(id);
+        std::fs::create_dir_all(workdir.join("src"))?;
+        std::fs::write(workdir.join("src/main.rs"), "fn main() {}")?;
+
+        let audit_log = dir.path().join("audit.jsonl");
+        let other = uuid::Uuid::new_v4();
+        std::fs::write(
+            &audit_log,
+            format!(
+                "{{\"session\":\"{id}\",\"event\":\"instruction\"}}\n{{\"session\":\"{other}\",\"\
+                 event\":\"instruction\"}}\n"
+            ),
+        )?;
+
+        let archive = dir.path().join(format!("session.{EXTENSION}"));
+        export(&checkpoint, id, &sessions, Some(&audit_log), &archive)?;
+        assert!(export(&checkpoint, other, &sessions, None, &archive).is_err());
+
+        let (metadata, files) = super::read(&archive)?;
+        assert_eq!(metadata.session, id);
+        assert_eq!(files["workspace/src/main.rs"], b"fn main() {}");
+        assert_eq!(
+            files["events.jsonl"]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count(),
+            1
+        );
+        let transcript = String::from_utf8(files["transcript.md"].clone())?;
+        assert!(transcript.contains("**Q:** Which language?\n\n**A:** Rust"));
+
+        // the session exists already
+        assert!(import(&archive, &checkpoint, &sessions).is_err());
+
+        // on another machine
+        std::fs::remove_dir_all(&workdir)?;
+        let elsewhere = dir.path().join("elsewhere.json");
+        assert_eq!(import(&archive, &elsewhere, &sessions)?, id);
+        assert_eq!(
+            Checkpoint::load(&elsewhere)?,
+            Checkpoint::load(&checkpoint)?
+        );
+        assert_eq!(
+            std::fs::read_to_string(workdir.join("src/main.rs"))?,
+            "fn main() {}"
+        );
+
+        sessions.remove(id);
+        std::fs::remove_dir_all(&workdir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_altered() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let checkpoint = dir.path().join("sessions.json");
+        let id = uuid::Uuid::new_v4();
+        Checkpoint::save(&checkpoint, &[Checkpoint {
+            id,
+            instruction: "Create a calculator".to_string(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            workdir: None,
+            key: None,
+        }])?;
+
+        let sessions = SessionManager::new();
+        let archive = dir.path().join("session.zip");
+        export(&checkpoint, id, &sessions, None, &archive)?;
+
+        // the same files, but the session changed
+        let (metadata, mut files) = super::read(&archive)?;
+        files.insert(
+            "session.json".to_string(),
+            String::from_utf8(files["session.json"].clone())?
+                .replace("calculator", "virus")
+                .into_bytes(),
+        );
+        let altered = dir.path().join("altered.zip");
+        let mut zip = ZipWriter::new(File::create(&altered)?);
+        zip.start_file("metadata.json", SimpleFileOptions::default())?;
+        zip.write_all(&serde_json::to_vec(&metadata)?)?;
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(&content)?;
+        }
+        zip.finish()?;
+
+        let error = import(&altered, &dir.path().join("new.json"), &sessions).unwrap_err();
+        assert!(error.to_string().contains("altered or damaged"), "{error}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_escaping_path() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let checkpoint = dir.path().join("sessions.json");
+        let id = uuid::Uuid::new_v4();
+        Checkpoint::save(&checkpoint, &[Checkpoint {
+            id,
+            instruction: "Create a calculator".to_string(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            workdir: None,
+            key: None,
+        }])?;
+
+        let sessions = SessionManager::new();
+        let archive = dir.path().join("session.zip");
+        export(&checkpoint, id, &sessions, None, &archive)?;
+
+        // a file in the working directory of another session, listed with its hash
+        let other = uuid::Uuid::new_v4();
+        let name = format!("workspace/../{other}/src/main.rs");
+        let (mut metadata, files) = super::read(&archive)?;
+        metadata
+            .files
+            .insert(name.clone(), super::hash(b"fn main() {}"));
+        let escaping = dir.path().join("escaping.zip");
+        let mut zip = ZipWriter::new(File::create(&escaping)?);
+        zip.start_file("metadata.json", SimpleFileOptions::default())?;
+        zip.write_all(&serde_json::to_vec(&metadata)?)?;
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(&content)?;
+        }
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(b"fn main() {}")?;
+        zip.finish()?;
+
+        let error = import(&escaping, &dir.path().join("new.json"), &sessions).unwrap_err();
+        assert!(error.to_string().contains("unsafe path"), "{error}");
+        assert!(!sessions.workdir(other).exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! An append-only log of what the agent did, for reviewing a session afterwards.
+//!
+//! With [`Settings::audit_log`](crate::Settings) set, every instruction, question, answer, request
+//! to the model, command and step result is appended to the file as a line of JSON, and so is
+//! every decision of the user or a hook and what the requests of every execution cost, e.g.
+//!
+//! ```json
+//! {"at":1700000000000,"session":"…","event":"command","step":0,"command":"bash","input":"ls"}
+//! ```
+//!
+//! `at` is in milliseconds since the epoch. Requests to the model are only recorded by their hash,
+//! the same the [`ResponseCache`](crate::responses::ResponseCache) keeps their response under, and
+//! the answers to secret questions not at all, secrets only by their name.
+
+use std::{fs::File, future::Future, io::Write, path::Path};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use protocol::{trace::now_ms, FailureKind, Resolution, Risk, SessionId, UsageStats};
+use serde::Serialize;
+
+tokio::task_local! {
+    /// The session whose packet is being handled, see [`scope`]
+    static SESSION: SessionId;
+}
+
+/// Something the agent did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Instruction {
+        instruction: &'a str,
+    },
+    Question {
+        /// the id of a question asked with others at once
+        id: Option<u64>,
+        question: &'a str,
+    },
+    Answer {
+        id: Option<u64>,
+        /// `None` for the answer to a secret question
+        answer: Option<&'a str>,
+    },
+    ModelRequest {
+        /// which endpoint was requested, e.g. `chat`
+        kind: &'static str,
+        /// the hex SHA-256 of the kind and the request
+        hash: String,
+    },
+    Command {
+        step: usize,
+        command: String,
+        input: &'a str,
+    },
+    StepFinished {
+        step: usize,
+        succeeded: bool,
+        /// why the step failed
+        error: Option<String>,
+    },
+    /// why a step failed, as classified by the model
+    StepDiagnosed {
+        step: usize,
+        kind: FailureKind,
+        explanation: &'a str,
+        remediation: &'a str,
+    },
+    /// a crate a step added with `cargo add`
+    DependencyAdded {
+        step: usize,
+        #[serde(rename = "crate")]
+        krate: &'a str,
+        /// the version it was resolved to
+        version: &'a str,
+        manifest: &'a str,
+    },
+    SecretsPassed {
+        step: usize,
+        /// the variables the step reads them from
+        names: Vec<&'a str>,
+    },
+    ScratchKept {
+        step: usize,
+        dir: String,
+    },
+    SourceVetoed {
+        source: u64,
+        url: &'a str,
+    },
+    CommandConfirmed {
+        command: &'a str,
+        risk: Risk,
+        approved: bool,
+    },
+    CommitConfirmed {
+        subject: &'a str,
+        approved: bool,
+    },
+    PushConfirmed {
+        branch: &'a str,
+        approved: bool,
+    },
+    ConflictResolved {
+        step: usize,
+        path: &'a str,
+        resolution: Resolution,
+        conflicts: usize,
+    },
+    Reverted {
+        /// whether the instruction was reverted, else the execution
+        instruction: bool,
+        summary: &'a str,
+    },
+    RepoCloned {
+        url: &'a str,
+        branch: &'a str,
+        head: &'a str,
+    },
+    WorkspaceSet {
+        path: String,
+    },
+    HookVetoed {
+        hook: &'a str,
+        /// the event of the hook, e.g. `before_command`
+        on: &'static str,
+        reason: String,
+    },
+    /// the session spent `micro_usd` millionths of a dollar of its cap of `cap_cents`, its
+    /// questions and summaries are sent to `model` from now on
+    Downshifted {
+        micro_usd: u64,
+        cap_cents: u64,
+        model: &'static str,
+    },
+    /// what the requests of the session to the model cost so far, after every execution
+    Usage {
+        usage: &'a [UsageStats],
+    },
+}
+
+/// A line of the log
+#[derive(Serialize)]
+struct Line<'a> {
+    at: u64,
+    session: Option<SessionId>,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+pub struct AuditLog(Mutex<File>);
+
+impl AuditLog {
+    /// Append to `path`, creating it if needed.
+    ///
+    /// # Errors
+    /// If the log cannot be opened
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the audit log {}", path.display()))?;
+        Ok(Self(Mutex::new(file)))
+    }
+
+    /// Append `event` of the session in [`scope`], if any.
+    ///
+    /// # Errors
+    /// If the log cannot be written
+    pub fn record(&self, event: &Event) -> anyhow::Result<()> {
+        let line = Line {
+            at: now_ms(),
+            session: SESSION.try_with(|session| *session).ok(),
+            event,
+        };
+        let mut json = serde_json::to_string(&line)?;
+        json.push('\n');
+        self.0
+            .lock()
+            .write_all(json.as_bytes())
+            .context("Failed to write the audit log")
+    }
+}
+
+/// Run `f` for `session`, the events it records belong to it.
+pub async fn scope<F: Future>(session: SessionId, f: F) -> F::Output {
+    SESSION.scope(session, f).await
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::FailureKind;
+    use serde_json::{json, Value};
+
+    use super::{AuditLog, Event};
+
+    #[tokio::test]
+    async fn test_record() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("audit.jsonl");
+        let session = uuid::Uuid::new_v4();
+
+        let log = AuditLog::open(&path)?;
+        log.record(&Event::Instruction {
+            instruction: "Create a calculator",
+        })?;
+        super::scope(session, async {
+            log.record(&Event::Answer {
+                id: Some(2),
+                answer: None,
+            })
+        })
+        .await?;
+        // appended to, not truncated
+        AuditLog::open(&path)?.record(&Event::StepFinished {
+            step: 0,
+            succeeded: false,
+            error: Some("exited with 1".to_string()),
+        })?;
+        AuditLog::open(&path)?.record(&Event::StepDiagnosed {
+            step: 0,
+            kind: FailureKind::MissingDependency,
+            explanation: "serde is not a dependency",
+            remediation: "cargo add serde",
+        })?;
+        AuditLog::open(&path)?.record(&Event::DependencyAdded {
+            step: 0,
+            krate: "serde",
+            version: "1.0.188",
+            manifest: "Cargo.toml",
+        })?;
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line["at"].as_u64() > Some(0)));
+
+        assert_eq!(lines[0]["session"], Value::Null);
+        assert_eq!(lines[0]["event"], "instruction");
+        assert_eq!(lines[0]["instruction"], "Create a calculator");
+
+        assert_eq!(lines[1]["session"], json!(session));
+        assert_eq!(lines[1]["event"], "answer");
+        assert_eq!(lines[1]["answer"], Value::Null);
+
+        assert_eq!(lines[2]["event"], "step_finished");
+        assert_eq!(lines[2]["error"], "exited with 1");
+
+        assert_eq!(lines[3]["event"], "step_diagnosed");
+        assert_eq!(lines[3]["kind"], "missing_dependency");
+        assert_eq!(lines[3]["remediation"], "cargo add serde");
+
+        assert_eq!(lines[4]["event"], "dependency_added");
+        assert_eq!(lines[4]["crate"], "serde");
+        assert_eq!(lines[4]["version"], "1.0.188");
+
+        Ok(())
+    }
+}
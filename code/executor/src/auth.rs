@@ -0,0 +1,114 @@
+//! Tokens frontends authenticate with, so only those given one can drive the agent.
+//!
+//! With `--token` set, the websocket handshake is rejected with `401 Unauthorized` unless the
+//! request has the header `Authorization: Bearer <token>`, before a session is created.
+
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{header::AUTHORIZATION, StatusCode},
+    },
+    WebSocketStream,
+};
+
+/// Complete the websocket handshake on `socket`, if it carries `token`.
+///
+/// # Errors
+/// If the handshake fails or the token is missing or wrong
+pub async fn accept(
+    socket: TcpStream,
+    token: Option<&str>,
+) -> anyhow::Result<WebSocketStream<TcpStream>> {
+    // the signature tungstenite expects
+    #[allow(clippy::result_large_err)]
+    let check = |request: &Request, response: Response| match token {
+        Some(token) if !authorized(request, token) => {
+            let mut rejection = ErrorResponse::new(Some("A valid token is required".to_string()));
+            *rejection.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(rejection)
+        }
+        _ => Ok(response),
+    };
+    Ok(accept_hdr_async(socket, check).await?)
+}
+
+/// Whether `request` carries `token` as a bearer token
+fn authorized(request: &Request, token: &str) -> bool {
+    bearer(
+        request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok()),
+        token,
+    )
+}
+
+/// Whether the `Authorization` header `authorization` is `token` as a bearer token
+pub(crate) fn bearer(authorization: Option<&str>, token: &str) -> bool {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| equal(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compare in constant time, so the time a rejection takes tells nothing about the token
+pub(crate) fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{client::IntoClientRequest, handshake::server::Request, Error},
+    };
+
+    use super::{accept, authorized};
+
+    #[test]
+    fn test_authorized() -> anyhow::Result<()> {
+        let request = |authorization: Option<&str>| {
+            let mut request = Request::builder().uri("ws://127.0.0.1:8080");
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+            request.body(())
+        };
+
+        assert!(authorized(&request(Some("Bearer secret"))?, "secret"));
+        assert!(!authorized(&request(Some("Bearer wrong"))?, "secret"));
+        assert!(!authorized(&request(Some("Bearer secre"))?, "secret"));
+        assert!(!authorized(&request(Some("secret"))?, "secret"));
+        assert!(!authorized(&request(None)?, "secret"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_accept() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("ws://{}", listener.local_addr()?);
+        let server = tokio::spawn(async move {
+            let mut accepted = Vec::new();
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().await?;
+                accepted.push(accept(socket, Some("secret")).await.is_ok());
+            }
+            anyhow::Ok(accepted)
+        });
+
+        let rejected = connect_async(&address).await;
+        assert!(matches!(rejected, Err(Error::Http(response)) if response.status() == 401));
+
+        let mut request = address.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert("Authorization", "Bearer secret".parse()?);
+        connect_async(request).await?;
+
+        assert_eq!(server.await??, [false, true]);
+        Ok(())
+    }
+}
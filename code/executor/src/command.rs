@@ -5,30 +5,336 @@
 //! {args}
 //! ```
 //!
-//! where {cmd data} is one line of RON
-//! but {args} can be several lines
+//! where {cmd header} is one line of RON naming the [`Cmd`], e.g. `bash`,
+//! but {args} can be several lines. See [`Cmd::parse`].
 
-use async_trait::async_trait;
+use std::{path::Path, process::Stdio};
+
+use anyhow::{anyhow, ensure, Context};
 use derive_discriminant::Discriminant;
+use futures::{future::Future, stream, stream::BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+    time::Instant,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
-use crate::Ctx;
+use self::limits::Group;
+pub use self::limits::{LimitExceeded, Limits};
+use crate::{scratch, secrets, Ctx};
 
+mod apply_patch;
 mod bash;
+pub mod cargo;
+pub mod codegen;
+pub mod crate_search;
+mod docs_rs;
+mod encoding;
+mod fetch;
 mod librs;
+mod limits;
+mod read_file;
+pub mod rust_run;
+pub mod write_file;
 mod zsh;
 
-/// The command we are executing
+/// The command we are executing. Deserialized from its lowercase name in a
+/// [`Plan`](crate::plan::Plan).
 #[derive(Discriminant)]
-enum Cmd {
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cmd {
     /// a zsh script to execute
     Zsh,
     /// a bash script to execute
     Bash,
     /// Search for a crate on lib.rs
     LibRs,
+    /// Read the documentation of a crate item on docs.rs
+    DocsRs,
+    /// Search crates.io for crates
+    CrateSearch,
+    /// Download a web page as markdown
+    Fetch,
+    /// Generate the code of a file
+    CodeGen,
+    /// Run cargo in the working directory of the session
+    Cargo,
+    /// Compile a single Rust file and run it
+    RustRun,
+    /// Read a file in the working directory
+    ReadFile,
+    /// Write a file in the working directory
+    WriteFile,
+    /// Apply a unified diff to files in the working directory
+    ApplyPatch,
+}
+
+impl Cmd {
+    pub const ALL: [Self; 12] = [
+        Self::Zsh,
+        Self::Bash,
+        Self::LibRs,
+        Self::DocsRs,
+        Self::CrateSearch,
+        Self::Fetch,
+        Self::CodeGen,
+        Self::Cargo,
+        Self::RustRun,
+        Self::ReadFile,
+        Self::WriteFile,
+        Self::ApplyPatch,
+    ];
+
+    /// The header line naming the command
+    #[must_use]
+    pub fn header(self) -> String {
+        ron::to_string(&self).expect("a unit variant always serializes")
+    }
+
+    /// Split `input` into the command named by its header line and its args.
+    ///
+    /// The header is case-insensitive and may be surrounded by whitespace.
+    ///
+    /// # Errors
+    /// If the header is empty or does not name a command.
+    pub fn parse(input: &str) -> anyhow::Result<(Self, &str)> {
+        let (header, args) = input.split_once('\n').unwrap_or((input, ""));
+        let header = header.trim();
+
+        ensure!(!header.is_empty(), "The command header is empty");
+
+        let cmd = ron::from_str(&header.to_lowercase()).map_err(|_| {
+            let names: Vec<_> = Self::ALL.iter().map(|cmd| cmd.header()).collect();
+            anyhow!(
+                "`{header}` is not a command, expected one of {}",
+                names.join(", ")
+            )
+        })?;
+
+        Ok((cmd, args))
+    }
+}
+
+/// A line of output or the end of a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandEvent {
+    Stdout(String),
+    Stderr(String),
+    /// `lines` lines of output were not valid UTF-8 and had bytes replaced. Sent before the
+    /// [`CommandEvent::Exit`].
+    InvalidUtf8 {
+        lines: usize,
+    },
+    /// The exit code. Always the last event, unless the stream ends with an error.
+    Exit(i32),
+}
+
+/// The output of a command as it is produced
+pub type CommandStream<'a> = BoxStream<'a, anyhow::Result<CommandEvent>>;
+
+pub trait Command {
+    /// Execute the command with `dir` as the working directory.
+    ///
+    /// Dropping the stream stops the command.
+    fn execute<'a>(&'a self, ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a>;
+}
+
+/// Stream the stdout and stderr of `cmd` line by line.
+///
+/// Lines that are not valid UTF-8 are decoded lossily and lines of binary data are hex dumped,
+/// see [`encoding`]. The command is killed with a [`LimitExceeded`] error once it exceeds its
+/// `limits`, with every process it started. It gets the secrets and the scratch directory of the
+/// running step, see [`secrets::Env::scope`] and [`scratch::scope`].
+fn spawn(mut cmd: tokio::process::Command, limits: Limits) -> CommandStream<'static> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    limits.apply(&mut cmd);
+    Group::apply(&mut cmd);
+    secrets::Env::apply(&mut cmd);
+    scratch::apply(&mut cmd);
+
+    tokio::spawn(async move {
+        if let Err(e) = forward(&mut cmd, limits, &tx).await {
+            let _ = tx.send(Err(e));
+        }
+    });
+
+    UnboundedReceiverStream::new(rx).boxed()
+}
+
+async fn forward(
+    cmd: &mut tokio::process::Command,
+    limits: Limits,
+    tx: &mpsc::UnboundedSender<anyhow::Result<CommandEvent>>,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + limits.timeout;
+    let timed_out = LimitExceeded::TimedOut {
+        after: limits.timeout,
+    };
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start the command")?;
+    // killed on every return before the command exited by itself
+    let group = Group::of(&child);
+
+    let mut stdout = BufReader::new(child.stdout.take().context("stdout is piped")?);
+    let mut stderr = BufReader::new(child.stderr.take().context("stderr is piped")?);
+    // `read_until` keeps what it read so far when another branch wins the select, so a line is
+    // complete once it returns. Only the end of the stream leaves the line empty.
+    let (mut stdout_line, mut stderr_line) = (Vec::new(), Vec::new());
+    let (mut stdout_done, mut stderr_done) = (false, false);
+    let mut bytes = 0;
+    let mut invalid_lines = 0;
+
+    while !(stdout_done && stderr_done) {
+        let (line, is_stdout) = tokio::select! {
+            read = stdout.read_until(b'\n', &mut stdout_line), if !stdout_done => {
+                read?;
+                if stdout_line.is_empty() {
+                    stdout_done = true;
+                    continue;
+                }
+                (std::mem::take(&mut stdout_line), true)
+            },
+            read = stderr.read_until(b'\n', &mut stderr_line), if !stderr_done => {
+                read?;
+                if stderr_line.is_empty() {
+                    stderr_done = true;
+                    continue;
+                }
+                (std::mem::take(&mut stderr_line), false)
+            },
+            // the stream was dropped, dropping `child` and `group` kills it
+            () = tx.closed() => return Ok(()),
+            () = tokio::time::sleep_until(deadline) => return Err(timed_out.into()),
+        };
+
+        bytes += line.len();
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let (line, replaced) = encoding::text(line);
+        invalid_lines += usize::from(replaced);
+
+        let event = match is_stdout {
+            true => CommandEvent::Stdout(line),
+            false => CommandEvent::Stderr(line),
+        };
+
+        if bytes > limits.max_output {
+            return Err(LimitExceeded::OutputTooLarge {
+                bytes: limits.max_output,
+            }
+            .into());
+        }
+
+        if tx.send(Ok(event)).is_err() {
+            return Ok(());
+        }
+    }
+
+    // the output can be closed before the process exits
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let status = utils::with_timeout(remaining, child.wait())
+        .await
+        .map_err(|_| timed_out)??;
+    group.release();
+
+    if invalid_lines > 0 {
+        let _ = tx.send(Ok(CommandEvent::InvalidUtf8 {
+            lines: invalid_lines,
+        }));
+    }
+
+    // killed by a signal
+    let code = status.code().unwrap_or(-1);
+    let _ = tx.send(Ok(CommandEvent::Exit(code)));
+
+    Ok(())
 }
 
-#[async_trait]
-trait Command {
-    async fn execute(&self, ctx: Ctx, input: &str) -> anyhow::Result<String>;
+/// Stream the result of a command that produces its output all at once.
+fn buffered<'a>(
+    output: impl Future<Output = anyhow::Result<String>> + Send + 'a,
+) -> CommandStream<'a> {
+    stream::once(output)
+        .flat_map(|output| {
+            let events = match output {
+                Ok(output) => output
+                    .lines()
+                    .map(|line| Ok(CommandEvent::Stdout(line.to_string())))
+                    .chain([Ok(CommandEvent::Exit(0))])
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(events)
+        })
+        .boxed()
+}
+
+/// Wait for the command to finish, returning its stdout.
+///
+/// # Errors
+/// If the command failed, with its stderr.
+#[cfg(test)]
+pub async fn collect(mut events: CommandStream<'_>) -> anyhow::Result<String> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+
+    while let Some(event) = events.next().await {
+        match event? {
+            CommandEvent::Stdout(line) => stdout.push(line),
+            CommandEvent::Stderr(line) => stderr.push(line),
+            CommandEvent::InvalidUtf8 { .. } => {}
+            CommandEvent::Exit(0) => return Ok(stdout.join("\n")),
+            CommandEvent::Exit(code) => anyhow::bail!("exited with {code}:\n{}", stderr.join("\n")),
+        }
+    }
+
+    anyhow::bail!("the command ended without an exit code")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cmd;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        assert_eq!(
+            Cmd::parse("bash\necho hello\necho there")?,
+            (Cmd::Bash, "echo hello\necho there")
+        );
+        assert_eq!(
+            Cmd::parse("  CodeGen \nsrc/main.rs")?,
+            (Cmd::CodeGen, "src/main.rs")
+        );
+        assert_eq!(Cmd::parse("readfile")?, (Cmd::ReadFile, ""));
+
+        let err = Cmd::parse("python\nprint()").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`python` is not a command, expected one of zsh, bash, librs, docsrs, cratesearch, \
+             fetch, codegen, cargo, rustrun, readfile, writefile, applypatch"
+        );
+        assert!(Cmd::parse("\necho hello").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        for cmd in Cmd::ALL {
+            let input = format!("{}\nline one\nline two", cmd.header());
+            assert_eq!(Cmd::parse(&input)?, (cmd, "line one\nline two"));
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,188 @@
+//! Apply a unified diff to files in the working directory of the session.
+//!
+//! Either every file is patched or, if any hunk does not apply, none is and the conflicts are
+//! reported.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    command::{buffered, ApplyPatch, Command, CommandStream},
+    file::read,
+    patch, workspace, Ctx,
+};
+
+/// What a patch does to a file once it applies
+enum Change {
+    Write { path: PathBuf, content: String },
+    Remove(PathBuf),
+}
+
+impl Command for ApplyPatch {
+    fn execute<'a>(&'a self, _ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(apply(dir, input))
+    }
+}
+
+async fn apply(dir: &Path, input: &str) -> anyhow::Result<String> {
+    let patches = patch::parse(input)?;
+
+    let mut changes = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut summary = Vec::new();
+
+    for patch in &patches {
+        let old = patch
+            .old
+            .as_deref()
+            .map(|path| workspace::resolve(dir, path))
+            .transpose()?;
+        let new = patch
+            .new
+            .as_deref()
+            .map(|path| workspace::resolve(dir, path))
+            .transpose()?;
+
+        let original = match &old {
+            Some(old) => read(old)
+                .await?
+                .with_context(|| format!("{} does not exist", patch.path()))?,
+            None => {
+                let new = new.as_deref().context("a patch has at least one path")?;
+                ensure!(
+                    read(new).await?.is_none(),
+                    "{} already exists",
+                    patch.path()
+                );
+                String::new()
+            }
+        };
+
+        let patched = match patch.apply(&original) {
+            Ok(patched) => patched,
+            Err(found) => {
+                conflicts.extend(
+                    found
+                        .into_iter()
+                        .map(|conflict| format!("{}: {conflict}", patch.path())),
+                );
+                continue;
+            }
+        };
+
+        match (old, new) {
+            (Some(old), None) => {
+                changes.push(Change::Remove(old));
+                summary.push(format!("deleted {}", patch.path()));
+            }
+            (old, Some(new)) => {
+                // a renamed file
+                if let Some(old) = old.filter(|old| *old != new) {
+                    changes.push(Change::Remove(old));
+                }
+                changes.push(Change::Write {
+                    path: new,
+                    content: patched,
+                });
+                summary.push(format!("patched {}", patch.path()));
+            }
+            (None, None) => bail!("a patch has at least one path"),
+        }
+    }
+
+    ensure!(
+        conflicts.is_empty(),
+        "the patch does not apply, no file was changed:\n{}",
+        conflicts.join("\n")
+    );
+
+    for change in changes {
+        match change {
+            Change::Write { path, content } => {
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&path, content)
+                    .await
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+            Change::Remove(path) => tokio::fs::remove_file(&path)
+                .await
+                .with_context(|| format!("Failed to delete {}", path.display()))?,
+        }
+    }
+
+    Ok(summary.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::{collect, ApplyPatch, Command},
+        ctx,
+    };
+
+    const DIFF: &str = "--- a/greet.txt
++++ b/greet.txt
+@@ -1,2 +1,2 @@
+ hello
+-world
++there
+--- /dev/null
++++ b/new/file.txt
+@@ -0,0 +1 @@
++created
+";
+
+    #[tokio::test]
+    async fn test_apply() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("greet.txt"), "hello\nworld\n")?;
+
+        let output = collect(ApplyPatch.execute(ctx()?, dir.path(), DIFF)).await?;
+
+        assert_eq!(output, "patched greet.txt\npatched new/file.txt");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("greet.txt"))?,
+            "hello\nthere\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("new/file.txt"))?,
+            "created\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conflict() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("greet.txt"), "hello\nmoon\n")?;
+
+        let e = collect(ApplyPatch.execute(ctx()?, dir.path(), DIFF))
+            .await
+            .unwrap_err();
+
+        assert!(e
+            .to_string()
+            .contains("greet.txt: hunk 1 does not apply at line 2"));
+
+        // nothing was applied, not even the file without conflicts
+        assert!(!dir.path().join("new/file.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outside() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let diff = "--- /dev/null\n+++ b/../escape.txt\n@@ -0,0 +1 @@\n+escaped\n";
+
+        assert!(collect(ApplyPatch.execute(ctx()?, dir.path(), diff))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
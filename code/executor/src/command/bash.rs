@@ -1,40 +1,38 @@
-use anyhow::{ensure, Context};
-use async_trait::async_trait;
-use utils::str::StringExt;
+use std::path::Path;
 
 use crate::{
-    command::{Bash, Command},
+    command::{spawn, Bash, Command, CommandStream},
     Ctx,
 };
 
-#[async_trait]
 impl Command for Bash {
-    async fn execute(&self, _exec: Ctx, input: &str) -> anyhow::Result<String> {
-        let output = tokio::process::Command::new("bash")
-            .arg("-c")
-            .arg(input)
-            .output()
-            .await?;
-
-        ensure!(output.status.success(), "bash command failed");
-
-        let mut output = String::from_utf8(output.stdout).context("could not parse to UTF-8")?;
-        output.trim_end_in_place(); // remove trailing newline
-
-        Ok(output)
+    fn execute<'a>(&'a self, ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        let mut cmd = tokio::process::Command::new("bash");
+        cmd.arg("-c").arg(input).current_dir(dir);
+        spawn(cmd, ctx.limits())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{command::Command, ctx};
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use futures::StreamExt;
+
+    use crate::{
+        command::{collect, Command, CommandEvent, LimitExceeded, Limits},
+        ctx, ctx_with,
+        policy::Policy,
+        remote::Credentials,
+        Network, Prompts,
+    };
 
     #[tokio::test]
     async fn test_oneline() -> anyhow::Result<()> {
         let exec = ctx()?;
         let cmd = super::Bash;
 
-        let output = cmd.execute(exec, "echo hello there").await?;
+        let output = collect(cmd.execute(exec, Path::new("."), "echo hello there")).await?;
 
         assert_eq!(output, "hello there");
 
@@ -49,10 +47,156 @@ mod tests {
         let input = r#"echo hello
         echo there"#;
 
-        let output = cmd.execute(exec, input).await?;
+        let output = collect(cmd.execute(exec, Path::new("."), input)).await?;
 
         assert_eq!(output, "hello\nthere");
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_events() -> anyhow::Result<()> {
+        let exec = ctx()?;
+        let cmd = super::Bash;
+
+        let input = "echo building; echo 'missing file' >&2; exit 3";
+        let events: Vec<_> = cmd
+            .execute(exec, Path::new("."), input)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<_>>()?;
+
+        assert!(events.contains(&CommandEvent::Stdout("building".to_string())));
+        assert!(events.contains(&CommandEvent::Stderr("missing file".to_string())));
+        assert_eq!(events.last(), Some(&CommandEvent::Exit(3)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_not_utf8() -> anyhow::Result<()> {
+        let exec = ctx()?;
+        let cmd = super::Bash;
+
+        // Latin-1, UTF-8 and binary output, the last line without a newline
+        let input = r"printf 'caf\xe9\n'; printf 'na\xc3\xafve\r\n'; printf '\x00\x01'";
+        let events: Vec<_> = cmd
+            .execute(exec, Path::new("."), input)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<_>>()?;
+
+        assert_eq!(events, [
+            CommandEvent::Stdout("caf\u{fffd}".to_string()),
+            CommandEvent::Stdout("naïve".to_string()),
+            CommandEvent::Stdout("binary data, 2 bytes\n00000000  00 01".to_string()),
+            CommandEvent::InvalidUtf8 { lines: 1 },
+            CommandEvent::Exit(0),
+        ]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limits() -> anyhow::Result<()> {
+        let limits = Limits {
+            timeout: Duration::from_secs(1),
+            max_output: 1000,
+            nice: Some(5),
+            ..Limits::default()
+        };
+        let exec = ctx_with(
+            None,
+            Prompts::default(),
+            limits,
+            Policy::default(),
+            Credentials::default(),
+            &Network::default(),
+            false,
+        )
+        .map(Arc::new)?;
+        let cmd = super::Bash;
+
+        let err = collect(cmd.execute(exec.clone(), Path::new("."), "echo started; sleep 30"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&LimitExceeded::TimedOut {
+                after: Duration::from_secs(1)
+            })
+        );
+
+        let err = collect(cmd.execute(exec.clone(), Path::new("."), "yes"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&LimitExceeded::OutputTooLarge { bytes: 1000 })
+        );
+
+        let niceness = collect(cmd.execute(exec, Path::new("."), "nice")).await?;
+        assert_eq!(niceness, "5");
+
+        Ok(())
+    }
+
+    /// Whether a process of the group `group` is still running, not counting zombies
+    #[cfg(target_os = "linux")]
+    fn running(group: &str) -> anyhow::Result<bool> {
+        for entry in std::fs::read_dir("/proc")? {
+            // processes can exit while they are listed
+            let Ok(stat) = std::fs::read_to_string(entry?.path().join("stat")) else {
+                continue;
+            };
+            // pid (comm) state ppid pgrp ...
+            let Some((_, fields)) = stat.rsplit_once(')') else {
+                continue;
+            };
+            let fields: Vec<_> = fields.split_whitespace().collect();
+            if fields.get(2) == Some(&group) && fields.first() != Some(&"Z") {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_pipeline_killed() -> anyhow::Result<()> {
+        let limits = Limits {
+            timeout: Duration::from_millis(500),
+            ..Limits::default()
+        };
+        let exec = ctx_with(
+            None,
+            Prompts::default(),
+            limits,
+            Policy::default(),
+            Credentials::default(),
+            &Network::default(),
+            false,
+        )
+        .map(Arc::new)?;
+
+        let mut events = super::Bash.execute(exec, Path::new("."), "echo $$; sleep 30 | cat");
+        let Some(Ok(CommandEvent::Stdout(group))) = events.next().await else {
+            anyhow::bail!("the command did not print its process group");
+        };
+        assert!(running(&group)?);
+
+        let err = events.next().await.and_then(Result::err);
+        assert!(err.is_some_and(|e| e.is::<LimitExceeded>()));
+
+        // the signal may take a moment to be delivered
+        for _ in 0..50 {
+            if !running(&group)? {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        anyhow::bail!("the pipeline still runs after the timeout")
+    }
 }
@@ -0,0 +1,253 @@
+//! Run cargo in the working directory of the session.
+//!
+//! The input is the cargo invocation without `cargo`, e.g. `new calculator` or
+//! `build --manifest-path calculator/Cargo.toml`. Only `new`, `build`, `test`, `run` and `add` are
+//! allowed.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+
+use crate::{
+    command::{spawn, Cargo, Command, CommandEvent, CommandStream},
+    Ctx,
+};
+
+const SUBCOMMANDS: [&str; 5] = ["new", "build", "test", "run", "add"];
+
+/// The message cargo prints for every compiled artifact with `--message-format=json`
+#[derive(Deserialize)]
+struct Artifact {
+    reason: String,
+    executable: Option<PathBuf>,
+}
+
+/// A message cargo prints with `--message-format=json`, only diagnostics are read
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    message: Option<Diagnostic>,
+}
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    level: String,
+    rendered: Option<String>,
+    spans: Vec<Span>,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: PathBuf,
+    is_primary: bool,
+}
+
+/// An error the compiler reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// the file of the error, relative to the workspace of the package
+    pub file: PathBuf,
+    /// the error as the compiler shows it
+    pub rendered: String,
+}
+
+/// The errors `cargo check` reports for the package of `manifest`, none if it compiles.
+///
+/// # Errors
+/// If cargo fails without reporting errors, e.g. because the manifest is invalid
+pub async fn check(ctx: Ctx, manifest: &Path) -> anyhow::Result<Vec<CompileError>> {
+    let mut cmd = tokio::process::Command::new("cargo");
+    cmd.args([
+        "check",
+        "--quiet",
+        "--message-format=json",
+        "--manifest-path",
+    ])
+    .arg(manifest);
+    if let Some(dir) = manifest.parent() {
+        cmd.current_dir(dir);
+    }
+
+    let mut events = spawn(cmd, ctx.limits());
+    let mut errors = Vec::new();
+    let mut stderr = Vec::new();
+    while let Some(event) = events.next().await {
+        match event? {
+            CommandEvent::Stdout(line) => errors.extend(error(&line)),
+            CommandEvent::Stderr(line) => stderr.push(line),
+            CommandEvent::Exit(0) => return Ok(Vec::new()),
+            CommandEvent::Exit(_) if errors.is_empty() => {
+                bail!("cargo check failed: {}", stderr.join("\n"))
+            }
+            CommandEvent::Exit(_) => return Ok(errors),
+            CommandEvent::InvalidUtf8 { .. } => {}
+        }
+    }
+
+    bail!("cargo check ended without an exit code")
+}
+
+/// The error in the JSON message `line` of cargo, if it is one. Summaries such as `aborting due to
+/// 2 previous errors` have no file and are skipped.
+fn error(line: &str) -> Option<CompileError> {
+    let message: Message = serde_json::from_str(line).ok()?;
+    let diagnostic = message.message?;
+    if message.reason != "compiler-message" || diagnostic.level != "error" {
+        return None;
+    }
+
+    let span = diagnostic.spans.into_iter().find(|span| span.is_primary)?;
+    Some(CompileError {
+        file: span.file_name,
+        rendered: diagnostic.rendered?.trim_end().to_string(),
+    })
+}
+
+/// `cargo {input}` in `dir`, and whether it compiles.
+///
+/// # Errors
+/// If the subcommand is not allowed.
+fn command(dir: &Path, input: &str) -> anyhow::Result<(tokio::process::Command, bool)> {
+    let args: Vec<_> = input.split_whitespace().collect();
+
+    let Some(&subcommand) = args.first() else {
+        bail!("cargo needs a subcommand");
+    };
+
+    ensure!(
+        SUBCOMMANDS.contains(&subcommand),
+        "cargo {subcommand} is not allowed, use one of {}",
+        SUBCOMMANDS.join(", ")
+    );
+
+    let compiles = matches!(subcommand, "build" | "test" | "run");
+
+    let mut cmd = tokio::process::Command::new("cargo");
+    cmd.arg(subcommand);
+
+    // compiler messages go to stdout as JSON so the artifacts can be collected, while the
+    // diagnostics are still rendered to stderr
+    if compiles {
+        cmd.arg("--message-format=json-render-diagnostics");
+    }
+
+    cmd.args(&args[1..]).current_dir(dir);
+
+    Ok((cmd, compiles))
+}
+
+impl Command for Cargo {
+    fn execute<'a>(&'a self, ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        let (cmd, compiles) = match command(dir, input) {
+            Ok(cmd) => cmd,
+            Err(e) => return stream::once(async { Err(e) }).boxed(),
+        };
+
+        // show the compiled executables instead of the JSON compiler messages
+        spawn(cmd, ctx.limits())
+            .filter_map(move |event| async move {
+                let Ok(CommandEvent::Stdout(line)) = &event else {
+                    return Some(event);
+                };
+
+                match serde_json::from_str::<Artifact>(line) {
+                    Ok(artifact) if compiles => {
+                        match (artifact.reason.as_str(), artifact.executable) {
+                            ("compiler-artifact", Some(executable)) => Some(Ok(
+                                CommandEvent::Stdout(format!("artifact: {}", executable.display())),
+                            )),
+                            _ => None,
+                        }
+                    }
+                    _ => Some(event),
+                }
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{check, error, CompileError};
+    use crate::{
+        command::{collect, Cargo, Command},
+        ctx,
+    };
+
+    #[test]
+    fn test_error() {
+        let line = r#"{"reason":"compiler-message","message":{"level":"error","rendered":"error[E0425]: cannot find value `x`\n","spans":[{"file_name":"src/main.rs","is_primary":true}]}}"#;
+        assert_eq!(
+            error(line),
+            Some(CompileError {
+                file: PathBuf::from("src/main.rs"),
+                rendered: "error[E0425]: cannot find value `x`".to_string(),
+            })
+        );
+
+        let warning = line.replace(r#""level":"error""#, r#""level":"warning""#);
+        assert_eq!(error(&warning), None);
+        let summary = r#"{"reason":"compiler-message","message":{"level":"error","rendered":"error: aborting due to 1 previous error\n","spans":[]}}"#;
+        assert_eq!(error(summary), None);
+        assert_eq!(
+            error(r#"{"reason":"build-finished","success":false}"#),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        collect(Cargo.execute(ctx()?, dir.path(), "new hello --vcs none")).await?;
+        let manifest = dir.path().join("hello/Cargo.toml");
+
+        assert!(check(ctx()?, &manifest).await?.is_empty());
+
+        std::fs::write(
+            dir.path().join("hello/src/main.rs"),
+            "fn main() { let x: u32 = \"one\"; }",
+        )?;
+        let errors = check(ctx()?, &manifest).await?;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, PathBuf::from("src/main.rs"));
+        assert!(errors[0].rendered.contains("mismatched types"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_new_and_run() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        collect(Cargo.execute(ctx()?, dir.path(), "new hello --vcs none")).await?;
+        assert!(dir.path().join("hello/Cargo.toml").is_file());
+
+        let output =
+            collect(Cargo.execute(ctx()?, dir.path(), "run --manifest-path hello/Cargo.toml"))
+                .await?;
+
+        let lines: Vec<_> = output.lines().collect();
+        assert!(lines.contains(&"Hello, world!"), "{output}");
+        assert!(lines.iter().any(|line| line.starts_with("artifact: ")));
+
+        // the JSON compiler messages are not shown
+        assert!(!output.contains("\"reason\""));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disallowed() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        assert!(collect(Cargo.execute(ctx()?, &dir, "publish"))
+            .await
+            .is_err());
+        assert!(collect(Cargo.execute(ctx()?, &dir, "")).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Generate the code of a file.
+//!
+//! The input is the path of the file on the first line, followed by a description of its content.
+
+use std::path::Path;
+
+use anyhow::Context;
+use tokio_openai::ChatRequest;
+
+use crate::{
+    command::{buffered, CodeGen, Command, CommandStream},
+    prompts::Prompt,
+    Ctx,
+};
+
+/// Split the input into the path and the description of the file.
+///
+/// # Errors
+/// If there is no path or no description.
+pub fn split_input(input: &str) -> anyhow::Result<(&str, &str)> {
+    let (path, description) = input
+        .trim()
+        .split_once('\n')
+        .context("codegen needs a path and a description")?;
+
+    Ok((path.trim(), description.trim()))
+}
+
+/// The default system prompt
+pub const SYSTEM_PROMPT: &str = "You write the content of a single file. Only output the content \
+                                 of the file. Do not wrap it in markdown code fences and do not \
+                                 explain it.";
+
+/// The request to generate the file at `path`.
+///
+/// `context` describes what happened so far, e.g. the instruction and the output of previous
+/// steps.
+#[must_use]
+pub fn request(system: &str, path: &str, description: &str, context: &str) -> ChatRequest {
+    ChatRequest::new().sys_msg(system).user_msg(format!(
+        "{context}\n---\nWrite the file `{path}`.\n\n{description}"
+    ))
+}
+
+/// How many errors of `cargo check` are sent back to the model at once. The first ones often
+/// cause the others.
+const MAX_ERRORS: usize = 10;
+
+/// The message asking the model to generate the file again without the `errors` it has.
+#[must_use]
+pub fn correction(errors: &[String]) -> String {
+    let shown = errors.len().min(MAX_ERRORS);
+    let mut message = format!(
+        "The file does not compile, `cargo check` reports {} errors:\n\n{}",
+        errors.len(),
+        errors[..shown].join("\n\n")
+    );
+    if shown < errors.len() {
+        message.push_str(&format!("\n\n... and {} more", errors.len() - shown));
+    }
+    message.push_str("\n\nWrite the whole file again with the errors fixed.");
+    message
+}
+
+impl Command for CodeGen {
+    fn execute<'a>(&'a self, ctx: Ctx, _dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(generate(ctx, input))
+    }
+}
+
+async fn generate(ctx: Ctx, input: &str) -> anyhow::Result<String> {
+    let (path, description) = split_input(input)?;
+    let system = ctx.prompts.render(Prompt::CodeGen, &[("path", path)]);
+    ctx.chat(request(&system, path, description, "")).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{correction, split_input};
+
+    #[test]
+    fn test_split_input() -> anyhow::Result<()> {
+        let (path, description) = split_input("src/main.rs\nA calculator\nthat adds numbers\n")?;
+
+        assert_eq!(path, "src/main.rs");
+        assert_eq!(description, "A calculator\nthat adds numbers");
+
+        assert!(split_input("src/main.rs").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_correction() {
+        let errors: Vec<_> = (0..12).map(|i| format!("error {i}")).collect();
+        let message = correction(&errors);
+
+        assert!(message.contains("reports 12 errors"));
+        assert!(message.contains("error 9"));
+        assert!(!message.contains("error 10"));
+        assert!(message.contains("... and 2 more"));
+    }
+}
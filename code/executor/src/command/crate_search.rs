@@ -0,0 +1,207 @@
+//! Search crates.io for crates matching a query.
+//!
+//! The output is one JSON object per line, most relevant first, so the planner can compare
+//! candidates by downloads and version before picking a dependency.
+
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use reqwest::{header::USER_AGENT, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::{buffered, Command, CommandStream, CrateSearch},
+    Ctx,
+};
+
+const CRATES_URL: &str = "https://crates.io/api/v1/crates";
+
+/// crates.io rejects requests without a user agent
+const AGENT: &str = "collective (https://github.com/getcollective-ai/collective)";
+
+/// How many crates a search returns
+const MAX_RESULTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateInfo {
+    pub name: String,
+    pub description: String,
+    pub downloads: u64,
+    pub latest_version: String,
+}
+
+/// A crate as returned by the crates.io API
+#[derive(Deserialize)]
+struct ApiCrate {
+    name: String,
+    description: Option<String>,
+    downloads: u64,
+    max_stable_version: Option<String>,
+    max_version: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    crates: Vec<ApiCrate>,
+}
+
+#[derive(Deserialize)]
+struct ApiLookup {
+    #[serde(rename = "crate")]
+    krate: ApiCrate,
+}
+
+impl From<ApiCrate> for CrateInfo {
+    fn from(api: ApiCrate) -> Self {
+        Self {
+            name: api.name,
+            description: api.description.unwrap_or_default().trim().to_string(),
+            downloads: api.downloads,
+            // prereleases only if there is no stable release
+            latest_version: api.max_stable_version.unwrap_or(api.max_version),
+        }
+    }
+}
+
+impl Command for CrateSearch {
+    fn execute<'a>(&'a self, ctx: Ctx, _dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(async move {
+            let crates = search(&ctx, input).await?;
+            ensure!(!crates.is_empty(), "No crates match `{}`", input.trim());
+
+            let lines = crates
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(lines.join("\n"))
+        })
+    }
+}
+
+/// The crates matching `query`, most relevant first.
+///
+/// # Errors
+/// If the query is empty or crates.io cannot be reached.
+pub async fn search(ctx: &Ctx, query: &str) -> anyhow::Result<Vec<CrateInfo>> {
+    let query = query.trim();
+    ensure!(!query.is_empty(), "The search query is empty");
+
+    let per_page = MAX_RESULTS.to_string();
+    let response = ctx
+        .req
+        .get(CRATES_URL)
+        .header(USER_AGENT, AGENT)
+        .query(&[("q", query), ("per_page", &per_page), ("sort", "relevance")])
+        .send()
+        .await?
+        .error_for_status()
+        .context("Failed to search crates.io")?;
+
+    parse(&response.text().await?)
+}
+
+/// The crate called `name`, `None` if there is none. Dashes and underscores are interchangeable,
+/// the returned name is the one the crate was published with.
+///
+/// # Errors
+/// If crates.io cannot be reached.
+pub async fn lookup(ctx: &Ctx, name: &str) -> anyhow::Result<Option<CrateInfo>> {
+    let response = ctx
+        .req
+        .get(format!("{CRATES_URL}/{name}"))
+        .header(USER_AGENT, AGENT)
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let json = response
+        .error_for_status()
+        .with_context(|| format!("Failed to look up {name} on crates.io"))?
+        .text()
+        .await?;
+
+    let lookup: ApiLookup =
+        serde_json::from_str(&json).context("Unexpected response from crates.io")?;
+    Ok(Some(lookup.krate.into()))
+}
+
+fn parse(json: &str) -> anyhow::Result<Vec<CrateInfo>> {
+    let response: ApiResponse =
+        serde_json::from_str(json).context("Unexpected response from crates.io")?;
+
+    Ok(response.crates.into_iter().map(CrateInfo::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse, CrateInfo};
+    use crate::{
+        command::{collect, Command, CrateSearch},
+        ctx,
+    };
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let json = r#"{
+            "crates": [
+                {
+                    "name": "serde",
+                    "description": "A generic serialization/deserialization framework\n",
+                    "downloads": 300000000,
+                    "max_stable_version": "1.0.160",
+                    "max_version": "1.0.160",
+                    "repository": "https://github.com/serde-rs/serde"
+                },
+                {
+                    "name": "serde-next",
+                    "description": null,
+                    "downloads": 12,
+                    "max_stable_version": null,
+                    "max_version": "0.1.0-alpha.1"
+                }
+            ],
+            "meta": { "total": 2 }
+        }"#;
+
+        assert_eq!(parse(json)?, [
+            CrateInfo {
+                name: "serde".to_string(),
+                description: "A generic serialization/deserialization framework".to_string(),
+                downloads: 300_000_000,
+                latest_version: "1.0.160".to_string(),
+            },
+            CrateInfo {
+                name: "serde-next".to_string(),
+                description: String::new(),
+                downloads: 12,
+                latest_version: "0.1.0-alpha.1".to_string(),
+            },
+        ]);
+
+        assert!(parse("<html></html>").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_empty_query() -> anyhow::Result<()> {
+        let output = collect(CrateSearch.execute(ctx()?, Path::new("."), "  ")).await;
+        assert!(output.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs network access"]
+    async fn test_search() -> anyhow::Result<()> {
+        let output = collect(CrateSearch.execute(ctx()?, Path::new("."), "async runtime")).await?;
+        assert!(output.contains("\"name\":\"tokio\""));
+
+        Ok(())
+    }
+}
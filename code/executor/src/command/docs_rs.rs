@@ -0,0 +1,148 @@
+//! Read the documentation of a crate item on docs.rs as markdown.
+//!
+//! The input is a path such as `tokio`, `tokio::sync::mpsc` or `tokio::sync::mpsc::channel`.
+//! Rustdoc names item pages after their kind (`fn.channel.html`), which the path does not say, so
+//! the page is looked up in the list of all items of the crate. Paths that are not an item are
+//! modules.
+
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use html_to_md::HtmlToMd;
+
+use crate::{
+    command::{buffered, Command, CommandStream, DocsRs},
+    Ctx,
+};
+
+const DOCS_RS: &str = "https://docs.rs";
+
+/// The kinds of items rustdoc writes a page for, as used in the name of the page
+const ITEM_KINDS: &[&str] = &[
+    "struct",
+    "enum",
+    "trait",
+    "fn",
+    "macro",
+    "type",
+    "constant",
+    "static",
+    "union",
+    "attr",
+    "derive",
+    "traitalias",
+];
+
+impl Command for DocsRs {
+    fn execute<'a>(&'a self, ctx: Ctx, _dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(docs(ctx, input))
+    }
+}
+
+async fn docs(ctx: Ctx, input: &str) -> anyhow::Result<String> {
+    let path = input.trim();
+    let segments: Vec<_> = path.split("::").collect();
+
+    ensure!(
+        segments.iter().all(|segment| is_ident(segment)),
+        "`{path}` is not a path like tokio::sync::mpsc::channel"
+    );
+
+    let (krate, rest) = segments.split_first().context("The path is empty")?;
+    let base = format!("{DOCS_RS}/{krate}/latest/{}", krate.replace('-', "_"));
+
+    let page = match rest.split_last() {
+        None => "index.html".to_string(),
+        Some((name, module)) => {
+            let all = get(&ctx, &format!("{base}/all.html")).await?;
+            item_page(&all, module, name)
+                .unwrap_or_else(|| format!("{}/index.html", rest.join("/")))
+        }
+    };
+
+    let html = get(&ctx, &format!("{base}/{page}")).await?;
+
+    HtmlToMd::new(&html)
+        .id("main-content")
+        .run()
+        .with_context(|| format!("Failed to convert the documentation of {path}"))
+}
+
+async fn get(ctx: &Ctx, url: &str) -> anyhow::Result<String> {
+    let response = ctx
+        .req
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to fetch {url}"))?;
+
+    Ok(response.text().await?)
+}
+
+/// Crate names may contain dashes, the rest of the path is Rust identifiers
+fn is_ident(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The page of the item `name` in `module`, if `all` (the `all.html` of the crate) lists one.
+fn item_page(all: &str, module: &[&str], name: &str) -> Option<String> {
+    let dir: String = module.iter().map(|segment| format!("{segment}/")).collect();
+
+    ITEM_KINDS
+        .iter()
+        .map(|kind| format!("{dir}{kind}.{name}.html"))
+        .find(|page| all.contains(&format!("href=\"{page}\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::item_page;
+    use crate::{
+        command::{collect, Command, DocsRs},
+        ctx,
+    };
+
+    #[test]
+    fn test_item_page() {
+        let all = r#"<ul class="all-items">
+            <li><a href="sync/mpsc/fn.channel.html">sync::mpsc::channel</a></li>
+            <li><a href="sync/mpsc/struct.Sender.html">sync::mpsc::Sender</a></li>
+            <li><a href="macro.select.html">select</a></li>
+        </ul>"#;
+
+        assert_eq!(
+            item_page(all, &["sync", "mpsc"], "channel"),
+            Some("sync/mpsc/fn.channel.html".to_string())
+        );
+        assert_eq!(
+            item_page(all, &[], "select"),
+            Some("macro.select.html".to_string())
+        );
+        assert_eq!(item_page(all, &["sync"], "mpsc"), None);
+        assert_eq!(item_page(all, &[], "channel"), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_path() -> anyhow::Result<()> {
+        let output = collect(DocsRs.execute(ctx()?, Path::new("."), "tokio::sync::<T>")).await;
+        assert!(output.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs network access"]
+    async fn test_docs() -> anyhow::Result<()> {
+        let output =
+            collect(DocsRs.execute(ctx()?, Path::new("."), "tokio::sync::mpsc::channel")).await?;
+        assert!(output.contains("bounded"));
+
+        Ok(())
+    }
+}
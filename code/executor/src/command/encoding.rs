@@ -0,0 +1,95 @@
+//! Output that is not valid UTF-8.
+//!
+//! Text in other encodings, e.g. Latin-1, is decoded lossily: invalid bytes become U+FFFD, so one
+//! stray byte does not fail a step. Binary data is shown as a hex dump of its first
+//! [`MAX_BINARY`] bytes.
+
+use std::borrow::Cow;
+
+/// How many bytes of binary data are dumped
+pub const MAX_BINARY: usize = 256;
+
+/// How far to look for the NUL bytes that make data binary
+const BINARY_SNIFF: usize = 8192;
+
+/// `bytes` as text, and whether invalid bytes had to be replaced.
+#[must_use]
+pub fn decode(bytes: &[u8]) -> (String, bool) {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(text) => (text.to_string(), false),
+        Cow::Owned(text) => (text, true),
+    }
+}
+
+/// Whether `bytes` is binary data rather than text in some encoding
+#[must_use]
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_SNIFF).any(|&byte| byte == 0)
+}
+
+/// The size of `bytes` and a hex dump of its first [`MAX_BINARY`] bytes, 16 per line.
+#[must_use]
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = format!("binary data, {} bytes", bytes.len());
+
+    for (i, chunk) in bytes[..bytes.len().min(MAX_BINARY)].chunks(16).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+        dump.push_str(&format!("\n{:08x}  {}", i * 16, hex.join(" ")));
+    }
+
+    if bytes.len() > MAX_BINARY {
+        dump.push_str("\n...");
+    }
+
+    dump
+}
+
+/// `bytes` as text: a hex dump if it is binary, lossily decoded otherwise. The flag tells
+/// whether bytes were replaced.
+#[must_use]
+pub fn text(bytes: &[u8]) -> (String, bool) {
+    if is_binary(bytes) {
+        (hex_dump(bytes), false)
+    } else {
+        decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, hex_dump, is_binary, text, MAX_BINARY};
+
+    /// "café, naïve" in Latin-1, UTF-8 and both
+    const LATIN_1: &[u8] = b"caf\xe9, na\xefve";
+    const UTF_8: &[u8] = "café, naïve".as_bytes();
+    const MIXED: &[u8] = b"caf\xc3\xa9, na\xefve";
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(UTF_8), ("café, naïve".to_string(), false));
+        assert_eq!(
+            decode(LATIN_1),
+            ("caf\u{fffd}, na\u{fffd}ve".to_string(), true)
+        );
+        assert_eq!(decode(MIXED), ("café, na\u{fffd}ve".to_string(), true));
+    }
+
+    #[test]
+    fn test_binary() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert!(is_binary(png));
+        assert!(!is_binary(LATIN_1));
+
+        let (dump, replaced) = text(png);
+        assert!(!replaced);
+        assert_eq!(
+            dump,
+            "binary data, 16 bytes\n00000000  89 50 4e 47 0d 0a 1a 0a 00 00 00 0d 49 48 44 52"
+        );
+
+        let large = vec![0; MAX_BINARY * 4];
+        let dump = hex_dump(&large);
+        assert_eq!(dump.lines().count(), 1 + MAX_BINARY / 16 + 1);
+        assert!(dump.ends_with("..."));
+    }
+}
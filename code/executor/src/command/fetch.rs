@@ -0,0 +1,140 @@
+//! Download a web page as markdown to include in a prompt.
+//!
+//! The input is the URL. Of an HTML page only the readable content is kept: the `<article>` or
+//! `<main>` element if there is one, the body otherwise. Pages are cut to their first
+//! [`PAGE_CHARS`] characters, at a paragraph or sentence where possible, so they fit in a prompt.
+//! Only domains allowed by the [`Policy`](crate::policy::Policy) can be fetched.
+
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use html_to_md::HtmlToMd;
+use reqwest::{header::CONTENT_TYPE, Url};
+use utils::discretize::Chunker;
+
+use crate::{
+    command::{buffered, Command, CommandStream, Fetch},
+    network, Ctx,
+};
+
+/// How many characters of a page are kept
+const PAGE_CHARS: usize = 4000;
+
+/// The elements holding the content of a page, most specific first
+const CONTENT: &[&str] = &["article", "main", "body"];
+
+impl Command for Fetch {
+    fn execute<'a>(&'a self, ctx: Ctx, _dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(fetch(ctx, input))
+    }
+}
+
+async fn fetch(ctx: Ctx, input: &str) -> anyhow::Result<String> {
+    let url =
+        Url::parse(input.trim()).with_context(|| format!("`{}` is not a URL", input.trim()))?;
+
+    ensure!(
+        matches!(url.scheme(), "http" | "https"),
+        "Only http and https URLs can be fetched"
+    );
+    ensure!(
+        ctx.policy.read().allows_url(&url),
+        "{} is not an allowed domain",
+        url.host_str().unwrap_or_default()
+    );
+
+    let response = ctx
+        .req
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| network::explain(e.into()))?
+        .error_for_status()
+        .with_context(|| format!("Failed to fetch {url}"))?;
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_none_or(|content_type| content_type.contains("html"));
+
+    let body = response.text().await?;
+    let text = if is_html { readable(&body)? } else { body };
+
+    Ok(truncate(&text))
+}
+
+/// The content of the page `html` as markdown
+fn readable(html: &str) -> anyhow::Result<String> {
+    let dom = tl::parse(html, tl::ParserOptions::default()).context("Failed to parse the page")?;
+    let parser = dom.parser();
+
+    let content = CONTENT.iter().find_map(|selector| {
+        let node = dom.query_selector(selector)?.next()?.get(parser)?;
+        Some(node.outer_html(parser).into_owned())
+    });
+
+    HtmlToMd::new(content.as_deref().unwrap_or(html)).run()
+}
+
+/// The first chunk of `text`, marked if there is more
+fn truncate(text: &str) -> String {
+    let chunks = Chunker::new(PAGE_CHARS).split(text);
+
+    match chunks.first() {
+        Some(first) if first.len() < text.len() => format!("{}\n[truncated]", first.trim_end()),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{readable, truncate};
+    use crate::{
+        command::{collect, Command, Fetch},
+        ctx,
+    };
+
+    #[test]
+    fn test_readable() -> anyhow::Result<()> {
+        let html = "<html><body><nav><a href=\"/\">Home</a></nav><article><h1>Title</h1><p>The \
+                    content</p></article><footer>Copyright</footer></body></html>";
+        assert_eq!(readable(html)?, "# TitleThe content");
+
+        let html = "<html><body><nav>Menu</nav><p>Only a body</p></body></html>";
+        assert_eq!(readable(html)?, "Only a body");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short"), "short");
+
+        let long = "word ".repeat(2000);
+        let truncated = truncate(&long);
+        assert!(truncated.len() < long.len());
+        assert!(truncated.ends_with("[truncated]"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_url() -> anyhow::Result<()> {
+        for input in ["not a url", "file:///etc/passwd"] {
+            let output = collect(Fetch.execute(ctx()?, Path::new("."), input)).await;
+            assert!(output.is_err(), "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs network access"]
+    async fn test_fetch() -> anyhow::Result<()> {
+        let output = collect(Fetch.execute(ctx()?, Path::new("."), "https://example.com")).await?;
+        assert!(output.contains("Example Domain"));
+
+        Ok(())
+    }
+}
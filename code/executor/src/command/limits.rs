@@ -0,0 +1,157 @@
+//! Limits for the processes spawned by commands, so a runaway script cannot hang a session or
+//! exhaust the machine.
+
+use std::{
+    fmt::{Display, Formatter},
+    num::ParseIntError,
+    time::Duration,
+};
+
+fn secs(s: &str) -> Result<Duration, ParseIntError> {
+    s.parse().map(Duration::from_secs)
+}
+
+#[derive(clap::Args, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Seconds a command may run before it is killed
+    #[clap(long = "command-timeout", default_value = "600", value_parser = secs)]
+    pub timeout: Duration,
+
+    /// Bytes of output a command may produce before it is killed
+    #[clap(long = "max-output", default_value = "10485760")]
+    pub max_output: usize,
+
+    /// Niceness commands run with (unix only)
+    #[clap(long)]
+    pub nice: Option<i32>,
+
+    /// Bytes of address space a command may use (unix only)
+    #[clap(long = "max-memory")]
+    pub max_memory: Option<u64>,
+
+    /// Seconds of CPU time a command may use (unix only)
+    #[clap(long = "max-cpu")]
+    pub max_cpu: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(600),
+            max_output: 10 * 1024 * 1024,
+            nice: None,
+            max_memory: None,
+            max_cpu: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Apply the process limits to `cmd`. The timeout and output size are enforced while its
+    /// output is read.
+    pub fn apply(&self, cmd: &mut tokio::process::Command) {
+        #[cfg(unix)]
+        {
+            let Self {
+                nice,
+                max_memory,
+                max_cpu,
+                ..
+            } = *self;
+
+            if nice.is_none() && max_memory.is_none() && max_cpu.is_none() {
+                return;
+            }
+
+            // SAFETY: only async-signal-safe libc calls between fork and exec
+            unsafe {
+                cmd.pre_exec(move || {
+                    let check = |result| match result {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    };
+                    // the type of the resource differs between platforms
+                    let rlimit = |resource, value| {
+                        let limit = libc::rlimit {
+                            rlim_cur: value,
+                            rlim_max: value,
+                        };
+                        check(libc::setrlimit(resource, &limit))
+                    };
+
+                    if let Some(nice) = nice {
+                        check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+                    }
+                    if let Some(bytes) = max_memory {
+                        rlimit(libc::RLIMIT_AS, bytes)?;
+                    }
+                    if let Some(secs) = max_cpu {
+                        rlimit(libc::RLIMIT_CPU, secs)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(not(unix))]
+        let _ = cmd;
+    }
+}
+
+/// A command was killed because it exceeded its [`Limits`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    TimedOut { after: Duration },
+    OutputTooLarge { bytes: usize },
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimedOut { after } => write!(f, "timed out after {}s", after.as_secs()),
+            Self::OutputTooLarge { bytes } => {
+                write!(f, "produced more than {bytes} bytes of output")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The process group a command runs in, so the processes it started, e.g. the other commands of a
+/// pipeline or a server in the background, are killed with it. Killed when dropped, unless the
+/// command finished by itself.
+pub struct Group(Option<i32>);
+
+impl Group {
+    /// Make `cmd` start a process group of its own.
+    pub fn apply(cmd: &mut tokio::process::Command) {
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        #[cfg(not(unix))]
+        let _ = cmd;
+    }
+
+    /// The group `child` leads, after [`Group::apply`]
+    pub fn of(child: &tokio::process::Child) -> Self {
+        Self(child.id().and_then(|id| i32::try_from(id).ok()))
+    }
+
+    /// Leave what the finished command left running alone.
+    pub fn release(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(group) = self.0 {
+            // SAFETY: only sends a signal, to a group that is gone at worst
+            unsafe {
+                libc::killpg(group, libc::SIGKILL);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{
+    command::{buffered, encoding, Command, CommandStream, ReadFile},
+    workspace, Ctx,
+};
+
+impl Command for ReadFile {
+    fn execute<'a>(&'a self, _ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(read(dir, input))
+    }
+}
+
+/// The content of the file, lossily decoded if it is not UTF-8 and hex dumped if it is binary
+async fn read(dir: &Path, input: &str) -> anyhow::Result<String> {
+    let path = workspace::resolve(dir, input.trim())?;
+
+    let bytes = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", input.trim()))?;
+
+    let (mut content, replaced) = encoding::text(&bytes);
+    if replaced {
+        content.push_str("\n[the file is not valid UTF-8, bytes were replaced]");
+    }
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::{collect, Command, ReadFile},
+        ctx,
+    };
+
+    #[tokio::test]
+    async fn test_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("notes.txt"), "hello\n")?;
+
+        let content = collect(ReadFile.execute(ctx()?, dir.path(), "notes.txt\n")).await?;
+        assert_eq!(content, "hello");
+
+        assert!(collect(ReadFile.execute(ctx()?, dir.path(), "missing.txt"))
+            .await
+            .is_err());
+        assert!(
+            collect(ReadFile.execute(ctx()?, dir.path(), "../notes.txt"))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_not_utf8() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("latin1.txt"), b"caf\xe9\n")?;
+        std::fs::write(dir.path().join("data.bin"), b"\0\x01\x02")?;
+
+        let content = collect(ReadFile.execute(ctx()?, dir.path(), "latin1.txt")).await?;
+        assert!(content.starts_with("caf\u{fffd}\n"), "{content}");
+        assert!(content.ends_with("bytes were replaced]"));
+
+        let content = collect(ReadFile.execute(ctx()?, dir.path(), "data.bin")).await?;
+        assert_eq!(content, "binary data, 3 bytes\n00000000  00 01 02");
+
+        Ok(())
+    }
+}
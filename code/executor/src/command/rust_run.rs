@@ -0,0 +1,176 @@
+//! Compile a single Rust file with rustc and run it.
+//!
+//! The input is the source of the program. It is built in a temporary directory, so it can be
+//! used to check generated code without touching the working directory of the session.
+
+use std::{path::Path, process::Output, time::Duration};
+
+use anyhow::{ensure, Context};
+use serde::Serialize;
+
+use crate::{
+    command::{buffered, Command, CommandStream, RustRun},
+    Ctx,
+};
+
+/// How long compiling and running may take each
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The result of compiling and running a program
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompileRunResult {
+    pub compiled: bool,
+    /// the diagnostics of rustc
+    pub compiler_output: String,
+    /// `None` if the program did not run, timed out or was killed by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl CompileRunResult {
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.compiled && self.exit_code == Some(0)
+    }
+}
+
+/// Run `cmd`, killing it after `timeout`. Returns `None` if it timed out.
+async fn output(
+    cmd: &mut tokio::process::Command,
+    timeout: Duration,
+) -> anyhow::Result<Option<Output>> {
+    let output = cmd.kill_on_drop(true).output();
+
+    match tokio::time::timeout(timeout, output).await {
+        Ok(output) => Ok(Some(output?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Compile `source` as a binary with rustc and run it, giving each at most `timeout`.
+///
+/// # Errors
+/// If the temporary directory could not be created or rustc could not be started.
+pub async fn run(source: &str, timeout: Duration) -> anyhow::Result<CompileRunResult> {
+    let dir = tempfile::tempdir()?;
+    let main = dir.path().join("main.rs");
+    let binary = dir.path().join("main");
+
+    tokio::fs::write(&main, source).await?;
+
+    let mut result = CompileRunResult {
+        compiled: false,
+        compiler_output: String::new(),
+        exit_code: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        timed_out: false,
+    };
+
+    let compile = output(
+        tokio::process::Command::new("rustc")
+            .args(["--edition", "2021", "--crate-name", "main", "-o"])
+            .arg(&binary)
+            .arg(&main)
+            .current_dir(dir.path()),
+        timeout,
+    )
+    .await
+    .context("Failed to start rustc")?;
+
+    let Some(compile) = compile else {
+        result.timed_out = true;
+        return Ok(result);
+    };
+
+    result.compiled = compile.status.success();
+    result.compiler_output = String::from_utf8_lossy(&compile.stderr)
+        .trim_end()
+        .to_string();
+
+    if !result.compiled {
+        return Ok(result);
+    }
+
+    let Some(run) = output(
+        tokio::process::Command::new(&binary).current_dir(dir.path()),
+        timeout,
+    )
+    .await?
+    else {
+        result.timed_out = true;
+        return Ok(result);
+    };
+
+    result.exit_code = run.status.code();
+    result.stdout = String::from_utf8_lossy(&run.stdout).trim_end().to_string();
+    result.stderr = String::from_utf8_lossy(&run.stderr).trim_end().to_string();
+
+    Ok(result)
+}
+
+impl Command for RustRun {
+    fn execute<'a>(&'a self, _ctx: Ctx, _dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(checked_run(input))
+    }
+}
+
+async fn checked_run(input: &str) -> anyhow::Result<String> {
+    let result = run(input, DEFAULT_TIMEOUT).await?;
+
+    ensure!(
+        result.compiled,
+        "compilation failed:\n{}",
+        result.compiler_output
+    );
+    ensure!(!result.timed_out, "timed out after {DEFAULT_TIMEOUT:?}");
+    ensure!(
+        result.success(),
+        "exited with {:?}:\n{}",
+        result.exit_code,
+        result.stderr
+    );
+
+    Ok(result.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{run, DEFAULT_TIMEOUT};
+
+    #[tokio::test]
+    async fn test_run() -> anyhow::Result<()> {
+        let result = run(r#"fn main() { println!("hello"); }"#, DEFAULT_TIMEOUT).await?;
+
+        assert!(result.success(), "{result:?}");
+        assert_eq!(result.stdout, "hello");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compile_error() -> anyhow::Result<()> {
+        let result = run("fn main() { let x: u32 = \"no\"; }", DEFAULT_TIMEOUT).await?;
+
+        assert!(!result.compiled);
+        assert!(result.compiler_output.contains("mismatched types"));
+        assert_eq!(result.exit_code, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout() -> anyhow::Result<()> {
+        let source = "fn main() { std::thread::sleep(std::time::Duration::from_secs(60)); }";
+        let result = run(source, Duration::from_secs(5)).await?;
+
+        assert!(result.compiled);
+        assert!(result.timed_out);
+
+        Ok(())
+    }
+}
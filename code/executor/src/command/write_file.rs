@@ -0,0 +1,81 @@
+//! Write a file in the working directory of the session.
+//!
+//! The input is the path of the file on the first line, followed by its content.
+
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+
+use crate::{
+    command::{buffered, Command, CommandStream, WriteFile},
+    workspace, Ctx,
+};
+
+/// Split the input into the path and the content of the file.
+///
+/// # Errors
+/// If the input has no path.
+pub fn split_input(input: &str) -> anyhow::Result<(&str, &str)> {
+    let (path, content) = input.split_once('\n').unwrap_or((input, ""));
+    let path = path.trim();
+
+    ensure!(!path.is_empty(), "writefile needs a path on the first line");
+
+    Ok((path, content))
+}
+
+impl Command for WriteFile {
+    fn execute<'a>(&'a self, _ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        buffered(write(dir, input))
+    }
+}
+
+async fn write(dir: &Path, input: &str) -> anyhow::Result<String> {
+    let (path, content) = split_input(input)?;
+    let resolved = workspace::resolve(dir, path)?;
+
+    if let Some(parent) = resolved.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    tokio::fs::write(&resolved, content)
+        .await
+        .with_context(|| format!("Failed to write {path}"))?;
+
+    Ok(format!("wrote {path} ({} lines)", content.lines().count()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::{collect, Command, WriteFile},
+        ctx,
+    };
+
+    #[tokio::test]
+    async fn test_write() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let input = "src/lib.rs\npub mod a;\npub mod b;\n";
+        let output = collect(WriteFile.execute(ctx()?, dir.path(), input)).await?;
+
+        assert_eq!(output, "wrote src/lib.rs (2 lines)");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/lib.rs"))?,
+            "pub mod a;\npub mod b;\n"
+        );
+
+        assert!(
+            collect(WriteFile.execute(ctx()?, dir.path(), "../escape.rs\nfn main() {}"))
+                .await
+                .is_err()
+        );
+        assert!(
+            collect(WriteFile.execute(ctx()?, dir.path(), "\nfn main() {}"))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -1,40 +1,33 @@
-use anyhow::{ensure, Context};
-use async_trait::async_trait;
-use utils::str::StringExt;
+use std::path::Path;
 
 use crate::{
-    command::{Command, Zsh},
+    command::{spawn, Command, CommandStream, Zsh},
     Ctx,
 };
 
-#[async_trait]
 impl Command for Zsh {
-    async fn execute(&self, _exec: Ctx, input: &str) -> anyhow::Result<String> {
-        let output = tokio::process::Command::new("zsh")
-            .arg("-c")
-            .arg(input)
-            .output()
-            .await?;
-
-        ensure!(output.status.success(), "zsh command failed");
-
-        let mut output = String::from_utf8(output.stdout).context("could not parse to UTF-8")?;
-        output.trim_end_in_place(); // remove trailing newline
-
-        Ok(output)
+    fn execute<'a>(&'a self, ctx: Ctx, dir: &'a Path, input: &'a str) -> CommandStream<'a> {
+        let mut cmd = tokio::process::Command::new("zsh");
+        cmd.arg("-c").arg(input).current_dir(dir);
+        spawn(cmd, ctx.limits())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{command::Command, ctx};
+    use std::path::Path;
+
+    use crate::{
+        command::{collect, Command},
+        ctx,
+    };
 
     #[tokio::test]
     async fn test_oneline() -> anyhow::Result<()> {
         let exec = ctx()?;
         let cmd = super::Zsh;
 
-        let output = cmd.execute(exec, "echo hello there").await?;
+        let output = collect(cmd.execute(exec, Path::new("."), "echo hello there")).await?;
 
         assert_eq!(output, "hello there");
 
@@ -49,7 +42,7 @@ mod tests {
         let input = r#"echo hello
         echo there"#;
 
-        let output = cmd.execute(exec, input).await?;
+        let output = collect(cmd.execute(exec, Path::new("."), input)).await?;
 
         assert_eq!(output, "hello\nthere");
 
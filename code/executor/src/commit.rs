@@ -0,0 +1,313 @@
+//! Commit what a plan changed.
+//!
+//! Once every step succeeded, a model pass writes a conventional commit message, and optionally a
+//! changelog entry, from the journal of the execution and the diff of the working directory. The
+//! commit is proposed to the frontend like a risky command and only created once it is approved.
+//!
+//! Only working directories that are the root of a git repository are committed, so a session
+//! inside another repository never commits to it.
+
+use std::{path::Path, process::Stdio};
+
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tokio_openai::ChatRequest;
+
+/// The default system prompt
+pub const SYSTEM_PROMPT: &str =
+    "Changes were made to a repository. Answer with only a JSON object with the fields `subject`, \
+     `body` and `changelog`. `subject` is a conventional commit subject of at most 72 characters, \
+     e.g. `feat(cli): add a --verbose flag`. `body` says what changed and why in a few sentences, \
+     or is empty. `changelog` is a one line entry for users of the project, or null if the change \
+     does not affect them.";
+
+/// How much of the diff the model sees
+const MAX_DIFF: usize = 8000;
+
+/// The longest subject git tools show without cutting it off
+const MAX_SUBJECT: usize = 72;
+
+/// The types of conventional commits
+const TYPES: [&str; 11] = [
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// The directory changelog entries are written to, if the working directory has it
+pub const CHANGELOG_DIR: &str = "changelog.d";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Message {
+    pub subject: String,
+    #[serde(default)]
+    pub body: String,
+    /// an entry for the changelog, if the change affects users
+    #[serde(default)]
+    pub changelog: Option<String>,
+}
+
+impl Message {
+    /// The message as it is committed
+    #[must_use]
+    pub fn text(&self) -> String {
+        match self.body.trim() {
+            "" => self.subject.clone(),
+            body => format!("{}\n\n{body}", self.subject),
+        }
+    }
+
+    /// The command shown to the frontend for approval
+    #[must_use]
+    pub fn command(&self) -> String {
+        let mut command = format!(
+            "git add -A && git commit -F - <<'EOF'\n{}\nEOF",
+            self.text()
+        );
+        if let Some(entry) = &self.changelog {
+            command.push_str(&format!("\n# {CHANGELOG_DIR}: {entry}"));
+        }
+        command
+    }
+}
+
+#[must_use]
+pub fn request(system: &str, journal: &[String], diff: &str) -> ChatRequest {
+    let start = diff
+        .char_indices()
+        .nth(MAX_DIFF)
+        .map_or(diff.len(), |(index, _)| index);
+
+    ChatRequest::new().sys_msg(system).user_msg(format!(
+        "What was done:\n{}\n\nDiff:\n{}",
+        journal.join("\n"),
+        &diff[..start]
+    ))
+}
+
+/// Parse the answer to a [`request`].
+///
+/// # Errors
+/// If the answer is not a message or its subject is not a conventional commit subject.
+pub fn parse(text: &str) -> anyhow::Result<Message> {
+    let mut message: Message = serde_json::from_str(utils::markdown::strip_fence(text))
+        .context("The commit message is not valid JSON")?;
+
+    message.subject = message.subject.trim().to_string();
+    message.changelog = message
+        .changelog
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty());
+    check_subject(&message.subject)?;
+
+    Ok(message)
+}
+
+/// Check that `subject` is `type(scope)!: summary`, with the scope and `!` optional.
+fn check_subject(subject: &str) -> anyhow::Result<()> {
+    ensure!(
+        subject.chars().count() <= MAX_SUBJECT,
+        "the subject is longer than {MAX_SUBJECT} characters"
+    );
+    ensure!(
+        !subject.contains('\n'),
+        "the subject has more than one line"
+    );
+
+    let Some((prefix, summary)) = subject.split_once(": ") else {
+        bail!("the subject does not start with a type, e.g. `feat: `");
+    };
+    ensure!(!summary.trim().is_empty(), "the subject has no summary");
+
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let kind = match prefix.split_once('(') {
+        Some((kind, scope)) => {
+            let scope = scope.strip_suffix(')').context("the scope is not closed")?;
+            ensure!(!scope.is_empty(), "the scope is empty");
+            kind
+        }
+        None => prefix,
+    };
+    ensure!(
+        TYPES.contains(&kind),
+        "`{kind}` is not a conventional commit type"
+    );
+
+    Ok(())
+}
+
+/// The changes in `dir`: the status followed by the diff of tracked files. `None` if there are
+/// none or `dir` is not the root of a git repository.
+///
+/// # Errors
+/// If git failed on a repository.
+pub async fn changes(dir: &Path) -> anyhow::Result<Option<String>> {
+    let Ok(root) = git(dir, &["rev-parse", "--show-toplevel"], None).await else {
+        return Ok(None);
+    };
+    if Path::new(root.trim()).canonicalize()? != dir.canonicalize()? {
+        return Ok(None);
+    }
+
+    let status = git(dir, &["status", "--porcelain"], None).await?;
+    if status.trim().is_empty() {
+        return Ok(None);
+    }
+
+    // a repository without commits has nothing to diff against
+    let diff = git(dir, &["diff", "HEAD"], None).await.unwrap_or_default();
+    Ok(Some(format!("{status}\n{diff}")))
+}
+
+/// Commit every change in `dir` with `message`, writing its changelog entry first if `dir` has
+/// a [`CHANGELOG_DIR`].
+///
+/// # Errors
+/// If the entry could not be written or git failed.
+pub async fn commit(dir: &Path, message: &Message) -> anyhow::Result<()> {
+    let changelog = dir.join(CHANGELOG_DIR);
+    if let (Some(entry), true) = (&message.changelog, changelog.is_dir()) {
+        let path = changelog.join(format!("{}.md", slug(&message.subject)));
+        tokio::fs::write(&path, format!("- {entry}\n"))
+            .await
+            .context("Failed to write the changelog entry")?;
+    }
+
+    git(dir, &["add", "-A"], None).await?;
+    git(dir, &["commit", "-F", "-"], Some(&message.text())).await?;
+    Ok(())
+}
+
+/// The summary of `subject` in lowercase words joined by `-`, e.g. `add-a-verbose-flag`
+pub fn slug(subject: &str) -> String {
+    let summary = subject
+        .split_once(": ")
+        .map_or(subject, |(_, summary)| summary);
+    summary
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Run git in `dir` with `stdin`, returning its stdout.
+async fn git(dir: &Path, args: &[&str], stdin: Option<&str>) -> anyhow::Result<String> {
+    git_with(dir, args, stdin, &[]).await
+}
+
+/// Run git in `dir` with `stdin` and the environment variables `env`, returning its stdout.
+pub async fn git_with(
+    dir: &Path,
+    args: &[&str],
+    stdin: Option<&str>,
+    env: &[(String, String)],
+) -> anyhow::Result<String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to run git")?;
+
+    let mut input = child.stdin.take().context("git has no stdin")?;
+    if let Some(stdin) = stdin {
+        input.write_all(stdin.as_bytes()).await?;
+    }
+    drop(input);
+
+    let output = child.wait_with_output().await?;
+    ensure!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changes, check_subject, commit, git, parse, slug, Message, CHANGELOG_DIR};
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let message = parse(
+            r#"```json
+            {"subject": "feat(cli): add a --verbose flag", "body": "Prints every step.", "changelog": " "}
+            ```"#,
+        )?;
+        assert_eq!(
+            message.text(),
+            "feat(cli): add a --verbose flag\n\nPrints every step."
+        );
+        assert_eq!(message.changelog, None);
+
+        assert!(parse(r#"{"subject": "Add a flag"}"#).is_err());
+        assert!(parse(r#"{"body": "no subject"}"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_subject() {
+        assert!(check_subject("fix: handle empty input").is_ok());
+        assert!(check_subject("refactor(parser)!: drop the old syntax").is_ok());
+
+        assert!(check_subject("feature: add it").is_err());
+        assert!(check_subject("fix(): empty scope").is_err());
+        assert!(check_subject("fix(parser: unclosed").is_err());
+        assert!(check_subject("fix: ").is_err());
+        assert!(check_subject(&format!("fix: {}", "a".repeat(80))).is_err());
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(
+            slug("feat(cli): Add a --verbose flag"),
+            "add-a-verbose-flag"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+
+        // not a repository
+        assert!(changes(dir).await?.is_none());
+
+        git(dir, &["init", "-q"], None).await?;
+        git(dir, &["config", "user.name", "Test"], None).await?;
+        git(dir, &["config", "user.email", "test@example.com"], None).await?;
+        assert!(changes(dir).await?.is_none());
+
+        // a directory inside the repository is not committed
+        std::fs::create_dir_all(dir.join("nested"))?;
+        std::fs::write(dir.join("nested/main.rs"), "fn main() {}\n")?;
+        assert!(changes(&dir.join("nested")).await?.is_none());
+
+        std::fs::create_dir(dir.join(CHANGELOG_DIR))?;
+        assert!(changes(dir)
+            .await?
+            .is_some_and(|diff| diff.contains("nested/")));
+
+        let message = Message {
+            subject: "feat: add the entry point".to_string(),
+            body: String::new(),
+            changelog: Some("Runs without arguments".to_string()),
+        };
+        commit(dir, &message).await?;
+
+        assert!(changes(dir).await?.is_none());
+        let log = git(dir, &["log", "--format=%s", "--name-only"], None).await?;
+        assert!(log.starts_with("feat: add the entry point\n"));
+        assert!(log.contains("changelog.d/add-the-entry-point.md"));
+
+        Ok(())
+    }
+}
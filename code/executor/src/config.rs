@@ -0,0 +1,492 @@
+//! A TOML file with the [`Settings`] of the executor, reloaded while it runs.
+//!
+//! The keys are the names of the command line flags, durations in seconds, e.g.
+//!
+//! ```toml
+//! log-level = "debug"
+//! command-timeout = 120
+//! fetch-allow = ["docs.rs", "crates.io"]
+//! prompts = "prompts/"
+//! ```
+//!
+//! The file overrides the flags. It is reloaded when it changes and on [`reload`], which the
+//! executor calls on SIGHUP. The log level, the limits of commands, the domains that can be
+//! fetched and the prompts change at once, applying to the next command or request of every
+//! session. Other settings need a restart. Attached frontends get a notice of what changed.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Weak,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::{error, info, level_filters::LevelFilter};
+use tracing_subscriber::{reload, Registry};
+
+use crate::{Inner, Network, Settings};
+
+/// How often the file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wakes the watchers of the config
+static RELOAD: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Changes the log level, if the executor set up logging, see [`init_logging`]
+static LOG_LEVEL: OnceCell<reload::Handle<LevelFilter, Registry>> = OnceCell::new();
+
+/// The settings in the file, `None` for those it leaves to the flags
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    log_level: Option<String>,
+    prompts: Option<PathBuf>,
+    fetch_allow: Option<Vec<String>>,
+    proxy: Option<String>,
+    no_proxy: Option<Vec<String>>,
+    ca_bundle: Option<PathBuf>,
+    command_timeout: Option<u64>,
+    max_output: Option<usize>,
+    nice: Option<i32>,
+    max_memory: Option<u64>,
+    max_cpu: Option<u64>,
+    memory: Option<PathBuf>,
+    dev: Option<bool>,
+    warm_interval: Option<u64>,
+    git_credentials: Option<PathBuf>,
+    index: Option<bool>,
+    question_candidates: Option<u32>,
+    codegen_iterations: Option<u32>,
+    response_cache: Option<PathBuf>,
+    response_cache_ttl: Option<u64>,
+    response_cache_size: Option<u64>,
+    keep_failed_scratch: Option<bool>,
+    audit_log: Option<PathBuf>,
+    hooks: Option<PathBuf>,
+    cost_cap: Option<u64>,
+}
+
+/// Log with a level that [`Settings::log_level`] and the config can change, instead of
+/// `tracing_subscriber::fmt::init`. With the `console` feature, also serve `tokio-console`, see
+/// [`tasks`](crate::tasks).
+pub fn init_logging() {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+    // only for the log, the console needs the traces of the runtime
+    let (level, handle) = reload::Layer::new(LevelFilter::INFO);
+    #[cfg(feature = "console")]
+    let console = Some(console_subscriber::spawn());
+    #[cfg(not(feature = "console"))]
+    let console: Option<tracing_subscriber::layer::Identity> = None;
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(level))
+        .with(console)
+        .init();
+    let _ = LOG_LEVEL.set(handle);
+}
+
+/// Reload the config of every executor now instead of once the file changes.
+pub fn reload() {
+    RELOAD.notify_one();
+}
+
+/// Parse a log level such as `debug` or `off`.
+///
+/// # Errors
+/// If `level` is not a level
+pub fn log_level(level: &str) -> anyhow::Result<LevelFilter> {
+    LevelFilter::from_str(level).map_err(|_| {
+        anyhow::anyhow!(
+            "`{level}` is not a log level, expected trace, debug, info, warn, error or off"
+        )
+    })
+}
+
+/// Change the level of the logs, if the executor set up logging.
+pub(crate) fn set_log_level(level: LevelFilter) -> anyhow::Result<()> {
+    let handle = LOG_LEVEL
+        .get()
+        .context("the log level can only change if the executor set up logging")?;
+    handle.reload(level)?;
+    Ok(())
+}
+
+/// `settings` with the settings in the file at `path` instead of their flags.
+///
+/// # Errors
+/// If the file cannot be read or is not valid
+pub fn load(path: &Path, settings: Settings) -> anyhow::Result<Settings> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the config {}", path.display()))?;
+    let config: Config =
+        toml::from_str(&content).with_context(|| format!("{} is not valid", path.display()))?;
+    config.apply(settings)
+}
+
+impl Config {
+    fn apply(self, settings: Settings) -> anyhow::Result<Settings> {
+        let Self {
+            log_level,
+            prompts,
+            fetch_allow,
+            proxy,
+            no_proxy,
+            ca_bundle,
+            command_timeout,
+            max_output,
+            nice,
+            max_memory,
+            max_cpu,
+            memory,
+            dev,
+            warm_interval,
+            git_credentials,
+            index,
+            question_candidates,
+            codegen_iterations,
+            response_cache,
+            response_cache_ttl,
+            response_cache_size,
+            keep_failed_scratch,
+            audit_log,
+            hooks,
+            cost_cap,
+        } = self;
+        let mut limits = settings.limits;
+        let secs = |secs: Option<u64>| secs.map(Duration::from_secs);
+
+        limits.timeout = secs(command_timeout).unwrap_or(limits.timeout);
+        limits.max_output = max_output.unwrap_or(limits.max_output);
+        limits.nice = nice.or(limits.nice);
+        limits.max_memory = max_memory.or(limits.max_memory);
+        limits.max_cpu = max_cpu.or(limits.max_cpu);
+
+        Ok(Settings {
+            log_level: log_level
+                .as_deref()
+                .map(self::log_level)
+                .transpose()?
+                .or(settings.log_level),
+            memory: memory.or(settings.memory),
+            prompts: prompts.or(settings.prompts),
+            dev: dev.unwrap_or(settings.dev),
+            limits,
+            fetch_allow: fetch_allow.unwrap_or(settings.fetch_allow),
+            network: Network {
+                proxy: proxy.or(settings.network.proxy),
+                no_proxy: no_proxy.unwrap_or(settings.network.no_proxy),
+                ca_bundle: ca_bundle.or(settings.network.ca_bundle),
+            },
+            warm_interval: secs(warm_interval).unwrap_or(settings.warm_interval),
+            git_credentials: git_credentials.or(settings.git_credentials),
+            index: index.unwrap_or(settings.index),
+            question_candidates: question_candidates.unwrap_or(settings.question_candidates),
+            codegen_iterations: codegen_iterations.unwrap_or(settings.codegen_iterations),
+            response_cache: response_cache.or(settings.response_cache),
+            response_cache_ttl: secs(response_cache_ttl).unwrap_or(settings.response_cache_ttl),
+            response_cache_size: response_cache_size.unwrap_or(settings.response_cache_size),
+            keep_failed_scratch: keep_failed_scratch.unwrap_or(settings.keep_failed_scratch),
+            audit_log: audit_log.or(settings.audit_log),
+            hooks: hooks.or(settings.hooks),
+            cost_cap: cost_cap.or(settings.cost_cap),
+            config: settings.config,
+        })
+    }
+}
+
+/// The flags of the settings that differ between `old` and `new`, those that can change while
+/// running first
+fn changes(old: &Settings, new: &Settings) -> (Vec<&'static str>, Vec<&'static str>) {
+    let Settings {
+        log_level,
+        memory,
+        prompts,
+        dev,
+        limits,
+        fetch_allow,
+        network,
+        warm_interval,
+        git_credentials,
+        index,
+        question_candidates,
+        codegen_iterations,
+        response_cache,
+        response_cache_ttl,
+        response_cache_size,
+        keep_failed_scratch,
+        audit_log,
+        hooks,
+        cost_cap,
+        config: _,
+    } = new;
+
+    let live = [
+        ("log-level", *log_level != old.log_level),
+        ("prompts", *prompts != old.prompts),
+        ("limits", *limits != old.limits),
+        ("fetch-allow", *fetch_allow != old.fetch_allow),
+    ];
+    let restart = [
+        ("memory", *memory != old.memory),
+        ("proxy", network.proxy != old.network.proxy),
+        ("no-proxy", network.no_proxy != old.network.no_proxy),
+        ("ca-bundle", network.ca_bundle != old.network.ca_bundle),
+        ("dev", *dev != old.dev),
+        ("warm-interval", *warm_interval != old.warm_interval),
+        ("git-credentials", *git_credentials != old.git_credentials),
+        ("index", *index != old.index),
+        (
+            "question-candidates",
+            *question_candidates != old.question_candidates,
+        ),
+        (
+            "codegen-iterations",
+            *codegen_iterations != old.codegen_iterations,
+        ),
+        ("response-cache", *response_cache != old.response_cache),
+        (
+            "response-cache-ttl",
+            *response_cache_ttl != old.response_cache_ttl,
+        ),
+        (
+            "response-cache-size",
+            *response_cache_size != old.response_cache_size,
+        ),
+        (
+            "keep-failed-scratch",
+            *keep_failed_scratch != old.keep_failed_scratch,
+        ),
+        ("audit-log", *audit_log != old.audit_log),
+        ("hooks", *hooks != old.hooks),
+        ("cost-cap", *cost_cap != old.cost_cap),
+    ];
+
+    let changed = |settings: &[(&'static str, bool)]| {
+        settings
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| *name)
+            .collect()
+    };
+    (changed(&live), changed(&restart))
+}
+
+impl Inner {
+    /// Apply the settings of `new` that can change while running. Returns the settings in
+    /// effect and what to tell the frontends.
+    fn reconfigure(&self, current: Settings, new: Settings) -> (Settings, String) {
+        let (live, restart) = changes(&current, &new);
+        if live.is_empty() && restart.is_empty() {
+            return (current, "The config did not change".to_string());
+        }
+
+        let mut applied = Vec::new();
+        let mut failed = Vec::new();
+        let mut effective = Settings {
+            log_level: new.log_level,
+            limits: new.limits,
+            fetch_allow: new.fetch_allow.clone(),
+            ..current.clone()
+        };
+
+        if new.log_level != current.log_level {
+            let level = new.log_level.unwrap_or(LevelFilter::INFO);
+            match set_log_level(level) {
+                Ok(()) => applied.push("log-level"),
+                Err(e) => {
+                    failed.push(format!("log-level ({e:#})"));
+                    effective.log_level = current.log_level;
+                }
+            }
+        }
+        if new.prompts != current.prompts {
+            match self.prompts.switch(new.prompts.clone()) {
+                Ok(()) => {
+                    applied.push("prompts");
+                    effective.prompts = new.prompts;
+                }
+                Err(e) => failed.push(format!("prompts ({e:#})")),
+            }
+        }
+        if new.limits != current.limits {
+            *self.limits.write() = new.limits;
+            applied.push("limits");
+        }
+        if new.fetch_allow != current.fetch_allow {
+            let mut policy = self.policy.write();
+            *policy = std::mem::take(&mut *policy).allow_domains(new.fetch_allow);
+            applied.push("fetch-allow");
+        }
+
+        let mut notice = "Reloaded the config".to_string();
+        if !applied.is_empty() {
+            notice += &format!(", changed {}", applied.join(", "));
+        }
+        if !failed.is_empty() {
+            notice += &format!(", failed to change {}", failed.join(", "));
+        }
+        if !restart.is_empty() {
+            notice += &format!(", restart the executor to change {}", restart.join(", "));
+        }
+        (effective, notice)
+    }
+}
+
+/// Reload the config at `path` whenever it changes or [`reload`] is called, announcing what
+/// changed to every frontend. `base` are the settings of the flags, `current` those in effect.
+///
+/// Stops once the executor is dropped.
+pub fn watch(path: PathBuf, base: Settings, current: Settings, ctx: Weak<Inner>) {
+    crate::tasks::spawn("config watcher", async move {
+        info!("Watching the config {} for changes", path.display());
+
+        let mut current = current;
+        let mut last_modified = modified(&path);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let modified = modified(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                }
+                () = RELOAD.notified() => {}
+            }
+
+            let Some(ctx) = ctx.upgrade() else {
+                return;
+            };
+
+            let notice = match load(&path, base.clone()) {
+                Ok(new) => {
+                    let (effective, notice) = ctx.reconfigure(current, new);
+                    current = effective;
+                    notice
+                }
+                // keep the previous settings until the file is fixed
+                Err(e) => {
+                    error!("{e:#}");
+                    format!("{e:#}, keeping the previous config")
+                }
+            };
+
+            info!("{notice}");
+
+            // nobody is connected
+            let _ = ctx.notices.send(notice);
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::broadcast;
+    use tracing::level_filters::LevelFilter;
+
+    use crate::{Executor, Prompt, Settings};
+
+    /// The next notice telling what changed. Changing the file may reload it as well, telling
+    /// that nothing changed afterwards.
+    async fn notice(notices: &mut broadcast::Receiver<String>) -> anyhow::Result<String> {
+        loop {
+            let notice = tokio::time::timeout(Duration::from_secs(5), notices.recv()).await??;
+            if notice != "The config did not change" {
+                return Ok(notice);
+            }
+        }
+    }
+
+    #[test]
+    fn test_load() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "log-level = \"debug\"\ncommand-timeout = 5\nfetch-allow = [\"docs.rs\"]\nindex = \
+             true\n",
+        )?;
+
+        let base = Settings {
+            question_candidates: 3,
+            ..Settings::default()
+        };
+        let settings = super::load(&path, base.clone())?;
+        assert_eq!(settings.log_level, Some(LevelFilter::DEBUG));
+        assert_eq!(settings.limits.timeout, Duration::from_secs(5));
+        assert_eq!(settings.limits.max_output, base.limits.max_output);
+        assert_eq!(settings.fetch_allow, ["docs.rs"]);
+        assert!(settings.index);
+        assert_eq!(settings.question_candidates, 3);
+
+        let (live, restart) = super::changes(&base, &settings);
+        assert_eq!(live, ["log-level", "limits", "fetch-allow"]);
+        assert_eq!(restart, ["index"]);
+
+        std::fs::write(&path, "command-timeout = 5\nunknown = 1\n")?;
+        assert!(super::load(&path, base.clone()).is_err());
+        std::fs::write(&path, "log-level = \"loud\"\n")?;
+        assert!(super::load(&path, base).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reload() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        let prompts = dir.path().join("prompts.json");
+        std::fs::write(&prompts, r#"{"plan": "Plan quickly."}"#)?;
+        std::fs::write(&path, "command-timeout = 5\n")?;
+
+        let executor = Executor::new(Settings {
+            config: Some(path.clone()),
+            ..Settings::default()
+        })?;
+        let mut notices = executor.ctx.notices.subscribe();
+        assert_eq!(executor.ctx.limits().timeout, Duration::from_secs(5));
+
+        let config = format!(
+            "command-timeout = 7\nfetch-allow = [\"docs.rs\"]\nprompts = {:?}\nmemory = \
+             \"memory.json\"\n",
+            prompts.display().to_string()
+        );
+        std::fs::write(&path, config)?;
+        super::reload();
+
+        assert_eq!(
+            notice(&mut notices).await?,
+            "Reloaded the config, changed prompts, limits, fetch-allow, restart the executor to \
+             change memory"
+        );
+        assert_eq!(executor.ctx.limits().timeout, Duration::from_secs(7));
+        let blocked: reqwest::Url = "https://example.com".parse()?;
+        assert!(!executor.ctx.policy.read().allows_url(&blocked));
+        assert_eq!(executor.ctx.prompts.get(Prompt::Plan), "Plan quickly.");
+
+        // an invalid file keeps what is in effect
+        std::fs::write(&path, "command-timeout = \"soon\"\n")?;
+        super::reload();
+        assert!(notice(&mut notices)
+            .await?
+            .ends_with("keeping the previous config"));
+        assert_eq!(executor.ctx.limits().timeout, Duration::from_secs(7));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Find the crates generated code uses but its package does not depend on.
+//!
+//! After a codegen step writes a Rust file, the missing crates are added with `cargo add` at the
+//! latest version on crates.io, so the build does not fail on code referencing dependencies the
+//! project does not have.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Path roots that are never crates
+const BUILTIN: &[&str] = &[
+    "std",
+    "core",
+    "alloc",
+    "proc_macro",
+    "test",
+    "crate",
+    "self",
+    "super",
+    "bool",
+    "char",
+    "str",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "f32",
+    "f64",
+];
+
+/// `use foo::…` and `extern crate foo`
+static USE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?(?:use\s+(?:::)?|extern\s+crate\s+)([a-z_][a-z0-9_]*)",
+    )
+    .expect("valid regex")
+});
+
+/// The rest of a `use` statement, the names it imports are not crates
+static USE_TREE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)\buse\s+[^;]*;").expect("valid regex"));
+
+static IDENT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").expect("valid regex"));
+
+/// `foo::bar` anywhere, e.g. `#[tokio::main]` or `rand::random()`
+static PATH: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?:^|[^A-Za-z0-9_:])([a-z_][a-z0-9_]*)::").expect("valid regex"));
+
+static MOD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\bmod\s+([a-z_][a-z0-9_]*)").expect("valid regex"));
+
+/// The crates `source` refers to, as written in code (with underscores).
+#[must_use]
+pub fn referenced(source: &str) -> BTreeSet<String> {
+    // comments may mention paths that are not used
+    let code: String = source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut local: BTreeSet<&str> = BUILTIN.iter().copied().collect();
+    local.extend(
+        MOD.captures_iter(&code)
+            .filter_map(|c| c.get(1))
+            .map(|m| m.as_str()),
+    );
+
+    // everything a `use` statement names after its root is a local name, e.g. `io` in
+    // `use std::{fs, io};`
+    for statement in USE_TREE.find_iter(&code) {
+        local.extend(
+            IDENT
+                .find_iter(statement.as_str())
+                .skip(2)
+                .map(|m| m.as_str()),
+        );
+    }
+
+    USE.captures_iter(&code)
+        .chain(PATH.captures_iter(&code))
+        .filter_map(|c| c.get(1))
+        .map(|m| m.as_str())
+        .filter(|name| !local.contains(name))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The crates the manifest `content` depends on and its own name, with underscores as in code.
+///
+/// # Errors
+/// If `content` is not a valid manifest.
+pub fn declared(content: &str) -> anyhow::Result<BTreeSet<String>> {
+    let manifest: toml::Table = content.parse().context("Cargo.toml is not valid TOML")?;
+
+    let tables = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let mut names: BTreeSet<String> = tables
+        .iter()
+        .filter_map(|table| manifest.get(*table)?.as_table())
+        .flat_map(|table| table.keys().cloned())
+        .collect();
+
+    let package = manifest
+        .get("package")
+        .and_then(|package| package.get("name"));
+    if let Some(name) = package.and_then(toml::Value::as_str) {
+        names.insert(name.to_string());
+    }
+
+    Ok(names
+        .into_iter()
+        .map(|name| name.replace('-', "_"))
+        .collect())
+}
+
+/// The crates `source` refers to that the manifest `content` does not declare.
+///
+/// # Errors
+/// If `content` is not a valid manifest.
+pub fn missing(source: &str, manifest: &str) -> anyhow::Result<BTreeSet<String>> {
+    let declared = declared(manifest)?;
+
+    Ok(referenced(source)
+        .into_iter()
+        .filter(|name| !declared.contains(name))
+        .collect())
+}
+
+/// The manifest of the package containing `file`, looking no further up than `root`.
+#[must_use]
+pub fn manifest(root: &Path, file: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{declared, manifest, missing, referenced};
+
+    #[test]
+    fn test_referenced() {
+        let source = r#"
+            use std::{fs, io::{self, Write}};
+            use serde::Deserialize;
+            pub(crate) use crate::parser::Token;
+            extern crate rand_core;
+
+            mod parser;
+
+            // this::is::not::code
+            #[tokio::main]
+            async fn main() -> anyhow::Result<()> {
+                let n = u32::MAX;
+                let text = fs::read_to_string("input")?;
+                io::stdout().write_all(text.as_bytes())?;
+                let value: serde_json::Value = serde_json::from_str(&text)?;
+                parser::parse(&text);
+                Ok(())
+            }
+        "#;
+
+        let crates: Vec<_> = referenced(source).into_iter().collect();
+        assert_eq!(crates, [
+            "anyhow",
+            "rand_core",
+            "serde",
+            "serde_json",
+            "tokio"
+        ]);
+    }
+
+    #[test]
+    fn test_missing() -> anyhow::Result<()> {
+        let manifest = r#"
+            [package]
+            name = "my-app"
+
+            [dependencies]
+            serde = { version = "1", features = ["derive"] }
+            serde-json = "1"
+
+            [dev-dependencies]
+            tokio = "1"
+        "#;
+
+        assert!(declared(manifest)?.contains("my_app"));
+
+        let source = "use my_app::run;\nuse serde::Serialize;\nfn main() { serde_json::json!({}); \
+                      regex::Regex::new(\"\"); }";
+        let missing: Vec<_> = missing(source, manifest)?.into_iter().collect();
+        assert_eq!(missing, ["regex"]);
+
+        assert!(declared("[dependencies").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("app/src/bin"))?;
+        std::fs::write(root.join("app/Cargo.toml"), "")?;
+
+        assert_eq!(
+            manifest(root, &root.join("app/src/bin/tool.rs")),
+            Some(root.join("app/Cargo.toml"))
+        );
+        assert_eq!(manifest(root, &root.join("other/main.rs")), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Explain why a step failed before it is retried.
+//!
+//! A quick model pass classifies the failure as a [`FailureKind`] and proposes a fix, so the user
+//! can decide whether retrying makes sense instead of blindly re-running the plan.
+
+use anyhow::Context;
+use protocol::FailureKind;
+use serde::Deserialize;
+use tokio_openai::ChatRequest;
+
+use crate::plan::Step;
+
+/// How much of the output of the failed step the model sees. Errors are usually at the end.
+const MAX_OUTPUT: usize = 4000;
+
+/// The default system prompt
+pub const SYSTEM_PROMPT: &str = "A command failed. Answer with only a JSON object with the fields \
+                                 `kind`, `explanation` and `remediation`. `kind` is one of \
+                                 `missing_dependency`, `syntax`, `permissions`, `flaky` or \
+                                 `other`. `explanation` says in one sentence why it failed, \
+                                 `remediation` is the most targeted fix.";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Diagnosis {
+    pub kind: FailureKind,
+    pub explanation: String,
+    pub remediation: String,
+}
+
+#[must_use]
+pub fn request(system: &str, step: &Step, output: &str) -> ChatRequest {
+    let start = output
+        .char_indices()
+        .rev()
+        .nth(MAX_OUTPUT)
+        .map_or(0, |(index, _)| index);
+
+    ChatRequest::new().sys_msg(system).user_msg(format!(
+        "Step `{}`: {}\nCommand ({:?}):\n{}\n\nOutput:\n{}",
+        step.title,
+        step.description,
+        step.command.cmd,
+        step.command.input,
+        &output[start..]
+    ))
+}
+
+/// Parse the answer to a [`request`].
+///
+/// # Errors
+/// If the answer is not a diagnosis.
+pub fn parse(text: &str) -> anyhow::Result<Diagnosis> {
+    serde_json::from_str(utils::markdown::strip_fence(text))
+        .context("The diagnosis is not valid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::FailureKind;
+
+    use super::{parse, request, SYSTEM_PROMPT};
+    use crate::plan::Plan;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let diagnosis = parse(
+            r#"```json
+            {"kind": "missing_dependency", "explanation": "serde is not a dependency", "remediation": "cargo add serde"}
+            ```"#,
+        )?;
+
+        assert_eq!(diagnosis.kind, FailureKind::MissingDependency);
+        assert_eq!(diagnosis.remediation, "cargo add serde");
+
+        assert!(parse(r#"{"kind": "cosmic_rays"}"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_truncates() -> anyhow::Result<()> {
+        let plan = Plan::parse(
+            r#"[{"title": "Build", "description": "", "command": {"type": "bash", "input": "make"}}]"#,
+        )?;
+        let output = format!("{}error: the end", "é".repeat(10_000));
+
+        let request = request(SYSTEM_PROMPT, &plan.steps[0], &output);
+        let prompt = &request.messages[1].content;
+
+        assert!(prompt.ends_with("error: the end"));
+        assert!(prompt.len() < 10_000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+//! Turn text into vectors, e.g. to index a codebase.
+//!
+//! [`OpenAiEmbeddings`] sends the text to `OpenAI`. With the `local-embeddings` feature,
+//! [`LocalEmbeddings`] runs a small model on the machine instead (on the GPU with the `metal` or
+//! `cuda` feature), so source code never leaves it. Either is wrapped in [`Memoized`] to embed
+//! texts that come back only once, and in [`Persisted`] to keep the vectors across restarts.
+
+use async_trait::async_trait;
+
+#[cfg(feature = "local-embeddings")]
+pub use crate::embedding::local::LocalEmbeddings;
+pub use crate::embedding::{
+    cache::ModelCache, memo::Memoized, openai::OpenAiEmbeddings, persisted::Persisted,
+};
+
+mod cache;
+#[cfg(feature = "local-embeddings")]
+mod local;
+mod memo;
+mod openai;
+mod persisted;
+
+/// Describes the vectors a provider returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddingModel {
+    pub name: String,
+    /// the length of every vector
+    pub dimensions: usize,
+}
+
+#[async_trait]
+pub trait EmbeddingProvider {
+    fn model(&self) -> &EmbeddingModel;
+
+    /// Embed every input, returning one vector of [`EmbeddingModel::dimensions`] per input.
+    async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>>;
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+/// Downloads model files from Hugging Face once and keeps them on disk.
+#[derive(Clone)]
+pub struct ModelCache {
+    dir: PathBuf,
+    req: reqwest::Client,
+}
+
+impl ModelCache {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>, req: reqwest::Client) -> Self {
+        Self {
+            dir: dir.into(),
+            req,
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/collective/models`, falling back to `~/.cache/collective/models`.
+    #[must_use]
+    pub fn default_dir() -> PathBuf {
+        let cache = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+
+        cache.join("collective").join("models")
+    }
+
+    /// Where `file` of the Hugging Face `repo` is cached.
+    #[must_use]
+    pub fn path(&self, repo: &str, file: &str) -> PathBuf {
+        self.dir.join(repo.replace('/', "--")).join(file)
+    }
+
+    /// The cached `file` of `repo`, downloading it first if needed.
+    ///
+    /// # Errors
+    /// If the file is not cached and cannot be downloaded.
+    pub async fn fetch(&self, repo: &str, file: &str) -> anyhow::Result<PathBuf> {
+        let path = self.path(repo, file);
+
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(path);
+        }
+
+        let url = format!("https://huggingface.co/{repo}/resolve/main/{file}");
+        info!("Downloading {url}");
+
+        let mut response = self.req.get(&url).send().await?;
+        ensure!(
+            response.status().is_success(),
+            "Failed to download {url}: {}",
+            response.status()
+        );
+
+        let dir = path.parent().context("a cached file is in a directory")?;
+        tokio::fs::create_dir_all(dir).await?;
+
+        // download next to the target so an interrupted download is never mistaken for a model
+        let partial = path.with_extension("partial");
+        let mut out = tokio::fs::File::create(&partial).await?;
+        while let Some(chunk) = response.chunk().await? {
+            out.write_all(&chunk).await?;
+        }
+        out.sync_all().await?;
+
+        tokio::fs::rename(&partial, &path).await?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModelCache;
+
+    #[tokio::test]
+    async fn test_cached() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ModelCache::new(dir.path(), reqwest::Client::new());
+
+        let path = cache.path("sentence-transformers/all-MiniLM-L6-v2", "config.json");
+        assert!(path.ends_with("sentence-transformers--all-MiniLM-L6-v2/config.json"));
+
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, "{}")?;
+
+        // served from disk, without a request
+        let fetched = cache
+            .fetch("sentence-transformers/all-MiniLM-L6-v2", "config.json")
+            .await?;
+        assert_eq!(fetched, path);
+
+        Ok(())
+    }
+}
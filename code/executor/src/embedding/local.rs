@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use tokenizers::{PaddingParams, Tokenizer};
+use tracing::info;
+
+use crate::embedding::{EmbeddingModel, EmbeddingProvider, ModelCache};
+
+struct Model {
+    bert: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+/// A BERT sentence-transformer running on this machine.
+pub struct LocalEmbeddings {
+    model: Arc<Model>,
+    metadata: EmbeddingModel,
+}
+
+/// The GPU if the executor was built for one, otherwise the CPU.
+fn device() -> Device {
+    #[cfg(feature = "metal")]
+    if let Ok(device) = Device::new_metal(0) {
+        return device;
+    }
+
+    #[cfg(feature = "cuda")]
+    if let Ok(device) = Device::new_cuda(0) {
+        return device;
+    }
+
+    Device::Cpu
+}
+
+impl LocalEmbeddings {
+    /// A model small enough to run on a laptop
+    pub const DEFAULT_MODEL: &'static str = "sentence-transformers/all-MiniLM-L6-v2";
+
+    /// Load the Hugging Face model `repo`, downloading it into `cache` if needed.
+    ///
+    /// # Errors
+    /// If the model cannot be downloaded or loaded.
+    pub async fn load(cache: &ModelCache, repo: &str) -> anyhow::Result<Self> {
+        let config = cache.fetch(repo, "config.json").await?;
+        let tokenizer = cache.fetch(repo, "tokenizer.json").await?;
+        let weights = cache.fetch(repo, "model.safetensors").await?;
+
+        let config: Config = serde_json::from_str(&tokio::fs::read_to_string(config).await?)
+            .context("Failed to parse the model config")?;
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+
+        let device = device();
+        info!("Loading {repo} on {device:?}");
+
+        // SAFETY: the weights are not modified while they are mapped, the cache only ever
+        // replaces complete files
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let bert = BertModel::load(vb, &config)?;
+
+        Ok(Self {
+            model: Arc::new(Model {
+                bert,
+                tokenizer,
+                device,
+            }),
+            metadata: EmbeddingModel {
+                name: repo.to_string(),
+                dimensions: config.hidden_size,
+            },
+        })
+    }
+}
+
+impl Model {
+    /// Mean pooled, normalized embeddings of `inputs`.
+    fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs.to_vec(), true)
+            .map_err(anyhow::Error::msg)?;
+
+        let stack = |f: fn(&tokenizers::Encoding) -> &[u32]| {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(f(encoding), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Tensor::stack(&rows, 0)
+        };
+
+        let token_ids = stack(tokenizers::Encoding::get_ids)?;
+        let attention_mask = stack(tokenizers::Encoding::get_attention_mask)?;
+        let token_type_ids = token_ids.zeros_like()?;
+
+        let hidden = self
+            .bert
+            .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+
+        // average the tokens, ignoring padding
+        let mask = attention_mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let sum = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let pooled = sum.broadcast_div(&mask.sum(1)?)?;
+
+        let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        let embeddings = pooled.broadcast_div(&norm)?;
+
+        Ok(embeddings.to_vec2()?)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for LocalEmbeddings {
+    fn model(&self) -> &EmbeddingModel {
+        &self.metadata
+    }
+
+    async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let model = self.model.clone();
+        let inputs = inputs.to_vec();
+
+        // inference is CPU (or GPU) bound and would block the runtime
+        tokio::task::spawn_blocking(move || model.embed(&inputs)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalEmbeddings;
+    use crate::embedding::{EmbeddingProvider, ModelCache};
+
+    fn similarity(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    #[tokio::test]
+    #[ignore = "downloads the model"]
+    async fn test_embed() -> anyhow::Result<()> {
+        let cache = ModelCache::new(ModelCache::default_dir(), reqwest::Client::new());
+        let embeddings = LocalEmbeddings::load(&cache, LocalEmbeddings::DEFAULT_MODEL).await?;
+
+        assert_eq!(embeddings.model().dimensions, 384);
+
+        let inputs =
+            ["parse a json file", "read json from disk", "bake a cake"].map(ToString::to_string);
+        let vectors = embeddings.embed(&inputs).await?;
+
+        assert_eq!(vectors.len(), 3);
+        assert!(vectors.iter().all(|vector| vector.len() == 384));
+        assert!(similarity(&vectors[0], &vectors[1]) > similarity(&vectors[0], &vectors[2]));
+
+        Ok(())
+    }
+}
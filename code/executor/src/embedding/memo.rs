@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+
+use crate::embedding::{EmbeddingModel, EmbeddingProvider};
+
+/// The SHA-256 of an embedded text
+type Key = [u8; 32];
+
+/// Keeps the vectors of the texts a provider embedded in memory, so texts that come back, such as
+/// the questions asked so far or the instruction, are only embedded once.
+///
+/// The least recently used vectors are evicted once they take more than the capacity in bytes.
+pub struct Memoized<P> {
+    provider: P,
+    cache: Mutex<Lru>,
+}
+
+impl<P> Memoized<P> {
+    /// Keep up to `capacity` bytes of the vectors `provider` returns
+    pub fn new(provider: P, capacity: usize) -> Self {
+        Self {
+            provider,
+            cache: Mutex::new(Lru::new(capacity)),
+        }
+    }
+}
+
+fn key(input: &str) -> Key {
+    Sha256::digest(input.as_bytes()).into()
+}
+
+#[async_trait]
+impl<P: EmbeddingProvider + Send + Sync> EmbeddingProvider for Memoized<P> {
+    fn model(&self) -> &EmbeddingModel {
+        self.provider.model()
+    }
+
+    async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let keys: Vec<_> = inputs.iter().map(|input| key(input)).collect();
+        let mut vectors: Vec<_> = {
+            let mut cache = self.cache.lock();
+            keys.iter().map(|key| cache.get(key)).collect()
+        };
+
+        // every missing text once, even if it is repeated in `inputs`
+        let mut missing: Vec<usize> = Vec::new();
+        for (index, vector) in vectors.iter().enumerate() {
+            if vector.is_none() && !missing.iter().any(|&other| keys[other] == keys[index]) {
+                missing.push(index);
+            }
+        }
+
+        if !missing.is_empty() {
+            let texts: Vec<_> = missing.iter().map(|&index| inputs[index].clone()).collect();
+            let embedded = self.provider.embed(&texts).await?;
+
+            let mut cache = self.cache.lock();
+            for (&index, embedded) in missing.iter().zip(embedded) {
+                for (key, vector) in keys.iter().zip(&mut vectors) {
+                    if *key == keys[index] && vector.is_none() {
+                        *vector = Some(embedded.clone());
+                    }
+                }
+                cache.insert(keys[index], embedded);
+            }
+        }
+
+        vectors
+            .into_iter()
+            .map(|vector| vector.ok_or_else(|| anyhow::anyhow!("the provider skipped an input")))
+            .collect()
+    }
+}
+
+/// Vectors by key, evicting the least recently used beyond a size in bytes
+struct Lru {
+    /// the vectors and when they were last used
+    entries: HashMap<Key, (Vec<f32>, u64)>,
+    /// the keys by when they were last used
+    recency: BTreeMap<u64, Key>,
+    /// incremented with every use
+    clock: u64,
+    size: usize,
+    capacity: usize,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            size: 0,
+            capacity,
+        }
+    }
+
+    /// The bytes an entry takes
+    fn size_of(vector: &[f32]) -> usize {
+        std::mem::size_of::<Key>() + std::mem::size_of_val(vector)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Vec<f32>> {
+        let now = self.tick();
+        let (vector, used) = self.entries.get_mut(key)?;
+        self.recency.remove(used);
+        self.recency.insert(now, *key);
+        *used = now;
+        Some(vector.clone())
+    }
+
+    fn insert(&mut self, key: Key, vector: Vec<f32>) {
+        let size = Self::size_of(&vector);
+        if size > self.capacity || self.entries.contains_key(&key) {
+            return;
+        }
+
+        while self.size + size > self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.size -= Self::size_of(&evicted);
+            }
+        }
+
+        let now = self.tick();
+        self.recency.insert(now, key);
+        self.entries.insert(key, (vector, now));
+        self.size += size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::{key, Lru, Memoized};
+    use crate::embedding::{EmbeddingModel, EmbeddingProvider};
+
+    /// Embeds text by its length, counting the embedded inputs
+    struct Lengths {
+        model: EmbeddingModel,
+        embedded: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EmbeddingProvider for Lengths {
+        fn model(&self) -> &EmbeddingModel {
+            &self.model
+        }
+
+        async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+            self.embedded.fetch_add(inputs.len(), Ordering::SeqCst);
+            Ok(inputs
+                .iter()
+                .map(|input| vec![input.len() as f32])
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memoized() -> anyhow::Result<()> {
+        let lengths = Lengths {
+            model: EmbeddingModel {
+                name: "lengths".to_string(),
+                dimensions: 1,
+            },
+            embedded: AtomicUsize::new(0),
+        };
+        let memoized = Memoized::new(lengths, 1 << 20);
+
+        let inputs = ["a", "bb", "a"].map(String::from);
+        assert_eq!(memoized.embed(&inputs).await?, [[1.0], [2.0], [1.0]]);
+        assert_eq!(memoized.provider.embedded.load(Ordering::SeqCst), 2);
+
+        let inputs = ["bb", "ccc"].map(String::from);
+        assert_eq!(memoized.embed(&inputs).await?, [[2.0], [3.0]]);
+        assert_eq!(memoized.provider.embedded.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lru() {
+        // room for two vectors of two dimensions
+        let mut lru = Lru::new(2 * (32 + 8));
+        lru.insert(key("a"), vec![1.0, 1.0]);
+        lru.insert(key("b"), vec![2.0, 2.0]);
+        assert!(lru.get(&key("a")).is_some());
+
+        // `b` was used least recently
+        lru.insert(key("c"), vec![3.0, 3.0]);
+        assert_eq!(lru.get(&key("b")), None);
+        assert_eq!(lru.get(&key("a")), Some(vec![1.0, 1.0]));
+        assert_eq!(lru.get(&key("c")), Some(vec![3.0, 3.0]));
+        assert_eq!(lru.size, 80);
+
+        // larger than the whole cache
+        lru.insert(key("d"), vec![0.0; 100]);
+        assert_eq!(lru.get(&key("d")), None);
+    }
+}
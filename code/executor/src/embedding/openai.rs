@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use anyhow::ensure;
+use async_trait::async_trait;
+
+use crate::{
+    embedding::{EmbeddingModel, EmbeddingProvider},
+    tape::Tape,
+};
+
+/// Embeddings of `text-embedding-ada-002`
+pub struct OpenAiEmbeddings {
+    client: tokio_openai::Client,
+    model: EmbeddingModel,
+    /// records or replays the embeddings, see [`crate::tape`]
+    tape: Option<Arc<Tape>>,
+}
+
+impl OpenAiEmbeddings {
+    #[must_use]
+    pub fn new(client: tokio_openai::Client) -> Self {
+        Self {
+            client,
+            model: EmbeddingModel {
+                name: "text-embedding-ada-002".to_string(),
+                dimensions: 1536,
+            },
+            tape: None,
+        }
+    }
+
+    #[must_use]
+    pub fn taped(self, tape: Option<Arc<Tape>>) -> Self {
+        Self { tape, ..self }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAiEmbeddings {
+    fn model(&self) -> &EmbeddingModel {
+        &self.model
+    }
+
+    async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(inputs.len());
+
+        for input in inputs {
+            let embedding = match &self.tape {
+                Some(tape) => {
+                    let request = serde_json::Value::String(input.clone());
+                    tape.play("embed", request, self.client.embed(input))
+                        .await?
+                }
+                None => self.client.embed(input).await?,
+            };
+            ensure!(
+                embedding.len() == self.model.dimensions,
+                "expected {} dimensions, got {}",
+                self.model.dimensions,
+                embedding.len()
+            );
+            embeddings.push(embedding);
+        }
+
+        Ok(embeddings)
+    }
+}
@@ -0,0 +1,61 @@
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::{
+    embedding::{EmbeddingModel, EmbeddingProvider},
+    responses::ResponseCache,
+};
+
+/// Keeps the vectors a provider returns in a [`ResponseCache`], so a workspace indexed before is
+/// not embedded again after the executor restarts.
+pub struct Persisted<P> {
+    provider: P,
+    responses: ResponseCache,
+}
+
+impl<P> Persisted<P> {
+    pub fn new(provider: P, responses: ResponseCache) -> Self {
+        Self {
+            provider,
+            responses,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: EmbeddingProvider + Send + Sync> EmbeddingProvider for Persisted<P> {
+    fn model(&self) -> &EmbeddingModel {
+        self.provider.model()
+    }
+
+    async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        // vectors of different models must not be mixed up
+        let kind = format!("embed:{}", self.model().name);
+        let keys = inputs
+            .iter()
+            .map(|input| ResponseCache::key(&kind, input))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut vectors: Vec<Option<Vec<f32>>> =
+            keys.iter().map(|key| self.responses.get(key)).collect();
+
+        let missing: Vec<usize> = (0..inputs.len())
+            .filter(|&index| vectors[index].is_none())
+            .collect();
+        if !missing.is_empty() {
+            let texts: Vec<_> = missing.iter().map(|&index| inputs[index].clone()).collect();
+            let embedded = self.provider.embed(&texts).await?;
+
+            for (&index, embedded) in missing.iter().zip(embedded) {
+                if let Err(e) = self.responses.put(&keys[index], &embedded) {
+                    warn!("Failed to cache an embedding: {e:#}");
+                }
+                vectors[index] = Some(embedded);
+            }
+        }
+
+        vectors
+            .into_iter()
+            .map(|vector| vector.ok_or_else(|| anyhow::anyhow!("the provider skipped an input")))
+            .collect()
+    }
+}
@@ -0,0 +1,234 @@
+//! A C API over an in-process executor, for embedding it without running the websocket server.
+//!
+//! Build it with `cargo rustc -p executor --lib --release --features ffi --crate-type cdylib`,
+//! which also generates the header `include/collective.h`. Packets from the executor are polled as
+//! the same JSON that is sent over the websocket.
+//!
+//! Every string passed in must be valid UTF-8 and NUL terminated. Strings returned by
+//! [`collective_agent_poll`] are owned by the caller and freed with [`collective_string_free`].
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr,
+    time::Duration,
+};
+
+use protocol::{client::Client, ClientPacket, ServerPacket};
+use tokio::{
+    runtime::Runtime,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{launch, Settings};
+
+/// The call succeeded
+pub const COLLECTIVE_OK: i32 = 0;
+/// An argument is null or not valid UTF-8
+pub const COLLECTIVE_INVALID_ARGUMENT: i32 = -1;
+/// The executor stopped, the agent has to be freed
+pub const COLLECTIVE_CLOSED: i32 = -2;
+
+/// An executor running on its own runtime, created with [`collective_agent_new`]
+pub struct CollectiveAgent {
+    tx: UnboundedSender<ClientPacket>,
+    rx: UnboundedReceiver<ServerPacket>,
+    /// dropped last, so the executor sees the channels close before it is stopped
+    runtime: Runtime,
+}
+
+impl CollectiveAgent {
+    fn new() -> anyhow::Result<Self> {
+        let runtime = Runtime::new()?;
+        let (tx, rx) = {
+            let _guard = runtime.enter();
+            launch(Settings::default())?
+        };
+        Ok(Self { tx, rx, runtime })
+    }
+
+    fn send(&self, data: Client) -> i32 {
+        match self.tx.send(ClientPacket::client(data)) {
+            Ok(()) => COLLECTIVE_OK,
+            Err(_) => COLLECTIVE_CLOSED,
+        }
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Option<String> {
+        let Self { runtime, rx, .. } = self;
+        let packet = runtime
+            .block_on(async { tokio::time::timeout(timeout, rx.recv()).await })
+            .ok()??;
+        packet.to_json().ok()
+    }
+}
+
+/// # Safety
+/// `s` must be null or a NUL terminated string.
+unsafe fn str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Start an executor. Returns null if it cannot be started, e.g. because `OPENAI_KEY` is not set.
+#[no_mangle]
+pub extern "C" fn collective_agent_new() -> *mut CollectiveAgent {
+    match CollectiveAgent::new() {
+        Ok(agent) => Box::into_raw(Box::new(agent)),
+        Err(e) => {
+            tracing::error!("Failed to start the executor: {e:#}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Stop the executor and free the agent.
+///
+/// # Safety
+/// `agent` must be null or returned by [`collective_agent_new`] and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_free(agent: *mut CollectiveAgent) {
+    if !agent.is_null() {
+        drop(Box::from_raw(agent));
+    }
+}
+
+/// Send an instruction, which starts a session.
+///
+/// # Safety
+/// `agent` must be a live agent, `instruction` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_instruct(
+    agent: *mut CollectiveAgent,
+    instruction: *const c_char,
+) -> i32 {
+    let (Some(agent), Some(instruction)) = (agent.as_ref(), str(instruction)) else {
+        return COLLECTIVE_INVALID_ARGUMENT;
+    };
+    agent.send(Client::Instruction {
+        instruction: instruction.to_string(),
+    })
+}
+
+/// Answer the current question.
+///
+/// # Safety
+/// `agent` must be a live agent, `answer` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_answer(
+    agent: *mut CollectiveAgent,
+    answer: *const c_char,
+) -> i32 {
+    let (Some(agent), Some(answer)) = (agent.as_ref(), str(answer)) else {
+        return COLLECTIVE_INVALID_ARGUMENT;
+    };
+    agent.send(Client::Answer {
+        answer: answer.to_string(),
+    })
+}
+
+/// Answer the question `id` of a batch of questions.
+///
+/// # Safety
+/// `agent` must be a live agent, `answer` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_answer_to(
+    agent: *mut CollectiveAgent,
+    id: u64,
+    answer: *const c_char,
+) -> i32 {
+    let (Some(agent), Some(answer)) = (agent.as_ref(), str(answer)) else {
+        return COLLECTIVE_INVALID_ARGUMENT;
+    };
+    agent.send(Client::AnswerTo {
+        id,
+        answer: answer.to_string(),
+    })
+}
+
+/// Send a packet given as JSON, e.g. `Execute`, which has no function of its own.
+///
+/// # Safety
+/// `agent` must be a live agent, `json` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_send(
+    agent: *mut CollectiveAgent,
+    json: *const c_char,
+) -> i32 {
+    let (Some(agent), Some(json)) = (agent.as_ref(), str(json)) else {
+        return COLLECTIVE_INVALID_ARGUMENT;
+    };
+    match ClientPacket::from_json(json) {
+        Ok(packet) => agent.send(packet.data),
+        Err(_) => COLLECTIVE_INVALID_ARGUMENT,
+    }
+}
+
+/// Wait up to `timeout_ms` milliseconds for the next packet of the executor and return it as
+/// JSON. Returns null if none arrived or the executor stopped.
+///
+/// # Safety
+/// `agent` must be a live agent that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn collective_agent_poll(
+    agent: *mut CollectiveAgent,
+    timeout_ms: u64,
+) -> *mut c_char {
+    let Some(agent) = agent.as_mut() else {
+        return ptr::null_mut();
+    };
+    agent
+        .poll(Duration::from_millis(timeout_ms))
+        .and_then(|json| CString::new(json).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Free a string returned by [`collective_agent_poll`].
+///
+/// # Safety
+/// `s` must be null or returned by [`collective_agent_poll`] and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn collective_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    #[test]
+    fn test_agent() {
+        unsafe {
+            let agent = collective_agent_new();
+            assert!(!agent.is_null());
+
+            let json = collective_agent_poll(agent, 5000);
+            assert!(!json.is_null());
+            let packet = ServerPacket::from_json(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert!(matches!(
+                packet.data,
+                protocol::server::Server::SessionCreated { .. }
+            ));
+            collective_string_free(json);
+
+            assert_eq!(
+                collective_agent_instruct(agent, ptr::null()),
+                COLLECTIVE_INVALID_ARGUMENT
+            );
+            let invalid = CString::new("{\"data\": 1}").unwrap();
+            assert_eq!(
+                collective_agent_send(agent, invalid.as_ptr()),
+                COLLECTIVE_INVALID_ARGUMENT
+            );
+            let ping = CString::new(ClientPacket::client(Client::Ping).to_json().unwrap()).unwrap();
+            assert_eq!(collective_agent_send(agent, ping.as_ptr()), COLLECTIVE_OK);
+
+            collective_agent_free(agent);
+        }
+    }
+}
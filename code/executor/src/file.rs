@@ -0,0 +1,294 @@
+//! Stream generated files to the frontend while they are written.
+//!
+//! Large generated files are sent line by line as [`server::FileChunk`] packets so the frontend
+//! can render them immediately. The content is written to a temporary file next to the target,
+//! which replaces the target (atomic rename) only once its checksum matches what was streamed.
+//!
+//! The target is only replaced if it still has the content it had when generation started,
+//! otherwise the write fails with [`Changed`] so the edits made meanwhile are not lost.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use anyhow::{ensure, Context};
+use futures::{Stream, StreamExt};
+use protocol::{server, Packet, ServerPacket};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncWriteExt, sync::mpsc::UnboundedSender};
+
+/// A file that was written completely
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrittenFile {
+    pub checksum: String,
+    pub lines: usize,
+    pub bytes: usize,
+    pub content: String,
+}
+
+/// The target was changed while its new content was generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changed {
+    /// the content on disk, `None` if the file was removed
+    pub current: Option<String>,
+    pub generated: String,
+}
+
+impl Display for Changed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the file was changed on disk while it was generated")
+    }
+}
+
+impl std::error::Error for Changed {}
+
+/// The content of the file at `path`, `None` if it does not exist.
+///
+/// # Errors
+/// If the file exists but could not be read.
+pub async fn read(path: &Path) -> anyhow::Result<Option<String>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Write `content` to `path` while streaming complete lines to the frontend.
+///
+/// The frontend receives a [`server::FileWritten`] on success and a [`server::FileFailed`] if
+/// anything went wrong, in which case the file at `path` is left untouched. `expected` is the
+/// content the file must still have, `None` if it must not exist.
+///
+/// # Errors
+/// - Sending packets to the frontend failed
+/// - `content` yielded an error
+/// - The file could not be written or failed checksum validation
+/// - The file no longer has the `expected` content, the error is a [`Changed`]
+pub async fn write_streamed<S>(
+    tx: &UnboundedSender<ServerPacket>,
+    path: &Path,
+    content: S,
+    expected: Option<&str>,
+) -> anyhow::Result<WrittenFile>
+where
+    S: Stream<Item = anyhow::Result<String>> + Send,
+{
+    let display = path.display().to_string();
+
+    match write(tx, path, &display, content, expected).await {
+        Ok(written) => {
+            tx.send(Packet::server(server::FileWritten {
+                path: display,
+                checksum: written.checksum.clone(),
+            }))?;
+            Ok(written)
+        }
+        Err(e) => {
+            tx.send(Packet::server(server::FileFailed {
+                path: display,
+                reason: format!("{e:#}"),
+            }))?;
+            Err(e)
+        }
+    }
+}
+
+async fn write<S>(
+    tx: &UnboundedSender<ServerPacket>,
+    path: &Path,
+    display: &str,
+    content: S,
+    expected: Option<&str>,
+) -> anyhow::Result<WrittenFile>
+where
+    S: Stream<Item = anyhow::Result<String>> + Send,
+{
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    // the temporary file lives in the same directory so the rename is atomic
+    let (file, temp_path) = tempfile::Builder::new()
+        .prefix(".collective-")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create temporary file in {}", dir.display()))?
+        .into_parts();
+
+    let mut file = tokio::fs::File::from_std(file);
+    let mut hasher = Sha256::new();
+
+    let mut pending = String::new();
+    let mut seq = 0;
+    let mut written = WrittenFile {
+        checksum: String::new(),
+        lines: 0,
+        bytes: 0,
+        content: String::new(),
+    };
+
+    let mut content = std::pin::pin!(content);
+
+    loop {
+        let delta = content.next().await.transpose()?;
+        let done = delta.is_none();
+
+        if let Some(delta) = delta {
+            pending.push_str(&delta);
+        }
+
+        // only complete lines are sent, unless the stream ended
+        let chunk = match pending.rfind('\n') {
+            _ if done => std::mem::take(&mut pending),
+            Some(idx) => pending.drain(..=idx).collect(),
+            None => continue,
+        };
+
+        if !chunk.is_empty() {
+            file.write_all(chunk.as_bytes()).await?;
+            hasher.update(chunk.as_bytes());
+
+            written.lines += chunk.lines().count();
+            written.bytes += chunk.len();
+            written.content.push_str(&chunk);
+
+            tx.send(Packet::server(server::FileChunk {
+                path: display.to_string(),
+                seq,
+                content: chunk,
+            }))?;
+
+            seq += 1;
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+
+    written.checksum = format!("{:x}", hasher.finalize());
+
+    // validate what actually ended up on disk before replacing the target
+    let on_disk = tokio::fs::read(&temp_path).await?;
+    let on_disk = protocol::checksum(on_disk);
+    ensure!(
+        on_disk == written.checksum,
+        "Checksum mismatch for {display}: streamed {} but wrote {on_disk}",
+        written.checksum
+    );
+
+    let current = read(path).await?;
+    if current.as_deref() != expected {
+        return Err(Changed {
+            current,
+            generated: written.content,
+        }
+        .into());
+    }
+
+    temp_path
+        .persist(path)
+        .with_context(|| format!("Failed to move temporary file to {display}"))?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use protocol::server::Server;
+
+    use super::{write_streamed, Changed};
+
+    #[tokio::test]
+    async fn test_write_streamed() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("main.rs");
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let deltas =
+            ["fn main() {\n    pri", "ntln!(\"hi\");\n", "}"].map(|delta| Ok(delta.to_string()));
+
+        let written = write_streamed(&tx, &path, stream::iter(deltas), None).await?;
+
+        let content = "fn main() {\n    println!(\"hi\");\n}";
+        assert_eq!(std::fs::read_to_string(&path)?, content);
+        assert_eq!(written.checksum, protocol::checksum(content));
+        assert_eq!(written.lines, 3);
+
+        let mut chunks = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            match packet.data {
+                Server::FileChunk { seq, content, .. } => chunks.push((seq, content)),
+                Server::FileWritten { checksum, .. } => assert_eq!(checksum, written.checksum),
+                other => panic!("unexpected packet: {other:?}"),
+            }
+        }
+
+        assert_eq!(chunks, vec![
+            (0, "fn main() {\n".to_string()),
+            (1, "    println!(\"hi\");\n".to_string()),
+            (2, "}".to_string()),
+        ]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_stream_keeps_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "original")?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let deltas = vec![
+            Ok("partial\n".to_string()),
+            Err(anyhow::anyhow!("stream broke")),
+        ];
+
+        let res = write_streamed(&tx, &path, stream::iter(deltas), Some("original")).await;
+        assert!(res.is_err());
+
+        assert_eq!(std::fs::read_to_string(&path)?, "original");
+
+        // only the target remains, the temporary file was removed
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        let last = std::iter::from_fn(|| rx.try_recv().ok()).last().unwrap();
+        assert!(matches!(last.data, Server::FileFailed { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changed_keeps_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "edited\n")?;
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let deltas = [Ok("generated\n".to_string())];
+
+        let e = write_streamed(&tx, &path, stream::iter(deltas), Some("original\n"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<Changed>(),
+            Some(&Changed {
+                current: Some("edited\n".to_string()),
+                generated: "generated\n".to_string(),
+            })
+        );
+        assert_eq!(std::fs::read_to_string(&path)?, "edited\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! Catch degenerate questions and plans before the user sees them, so they are generated again.
+//!
+//! A question is degenerate if it is empty, repeats an earlier question or echoes the instruction.
+//! Streamed questions are held back by a [`QuestionGuard`] for as long as they could still be
+//! one of those, so a degenerate question is never shown unless it keeps coming back. A plan is
+//! degenerate if it is a single vague step, or if a cheap model judges that it does not carry out
+//! the instruction.
+//!
+//! Every rejection is logged with the `guardrails` target, so prompts can be tuned from the
+//! outputs they were rejected for.
+
+use std::fmt;
+
+use tokio_openai::{ChatModel, ChatRequest};
+
+use crate::{plan::Plan, prompts::Prompt, Ctx};
+
+/// How often a degenerate question or plan is generated again before it is used anyway
+pub const MAX_REGENERATIONS: u32 = 2;
+
+/// The fewest words the description of a plan with a single step may have
+const MIN_DESCRIPTION_WORDS: usize = 5;
+
+/// The default template of [`Prompt::Judge`]
+pub const JUDGE_PROMPT: &str = "Judge whether the plan carries out the instruction of the \
+                                conversation. Answer with only `OK` if it does, otherwise with \
+                                one sentence saying what is wrong.";
+
+/// What is wrong with a question or a plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Flaw {
+    Empty,
+    /// it repeats an earlier question
+    Repeated,
+    /// it repeats the instruction
+    Echo,
+    /// a plan with a single step that says little
+    Vague,
+    /// the model judging the plan found it wrong, for this reason
+    Judged(String),
+}
+
+impl fmt::Display for Flaw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty"),
+            Self::Repeated => write!(f, "a repetition of an earlier question"),
+            Self::Echo => write!(f, "an echo of the instruction"),
+            Self::Vague => write!(f, "a single vague step"),
+            Self::Judged(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// The text questions are compared by
+fn normalize(text: &str) -> String {
+    text.trim()
+        .trim_end_matches(['?', '.', '!'])
+        .trim_end()
+        .to_lowercase()
+}
+
+/// Holds a streamed question back for as long as it could still be degenerate
+pub struct QuestionGuard {
+    instruction: String,
+    asked: Vec<String>,
+    held: String,
+    released: bool,
+}
+
+impl QuestionGuard {
+    pub fn new(instruction: &str, asked: &[String]) -> Self {
+        Self {
+            instruction: normalize(instruction),
+            asked: asked.iter().map(|question| normalize(question)).collect(),
+            held: String::new(),
+            released: false,
+        }
+    }
+
+    /// What of the question to show after it continued with `text`, `None` while it could still
+    /// be degenerate
+    pub fn push(&mut self, text: &str) -> Option<String> {
+        if self.released {
+            return Some(text.to_string());
+        }
+
+        self.held.push_str(text);
+        let so_far = normalize(&self.held);
+        let possible = so_far.is_empty()
+            || self.instruction.starts_with(&so_far)
+            || self
+                .asked
+                .iter()
+                .any(|question| question.starts_with(&so_far));
+        if possible {
+            return None;
+        }
+
+        Some(self.release())
+    }
+
+    /// What is wrong with the complete question, if anything
+    pub fn flaw(&self) -> Option<Flaw> {
+        if self.released {
+            return None;
+        }
+
+        let question = normalize(&self.held);
+        if question.is_empty() {
+            Some(Flaw::Empty)
+        } else if question == self.instruction {
+            Some(Flaw::Echo)
+        } else if self.asked.contains(&question) {
+            Some(Flaw::Repeated)
+        } else {
+            None
+        }
+    }
+
+    /// The part of the question that was held back, to show now
+    pub fn release(&mut self) -> String {
+        self.released = true;
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// What is obviously wrong with `plan`, without asking a model
+pub fn check_plan(plan: &Plan) -> Option<Flaw> {
+    match plan.steps.as_slice() {
+        [step] if step.description.split_whitespace().count() < MIN_DESCRIPTION_WORDS => {
+            Some(Flaw::Vague)
+        }
+        _ => None,
+    }
+}
+
+/// Ask a cheap model whether the plan `text` carries out the instruction in `context`.
+///
+/// # Errors
+/// If the model could not be reached.
+pub async fn judge_plan(ctx: &Ctx, context: &str, text: &str) -> anyhow::Result<Option<Flaw>> {
+    let request = ChatRequest::new()
+        .model(ChatModel::Turbo)
+        .sys_msg(ctx.prompts.get(Prompt::Judge))
+        .user_msg(format!("{context}\n\nPlan:\n{text}"));
+
+    let verdict = ctx.chat(request).await?;
+    Ok(parse_verdict(&verdict))
+}
+
+fn parse_verdict(verdict: &str) -> Option<Flaw> {
+    let verdict = verdict.trim().trim_matches('`');
+    if verdict.is_empty() || verdict.to_uppercase().starts_with("OK") {
+        return None;
+    }
+    Some(Flaw::Judged(verdict.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_plan, parse_verdict, Flaw, QuestionGuard};
+    use crate::plan::Plan;
+
+    /// Stream `question` through a guard in words, returning what was shown and the flaw
+    fn stream(guard: &mut QuestionGuard, question: &str) -> (String, Option<Flaw>) {
+        let mut shown: String = question
+            .split_inclusive(' ')
+            .filter_map(|word| guard.push(word))
+            .collect();
+        let flaw = guard.flaw();
+        if flaw.is_none() {
+            shown.push_str(&guard.release());
+        }
+        (shown, flaw)
+    }
+
+    #[test]
+    fn test_question_guard() {
+        let asked = ["Which language should it use?".to_string()];
+        let guard = || QuestionGuard::new("Create a calculator", &asked);
+
+        assert_eq!(
+            stream(&mut guard(), "  "),
+            ("".to_string(), Some(Flaw::Empty))
+        );
+        assert_eq!(
+            stream(&mut guard(), " which language should it use"),
+            ("".to_string(), Some(Flaw::Repeated))
+        );
+        assert_eq!(
+            stream(&mut guard(), "Create a calculator?"),
+            ("".to_string(), Some(Flaw::Echo))
+        );
+
+        // shown as soon as it differs, and completely
+        let mut diverging = guard();
+        assert_eq!(diverging.push(" Which"), None);
+        assert_eq!(
+            diverging.push(" operations"),
+            Some(" Which operations".to_string())
+        );
+        assert_eq!(diverging.push("?"), Some("?".to_string()));
+        assert_eq!(diverging.flaw(), None);
+
+        // a prefix of an earlier question is a question of its own
+        assert_eq!(
+            stream(&mut guard(), "Which language?"),
+            ("Which language?".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_check_plan() -> anyhow::Result<()> {
+        let plan = |steps: &[&str]| {
+            let steps: Vec<_> = steps
+                .iter()
+                .map(|description| {
+                    format!(
+                        r#"{{"title": "Step", "description": "{description}", "command": {{"type": "bash", "input": "ls"}}}}"#
+                    )
+                })
+                .collect();
+            Plan::parse(&format!("[{}]", steps.join(", ")))
+        };
+
+        assert_eq!(check_plan(&plan(&["Do it"])?), Some(Flaw::Vague));
+        assert_eq!(
+            check_plan(&plan(&["List the files of the working directory"])?),
+            None
+        );
+        assert_eq!(check_plan(&plan(&["Do it", "Check it"])?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_verdict() {
+        assert_eq!(parse_verdict("OK"), None);
+        assert_eq!(parse_verdict("`OK`."), None);
+        assert_eq!(
+            parse_verdict("It never writes the calculator."),
+            Some(Flaw::Judged("It never writes the calculator.".to_string()))
+        );
+    }
+}
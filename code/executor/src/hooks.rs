@@ -0,0 +1,302 @@
+//! Scripts the operator runs on the events of every session, e.g. to enforce a policy of the team.
+//!
+//! They are configured in the file of `--hooks`:
+//!
+//! ```toml
+//! [hooks]
+//! # before the interview about an instruction starts
+//! on_session_start = ["/etc/collective/check-instruction.sh"]
+//! # once the plan is written, before any step runs
+//! on_plan_approved = ["/etc/collective/review-plan.py"]
+//! # before every step
+//! before_command = ["/etc/collective/allow-command.sh"]
+//! # once every step ran, its exit code is ignored
+//! after_execution = ["/etc/collective/notify.sh"]
+//! ```
+//!
+//! Every hook is a shell command run in the working directory, with the [`Event`] as JSON on
+//! stdin. A hook exiting with a non-zero code vetoes what the event is about, with its stderr as
+//! the reason. So does a hook that cannot be started or does not finish within [`TIMEOUT`].
+//!
+//! Hooks are never read from the working directory. It may be a repository a client cloned, and
+//! its hooks would run its code on the executor before any plan is confirmed. For the same reason
+//! scripts are best given by absolute path, a relative one is looked up in the working directory.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{error, info};
+
+use crate::{
+    audit,
+    command::Cmd,
+    plan::{Plan, Step},
+    Inner,
+};
+
+/// How long a hook may run before it counts as a veto
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Default)]
+struct Config {
+    #[serde(default)]
+    hooks: Hooks,
+}
+
+/// The hooks of the executor, by event
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default)]
+    on_session_start: Vec<String>,
+    #[serde(default)]
+    on_plan_approved: Vec<String>,
+    #[serde(default)]
+    before_command: Vec<String>,
+    #[serde(default)]
+    after_execution: Vec<String>,
+}
+
+/// What happened, sent to the hooks of the event on stdin
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    SessionStart {
+        instruction: &'a str,
+    },
+    PlanApproved {
+        steps: Vec<StepEvent<'a>>,
+    },
+    BeforeCommand {
+        index: usize,
+        #[serde(flatten)]
+        step: StepEvent<'a>,
+    },
+    AfterExecution {
+        success: bool,
+    },
+}
+
+/// A step of the plan as hooks see it
+#[derive(Debug, Serialize)]
+pub struct StepEvent<'a> {
+    title: &'a str,
+    command: Cmd,
+    input: &'a str,
+}
+
+impl<'a> From<&'a Step> for StepEvent<'a> {
+    fn from(step: &'a Step) -> Self {
+        Self {
+            title: &step.title,
+            command: step.command.cmd,
+            input: &step.command.input,
+        }
+    }
+}
+
+impl<'a> Event<'a> {
+    /// The name of the event, as in the configuration
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::SessionStart { .. } => "on_session_start",
+            Self::PlanApproved { .. } => "on_plan_approved",
+            Self::BeforeCommand { .. } => "before_command",
+            Self::AfterExecution { .. } => "after_execution",
+        }
+    }
+
+    pub fn plan_approved(plan: &'a Plan) -> Self {
+        Self::PlanApproved {
+            steps: plan.steps.iter().map(StepEvent::from).collect(),
+        }
+    }
+
+    pub fn before_command(index: usize, step: &'a Step) -> Self {
+        Self::BeforeCommand {
+            index,
+            step: step.into(),
+        }
+    }
+}
+
+impl Hooks {
+    /// Load the hooks at `path`, none without it.
+    ///
+    /// # Errors
+    /// If the file cannot be read or is not valid.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the hooks {}", path.display()))?;
+
+        let config: Config =
+            toml::from_str(&content).with_context(|| format!("{} is not valid", path.display()))?;
+        Ok(config.hooks)
+    }
+
+    /// Whether there are hooks that can veto single commands
+    pub fn vets_commands(&self) -> bool {
+        !self.before_command.is_empty()
+    }
+
+    fn scripts(&self, event: &Event) -> &[String] {
+        match event {
+            Event::SessionStart { .. } => &self.on_session_start,
+            Event::PlanApproved { .. } => &self.on_plan_approved,
+            Event::BeforeCommand { .. } => &self.before_command,
+            Event::AfterExecution { .. } => &self.after_execution,
+        }
+    }
+
+    /// Run the hooks of `event` in `dir`, in the order they are configured, recording a veto in
+    /// the audit log of `ctx`.
+    ///
+    /// # Errors
+    /// If a hook vetoed the event, with its reason. The hooks after it do not run.
+    pub async fn check(&self, ctx: &Inner, dir: &Path, event: &Event<'_>) -> anyhow::Result<()> {
+        let scripts = self.scripts(event);
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_vec(event)?;
+        for script in scripts {
+            if let Err(e) = run(dir, script, &payload).await {
+                info!(hook = script, ?event, "Vetoed by hook: {e:#}");
+                ctx.audit(&audit::Event::HookVetoed {
+                    hook: script,
+                    on: event.name(),
+                    reason: format!("{e:#}"),
+                });
+                return Err(e).context(format!("vetoed by the hook `{script}`"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the hooks of `event`, which cannot veto anything, logging those that fail.
+    pub async fn notify(&self, ctx: &Inner, dir: &Path, event: &Event<'_>) {
+        if let Err(e) = self.check(ctx, dir, event).await {
+            error!("{e:#}");
+        }
+    }
+}
+
+/// Run `script` in `dir` with `payload` on stdin.
+///
+/// # Errors
+/// If it could not be started, timed out or exited with a non-zero code, with its stderr.
+async fn run(dir: &Path, script: &str, payload: &[u8]) -> anyhow::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start the hook")?;
+
+    let mut stdin = child.stdin.take().context("the hook has no stdin")?;
+    // a hook may exit without reading its event
+    let _ = stdin.write_all(payload).await;
+    drop(stdin);
+
+    let output = tokio::time::timeout(TIMEOUT, child.wait_with_output())
+        .await
+        .with_context(|| format!("the hook did not finish within {}s", TIMEOUT.as_secs()))??;
+
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        let code = output
+            .status
+            .code()
+            .map_or_else(|| "a signal".to_string(), |code| code.to_string());
+        bail!("exited with {code}: {}", reason.trim());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Event, Hooks};
+    use crate::audit::AuditLog;
+
+    #[tokio::test]
+    async fn test_hooks() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let audit = tempfile::NamedTempFile::new()?;
+        let mut ctx = crate::ctx()?;
+        Arc::get_mut(&mut ctx).unwrap().audit = Some(AuditLog::open(audit.path())?);
+        assert!(!Hooks::load(None)?.vets_commands());
+        let config = tempfile::NamedTempFile::new()?;
+        assert!(Hooks::load(Some(dir.path().join("missing.toml"))).is_err());
+
+        std::fs::write(
+            config.path(),
+            r#"
+            [hooks]
+            on_session_start = [
+                "cat > event.json",
+                "grep -q 'rm -rf' event.json && echo 'no deleting' >&2 && exit 3 || true",
+            ]
+            after_execution = ["exit 1"]
+            "#,
+        )?;
+        let hooks = Hooks::load(Some(config.path().to_path_buf()))?;
+        assert!(!hooks.vets_commands());
+
+        let start = Event::SessionStart {
+            instruction: "Build a calculator",
+        };
+        hooks.check(&ctx, dir.path(), &start).await?;
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("event.json"))?,
+            r#"{"event":"session_start","instruction":"Build a calculator"}"#
+        );
+
+        let start = Event::SessionStart {
+            instruction: "rm -rf the repository",
+        };
+        let err = hooks.check(&ctx, dir.path(), &start).await.unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "vetoed by the hook `grep -q 'rm -rf' event.json && echo 'no deleting' >&2 && exit 3 \
+             || true`: exited with 3: no deleting"
+        );
+
+        // nothing to veto, the failure is only logged
+        hooks
+            .notify(&ctx, dir.path(), &Event::AfterExecution { success: true })
+            .await;
+
+        let vetoes: Vec<serde_json::Value> = std::fs::read_to_string(audit.path())?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(vetoes.len(), 2);
+        assert_eq!(vetoes[0]["event"], "hook_vetoed");
+        assert_eq!(vetoes[0]["on"], "on_session_start");
+        assert_eq!(vetoes[0]["reason"], "exited with 3: no deleting");
+        assert_eq!(vetoes[1]["on"], "after_execution");
+
+        std::fs::write(config.path(), "[hooks]\non_start = []")?;
+        assert!(Hooks::load(Some(config.path().to_path_buf())).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Plain HTTP endpoints served next to the websocket, for operators and their orchestrators:
+//!
+//! - `/healthz`: `ok`, or 503 once the executor is shutting down
+//! - `/sessions`: the sessions as JSON, see [`SessionInfo`](crate::SessionInfo)
+//! - `/metrics`: the sessions, how the structured answers of the model turned out and the
+//!   [`Telemetry`](crate::telemetry::Telemetry) of the executor, in the Prometheus text format
+//! - `/version`: the version of the executor and of the protocol as JSON
+//!
+//! With `--token` set, `/sessions` and `/metrics` take it like the websocket does, as
+//! `Authorization: Bearer <token>`, and answer `401 Unauthorized` without it.
+
+use std::{fmt::Write, sync::Arc, time::Instant};
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Value};
+
+use crate::{auth, telemetry::header, Executor, SessionManager};
+
+#[derive(Clone)]
+struct Status {
+    executor: Executor,
+    sessions: SessionManager,
+    started: Instant,
+    /// the token of `--token`, if set
+    token: Option<Arc<str>>,
+}
+
+fn router(executor: Executor, sessions: SessionManager, token: Option<String>) -> Router {
+    let status = Status {
+        executor,
+        sessions,
+        started: Instant::now(),
+        token: token.map(Arc::from),
+    };
+    Router::new()
+        .route("/sessions", get(sessions_list))
+        .route("/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(status.clone(), authorize))
+        .route("/healthz", get(healthz))
+        .route("/version", get(version))
+        .with_state(status)
+}
+
+/// Answer `request` only if it carries the token, if one is set
+async fn authorize<B>(
+    State(status): State<Status>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(token) = &status.token {
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        if !auth::bearer(authorization, token) {
+            return (StatusCode::UNAUTHORIZED, "A valid token is required").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Serve the endpoints on `listener` until the returned future is dropped.
+///
+/// # Errors
+/// If the listener cannot be used or the server fails
+pub async fn serve(
+    listener: std::net::TcpListener,
+    executor: Executor,
+    sessions: SessionManager,
+    token: Option<String>,
+) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+    axum::Server::from_tcp(listener)
+        .context("Failed to serve HTTP")?
+        .serve(router(executor, sessions, token).into_make_service())
+        .await
+        .context("The HTTP server failed")
+}
+
+async fn healthz(State(status): State<Status>) -> (StatusCode, &'static str) {
+    if status.sessions.is_shutting_down() {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+    } else {
+        (StatusCode::OK, "ok")
+    }
+}
+
+async fn sessions_list(State(status): State<Status>) -> Json<Value> {
+    let sessions: Vec<_> = status
+        .sessions
+        .list()
+        .into_iter()
+        .map(|info| {
+            json!({
+                "id": info.id.to_string(),
+                "attached": info.attached,
+            })
+        })
+        .collect();
+    Json(Value::Array(sessions))
+}
+
+async fn metrics(State(status): State<Status>) -> String {
+    let sessions = status.sessions.list();
+    let attached = sessions.iter().filter(|info| info.attached).count();
+
+    let mut metrics = String::new();
+
+    let name = "collective_uptime_seconds";
+    header(
+        &mut metrics,
+        name,
+        "gauge",
+        "Seconds since the executor started",
+    );
+    let _ = writeln!(metrics, "{name} {}", status.started.elapsed().as_secs());
+
+    let name = "collective_sessions";
+    header(
+        &mut metrics,
+        name,
+        "gauge",
+        "Sessions, attached ones have a connection",
+    );
+    let _ = writeln!(metrics, "{name}{{attached=\"true\"}} {attached}");
+    let detached = sessions.len() - attached;
+    let _ = writeln!(metrics, "{name}{{attached=\"false\"}} {detached}");
+
+    let name = "collective_structured_answers_total";
+    let help = "Structured answers of the model by how they turned out";
+    header(&mut metrics, name, "counter", help);
+    for (prompt, counts) in status.executor.ctx.repairs.counts() {
+        let prompt = prompt.name();
+        for (outcome, count) in [
+            ("valid", counts.valid),
+            ("repaired", counts.repaired),
+            ("lenient", counts.lenient),
+            ("failed", counts.failed),
+        ] {
+            let labels = format!("prompt=\"{prompt}\",outcome=\"{outcome}\"");
+            let _ = writeln!(metrics, "{name}{{{labels}}} {count}");
+        }
+    }
+
+    status.executor.ctx.telemetry.render(&mut metrics);
+
+    metrics
+}
+
+async fn version() -> Json<Value> {
+    Json(json!({
+        "executor": env!("CARGO_PKG_VERSION"),
+        "protocol": protocol::PROTOCOL_VERSION,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use crate::{prompts::Prompt, repair::Outcome, Executor, SessionManager, Settings};
+
+    #[tokio::test]
+    async fn test_endpoints() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        executor.ctx.repairs.record(Prompt::Plan, Outcome::Repaired);
+        executor.ctx.telemetry.packet("Instruction");
+        let sessions = SessionManager::new();
+        let id = sessions.create();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(super::serve(listener, executor, sessions.clone(), None));
+        let get = |path: &str| reqwest::get(format!("{url}{path}"));
+
+        let health = get("/healthz").await?;
+        assert_eq!(health.status(), StatusCode::OK);
+        assert_eq!(health.text().await?, "ok");
+
+        let listed: serde_json::Value =
+            serde_json::from_str(&get("/sessions").await?.text().await?)?;
+        assert_eq!(
+            listed,
+            serde_json::json!([{"id": id.to_string(), "attached": true}])
+        );
+
+        let metrics = get("/metrics").await?.text().await?;
+        assert!(metrics.contains("collective_sessions{attached=\"true\"} 1\n"));
+        assert!(metrics.contains(
+            "collective_structured_answers_total{prompt=\"plan\",outcome=\"repaired\"} 1\n"
+        ));
+        assert!(metrics.contains("collective_packets_total{type=\"Instruction\"} 1\n"));
+
+        let version: serde_json::Value =
+            serde_json::from_str(&get("/version").await?.text().await?)?;
+        assert_eq!(version["protocol"], protocol::PROTOCOL_VERSION);
+
+        sessions.shutdown();
+        let health = get("/healthz").await?;
+        assert_eq!(health.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_token() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        let sessions = SessionManager::new();
+        let _ = sessions.create();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let token = Some("secret".to_string());
+        tokio::spawn(super::serve(listener, executor, sessions, token));
+        let client = reqwest::Client::new();
+        let get = |path: &str, token: Option<&str>| {
+            let mut request = client.get(format!("{url}{path}"));
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+
+        for path in ["/sessions", "/metrics"] {
+            assert_eq!(get(path, None).await?.status(), StatusCode::UNAUTHORIZED);
+            let wrong = get(path, Some("wrong")).await?;
+            assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(get(path, Some("secret")).await?.status(), StatusCode::OK);
+        }
+        // for orchestrators without the token
+        assert_eq!(get("/healthz", None).await?.status(), StatusCode::OK);
+        assert_eq!(get("/version", None).await?.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
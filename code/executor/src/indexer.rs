@@ -0,0 +1,374 @@
+//! Index the working directory of a session so questions and plans can cite relevant files.
+//!
+//! Files that are not ignored (`.gitignore`, hidden files) are split into chunks of lines,
+//! embedded and kept in a [`VectorStore`]. The symbols they define are kept in a [`SymbolIndex`],
+//! also without embeddings. Indexing again only processes the files whose content changed since
+//! and forgets removed ones, [`Indexer::watch`] does so whenever a file changes.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+use tracing::{error, info};
+
+pub use crate::indexer::store::Chunk;
+use crate::{
+    embedding::EmbeddingProvider,
+    indexer::{store::VectorStore, symbols::SymbolIndex},
+};
+
+mod store;
+mod symbols;
+
+/// How many lines are embedded together
+const CHUNK_LINES: usize = 40;
+
+/// Larger files are likely generated or data, not worth citing
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+
+/// Stop indexing in huge workspaces
+const MAX_FILES: usize = 5_000;
+
+/// How many chunks are cited in a prompt
+const CITED_CHUNKS: usize = 5;
+
+/// How many of the symbols a query mentions are cited
+const CITED_SYMBOLS: usize = 20;
+
+pub type Embeddings = Arc<dyn EmbeddingProvider + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Indexer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    root: PathBuf,
+    /// `None` if only symbols are indexed
+    embeddings: Option<Embeddings>,
+    store: Mutex<VectorStore>,
+    symbols: Mutex<SymbolIndex>,
+}
+
+impl Indexer {
+    pub fn new(root: impl Into<PathBuf>, embeddings: Option<Embeddings>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                root: root.into(),
+                embeddings,
+                store: Mutex::default(),
+                symbols: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The `limit` chunks most similar to `query`, the most similar first. None without
+    /// embeddings.
+    ///
+    /// # Errors
+    /// If `query` could not be embedded.
+    pub async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<Chunk>> {
+        let Some(embeddings) = &self.inner.embeddings else {
+            return Ok(Vec::new());
+        };
+        if self.inner.store.lock().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query = embeddings.embed(&[query.to_string()]).await?;
+        let query = query.first().map_or(&[][..], Vec::as_slice);
+
+        Ok(self.inner.store.lock().search(query, limit))
+    }
+
+    /// The parts of the workspace relevant to `query` and the symbols it mentions with where they
+    /// are defined, for a prompt. Empty if nothing is indexed yet. A failed search is only
+    /// logged.
+    pub async fn cite(&self, query: &str) -> String {
+        let chunks = self.search(query, CITED_CHUNKS).await.unwrap_or_else(|e| {
+            error!("Failed to search the index: {e:#}");
+            Vec::new()
+        });
+
+        let symbols = self.inner.symbols.lock();
+        let mut cited = String::new();
+
+        if !chunks.is_empty() {
+            cited.push_str("\n\nRelevant parts of the workspace:\n");
+        }
+        for chunk in chunks {
+            let defined: Vec<_> = symbols
+                .within(&chunk.path, chunk.start, chunk.end)
+                .map(ToString::to_string)
+                .collect();
+            let defines = if defined.is_empty() {
+                String::new()
+            } else {
+                format!(", defining {}", defined.join(", "))
+            };
+            cited.push_str(&format!(
+                "\n{} (lines {}-{}{defines}):\n```\n{}\n```\n",
+                chunk.path,
+                chunk.start,
+                chunk.end,
+                chunk.content.trim_end()
+            ));
+        }
+
+        let mentioned = symbols.mentioned(query, CITED_SYMBOLS);
+        if !mentioned.is_empty() {
+            cited
+                .push_str("\n\nSymbols of the workspace it mentions, refer to them by location:\n");
+            for symbol in mentioned {
+                cited.push_str(&format!("- {symbol}\n"));
+            }
+        }
+        cited
+    }
+
+    /// Index the workspace, then again every `interval` a file changed, until the indexer is
+    /// dropped.
+    pub fn watch(&self, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        crate::tasks::spawn("indexer", watch(inner, interval));
+    }
+}
+
+async fn watch(inner: Weak<Inner>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+
+        match inner.update().await {
+            Ok(0) => {}
+            Ok(indexed) => info!("Indexed {indexed} files in {}", inner.root.display()),
+            // the next change tries again
+            Err(e) => error!("Failed to index {}: {e:#}", inner.root.display()),
+        }
+    }
+}
+
+impl Inner {
+    /// Index the files that changed since they were indexed and forget removed ones. Returns how
+    /// many files were indexed.
+    ///
+    /// If a file could not be embedded, the files indexed before it stay indexed.
+    async fn update(&self) -> anyhow::Result<usize> {
+        let root = self.root.clone();
+        let files = tokio::task::spawn_blocking(move || files(&root)).await?;
+
+        let exists = |path: &str| files.iter().any(|(indexed, ..)| indexed == path);
+        self.symbols.lock().retain(exists);
+        let forgotten = self.store.lock().retain(exists);
+        if forgotten > 0 {
+            info!(
+                "Forgot {forgotten} removed files in {}",
+                self.root.display()
+            );
+        }
+
+        let mut indexed = 0;
+        for (path, modified) in files {
+            if self.store.lock().is_unmodified(&path, modified) {
+                continue;
+            }
+
+            // binary files are not worth citing
+            let Ok(content) = tokio::fs::read_to_string(self.root.join(&path)).await else {
+                continue;
+            };
+
+            let checksum = protocol::checksum(&content);
+            if self.store.lock().is_current(&path, &checksum) {
+                self.store.lock().touch(&path, modified);
+                continue;
+            }
+
+            let symbols = symbols::parse(&path, &content);
+            self.symbols.lock().insert(path.clone(), symbols);
+
+            let chunks = match &self.embeddings {
+                Some(embeddings) => {
+                    let chunks = chunks(&path, &content);
+                    // the path tells the model what the lines are about
+                    let inputs: Vec<_> = chunks
+                        .iter()
+                        .map(|chunk| format!("{}\n{}", chunk.path, chunk.content))
+                        .collect();
+                    let vectors = embeddings.embed(&inputs).await?;
+                    chunks.into_iter().zip(vectors).collect()
+                }
+                None => Vec::new(),
+            };
+            self.store.lock().insert(path, modified, checksum, chunks);
+            indexed += 1;
+        }
+
+        Ok(indexed)
+    }
+}
+
+/// The files in `root` worth indexing with when they were last modified, their paths relative to
+/// `root`
+fn files(root: &Path) -> Vec<(String, Option<SystemTime>)> {
+    let walk = ignore::WalkBuilder::new(root)
+        // a workspace that is not a repository yet can ignore files as well
+        .require_git(false)
+        .build();
+
+    walk.flatten()
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if metadata.len() > MAX_FILE_BYTES {
+                return None;
+            }
+
+            let path = entry.path().strip_prefix(root).ok()?;
+            Some((path.display().to_string(), metadata.modified().ok()))
+        })
+        .take(MAX_FILES)
+        .collect()
+}
+
+/// `content` of the file at `path` split into chunks of [`CHUNK_LINES`] lines
+fn chunks(path: &str, content: &str) -> Vec<Chunk> {
+    let lines: Vec<_> = content.lines().collect();
+
+    lines
+        .chunks(CHUNK_LINES)
+        .enumerate()
+        .map(|(index, lines)| {
+            let start = index * CHUNK_LINES + 1;
+            Chunk {
+                path: path.to_string(),
+                start,
+                end: start + lines.len() - 1,
+                content: lines.join("\n"),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{chunks, Indexer};
+    use crate::embedding::{EmbeddingModel, EmbeddingProvider};
+
+    /// Embeds text by how often it contains `a`, `b` and `c`, counting the embedded inputs
+    struct Letters {
+        model: EmbeddingModel,
+        embedded: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EmbeddingProvider for Letters {
+        fn model(&self) -> &EmbeddingModel {
+            &self.model
+        }
+
+        async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+            self.embedded.fetch_add(inputs.len(), Ordering::SeqCst);
+            Ok(inputs
+                .iter()
+                .map(|input| {
+                    ['a', 'b', 'c']
+                        .map(|letter| input.matches(letter).count() as f32)
+                        .to_vec()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_chunks() {
+        let content: String = (1..=45).map(|line| format!("{line}\n")).collect();
+        let chunks = chunks("lines.txt", &content);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].start, chunks[0].end), (1, 40));
+        assert_eq!((chunks[1].start, chunks[1].end), (41, 45));
+        assert_eq!(chunks[1].content, "41\n42\n43\n44\n45");
+    }
+
+    #[tokio::test]
+    async fn test_update() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join(".gitignore"), "ignored.txt\n")?;
+        std::fs::write(dir.path().join("a.txt"), "aaaa")?;
+        std::fs::write(dir.path().join("b.txt"), "bbbb")?;
+        std::fs::write(dir.path().join("ignored.txt"), "cccc")?;
+
+        let letters = Arc::new(Letters {
+            model: EmbeddingModel {
+                name: "letters".to_string(),
+                dimensions: 3,
+            },
+            embedded: AtomicUsize::new(0),
+        });
+        let indexer = Indexer::new(dir.path(), Some(letters.clone()));
+
+        assert_eq!(indexer.inner.update().await?, 2);
+        let found = indexer.search("b", 1).await?;
+        assert_eq!(found[0].path, "b.txt");
+        // nothing is similar to what is ignored
+        let found = indexer.search("c", 3).await?;
+        assert!(found.iter().all(|chunk| chunk.path != "ignored.txt"));
+
+        // only the changed file is embedded again
+        letters.embedded.store(0, Ordering::SeqCst);
+        std::fs::write(dir.path().join("a.txt"), "cccc")?;
+        std::fs::remove_file(dir.path().join("b.txt"))?;
+        assert_eq!(indexer.inner.update().await?, 1);
+        assert_eq!(letters.embedded.load(Ordering::SeqCst), 1);
+
+        let cited = indexer.cite("c").await;
+        assert!(cited.contains("a.txt (lines 1-1):\n```\ncccc\n```"));
+        assert!(!cited.contains("b.txt"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_symbols() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let lib = "struct Executor;\n\nimpl Executor {\n    fn run(&self) {}\n}\n";
+        std::fs::write(dir.path().join("lib.rs"), lib)?;
+
+        // symbols are indexed without embeddings
+        let indexer = Indexer::new(dir.path(), None);
+        assert_eq!(indexer.inner.update().await?, 1);
+
+        let cited = indexer.cite("Make Executor::run faster").await;
+        assert!(
+            cited.contains("- fn `Executor::run` in lib.rs:4\n"),
+            "{cited}"
+        );
+        assert!(indexer.cite("Make it faster").await.is_empty());
+
+        std::fs::remove_file(dir.path().join("lib.rs"))?;
+        assert_eq!(indexer.inner.update().await?, 0);
+        assert!(indexer.cite("Make Executor::run faster").await.is_empty());
+
+        Ok(())
+    }
+}
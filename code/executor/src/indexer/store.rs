@@ -0,0 +1,160 @@
+use std::{collections::HashMap, time::SystemTime};
+
+/// Lines of a file that are embedded together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// relative to the workspace root
+    pub path: String,
+    /// the first line, starting at 1
+    pub start: usize,
+    /// the last line, inclusive
+    pub end: usize,
+    pub content: String,
+}
+
+/// An indexed file and the vectors of its chunks
+struct File {
+    modified: Option<SystemTime>,
+    checksum: String,
+    chunks: Vec<(Chunk, Vec<f32>)>,
+}
+
+/// The chunks of every indexed file, searched by cosine similarity
+#[derive(Default)]
+pub struct VectorStore {
+    files: HashMap<String, File>,
+}
+
+impl VectorStore {
+    /// Whether `path` was indexed with `checksum`
+    #[must_use]
+    pub fn is_current(&self, path: &str, checksum: &str) -> bool {
+        self.files
+            .get(path)
+            .is_some_and(|file| file.checksum == checksum)
+    }
+
+    /// Whether `path` was indexed when it was last modified at `modified`
+    #[must_use]
+    pub fn is_unmodified(&self, path: &str, modified: Option<SystemTime>) -> bool {
+        modified.is_some()
+            && self
+                .files
+                .get(path)
+                .is_some_and(|file| file.modified == modified)
+    }
+
+    /// Record that `path` was modified at `modified` without its content changing.
+    pub fn touch(&mut self, path: &str, modified: Option<SystemTime>) {
+        if let Some(file) = self.files.get_mut(path) {
+            file.modified = modified;
+        }
+    }
+
+    /// Replace the chunks of `path` with `chunks`, which are paired with their vectors.
+    pub fn insert(
+        &mut self,
+        path: String,
+        modified: Option<SystemTime>,
+        checksum: String,
+        chunks: Vec<(Chunk, Vec<f32>)>,
+    ) {
+        let chunks = chunks
+            .into_iter()
+            .map(|(chunk, vector)| (chunk, normalized(vector)))
+            .collect();
+
+        self.files.insert(path, File {
+            modified,
+            checksum,
+            chunks,
+        });
+    }
+
+    /// Forget the files `keep` returns `false` for. Returns how many were forgotten.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) -> usize {
+        let len = self.files.len();
+        self.files.retain(|path, _| keep(path));
+        len - self.files.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The `limit` chunks most similar to `query`, the most similar first
+    #[must_use]
+    pub fn search(&self, query: &[f32], limit: usize) -> Vec<Chunk> {
+        let query = normalized(query.to_vec());
+
+        let mut scored: Vec<_> = self
+            .files
+            .values()
+            .flat_map(|file| &file.chunks)
+            .map(|(chunk, vector)| (dot(&query, vector), chunk))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, chunk)| chunk.clone())
+            .collect()
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// `vector` scaled to a length of 1, so the dot product is the cosine similarity
+fn normalized(mut vector: Vec<f32>) -> Vec<f32> {
+    let length = dot(&vector, &vector).sqrt();
+    if length > 0.0 {
+        for value in &mut vector {
+            *value /= length;
+        }
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, VectorStore};
+
+    fn chunk(path: &str) -> Chunk {
+        Chunk {
+            path: path.to_string(),
+            start: 1,
+            end: 1,
+            content: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let mut store = VectorStore::default();
+        store.insert("a.rs".to_string(), None, "a".to_string(), vec![(
+            chunk("a.rs"),
+            vec![1.0, 0.0],
+        )]);
+        store.insert("b.rs".to_string(), None, "b".to_string(), vec![(
+            chunk("b.rs"),
+            vec![3.0, 3.0],
+        )]);
+
+        // the direction matters, not the length
+        let paths = |query: &[f32]| -> Vec<String> {
+            store.search(query, 2).into_iter().map(|c| c.path).collect()
+        };
+        assert_eq!(paths(&[0.1, 0.2]), ["b.rs", "a.rs"]);
+        assert_eq!(paths(&[5.0, 0.0]), ["a.rs", "b.rs"]);
+        assert_eq!(store.search(&[1.0, 0.0], 1), [chunk("a.rs")]);
+
+        assert!(store.is_current("a.rs", "a"));
+        assert!(!store.is_current("a.rs", "b"));
+        assert_eq!(store.retain(|path| path == "b.rs"), 1);
+        assert!(!store.is_current("a.rs", "a"));
+    }
+}
@@ -0,0 +1,283 @@
+//! The symbols a workspace defines, so prompts can name them with the file and line they are on.
+//!
+//! Rust files are parsed with `syn`. A file that does not parse, e.g. while it is being written,
+//! defines no symbols until it does.
+
+use std::{collections::HashMap, fmt};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use syn::{ImplItem, Item, TraitItem};
+
+/// Paths such as `run` or `Executor::run`
+static PATH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z_][A-Za-z0-9_]*(?:::[A-Za-z_][A-Za-z0-9_]*)*").expect("valid regex")
+});
+
+/// What a symbol is, named like its keyword
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Fn,
+    Struct,
+    Enum,
+    Union,
+    Trait,
+    Type,
+    Const,
+    Static,
+    Mod,
+    Macro,
+}
+
+impl Kind {
+    const fn keyword(self) -> &'static str {
+        match self {
+            Self::Fn => "fn",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Union => "union",
+            Self::Trait => "trait",
+            Self::Type => "type",
+            Self::Const => "const",
+            Self::Static => "static",
+            Self::Mod => "mod",
+            Self::Macro => "macro",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind: Kind,
+    /// with the inline modules and the type it is defined in, e.g. `Executor::run`
+    pub name: String,
+    /// relative to the workspace root
+    pub path: String,
+    /// the line of its name, starting at 1
+    pub line: usize,
+}
+
+/// e.g. ``fn `Executor::run` in src/lib.rs:83``
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            kind,
+            name,
+            path,
+            line,
+        } = self;
+        write!(f, "{} `{name}` in {path}:{line}", kind.keyword())
+    }
+}
+
+/// The symbols of every indexed file
+#[derive(Default)]
+pub struct SymbolIndex {
+    files: HashMap<String, Vec<Symbol>>,
+}
+
+impl SymbolIndex {
+    /// Replace the symbols of `path` with `symbols`.
+    pub fn insert(&mut self, path: String, symbols: Vec<Symbol>) {
+        self.files.insert(path, symbols);
+    }
+
+    /// Forget the files `keep` returns `false` for.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.files.retain(|path, _| keep(path));
+    }
+
+    /// The symbols defined on lines `start` to `end` of `path`
+    pub fn within(&self, path: &str, start: usize, end: usize) -> impl Iterator<Item = &Symbol> {
+        self.files
+            .get(path)
+            .into_iter()
+            .flatten()
+            .filter(move |symbol| (start..=end).contains(&symbol.line))
+    }
+
+    /// Up to `limit` of the symbols `text` names, by their name or the end of it, e.g.
+    /// `Executor::run` or `run` for `Executor::run`. Only words that look like code count, with an
+    /// `_`, `::` or a capital letter, so `run` alone does not.
+    #[must_use]
+    pub fn mentioned(&self, text: &str, limit: usize) -> Vec<&Symbol> {
+        let paths: Vec<_> = PATH
+            .find_iter(text)
+            .map(|path| path.as_str())
+            .filter(|path| path.contains(['_', ':']) || path.contains(char::is_uppercase))
+            .collect();
+
+        let mut mentioned: Vec<_> = self
+            .files
+            .values()
+            .flatten()
+            .filter(|symbol| {
+                paths.iter().any(|path| {
+                    symbol.name == *path
+                        || symbol
+                            .name
+                            .strip_suffix(path)
+                            .is_some_and(|prefix| prefix.ends_with("::"))
+                })
+            })
+            .collect();
+
+        mentioned.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        mentioned.truncate(limit);
+        mentioned
+    }
+}
+
+/// The symbols the file at `path` with `content` defines, none if it is not Rust or does not
+/// parse
+#[must_use]
+pub fn parse(path: &str, content: &str) -> Vec<Symbol> {
+    if !path.ends_with(".rs") {
+        return Vec::new();
+    }
+    let Ok(file) = syn::parse_file(content) else {
+        return Vec::new();
+    };
+
+    let mut symbols = Vec::new();
+    items(path, "", &file.items, &mut symbols);
+    symbols
+}
+
+/// Add the symbols of `items` in the module `prefix`, e.g. `tests::`, to `symbols`.
+fn items(path: &str, prefix: &str, items: &[Item], symbols: &mut Vec<Symbol>) {
+    let symbol = |kind, name, ident: &syn::Ident| Symbol {
+        kind,
+        name,
+        path: path.to_string(),
+        line: ident.span().start().line,
+    };
+
+    for item in items {
+        let (kind, ident) = match item {
+            Item::Fn(item) => (Kind::Fn, &item.sig.ident),
+            Item::Struct(item) => (Kind::Struct, &item.ident),
+            Item::Enum(item) => (Kind::Enum, &item.ident),
+            Item::Union(item) => (Kind::Union, &item.ident),
+            Item::Type(item) => (Kind::Type, &item.ident),
+            Item::Const(item) => (Kind::Const, &item.ident),
+            Item::Static(item) => (Kind::Static, &item.ident),
+            Item::Macro(item) => match &item.ident {
+                Some(ident) => (Kind::Macro, ident),
+                None => continue,
+            },
+            Item::Trait(item) => {
+                let name = format!("{prefix}{}", item.ident);
+                symbols.push(symbol(Kind::Trait, name.clone(), &item.ident));
+                for item in &item.items {
+                    let (kind, ident) = match item {
+                        TraitItem::Fn(item) => (Kind::Fn, &item.sig.ident),
+                        TraitItem::Type(item) => (Kind::Type, &item.ident),
+                        TraitItem::Const(item) => (Kind::Const, &item.ident),
+                        _ => continue,
+                    };
+                    symbols.push(symbol(kind, format!("{name}::{ident}"), ident));
+                }
+                continue;
+            }
+            Item::Impl(item) => {
+                let syn::Type::Path(ty) = &*item.self_ty else {
+                    continue;
+                };
+                let Some(ty) = ty.path.segments.last() else {
+                    continue;
+                };
+                for item in &item.items {
+                    let (kind, ident) = match item {
+                        ImplItem::Fn(item) => (Kind::Fn, &item.sig.ident),
+                        ImplItem::Type(item) => (Kind::Type, &item.ident),
+                        ImplItem::Const(item) => (Kind::Const, &item.ident),
+                        _ => continue,
+                    };
+                    let name = format!("{prefix}{}::{ident}", ty.ident);
+                    symbols.push(symbol(kind, name, ident));
+                }
+                continue;
+            }
+            Item::Mod(item) => {
+                let name = format!("{prefix}{}", item.ident);
+                symbols.push(symbol(Kind::Mod, name.clone(), &item.ident));
+                if let Some((_, inline)) = &item.content {
+                    self::items(path, &format!("{name}::"), inline, symbols);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        symbols.push(symbol(kind, format!("{prefix}{ident}"), ident));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Kind, SymbolIndex};
+
+    const LIB: &str = "\
+pub struct Executor;
+
+impl Executor {
+    pub fn run(&self) {}
+}
+
+trait Command {
+    fn execute(&self);
+}
+
+mod tests {
+    fn test_run() {}
+}
+";
+
+    #[test]
+    fn test_parse() {
+        let symbols = parse("src/lib.rs", LIB);
+        let found: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.kind, symbol.name.as_str(), symbol.line))
+            .collect();
+        assert_eq!(found, [
+            (Kind::Struct, "Executor", 1),
+            (Kind::Fn, "Executor::run", 4),
+            (Kind::Trait, "Command", 7),
+            (Kind::Fn, "Command::execute", 8),
+            (Kind::Mod, "tests", 11),
+            (Kind::Fn, "tests::test_run", 12),
+        ]);
+        assert_eq!(symbols[1].to_string(), "fn `Executor::run` in src/lib.rs:4");
+
+        assert!(parse("src/lib.rs", "fn unfinished(").is_empty());
+        assert!(parse("README.md", LIB).is_empty());
+    }
+
+    #[test]
+    fn test_mentioned() {
+        let mut index = SymbolIndex::default();
+        index.insert("src/lib.rs".to_string(), parse("src/lib.rs", LIB));
+
+        let names = |text| -> Vec<_> {
+            index
+                .mentioned(text, 10)
+                .iter()
+                .map(|symbol| symbol.name.clone())
+                .collect()
+        };
+        assert_eq!(names("Make Executor::run faster"), ["Executor::run"]);
+        assert_eq!(names("Rename test_run and Command"), [
+            "Command",
+            "tests::test_run"
+        ]);
+        // plain words are not symbols
+        assert!(names("run the tests").is_empty());
+
+        let within: Vec<_> = index.within("src/lib.rs", 3, 8).collect();
+        assert_eq!(within.len(), 3);
+
+        index.retain(|_| false);
+        assert!(index.mentioned("Executor", 10).is_empty());
+    }
+}
@@ -1,22 +1,93 @@
 #![feature(unsize)]
 
-use std::sync::Arc;
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use clap::Parser;
-use protocol::{ClientPacket, ServerPacket};
+use futures::{stream, stream::BoxStream, StreamExt};
+use parking_lot::RwLock;
+use protocol::{trace::Tracer, ClientPacket, ServerPacket};
 use tokio::{
     net::TcpListener,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    sync::{
+        broadcast,
+        mpsc::{UnboundedReceiver, UnboundedSender},
+    },
+    task::JoinSet,
+};
+use tokio_openai::{ChatRequest, ChatResponse};
+use tracing::{error, info, level_filters::LevelFilter, warn};
+
+use crate::{
+    audit::AuditLog,
+    embedding::{Memoized, OpenAiEmbeddings, Persisted},
+    hooks::Hooks,
+    indexer::Embeddings,
+    policy::Policy,
+    process::{Process, WebSocketComm},
+    remote::Credentials,
+    repair::Repairs,
+    responses::ResponseCache,
+    tape::Tape,
+    telemetry::Telemetry,
+    usage::Meter,
+    warm::Warmer,
+};
+pub use crate::{
+    command::{LimitExceeded, Limits},
+    config::{init_logging, reload as reload_config},
+    memory::Memory,
+    network::Network,
+    prompts::{Prompt, Prompts},
+    session::{SessionInfo, SessionManager},
 };
-use tokio_tungstenite::accept_async;
-use tracing::{error, info};
-
-use crate::process::{Process, WebSocketComm};
 
+mod answer_format;
+pub mod archive;
+mod audit;
+mod auth;
 mod command;
+mod commit;
+mod config;
+mod dependencies;
+mod diagnosis;
+pub mod embedding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod file;
+mod guardrails;
+mod hooks;
+mod http;
+mod indexer;
+mod memory;
+mod merge;
+mod metrics;
+mod network;
+pub mod patch;
+pub mod plan;
+mod policy;
+mod prefetch;
 mod process;
+mod prompts;
+mod remote;
+mod repair;
+mod responses;
+mod scratch;
+mod secrets;
+mod session;
+mod sources;
+mod tape;
+mod tasks;
+mod telemetry;
+mod usage;
+mod warm;
+pub mod workspace;
 
 #[derive(Parser)]
 pub struct Args {
@@ -25,105 +96,653 @@ pub struct Args {
 
     #[clap(short, long, default_value = "8080")]
     pub port: u16,
+
+    /// Seconds running executions get to finish when the executor is stopped
+    #[clap(long, default_value = "30", value_parser = secs)]
+    pub shutdown_grace: Duration,
+
+    /// File the sessions are saved to when the executor stops and restored from when it starts
+    #[clap(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Port to serve /healthz, /sessions, /metrics and /version on, on the same ip. Without it
+    /// they are not served. /sessions and /metrics take the --token, if set.
+    #[clap(long)]
+    pub http_port: Option<u16>,
+
+    /// Token frontends have to connect with, as `Authorization: Bearer <token>`. Without it
+    /// anyone who can reach the port can drive the agent and run commands.
+    #[clap(long, env = "COLLECTIVE_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    #[clap(flatten)]
+    pub settings: Settings,
+}
+
+fn secs(s: &str) -> Result<Duration, std::num::ParseIntError> {
+    s.parse().map(Duration::from_secs)
+}
+
+/// How the executor behaves, no matter how frontends connect to it
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct Settings {
+    /// File to remember the preferences of the user in. Without it nothing is remembered.
+    #[clap(long)]
+    pub memory: Option<PathBuf>,
+
+    /// JSON file overriding prompt templates, e.g. {"codegen": "..."}, or a directory with a
+    /// file per template, e.g. codegen.txt
+    #[clap(long)]
+    pub prompts: Option<PathBuf>,
+
+    /// Reload the prompt templates whenever they change
+    #[clap(long)]
+    pub dev: bool,
+
+    /// TOML file with settings overriding these flags, named like them, e.g.
+    /// `command-timeout = 120`. Reloaded when it changes or on SIGHUP, the log level, limits,
+    /// fetch domains and prompts take effect at once.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    /// Level of the logs: trace, debug, info, warn, error or off
+    #[clap(long, value_parser = config::log_level)]
+    pub log_level: Option<LevelFilter>,
+
+    #[clap(flatten)]
+    pub limits: Limits,
+
+    /// Domain the fetch command may download from, with its subdomains. Can be repeated, without
+    /// it every domain is allowed.
+    #[clap(long = "fetch-allow")]
+    pub fetch_allow: Vec<String>,
+
+    #[clap(flatten)]
+    pub network: Network,
+
+    /// Seconds between pings keeping the connection to the provider open, 0 to never ping
+    #[clap(long, default_value = "30", value_parser = secs)]
+    pub warm_interval: Duration,
+
+    /// TOML file with the credentials of the hosts repositories are cloned from, e.g.
+    /// [hosts."github.com"] with `token = "..."` or `env = "GITHUB_TOKEN"`
+    #[clap(long)]
+    pub git_credentials: Option<PathBuf>,
+
+    /// Embed the files of every workspace so questions and plans can cite the relevant ones.
+    /// Sends the files to `OpenAI`.
+    #[clap(long)]
+    pub index: bool,
+
+    /// How many candidates for every question the model proposes at once. The one least similar
+    /// to the questions asked so far is asked, the others are shown as alternatives.
+    #[clap(long, default_value = "1")]
+    pub question_candidates: u32,
+
+    /// How often a codegen step generates a Rust file of a cargo package at most. Until it
+    /// compiles, the file is checked with `cargo check` and generated again with the errors. 1
+    /// generates it once without checking it.
+    #[clap(long, default_value = "1")]
+    pub codegen_iterations: u32,
+
+    /// Directory to keep the responses of the provider in, so the same requests are answered
+    /// from disk, e.g. when running an interview again during development. Without it nothing is
+    /// kept.
+    #[clap(long)]
+    pub response_cache: Option<PathBuf>,
+
+    /// Seconds a kept response is used for
+    #[clap(long, default_value = "86400", value_parser = secs)]
+    pub response_cache_ttl: Duration,
+
+    /// Megabytes the kept responses take at most, the oldest are deleted beyond it
+    #[clap(long, default_value = "256")]
+    pub response_cache_size: u64,
+
+    /// Keep the scratch directory of a step that failed until the session ends, to see what the
+    /// step left in it. The directories of other steps are deleted once they finish.
+    #[clap(long)]
+    pub keep_failed_scratch: bool,
+
+    /// File to append every instruction, question, answer, model request, command, step result
+    /// and decision of the user or a hook to, as lines of JSON. Without it nothing is logged.
+    #[clap(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// TOML file with the scripts run on the events of every session, e.g. [hooks] with
+    /// `before_command = ["/etc/collective/allow-command.sh"]`. Working directories cannot add
+    /// hooks.
+    #[clap(long)]
+    pub hooks: Option<PathBuf>,
+
+    /// Cents a session may spend on the provider, estimated from the length of the text. Once it
+    /// spent 80% of them, questions and summaries are written by a cheaper model, plans and code
+    /// still by the strong one. Without it the model is never switched.
+    #[clap(long)]
+    pub cost_cap: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Event {
     Connected,
+    /// All sessions ended after [`SessionManager::shutdown`]
+    Stopped {
+        /// sessions written to the checkpoint
+        saved: usize,
+        /// sessions that did not finish within the grace period
+        aborted: usize,
+    },
 }
 
 #[async_trait]
 pub trait Comm {
     async fn send(&mut self, packet: ServerPacket) -> Result<()>;
     async fn recv(&mut self) -> Result<ClientPacket>;
+    /// The latencies of the packets sent and received so far
+    fn tracer(&self) -> &Tracer;
 }
 
 struct SimpleComm {
     tx: UnboundedSender<ServerPacket>,
     rx: UnboundedReceiver<ClientPacket>,
+    tracer: Tracer,
 }
 
 #[async_trait]
 impl Comm for SimpleComm {
     async fn send(&mut self, packet: ServerPacket) -> Result<()> {
-        self.tx.send(packet)?;
+        self.tx.send(self.tracer.stamp(packet))?;
         Ok(())
     }
 
     async fn recv(&mut self) -> Result<ClientPacket> {
-        self.rx.recv().await.context("Failed to receive packet")
+        let packet = self.rx.recv().await.context("Failed to receive packet")?;
+        self.tracer.received(&packet);
+        Ok(packet)
+    }
+
+    fn tracer(&self) -> &Tracer {
+        &self.tracer
     }
 }
 
 /// Launch using [`SimpleComm`] and return (tx, rx) for sending and receiving packets.
 ///
-/// # Panics
-/// TODO: remove
-#[must_use]
-pub fn launch() -> (
+/// # Errors
+/// If the executor cannot be created, e.g. because `OPENAI_KEY` is not set.
+pub fn launch(
+    settings: Settings,
+) -> Result<(
     UnboundedSender<ClientPacket>,
     UnboundedReceiver<ServerPacket>,
-) {
-    let executor = Executor::new().unwrap();
+)> {
+    let executor = Executor::new(settings)?;
+    let sessions = SessionManager::new();
 
     let (tx1, rx1) = tokio::sync::mpsc::unbounded_channel();
     let (tx2, rx2) = tokio::sync::mpsc::unbounded_channel();
 
-    let comm = SimpleComm { tx: tx1, rx: rx2 };
+    let comm = SimpleComm {
+        tx: tx1,
+        rx: rx2,
+        tracer: Tracer::default(),
+    };
 
-    tokio::spawn(async move {
-        handle_client(executor, comm).await;
+    tasks::spawn("session", async move {
+        handle_client(executor, sessions, comm).await;
     });
 
-    (tx2, rx1)
+    Ok((tx2, rx1))
 }
 
+/// Launch the websocket server.
+///
+/// Every connection gets its own session in the returned [`SessionManager`]. Calling
+/// [`SessionManager::shutdown`] stops accepting connections and lets every session finish the
+/// packet it is processing within the grace period. Sessions still running after it are aborted.
+/// The sessions are then saved to the checkpoint, if any, and [`Event::Stopped`] is emitted.
+///
 /// # Panics
 /// TODO: remove
 #[must_use]
-pub fn launch_websocket(args: Args) -> UnboundedReceiver<Event> {
+pub fn launch_websocket(args: Args) -> (SessionManager, UnboundedReceiver<Event>) {
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-    tokio::spawn(async move {
-        info!("Starting executor");
+    let sessions = SessionManager::new().with_grace(args.shutdown_grace);
+
+    tasks::spawn("server", {
+        let sessions = sessions.clone();
+        async move {
+            info!("Starting executor");
+
+            let Args {
+                ip,
+                port,
+                settings,
+                checkpoint,
+                http_port,
+                token,
+                ..
+            } = args;
+
+            let executor = Executor::new(settings).unwrap();
+
+            let http = http_port.map(|http_port| {
+                let addr = format!("{ip}:{http_port}");
+                let executor = executor.clone();
+                let sessions = sessions.clone();
+                let token = token.clone();
+                tasks::spawn("http", async move {
+                    let served = match std::net::TcpListener::bind(&addr) {
+                        Ok(listener) => {
+                            info!("Serving HTTP on: {addr}");
+                            http::serve(listener, executor, sessions, token).await
+                        }
+                        Err(e) => Err(e).with_context(|| format!("Failed to bind {addr}")),
+                    };
+                    if let Err(e) = served {
+                        error!("{e:#}");
+                    }
+                })
+            });
+
+            if let Some(checkpoint) = &checkpoint {
+                match sessions.restore(checkpoint, &executor) {
+                    Ok(restored) => info!("Restored {restored} sessions"),
+                    Err(e) => error!("{e:#}"),
+                }
+            }
+
+            let addr = format!("{ip}:{port}");
+
+            let listener = TcpListener::bind(&addr).await.unwrap();
+
+            tx.send(Event::Connected).unwrap();
+
+            info!("Listening on: {addr}");
+            let loopback = ip
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback());
+            if token.is_none() && !loopback {
+                warn!("No --token is set, anyone who can reach {addr} can run commands");
+            }
+
+            let mut clients = JoinSet::new();
+
+            loop {
+                let (socket, peer) = tokio::select! {
+                    () = sessions.shutdown_requested() => break,
+                    res = listener.accept() => res.unwrap(),
+                };
+
+                let ws_stream = match auth::accept(socket, token.as_deref()).await {
+                    Ok(ws_stream) => ws_stream,
+                    Err(e) => {
+                        warn!("Rejected a connection: {e:#}");
+                        continue;
+                    }
+                };
+                info!("New WebSocket connection: {peer}");
+
+                let ws = WebSocketComm::new(ws_stream);
+
+                let executor = executor.clone();
+                let sessions = sessions.clone();
+                tasks::spawn_in(&mut clients, &format!("session {peer}"), async move {
+                    handle_client(executor, sessions, ws).await;
+                });
+            }
+
+            info!("Shutting down, waiting for {} sessions", sessions.active());
+
+            let finished = async { while clients.join_next().await.is_some() {} };
+            let aborted = match tokio::time::timeout(sessions.grace(), finished).await {
+                Ok(()) => 0,
+                Err(_) => {
+                    let aborted = clients.len();
+                    clients.shutdown().await;
+                    aborted
+                }
+            };
+
+            let saved = match &checkpoint {
+                Some(checkpoint) => sessions.checkpoint(checkpoint).unwrap_or_else(|e| {
+                    error!("{e:#}");
+                    0
+                }),
+                None => 0,
+            };
+
+            // healthy until the sessions are saved
+            if let Some(http) = http {
+                http.abort();
+            }
+
+            let _ = tx.send(Event::Stopped { saved, aborted });
+        }
+    });
 
-        let executor = Executor::new().unwrap();
+    (sessions, rx)
+}
 
-        let Args { ip, port } = args;
+type Ctx = Arc<Inner>;
 
-        let addr = format!("{ip}:{port}");
+/// Bytes of embeddings kept in memory, shared by every session
+const EMBEDDING_CACHE: usize = 64 << 20;
 
-        let listener = TcpListener::bind(&addr).await.unwrap();
+/// The kind of chat requests in the [`ResponseCache`] and the [`Tape`], streamed or not
+const CHAT: &str = "chat";
 
-        tx.send(Event::Connected).unwrap();
+/// The kind of chat requests whose whole response is kept
+const RAW_CHAT: &str = "raw_chat";
 
-        info!("Listening on: {addr}");
+/// How requests to the provider are retried, e.g. when rate limited
+const PROVIDER_RETRY: utils::retry::Policy = utils::retry::Policy::new(4)
+    .backoff(Duration::from_secs(1), Duration::from_secs(16))
+    .when(network::is_transient);
 
-        loop {
-            let (socket, _) = listener.accept().await.unwrap();
-            let ws_stream = accept_async(socket).await.unwrap();
-            info!(
-                "New WebSocket connection: {}",
-                ws_stream.get_ref().peer_addr().unwrap() /* TODO: is this unwrap bad? What if it
-                                                          * panics O_O */
-            );
+struct Inner {
+    ai: tokio_openai::Client,
+    req: reqwest::Client,
+    memory: Option<Memory>,
+    prompts: Prompts,
+    /// changed when the config is reloaded, see [`config`]
+    limits: RwLock<Limits>,
+    /// changed when the config is reloaded, see [`config`]
+    policy: RwLock<Policy>,
+    /// for the repositories sessions clone
+    credentials: Credentials,
+    /// keeps the connection `ai` uses open, `None` if disabled
+    warmer: Option<Warmer>,
+    /// for indexing workspaces, `None` if disabled
+    embeddings: Option<Embeddings>,
+    /// how many candidates the model proposes for every question, see [`Settings`]
+    question_candidates: u32,
+    /// how often a codegen step generates a Rust file at most, see [`Settings`]
+    codegen_iterations: u32,
+    /// how the structured answers of the model turned out, by prompt
+    repairs: Repairs,
+    /// messages for every connected frontend, sent as [`protocol::server::Notice`]s
+    notices: broadcast::Sender<String>,
+    /// responses of the provider kept on disk, `None` if disabled
+    responses: Option<ResponseCache>,
+    /// whether failed steps keep their scratch directory, see [`Settings`]
+    keep_failed_scratch: bool,
+    /// records or replays the traffic with the provider, see [`tape`]
+    tape: Option<Arc<Tape>>,
+    /// what every session did, served by `/metrics`
+    telemetry: Telemetry,
+    /// what every session did, `None` if disabled, see [`audit`]
+    audit: Option<AuditLog>,
+    /// run on the events of every session, see [`hooks`]
+    hooks: Hooks,
+    /// cents every session may spend before its low-stakes requests are sent to a cheaper model,
+    /// see [`usage`]
+    cost_cap: Option<u64>,
+}
 
-            let ws = WebSocketComm::new(ws_stream);
+impl Inner {
+    /// The limits of the commands started from now on
+    fn limits(&self) -> Limits {
+        *self.limits.read()
+    }
 
-            let executor = executor.clone();
-            tokio::spawn(async move {
-                handle_client(executor, ws).await;
-            });
+    /// Append `event` to the audit log, if enabled.
+    fn audit(&self, event: &audit::Event) {
+        if let Some(log) = &self.audit {
+            if let Err(e) = log.record(event) {
+                warn!("{e:#}");
+            }
+        }
+    }
+
+    /// Record a request of `kind` to the model in the audit log, if enabled.
+    fn audit_request(&self, kind: &'static str, request: &ChatRequest) -> Result<()> {
+        if self.audit.is_some() {
+            let hash = ResponseCache::key(kind, request)?.to_string();
+            self.audit(&audit::Event::ModelRequest { kind, hash });
+        }
+        Ok(())
+    }
+
+    /// Include the remembered preferences of the user in `request`.
+    fn personalize(&self, request: ChatRequest) -> ChatRequest {
+        match &self.memory {
+            Some(memory) => memory.personalize(request),
+            None => request,
+        }
+    }
+
+    /// Send `request` to the model, a cheaper one if the budget of the session runs low, see
+    /// [`usage::downshift`]. It is answered from the [`Tape`] when replaying, and from the
+    /// [`ResponseCache`] if it was sent before.
+    async fn chat(&self, request: ChatRequest) -> Result<String> {
+        let request = usage::downshift(request, self.cost_cap);
+        self.audit_request(CHAT, &request)?;
+        match &self.tape {
+            Some(tape) => {
+                let recorded = serde_json::to_value(&request)?;
+                tape.play(CHAT, recorded, self.cached_chat(request)).await
+            }
+            None => self.cached_chat(request).await,
+        }
+    }
+
+    async fn cached_chat(&self, request: ChatRequest) -> Result<String> {
+        let Some(responses) = &self.responses else {
+            return self.provider_chat(request).await;
+        };
+
+        let key = ResponseCache::key(CHAT, &request)?;
+        if let Some(text) = responses.get(&key) {
+            return Ok(text);
         }
-    });
 
-    rx
+        let text = self.provider_chat(request).await?;
+        if let Err(e) = responses.put(&key, &text) {
+            warn!("Failed to cache a response: {e:#}");
+        }
+        Ok(text)
+    }
+
+    /// Like [`Inner::chat`], but the answers of the model can differ in more than their text,
+    /// e.g. there can be several.
+    async fn raw_chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let request = usage::downshift(request, self.cost_cap);
+        self.audit_request(RAW_CHAT, &request)?;
+        match &self.tape {
+            Some(tape) => {
+                let recorded = serde_json::to_value(&request)?;
+                tape.play(RAW_CHAT, recorded, self.cached_raw_chat(request))
+                    .await
+            }
+            None => self.cached_raw_chat(request).await,
+        }
+    }
+
+    async fn cached_raw_chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let Some(responses) = &self.responses else {
+            return self.provider_raw_chat(request).await;
+        };
+
+        let key = ResponseCache::key(RAW_CHAT, &request)?;
+        if let Some(response) = responses.get(&key) {
+            return Ok(response);
+        }
+
+        let response = self.provider_raw_chat(request).await?;
+        if let Err(e) = responses.put(&key, &response) {
+            warn!("Failed to cache a response: {e:#}");
+        }
+        Ok(response)
+    }
+
+    /// Stream the answer of the model to `request`. A replayed or cached answer arrives at once,
+    /// an answer that is streamed completely without errors is recorded and cached.
+    async fn stream_chat(
+        &self,
+        request: ChatRequest,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let request = usage::downshift(request, self.cost_cap);
+        self.audit_request(CHAT, &request)?;
+        let Some(tape) = self.tape.clone() else {
+            return self.cached_stream_chat(request).await;
+        };
+
+        let recorded = serde_json::to_value(&request)?;
+        if tape.is_replaying() {
+            let text: String = tape.replayed(CHAT, &recorded)?;
+            return Ok(stream::once(async { Ok(text) }).boxed());
+        }
+
+        let chunks = self.cached_stream_chat(request).await?;
+        Ok(tee(chunks, move |text| {
+            if let Err(e) = tape.recorded(CHAT, recorded, &text) {
+                warn!("Failed to record a response: {e:#}");
+            }
+        }))
+    }
+
+    async fn cached_stream_chat(
+        &self,
+        request: ChatRequest,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let Some(responses) = self.responses.clone() else {
+            return self.provider_stream_chat(request).await;
+        };
+
+        let key = ResponseCache::key(CHAT, &request)?;
+        if let Some(text) = responses.get::<String>(&key) {
+            return Ok(stream::once(async { Ok(text) }).boxed());
+        }
+
+        let chunks = self.provider_stream_chat(request).await?;
+        Ok(tee(chunks, move |text| {
+            if let Err(e) = responses.put(&key, &text) {
+                warn!("Failed to cache a response: {e:#}");
+            }
+        }))
+    }
+
+    /// Send `request` to the provider itself, counting it in the [`Telemetry`] and the usage of
+    /// the session, see [`usage`].
+    async fn provider_chat(&self, request: ChatRequest) -> Result<String> {
+        let meter = self.sent(&request);
+        let text = utils::retry(PROVIDER_RETRY, || {
+            self.measured(CHAT, self.ai.chat(request.clone()))
+        })
+        .await?;
+        self.received(meter.as_ref(), &text);
+        Ok(text)
+    }
+
+    async fn provider_raw_chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let meter = self.sent(&request);
+        let response = utils::retry(PROVIDER_RETRY, || {
+            self.measured(RAW_CHAT, self.ai.raw_chat(request.clone()))
+        })
+        .await?;
+        for choice in &response.choices {
+            self.received(meter.as_ref(), &choice.message.content);
+        }
+        Ok(response)
+    }
+
+    async fn provider_stream_chat(
+        &self,
+        request: ChatRequest,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let meter = self.sent(&request);
+        // once streaming, the chunks shown cannot be taken back, so only connecting is retried
+        let chunks = utils::retry(PROVIDER_RETRY, || {
+            self.measured(CHAT, self.ai.stream_chat(request.clone()))
+        })
+        .await?;
+        let telemetry = self.telemetry.clone();
+        Ok(network::checked(chunks.boxed())
+            .inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    telemetry.completion(chunk);
+                    if let Some(meter) = &meter {
+                        meter.received(chunk);
+                    }
+                }
+            })
+            .boxed())
+    }
+
+    fn sent(&self, request: &ChatRequest) -> Option<Meter> {
+        for message in &request.messages {
+            self.telemetry.prompt(&message.content);
+        }
+        Meter::sent(request)
+    }
+
+    fn received(&self, meter: Option<&Meter>, text: &str) {
+        self.telemetry.completion(text);
+        if let Some(meter) = meter {
+            meter.received(text);
+        }
+    }
+
+    /// Count the time the `response` of `kind` takes and whether it fails, explaining failures a
+    /// proxy may have caused.
+    async fn measured<T>(
+        &self,
+        kind: &'static str,
+        response: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let response = response.await.map_err(network::explain);
+        self.telemetry
+            .request(kind, started.elapsed(), response.is_ok());
+        response
+    }
 }
 
-type Ctx = Arc<Inner>;
+/// Pass `chunks` on, handing the whole text to `done` once all of them were streamed without
+/// errors. An incomplete answer is not worth keeping.
+fn tee(
+    chunks: BoxStream<'static, Result<String>>,
+    done: impl FnOnce(String) + Send + 'static,
+) -> BoxStream<'static, Result<String>> {
+    stream::unfold(
+        (chunks, Some(String::new()), done),
+        |(mut chunks, mut text, done)| async move {
+            let Some(chunk) = chunks.next().await else {
+                if let Some(text) = text {
+                    done(text);
+                }
+                return None;
+            };
+
+            match &chunk {
+                Ok(delta) => {
+                    if let Some(text) = &mut text {
+                        text.push_str(delta);
+                    }
+                }
+                Err(_) => text = None,
+            }
+            Some((chunk, (chunks, text, done)))
+        },
+    )
+    .boxed()
+}
 
-struct Inner {
-    ai: tokio_openai::Client,
-    req: reqwest::Client,
+/// Embeddings of `OpenAI`, kept in memory and in `responses` if enabled
+fn embeddings(
+    ai: &tokio_openai::Client,
+    responses: Option<ResponseCache>,
+    tape: Option<Arc<Tape>>,
+) -> Embeddings {
+    let openai = OpenAiEmbeddings::new(ai.clone()).taped(tape);
+    match responses {
+        Some(responses) => Arc::new(Memoized::new(
+            Persisted::new(openai, responses),
+            EMBEDDING_CACHE,
+        )),
+        None => Arc::new(Memoized::new(openai, EMBEDDING_CACHE)),
+    }
 }
 
 #[derive(Clone)]
@@ -131,24 +750,164 @@ pub struct Executor {
     ctx: Ctx,
 }
 
-/// construct a new context
+/// construct a new context without memory
+#[cfg(test)]
 fn ctx() -> Result<Ctx> {
+    ctx_with(
+        None,
+        Prompts::default(),
+        Limits::default(),
+        Policy::default(),
+        Credentials::default(),
+        &Network::default(),
+        false,
+    )
+    .map(Arc::new)
+}
+
+fn ctx_with(
+    memory: Option<Memory>,
+    prompts: Prompts,
+    limits: Limits,
+    policy: Policy,
+    credentials: Credentials,
+    network: &Network,
+    index: bool,
+) -> Result<Inner> {
+    let (notices, _) = broadcast::channel(16);
+
+    // one connection pool for the provider, the warmer and the commands
+    let req = network.client()?;
+
+    let tape = Tape::from_env()?.map(Arc::new);
+    // a replay does not reach the provider
+    let key = match &tape {
+        Some(tape) if tape.is_replaying() => tokio_openai::openai_key().unwrap_or_default(),
+        _ => tokio_openai::openai_key()?,
+    };
+    let ai = tokio_openai::Client::new(req.clone(), key);
+    let embeddings = index.then(|| embeddings(&ai, None, tape.clone()));
+
     let inner = Inner {
-        ai: tokio_openai::Client::simple()?,
-        req: reqwest::Client::new(),
+        ai,
+        req,
+        memory,
+        prompts,
+        limits: RwLock::new(limits),
+        policy: RwLock::new(policy),
+        credentials,
+        warmer: None,
+        embeddings,
+        question_candidates: 1,
+        codegen_iterations: 1,
+        repairs: Repairs::default(),
+        notices,
+        responses: None,
+        keep_failed_scratch: false,
+        tape,
+        telemetry: Telemetry::default(),
+        audit: None,
+        hooks: Hooks::default(),
+        cost_cap: None,
     };
 
-    Ok(Arc::new(inner))
+    Ok(inner)
 }
 
 impl Executor {
-    fn new() -> Result<Self> {
-        Ok(Self { ctx: ctx()? })
+    fn new(settings: Settings) -> Result<Self> {
+        let base = settings.clone();
+        let settings = match &base.config {
+            Some(path) => config::load(path, base.clone())?,
+            None => settings,
+        };
+        let current = settings.clone();
+
+        let Settings {
+            log_level,
+            config,
+            memory,
+            prompts,
+            dev,
+            limits,
+            fetch_allow,
+            network,
+            warm_interval,
+            git_credentials,
+            index,
+            question_candidates,
+            codegen_iterations,
+            response_cache,
+            response_cache_ttl,
+            response_cache_size,
+            keep_failed_scratch,
+            audit_log,
+            hooks,
+            cost_cap,
+        } = settings;
+
+        let memory = memory.map(Memory::load).transpose()?;
+        let prompts = Prompts::load(prompts)?;
+        let policy = Policy::default().allow_domains(fetch_allow);
+        let credentials = Credentials::load(git_credentials)?;
+        let responses = response_cache
+            .map(|dir| ResponseCache::open(dir, response_cache_ttl, response_cache_size << 20))
+            .transpose()?;
+        let audit = audit_log.as_deref().map(AuditLog::open).transpose()?;
+        let hooks = Hooks::load(hooks)?;
+        let inner = ctx_with(
+            memory,
+            prompts,
+            limits,
+            policy,
+            credentials,
+            &network,
+            index,
+        )?;
+        let warmer = (!warm_interval.is_zero()).then(|| {
+            let warmer = Warmer::new(inner.req.clone(), warm::PROVIDER_URL);
+            warmer.keep_warm(warm_interval);
+            warmer
+        });
+        let embeddings = match &responses {
+            Some(responses) if index => Some(embeddings(
+                &inner.ai,
+                Some(responses.clone()),
+                inner.tape.clone(),
+            )),
+            _ => inner.embeddings,
+        };
+        let ctx = Arc::new(Inner {
+            question_candidates,
+            codegen_iterations,
+            embeddings,
+            responses,
+            keep_failed_scratch,
+            audit,
+            hooks,
+            cost_cap,
+            warmer,
+            ..inner
+        });
+
+        if dev {
+            ctx.prompts.watch(ctx.notices.clone());
+        }
+        if let Some(level) = log_level {
+            if let Err(e) = config::set_log_level(level) {
+                warn!("Ignoring the log level: {e:#}");
+            }
+        }
+        if let Some(path) = config {
+            config::watch(path, base, current, Arc::downgrade(&ctx));
+        }
+
+        Ok(Self { ctx })
     }
 }
 
-async fn handle_client(executor: Executor, comm: impl Comm + Send) {
-    let process = Process::new(executor, comm);
+async fn handle_client(executor: Executor, sessions: SessionManager, comm: impl Comm + Send) {
+    let process = Process::new(executor, sessions, comm);
 
     if let Err(e) = process.run().await {
         error!("Error: {}", e);
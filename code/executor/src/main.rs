@@ -0,0 +1,85 @@
+//! The websocket executor.
+//!
+//! SIGTERM and SIGINT stop it gracefully: no new connections are accepted, frontends are told how
+//! long running executions have to finish and the sessions are saved to the checkpoint. The exit
+//! code is 0 if every session finished in time.
+//!
+//! SIGHUP reloads the file of `--config`.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use executor::{Args, Event};
+use tracing::{error, info};
+
+/// Completes when the executor is asked to stop
+#[cfg(unix)]
+async fn stop_signal() -> anyhow::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            _ = terminate.recv() => return Ok("SIGTERM"),
+            _ = interrupt.recv() => return Ok("SIGINT"),
+            _ = hangup.recv() => {
+                info!("Received SIGHUP, reloading the config");
+                executor::reload_config();
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn stop_signal() -> anyhow::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    executor::init_logging();
+
+    let (sessions, mut events) = executor::launch_websocket(Args::parse());
+
+    let stopped = async {
+        while let Some(event) = events.recv().await {
+            if let Event::Stopped { saved, aborted } = event {
+                return Some((saved, aborted));
+            }
+        }
+        None
+    };
+    tokio::pin!(stopped);
+
+    tokio::select! {
+        signal = stop_signal() => match signal {
+            Ok(signal) => info!("Received {signal}, shutting down"),
+            Err(e) => error!("Failed to listen for signals, shutting down: {e:#}"),
+        },
+        // the server stopped by itself
+        _ = &mut stopped => {
+            error!("The executor stopped unexpectedly");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    sessions.shutdown();
+
+    match stopped.await {
+        Some((saved, 0)) => {
+            info!("Stopped, saved {saved} sessions");
+            ExitCode::SUCCESS
+        }
+        Some((saved, aborted)) => {
+            error!(
+                "Stopped, saved {saved} sessions, aborted {aborted} that did not finish in time"
+            );
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
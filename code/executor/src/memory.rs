@@ -0,0 +1,206 @@
+//! Durable preferences of the user, shared by all sessions.
+//!
+//! Memory is opt-in: it is only used when the executor is given a file to store it in. After a
+//! plan was executed, the model extracts preferences from the session ("prefers Rust 2021",
+//! "always uses tokio"). The user can list, add and forget them, and they are included in the
+//! system prompt of every request.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use protocol::MemoryEntry;
+use tokio_openai::{ChatRequest, Msg};
+
+/// The model answers with this if there is nothing worth remembering
+const NOTHING: &str = "NONE";
+
+/// The default system prompt of a [`learn_request`]
+pub const LEARN_PROMPT: &str =
+    "List durable preferences of the user that apply to future projects, e.g. \"prefers Rust \
+     2021\" or \"no unwrap in production code\". Write one preference per line without numbering \
+     or bullets. Only include preferences the user stated. If there are none, write NONE.";
+
+#[derive(Clone)]
+pub struct Memory {
+    path: PathBuf,
+    entries: Arc<Mutex<Vec<MemoryEntry>>>,
+}
+
+impl Memory {
+    /// Load the memory stored at `path`. A missing file is an empty memory.
+    ///
+    /// # Errors
+    /// If the file exists but cannot be read or parsed.
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse memory at {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("Failed to read memory"),
+        };
+
+        Ok(Self {
+            path,
+            entries: Arc::new(Mutex::new(entries)),
+        })
+    }
+
+    #[must_use]
+    pub fn entries(&self) -> Vec<MemoryEntry> {
+        self.entries.lock().clone()
+    }
+
+    /// Remember `preference`. Returns `None` if it is already remembered.
+    ///
+    /// # Errors
+    /// If the memory could not be saved.
+    pub fn add(&self, preference: &str) -> anyhow::Result<Option<MemoryEntry>> {
+        let preference = preference.trim();
+        let mut entries = self.entries.lock();
+
+        let known = entries
+            .iter()
+            .any(|entry| entry.preference.eq_ignore_ascii_case(preference));
+
+        if preference.is_empty() || known {
+            return Ok(None);
+        }
+
+        let id = entries.iter().map(|entry| entry.id + 1).max().unwrap_or(1);
+        let entry = MemoryEntry {
+            id,
+            preference: preference.to_string(),
+        };
+
+        entries.push(entry.clone());
+        save(&self.path, &entries)?;
+
+        Ok(Some(entry))
+    }
+
+    /// Forget the entry with `id`. Returns whether there was such an entry.
+    ///
+    /// # Errors
+    /// If the memory could not be saved.
+    pub fn forget(&self, id: u64) -> anyhow::Result<bool> {
+        let mut entries = self.entries.lock();
+
+        let len = entries.len();
+        entries.retain(|entry| entry.id != id);
+
+        if entries.len() == len {
+            return Ok(false);
+        }
+
+        save(&self.path, &entries)?;
+        Ok(true)
+    }
+
+    /// Add the preferences as the first system message of `request`.
+    #[must_use]
+    pub fn personalize(&self, mut request: ChatRequest) -> ChatRequest {
+        let entries = self.entries.lock();
+
+        if entries.is_empty() {
+            return request;
+        }
+
+        let mut prompt = String::from("The user has these preferences:\n");
+        for entry in entries.iter() {
+            prompt.push_str(&format!("- {}\n", entry.preference));
+        }
+
+        request.messages.insert(0, Msg::system(prompt));
+        request
+    }
+}
+
+/// The request to extract durable preferences from the transcript of a session.
+#[must_use]
+pub fn learn_request(system: &str, transcript: &str) -> ChatRequest {
+    ChatRequest::new().sys_msg(system).user_msg(transcript)
+}
+
+/// Parse the answer to a [`learn_request`].
+#[must_use]
+pub fn parse_learned(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*']).trim())
+        .filter(|line| !line.is_empty() && *line != NOTHING)
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn save(path: &Path, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let content = serde_json::to_string_pretty(entries)?;
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to save memory to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_openai::{ChatRequest, Role};
+
+    use super::{parse_learned, Memory, LEARN_PROMPT, NOTHING};
+
+    #[test]
+    fn test_add_forget() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("memory.json");
+
+        let memory = Memory::load(&path)?;
+        assert!(memory.entries().is_empty());
+
+        let tokio = memory.add("always uses tokio")?.unwrap();
+        memory.add("prefers Rust 2021")?.unwrap();
+        assert!(memory.add("Always uses tokio")?.is_none());
+
+        assert!(memory.forget(tokio.id)?);
+        assert!(!memory.forget(tokio.id)?);
+
+        // persisted across loads
+        let entries = Memory::load(&path)?.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].preference, "prefers Rust 2021");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_personalize() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let memory = Memory::load(dir.path().join("memory.json"))?;
+
+        let request = memory.personalize(ChatRequest::new().user_msg("hi"));
+        assert_eq!(request.messages.len(), 1);
+
+        memory.add("no unwrap in production code")?;
+
+        let request = memory.personalize(ChatRequest::new().user_msg("hi"));
+        assert_eq!(request.messages[0].role, Role::System);
+        assert!(request.messages[0].content.contains("no unwrap"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_learned() {
+        assert!(LEARN_PROMPT.contains(NOTHING));
+
+        assert_eq!(
+            parse_learned("- prefers Rust 2021\n\nalways uses tokio\n"),
+            vec!["prefers Rust 2021", "always uses tokio"]
+        );
+        assert!(parse_learned("NONE").is_empty());
+    }
+}
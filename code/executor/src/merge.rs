@@ -0,0 +1,198 @@
+//! Three-way merges of a file the executor generated with the changes the user made to it
+//! meanwhile.
+//!
+//! Both versions are compared line by line with the content they started from. Lines only one
+//! side changed are taken from that side, lines both sides changed differently are conflicts,
+//! which are kept between git style markers for the user to resolve.
+
+/// The markers around the two sides of a conflict
+const OURS: &str = "<<<<<<< on disk";
+const SEPARATOR: &str = "=======";
+const THEIRS: &str = ">>>>>>> generated";
+
+/// The largest number of line pairs compared, larger files conflict as a whole
+const MAX_CELLS: usize = 16_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merge {
+    Clean(String),
+    /// the merge with the conflicting lines between markers
+    Conflicted {
+        content: String,
+        conflicts: usize,
+    },
+}
+
+/// Merge `ours`, the file on disk, and `theirs`, the generated file, which both started as `base`.
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Merge {
+    let base: Vec<_> = base.split_inclusive('\n').collect();
+    let ours: Vec<_> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<_> = theirs.split_inclusive('\n').collect();
+
+    let in_ours = matches(&base, &ours);
+    let in_theirs = matches(&base, &theirs);
+
+    let mut content = String::new();
+    let mut conflicts = 0;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+
+    while b < base.len() || o < ours.len() || t < theirs.len() {
+        // lines that are unchanged on both sides
+        let mut stable = 0;
+        while b + stable < base.len()
+            && in_ours[b + stable] == Some(o + stable)
+            && in_theirs[b + stable] == Some(t + stable)
+        {
+            stable += 1;
+        }
+        if stable > 0 {
+            content.push_str(&base[b..b + stable].concat());
+            (b, o, t) = (b + stable, o + stable, t + stable);
+            continue;
+        }
+
+        // the lines up to the next line that is unchanged on both sides
+        let next = (b..base.len()).find_map(|next| Some((next, in_ours[next]?, in_theirs[next]?)));
+        let (next_b, next_o, next_t) = next.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let (old, mine, generated) = (&base[b..next_b], &ours[o..next_o], &theirs[t..next_t]);
+
+        if mine == old || mine == generated {
+            content.push_str(&generated.concat());
+        } else if generated == old {
+            content.push_str(&mine.concat());
+        } else {
+            conflicts += 1;
+            content.push_str(&marked(mine, generated));
+        }
+        (b, o, t) = (next_b, next_o, next_t);
+    }
+
+    match conflicts {
+        0 => Merge::Clean(content),
+        conflicts => Merge::Conflicted { content, conflicts },
+    }
+}
+
+/// The conflicting lines of both sides between markers
+fn marked(ours: &[&str], theirs: &[&str]) -> String {
+    let mut marked = format!("{OURS}\n");
+    for (lines, marker) in [(ours, SEPARATOR), (theirs, THEIRS)] {
+        let side: String = lines.concat();
+        marked.push_str(&side);
+        if !side.is_empty() && !side.ends_with('\n') {
+            marked.push('\n');
+        }
+        marked.push_str(marker);
+        marked.push('\n');
+    }
+    marked
+}
+
+/// The line of `b` every line of `a` is kept as, by their longest common subsequence
+fn matches(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; a.len()];
+
+    // the unchanged start and end need no table
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    for (i, matched) in matched.iter_mut().enumerate().take(prefix) {
+        *matched = Some(i);
+    }
+    for i in 0..suffix {
+        matched[a.len() - 1 - i] = Some(b.len() - 1 - i);
+    }
+
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n == 0 || m == 0 || n * m > MAX_CELLS {
+        return matched;
+    }
+
+    // lengths[i][j] is the length of the common subsequence of a_mid[i..] and b_mid[j..]
+    let mut lengths = vec![0_u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[at(i, j)] = if a_mid[i] == b_mid[j] {
+                lengths[at(i + 1, j + 1)] + 1
+            } else {
+                lengths[at(i + 1, j)].max(lengths[at(i, j + 1)])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_mid[i] == b_mid[j] {
+            matched[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[at(i + 1, j)] >= lengths[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, Merge};
+
+    const BASE: &str = "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{a}\");\n}\n";
+
+    #[test]
+    fn test_clean() {
+        // the user changed `a`, the executor changed what is printed
+        let ours = BASE.replace("a = 1", "a = 10");
+        let theirs = BASE.replace("{a}", "{a} {b}");
+        assert_eq!(
+            merge(BASE, &ours, &theirs),
+            Merge::Clean(BASE.replace("a = 1", "a = 10").replace("{a}", "{a} {b}"))
+        );
+
+        // lines added on both sides
+        let ours = format!("// by the user\n{BASE}");
+        let theirs = format!("{BASE}// generated\n");
+        assert_eq!(
+            merge(BASE, &ours, &theirs),
+            Merge::Clean(format!("// by the user\n{BASE}// generated\n"))
+        );
+
+        // the same change on both sides
+        let same = BASE.replace('1', "10");
+        assert_eq!(merge(BASE, &same, &same), Merge::Clean(same));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let ours = BASE.replace("let a = 1", "let a = 10");
+        let theirs = BASE.replace("let a = 1", "let a = 100");
+
+        let Merge::Conflicted { content, conflicts } = merge(BASE, &ours, &theirs) else {
+            panic!("both sides changed the same line");
+        };
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            content,
+            "fn main() {\n<<<<<<< on disk\n    let a = 10;\n=======\n    let a = 100;\n>>>>>>> \
+             generated\n    let b = 2;\n    println!(\"{a}\");\n}\n"
+        );
+
+        // a file without a newline at its end
+        let Merge::Conflicted { content, .. } = merge("a", "b", "c") else {
+            panic!("both sides changed the only line");
+        };
+        assert_eq!(
+            content,
+            "<<<<<<< on disk\nb\n=======\nc\n>>>>>>> generated\n"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Latencies of a session, reported to the frontend with a
+//! [`Server::Latency`](protocol::server::Server::Latency).
+
+use std::{collections::BTreeMap, time::Duration};
+
+use protocol::{
+    trace::{Distribution, Tracer},
+    LatencyStats,
+};
+
+/// From sending a request to the provider to its response starting
+pub const PROVIDER: &str = "executor → provider";
+/// From the response starting to its first token
+pub const FIRST_TOKEN: &str = "provider → first token";
+/// From sending a request to the provider to its first token, what the user waits for
+pub const TIME_TO_FIRST_TOKEN: &str = "time to first token";
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    latencies: BTreeMap<&'static str, Distribution>,
+}
+
+impl Metrics {
+    pub fn record(&mut self, name: &'static str, latency: Duration) {
+        self.latencies.entry(name).or_default().record(latency);
+    }
+
+    /// The latencies to the frontend, as measured by `tracer`, and to the provider
+    #[must_use]
+    pub fn stats(&self, tracer: &Tracer) -> Vec<LatencyStats> {
+        [
+            tracer.one_way.stats("client → executor"),
+            tracer.round_trip.stats("executor ↔ client round trip"),
+        ]
+        .into_iter()
+        .chain(
+            self.latencies
+                .iter()
+                .map(|(name, distribution)| distribution.stats(name)),
+        )
+        .flatten()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use protocol::trace::Tracer;
+
+    use super::{Metrics, FIRST_TOKEN, PROVIDER};
+
+    #[test]
+    fn test_stats() {
+        let mut metrics = Metrics::default();
+        assert!(metrics.stats(&Tracer::default()).is_empty());
+
+        metrics.record(PROVIDER, Duration::from_millis(120));
+        metrics.record(PROVIDER, Duration::from_millis(80));
+        metrics.record(FIRST_TOKEN, Duration::from_millis(300));
+
+        let stats = metrics.stats(&Tracer::default());
+        let names: Vec<_> = stats.iter().map(|stats| stats.name.as_str()).collect();
+        assert_eq!(names, [PROVIDER, FIRST_TOKEN]);
+        assert_eq!(stats[0].count, 2);
+        assert_eq!(stats[0].max_ms, 120);
+    }
+}
@@ -0,0 +1,378 @@
+//! How the executor reaches the network, for the provider and for the commands fetching pages.
+//!
+//! Every request goes through the one client [`Network::client`] builds. It uses the proxy of the
+//! flags, else the one of `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` like other tools do, except
+//! for the hosts of `--no-proxy` and `NO_PROXY`. A proxy intercepting TLS needs its certificate
+//! authority in `--ca-bundle`, and may break the server-sent events the answers of the model are
+//! streamed with, its errors say so.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use futures::{stream, stream::BoxStream, StreamExt};
+use reqwest::{Certificate, NoProxy, Proxy};
+
+/// How often idle connections are probed by the OS, so they are not dropped silently
+const KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Network {
+    /// Proxy for every request, instead of the one of $HTTPS_PROXY, $HTTP_PROXY or $ALL_PROXY
+    #[clap(long)]
+    pub proxy: Option<String>,
+
+    /// Hosts reached without the proxy, with their subdomains, like $NO_PROXY, e.g.
+    /// `internal.example.com,10.0.0.0/8`. Can be repeated, $NO_PROXY still applies.
+    #[clap(long)]
+    pub no_proxy: Vec<String>,
+
+    /// PEM file with the certificates of additional certificate authorities, e.g. of a proxy
+    /// intercepting TLS
+    #[clap(long)]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl Network {
+    /// The client every request of the executor is sent with.
+    ///
+    /// # Errors
+    /// - A proxy is not a URL
+    /// - The CA bundle cannot be read or contains no certificates
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        // the proxies of the environment are picked here, with the flags
+        let mut builder = reqwest::Client::builder()
+            .tcp_keepalive(KEEPALIVE)
+            .no_proxy();
+
+        let no_proxy = NoProxy::from_string(&self.no_proxy(var));
+        for (scheme, url) in [
+            ("https", self.proxy("https", var)),
+            ("http", self.proxy("http", var)),
+        ] {
+            let Some(url) = url else {
+                continue;
+            };
+            let proxy = match scheme {
+                "https" => Proxy::https(&url),
+                _ => Proxy::http(&url),
+            }
+            .with_context(|| format!("`{url}` is not a proxy URL"))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+        }
+
+        if let Some(path) = &self.ca_bundle {
+            for certificate in certificates(path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder.build().context("Failed to create the HTTP client")
+    }
+
+    /// The proxy for URLs with `scheme`, http or https, if any
+    fn proxy(&self, scheme: &str, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let upper = format!("{}_PROXY", scheme.to_uppercase());
+        let lower = format!("{scheme}_proxy");
+        self.proxy
+            .clone()
+            .or_else(|| var(&upper))
+            .or_else(|| var(&lower))
+            .or_else(|| var("ALL_PROXY"))
+            .or_else(|| var("all_proxy"))
+    }
+
+    /// The hosts reached without the proxy, separated by commas
+    fn no_proxy(&self, var: impl Fn(&str) -> Option<String>) -> String {
+        let env = var("NO_PROXY").or_else(|| var("no_proxy"));
+        self.no_proxy
+            .iter()
+            .cloned()
+            .chain(env)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The certificates in the PEM file at `path`
+fn certificates(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the CA bundle {}", path.display()))?;
+
+    let certificates = pem_blocks(&pem)
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("{} contains an invalid certificate", path.display()))?;
+    anyhow::ensure!(
+        !certificates.is_empty(),
+        "{} contains no PEM certificates",
+        path.display()
+    );
+    Ok(certificates)
+}
+
+/// The certificates of a bundle, each from its `BEGIN` to its `END` line
+fn pem_blocks(pem: &str) -> impl Iterator<Item = &str> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut rest = pem;
+    std::iter::from_fn(move || {
+        let start = rest.find(BEGIN)?;
+        let end = start + rest[start..].find(END)? + END.len();
+        let block = &rest[start..end];
+        rest = &rest[end..];
+        Some(block)
+    })
+}
+
+/// `error` with a hint if a proxy intercepting TLS may have caused it.
+#[must_use]
+pub fn explain(error: anyhow::Error) -> anyhow::Error {
+    if error
+        .chain()
+        .any(|cause| cause.to_string().contains("certificate"))
+    {
+        return error.context(
+            "The TLS certificate was not trusted. A proxy intercepting TLS needs its certificate \
+             authority in --ca-bundle",
+        );
+    }
+    error
+}
+
+/// The body of an error response of the provider
+#[derive(serde::Deserialize, Debug)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ApiError {
+    code: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+impl ApiError {
+    /// The error the provider answered with, if `message` reports an error response. The client
+    /// does not check the status, it reports the body as an answer it could not parse.
+    fn of(message: &str) -> Option<Self> {
+        let body = message.strip_prefix("could not parse chat response ")?;
+        // the body is followed by why it is not an answer
+        let response = serde_json::Deserializer::from_str(body)
+            .into_iter::<ErrorResponse>()
+            .next()?
+            .ok()?;
+        Some(response.error)
+    }
+
+    /// Whether the provider was rate limited or overloaded
+    fn is_transient(&self) -> bool {
+        self.code.as_deref() == Some("rate_limit_exceeded")
+            || self.kind.as_deref() == Some("server_error")
+    }
+}
+
+/// Whether a request to the provider failing with `error` may succeed when sent again: the
+/// connection failed or timed out, or the provider was rate limited or overloaded.
+#[must_use]
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout() || e.is_connect();
+        }
+        ApiError::of(&cause.to_string()).is_some_and(|e| e.is_transient())
+    })
+}
+
+/// Pass the `chunks` of a streamed answer on, explaining how a proxy intercepting TLS may have
+/// broken the server-sent events they come from: cutting them off, or buffering or replacing the
+/// response so there are none. A complete answer has at least one chunk, if empty.
+pub fn checked(
+    chunks: BoxStream<'static, anyhow::Result<String>>,
+) -> BoxStream<'static, anyhow::Result<String>> {
+    stream::unfold(Some((chunks, false)), |state| async move {
+        let (mut chunks, received) = state?;
+        match chunks.next().await {
+            Some(Ok(chunk)) => Some((Ok(chunk), Some((chunks, true)))),
+            Some(Err(e)) => {
+                let broken = e
+                    .chain()
+                    .any(|cause| cause.is::<std::io::Error>() || cause.is::<reqwest::Error>());
+                let e = if broken {
+                    e.context(
+                        "The stream of the answer broke off. A proxy intercepting TLS may not \
+                         pass server-sent events through, exclude the provider with --no-proxy",
+                    )
+                } else {
+                    e
+                };
+                Some((Err(e), Some((chunks, received))))
+            }
+            None if received => None,
+            None => Some((
+                Err(anyhow!(
+                    "The provider answered without server-sent events. A proxy intercepting TLS \
+                     may have buffered or replaced the response, exclude the provider with \
+                     --no-proxy"
+                )),
+                None,
+            )),
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, StreamExt};
+
+    use super::{checked, is_transient, pem_blocks, Network};
+
+    /// The error the client reports for an error response with `body`
+    fn response(body: &str) -> anyhow::Error {
+        anyhow::Error::msg(format!(
+            "could not parse chat response {body}: missing field `id` at line 1 column 80"
+        ))
+    }
+
+    #[test]
+    fn test_is_transient_rate_limited() {
+        let error = response(
+            r#"{"error": {"message": "Rate limit reached", "type": "requests", "param": null, "code": "rate_limit_exceeded"}}"#,
+        );
+        assert!(is_transient(
+            &error.context("could not complete chat request")
+        ));
+    }
+
+    #[test]
+    fn test_is_transient_server_error() {
+        let error = response(
+            r#"{"error": {"message": "The server had an error", "type": "server_error", "param": null, "code": null}}"#,
+        );
+        assert!(is_transient(&error));
+    }
+
+    #[test]
+    fn test_is_transient_invalid() {
+        let error = response(
+            r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}}"#,
+        );
+        assert!(!is_transient(&error));
+
+        // the codes only count in an error response
+        let error = response(r#"{"choices": [], "code": "rate_limit_exceeded"}"#);
+        assert!(!is_transient(&error));
+        assert!(!is_transient(&anyhow::Error::msg("server_error")));
+    }
+
+    #[tokio::test]
+    async fn test_is_transient_connect() -> anyhow::Result<()> {
+        // a port nothing listens on anymore
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let client = reqwest::Client::builder().no_proxy().build()?;
+        let error = client
+            .get(format!("http://127.0.0.1:{port}"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(is_transient(&anyhow::Error::new(error)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_is_transient_timeout() -> anyhow::Result<()> {
+        // accepts the connection but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_millis(50))
+            .no_proxy()
+            .build()?;
+        let error = client.get(url).send().await.unwrap_err();
+        assert!(is_transient(&anyhow::Error::new(error)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_proxy() {
+        let env = |name: &str| match name {
+            "HTTPS_PROXY" => Some("http://secure:3128".to_string()),
+            "all_proxy" => Some("http://all:3128".to_string()),
+            "NO_PROXY" => Some("localhost".to_string()),
+            _ => None,
+        };
+
+        let network = Network::default();
+        assert_eq!(
+            network.proxy("https", env).as_deref(),
+            Some("http://secure:3128")
+        );
+        assert_eq!(
+            network.proxy("http", env).as_deref(),
+            Some("http://all:3128")
+        );
+        assert_eq!(network.proxy("http", |_| None), None);
+        assert_eq!(network.no_proxy(env), "localhost");
+
+        let network = Network {
+            proxy: Some("http://flag:3128".to_string()),
+            no_proxy: vec!["internal.example.com".to_string(), "10.0.0.0/8".to_string()],
+            ca_bundle: None,
+        };
+        assert_eq!(
+            network.proxy("https", env).as_deref(),
+            Some("http://flag:3128")
+        );
+        assert_eq!(
+            network.no_proxy(env),
+            "internal.example.com,10.0.0.0/8,localhost"
+        );
+
+        assert!(network.client().is_ok());
+        let invalid = Network {
+            proxy: Some("not a url".to_string()),
+            ..Network::default()
+        };
+        assert!(invalid.client().is_err());
+    }
+
+    #[test]
+    fn test_ca_bundle() -> anyhow::Result<()> {
+        let pem = "comment\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END \
+                   CERTIFICATE-----\n-----BEGIN CERTIFICATE-----\nBBBB\n-----END \
+                   CERTIFICATE-----\n";
+        let blocks: Vec<_> = pem_blocks(pem).collect();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[1].contains("BBBB") && blocks[1].ends_with("-----END CERTIFICATE-----"));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, "no certificates")?;
+        let network = Network {
+            ca_bundle: Some(path),
+            ..Network::default()
+        };
+        let error = network.client().unwrap_err();
+        assert!(format!("{error:#}").contains("contains no PEM certificates"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checked() {
+        let chunks = checked(stream::iter([Ok(String::new())]).boxed());
+        assert_eq!(chunks.count().await, 1);
+
+        // a proxy replaced the event stream
+        let mut chunks = checked(stream::empty().boxed());
+        let error = chunks.next().await.and_then(Result::err);
+        assert!(format!("{error:?}").contains("--no-proxy"));
+        assert!(chunks.next().await.is_none());
+    }
+}
@@ -0,0 +1,389 @@
+//! Parse and apply unified diffs.
+//!
+//! Hunks are applied at the line their header names. If the file changed since the diff was
+//! made, the nearest position where the context still matches is used instead. Hunks that match
+//! nowhere are reported as [`Conflict`]s and nothing is applied.
+
+use std::fmt;
+
+use anyhow::{bail, ensure, Context};
+
+/// The changes of a single file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+    /// `None` if the file is created
+    pub old: Option<String>,
+    /// `None` if the file is deleted
+    pub new: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// the first line of the hunk in the old file, starting at 1
+    pub old_start: usize,
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+/// A hunk that does not match the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// the index of the hunk, starting at 0
+    pub hunk: usize,
+    /// the line of the file where the hunk was expected, starting at 1
+    pub line: usize,
+    pub expected: String,
+    /// `None` if the file ended
+    pub found: Option<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hunk {} does not apply at line {}: expected `{}`, found ",
+            self.hunk + 1,
+            self.line,
+            self.expected
+        )?;
+
+        match &self.found {
+            Some(found) => write!(f, "`{found}`"),
+            None => f.write_str("the end of the file"),
+        }
+    }
+}
+
+impl FilePatch {
+    /// The path the patch applies to
+    ///
+    /// # Panics
+    /// If the patch has neither an old nor a new path, which [`parse`] does not return
+    #[must_use]
+    pub fn path(&self) -> &str {
+        self.new
+            .as_deref()
+            .or(self.old.as_deref())
+            .expect("a patch has at least one path")
+    }
+
+    /// Apply the hunks to `original`.
+    ///
+    /// # Errors
+    /// Every hunk that does not match `original`.
+    pub fn apply(&self, original: &str) -> Result<String, Vec<Conflict>> {
+        let mut lines: Vec<&str> = original.lines().collect();
+        let mut conflicts = Vec::new();
+
+        // how far the hunks moved because of the lines earlier hunks added or removed
+        let mut shift: isize = 0;
+
+        for (index, hunk) in self.hunks.iter().enumerate() {
+            let old: Vec<&str> = hunk.old_lines().collect();
+            let new: Vec<&str> = hunk.new_lines().collect();
+
+            // a hunk of a new file starts at line 0
+            let expected = hunk
+                .old_start
+                .saturating_sub(1)
+                .saturating_add_signed(shift);
+
+            let Some(start) = find(&lines, &old, expected) else {
+                conflicts.push(conflict(index, &lines, &old, expected));
+                continue;
+            };
+
+            lines.splice(start..start + old.len(), new.iter().copied());
+            // a `Vec` never holds more than `isize::MAX` elements
+            shift += new.len().cast_signed() - old.len().cast_signed();
+        }
+
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        let mut patched = lines.join("\n");
+        if !patched.is_empty() && (original.is_empty() || original.ends_with('\n')) {
+            patched.push('\n');
+        }
+
+        Ok(patched)
+    }
+}
+
+impl Hunk {
+    /// The lines of the hunk in the old file
+    fn old_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Context(line) | Line::Remove(line) => Some(line.as_str()),
+            Line::Add(_) => None,
+        })
+    }
+
+    /// The lines of the hunk in the new file
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Context(line) | Line::Add(line) => Some(line.as_str()),
+            Line::Remove(_) => None,
+        })
+    }
+}
+
+/// The position of `old` in `lines` closest to `expected`.
+fn find(lines: &[&str], old: &[&str], expected: usize) -> Option<usize> {
+    let matches = |start: usize| lines.get(start..start + old.len()) == Some(old);
+
+    (0..=lines.len()).find_map(|distance| {
+        [
+            expected.checked_sub(distance),
+            expected.checked_add(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&start| matches(start))
+    })
+}
+
+/// The first line of the hunk that does not match at `expected`.
+fn conflict(hunk: usize, lines: &[&str], old: &[&str], expected: usize) -> Conflict {
+    let (offset, expected_line) = old
+        .iter()
+        .enumerate()
+        .find(|&(offset, line)| lines.get(expected + offset) != Some(line))
+        .map_or((0, ""), |(offset, line)| (offset, *line));
+
+    Conflict {
+        hunk,
+        line: expected + offset + 1,
+        expected: expected_line.to_string(),
+        found: lines.get(expected + offset).map(|line| (*line).to_string()),
+    }
+}
+
+/// The path of a `---` or `+++` header, without the `a/` or `b/` prefix git adds.
+fn header_path(header: &str) -> Option<String> {
+    // a timestamp may follow the path
+    let path = header.split('\t').next().unwrap_or_default().trim();
+
+    if path == "/dev/null" {
+        return None;
+    }
+
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+
+    Some(path.to_string())
+}
+
+/// Parse `@@ -old_start,old_len +new_start,new_len @@`, returning the start and lengths.
+fn hunk_header(header: &str) -> anyhow::Result<(usize, usize, usize)> {
+    let ranges = header
+        .strip_prefix("@@ ")
+        .and_then(|header| header.split_once(" @@"))
+        .map(|(ranges, _)| ranges)
+        .with_context(|| format!("Invalid hunk header `{header}`"))?;
+
+    let (old, new) = ranges
+        .split_once(' ')
+        .with_context(|| format!("Invalid hunk header `{header}`"))?;
+
+    let range = |range: &str, sign: char| -> anyhow::Result<(usize, usize)> {
+        let range = range
+            .strip_prefix(sign)
+            .with_context(|| format!("Invalid hunk header `{header}`"))?;
+
+        let (start, len) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((start.parse()?, len.parse()?))
+    };
+
+    let (old_start, old_len) = range(old, '-')?;
+    let (_, new_len) = range(new, '+')?;
+
+    Ok((old_start, old_len, new_len))
+}
+
+/// Parse a unified diff of one or more files.
+///
+/// Lines before the first `---` header, like `diff --git` lines, are ignored.
+///
+/// # Errors
+/// If the diff is malformed, e.g. a hunk has fewer lines than its header says.
+pub fn parse(diff: &str) -> anyhow::Result<Vec<FilePatch>> {
+    let mut patches = Vec::new();
+    let mut lines = diff.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(old) = line.strip_prefix("--- ") else {
+            continue;
+        };
+
+        let new = lines
+            .next()
+            .and_then(|line| line.strip_prefix("+++ "))
+            .context("`---` must be followed by `+++`")?;
+
+        let mut patch = FilePatch {
+            old: header_path(old),
+            new: header_path(new),
+            hunks: Vec::new(),
+        };
+
+        ensure!(
+            patch.old.is_some() || patch.new.is_some(),
+            "A patch needs a path"
+        );
+
+        while let Some(header) = lines.next_if(|line| line.starts_with("@@")) {
+            let (old_start, mut old_len, mut new_len) = hunk_header(header)?;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+            };
+
+            while old_len > 0 || new_len > 0 {
+                let line = lines
+                    .next()
+                    .with_context(|| format!("Hunk `{header}` ends early"))?;
+
+                let (kind, content) = line.split_at(line.len().min(1));
+                let line = match kind {
+                    // some editors strip the space of empty context lines
+                    " " | "" => Line::Context(content.to_string()),
+                    "-" => Line::Remove(content.to_string()),
+                    "+" => Line::Add(content.to_string()),
+                    "\\" => continue,
+                    _ => bail!("Invalid line `{line}` in hunk `{header}`"),
+                };
+
+                match line {
+                    Line::Context(_) => {
+                        old_len = old_len.checked_sub(1).context("Hunk is too long")?;
+                        new_len = new_len.checked_sub(1).context("Hunk is too long")?;
+                    }
+                    Line::Remove(_) => {
+                        old_len = old_len.checked_sub(1).context("Hunk is too long")?;
+                    }
+                    Line::Add(_) => {
+                        new_len = new_len.checked_sub(1).context("Hunk is too long")?;
+                    }
+                }
+
+                hunk.lines.push(line);
+            }
+
+            // `\ No newline at end of file`
+            lines.next_if(|line| line.starts_with('\\'));
+
+            patch.hunks.push(hunk);
+        }
+
+        patches.push(patch);
+    }
+
+    ensure!(!patches.is_empty(), "The diff does not change any file");
+
+    Ok(patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{parse, Conflict};
+
+    const ORIGINAL: &str =
+        "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+
+    const DIFF: &str = "diff --git a/src/main.rs b/src/main.rs
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,4 +1,4 @@
+ fn main() {
+     let a = 1;
+-    let b = 2;
++    let b = 3;
+     println!(\"{}\", a + b);
+";
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let patches = parse(DIFF)?;
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path(), "src/main.rs");
+
+        let patched = patches[0].apply(ORIGINAL).unwrap();
+        assert_eq!(patched, ORIGINAL.replace("b = 2", "b = 3"));
+
+        // lines were added above the hunk since the diff was made
+        let moved = format!("use std::io;\n\n{ORIGINAL}");
+        let patched = patches[0].apply(&moved).unwrap();
+        assert_eq!(patched, moved.replace("b = 2", "b = 3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict() -> anyhow::Result<()> {
+        let patches = parse(DIFF)?;
+
+        let conflicts = patches[0]
+            .apply(&ORIGINAL.replace("b = 2", "b = 5"))
+            .unwrap_err();
+
+        assert_eq!(conflicts, vec![Conflict {
+            hunk: 0,
+            line: 3,
+            expected: "    let b = 2;".to_string(),
+            found: Some("    let b = 5;".to_string()),
+        }]);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "hunk 1 does not apply at line 3: expected `    let b = 2;`, found `    let b = 5;`"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_and_deleted() -> anyhow::Result<()> {
+        let patches = parse(
+            "--- /dev/null
++++ b/hello.txt
+@@ -0,0 +1,2 @@
++hello
++there
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+",
+        )?;
+
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].old, None);
+        assert_eq!(patches[0].apply("").unwrap(), "hello\nthere\n");
+
+        assert_eq!(patches[1].new, None);
+        assert_eq!(patches[1].path(), "old.txt");
+        assert_eq!(patches[1].apply("gone\n").unwrap(), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("--- a/main.rs\n").is_err());
+        assert!(parse("--- a/main.rs\n+++ b/main.rs\n@@ -1,2 +1,2 @@\n-a\n").is_err());
+        assert!(parse("--- a/main.rs\n+++ b/main.rs\n@@ nonsense @@\n").is_err());
+    }
+}
@@ -0,0 +1,342 @@
+//! A plan is a list of steps generated by the model, each of which runs a [`Cmd`].
+//!
+//! The model writes plans as a JSON array such as
+//!
+//! ```json
+//! [
+//!   {
+//!     "title": "Create the project",
+//!     "description": "A binary crate for the calculator",
+//!     "command": { "type": "bash", "input": "cargo new calculator" },
+//!     "dependencies": []
+//!   }
+//! ]
+//! ```
+//!
+//! where `type` is one of the commands in [`PLAN_FORMAT`] and `dependencies` are the indices of
+//! the steps that have to run first. A command can also be written as a string with the command
+//! on its first line, e.g. `"bash\ncargo new calculator"`, see [`Cmd::parse`].
+
+use anyhow::{bail, ensure, Context};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    command::{codegen, write_file, Cmd},
+    patch,
+};
+
+/// The default introduction of the plan request, followed by [`PLAN_FORMAT`]
+pub const PLAN_PROMPT: &str =
+    "You plan how to complete an instruction on the user's machine. {workspace}";
+
+/// Describes the plan format to the model
+pub const PLAN_FORMAT: &str =
+    "Answer with only a JSON array of steps, without markdown code fences. Every step is an \
+     object with the fields `title`, `description`, `command` and `dependencies`. `command` is an \
+     object with the fields `type` and `input`, `dependencies` lists the indices (starting at 0) \
+     of earlier steps this step needs. Command types:\n- `bash`: `input` is a bash script\n- \
+     `zsh`: `input` is a zsh script\n- `librs`: `input` is the name of a crate to read the README \
+     of\n- `docsrs`: `input` is the path of a crate item to read the documentation of, e.g. \
+     `tokio::sync::mpsc::channel`\n- `cratesearch`: `input` is a search query for crates.io, \
+     answered with the matching crates, their downloads and latest version\n- `fetch`: `input` is \
+     the URL of a web page to read as markdown\n- `codegen`: `input` is the path of the file to \
+     write on the first line, then a description of its content\n- `cargo`: `input` is a cargo \
+     invocation without `cargo`, one of `new`, `build`, `test`, `run` or `add`. Use \
+     `--manifest-path` to select the project\n- `rustrun`: `input` is the source of a single file \
+     Rust program to compile and run, e.g. to check a snippet\n- `readfile`: `input` is the path \
+     of a file to read\n- `writefile`: `input` is the path of the file on the first line, then \
+     its exact content\n- `applypatch`: `input` is a unified diff. Paths are relative to the \
+     working directory";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StepCommand {
+    #[serde(rename = "type")]
+    pub cmd: Cmd,
+    pub input: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub title: String,
+    pub description: String,
+    #[serde(deserialize_with = "step_command")]
+    pub command: StepCommand,
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+}
+
+fn step_command<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StepCommand, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Object(StepCommand),
+        Text(String),
+    }
+
+    match Raw::deserialize(deserializer)? {
+        Raw::Object(command) => Ok(command),
+        Raw::Text(text) => {
+            let (cmd, input) = Cmd::parse(&text).map_err(serde::de::Error::custom)?;
+            Ok(StepCommand {
+                cmd,
+                input: input.to_string(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Step {
+    fn validate(&self, index: usize) -> anyhow::Result<()> {
+        ensure!(!self.title.trim().is_empty(), "Step {index} has no title");
+
+        for &dependency in &self.dependencies {
+            ensure!(
+                dependency < index,
+                "Step {index} depends on step {dependency}, which does not run before it"
+            );
+        }
+
+        match self.command.cmd {
+            Cmd::CodeGen => {
+                codegen::split_input(&self.command.input)
+                    .with_context(|| format!("Step {index} has an invalid codegen input"))?;
+            }
+            Cmd::WriteFile => {
+                write_file::split_input(&self.command.input)
+                    .with_context(|| format!("Step {index} has an invalid writefile input"))?;
+            }
+            Cmd::ApplyPatch => {
+                patch::parse(&self.command.input)
+                    .with_context(|| format!("Step {index} has an invalid patch"))?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// Parse and validate the model output.
+    ///
+    /// # Errors
+    /// - The output is not a JSON array of steps
+    /// - There are no steps
+    /// - A step has no title, an invalid input or depends on a step that does not run before it
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        // models tend to wrap JSON in markdown code fences even when told not to
+        let json = utils::markdown::strip_fence(text);
+        let plan: Self = serde_json::from_str(json).context("The plan is not valid JSON")?;
+
+        ensure!(!plan.steps.is_empty(), "The plan has no steps");
+
+        for (index, step) in plan.steps.iter().enumerate() {
+            step.validate(index)?;
+        }
+
+        Ok(plan)
+    }
+
+    /// The selected steps, e.g. for the hooks approving them
+    #[must_use]
+    pub fn only(&self, selection: &Selection) -> Self {
+        let steps = selection
+            .steps
+            .iter()
+            .map(|&index| self.steps[index].clone())
+            .collect();
+        Self { steps }
+    }
+
+    /// Select the steps at `indices` to run on their own, in the order of the plan.
+    ///
+    /// # Errors
+    /// If no step is selected or the plan has no step at an index.
+    pub fn select(&self, indices: &[usize]) -> anyhow::Result<Selection> {
+        ensure!(!indices.is_empty(), "no step is selected");
+        if let Some(index) = indices.iter().find(|&&index| index >= self.steps.len()) {
+            bail!(
+                "the plan has no step {}, it has {}",
+                index + 1,
+                self.steps.len()
+            );
+        }
+
+        let mut steps = indices.to_vec();
+        steps.sort_unstable();
+        steps.dedup();
+
+        let skipped = steps
+            .iter()
+            .flat_map(|&index| {
+                self.steps[index]
+                    .dependencies
+                    .iter()
+                    .map(move |&dependency| (index, dependency))
+            })
+            .filter(|(_, dependency)| steps.binary_search(dependency).is_err())
+            .collect();
+
+        Ok(Selection { steps, skipped })
+    }
+}
+
+/// Steps of a plan that run on their own, see [`Plan::select`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// the indices of the steps, in order
+    pub steps: Vec<usize>,
+    /// a selected step and a dependency of it that is not selected, for every such dependency
+    pub skipped: Vec<(usize, usize)>,
+}
+
+impl Selection {
+    /// Every step of `plan`
+    #[must_use]
+    pub fn all(plan: &Plan) -> Self {
+        Self {
+            steps: (0..plan.steps.len()).collect(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// A warning for every skipped dependency, with the steps numbered from 1
+    #[must_use]
+    pub fn warnings(&self) -> Vec<String> {
+        self.skipped
+            .iter()
+            .map(|(step, dependency)| {
+                format!(
+                    "step {} depends on step {}, which is skipped",
+                    step + 1,
+                    dependency + 1
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{Plan, Step, StepCommand};
+    use crate::command::Cmd;
+
+    const PLAN: &str = r#"[
+        {
+            "title": "Create the project",
+            "description": "A binary crate",
+            "command": { "type": "bash", "input": "cargo new calculator" }
+        },
+        {
+            "title": "Look up a parser",
+            "description": "Learn how to use nom",
+            "command": { "type": "librs", "input": "nom" },
+            "dependencies": []
+        },
+        {
+            "title": "Write the calculator",
+            "description": "Parse and evaluate expressions",
+            "command": {
+                "type": "codegen",
+                "input": "calculator/src/main.rs\nA calculator that reads expressions from stdin"
+            },
+            "dependencies": [0, 1]
+        }
+    ]"#;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let plan = Plan::parse(PLAN)?;
+
+        assert_eq!(plan.steps[0], Step {
+            title: "Create the project".to_string(),
+            description: "A binary crate".to_string(),
+            command: StepCommand {
+                cmd: Cmd::Bash,
+                input: "cargo new calculator".to_string(),
+            },
+            dependencies: vec![],
+        });
+        assert_eq!(plan.steps[1].command.cmd, Cmd::LibRs);
+        assert_eq!(plan.steps[2].command.cmd, Cmd::CodeGen);
+        assert_eq!(plan.steps[2].dependencies, vec![0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_command() -> anyhow::Result<()> {
+        let plan = Plan::parse(
+            r#"[{"title": "Build", "description": "", "command": "cargo\nbuild --release"}]"#,
+        )?;
+
+        assert_eq!(plan.steps[0].command, StepCommand {
+            cmd: Cmd::Cargo,
+            input: "build --release".to_string(),
+        });
+
+        assert!(
+            Plan::parse(r#"[{"title": "Build", "description": "", "command": "make\nall"}]"#)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_fences() -> anyhow::Result<()> {
+        let plan = Plan::parse(&format!("```json\n{PLAN}\n```"))?;
+        assert_eq!(plan.steps.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select() -> anyhow::Result<()> {
+        let plan = Plan::parse(PLAN)?;
+
+        let selection = plan.select(&[2, 1, 2])?;
+        assert_eq!(selection.steps, [1, 2]);
+        assert_eq!(selection.skipped, [(2, 0)]);
+        assert_eq!(selection.warnings(), [
+            "step 3 depends on step 1, which is skipped"
+        ]);
+
+        assert!(plan.select(&[0, 1, 2])?.skipped.is_empty());
+        assert_eq!(
+            plan.select(&[3]).unwrap_err().to_string(),
+            "the plan has no step 4, it has 3"
+        );
+        assert!(plan.select(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        let step = |command: &str, dependencies: &str| {
+            format!(
+                r#"[{{"title": "Step", "description": "", "command": {command}, "dependencies": {dependencies}}}]"#
+            )
+        };
+
+        assert!(Plan::parse("STEP Create the project").is_err());
+        assert!(Plan::parse("[]").is_err());
+        assert!(Plan::parse(&step(r#"{"type": "python", "input": "print(1)"}"#, "[]")).is_err());
+        assert!(Plan::parse(&step(r#"{"type": "bash"}"#, "[]")).is_err());
+        assert!(Plan::parse(&step(r#"{"type": "codegen", "input": "main.rs"}"#, "[]")).is_err());
+        assert!(Plan::parse(&step(r#"{"type": "applypatch", "input": "+ added"}"#, "[]")).is_err());
+        assert!(Plan::parse(&step(r#"{"type": "bash", "input": "ls"}"#, "[0]")).is_err());
+        assert!(Plan::parse(&step(r#"{"type": "bash", "input": "ls"}"#, "[]")).is_ok());
+    }
+}
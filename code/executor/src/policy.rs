@@ -0,0 +1,148 @@
+//! Which shell commands need the user to confirm them before they run, and which domains can be
+//! fetched.
+//!
+//! Commands are matched against a deny-list of patterns, each with the [`Risk`] it stands for.
+//! A match does not stop the command, it asks the frontend with a
+//! [`server::ConfirmCommand`](protocol::server::ConfirmCommand) first.
+
+use protocol::Risk;
+use regex::Regex;
+use reqwest::Url;
+
+struct Rule {
+    pattern: Regex,
+    risk: Risk,
+}
+
+pub struct Policy {
+    rules: Vec<Rule>,
+    /// the domains the fetch command may download from, any if empty
+    domains: Vec<String>,
+}
+
+/// The commands that are risky enough to confirm, matched anywhere in a script
+const DENY_LIST: &[(&str, Risk)] = &[
+    (
+        r"\brm\s+(-\w*[rR]\w*[fF]|-\w*[fF]\w*[rR]|-[rR]\s+-[fF]|-[fF]\s+-[rR])",
+        Risk::Destructive,
+    ),
+    (r"\b(mkfs|dd|shred|truncate)\b", Risk::Destructive),
+    (
+        r"\bgit\s+(push\s+.*--force|push\s+-f|reset\s+--hard|clean\s+-\w*f)",
+        Risk::Destructive,
+    ),
+    (r"\b(sudo|su|doas|chown)\b", Risk::Privileged),
+    (r"\bchmod\s+(-R\s+)?[0-7]*7[0-7]{0,2}\b", Risk::Privileged),
+    (
+        r"\b(curl|wget|ssh|scp|rsync|nc|netcat|telnet|ftp)\b",
+        Risk::Network,
+    ),
+    (r"\b(cargo|npm|pip)\s+(publish|upload)\b", Risk::Network),
+];
+
+impl Default for Policy {
+    fn default() -> Self {
+        let rules = DENY_LIST
+            .iter()
+            .map(|&(pattern, risk)| Rule {
+                pattern: Regex::new(pattern).expect("the deny-list is valid"),
+                risk,
+            })
+            .collect();
+
+        Self {
+            rules,
+            domains: Vec::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// Only allow fetching from `domains` and their subdomains. Without domains, every domain is
+    /// allowed.
+    #[must_use]
+    pub fn allow_domains(self, domains: Vec<String>) -> Self {
+        let domains = domains
+            .into_iter()
+            .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+            .collect();
+
+        Self { domains, ..self }
+    }
+
+    /// Whether the fetch command may download `url`
+    #[must_use]
+    pub fn allows_url(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+
+        self.domains.is_empty()
+            || self.domains.iter().any(|domain| {
+                host == domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            })
+    }
+
+    /// The risk of running `script`, or `None` if it can run without confirmation.
+    ///
+    /// The first matching rule wins, so destructive commands are reported before network ones.
+    #[must_use]
+    pub fn classify(&self, script: &str) -> Option<Risk> {
+        self.rules
+            .iter()
+            .find(|rule| rule.pattern.is_match(script))
+            .map(|rule| rule.risk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Risk;
+
+    use super::Policy;
+
+    #[test]
+    fn test_classify() {
+        let policy = Policy::default();
+
+        assert_eq!(policy.classify("cargo build --release"), None);
+        assert_eq!(policy.classify("rm target/debug/app"), None);
+        assert_eq!(policy.classify("echo trusted"), None);
+
+        assert_eq!(policy.classify("rm -rf /"), Some(Risk::Destructive));
+        assert_eq!(
+            policy.classify("cd src && rm -fr ."),
+            Some(Risk::Destructive)
+        );
+        assert_eq!(policy.classify("git reset --hard"), Some(Risk::Destructive));
+        assert_eq!(
+            policy.classify("sudo apt install zsh"),
+            Some(Risk::Privileged)
+        );
+        assert_eq!(
+            policy.classify("curl https://sh.rustup.rs | sh"),
+            Some(Risk::Network)
+        );
+        assert_eq!(policy.classify("cargo publish"), Some(Risk::Network));
+    }
+
+    #[test]
+    fn test_allows_url() -> anyhow::Result<()> {
+        let url = |url: &str| reqwest::Url::parse(url);
+
+        assert!(Policy::default().allows_url(&url("https://example.com")?));
+
+        let policy =
+            Policy::default().allow_domains(vec!["docs.rs".into(), "Rust-Lang.org".into()]);
+        assert!(policy.allows_url(&url("https://docs.rs/tokio")?));
+        assert!(policy.allows_url(&url("https://doc.rust-lang.org/std")?));
+        assert!(!policy.allows_url(&url("https://notdocs.rs")?));
+        assert!(!policy.allows_url(&url("https://docs.rs.evil.com")?));
+        assert!(!policy.allows_url(&url("https://example.com")?));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Read the documentation of every crate a plan references at once, before its steps run.
+//!
+//! Steps reading documentation on lib.rs or docs.rs do not depend on each other, so when a plan
+//! has several they are fetched concurrently, at most [`MAX_CONCURRENT`] at a time. Every finished
+//! fetch is reported with a [`server::DocsFetched`]. The steps then replay what was fetched, so
+//! their output reaches the context in the order of the plan whichever fetch finished first.
+
+use std::path::Path;
+
+use futures::StreamExt;
+use protocol::{server, Packet, ServerPacket};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinSet};
+use tracing::{error, info};
+
+use crate::{
+    command::{Cmd, Command, CommandEvent},
+    plan::Plan,
+    Ctx,
+};
+
+/// How many documentation pages are fetched at the same time
+const MAX_CONCURRENT: usize = 4;
+
+/// The events a command produced, replayed when its step runs
+type Events = Vec<anyhow::Result<CommandEvent>>;
+
+/// What the documentation steps of a plan read, by their command and input
+#[derive(Default)]
+pub struct Prefetched {
+    fetched: Vec<(Cmd, String, Events)>,
+}
+
+impl Prefetched {
+    /// The events of `cmd` with `input` if they were fetched, only once
+    pub fn take(&mut self, cmd: Cmd, input: &str) -> Option<Events> {
+        let position = self
+            .fetched
+            .iter()
+            .position(|(fetched, fetched_input, _)| *fetched == cmd && fetched_input == input)?;
+        Some(self.fetched.remove(position).2)
+    }
+}
+
+/// The documentation steps of `plan`, without duplicates
+fn targets(plan: &Plan) -> Vec<(Cmd, String)> {
+    let mut targets: Vec<(Cmd, String)> = Vec::new();
+    for step in &plan.steps {
+        let target = (step.command.cmd, step.command.input.clone());
+        if matches!(target.0, Cmd::LibRs | Cmd::DocsRs) && !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// Fetch the documentation `plan` reads if it reads several crates.
+///
+/// # Errors
+/// If sending the progress to the frontend failed.
+pub async fn prefetch(
+    ctx: &Ctx,
+    dir: &Path,
+    plan: &Plan,
+    tx: &UnboundedSender<ServerPacket>,
+) -> anyhow::Result<Prefetched> {
+    let targets = targets(plan);
+    if targets.len() < 2 {
+        return Ok(Prefetched::default());
+    }
+
+    info!("Fetching the documentation of {} crates", targets.len());
+    fetch_all(ctx, dir, targets, tx).await
+}
+
+/// Run every command of `targets` concurrently, reporting each as it finishes.
+async fn fetch_all(
+    ctx: &Ctx,
+    dir: &Path,
+    targets: Vec<(Cmd, String)>,
+    tx: &UnboundedSender<ServerPacket>,
+) -> anyhow::Result<Prefetched> {
+    let total = targets.len();
+    let mut results: Vec<Option<Events>> = (0..total).map(|_| None).collect();
+    let mut pending = targets.iter().cloned().enumerate();
+    // dropping the set aborts the fetches still running
+    let mut running = JoinSet::new();
+    let mut fetched = 0;
+
+    loop {
+        while running.len() < MAX_CONCURRENT {
+            let Some((index, (cmd, input))) = pending.next() else {
+                break;
+            };
+            let (ctx, dir) = (ctx.clone(), dir.to_path_buf());
+            running.spawn(async move {
+                let command = cmd.cast::<dyn Command + Send + Sync>();
+                let events: Events = command.execute(ctx, &dir, &input).collect().await;
+                (index, events)
+            });
+        }
+
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (index, events) = match joined {
+            Ok(finished) => finished,
+            Err(e) => {
+                // the step fetches it again when it runs
+                error!("Fetching documentation failed: {e}");
+                continue;
+            }
+        };
+
+        fetched += 1;
+        let success = matches!(events.last(), Some(Ok(CommandEvent::Exit(0))));
+        tx.send(Packet::server(server::DocsFetched {
+            name: targets[index].1.trim().to_string(),
+            fetched,
+            total,
+            success,
+        }))?;
+        results[index] = Some(events);
+    }
+
+    let fetched = targets
+        .into_iter()
+        .zip(results)
+        .filter_map(|((cmd, input), events)| Some((cmd, input, events?)))
+        .collect();
+    Ok(Prefetched { fetched })
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::server::Server;
+
+    use super::{fetch_all, targets};
+    use crate::{command::Cmd, ctx, plan::Plan};
+
+    #[test]
+    fn test_targets() -> anyhow::Result<()> {
+        let plan = Plan::parse(
+            r#"[
+                {"title": "Read tokio", "description": "", "command": {"type": "librs", "input": "tokio"}},
+                {"title": "Run", "description": "", "command": {"type": "bash", "input": "ls"}},
+                {"title": "Read serde", "description": "", "command": {"type": "docsrs", "input": "serde"}},
+                {"title": "Read tokio again", "description": "", "command": {"type": "librs", "input": "tokio"}}
+            ]"#,
+        )?;
+
+        assert_eq!(targets(&plan), [
+            (Cmd::LibRs, "tokio".to_string()),
+            (Cmd::DocsRs, "serde".to_string())
+        ]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["a", "b", "c", "d", "e"] {
+            std::fs::write(dir.path().join(name), format!("{name}\n"))?;
+        }
+        let targets: Vec<_> = ["a", "b", "c", "d", "e", "missing"]
+            .into_iter()
+            .map(|name| (Cmd::ReadFile, name.to_string()))
+            .collect();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut prefetched = fetch_all(&ctx()?, dir.path(), targets, &tx).await?;
+
+        // one progress packet per fetch, however they finished
+        let mut progress = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            let Server::DocsFetched {
+                name,
+                fetched,
+                total,
+                success,
+            } = packet.data
+            else {
+                panic!("unexpected packet");
+            };
+            assert_eq!(total, 6);
+            progress.push((fetched, name, success));
+        }
+        progress.sort();
+        assert_eq!(progress.len(), 6);
+        assert_eq!(
+            progress
+                .iter()
+                .map(|(fetched, ..)| *fetched)
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert!(progress
+            .iter()
+            .all(|(_, name, success)| *success == (name != "missing")));
+
+        // in the order of the targets, not of the fetches
+        let inputs: Vec<_> = prefetched
+            .fetched
+            .iter()
+            .map(|(_, input, _)| input)
+            .collect();
+        assert_eq!(inputs, ["a", "b", "c", "d", "e", "missing"]);
+
+        assert!(prefetched.take(Cmd::ReadFile, "c").is_some());
+        assert!(prefetched.take(Cmd::ReadFile, "c").is_none());
+        assert!(prefetched.take(Cmd::LibRs, "a").is_none());
+        Ok(())
+    }
+}
@@ -1,23 +1,63 @@
-use anyhow::bail;
+use std::{collections::VecDeque, future::Future, sync::Arc, time::Duration};
+
+use anyhow::Context;
 use async_trait::async_trait;
 use futures::StreamExt;
-use protocol::{client::Client, server, ClientPacket, Packet, ServerPacket};
-use tokio::net::TcpStream;
+use protocol::{
+    client::Client, server, trace::Tracer, ClientPacket, Packet, Phase, ServerPacket, SessionId,
+    SessionSummary, StreamFrame,
+};
+use tokio::{net::TcpStream, sync::broadcast, time::Instant};
 use tokio_tungstenite::WebSocketStream;
-use tracing::info;
+use tracing::{error, info};
 
 use crate::{
-    process::{question::QAndA, reader::Reader, writer::Writer},
+    answer_format, audit,
+    indexer::Indexer,
+    memory::{self, Memory},
+    metrics::{self, Metrics},
+    plan::Plan,
+    process::{
+        execute::{Engine, Order},
+        extract::Extractor,
+        question::QAndA,
+        reader::Reader,
+        revision::Revision,
+        router::Router,
+        state::{State, StateViolation},
+        writer::Writer,
+    },
+    prompts::Prompt,
+    remote,
+    session::SessionManager,
+    usage,
+    warm::Warmer,
+    workspace::{
+        self,
+        stats::{self, Stats},
+    },
     Comm, Executor,
 };
 
-mod question;
+mod candidates;
+pub mod conversation;
+mod execute;
+mod extract;
+mod handlers;
+pub mod question;
 mod reader;
+mod revision;
+mod router;
+mod state;
 mod writer;
 
+/// How often the index of the working directory checks for changed files
+const INDEX_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct WebSocketComm {
     reader: Reader,
     writer: Writer,
+    tracer: Tracer,
 }
 
 impl WebSocketComm {
@@ -26,6 +66,7 @@ impl WebSocketComm {
         Self {
             reader: reader.into(),
             writer: writer.into(),
+            tracer: Tracer::default(),
         }
     }
 }
@@ -33,146 +74,850 @@ impl WebSocketComm {
 #[async_trait]
 impl Comm for WebSocketComm {
     async fn send(&mut self, packet: ServerPacket) -> anyhow::Result<()> {
-        self.writer.write(packet).await
+        self.writer.write(self.tracer.stamp(packet)).await
     }
 
     async fn recv(&mut self) -> anyhow::Result<ClientPacket> {
-        self.reader.read().await
+        let packet = self.reader.read().await?;
+        self.tracer.received(&packet);
+        Ok(packet)
+    }
+
+    fn tracer(&self) -> &Tracer {
+        &self.tracer
     }
 }
 
 pub struct Process<C> {
     executor: Executor,
+    sessions: SessionManager,
+    /// the id of the session this process is driving
+    id: SessionId,
     q_and_a: Option<QAndA>,
+    state: State,
     comm: C,
+
+    /// packets that arrived while a question was being streamed
+    queued: VecDeque<ClientPacket>,
+    notices: broadcast::Receiver<String>,
+    /// what the working directory contains, analyzed when the session starts
+    workspace: Stats,
+    /// the symbols and embedded files of the working directory, `None` until it was analyzed
+    index: Option<Indexer>,
+    /// whether the frontend was told about the shutdown
+    shutdown_sent: bool,
+    metrics: Metrics,
+    /// the number of streams started, the id of the last one
+    streams: u64,
+    /// hands every packet to its handler
+    router: Arc<Router<C>>,
+    /// what to go back to on a [`Client::RevertLast`], the last instruction or execution last
+    revisions: Vec<Revision>,
+    /// the last plan that was approved, whose steps a [`Client::ExecuteSteps`] runs
+    plan: Option<Plan>,
 }
 
-impl<C: Comm> Process<C> {
-    pub fn new(executor: Executor, comm: C) -> Self {
+impl<C: Comm + Send> Process<C> {
+    pub fn new(executor: Executor, sessions: SessionManager, comm: C) -> Self {
+        let id = sessions.create();
+        let notices = executor.ctx.notices.subscribe();
         Self {
             executor,
+            sessions,
+            id,
             comm,
             q_and_a: None,
+            state: State::Idle,
+            queued: VecDeque::new(),
+            notices,
+            workspace: Stats::default(),
+            index: None,
+            shutdown_sent: false,
+            metrics: Metrics::default(),
+            streams: 0,
+            router: Arc::new(Router::default()),
+            revisions: Vec::new(),
+            plan: None,
         }
     }
+
+    /// Start a stream of [`StreamFrame`]s with a new id.
+    fn stream(&mut self) -> Framer {
+        self.streams += 1;
+        Framer {
+            id: self.streams,
+            seq: 0,
+        }
+    }
+}
+
+/// Numbers the frames of a stream
+struct Framer {
+    id: u64,
+    seq: u64,
+}
+
+impl Framer {
+    fn frame(&mut self, payload: String, end: bool) -> StreamFrame {
+        let frame = StreamFrame {
+            id: self.id,
+            seq: self.seq,
+            payload,
+            end,
+        };
+        self.seq += 1;
+        frame
+    }
+}
+
+/// How streaming a question ended
+enum Streamed {
+    /// the full question
+    Complete(String),
+    /// the frontend sent a [`Client::Cancel`]
+    Cancelled,
+    /// the frontend sent a [`Client::Regenerate`]
+    Regenerate,
+    /// the provider failed, the question can be asked again
+    Failed(anyhow::Error),
+    /// the question was degenerate and was not shown, see [`guardrails`](crate::guardrails)
+    Rejected,
 }
 
 impl<C: Comm + Send> Process<C> {
-    async fn process_packet(&mut self, packet: Packet<Client>) -> anyhow::Result<()> {
-        match packet.data {
-            // we are getting an instruction from the frontend
-            // - This is the first packet we get from the frontend
-            // - This defines the purpose of a [`QAndA`] session
-            // - This is the first message sent from `frontend-cli`
-            // - For instance if we are building a calculator, the instruction would be "Build a
-            //   calculator"
-            // - Questions regarding the instruction are generated by GPT4 and sent back to the
-            //   frontend via the [`server::Question`] packet
-            Client::Instruction { instruction } => {
-                info!("Instruction: {}", instruction);
-
-                let mut q_and_a = QAndA::new(self.executor.clone(), instruction);
-
-                // get stream of Result<String> from chat GPT
-                let mut question_stream = q_and_a.gen_question().await?.enumerate();
-                let mut question = String::new();
-
-                // loop over stream of words (String),
-                // and append them to `question`
-                //
-                // each word will be sent as a packet to
-                // the frontend
-                loop {
-                    match question_stream.next().await {
-                        Some((i, word)) => {
-                            let word = word?;
-                            question.push_str(&word);
-                            let is_first_word = i == 0;
-                            // send a packet that will be handled by frontend-cli/app.rs
+    /// Count `packet` in the [`Telemetry`](crate::telemetry::Telemetry) once it was received.
+    /// Returns `false` if it was handled before and has to be dropped, see
+    /// [`delivery`](protocol::delivery).
+    fn received(&self, packet: &ClientPacket) -> bool {
+        if !self.sessions.deliver(self.id, packet.id) {
+            info!("Dropping packet {} that was handled before", packet.id);
+            return false;
+        }
+        self.executor
+            .ctx
+            .telemetry
+            .packet(state::name(&packet.data));
+        true
+    }
+
+    /// Generate the next question and stream it word by word to the frontend.
+    ///
+    /// While streaming we keep listening to the frontend, so the stream can be cancelled or
+    /// regenerated.
+    async fn stream_question(&mut self, q_and_a: &mut QAndA) -> anyhow::Result<Streamed> {
+        // get stream of Result<String> from chat GPT
+        let requested = Instant::now();
+        let follow_up = q_and_a.is_follow_up();
+        let mut word_stream = match q_and_a.gen_question().await {
+            Ok(words) => words.enumerate(),
+            Err(e) => return Ok(Streamed::Failed(e)),
+        };
+        if follow_up {
+            self.comm.send(Packet::server(server::FollowUp)).await?;
+        }
+        let responded = Instant::now();
+        self.metrics
+            .record(metrics::PROVIDER, responded - requested);
+        let mut question = String::new();
+        let mut extractor = Extractor::default();
+        let mut guard = q_and_a.guard();
+        let mut framer = self.stream();
+
+        // loop over stream of words (String),
+        // and append them to `question`
+        //
+        // each word will be sent as a packet to
+        // the frontend
+        loop {
+            tokio::select! {
+                word = word_stream.next() => match word {
+                    Some((i, word)) => {
+                        let word = match word {
+                            Ok(word) => word,
+                            Err(e) => return Ok(Streamed::Failed(e)),
+                        };
+                        let is_first_word = i == 0;
+                        if is_first_word {
+                            self.metrics.record(metrics::FIRST_TOKEN, responded.elapsed());
+                            self.metrics
+                                .record(metrics::TIME_TO_FIRST_TOKEN, requested.elapsed());
+                        }
+                        let word = extractor.push(&word);
+                        if word.is_empty() {
+                            continue;
+                        }
+                        question.push_str(&word);
+                        let Some(shown) = guard.push(&word) else {
+                            continue;
+                        };
+                        // send a packet that will be handled by frontend-cli/app.rs
+                        self.comm
+                            .send(Packet::server(server::Question {
+                                frame: framer.frame(shown, false),
+                            }))
+                            .await?;
+                    }
+                    None => {
+                        let rest = extractor.finish();
+                        question.push_str(&rest);
+                        let mut shown = guard.push(&rest).unwrap_or_default();
+                        if let Some(flaw) = guard.flaw() {
+                            info!(target: "guardrails", kind = "question", %flaw, question, "Rejected output");
+                            if q_and_a.reject() {
+                                return Ok(Streamed::Rejected);
+                            }
+                        }
+                        shown.push_str(&guard.release());
+                        if !shown.is_empty() {
                             self.comm
                                 .send(Packet::server(server::Question {
-                                    question: word,
-                                    is_first_word,
-                                    is_last_word: false,
+                                    frame: framer.frame(shown, false),
                                 }))
                                 .await?;
                         }
-                        None => {
+                        let others = extractor.others();
+                        if !others.is_empty() {
+                            info!(target: "guardrails", ?others, "Queued the questions asked at once");
+                            q_and_a.queue(others);
+                        }
+                        self.comm
+                            .send(Packet::server(server::Question {
+                                frame: framer.frame(String::new(), true),
+                            }))
+                            .await?;
+                        let alternatives = q_and_a.take_alternatives();
+                        if !alternatives.is_empty() {
                             self.comm
-                                .send(Packet::server(server::Question {
-                                    question: String::new(),
-                                    is_first_word: false,
-                                    is_last_word: true,
-                                }))
+                                .send(Packet::server(server::QuestionAlternatives { alternatives }))
                                 .await?;
-                            break;
                         }
+                        return Ok(Streamed::Complete(question));
+                    }
+                },
+                packet = self.comm.recv() => {
+                    let packet = packet?;
+                    if !self.received(&packet) {
+                        continue;
+                    }
+                    match packet.data {
+                        Client::Cancel => return Ok(Streamed::Cancelled),
+                        Client::Regenerate => return Ok(Streamed::Regenerate),
+                        Client::Ping => self.comm.send(Packet::server(server::Pong)).await?,
+                        _ => self.queued.push_back(packet),
                     }
                 }
+            }
+        }
+    }
 
-                // after getting all of the stream of words appended
-                // to `question`, making it a full question,
-                // push the sentence into the Vec
-                q_and_a.add_question(question);
-                self.q_and_a = Some(q_and_a);
+    /// Ask the next question, regenerating it as often as the frontend requests, and store the
+    /// session.
+    ///
+    /// If the frontend cancels the question, the session goes back to the previous question so it
+    /// can be answered again. Without a previous question, there is no session.
+    ///
+    /// If the provider fails, the frontend gets a recoverable [`server::Error`] and the session
+    /// goes back like after a cancel, so the answer can be sent again.
+    async fn ask(&mut self, mut q_and_a: QAndA) -> anyhow::Result<()> {
+        self.send_status(Phase::Asking).await?;
+
+        loop {
+            match self.stream_question(&mut q_and_a).await? {
+                Streamed::Complete(question) => {
+                    info!("Question: {}", question);
+                    self.executor.ctx.audit(&audit::Event::Question {
+                        id: None,
+                        question: &question,
+                    });
+                    self.send_answer_format(&question).await?;
+                    // after getting all of the stream of words appended
+                    // to `question`, making it a full question,
+                    // push the sentence into the Vec
+                    q_and_a.add_question(question);
+                    self.set_session(Some(q_and_a));
+                    break;
+                }
+                Streamed::Cancelled => {
+                    info!("Question cancelled");
+                    self.comm.send(Packet::server(server::Cancelled)).await?;
+                    self.restore(q_and_a);
+                    break;
+                }
+                Streamed::Failed(e) => {
+                    error!("Failed to generate a question: {e:#}");
+                    self.send_error(&e, true).await?;
+                    self.restore(q_and_a);
+                    break;
+                }
+                Streamed::Regenerate => {
+                    info!("Regenerating question");
+                }
+                Streamed::Rejected => {
+                    info!("Regenerating a degenerate question");
+                }
             }
-            // from the second prompt onwards, this Event
-            // will be used to continue the qa session
-            Client::Answer { answer } => {
-                let Some(q_and_a) = self.q_and_a.as_mut() else {
-                    bail!("No question to answer");
-                };
-
-                info!("Answer: {}", answer);
-
-                q_and_a.answer(answer);
-
-                let mut question = String::new();
-                let mut word_stream = q_and_a.gen_question().await?.enumerate();
-
-                // loop over stream of words (String),
-                // and append them to `question`
-                //
-                // each word will be sent as a packet to
-                // the frontend
-                loop {
-                    match word_stream.next().await {
-                        Some((i, word)) => {
-                            let word = word?;
-                            let is_first_word = i == 0;
-                            question.push_str(&word);
-                            // send a packet that will be handled by frontend-cli/app.rs
-                            self.comm
-                                .send(Packet::server(server::Question {
-                                    question: word,
-                                    is_first_word,
-                                    is_last_word: false,
-                                }))
-                                .await?;
+        }
+
+        self.send_status(Phase::Waiting).await
+    }
+
+    /// Go back to the previous question of `q_and_a` so it can be answered again. Without a
+    /// previous question, there is no session.
+    fn restore(&mut self, mut q_and_a: QAndA) {
+        if q_and_a.has_questions() {
+            q_and_a.discard_answer();
+            self.set_session(Some(q_and_a));
+        } else {
+            self.set_session(None);
+        }
+    }
+
+    /// Ask the questions about the instruction of `q_and_a` that are independent of each other at
+    /// once, or the first question alone if there are not several.
+    async fn ask_batch(&mut self, mut q_and_a: QAndA) -> anyhow::Result<()> {
+        self.send_status(Phase::Asking).await?;
+
+        let questions = match q_and_a.gen_batch().await {
+            Ok(questions) => questions,
+            Err(e) => {
+                error!("Failed to generate questions: {e:#}");
+                Vec::new()
+            }
+        };
+
+        // secret questions are asked alone, so the frontend can mask the answer
+        if questions.len() < 2
+            || questions
+                .iter()
+                .any(|question| answer_format::is_secret(question))
+        {
+            return self.ask(q_and_a).await;
+        }
+
+        info!("Questions: {questions:?}");
+        let items = q_and_a.add_batch(questions);
+        for item in &items {
+            self.executor.ctx.audit(&audit::Event::Question {
+                id: Some(item.id),
+                question: &item.question,
+            });
+        }
+        self.comm
+            .send(Packet::server(server::Questions { items }))
+            .await?;
+        self.set_session(Some(q_and_a));
+
+        self.send_status(Phase::Waiting).await
+    }
+
+    /// Answer the question `id` of the batch, asking the next question once all are answered.
+    async fn answer_to(&mut self, id: u64, answer: String) -> anyhow::Result<()> {
+        let q_and_a = self
+            .q_and_a
+            .as_mut()
+            .context("an interview always has an instruction")?;
+
+        let Some(question) = q_and_a.batch_question(id) else {
+            return self.reject(StateViolation::UnknownQuestion { id }).await;
+        };
+
+        if let Some(Err(invalid)) =
+            answer_format::infer(question).map(|validator| validator.check(&answer))
+        {
+            info!("Invalid answer: {invalid}");
+            return self
+                .comm
+                .send(Packet::server(server::Rejected {
+                    reason: invalid.to_string(),
+                }))
+                .await;
+        }
+
+        info!("Answer to {id}: {answer}");
+        self.executor.ctx.audit(&audit::Event::Answer {
+            id: Some(id),
+            answer: Some(&answer),
+        });
+
+        if q_and_a.answer_to(id, answer) == Some(true) {
+            let q_and_a = self.q_and_a.take().context("answered above")?;
+            self.ask(q_and_a).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Continue a reattached session whose frontend has seen `seen_questions` complete questions.
+    async fn sync(&mut self, q_and_a: QAndA, seen_questions: usize) -> anyhow::Result<()> {
+        // the frontend queues the batch again, answers it already gave can be given again
+        let items = q_and_a.batch();
+        if !items.is_empty() {
+            self.comm
+                .send(Packet::server(server::Questions { items }))
+                .await?;
+            self.set_session(Some(q_and_a));
+            return Ok(());
+        }
+
+        if q_and_a.needs_question() {
+            return self.ask(q_and_a).await;
+        }
+
+        // the question was completed while the frontend was disconnected
+        if q_and_a.questions().len() > seen_questions {
+            if let Some(question) = q_and_a.questions().last() {
+                let frame = self.stream().frame(question.clone(), true);
+                self.comm
+                    .send(Packet::server(server::Question { frame }))
+                    .await?;
+                self.send_answer_format(question).await?;
+            }
+        }
+
+        self.set_session(Some(q_and_a));
+        Ok(())
+    }
+
+    /// Generate a plan and run it, or run steps of the last plan, while forwarding its progress to
+    /// the frontend. The plan is kept for a [`Client::ExecuteSteps`] once it is approved.
+    ///
+    /// The frontend can stop the execution with a [`Client::Cancel`], which kills the running
+    /// step. Returns whether every step succeeded.
+    ///
+    /// # Errors
+    /// If the connection to the frontend failed.
+    async fn execute(&mut self, order: Order, context: String) -> anyhow::Result<bool> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let (planned, mut plan) = tokio::sync::oneshot::channel();
+        let dir = self.sessions.workdir(self.id);
+        let secrets = self
+            .q_and_a
+            .as_ref()
+            .map(|q_and_a| q_and_a.secrets().to_vec())
+            .unwrap_or_default();
+        let engine = Engine::new(
+            self.executor.ctx.clone(),
+            tx,
+            dir,
+            context,
+            confirmations,
+            resolutions,
+            vetoes,
+        )
+        .answered(secrets)
+        .planned(planned)
+        .scratch(self.sessions.scratch(self.id));
+        // answers without a pending question would approve the next risky command
+        let mut confirming = false;
+        // likewise, a resolution would resolve the next conflict
+        let mut resolving = false;
+
+        let run = engine.run(order);
+        tokio::pin!(run);
+
+        let success = loop {
+            tokio::select! {
+                // the execution can finish within the grace period
+                () = self.sessions.shutdown_requested(), if !self.shutdown_sent => {
+                    self.send_shutdown().await?;
+                }
+                Some(packet) = rx.recv() => {
+                    confirming |= matches!(packet.data, server::Server::ConfirmCommand { .. });
+                    resolving |= matches!(packet.data, server::Server::FileConflict { .. });
+                    self.comm.send(packet).await?;
+                }
+                res = &mut run => {
+                    // the engine may have sent packets right before finishing
+                    while let Ok(packet) = rx.try_recv() {
+                        self.comm.send(packet).await?;
+                    }
+
+                    break match res {
+                        Ok(success) => success,
+                        Err(e) => {
+                            error!("Execution failed: {e:#}");
+                            self.send_error(&e.context("Execution failed"), true).await?;
+                            false
                         }
-                        None => {
-                            self.comm
-                                .send(Packet::server(server::Question {
-                                    question: String::new(),
-                                    is_first_word: false,
-                                    is_last_word: true,
-                                }))
-                                .await?;
-                            break;
+                    };
+                }
+                packet = self.comm.recv() => {
+                    let packet = packet?;
+                    if !self.received(&packet) {
+                        continue;
+                    }
+                    match packet.data {
+                        Client::Cancel => {
+                            info!("Execution cancelled");
+                            break false;
+                        }
+                        Client::Ping => self.comm.send(Packet::server(server::Pong)).await?,
+                        // answered right away, executions take long
+                        Client::Latency => self.send_latency().await?,
+                        Client::ListSessions => self.send_sessions().await?,
+                        Client::Confirm { approved } if confirming => {
+                            confirming = false;
+                            confirm.send(approved)?;
+                        }
+                        Client::Confirm { .. } => self.reject(StateViolation::NothingToConfirm).await?,
+                        Client::ResolveConflict { resolution } if resolving => {
+                            resolving = false;
+                            resolve.send(resolution)?;
                         }
+                        Client::ResolveConflict { .. } => self.reject(StateViolation::NoConflict).await?,
+                        // applied before the next step
+                        Client::VetoSource { id } => veto.send(id)?,
+                        _ => match self.state.check(&packet.data) {
+                            Ok(()) => self.queued.push_back(packet),
+                            Err(violation) => self.reject(violation).await?,
+                        },
                     }
                 }
+            }
+        };
+
+        if let Ok(plan) = plan.try_recv() {
+            self.plan = Some(plan);
+        }
+        Ok(success)
+    }
+
+    /// Run `order` as the execution of the session, which can be reverted, and report how it
+    /// finished.
+    ///
+    /// # Errors
+    /// If the connection to the frontend failed.
+    async fn run_order(&mut self, order: Order, context: String) -> anyhow::Result<()> {
+        let revision = Revision::execution(self.sessions.workdir(self.id)).await;
+        self.revisions.push(revision);
+
+        self.state = State::Executing;
+        if matches!(order, Order::Plan(..)) {
+            self.send_status(Phase::Planning).await?;
+        }
+        let success = self.execute(order, context).await;
+        self.state = State::Interviewing;
+
+        let usage = self.sessions.usage(self.id).stats();
+        self.executor
+            .ctx
+            .audit(&audit::Event::Usage { usage: &usage });
+        self.comm
+            .send(Packet::server(server::Usage { usage }))
+            .await?;
+        self.comm
+            .send(Packet::server(server::ExecutionFinished {
+                success: success?,
+            }))
+            .await?;
+        self.send_status(Phase::Waiting).await
+    }
+
+    /// Replace the interview, which decides whether the session is idle or interviewing.
+    fn set_session(&mut self, q_and_a: Option<QAndA>) {
+        self.state = match q_and_a {
+            Some(..) => State::Interviewing,
+            None => State::Idle,
+        };
+        self.q_and_a = q_and_a;
+    }
+
+    /// Tell the frontend its packet was not processed.
+    async fn reject(&mut self, violation: StateViolation) -> anyhow::Result<()> {
+        info!("Rejected packet: {violation}");
+        self.comm
+            .send(Packet::server(server::Rejected {
+                reason: violation.to_string(),
+            }))
+            .await
+    }
 
-                info!("Question: {}", question);
-                q_and_a.add_question(question);
+    /// Handle `packet` with the [`Router`] of the process.
+    async fn process_packet(&mut self, packet: Packet<Client>) -> anyhow::Result<()> {
+        let router = self.router.clone();
+        let usage = self.sessions.usage(self.id);
+        let id = self.id;
+        let routed = async {
+            let routed = router.route(self, packet).await;
+            if let Some(spent) = usage.downshifted() {
+                self.send_downshift(spent).await?;
+            }
+            routed
+        };
+        usage::scope(usage.clone(), audit::scope(id, routed)).await
+    }
+
+    /// Tell the frontend that the low-stakes requests of the session are sent to a cheaper model
+    /// since it spent `spent` millionths of a dollar.
+    async fn send_downshift(&mut self, spent: u64) -> anyhow::Result<()> {
+        let cap = self.executor.ctx.cost_cap.unwrap_or_default();
+        let model = usage::cheap_model();
+        info!(spent, cap, "Downshifted to {model}");
+        self.executor.ctx.audit(&audit::Event::Downshifted {
+            micro_usd: spent,
+            cap_cents: cap,
+            model,
+        });
+
+        let message = format!(
+            "this session spent {} of its cap of {}, questions and summaries are written by \
+             {model} from now on",
+            usage::dollars(spent),
+            usage::dollars(cap * 10_000)
+        );
+        self.comm
+            .send(Packet::server(server::Notice { message }))
+            .await
+    }
+
+    /// Remember the preferences the user stated in this session and show them for review.
+    async fn learn(&mut self) -> anyhow::Result<()> {
+        let (Some(memory), Some(q_and_a)) = (&self.executor.ctx.memory, &self.q_and_a) else {
+            return Ok(());
+        };
+
+        let system = self.executor.ctx.prompts.get(Prompt::Learn);
+        let request = memory::learn_request(&system, &q_and_a.transcript());
+
+        let learned = match self.executor.ctx.chat(request).await {
+            Ok(learned) => memory::parse_learned(&learned),
+            Err(e) => {
+                error!("Failed to learn preferences: {e:#}");
+                return Ok(());
+            }
+        };
+
+        let mut changed = false;
+        for preference in learned {
+            if let Some(entry) = memory.add(&preference)? {
+                info!("Remembering: {}", entry.preference);
+                changed = true;
             }
         }
+
+        if changed {
+            self.send_memory().await?;
+        }
+
         Ok(())
     }
 
+    async fn send_memory(&mut self) -> anyhow::Result<()> {
+        let memory = self.executor.ctx.memory.as_ref();
+        self.comm
+            .send(Packet::server(server::Memory {
+                enabled: memory.is_some(),
+                entries: memory.map(Memory::entries).unwrap_or_default(),
+            }))
+            .await
+    }
+
+    /// Tell the frontend which format the answer to `question` must have, if any.
+    async fn send_answer_format(&mut self, question: &str) -> anyhow::Result<()> {
+        if answer_format::is_secret(question) {
+            self.comm.send(Packet::server(server::SecretAnswer)).await?;
+        }
+
+        let Some(validator) = answer_format::infer(question) else {
+            return Ok(());
+        };
+
+        self.comm
+            .send(Packet::server(server::AnswerFormat { validator }))
+            .await
+    }
+
+    async fn send_status(&mut self, phase: Phase) -> anyhow::Result<()> {
+        self.comm
+            .send(Packet::server(server::Status { phase }))
+            .await
+    }
+
+    async fn send_error(&mut self, error: &anyhow::Error, recoverable: bool) -> anyhow::Result<()> {
+        self.comm
+            .send(Packet::server(server::Error {
+                message: format!("{error:#}"),
+                recoverable,
+            }))
+            .await
+    }
+
+    async fn send_latency(&mut self) -> anyhow::Result<()> {
+        let mut latencies = self.metrics.stats(self.comm.tracer());
+        latencies.extend(self.executor.ctx.warmer.as_ref().and_then(Warmer::stats));
+        self.comm
+            .send(Packet::server(server::Latency { latencies }))
+            .await
+    }
+
+    async fn send_sessions(&mut self) -> anyhow::Result<()> {
+        let sessions = self
+            .sessions
+            .list()
+            .into_iter()
+            .map(|info| SessionSummary {
+                id: info.id,
+                attached: info.attached,
+            })
+            .collect();
+        self.comm
+            .send(Packet::server(server::Sessions { sessions }))
+            .await
+    }
+
+    async fn send_shutdown(&mut self) -> anyhow::Result<()> {
+        if self.shutdown_sent {
+            return Ok(());
+        }
+        self.shutdown_sent = true;
+
+        let grace_secs = self.sessions.grace().as_secs();
+        self.comm
+            .send(Packet::server(server::ShuttingDown { grace_secs }))
+            .await
+    }
+
+    /// Announce the session and what its working directory contains.
+    async fn send_session(&mut self) -> anyhow::Result<()> {
+        let key = self.sessions.key(self.id).unwrap_or_default();
+        self.comm
+            .send(Packet::server(server::SessionCreated { id: self.id, key }))
+            .await?;
+        self.send_workspace().await
+    }
+
+    /// Clone the repository at `url` into the working directory and announce what it contains.
+    /// A clone that failed is reported as a recoverable error.
+    async fn clone_repo(&mut self, url: &str, branch: Option<&str>) -> anyhow::Result<()> {
+        let dir = self.sessions.workdir(self.id);
+        info!("Cloning {url} into {}", dir.display());
+
+        let credentials = &self.executor.ctx.credentials;
+        let cloned = match remote::clone(&dir, url, branch, credentials).await {
+            Ok(cloned) => cloned,
+            Err(e) => {
+                error!("Failed to clone {url}: {e:#}");
+                return self.send_error(&e.context("Failed to clone"), true).await;
+            }
+        };
+        info!(branch = cloned.branch, head = cloned.head, "Cloned {url}");
+        self.executor.ctx.audit(&audit::Event::RepoCloned {
+            url,
+            branch: &cloned.branch,
+            head: &cloned.head,
+        });
+
+        self.comm
+            .send(Packet::server(server::Cloned {
+                url: url.to_string(),
+                branch: cloned.branch,
+                head: cloned.head,
+            }))
+            .await?;
+        self.send_workspace().await
+    }
+
+    /// Make the directory at `path` the working directory of the session and announce what it
+    /// contains. A path that cannot be used is reported as a recoverable error.
+    async fn set_workspace(&mut self, path: &str) -> anyhow::Result<()> {
+        let dir = match workspace::prepare(path) {
+            Ok(dir) => dir,
+            Err(e) => {
+                error!("Failed to set the workspace {path}: {e:#}");
+                return self
+                    .send_error(&e.context("Failed to set the workspace"), true)
+                    .await;
+            }
+        };
+        info!("Working in {}", dir.display());
+        self.executor.ctx.audit(&audit::Event::WorkspaceSet {
+            path: dir.display().to_string(),
+        });
+        self.sessions.set_workdir(self.id, dir.clone());
+
+        self.comm
+            .send(Packet::server(server::WorkspaceReady {
+                path: dir.display().to_string(),
+            }))
+            .await?;
+        self.send_workspace().await
+    }
+
+    /// The parts of the working directory relevant to `query`, for a prompt. The future holds
+    /// on to the index, not to the process, which is not `Sync`.
+    fn cite(&self, query: &str) -> impl Future<Output = String> + Send {
+        let index = self.index.clone();
+        let query = query.to_string();
+        async move {
+            match index {
+                Some(index) => index.cite(&query).await,
+                None => String::new(),
+            }
+        }
+    }
+
+    /// Analyze what the working directory contains and send it to the frontend. Its files are
+    /// indexed in the background from now on, only embedded if indexing is enabled.
+    async fn send_workspace(&mut self) -> anyhow::Result<()> {
+        let dir = self.sessions.workdir(self.id);
+
+        let index = Indexer::new(&dir, self.executor.ctx.embeddings.clone());
+        index.watch(INDEX_INTERVAL);
+        self.index = Some(index);
+
+        self.workspace = tokio::task::spawn_blocking(move || stats::analyze(&dir)).await?;
+
+        self.comm.send(Packet::server(self.workspace.info())).await
+    }
+
     pub async fn run(mut self) -> anyhow::Result<()> {
+        // the first question of the session is likely soon, it should not wait for a handshake
+        if let Some(warmer) = &self.executor.ctx.warmer {
+            warmer.prewarm();
+        }
+
+        self.send_session().await?;
+
+        let res = self.process_packets().await;
+
+        // the connection may be what failed, then the frontend notices on its own
+        if let Err(e) = &res {
+            let _ = self.send_error(e, false).await;
+        }
+
+        // keep the state around so the frontend can resume after reconnecting
+        self.sessions.detach(self.id, self.q_and_a.take());
+
+        res
+    }
+
+    async fn process_packets(&mut self) -> anyhow::Result<()> {
         loop {
-            let packet = self.comm.recv().await?;
+            let packet = match self.queued.pop_front() {
+                Some(packet) => packet,
+                None => {
+                    tokio::select! {
+                        () = self.sessions.shutdown_requested() => return self.send_shutdown().await,
+                        packet = self.comm.recv() => {
+                            let packet = packet?;
+                            if !self.received(&packet) {
+                                continue;
+                            }
+                            packet
+                        }
+                        notice = self.notices.recv() => {
+                            // missed notices are not worth disconnecting for
+                            if let Ok(message) = notice {
+                                self.comm.send(Packet::server(server::Notice { message })).await?;
+                            }
+                            continue;
+                        }
+                    }
+                }
+            };
             self.process_packet(packet).await?;
         }
     }
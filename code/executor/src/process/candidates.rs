@@ -0,0 +1,143 @@
+//! Pick the most informative of several candidates for the next question.
+//!
+//! With `--question-candidates` above 1 the model proposes several next questions in one request.
+//! The candidate least similar to the questions asked so far most likely covers something new, so
+//! it is asked. The others are sent to the frontend as [`server::QuestionAlternatives`].
+//!
+//! [`server::QuestionAlternatives`]: protocol::server::Server::QuestionAlternatives
+
+use std::collections::HashSet;
+
+use tracing::error;
+
+use crate::indexer::Embeddings;
+
+/// `candidates` from the most to the least informative given the questions `asked` so far,
+/// without empty and duplicate candidates.
+///
+/// Candidates are compared to the questions by their embeddings if there are `embeddings`,
+/// otherwise by the words they share.
+pub async fn rank(
+    embeddings: Option<&Embeddings>,
+    asked: &[String],
+    candidates: Vec<String>,
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let candidates: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| candidate.trim().to_string())
+        .filter(|candidate| !candidate.is_empty() && seen.insert(candidate.to_lowercase()))
+        .collect();
+
+    if asked.is_empty() || candidates.len() < 2 {
+        return candidates;
+    }
+
+    let scores = match embeddings {
+        Some(embeddings) => similarities(embeddings, asked, &candidates)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to embed the question candidates: {e:#}");
+                overlaps(asked, &candidates)
+            }),
+        None => overlaps(asked, &candidates),
+    };
+
+    // the sort is stable, equally informative candidates keep the order of the model
+    let mut scored: Vec<_> = scores.into_iter().zip(candidates).collect();
+    scored.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// The highest cosine similarity of every candidate to a question asked
+async fn similarities(
+    embeddings: &Embeddings,
+    asked: &[String],
+    candidates: &[String],
+) -> anyhow::Result<Vec<f32>> {
+    let inputs: Vec<_> = candidates.iter().chain(asked).cloned().collect();
+    let vectors = embeddings.embed(&inputs).await?;
+    let (candidates, asked) = vectors.split_at(candidates.len());
+
+    Ok(candidates
+        .iter()
+        .map(|candidate| {
+            asked
+                .iter()
+                .map(|question| cosine(candidate, question))
+                .fold(f32::MIN, f32::max)
+        })
+        .collect())
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot = |a: &[f32], b: &[f32]| -> f32 { a.iter().zip(b).map(|(a, b)| a * b).sum() };
+    let lengths = (dot(a, a) * dot(b, b)).sqrt();
+    if lengths > 0.0 {
+        dot(a, b) / lengths
+    } else {
+        0.0
+    }
+}
+
+/// The highest share of words every candidate has in common with a question asked
+fn overlaps(asked: &[String], candidates: &[String]) -> Vec<f32> {
+    let asked: Vec<_> = asked.iter().map(|question| words(question)).collect();
+
+    candidates
+        .iter()
+        .map(|candidate| {
+            let candidate = words(candidate);
+            asked
+                .iter()
+                .map(|question| {
+                    let union = candidate.union(question).count();
+                    if union == 0 {
+                        return 0.0;
+                    }
+                    count(candidate.intersection(question).count()) / count(union)
+                })
+                .fold(f32::MIN, f32::max)
+        })
+        .collect()
+}
+
+/// `words` as a float, saturating far beyond the words of a question
+fn count(words: usize) -> f32 {
+    f32::from(u16::try_from(words).unwrap_or(u16::MAX))
+}
+
+/// The lowercase words of `text`
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rank;
+
+    #[tokio::test]
+    async fn test_rank() {
+        let candidates = vec![
+            " Which language should the calculator use?".to_string(),
+            "Should it support scientific functions?".to_string(),
+            "which language should the calculator use?".to_string(),
+            String::new(),
+        ];
+
+        // without questions asked, the order of the model is kept
+        assert_eq!(rank(None, &[], candidates.clone()).await, [
+            "Which language should the calculator use?",
+            "Should it support scientific functions?"
+        ]);
+
+        let asked = ["Which language should the calculator be written in?".to_string()];
+        assert_eq!(rank(None, &asked, candidates).await, [
+            "Should it support scientific functions?",
+            "Which language should the calculator use?"
+        ]);
+    }
+}
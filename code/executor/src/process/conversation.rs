@@ -0,0 +1,109 @@
+//! Keep the questions and answers of long sessions within the budget of a prompt.
+//!
+//! Every question and answer is kept, but once they grow past [`BUDGET`] tokens the older ones are
+//! summarized with a cheaper model. Prompts then contain the summary followed by the recent
+//! questions and answers as they were.
+
+use tokio_openai::{ChatModel, ChatRequest};
+use utils::discretize::CHARS_PER_TOKEN;
+
+use crate::{prompts::Prompt, Ctx};
+
+/// Tokens the questions and answers may take in a prompt before older ones are summarized
+const BUDGET: usize = 1_500;
+
+/// How many of the latest turns are never summarized, the model needs them as they are
+const KEEP_TURNS: usize = 2;
+
+/// The default template of [`Prompt::Summary`]
+pub const SUMMARY_PROMPT: &str = "Summarize the conversation between an assistant and a user \
+                                  about an instruction in a few sentences. Keep every requirement \
+                                  and decision of the user, such as names, versions and choices.";
+
+/// The summary of the turns of a conversation that did not fit in a prompt anymore.
+///
+/// The turns themselves are kept by the caller, e.g. the questions and answers of a
+/// [`QAndA`](super::question::QAndA).
+#[derive(Default)]
+pub struct Conversation {
+    summary: String,
+    /// how many turns the summary covers
+    summarized: usize,
+}
+
+/// Rough number of tokens of `text`
+fn tokens(text: &str) -> usize {
+    text.len() / CHARS_PER_TOKEN
+}
+
+impl Conversation {
+    /// The summary of the older `turns` followed by the recent ones.
+    pub fn render(&self, turns: &[String]) -> String {
+        let recent = turns.get(self.summarized..).unwrap_or_default().concat();
+        if self.summary.is_empty() {
+            return recent;
+        }
+
+        format!(
+            "Summary of the earlier conversation: {}\n\n{recent}",
+            self.summary
+        )
+    }
+
+    /// Whether `turns` do not fit in a prompt and some of them can still be summarized
+    pub fn needs_summary(&self, turns: &[String]) -> bool {
+        tokens(&self.render(turns)) > BUDGET && turns.len() > self.summarized + KEEP_TURNS
+    }
+
+    /// Summarize every turn but the latest [`KEEP_TURNS`], together with the summary so far.
+    ///
+    /// # Errors
+    /// If the model could not be reached, the conversation stays as it is.
+    pub async fn summarize(&mut self, ctx: &Ctx, turns: &[String]) -> anyhow::Result<()> {
+        let end = turns.len().saturating_sub(KEEP_TURNS);
+        if end <= self.summarized {
+            return Ok(());
+        }
+
+        let older = Self {
+            summary: self.summary.clone(),
+            summarized: self.summarized,
+        }
+        .render(&turns[..end]);
+        let request = ChatRequest::new()
+            .model(ChatModel::Turbo)
+            .sys_msg(ctx.prompts.get(Prompt::Summary))
+            .user_msg(older);
+
+        self.summary = ctx.chat(request).await?.trim().to_string();
+        self.summarized = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conversation, BUDGET, CHARS_PER_TOKEN};
+
+    #[test]
+    fn test_render() {
+        let turn = |n: usize| format!("Q: {n}?\nA: {}\n", "x".repeat(BUDGET * CHARS_PER_TOKEN / 4));
+        let turns: Vec<_> = (0..3).map(turn).collect();
+
+        let mut conversation = Conversation::default();
+        assert_eq!(conversation.render(&turns), turns.concat());
+        assert!(!conversation.needs_summary(&turns));
+
+        // the latest turns are kept even if they alone exceed the budget
+        let turns: Vec<_> = (0..6).map(turn).collect();
+        assert!(conversation.needs_summary(&turns));
+
+        conversation.summary = "The user wants x.".to_string();
+        conversation.summarized = 4;
+        let rendered = conversation.render(&turns);
+        assert!(rendered.starts_with("Summary of the earlier conversation: The user wants x.\n\n"));
+        assert!(rendered.ends_with(&turns[4..].concat()));
+        assert!(!rendered.contains("Q: 3?"));
+        assert!(!conversation.needs_summary(&turns));
+    }
+}
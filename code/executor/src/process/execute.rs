@@ -0,0 +1,1290 @@
+//! Run a [`Plan`] step by step, or only some of its steps, see [`Order`].
+//!
+//! Progress is reported with [`server::StepStarted`], [`server::StepOutput`] and
+//! [`server::StepFinished`] packets. The output of every step is added to the context the model
+//! sees when generating code in later steps. Risky shell commands wait for the frontend to approve
+//! a [`server::ConfirmCommand`]. Crates that generated code uses are added to its package.
+//!
+//! With [`Settings::codegen_iterations`](crate::Settings::codegen_iterations), a generated Rust
+//! file is checked with `cargo check` and generated again with its errors until it compiles,
+//! reported with [`server::CompileChecked`] packets.
+//!
+//! A file the user changed while it was generated is merged with those changes, see [`merge`].
+//! Lines both changed are marked in the file and wait for the frontend to answer the
+//! [`server::FileConflict`].
+//!
+//! The documentation of the crates a plan reads is fetched at once before its steps run, see
+//! [`prefetch`].
+//!
+//! External material a step read is previewed with a [`server::SourcePreview`], see [`sources`].
+//! A source the frontend vetoes is dropped from the context of the steps after it.
+//!
+//! The hooks of the executor can veto the plan and every step, see [`hooks`].
+//!
+//! Every step gets a scratch directory for temporary files, see [`scratch`].
+//!
+//! What the steps did is kept in a journal next to the `audit` tracing events. Once every step
+//! succeeded, a commit of the changes is proposed from it, see [`commit`]. A commit in a
+//! repository the executor cloned is then proposed to be pushed, see [`remote`].
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Context};
+use futures::{stream, StreamExt};
+use protocol::{server, Packet, Phase, Resolution, Risk, ServerPacket};
+use tokio::sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+use tokio_openai::{ChatRequest, Msg};
+use tracing::{error, info};
+
+use crate::{
+    audit,
+    command::{cargo, codegen, crate_search, Cmd, Command, CommandEvent, CommandStream},
+    commit, dependencies, diagnosis, file,
+    guardrails::{self, Flaw},
+    hooks::{Event, Hooks},
+    merge::{self, Merge},
+    plan::{Plan, Selection, Step},
+    prefetch::{self, Prefetched},
+    prompts::Prompt,
+    remote,
+    repair::{self, Outcome},
+    scratch,
+    secrets::Secrets,
+    sources,
+    usage::{self, Purpose},
+    workspace, Ctx,
+};
+
+/// How often the model can try to write a valid plan
+const MAX_PLAN_ATTEMPTS: usize = 3;
+
+/// How often a generated file is merged again if the file keeps changing on disk
+const MAX_MERGE_ATTEMPTS: usize = 3;
+
+/// What an [`Engine`] runs
+pub enum Order {
+    /// write a plan with the request and run every step of it
+    Plan(ChatRequest),
+    /// run only the selected steps of a plan that was approved before
+    Steps(Plan, Selection),
+}
+
+pub struct Engine {
+    ctx: Ctx,
+    tx: UnboundedSender<ServerPacket>,
+    /// the working directory of the session
+    dir: PathBuf,
+    /// what happened so far, starting with the instruction
+    context: String,
+    /// answers to [`server::ConfirmCommand`]s
+    confirmations: UnboundedReceiver<bool>,
+    /// answers to [`server::FileConflict`]s
+    resolutions: UnboundedReceiver<Resolution>,
+    /// the ids of [`server::SourcePreview`]s the frontend vetoed
+    vetoes: UnboundedReceiver<u64>,
+    /// the link and the context of every source by id, starting at 1, `None` once vetoed
+    sources: Vec<Option<(String, String)>>,
+    /// the secrets steps may use, loaded from the working directory when the plan runs
+    secrets: Secrets,
+    /// the answers to secret questions, by the variable commands read them from
+    answers: Vec<(String, String)>,
+    /// the hooks of the executor, see [`Settings`](crate::Settings)
+    hooks: Hooks,
+    /// what the steps did, one entry per audited event
+    journal: Vec<String>,
+    /// the documentation fetched before the steps reading it run
+    prefetched: Prefetched,
+    /// gets the plan once it is approved
+    planned: Option<oneshot::Sender<Plan>>,
+    /// where the scratch directories of the steps are created, `None` to run steps without one
+    scratch: Option<PathBuf>,
+}
+
+impl Engine {
+    pub fn new(
+        ctx: Ctx,
+        tx: UnboundedSender<ServerPacket>,
+        dir: impl Into<PathBuf>,
+        context: impl Into<String>,
+        confirmations: UnboundedReceiver<bool>,
+        resolutions: UnboundedReceiver<Resolution>,
+        vetoes: UnboundedReceiver<u64>,
+    ) -> Self {
+        Self {
+            hooks: ctx.hooks.clone(),
+            ctx,
+            tx,
+            dir: dir.into(),
+            context: context.into(),
+            confirmations,
+            resolutions,
+            vetoes,
+            sources: Vec::new(),
+            secrets: Secrets::default(),
+            answers: Vec::new(),
+            journal: Vec::new(),
+            prefetched: Prefetched::default(),
+            planned: None,
+            scratch: None,
+        }
+    }
+
+    /// Pass the answers to secret questions to the commands, as the variables they are named
+    /// after.
+    #[must_use]
+    pub fn answered(mut self, answers: Vec<(String, String)>) -> Self {
+        self.answers = answers;
+        self
+    }
+
+    /// Send the plan to `planned` once it is approved, before its steps run.
+    #[must_use]
+    pub fn planned(mut self, planned: oneshot::Sender<Plan>) -> Self {
+        self.planned = Some(planned);
+        self
+    }
+
+    /// Give every step a scratch directory in `root`, see [`scratch`].
+    #[must_use]
+    pub fn scratch(mut self, root: impl Into<PathBuf>) -> Self {
+        self.scratch = Some(root.into());
+        self
+    }
+
+    /// Generate a plan and run it, or run the steps of `order`, then propose a commit of what they
+    /// changed and pushing the commit.
+    ///
+    /// Returns whether all steps succeeded.
+    ///
+    /// # Errors
+    /// - The plan could not be generated
+    /// - The plan was still invalid after [`MAX_PLAN_ATTEMPTS`]
+    /// - The secrets manifest of the working directory is invalid
+    /// - A hook vetoed the plan
+    /// - Sending packets to the frontend failed
+    pub async fn run(mut self, order: Order) -> anyhow::Result<bool> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create the working directory")?;
+        self.secrets = Secrets::load(&self.dir)?;
+        for (name, value) in &self.answers {
+            self.secrets.add(name, value);
+        }
+
+        let (plan, selection) = match order {
+            Order::Plan(request) => {
+                let plan = usage::purpose(Purpose::Plan, self.gen_plan(request)).await?;
+                let selection = Selection::all(&plan);
+                (plan, selection)
+            }
+            Order::Steps(plan, selection) => (plan, selection),
+        };
+        self.hooks
+            .check(
+                &self.ctx,
+                &self.dir,
+                &Event::plan_approved(&plan.only(&selection)),
+            )
+            .await
+            .context("The plan was not approved")?;
+        if let Some(planned) = self.planned.take() {
+            let _ = planned.send(plan.clone());
+        }
+        self.tx.send(Packet::server(server::Status {
+            phase: Phase::Executing,
+        }))?;
+        let success = self.run_plan(&plan, &selection).await?;
+        self.hooks
+            .notify(&self.ctx, &self.dir, &Event::AfterExecution { success })
+            .await;
+        if success {
+            if let Some(message) = self.propose_commit().await? {
+                self.propose_push(&message).await?;
+            }
+        }
+        Ok(success)
+    }
+
+    /// Ask the model for a plan, telling it what was wrong until it writes a valid one. A plan
+    /// the [`guardrails`] find degenerate is generated again as well, but used after
+    /// [`MAX_PLAN_ATTEMPTS`]. Of a plan that is still invalid then, the steps before the first
+    /// invalid one are used, see [`repair::lenient`].
+    async fn gen_plan(&self, request: ChatRequest) -> anyhow::Result<Plan> {
+        let mut request = self.ctx.personalize(request);
+        let mut attempt = 1;
+        let mut outcome = Outcome::Valid;
+
+        loop {
+            let text = self.stream_plan(request.clone()).await?;
+            info!("Plan: {text}");
+
+            match Plan::parse(&text) {
+                Ok(plan) if attempt >= MAX_PLAN_ATTEMPTS => {
+                    self.ctx.repairs.record(Prompt::Plan, outcome);
+                    return Ok(plan);
+                }
+                Ok(plan) => {
+                    let Some(flaw) = self.flaw(&plan, &text).await else {
+                        self.ctx.repairs.record(Prompt::Plan, outcome);
+                        return Ok(plan);
+                    };
+                    info!(target: "guardrails", kind = "plan", %flaw, plan = text, "Rejected output");
+                    request.messages.push(Msg::assistant(text));
+                    request.messages.push(Msg::user(format!(
+                        "The plan is flawed: {flaw}. Answer with only the corrected JSON array."
+                    )));
+                    attempt += 1;
+                }
+                Err(e) if attempt < MAX_PLAN_ATTEMPTS => {
+                    info!(target: "repairs", prompt = "plan", output = text, "Repairing: {e:#}");
+                    request.messages.push(Msg::assistant(text));
+                    request.messages.push(repair::correction(&e, "JSON array"));
+                    outcome = Outcome::Repaired;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let Some(plan) = repair::lenient(&text, Plan::parse) else {
+                        self.ctx.repairs.record(Prompt::Plan, Outcome::Failed);
+                        return Err(e).context("The model did not write a valid plan");
+                    };
+                    self.ctx.repairs.record(Prompt::Plan, Outcome::Lenient);
+                    self.tx.send(Packet::server(server::Notice {
+                        message: format!(
+                            "only the first {} steps of the plan are valid, the rest was dropped: \
+                             {e:#}",
+                            plan.steps.len()
+                        ),
+                    }))?;
+                    return Ok(plan);
+                }
+            }
+        }
+    }
+
+    /// What is wrong with `plan`, written as `text`, if anything. If the model judging it could
+    /// not be reached, only the heuristics count.
+    async fn flaw(&self, plan: &Plan, text: &str) -> Option<Flaw> {
+        if let Some(flaw) = guardrails::check_plan(plan) {
+            return Some(flaw);
+        }
+
+        guardrails::judge_plan(&self.ctx, &self.context, text)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to judge the plan: {e:#}");
+                None
+            })
+    }
+
+    /// Generate a plan with `request`, forwarding it to the frontend as it is written.
+    async fn stream_plan(&self, request: ChatRequest) -> anyhow::Result<String> {
+        let mut chunks = self.ctx.stream_chat(request).await?;
+        let mut text = String::new();
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            text.push_str(&chunk);
+            self.tx
+                .send(Packet::server(server::PlanChunk { content: chunk }))?;
+        }
+
+        Ok(text)
+    }
+
+    /// Run the selected steps of `plan` in order, stopping at the first step that fails.
+    pub async fn run_plan(&mut self, plan: &Plan, selection: &Selection) -> anyhow::Result<bool> {
+        // the steps reading the documentation may still be vetoed
+        if !self.hooks.vets_commands() {
+            let selected = plan.only(selection);
+            self.prefetched = prefetch::prefetch(&self.ctx, &self.dir, &selected, &self.tx).await?;
+        }
+
+        for &index in &selection.steps {
+            let step = &plan.steps[index];
+            self.apply_vetoes();
+            self.tx.send(Packet::server(server::StepStarted {
+                index,
+                title: step.title.clone(),
+            }))?;
+
+            let mut output = Vec::new();
+            let scratch = self
+                .scratch
+                .as_ref()
+                .map(|root| scratch::create(root, index))
+                .transpose()?;
+            let refused = self.refusal(index, step).await?;
+            let result = match &refused {
+                None => {
+                    let env = self.secrets.env(step);
+                    if env.names().next().is_some() {
+                        let names: Vec<_> = env.names().collect();
+                        info!(step = index, ?names, "Passing secrets");
+                        self.ctx
+                            .audit(&audit::Event::SecretsPassed { step: index, names });
+                    }
+                    let dir = scratch.as_ref().map(|dir| dir.path().to_path_buf());
+                    let run = scratch::scope(dir, self.run_step(index, step, &mut output));
+                    usage::purpose(Purpose::Step(index), env.scope(run)).await
+                }
+                Some(reason) => Err(anyhow!("{reason}")),
+            };
+
+            if let Err(e) = &result {
+                self.output(index, format!("{e:#}"), &mut output)?;
+            }
+
+            let success = result.is_ok();
+            self.ctx.audit(&audit::Event::StepFinished {
+                step: index,
+                succeeded: success,
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            });
+            // dropping the directory deletes it
+            if let Some(dir) = scratch.filter(|_| !success && self.ctx.keep_failed_scratch) {
+                let dir = scratch::keep(dir);
+                info!(step = index, dir = %dir.display(), "Kept scratch directory");
+                self.ctx.audit(&audit::Event::ScratchKept {
+                    step: index,
+                    dir: dir.display().to_string(),
+                });
+                let kept = format!("kept the scratch directory {}", dir.display());
+                self.output(index, kept, &mut output)?;
+            }
+            let output = output.join("\n");
+
+            info!("Step {index} `{}` success: {success}", step.title);
+            self.journal.push(format!(
+                "Step `{}` ({:?}) {}",
+                step.title,
+                step.command.cmd,
+                if success { "succeeded" } else { "failed" }
+            ));
+
+            let context = format!(
+                "\nStep `{}` ({:?}) {}:\n{output}\n",
+                step.title,
+                step.command.cmd,
+                if success { "succeeded" } else { "failed" }
+            );
+            self.context.push_str(&context);
+            if success {
+                self.preview(step, &output, context)?;
+            }
+
+            // there is nothing to diagnose about a declined command
+            if !success && refused.is_none() {
+                usage::purpose(Purpose::Step(index), self.diagnose(index, step, &output)).await?;
+            }
+
+            self.tx
+                .send(Packet::server(server::StepFinished { index, success }))?;
+
+            if !success {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Show the frontend the source `step` read, if it read one. `context` is what it added to
+    /// the context of the next steps.
+    fn preview(&mut self, step: &Step, output: &str, context: String) -> anyhow::Result<()> {
+        let id = self.sources.len() as u64 + 1;
+        let Some(preview) = sources::preview(id, step.command.cmd, &step.command.input, output)
+        else {
+            return Ok(());
+        };
+
+        self.sources.push(Some((preview.url.clone(), context)));
+        self.tx.send(Packet::server(preview))?;
+        Ok(())
+    }
+
+    /// Drop the sources the frontend vetoed from the context of the next steps.
+    fn apply_vetoes(&mut self) {
+        while let Ok(id) = self.vetoes.try_recv() {
+            let vetoed = usize::try_from(id)
+                .ok()
+                .and_then(|id| self.sources.get_mut(id.checked_sub(1)?))
+                .and_then(Option::take);
+            let Some((url, context)) = vetoed else {
+                continue;
+            };
+
+            info!(source = id, url, "Vetoed source");
+            self.ctx.audit(&audit::Event::SourceVetoed {
+                source: id,
+                url: &url,
+            });
+            self.context = self.context.replace(
+                &context,
+                &format!("\nThe user vetoed {url} as irrelevant, do not rely on it.\n"),
+            );
+            self.journal.push(format!("Vetoed {url}"));
+        }
+    }
+
+    /// Why `step` may not run: the frontend did not approve it or a hook vetoed it.
+    ///
+    /// # Errors
+    /// If the frontend stopped the execution while the command waited for approval.
+    async fn refusal(&mut self, index: usize, step: &Step) -> anyhow::Result<Option<String>> {
+        if !self.confirm(step).await? {
+            return Ok(Some("the command was not approved".to_string()));
+        }
+
+        let event = Event::before_command(index, step);
+        Ok(self
+            .hooks
+            .check(&self.ctx, &self.dir, &event)
+            .await
+            .err()
+            .map(|e| format!("{e:#}")))
+    }
+
+    /// Ask the frontend to approve `step` if the [`Policy`](crate::policy::Policy) considers it
+    /// risky.
+    ///
+    /// # Errors
+    /// If the frontend stopped the execution while the command waited for approval.
+    async fn confirm(&mut self, step: &Step) -> anyhow::Result<bool> {
+        if !matches!(step.command.cmd, Cmd::Bash | Cmd::Zsh) {
+            return Ok(true);
+        }
+
+        let command = &step.command.input;
+        let Some(risk) = self.ctx.policy.read().classify(command) else {
+            return Ok(true);
+        };
+
+        self.tx.send(Packet::server(server::ConfirmCommand {
+            command: command.clone(),
+            risk,
+        }))?;
+
+        let approved = self
+            .confirmations
+            .recv()
+            .await
+            .context("The execution stopped before the command was confirmed")?;
+
+        info!(%risk, approved, "Confirmed command: {command}");
+        self.ctx.audit(&audit::Event::CommandConfirmed {
+            command,
+            risk,
+            approved,
+        });
+        let verdict = if approved { "Approved" } else { "Declined" };
+        self.journal
+            .push(format!("{verdict} {risk} command: {command}"));
+
+        Ok(approved)
+    }
+
+    /// Classify why `step` failed and send the diagnosis to the frontend.
+    ///
+    /// The classification is recorded under the `audit` tracing target. A diagnosis that could
+    /// not be generated is only logged, it does not stop the execution.
+    async fn diagnose(&mut self, index: usize, step: &Step, output: &str) -> anyhow::Result<()> {
+        let system = self.ctx.prompts.get(Prompt::Diagnosis);
+        let request = diagnosis::request(&system, step, output);
+        let request = self.ctx.personalize(request);
+
+        let diagnosis = repair::chat(&self.ctx, Prompt::Diagnosis, request, diagnosis::parse).await;
+
+        let diagnosis = match diagnosis {
+            Ok(diagnosis) => diagnosis,
+            Err(e) => {
+                error!("Failed to diagnose step {index}: {e:#}");
+                return Ok(());
+            }
+        };
+
+        info!(
+            step = index,
+            title = step.title,
+            kind = %diagnosis.kind,
+            remediation = diagnosis.remediation,
+            "Step failed: {}",
+            diagnosis.explanation
+        );
+        self.ctx.audit(&audit::Event::StepDiagnosed {
+            step: index,
+            kind: diagnosis.kind,
+            explanation: &diagnosis.explanation,
+            remediation: &diagnosis.remediation,
+        });
+
+        self.context.push_str(&format!(
+            "Diagnosis ({}): {} Fix: {}\n",
+            diagnosis.kind, diagnosis.explanation, diagnosis.remediation
+        ));
+
+        self.tx.send(Packet::server(server::StepDiagnosed {
+            index,
+            kind: diagnosis.kind,
+            explanation: diagnosis.explanation,
+            remediation: diagnosis.remediation,
+        }))?;
+
+        Ok(())
+    }
+
+    /// Add the crates the generated Rust file at `path` uses but its package does not depend on,
+    /// at their latest version on crates.io, and build the package again.
+    ///
+    /// Every added dependency is recorded under the `audit` tracing target. A build that still
+    /// fails is only reported, the package may be completed by later steps.
+    async fn add_dependencies(
+        &mut self,
+        index: usize,
+        path: &Path,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        if path.extension() != Some(OsStr::new("rs")) {
+            return Ok(());
+        }
+
+        // `path` is resolved, so it is inside the canonical working directory
+        let root = self.dir.canonicalize()?;
+        let Some(manifest) = dependencies::manifest(&root, path) else {
+            return Ok(());
+        };
+
+        let source = tokio::fs::read_to_string(path).await?;
+        let content = tokio::fs::read_to_string(&manifest).await?;
+        let missing = dependencies::missing(&source, &content)?;
+
+        let manifest = manifest.strip_prefix(&root)?.display().to_string();
+        let mut added = false;
+
+        for name in missing {
+            let krate = match crate_search::lookup(&self.ctx, &name).await {
+                Ok(Some(krate)) => krate,
+                Ok(None) => {
+                    self.output(index, format!("! {name} is not on crates.io"), output)?;
+                    continue;
+                }
+                Err(e) => {
+                    self.output(index, format!("! failed to look up {name}: {e:#}"), output)?;
+                    continue;
+                }
+            };
+
+            let version = &krate.latest_version;
+            let input = format!("add {}@{version} --manifest-path {manifest}", krate.name);
+            self.run_command(index, Cmd::Cargo, &input, output).await?;
+
+            info!(
+                step = index,
+                krate = krate.name,
+                version,
+                manifest,
+                "Added dependency"
+            );
+            self.ctx.audit(&audit::Event::DependencyAdded {
+                step: index,
+                krate: &krate.name,
+                version,
+                manifest: &manifest,
+            });
+            self.journal
+                .push(format!("Added {}@{version} to {manifest}", krate.name));
+            added = true;
+        }
+
+        if added {
+            let input = format!("build --manifest-path {manifest}");
+            if let Err(e) = self.run_command(index, Cmd::Cargo, &input, output).await {
+                self.output(index, format!("! the build still fails: {e:#}"), output)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Propose a commit of the changes in the working directory, if it is a repository, and
+    /// create it once the frontend approves.
+    ///
+    /// A message that could not be generated or a commit that failed is only reported, the plan
+    /// itself succeeded. Returns the message of the commit that was created.
+    ///
+    /// # Errors
+    /// If the frontend stopped the execution while the commit waited for approval.
+    async fn propose_commit(&mut self) -> anyhow::Result<Option<commit::Message>> {
+        let message = match self.gen_commit().await {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                error!("Failed to write a commit message: {e:#}");
+                return Ok(None);
+            }
+        };
+
+        self.tx.send(Packet::server(server::ConfirmCommand {
+            command: message.command(),
+            risk: Risk::Commit,
+        }))?;
+
+        let approved = self
+            .confirmations
+            .recv()
+            .await
+            .context("The execution stopped before the commit was confirmed")?;
+
+        info!(approved, "Confirmed commit: {}", message.subject);
+        self.ctx.audit(&audit::Event::CommitConfirmed {
+            subject: &message.subject,
+            approved,
+        });
+        if !approved {
+            return Ok(None);
+        }
+
+        let (notice, committed) = match commit::commit(&self.dir, &message).await {
+            Ok(()) => (format!("Committed: {}", message.subject), Some(message)),
+            Err(e) => {
+                error!("Failed to commit: {e:#}");
+                (format!("Failed to commit: {e:#}"), None)
+            }
+        };
+        self.tx
+            .send(Packet::server(server::Notice { message: notice }))?;
+
+        Ok(committed)
+    }
+
+    /// Propose pushing the commit with `message` to a new branch, if the executor cloned the
+    /// working directory, and push it once the frontend approves.
+    ///
+    /// A push that failed is only reported, like a commit.
+    ///
+    /// # Errors
+    /// If the frontend stopped the execution while the push waited for approval.
+    async fn propose_push(&mut self, message: &commit::Message) -> anyhow::Result<()> {
+        let Some(base) = remote::base(&self.dir).await else {
+            return Ok(());
+        };
+        let branch = remote::branch(&message.subject);
+
+        self.tx.send(Packet::server(server::ConfirmCommand {
+            command: remote::push_command(&branch),
+            risk: Risk::Network,
+        }))?;
+
+        let approved = self
+            .confirmations
+            .recv()
+            .await
+            .context("The execution stopped before the push was confirmed")?;
+
+        info!(approved, "Confirmed push: {branch}");
+        self.ctx.audit(&audit::Event::PushConfirmed {
+            branch: &branch,
+            approved,
+        });
+        if !approved {
+            return Ok(());
+        }
+
+        let notice = match remote::push(&self.dir, &branch, &base, &self.ctx.credentials).await {
+            Ok(Some(pull_request)) => {
+                format!("Pushed {branch}, open a pull request at {pull_request}")
+            }
+            Ok(None) => format!("Pushed {branch}"),
+            Err(e) => {
+                error!("Failed to push: {e:#}");
+                format!("Failed to push: {e:#}")
+            }
+        };
+        self.tx
+            .send(Packet::server(server::Notice { message: notice }))?;
+
+        Ok(())
+    }
+
+    /// The message for a commit of the changes in the working directory, `None` if it is not a
+    /// repository or nothing changed.
+    async fn gen_commit(&self) -> anyhow::Result<Option<commit::Message>> {
+        let Some(diff) = commit::changes(&self.dir).await? else {
+            return Ok(None);
+        };
+        let diff = self.secrets.redact(diff);
+
+        let system = self.ctx.prompts.get(Prompt::Commit);
+        let request = commit::request(&system, &self.journal, &diff);
+        let request = self.ctx.personalize(request);
+
+        repair::chat(&self.ctx, Prompt::Commit, request, commit::parse)
+            .await
+            .map(Some)
+    }
+
+    /// Send a line of output of step `index` to the frontend, without the values of secrets.
+    fn output(&self, index: usize, line: String, output: &mut Vec<String>) -> anyhow::Result<()> {
+        let line = self.secrets.redact(line);
+        self.tx.send(Packet::server(server::StepOutput {
+            index,
+            output: line.clone(),
+        }))?;
+        output.push(line);
+        Ok(())
+    }
+
+    /// Run `step`, streaming its output to the frontend as it is produced and collecting it in
+    /// `output`.
+    async fn run_step(
+        &mut self,
+        index: usize,
+        step: &Step,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        // generated files are streamed to the frontend while they are written
+        if step.command.cmd == Cmd::CodeGen {
+            let (path, description) = codegen::split_input(&step.command.input)?;
+            let system = self.ctx.prompts.render(Prompt::CodeGen, &[("path", path)]);
+            let request = codegen::request(&system, path, description, &self.context);
+            let request = self.ctx.personalize(request);
+
+            let resolved = workspace::resolve(&self.dir, path)?;
+            self.generate(index, path, &resolved, request.clone(), output)
+                .await?;
+            self.add_dependencies(index, &resolved, output).await?;
+            return self.compile(index, path, &resolved, request, output).await;
+        }
+
+        self.run_command(index, step.command.cmd, &step.command.input, output)
+            .await
+    }
+
+    /// Generate the file at `path` with `request`, streaming it to the frontend while it is
+    /// written. Changes made to the file meanwhile are merged, see [`Self::reconcile`].
+    async fn generate(
+        &mut self,
+        index: usize,
+        path: &str,
+        resolved: &Path,
+        request: ChatRequest,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        // what the file has to still contain once it is generated
+        let base = file::read(resolved).await?;
+        let content = self.ctx.stream_chat(request).await?;
+
+        match file::write_streamed(&self.tx, resolved, content, base.as_deref()).await {
+            Ok(written) => {
+                self.output(
+                    index,
+                    format!("wrote {path} ({} lines)", written.lines),
+                    output,
+                )?;
+                self.journal
+                    .push(format!("Wrote {path} ({} lines)", written.lines));
+                Ok(())
+            }
+            Err(e) => {
+                let changed = e.downcast::<file::Changed>()?;
+                self.reconcile(index, path, resolved, base.as_deref(), changed, output)
+                    .await
+            }
+        }
+    }
+
+    /// Check the Rust file generated at `path` with `cargo check`, generating it again with
+    /// `request` and the errors it has until it compiles, at most
+    /// [`Settings::codegen_iterations`](crate::Settings::codegen_iterations) times in all.
+    ///
+    /// A file that still does not compile is only reported, later steps may complete its package.
+    async fn compile(
+        &mut self,
+        index: usize,
+        path: &str,
+        resolved: &Path,
+        mut request: ChatRequest,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let iterations = self.ctx.codegen_iterations as usize;
+        if iterations <= 1 || resolved.extension() != Some(OsStr::new("rs")) {
+            return Ok(());
+        }
+        let root = self.dir.canonicalize()?;
+        let Some(manifest) = dependencies::manifest(&root, resolved) else {
+            return Ok(());
+        };
+
+        for iteration in 1..=iterations {
+            let errors = match cargo::check(self.ctx.clone(), &manifest).await {
+                Ok(errors) => errors,
+                Err(e) => {
+                    self.output(index, format!("! could not check {path}: {e:#}"), output)?;
+                    return Ok(());
+                }
+            };
+            // errors in other files cannot be fixed by generating this one again
+            let (errors, others): (Vec<_>, Vec<_>) = errors
+                .into_iter()
+                .partition(|error| resolved.ends_with(&error.file));
+
+            self.tx.send(Packet::server(server::CompileChecked {
+                index,
+                path: path.to_string(),
+                iteration,
+                iterations,
+                errors: errors.len(),
+            }))?;
+
+            if errors.is_empty() {
+                if !others.is_empty() {
+                    let line = format!("! the package has {} errors in other files", others.len());
+                    self.output(index, line, output)?;
+                }
+                if iteration > 1 {
+                    self.journal.push(format!(
+                        "Generated {path} {iteration} times until it compiled"
+                    ));
+                }
+                return Ok(());
+            }
+
+            let count = errors.len();
+            if iteration == iterations {
+                self.output(index, format!("! {path} still has {count} errors"), output)?;
+                self.journal.push(format!(
+                    "{path} still had {count} errors after generating it {iterations} times"
+                ));
+                return Ok(());
+            }
+
+            info!(
+                step = index,
+                iteration,
+                errors = count,
+                "Generating {path} again"
+            );
+            let line = format!("{path} has {count} errors, generating it again");
+            self.output(index, line, output)?;
+
+            let generated = file::read(resolved).await?.unwrap_or_default();
+            let errors: Vec<_> = errors.into_iter().map(|error| error.rendered).collect();
+            request = request
+                .assistant_msg(generated)
+                .user_msg(codegen::correction(&errors));
+            self.generate(index, path, resolved, request.clone(), output)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge the file generated at `path` with the changes made on disk while it was generated
+    /// from `base`. Conflicting lines are marked in the file until the frontend resolves them.
+    ///
+    /// # Errors
+    /// - The file kept changing for [`MAX_MERGE_ATTEMPTS`]
+    /// - The file could not be written
+    /// - The frontend stopped the execution while the conflict waited to be resolved
+    async fn reconcile(
+        &mut self,
+        index: usize,
+        path: &str,
+        resolved: &Path,
+        base: Option<&str>,
+        mut changed: file::Changed,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let mut attempt = 1;
+        let (current, conflicts) = loop {
+            let current = changed.current.unwrap_or_default();
+            let (content, conflicts) =
+                match merge::merge(base.unwrap_or_default(), &current, &changed.generated) {
+                    Merge::Clean(content) => (content, 0),
+                    Merge::Conflicted { content, conflicts } => (content, conflicts),
+                };
+
+            let content = stream::once(async { Ok(content) });
+            match file::write_streamed(&self.tx, resolved, content, Some(&current)).await {
+                Ok(_) => break (current, conflicts),
+                Err(e) if attempt < MAX_MERGE_ATTEMPTS => {
+                    changed.current = e.downcast::<file::Changed>()?.current;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e).context(format!("{path} kept changing on disk")),
+            }
+        };
+
+        if conflicts == 0 {
+            self.output(
+                index,
+                format!("merged {path} with the changes made on disk"),
+                output,
+            )?;
+            self.journal
+                .push(format!("Merged {path} with the changes made on disk"));
+            return Ok(());
+        }
+
+        self.tx.send(Packet::server(server::FileConflict {
+            path: path.to_string(),
+            conflicts,
+        }))?;
+
+        let resolution = self
+            .resolutions
+            .recv()
+            .await
+            .context("The execution stopped before the conflict was resolved")?;
+
+        info!(
+            step = index,
+            ?resolution,
+            conflicts,
+            "Resolved conflict in {path}"
+        );
+        self.ctx.audit(&audit::Event::ConflictResolved {
+            step: index,
+            path,
+            resolution,
+            conflicts,
+        });
+
+        let (content, line) = match resolution {
+            Resolution::Keep => (current, format!("kept the changes made to {path} on disk")),
+            Resolution::Overwrite => (
+                changed.generated,
+                format!("overwrote the changes made to {path} on disk"),
+            ),
+            Resolution::Resolved => {
+                self.output(index, format!("resolved the conflicts in {path}"), output)?;
+                self.journal
+                    .push(format!("Resolved the conflicts in {path} by hand"));
+                return Ok(());
+            }
+        };
+
+        // the file is replaced as it is now, the user may have edited it while deciding
+        let now = file::read(resolved).await?;
+        let content = stream::once(async { Ok(content) });
+        file::write_streamed(&self.tx, resolved, content, now.as_deref()).await?;
+
+        self.journal
+            .push(format!("{line} after {conflicts} conflicts"));
+        self.output(index, line, output)
+    }
+
+    /// Run `cmd` for step `index`, streaming its output like [`Self::run_step`].
+    async fn run_command(
+        &mut self,
+        index: usize,
+        cmd: Cmd,
+        input: &str,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let command = cmd.cast::<dyn Command + Send + Sync>();
+        self.ctx.audit(&audit::Event::Command {
+            step: index,
+            command: cmd.header(),
+            input,
+        });
+        let prefetched = self.prefetched.take(cmd, input);
+        // prefetched commands ran before the step, without being measured
+        let started = prefetched.is_none().then(Instant::now);
+        let events = match prefetched {
+            Some(events) => stream::iter(events).boxed(),
+            None => command.execute(self.ctx.clone(), &self.dir, input),
+        };
+
+        let ran = self.stream_command(index, events, output).await;
+        if let Some(started) = started {
+            let telemetry = &self.ctx.telemetry;
+            telemetry.command(&cmd.header(), started.elapsed(), ran.is_ok());
+        }
+        ran
+    }
+
+    /// Stream the output of a command to step `index` until it exits.
+    async fn stream_command(
+        &self,
+        index: usize,
+        mut events: CommandStream<'_>,
+        output: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        while let Some(event) = events.next().await {
+            match event? {
+                CommandEvent::Stdout(line) | CommandEvent::Stderr(line) => {
+                    self.output(index, line, output)?;
+                }
+                CommandEvent::InvalidUtf8 { lines } => {
+                    let line = format!("! {lines} lines were not valid UTF-8, bytes were replaced");
+                    self.output(index, line, output)?;
+                }
+                CommandEvent::Exit(0) => return Ok(()),
+                CommandEvent::Exit(code) => bail!("exited with {code}"),
+            }
+        }
+
+        bail!("the command ended without an exit code")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{server::Server, Resolution, Risk};
+
+    use super::Engine;
+    use crate::{
+        ctx, file,
+        hooks::Hooks,
+        plan::{Plan, Selection},
+    };
+
+    #[tokio::test]
+    async fn test_run_plan() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let dir = tempfile::tempdir()?;
+        let (_confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (_resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (_veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let mut engine = Engine::new(
+            ctx()?,
+            tx,
+            dir.path(),
+            "Instruction: greet",
+            confirmations,
+            resolutions,
+            vetoes,
+        );
+
+        let step = |title: &str, input: &str| {
+            format!(
+                r#"{{"title": "{title}", "description": "", "command": {{"type": "bash", "input": "{input}"}}}}"#
+            )
+        };
+        let plan = Plan::parse(&format!(
+            "[{}, {}, {}]",
+            step("Greet", "echo hello"),
+            step("Fail", "exit 1"),
+            step("Never", "echo unreachable")
+        ))?;
+        assert!(!engine.run_plan(&plan, &Selection::all(&plan)).await?);
+
+        let packets: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|packet| packet.data)
+            .collect();
+
+        assert!(matches!(&packets[..], [
+            Server::StepStarted { index: 0, .. },
+            Server::StepOutput { index: 0, output },
+            Server::StepFinished { index: 0, success: true },
+            Server::StepStarted { index: 1, .. },
+            Server::StepOutput { index: 1, .. },
+            Server::StepFinished { index: 1, success: false },
+        ] if output == "hello"));
+
+        assert!(engine.context.contains("hello"));
+        assert_eq!(engine.journal, [
+            "Step `Greet` (Bash) succeeded",
+            "Step `Fail` (Bash) failed"
+        ]);
+
+        // skipping the failing step, under the indices of the plan
+        assert!(engine.run_plan(&plan, &plan.select(&[2])?).await?);
+        let packets: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|packet| packet.data)
+            .collect();
+        assert!(matches!(&packets[..], [
+            Server::StepStarted { index: 2, .. },
+            Server::StepOutput { index: 2, output },
+            Server::StepFinished { index: 2, success: true },
+        ] if output == "unreachable"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_confirm() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (_resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (_veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let dir = tempfile::tempdir()?;
+        let mut engine = Engine::new(
+            ctx()?,
+            tx,
+            dir.path(),
+            "Instruction: clean",
+            confirmations,
+            resolutions,
+            vetoes,
+        );
+
+        let plan = Plan::parse(
+            r#"[{"title": "Clean", "description": "", "command": {"type": "bash", "input": "mkdir a && rm -rf a"}}]"#,
+        )?;
+
+        confirm.send(false)?;
+        assert!(!engine.run_plan(&plan, &Selection::all(&plan)).await?);
+        assert!(dir.path().read_dir()?.next().is_none());
+
+        confirm.send(true)?;
+        assert!(engine.run_plan(&plan, &Selection::all(&plan)).await?);
+
+        let packets: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|packet| packet.data)
+            .collect();
+
+        assert!(matches!(&packets[..3], [
+            Server::StepStarted { index: 0, .. },
+            Server::ConfirmCommand { risk: Risk::Destructive, .. },
+            Server::StepOutput { index: 0, output },
+        ] if output == "the command was not approved"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hooks() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (_resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (_veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let dir = tempfile::tempdir()?;
+        let config = dir.path().join("hooks.toml");
+        std::fs::write(
+            &config,
+            "[hooks]\nbefore_command = [\"! grep -q secret || (echo 'not here' >&2; false)\"]",
+        )?;
+        let mut engine = Engine::new(
+            ctx()?,
+            tx,
+            dir.path(),
+            "Instruction: greet",
+            confirmations,
+            resolutions,
+            vetoes,
+        );
+        engine.hooks = Hooks::load(Some(config))?;
+
+        let plan = Plan::parse(
+            r#"[
+                {"title": "Greet", "description": "", "command": {"type": "bash", "input": "echo hello"}},
+                {"title": "Leak", "description": "", "command": {"type": "bash", "input": "echo secret"}}
+            ]"#,
+        )?;
+        assert!(!engine.run_plan(&plan, &Selection::all(&plan)).await?);
+
+        let outputs: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|packet| match packet.data {
+                Server::StepOutput { index, output } => Some((index, output)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(outputs[0], (0, "hello".to_string()));
+        assert_eq!(outputs[1].0, 1);
+        assert!(outputs[1].1.ends_with("exited with 1: not here"));
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_reconcile() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (_veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let dir = tempfile::tempdir()?;
+        let mut engine = Engine::new(
+            ctx()?,
+            tx,
+            dir.path(),
+            "Instruction: count",
+            confirmations,
+            resolutions,
+            vetoes,
+        );
+
+        let path = dir.path().join("count.txt");
+        let base = "one\ntwo\nthree\n";
+        let changed = |current: &str, generated: &str| file::Changed {
+            current: Some(current.to_string()),
+            generated: generated.to_string(),
+        };
+
+        // the user and the model changed different lines
+        std::fs::write(&path, "one!\ntwo\nthree\n")?;
+        let edits = changed("one!\ntwo\nthree\n", "one\ntwo\nthree!\n");
+        let mut output = Vec::new();
+        engine
+            .reconcile(0, "count.txt", &path, Some(base), edits, &mut output)
+            .await?;
+        assert_eq!(std::fs::read_to_string(&path)?, "one!\ntwo\nthree!\n");
+        assert_eq!(output, ["merged count.txt with the changes made on disk"]);
+
+        // both changed the same line, the generated one is kept
+        std::fs::write(&path, "one\n2\nthree\n")?;
+        let edits = changed("one\n2\nthree\n", "one\nTWO\nthree\n");
+        resolve.send(Resolution::Overwrite)?;
+        engine
+            .reconcile(0, "count.txt", &path, Some(base), edits, &mut output)
+            .await?;
+        assert_eq!(std::fs::read_to_string(&path)?, "one\nTWO\nthree\n");
+
+        let packets: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|packet| packet.data)
+            .collect();
+        assert!(packets.iter().any(|packet| matches!(
+            packet,
+            Server::FileConflict { path, conflicts: 1 } if path == "count.txt"
+        )));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_veto() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_confirm, confirmations) = tokio::sync::mpsc::unbounded_channel();
+        let (_resolve, resolutions) = tokio::sync::mpsc::unbounded_channel();
+        let (veto, vetoes) = tokio::sync::mpsc::unbounded_channel();
+        let dir = tempfile::tempdir()?;
+        let mut engine = Engine::new(
+            ctx()?,
+            tx,
+            dir.path(),
+            "Instruction: read",
+            confirmations,
+            resolutions,
+            vetoes,
+        );
+
+        let plan = Plan::parse(
+            r#"[{"title": "Read", "description": "", "command": {"type": "fetch", "input": "https://example.com"}}]"#,
+        )?;
+        let context = "\nStep `Read` (Fetch) succeeded:\nUnrelated.\n".to_string();
+        engine.context.push_str(&context);
+        engine.preview(&plan.steps[0], "# Example\n\nUnrelated.", context)?;
+
+        assert!(matches!(
+            rx.try_recv()?.data,
+            Server::SourcePreview { id: 1, title, .. } if title == "Example"
+        ));
+
+        // unknown sources are ignored
+        veto.send(1)?;
+        veto.send(7)?;
+        engine.apply_vetoes();
+        assert!(!engine.context.contains("Unrelated."));
+        assert!(engine
+            .context
+            .contains("The user vetoed https://example.com as irrelevant"));
+
+        Ok(())
+    }
+}
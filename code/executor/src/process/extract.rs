@@ -0,0 +1,221 @@
+//! Extract the question from what the model streams when it drifts from the format asked for.
+//!
+//! Despite the prompt and the stop sequence, models sometimes number the question, wrap it in
+//! markdown or ask several at once, e.g. `1. **Which language?** 2. Should it have a GUI?`. The
+//! [`Extractor`] passes on only the first question, without the numbering and the emphasis, and
+//! keeps the others so they can be asked later instead of being lost.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Numbering, bullets, headings, quotes and labels a question may start with, e.g. `1.`, `-` or
+/// `Q:`
+static MARKER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?:\s*(?:#{1,6}\s|>\s?|[-*•]\s|\(?\d{1,2}[.)]\s|(?i:q(?:uestion)?\s*\d*\s*:)))*\s*",
+    )
+    .expect("valid regex")
+});
+
+/// The fewest words a question after the first needs to be asked on its own. Shorter ones, such
+/// as `Rust or Python?`, narrow the question before them down and stay part of it.
+const MIN_QUESTION_WORDS: usize = 4;
+
+/// Passes on the first question of a streamed response
+#[derive(Default)]
+pub struct Extractor {
+    text: String,
+    shown: String,
+}
+
+impl Extractor {
+    /// What of the question to show after the response continued with `text`. Whatever could
+    /// still turn out to be numbering or another question is held back.
+    pub fn push(&mut self, text: &str) -> String {
+        self.text.push_str(text);
+        self.show(false)
+    }
+
+    /// What of the question is left to show once the response is complete
+    pub fn finish(&mut self) -> String {
+        self.show(true)
+    }
+
+    /// The questions the response asked after the first
+    pub fn others(&self) -> Vec<String> {
+        let mut others = Vec::new();
+        let mut rest = split(&self.text, true).1;
+        while let Some(text) = rest {
+            let (question, more) = split(&text, true);
+            if !question.trim().is_empty() {
+                others.push(question.trim().to_string());
+            }
+            rest = more;
+        }
+        others
+    }
+
+    fn show(&mut self, complete: bool) -> String {
+        let (question, _) = split(&self.text, complete);
+        // the settled question only ever grows
+        let Some(new) = question.strip_prefix(&self.shown) else {
+            return String::new();
+        };
+        let new = new.to_string();
+        self.shown = question;
+        new
+    }
+}
+
+/// The length of the numbering or other markers `text` starts with, including the whitespace
+fn marker(text: &str) -> usize {
+    MARKER.find(text).map_or(0, |marker| marker.end())
+}
+
+/// What of `text` is settled as its first question, and the text of the questions after it if
+/// it asks several. Unless the text is `complete`, the question stops before anything that could
+/// still change.
+fn split(text: &str, complete: bool) -> (String, Option<String>) {
+    let clean = text.replace("**", "");
+    let body = &clean[marker(&clean)..];
+    // the first word may still turn out to be a marker
+    if !complete && !body.contains(char::is_whitespace) {
+        return (String::new(), None);
+    }
+
+    // where the first question and the sentences belonging to it end
+    let mut question_end = None;
+    let mut start = 0;
+    while start < body.len() {
+        let sentence_start = start + body[start..].len() - body[start..].trim_start().len();
+        let rest = &body[sentence_start..];
+        let marker = marker(rest);
+        let end = sentence_end(&rest[marker..], complete).map(|end| sentence_start + marker + end);
+
+        if let Some(question_end) = question_end {
+            let sentence = &body[sentence_start..end.unwrap_or(body.len())];
+            let another = marker > 0
+                || (end.is_some()
+                    && sentence.ends_with('?')
+                    && sentence.split_whitespace().count() >= MIN_QUESTION_WORDS);
+            if another {
+                return (
+                    body[..question_end].to_string(),
+                    Some(body[sentence_start..].to_string()),
+                );
+            }
+        }
+
+        let Some(end) = end else {
+            break;
+        };
+        // a sentence that is not a question belongs to the question, as part of its context
+        if question_end.is_some() || body[..end].ends_with('?') {
+            question_end = Some(end);
+        }
+        start = end;
+    }
+
+    match question_end {
+        // unless complete, what follows could still be another question
+        Some(end) => (body[..end].to_string(), None),
+        None if complete => (body.to_string(), None),
+        // a lone `*` may be the start of `**`
+        None => (body.trim_end_matches('*').to_string(), None),
+    }
+}
+
+/// Where the sentence `text` starts with ends, after its `?`, `!` or `.`. Unless the text is
+/// `complete` the end has to be followed by whitespace, for e.g. `??` or `3.5`.
+fn sentence_end(text: &str, complete: bool) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if !matches!(c, '?' | '!' | '.') {
+            continue;
+        }
+        match chars.peek() {
+            Some((_, next)) if next.is_whitespace() => return Some(index + 1),
+            None if complete => return Some(index + 1),
+            _ => {}
+        }
+    }
+    complete.then_some(text.len()).filter(|&end| end > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, Extractor};
+
+    /// Stream `response` through an extractor character by character, returning what was shown
+    /// and the other questions
+    fn stream(response: &str) -> (String, Vec<String>) {
+        let mut extractor = Extractor::default();
+        let mut shown: String = response
+            .chars()
+            .map(|c| extractor.push(&c.to_string()))
+            .collect();
+        shown.push_str(&extractor.finish());
+        (shown, extractor.others())
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("Which language?", true),
+            ("Which language?".to_string(), None)
+        );
+        assert_eq!(
+            split("1. Which language? 2. Should it have a GUI?", true),
+            (
+                "Which language?".to_string(),
+                Some("2. Should it have a GUI?".to_string())
+            )
+        );
+        // undecided while streamed
+        assert_eq!(
+            split("Which language? Should it", false),
+            ("Which language?".to_string(), None)
+        );
+        assert_eq!(split("1", false), (String::new(), None));
+    }
+
+    #[test]
+    fn test_extract() {
+        assert_eq!(
+            stream("Which language should it use?"),
+            ("Which language should it use?".to_string(), Vec::new())
+        );
+
+        assert_eq!(
+            stream("1. **Which language should it use?** 2. Should it have a GUI? - Which OS?"),
+            ("Which language should it use?".to_string(), vec![
+                "Should it have a GUI?".to_string(),
+                "Which OS?".to_string()
+            ])
+        );
+
+        assert_eq!(
+            stream(
+                "Q: Which language should it use? Should it have a GUI? Which operations should \
+                 it support?"
+            ),
+            ("Which language should it use?".to_string(), vec![
+                "Should it have a GUI?".to_string(),
+                "Which operations should it support?".to_string()
+            ])
+        );
+
+        // short questions and statements narrow the question down
+        assert_eq!(
+            stream("Which language? Rust or Python? For example Rust."),
+            (
+                "Which language? Rust or Python? For example Rust.".to_string(),
+                Vec::new()
+            )
+        );
+        assert_eq!(
+            stream("What is 3.5 times 2?"),
+            ("What is 3.5 times 2?".to_string(), Vec::new())
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! The [`Handler`] of every [`Client`](protocol::client::Client) packet.
+//!
+//! A packet only reaches its handler once the [`Middleware`](super::router::Middleware) of the
+//! router let it through, e.g. after checking that the session is in a state that allows it.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use protocol::{client, server, Packet};
+use tracing::info;
+
+use crate::{
+    answer_format, audit,
+    hooks::Event,
+    process::{
+        execute::Order, question::QAndA, revision::Revision, router::Handler, Process,
+        StateViolation,
+    },
+    Comm,
+};
+
+/// We are getting an instruction from the frontend
+/// - This is the first packet we get from the frontend
+/// - This defines the purpose of a [`QAndA`] session
+/// - This is the first message sent from `frontend-cli`
+/// - For instance if we are building a calculator, the instruction would be "Build a calculator"
+/// - Questions regarding the instruction are generated by GPT4 and sent back to the frontend via
+///   the [`server::Question`] packet
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Instruction {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        let instruction = self.instruction;
+        info!("Instruction: {}", instruction);
+        process.executor.ctx.audit(&audit::Event::Instruction {
+            instruction: &instruction,
+        });
+
+        let dir = process.sessions.workdir(process.id);
+        let event = Event::SessionStart {
+            instruction: &instruction,
+        };
+        if let Err(e) = process
+            .executor
+            .ctx
+            .hooks
+            .check(&process.executor.ctx, &dir, &event)
+            .await
+        {
+            info!("Instruction refused: {e:#}");
+            return process
+                .comm
+                .send(Packet::server(server::Rejected {
+                    reason: format!("{e:#}"),
+                }))
+                .await;
+        }
+
+        let previous = process.q_and_a.take().map(Box::new);
+        let plan = process.plan.take();
+        process
+            .revisions
+            .push(Revision::Instruction(previous, plan));
+
+        let cited = process.cite(&instruction).await;
+        let q_and_a = QAndA::new(process.executor.clone(), instruction).citing(cited);
+        process.ask_batch(q_and_a).await
+    }
+}
+
+/// From the second prompt onwards, this is used to continue the Q&A session
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Answer {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        let answer = self.answer;
+
+        // without an id, an answer is for the first open question of a batch
+        if let Some(id) = process.q_and_a.as_ref().and_then(QAndA::next_unanswered) {
+            return process.answer_to(id, answer).await;
+        }
+
+        // the model would only ask again, let the user fix the answer instead
+        let question = process.q_and_a.as_ref().and_then(|q| q.questions().last());
+        if let Some(Err(invalid)) = question
+            .and_then(|question| answer_format::infer(question))
+            .map(|validator| validator.check(&answer))
+        {
+            info!("Invalid answer: {invalid}");
+            return process
+                .comm
+                .send(Packet::server(server::Rejected {
+                    reason: invalid.to_string(),
+                }))
+                .await;
+        }
+
+        let mut q_and_a = process
+            .q_and_a
+            .take()
+            .context("an interview always has a question")?;
+
+        let secret = q_and_a.awaits_secret();
+        if secret {
+            info!("Answer: (secret)");
+        } else {
+            info!("Answer: {}", answer);
+        }
+        process.executor.ctx.audit(&audit::Event::Answer {
+            id: None,
+            answer: (!secret).then_some(answer.as_str()),
+        });
+
+        q_and_a.answer(answer);
+        q_and_a.review().await;
+        process.ask(q_and_a).await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::AnswerTo {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.answer_to(self.id, self.answer).await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Ping {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.comm.send(Packet::server(server::Pong)).await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Latency {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.send_latency().await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::ListSessions {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.send_sessions().await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::CloneRepo {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        // the clone replaces the files an execution would be reverted to
+        process.revisions.clear();
+        process.clone_repo(&self.url, self.branch.as_deref()).await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::SetWorkspace {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        // they belong to the working directory that is replaced
+        process.revisions.clear();
+        process.set_workspace(&self.path).await
+    }
+}
+
+/// The frontend reconnected after the websocket dropped
+/// - if the session is still known, we take over its state
+/// - otherwise the frontend replays the session and we rebuild it
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Resume {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        let Self {
+            session,
+            key,
+            instruction,
+            questions,
+            answers,
+        } = *self;
+        // they belong to the session that is replaced
+        process.revisions.clear();
+
+        match process.sessions.attach(session, &key) {
+            Some(q_and_a) => {
+                info!("Reattaching to session {session}");
+
+                process.sessions.remove(process.id);
+                process.id = session;
+                process.send_session().await?;
+
+                match q_and_a {
+                    Some(q_and_a) => process.sync(q_and_a, questions.len()).await?,
+                    None => process.set_session(None),
+                }
+            }
+            None => {
+                info!("Rebuilding session {session} as {}", process.id);
+                process.send_session().await?;
+
+                let q_and_a =
+                    QAndA::resume(process.executor.clone(), instruction, questions, answers);
+
+                if q_and_a.needs_question() {
+                    process.ask(q_and_a).await?;
+                } else {
+                    process.set_session(Some(q_and_a));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A question that was already streamed completely can still be regenerated as long as it has
+/// not been answered
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Regenerate {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        match process.q_and_a.take() {
+            Some(mut q_and_a) if !q_and_a.needs_question() => {
+                q_and_a.discard_question();
+                process.ask(q_and_a).await?;
+            }
+            q_and_a => {
+                info!("Nothing to regenerate");
+                process.set_session(q_and_a);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Questions are cancelled while they are streamed, see [`Process::stream_question`]
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Cancel {
+    async fn handle(self: Box<Self>, _process: &mut Process<C>) -> anyhow::Result<()> {
+        info!("Nothing to cancel");
+        Ok(())
+    }
+}
+
+/// The interview is over, generate a plan and run it
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Execute {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        let q_and_a = process
+            .q_and_a
+            .as_mut()
+            .context("an interview always has an instruction")?;
+        q_and_a.condense().await;
+        let context = q_and_a.transcript();
+        let workspace = process.workspace.summary() + &process.cite(&context).await;
+        let request = process
+            .q_and_a
+            .as_ref()
+            .context("an interview always has an instruction")?
+            .plan_request(&workspace);
+
+        info!("Executing plan");
+        process.run_order(Order::Plan(request), context).await?;
+
+        process.learn().await
+    }
+}
+
+/// Run steps of the plan the last execution approved again, or for the first time
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::ExecuteSteps {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        let Some(plan) = process.plan.clone() else {
+            return process.reject(StateViolation::NoPlan).await;
+        };
+        let selection = match plan.select(&self.steps) {
+            Ok(selection) => selection,
+            Err(e) => {
+                info!("Invalid selection: {e:#}");
+                return process
+                    .comm
+                    .send(Packet::server(server::Rejected {
+                        reason: format!("{e:#}"),
+                    }))
+                    .await;
+            }
+        };
+
+        for message in selection.warnings() {
+            process
+                .comm
+                .send(Packet::server(server::Notice { message }))
+                .await?;
+        }
+
+        let context = process
+            .q_and_a
+            .as_ref()
+            .context("an interview always has an instruction")?
+            .transcript();
+
+        info!("Executing steps {:?}", selection.steps);
+        process
+            .run_order(Order::Steps(plan, selection), context)
+            .await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::ListMemory {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.send_memory().await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Remember {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        if let Some(memory) = &process.executor.ctx.memory {
+            memory.add(&self.preference)?;
+        }
+        process.send_memory().await
+    }
+}
+
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Forget {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        if let Some(memory) = &process.executor.ctx.memory {
+            memory.forget(self.id)?;
+        }
+        process.send_memory().await
+    }
+}
+
+/// Only allowed while executing, where it is handled by [`Process::execute`]
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::Confirm {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.reject(StateViolation::NothingToConfirm).await
+    }
+}
+
+/// Only allowed while executing, where it is handled by [`Process::execute`]
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::ResolveConflict {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.reject(StateViolation::NoConflict).await
+    }
+}
+
+/// Only allowed while executing, where it is handled by [`Process::execute`]
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::VetoSource {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.reject(StateViolation::NothingToVeto).await
+    }
+}
+
+/// Not while executing, see [`State::check`](super::state::State::check)
+#[async_trait]
+impl<C: Comm + Send> Handler<C> for client::RevertLast {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()> {
+        process.revert_last().await
+    }
+}
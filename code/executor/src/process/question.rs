@@ -1,18 +1,79 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
-use futures::{Stream, StreamExt};
+use anyhow::Context;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use protocol::QuestionItem;
 use smooth_stream::smooth_stream;
-use tokio_openai::ChatRequest;
+use tokio_openai::{ChatModel, ChatRequest};
 use tokio_stream::wrappers::ReceiverStream;
-use tracing::info;
+use tracing::{error, info};
 
-use crate::Executor;
+use crate::{
+    answer_format,
+    guardrails::{QuestionGuard, MAX_REGENERATIONS},
+    plan::PLAN_FORMAT,
+    process::{candidates, conversation::Conversation},
+    prompts::Prompt,
+    usage::{self, Purpose},
+    Executor,
+};
+
+/// How many questions are asked at once at most
+const MAX_BATCH: usize = 5;
+
+/// How much the temperature rises with every degenerate question, which tend to come back
+const REGENERATION_TEMPERATURE: f64 = 0.25;
+
+/// The default template of [`Prompt::Question`]
+pub const QUESTION_PROMPT: &str = "Ask clarifying questions for the instruction. Do not include \
+                                   numbering or bullets.\n\nInstruction: {instruction}{cited}";
+
+/// The default template of [`Prompt::Batch`]
+pub const BATCH_PROMPT: &str =
+    "List the clarifying questions for the instruction that can be answered independently of each \
+     other, at most {max}, one per line. Do not include numbering or bullets. Answer with only \
+     `NONE` if the instruction is clear.\n\nInstruction: {instruction}{cited}";
+
+/// The default template of [`Prompt::Review`]
+pub const REVIEW_PROMPT: &str = "Check the last answer of the user. If it is ambiguous, does not \
+                                 answer its question or contradicts an earlier answer, ask one \
+                                 short question clarifying it. Otherwise answer with only `NONE`.";
+
+/// A question asked together with others, and its answer once given
+struct Pending {
+    id: u64,
+    question: String,
+    answer: Option<String>,
+}
 
 pub struct QAndA {
     executor: Executor,
     instruction: String,
     questions: Vec<String>,
     answers: Vec<String>,
+    /// questions asked at once that are not all answered yet, in the order they were asked
+    batch: Vec<Pending>,
+    /// the parts of the workspace relevant to the instruction, see [`Indexer::cite`]
+    ///
+    /// [`Indexer::cite`]: crate::indexer::Indexer::cite
+    cited: String,
+    /// the summary of the questions and answers that do not fit in a prompt anymore
+    conversation: Conversation,
+    /// the answers to secret questions by the variable commands read them from, only kept in
+    /// memory. The answers themselves only tell the model the name.
+    secrets: Vec<(String, String)>,
+    /// the other candidates for the last question, see [`candidates`]
+    alternatives: Vec<String>,
+    /// a question clarifying the last answer, asked instead of generating the next question
+    follow_up: Option<String>,
+    /// whether the last question asked was a follow-up, whose answer is not reviewed again
+    followed_up: bool,
+    /// questions the model asked at once with an earlier one, asked before generating new ones,
+    /// see [`extract`](crate::process::extract)
+    queued: VecDeque<String>,
+    /// how many degenerate questions were generated in a row, see
+    /// [`guardrails`](crate::guardrails)
+    rejections: u32,
 }
 
 impl QAndA {
@@ -20,47 +81,306 @@ impl QAndA {
         Self {
             questions: vec![],
             answers: vec![],
+            batch: vec![],
+            cited: String::new(),
+            conversation: Conversation::default(),
+            secrets: vec![],
+            alternatives: vec![],
+            follow_up: None,
+            followed_up: false,
+            queued: VecDeque::new(),
+            rejections: 0,
             instruction: instruction.into(),
             executor,
         }
     }
 
+    /// Include `cited` parts of the workspace when asking questions.
+    #[must_use]
+    pub fn citing(mut self, cited: String) -> Self {
+        self.cited = cited;
+        self
+    }
+
+    /// Rebuild a session from the history a frontend replays after reconnecting.
+    pub fn resume(
+        executor: Executor,
+        instruction: impl Into<String>,
+        questions: Vec<String>,
+        answers: Vec<String>,
+    ) -> Self {
+        let mut q_and_a = Self::new(executor, instruction);
+        // the frontend replays what was typed, secrets included
+        for (question, answer) in questions.iter().zip(answers) {
+            let answer = q_and_a.keep(question, answer);
+            q_and_a.answers.push(answer);
+        }
+        q_and_a.questions = questions;
+        q_and_a
+    }
+
+    /// Whether the last question has been answered (or no question was asked yet), meaning the
+    /// next question still has to be generated.
+    pub fn needs_question(&self) -> bool {
+        self.batch.is_empty() && self.questions.len() <= self.answers.len()
+    }
+
+    pub fn instruction(&self) -> &str {
+        &self.instruction
+    }
+
+    pub fn questions(&self) -> &[String] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[String] {
+        &self.answers
+    }
+
+    pub fn has_questions(&self) -> bool {
+        !self.questions.is_empty()
+    }
+
     pub fn add_question(&mut self, question: String) {
+        self.rejections = 0;
         self.questions.push(question);
     }
 
-    fn question_request(&self) -> ChatRequest {
-        let mut message = String::new();
+    /// Holds the next question back while it could still be degenerate
+    pub fn guard(&self) -> QuestionGuard {
+        QuestionGuard::new(&self.instruction, &self.questions)
+    }
+
+    /// The next question was degenerate. Returns whether it is generated again, at a higher
+    /// temperature, or used anyway after [`MAX_REGENERATIONS`].
+    pub fn reject(&mut self) -> bool {
+        if self.rejections >= MAX_REGENERATIONS {
+            return false;
+        }
+        self.rejections += 1;
+        true
+    }
 
-        message.push_str(&format!(
-            "Ask clarifying questions for the instruction. Do not include numbering or \
-             bullets.\n\nInstruction: {}\n---\n",
-            self.instruction
-        ));
+    /// Ask `questions` at once. They are numbered after the questions asked so far.
+    pub fn add_batch(&mut self, questions: Vec<String>) -> Vec<QuestionItem> {
+        let asked = self.questions.len() as u64;
+        self.batch = questions
+            .into_iter()
+            .zip(asked..)
+            .map(|(question, id)| Pending {
+                id,
+                question,
+                answer: None,
+            })
+            .collect();
+        self.batch()
+    }
+
+    /// The questions asked at once that are not all answered yet
+    pub fn batch(&self) -> Vec<QuestionItem> {
+        self.batch
+            .iter()
+            .map(|pending| QuestionItem {
+                id: pending.id,
+                question: pending.question.clone(),
+                validator: answer_format::infer(&pending.question),
+            })
+            .collect()
+    }
+
+    /// The question `id` of the batch
+    pub fn batch_question(&self, id: u64) -> Option<&str> {
+        self.batch
+            .iter()
+            .find(|pending| pending.id == id)
+            .map(|pending| pending.question.as_str())
+    }
 
-        for (question, answer) in self.questions.iter().zip(self.answers.iter()) {
-            message.push_str(&format!("Q: {question}\nA: {answer}\n\n"));
+    /// The first question of the batch without an answer
+    pub fn next_unanswered(&self) -> Option<u64> {
+        self.batch
+            .iter()
+            .find(|pending| pending.answer.is_none())
+            .map(|pending| pending.id)
+    }
+
+    /// Answer the question `id` of the batch, replacing an earlier answer. Once every question
+    /// is answered, they are added to the questions and answers in the order they were asked.
+    ///
+    /// Returns whether the batch is complete, `None` if it has no question `id`.
+    pub fn answer_to(&mut self, id: u64, answer: String) -> Option<bool> {
+        let pending = self.batch.iter_mut().find(|pending| pending.id == id)?;
+        pending.answer = Some(answer);
+
+        if self.next_unanswered().is_some() {
+            return Some(false);
+        }
+
+        for pending in std::mem::take(&mut self.batch) {
+            if let Some(answer) = pending.answer {
+                let answer = self.keep(&pending.question, answer);
+                self.answers.push(answer);
+            }
+            self.questions.push(pending.question);
+        }
+        Some(true)
+    }
+
+    /// Whether the question waiting for an answer is secret
+    pub fn awaits_secret(&self) -> bool {
+        self.batch.is_empty()
+            && self
+                .questions
+                .get(self.answers.len())
+                .is_some_and(|question| answer_format::is_secret(question))
+    }
+
+    /// The answers to secret questions by the variable commands read them from
+    pub fn secrets(&self) -> &[(String, String)] {
+        &self.secrets
+    }
+
+    /// What is kept of `answer` to `question`: a secret is put aside and replaced by where
+    /// commands can read it.
+    fn keep(&mut self, question: &str, answer: String) -> String {
+        if !answer_format::is_secret(question) || answer.trim().is_empty() {
+            return answer;
+        }
+
+        let name = format!("SECRET_ANSWER_{}", self.secrets.len() + 1);
+        let kept = format!("(secret, commands can read it from ${name})");
+        self.secrets.push((name, answer.trim().to_string()));
+        kept
+    }
+
+    /// The questions that were answered, including those of an incomplete batch
+    fn answered(&self) -> impl Iterator<Item = (&str, &str)> {
+        let batch = self.batch.iter().filter_map(|pending| {
+            let answer = pending.answer.as_deref()?;
+            Some((pending.question.as_str(), answer))
+        });
+
+        self.questions
+            .iter()
+            .map(String::as_str)
+            .zip(self.answers.iter().map(String::as_str))
+            .chain(batch)
+    }
+
+    fn batch_request(&self) -> ChatRequest {
+        let message = self.executor.ctx.prompts.render(Prompt::Batch, &[
+            ("max", &MAX_BATCH.to_string()),
+            ("instruction", &self.instruction),
+            ("cited", &self.cited),
+        ]);
+
+        ChatRequest::new().user_msg(message)
+    }
+
+    /// Generate the questions that can be asked at once, usually before the first answer.
+    pub async fn gen_batch(&self) -> anyhow::Result<Vec<String>> {
+        let request = self.executor.ctx.personalize(self.batch_request());
+        let text = usage::purpose(Purpose::Questions, self.executor.ctx.chat(request)).await?;
+        Ok(parse_batch(&text))
+    }
+
+    fn question_request(&self) -> ChatRequest {
+        let mut message = self.executor.ctx.prompts.render(Prompt::Question, &[
+            ("instruction", &self.instruction),
+            ("cited", &self.cited),
+        ]);
+        message.push_str("\n---\n");
+
+        let answered = self.conversation.render(&self.turns());
+        if !answered.is_empty() {
+            message.push_str(&answered);
+            message.push('\n');
         }
 
         message.push_str("Q:");
 
         info!("message: {}", message);
 
-        ChatRequest::new()
+        let mut request = ChatRequest::new()
             .stop_at("\n")
             // .sys_msg(
             //     "list relevant questions (one per line) that are important for completing the \
             //      task.",
             // )
-            .user_msg(message)
+            .user_msg(message);
+        request.temperature += REGENERATION_TEMPERATURE * f64::from(self.rejections);
+        request
+    }
+
+    /// The instruction and all answered questions, the older ones summarized if they do not
+    /// fit in a prompt.
+    pub fn transcript(&self) -> String {
+        format!(
+            "Instruction: {}\n{}",
+            self.instruction,
+            self.conversation.render(&self.turns())
+        )
+    }
+
+    /// Every answered question as a turn of the conversation
+    fn turns(&self) -> Vec<String> {
+        self.answered()
+            .map(|(question, answer)| format!("Q: {question}\nA: {answer}\n"))
+            .collect()
+    }
+
+    /// Summarize the older questions and answers once they do not fit in a prompt. If that
+    /// fails they are kept as they are, which only costs more tokens.
+    pub async fn condense(&mut self) {
+        let turns = self.turns();
+        if !self.conversation.needs_summary(&turns) {
+            return;
+        }
+
+        info!("Summarizing {} questions and answers", turns.len());
+        let summarized = self.conversation.summarize(&self.executor.ctx, &turns);
+        if let Err(e) = usage::purpose(Purpose::Summary, summarized).await {
+            error!("Failed to summarize the conversation: {e:#}");
+        }
+    }
+
+    /// The request to generate a [`Plan`](crate::plan::Plan) for the instruction in a
+    /// workspace described by `workspace`.
+    pub fn plan_request(&self, workspace: &str) -> ChatRequest {
+        let prompt = self
+            .executor
+            .ctx
+            .prompts
+            .render(Prompt::Plan, &[("workspace", workspace)]);
+
+        ChatRequest::new()
+            .sys_msg(format!("{prompt} {PLAN_FORMAT}"))
+            .user_msg(self.transcript())
     }
 
     pub async fn gen_question(
         &mut self,
     ) -> anyhow::Result<impl Stream<Item = Result<String, anyhow::Error>>> {
-        let request = self.question_request();
+        self.condense().await;
 
-        let mut tokens = self.executor.ctx.ai.stream_chat(request).await?.boxed();
+        self.followed_up = self.follow_up.is_some();
+        if let Some(question) = self.follow_up.take() {
+            return Ok(stream_text(&question));
+        }
+        if let Some(question) = self.queued.pop_front() {
+            return Ok(stream_text(&question));
+        }
+
+        let purpose = Purpose::Question(self.questions.len() + 1);
+        let candidates = self.executor.ctx.question_candidates;
+        if candidates > 1 {
+            let question = usage::purpose(purpose, self.pick_question(candidates)).await?;
+            return Ok(stream_text(&question));
+        }
+
+        let request = self.executor.ctx.personalize(self.question_request());
+
+        let mut tokens = usage::purpose(purpose, self.executor.ctx.stream_chat(request)).await?;
         let characters = {
             let (tx, rx) = tokio::sync::mpsc::channel(1);
 
@@ -92,20 +412,263 @@ impl QAndA {
         Ok(stream)
     }
 
+    /// Check the last answer against its question and the earlier answers. If it is ambiguous or
+    /// contradicts them, the next question clarifies it, see [`Self::is_follow_up`].
+    ///
+    /// Secret answers are not reviewed, and neither are answers to follow-ups so the interview
+    /// moves on. If the model could not be reached, the answer is taken as it is.
+    pub async fn review(&mut self) {
+        let last = self.questions.len().checked_sub(1);
+        let answered = last.is_some_and(|last| self.answers.len() > last);
+        let secret = last.is_some_and(|last| answer_format::is_secret(&self.questions[last]));
+        if !self.batch.is_empty() || !answered || secret || self.followed_up {
+            return;
+        }
+
+        let request = ChatRequest::new()
+            .model(ChatModel::Turbo)
+            .sys_msg(self.executor.ctx.prompts.get(Prompt::Review))
+            .user_msg(self.transcript());
+
+        let purpose = Purpose::Question(self.questions.len());
+        match usage::purpose(purpose, self.executor.ctx.chat(request)).await {
+            Ok(text) => {
+                self.follow_up = parse_batch(&text).into_iter().next();
+                if let Some(follow_up) = &self.follow_up {
+                    info!("Following up: {follow_up}");
+                }
+            }
+            Err(e) => error!("Failed to review the answer: {e:#}"),
+        }
+    }
+
+    /// Whether the next question clarifies the last answer instead of moving on
+    pub fn is_follow_up(&self) -> bool {
+        self.follow_up.is_some()
+    }
+
+    /// Ask the model for `n` candidates for the next question at once and pick the most
+    /// informative. The others are kept until [`Self::take_alternatives`].
+    async fn pick_question(&mut self, n: u32) -> anyhow::Result<String> {
+        let mut request = self.executor.ctx.personalize(self.question_request());
+        request.n = n;
+
+        let response = self.executor.ctx.raw_chat(request).await?;
+        let proposed = response
+            .choices
+            .into_iter()
+            .map(|choice| choice.message.content)
+            .collect();
+
+        let ctx = &self.executor.ctx;
+        let mut ranked = candidates::rank(ctx.embeddings.as_ref(), &self.questions, proposed)
+            .await
+            .into_iter();
+        let question = ranked.next().context("the model proposed no question")?;
+        self.alternatives = ranked.collect();
+        Ok(question)
+    }
+
+    /// Ask `questions` the model asked together with the last one after it, unless they were
+    /// asked already.
+    pub fn queue(&mut self, questions: Vec<String>) {
+        for question in questions {
+            let asked = self
+                .questions
+                .iter()
+                .chain(&self.queued)
+                .any(|asked| asked.trim().eq_ignore_ascii_case(question.trim()));
+            if !asked {
+                self.queued.push_back(question);
+            }
+        }
+    }
+
+    /// The other candidates for the last generated question, if several were proposed
+    pub fn take_alternatives(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.alternatives)
+    }
+
     pub fn answer(&mut self, answer: String) {
+        let question = self.questions.get(self.answers.len()).cloned();
+        let answer = match question {
+            Some(question) => self.keep(&question, answer),
+            None => answer,
+        };
         self.answers.push(answer);
     }
+
+    /// Remove the last question, or the unanswered batch, so it can be regenerated.
+    pub fn discard_question(&mut self) {
+        if self.batch.is_empty() {
+            self.questions.pop();
+        } else {
+            self.batch.clear();
+        }
+    }
+
+    /// Remove the last answer so the question can be answered again.
+    pub fn discard_answer(&mut self) {
+        self.answers.pop();
+    }
+}
+
+/// `text` streamed character by character, like the questions of the model
+fn stream_text(text: &str) -> BoxStream<'static, anyhow::Result<String>> {
+    let characters: Vec<_> = text.chars().map(|c| Ok(c.to_string())).collect();
+    smooth_stream(futures::stream::iter(characters), Duration::from_millis(20)).boxed()
+}
+
+/// The questions in the answer to a batch request, without numbering or bullets. Lines that are
+/// not questions, e.g. an introduction, are skipped.
+fn parse_batch(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(|c: char| c.is_ascii_digit() || "-*.) ".contains(c))
+        })
+        .filter(|line| line.ends_with('?'))
+        .take(MAX_BATCH)
+        .map(String::from)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use futures::TryStreamExt;
 
-    use crate::{process::question::QAndA, Executor};
+    use super::parse_batch;
+    use crate::{process::question::QAndA, Executor, Settings};
+
+    #[test]
+    fn test_parse_batch() {
+        let text = "Here are the questions:\n1. Which language?\n- Should it have a GUI?\n\n* \
+                    Which operations?";
+        assert_eq!(parse_batch(text), [
+            "Which language?",
+            "Should it have a GUI?",
+            "Which operations?"
+        ]);
+        assert!(parse_batch("NONE").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_follow_up() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        let mut q_and_a = QAndA::resume(
+            executor,
+            "Create a web server",
+            vec!["Which port?".to_string()],
+            vec!["the usual".to_string()],
+        );
+        q_and_a.follow_up = Some("Do you mean port 80?".to_string());
+        assert!(q_and_a.is_follow_up());
+
+        // the follow-up is asked as it is, without the model
+        let question: String = q_and_a.gen_question().await?.try_collect().await?;
+        assert_eq!(question, "Do you mean port 80?");
+        assert!(!q_and_a.is_follow_up());
+
+        // its answer is not reviewed again
+        q_and_a.add_question(question);
+        q_and_a.answer("80".to_string());
+        q_and_a.review().await;
+        assert!(!q_and_a.is_follow_up());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queue() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        let mut q_and_a = QAndA::resume(
+            executor,
+            "Create a web server",
+            vec!["Which language?".to_string()],
+            vec!["Rust".to_string()],
+        );
+        q_and_a.queue(vec![
+            "which language?".to_string(),
+            "Should it serve static files?".to_string(),
+            "Should it serve static files?".to_string(),
+        ]);
+
+        // asked next, without the model
+        let question: String = q_and_a.gen_question().await?.try_collect().await?;
+        assert_eq!(question, "Should it serve static files?");
+        assert!(q_and_a.queued.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        let mut q_and_a = QAndA::resume(
+            executor,
+            "Create a web server",
+            vec!["Which language?".to_string()],
+            vec!["Rust".to_string()],
+        );
+
+        let items = q_and_a.add_batch(vec![
+            "Which port should it listen on?".to_string(),
+            "Should it serve static files?".to_string(),
+        ]);
+        assert_eq!(items.iter().map(|item| item.id).collect::<Vec<_>>(), [1, 2]);
+        assert!(items[0].validator.is_some());
+        assert!(!q_and_a.needs_question());
+
+        // answered out of order, and answered again
+        assert_eq!(q_and_a.answer_to(2, "no".to_string()), Some(false));
+        assert_eq!(q_and_a.answer_to(2, "yes".to_string()), Some(false));
+        assert_eq!(q_and_a.next_unanswered(), Some(1));
+        assert!(q_and_a
+            .transcript()
+            .contains("Should it serve static files?\nA: yes"));
+        assert_eq!(q_and_a.answer_to(3, "8080".to_string()), None);
+
+        assert_eq!(q_and_a.answer_to(1, "8080".to_string()), Some(true));
+        assert!(q_and_a.needs_question());
+        assert_eq!(q_and_a.questions(), [
+            "Which language?",
+            "Which port should it listen on?",
+            "Should it serve static files?"
+        ]);
+        assert_eq!(q_and_a.answers(), ["Rust", "8080", "yes"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret() -> anyhow::Result<()> {
+        let executor = Executor::new(Settings::default())?;
+        let mut q_and_a = QAndA::resume(
+            executor,
+            "Publish the crate",
+            vec!["What is your crates.io API token?".to_string()],
+            vec!["cio_abc".to_string()],
+        );
+
+        q_and_a.add_question("Please enter the password for the server?".to_string());
+        assert!(q_and_a.awaits_secret());
+        q_and_a.answer("hunter2".to_string());
+
+        assert_eq!(q_and_a.secrets(), [
+            ("SECRET_ANSWER_1".to_string(), "cio_abc".to_string()),
+            ("SECRET_ANSWER_2".to_string(), "hunter2".to_string())
+        ]);
+        // the model and the saved session only see where commands read them from
+        let transcript = q_and_a.transcript();
+        assert!(transcript.contains("$SECRET_ANSWER_2"));
+        assert!(!transcript.contains("cio_abc") && !transcript.contains("hunter2"));
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_get_question() -> anyhow::Result<()> {
-        let mut q_and_a = QAndA::new(Executor::new()?, "Create a calculator");
+        let mut q_and_a = QAndA::new(Executor::new(Settings::default())?, "Create a calculator");
         let question = q_and_a.gen_question().await?;
 
         let question: String = question.try_collect().await?;
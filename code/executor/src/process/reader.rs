@@ -1,4 +1,4 @@
-use anyhow::bail;
+use anyhow::{bail, Context};
 use derive_build::Build;
 use futures::{stream::SplitStream, StreamExt};
 use protocol::ClientPacket;
@@ -19,7 +19,7 @@ impl From<SplitStream<WebSocketStream<TcpStream>>> for Reader {
 
 impl Reader {
     pub async fn read(&mut self) -> anyhow::Result<ClientPacket> {
-        let msg = self.inner.next().await.unwrap()?;
+        let msg = self.inner.next().await.context("Connection closed")??;
 
         let Message::Text(msg) = msg else {
             bail!("Expected text message, got: {:?}", msg)
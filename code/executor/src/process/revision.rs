@@ -0,0 +1,101 @@
+//! Undo the last instruction or execution of a session after a
+//! [`Client::RevertLast`](protocol::client::Client::RevertLast).
+//!
+//! Before an instruction replaces the interview, the interview is kept. Before a plan is executed,
+//! the files of the working directory are kept in a [`Snapshot`]. Reverting takes the last of
+//! them back, so reverting repeatedly walks back through the session.
+
+use std::path::PathBuf;
+
+use protocol::{server, Packet};
+use tracing::{error, info};
+
+use crate::{
+    audit,
+    plan::Plan,
+    process::{question::QAndA, Process, StateViolation},
+    workspace::snapshot::Snapshot,
+    Comm,
+};
+
+/// What the session was before an instruction or execution changed it
+pub enum Revision {
+    /// the interview an instruction replaced, `None` if it was the first instruction, and the
+    /// last plan executed for it
+    Instruction(Option<Box<QAndA>>, Option<Plan>),
+    /// the files before a plan was executed, `None` if they could not be kept
+    Execution(Option<Snapshot>),
+}
+
+impl Revision {
+    /// Keep the files of the working directory `dir` before a plan is executed.
+    pub async fn execution(dir: PathBuf) -> Self {
+        let snapshot = tokio::task::spawn_blocking(move || Snapshot::take(&dir)).await;
+        match snapshot {
+            Ok(Ok(snapshot)) => Self::Execution(Some(snapshot)),
+            Ok(Err(e)) => {
+                error!("Failed to snapshot the working directory: {e:#}");
+                Self::Execution(None)
+            }
+            Err(e) => {
+                error!("Failed to snapshot the working directory: {e}");
+                Self::Execution(None)
+            }
+        }
+    }
+}
+
+impl<C: Comm + Send> Process<C> {
+    /// Undo the last instruction or execution and tell the frontend what was undone.
+    pub(super) async fn revert_last(&mut self) -> anyhow::Result<()> {
+        let Some(revision) = self.revisions.pop() else {
+            return self.reject(StateViolation::NothingToRevert).await;
+        };
+
+        let (instruction, summary) = match revision {
+            Revision::Instruction(previous, plan) => {
+                let dropped = self.q_and_a.as_ref().map(QAndA::instruction);
+                let summary = match (dropped, &previous) {
+                    (Some(dropped), Some(previous)) => format!(
+                        "dropped the instruction `{dropped}`, back to `{}`",
+                        previous.instruction()
+                    ),
+                    (Some(dropped), None) => format!("dropped the instruction `{dropped}`"),
+                    (None, _) => "dropped the last instruction".to_string(),
+                };
+                self.set_session(previous.map(|previous| *previous));
+                self.plan = plan;
+                (true, summary)
+            }
+            Revision::Execution(Some(snapshot)) => {
+                let dir = self.sessions.workdir(self.id);
+                let restored = tokio::task::spawn_blocking(move || snapshot.restore(&dir)).await?;
+                match restored {
+                    Ok(restored) => (false, restored.summary()),
+                    Err(e) => {
+                        error!("Failed to revert the execution: {e:#}");
+                        return self
+                            .send_error(&e.context("Failed to revert the execution"), true)
+                            .await;
+                    }
+                }
+            }
+            Revision::Execution(None) => (
+                false,
+                "the files before the execution were not kept, nothing was restored".to_string(),
+            ),
+        };
+
+        info!(instruction, "Reverted: {summary}");
+        self.executor.ctx.audit(&audit::Event::Reverted {
+            instruction,
+            summary: &summary,
+        });
+        self.comm
+            .send(Packet::server(server::Reverted {
+                instruction,
+                summary,
+            }))
+            .await
+    }
+}
@@ -0,0 +1,156 @@
+//! Route every [`Client`] packet to its [`Handler`], after the [`Middleware`] of the router.
+//!
+//! Every packet kind has its own handler in [`handlers`](super::handlers). Packets are cast into
+//! a handler with the `cast` method `Discriminant` generates, which only compiles if every kind
+//! implements [`Handler`], so a new packet kind cannot be forgotten.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use protocol::{client::Client, Packet};
+use tracing::{debug, Instrument};
+
+use crate::{
+    process::{state, Process},
+    Comm,
+};
+
+/// Handles one kind of packet, implemented by the struct `Discriminant` generates for it
+#[async_trait]
+pub trait Handler<C: Comm + Send> {
+    async fn handle(self: Box<Self>, process: &mut Process<C>) -> anyhow::Result<()>;
+}
+
+/// Whether a packet continues to its handler
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// the middleware dealt with the packet, e.g. by rejecting it
+    Stop,
+}
+
+/// Runs before the handler of every packet, in the order it was added to the [`Router`]
+#[async_trait]
+pub trait Middleware<C: Comm + Send>: Send + Sync {
+    async fn before(&self, process: &mut Process<C>, packet: &Client) -> anyhow::Result<Flow>;
+}
+
+pub struct Router<C> {
+    middleware: Vec<Arc<dyn Middleware<C>>>,
+}
+
+impl<C: Comm + Send> Default for Router<C> {
+    /// Logs every packet and rejects those the state of the session does not allow
+    fn default() -> Self {
+        Self::empty().with(Trace).with(Validate)
+    }
+}
+
+impl<C: Comm + Send> Router<C> {
+    /// A router without middleware
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Run `middleware` before the handlers, after the middleware added so far.
+    #[must_use]
+    pub fn with(mut self, middleware: impl Middleware<C> + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Pass `packet` through the middleware to its handler.
+    ///
+    /// # Errors
+    /// If a middleware or the handler failed.
+    pub async fn route(
+        &self,
+        process: &mut Process<C>,
+        packet: Packet<Client>,
+    ) -> anyhow::Result<()> {
+        let span = tracing::info_span!("packet", kind = state::name(&packet.data));
+
+        async {
+            for middleware in &self.middleware {
+                if middleware.before(process, &packet.data).await? == Flow::Stop {
+                    return Ok(());
+                }
+            }
+
+            let handler = packet.data.cast::<dyn Handler<C> + Send>();
+            handler.handle(process).await
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+/// Logs every packet
+pub struct Trace;
+
+#[async_trait]
+impl<C: Comm + Send> Middleware<C> for Trace {
+    async fn before(&self, process: &mut Process<C>, packet: &Client) -> anyhow::Result<Flow> {
+        debug!(session = %process.id, state = ?process.state, "Handling {}", state::name(packet));
+        Ok(Flow::Continue)
+    }
+}
+
+/// Rejects packets the state of the session does not allow, see [`State::check`]
+///
+/// [`State::check`]: super::state::State::check
+pub struct Validate;
+
+#[async_trait]
+impl<C: Comm + Send> Middleware<C> for Validate {
+    async fn before(&self, process: &mut Process<C>, packet: &Client) -> anyhow::Result<Flow> {
+        match process.state.check(packet) {
+            Ok(()) => Ok(Flow::Continue),
+            Err(violation) => {
+                process.reject(violation).await?;
+                Ok(Flow::Stop)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{client, server::Server, trace::Tracer, Packet};
+
+    use super::Router;
+    use crate::{ctx, process::Process, Executor, SessionManager, SimpleComm};
+
+    #[tokio::test]
+    async fn test_route() -> anyhow::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_frontend, frontend_rx) = tokio::sync::mpsc::unbounded_channel();
+        let comm = SimpleComm {
+            tx,
+            rx: frontend_rx,
+            tracer: Tracer::default(),
+        };
+        let executor = Executor { ctx: ctx()? };
+        let mut process = Process::new(executor, SessionManager::new(), comm);
+        let router = Router::default();
+
+        // the state does not allow it, the handler never runs
+        router
+            .route(&mut process, Packet::client(client::Execute))
+            .await?;
+        assert!(matches!(
+            rx.try_recv()?.data,
+            Server::Rejected { reason } if reason == "cannot handle Execute before an instruction was given"
+        ));
+
+        router
+            .route(&mut process, Packet::client(client::Ping))
+            .await?;
+        assert!(matches!(rx.try_recv()?.data, Server::Pong));
+
+        Ok(())
+    }
+}
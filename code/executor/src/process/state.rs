@@ -0,0 +1,336 @@
+use std::fmt::{Display, Formatter};
+
+use protocol::client::Client;
+
+/// The state of a session
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// No instruction was given yet (or the first question was cancelled)
+    Idle,
+    /// Questions about the instruction are being asked and answered
+    Interviewing,
+    /// The plan is being executed
+    Executing,
+}
+
+/// A packet arrived in a state where it is not allowed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StateViolation {
+    /// The packet requires an instruction, but none was given yet
+    NoInstruction { packet: &'static str },
+    /// A plan is already being executed
+    AlreadyExecuting,
+    /// The packet cannot be handled while a plan is being executed
+    Busy { packet: &'static str },
+    /// A session can only be resumed or cloned before anything else happened on the connection
+    AlreadyStarted { packet: &'static str },
+    /// Commands are only confirmed while a plan is being executed
+    NothingToConfirm,
+    /// Conflicts are only resolved while a generated file is waiting for it
+    NoConflict,
+    /// Sources are only vetoed while a plan is being executed
+    NothingToVeto,
+    /// An answer refers to a question that is not waiting for one
+    UnknownQuestion { id: u64 },
+    /// No instruction was given or plan executed since the session started, or all were reverted
+    NothingToRevert,
+    /// Steps can only be selected from a plan that was executed before
+    NoPlan,
+}
+
+impl Display for StateViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoInstruction { packet } => {
+                write!(f, "cannot handle {packet} before an instruction was given")
+            }
+            Self::AlreadyExecuting => f.write_str("a plan is already being executed"),
+            Self::Busy { packet } => {
+                write!(f, "cannot handle {packet} while a plan is being executed")
+            }
+            Self::AlreadyStarted { packet } => {
+                write!(f, "cannot handle {packet} after the session started")
+            }
+            Self::NothingToConfirm => f.write_str("no command is waiting for confirmation"),
+            Self::NoConflict => f.write_str("no file is waiting for its conflict to be resolved"),
+            Self::NothingToVeto => {
+                f.write_str("no source can be vetoed while no plan is being executed")
+            }
+            Self::UnknownQuestion { id } => write!(f, "question {id} is not waiting for an answer"),
+            Self::NothingToRevert => f.write_str("no instruction or execution is left to revert"),
+            Self::NoPlan => f.write_str("no plan was executed in this session yet"),
+        }
+    }
+}
+
+impl std::error::Error for StateViolation {}
+
+pub fn name(packet: &Client) -> &'static str {
+    match packet {
+        Client::Instruction { .. } => "Instruction",
+        Client::Answer { .. } => "Answer",
+        Client::Regenerate => "Regenerate",
+        Client::Cancel => "Cancel",
+        Client::Ping => "Ping",
+        Client::Resume { .. } => "Resume",
+        Client::Execute => "Execute",
+        Client::ListMemory => "ListMemory",
+        Client::Remember { .. } => "Remember",
+        Client::Forget { .. } => "Forget",
+        Client::Confirm { .. } => "Confirm",
+        Client::Latency => "Latency",
+        Client::AnswerTo { .. } => "AnswerTo",
+        Client::ListSessions => "ListSessions",
+        Client::CloneRepo { .. } => "CloneRepo",
+        Client::ResolveConflict { .. } => "ResolveConflict",
+        Client::VetoSource { .. } => "VetoSource",
+        Client::RevertLast => "RevertLast",
+        Client::ExecuteSteps { .. } => "ExecuteSteps",
+        Client::SetWorkspace { .. } => "SetWorkspace",
+    }
+}
+
+impl State {
+    /// Check whether `packet` can be processed in this state.
+    ///
+    /// # Errors
+    /// If the packet is not allowed in this state.
+    pub fn check(self, packet: &Client) -> Result<(), StateViolation> {
+        let packet_name = name(packet);
+
+        match (self, packet) {
+            (
+                _,
+                Client::Ping
+                | Client::Latency
+                | Client::ListSessions
+                | Client::Cancel
+                | Client::ListMemory
+                | Client::Remember { .. }
+                | Client::Forget { .. },
+            )
+            | (Self::Idle | Self::Interviewing, Client::Instruction { .. } | Client::RevertLast)
+            | (
+                Self::Idle,
+                Client::Resume { .. } | Client::CloneRepo { .. } | Client::SetWorkspace { .. },
+            )
+            | (
+                Self::Interviewing,
+                Client::Answer { .. }
+                | Client::AnswerTo { .. }
+                | Client::Regenerate
+                | Client::Execute
+                | Client::ExecuteSteps { .. },
+            )
+            | (
+                Self::Executing,
+                Client::Confirm { .. } | Client::ResolveConflict { .. } | Client::VetoSource { .. },
+            ) => Ok(()),
+            (
+                Self::Idle,
+                Client::Answer { .. }
+                | Client::AnswerTo { .. }
+                | Client::Regenerate
+                | Client::Execute
+                | Client::ExecuteSteps { .. },
+            ) => Err(StateViolation::NoInstruction {
+                packet: packet_name,
+            }),
+            (
+                Self::Interviewing,
+                Client::Resume { .. } | Client::CloneRepo { .. } | Client::SetWorkspace { .. },
+            ) => Err(StateViolation::AlreadyStarted {
+                packet: packet_name,
+            }),
+            (Self::Idle | Self::Interviewing, Client::Confirm { .. }) => {
+                Err(StateViolation::NothingToConfirm)
+            }
+            (Self::Idle | Self::Interviewing, Client::ResolveConflict { .. }) => {
+                Err(StateViolation::NoConflict)
+            }
+            (Self::Idle | Self::Interviewing, Client::VetoSource { .. }) => {
+                Err(StateViolation::NothingToVeto)
+            }
+            (Self::Executing, Client::Execute | Client::ExecuteSteps { .. }) => {
+                Err(StateViolation::AlreadyExecuting)
+            }
+            (
+                Self::Executing,
+                Client::Instruction { .. }
+                | Client::Answer { .. }
+                | Client::AnswerTo { .. }
+                | Client::Regenerate
+                | Client::Resume { .. }
+                | Client::CloneRepo { .. }
+                | Client::SetWorkspace { .. }
+                | Client::RevertLast,
+            ) => Err(StateViolation::Busy {
+                packet: packet_name,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{client::Client, Resolution};
+    use uuid::Uuid;
+
+    use super::{State, StateViolation};
+
+    #[test]
+    fn test_all_transitions() {
+        use StateViolation::{
+            AlreadyExecuting, AlreadyStarted, Busy, NoConflict, NoInstruction, NothingToConfirm,
+            NothingToVeto,
+        };
+
+        let ok = Ok(());
+        let busy = |packet| Err(Busy { packet });
+        let no_instruction = |packet| Err(NoInstruction { packet });
+        let started = |packet| Err(AlreadyStarted { packet });
+
+        // the packet, then whether it is allowed when idle, interviewing and executing
+        let table = [
+            (Client::Ping, ok, ok, ok),
+            (Client::Latency, ok, ok, ok),
+            (Client::ListSessions, ok, ok, ok),
+            (Client::Cancel, ok, ok, ok),
+            (Client::ListMemory, ok, ok, ok),
+            (
+                Client::Remember {
+                    preference: "always uses tokio".to_string(),
+                },
+                ok,
+                ok,
+                ok,
+            ),
+            (Client::Forget { id: 1 }, ok, ok, ok),
+            (
+                Client::Instruction {
+                    instruction: "Create a calculator".to_string(),
+                },
+                ok,
+                ok,
+                busy("Instruction"),
+            ),
+            (Client::RevertLast, ok, ok, busy("RevertLast")),
+            (
+                Client::Resume {
+                    session: Uuid::nil(),
+                    key: String::new(),
+                    instruction: "Create a calculator".to_string(),
+                    questions: vec![],
+                    answers: vec![],
+                },
+                ok,
+                started("Resume"),
+                busy("Resume"),
+            ),
+            (
+                Client::CloneRepo {
+                    url: "https://github.com/getcollective-ai/collective.git".to_string(),
+                    branch: None,
+                },
+                ok,
+                started("CloneRepo"),
+                busy("CloneRepo"),
+            ),
+            (
+                Client::SetWorkspace {
+                    path: "/home/user/calculator".to_string(),
+                },
+                ok,
+                started("SetWorkspace"),
+                busy("SetWorkspace"),
+            ),
+            (
+                Client::Answer {
+                    answer: "Rust".to_string(),
+                },
+                no_instruction("Answer"),
+                ok,
+                busy("Answer"),
+            ),
+            (
+                Client::AnswerTo {
+                    id: 0,
+                    answer: "Rust".to_string(),
+                },
+                no_instruction("AnswerTo"),
+                ok,
+                busy("AnswerTo"),
+            ),
+            (
+                Client::Regenerate,
+                no_instruction("Regenerate"),
+                ok,
+                busy("Regenerate"),
+            ),
+            (
+                Client::Execute,
+                no_instruction("Execute"),
+                ok,
+                Err(AlreadyExecuting),
+            ),
+            (
+                Client::ExecuteSteps { steps: vec![1] },
+                no_instruction("ExecuteSteps"),
+                ok,
+                Err(AlreadyExecuting),
+            ),
+            (
+                Client::Confirm { approved: true },
+                Err(NothingToConfirm),
+                Err(NothingToConfirm),
+                ok,
+            ),
+            (
+                Client::ResolveConflict {
+                    resolution: Resolution::Keep,
+                },
+                Err(NoConflict),
+                Err(NoConflict),
+                ok,
+            ),
+            (
+                Client::VetoSource { id: 1 },
+                Err(NothingToVeto),
+                Err(NothingToVeto),
+                ok,
+            ),
+        ];
+
+        for (packet, idle, interviewing, executing) in table {
+            for (state, expected) in [
+                (State::Idle, idle),
+                (State::Interviewing, interviewing),
+                (State::Executing, executing),
+            ] {
+                assert_eq!(
+                    state.check(&packet),
+                    expected,
+                    "state: {state:?}, packet: {packet:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_duplicate_execute() {
+        assert_eq!(State::Interviewing.check(&Client::Execute), Ok(()));
+        assert_eq!(
+            State::Executing.check(&Client::Execute),
+            Err(StateViolation::AlreadyExecuting)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let violation = StateViolation::NoInstruction { packet: "Execute" };
+        assert_eq!(
+            violation.to_string(),
+            "cannot handle Execute before an instruction was given"
+        );
+    }
+}
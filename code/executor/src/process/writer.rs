@@ -1,8 +1,15 @@
 use derive_build::Build;
 use futures::{stream::SplitSink, SinkExt};
-use protocol::ServerPacket;
+use protocol::{
+    delivery::{self, RETRY_DELAY, SEND_ATTEMPTS},
+    ServerPacket,
+};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tokio_tungstenite::{
+    tungstenite::{Error, Message},
+    WebSocketStream,
+};
+use tracing::debug;
 
 #[derive(Build)]
 pub struct Writer {
@@ -17,13 +24,32 @@ impl From<SplitSink<WebSocketStream<TcpStream>, Message>> for Writer {
 }
 
 impl Writer {
+    /// Send `element`, again if sending fails transiently. The frontend drops it if it arrives
+    /// twice, see [`delivery`].
     pub async fn write(&mut self, element: ServerPacket) -> anyhow::Result<()> {
         let s = serde_json::to_string(&element)?;
 
-        let message = Message::Text(s);
-
-        self.inner.send(message).await?;
+        for attempt in 1.. {
+            let message = Message::Text(s.clone());
+            match self.inner.send(message).await {
+                Ok(()) => break,
+                Err(e) if attempt < SEND_ATTEMPTS && is_transient(&e) => {
+                    debug!("Sending packet {} again: {e}", element.id);
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
 
         Ok(())
     }
 }
+
+/// Whether the websocket can still send after `e`
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Io(e) => delivery::is_transient(e.kind()),
+        Error::SendQueueFull(_) => true,
+        _ => false,
+    }
+}
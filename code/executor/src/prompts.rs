@@ -0,0 +1,420 @@
+//! Prompt templates, customizable with a JSON file or a directory.
+//!
+//! The file maps [`Prompt`]s to the template replacing the default, e.g.
+//! `{"codegen": "You write idiomatic Rust. Only output the content of the file."}`. A directory
+//! has a file per template instead, named after the prompt, e.g. `codegen.txt`. Prompts missing
+//! from either keep their default. In dev mode the file or directory is watched and reloaded when
+//! it changes, so prompts can be tuned against a live session. Another file or directory can be
+//! switched to while running, see [`crate::config`].
+//!
+//! Templates refer to variables as `{name}`, see [`Prompts::render`]. Which variables a prompt has
+//! is documented on its [`Prompt`].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+use crate::{
+    command::codegen,
+    commit, diagnosis, guardrails, memory, plan,
+    process::{conversation, question},
+};
+
+/// How often the prompt file is checked for changes in dev mode
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Prompt {
+    /// Introduces the plan request, with the `{workspace}` it is executed in. The plan format is
+    /// always appended.
+    Plan,
+    /// Generating the content of a file at `{path}`
+    CodeGen,
+    /// Classifying a failed step. Has to ask for the JSON the diagnosis is parsed from.
+    Diagnosis,
+    /// Extracting preferences to remember. Has to ask for one per line, or `NONE`.
+    Learn,
+    /// Writing the commit message for what a plan changed. Has to ask for the JSON the message
+    /// is parsed from.
+    Commit,
+    /// Asking the next clarifying question about the `{instruction}`, with the parts of the
+    /// workspace `{cited}` for it. The questions answered so far follow.
+    Question,
+    /// Asking up to `{max}` clarifying questions about the `{instruction}` at once, with the parts
+    /// of the workspace `{cited}` for it. Has to ask for one per line, or `NONE`.
+    Batch,
+    /// Summarizing the older questions and answers of a long session, with a cheaper model
+    Summary,
+    /// Checking the last answer against its question and the earlier answers. Has to ask for a
+    /// clarifying question, or `NONE`.
+    Review,
+    /// Judging whether a plan carries out the instruction, with a cheaper model. Has to ask for
+    /// `OK`, or what is wrong.
+    Judge,
+}
+
+impl Prompt {
+    const ALL: [Self; 10] = [
+        Self::Plan,
+        Self::CodeGen,
+        Self::Diagnosis,
+        Self::Learn,
+        Self::Commit,
+        Self::Question,
+        Self::Batch,
+        Self::Summary,
+        Self::Review,
+        Self::Judge,
+    ];
+
+    /// The key in the prompt file, and the name of the file in the prompt directory
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Plan => "plan",
+            Self::CodeGen => "codegen",
+            Self::Diagnosis => "diagnosis",
+            Self::Learn => "learn",
+            Self::Commit => "commit",
+            Self::Question => "question",
+            Self::Batch => "batch",
+            Self::Summary => "summary",
+            Self::Review => "review",
+            Self::Judge => "judge",
+        }
+    }
+
+    fn default_text(self) -> &'static str {
+        match self {
+            Self::Plan => plan::PLAN_PROMPT,
+            Self::CodeGen => codegen::SYSTEM_PROMPT,
+            Self::Diagnosis => diagnosis::SYSTEM_PROMPT,
+            Self::Learn => memory::LEARN_PROMPT,
+            Self::Commit => commit::SYSTEM_PROMPT,
+            Self::Question => question::QUESTION_PROMPT,
+            Self::Batch => question::BATCH_PROMPT,
+            Self::Summary => conversation::SUMMARY_PROMPT,
+            Self::Review => question::REVIEW_PROMPT,
+            Self::Judge => guardrails::JUDGE_PROMPT,
+        }
+    }
+}
+
+type Custom = HashMap<Prompt, String>;
+
+#[derive(Clone, Default)]
+pub struct Prompts {
+    path: Arc<RwLock<Option<PathBuf>>>,
+    custom: Arc<RwLock<Custom>>,
+}
+
+fn read(path: &Path) -> anyhow::Result<Custom> {
+    if path.is_dir() {
+        return read_dir(path);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read prompts at {}", path.display()))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse prompts at {}", path.display()))
+}
+
+/// The templates in `dir`, one file per prompt named after it, e.g. `plan.txt`
+fn read_dir(dir: &Path) -> anyhow::Result<Custom> {
+    let mut custom = Custom::new();
+
+    for path in files(dir)? {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let prompt = Prompt::ALL
+            .into_iter()
+            .find(|prompt| prompt.name() == name)
+            .with_context(|| format!("{} is not a prompt", path.display()))?;
+
+        let template = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the prompt at {}", path.display()))?;
+        // editors end files with a newline
+        custom.insert(prompt, template.trim_end().to_string());
+    }
+
+    Ok(custom)
+}
+
+/// The files in `dir`, hidden ones like editor swap files excluded
+fn files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read prompts at {}", dir.display()))?;
+
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect())
+}
+
+/// When the prompts at `path` last changed, the latest of its files for a directory
+fn modified(path: &Path) -> Option<SystemTime> {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+
+    // removing a file changes the directory, editing one only the file
+    let files = if path.is_dir() {
+        files(path).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    files
+        .iter()
+        .map(|file| modified(file))
+        .chain([modified(path)])
+        .max()
+        .flatten()
+}
+
+/// `template` with every `{name}` of `vars` replaced by its value. Other braces are kept, e.g.
+/// those of JSON examples.
+fn interpolate(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            let (_, value) = vars.iter().find(|(var, _)| *var == name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+impl Prompts {
+    /// The default prompts, overridden by the prompts in the file or directory at `path` if
+    /// given.
+    ///
+    /// # Errors
+    /// If the prompts cannot be read or one of them is unknown.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let custom = path.as_deref().map(read).transpose()?.unwrap_or_default();
+
+        Ok(Self {
+            path: Arc::new(RwLock::new(path)),
+            custom: Arc::new(RwLock::new(custom)),
+        })
+    }
+
+    /// Use the prompts in the file or directory at `path` instead, only the defaults without one.
+    ///
+    /// # Errors
+    /// If the prompts cannot be read or one of them is unknown, the current ones are kept then.
+    pub fn switch(&self, path: Option<PathBuf>) -> anyhow::Result<()> {
+        let custom = path.as_deref().map(read).transpose()?.unwrap_or_default();
+        *self.custom.write() = custom;
+        *self.path.write() = path;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn get(&self, prompt: Prompt) -> String {
+        self.custom
+            .read()
+            .get(&prompt)
+            .map_or_else(|| prompt.default_text().to_string(), Clone::clone)
+    }
+
+    /// The template of `prompt` with its variables replaced by `vars`, e.g.
+    /// `[("instruction", "Build a calculator")]`.
+    #[must_use]
+    pub fn render(&self, prompt: Prompt, vars: &[(&str, &str)]) -> String {
+        interpolate(&self.get(prompt), vars)
+    }
+
+    /// Reload the prompt file or directory whenever it changes, announcing it on `notices`. A
+    /// file or directory that is switched to is watched instead.
+    ///
+    /// Stops once the prompts are dropped.
+    pub fn watch(&self, notices: broadcast::Sender<String>) {
+        let path = Arc::downgrade(&self.path);
+        let custom = Arc::downgrade(&self.custom);
+        crate::tasks::spawn("prompts watcher", watch(path, custom, notices));
+    }
+}
+
+async fn watch(
+    path: Weak<RwLock<Option<PathBuf>>>,
+    custom: Weak<RwLock<Custom>>,
+    notices: broadcast::Sender<String>,
+) {
+    let mut watched = None;
+    let mut last_modified = None;
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let (Some(path), Some(custom)) = (path.upgrade(), custom.upgrade()) else {
+            return;
+        };
+        // without custom prompts there is nothing to reload
+        let Some(path) = path.read().clone() else {
+            continue;
+        };
+
+        if watched.as_ref() != Some(&path) {
+            info!("Watching {} for changes", path.display());
+            last_modified = modified(&path);
+            watched = Some(path);
+            continue;
+        }
+
+        let modified = modified(&path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        let notice = match read(&path) {
+            Ok(reloaded) => {
+                *custom.write() = reloaded;
+                format!("Reloaded prompts from {}", path.display())
+            }
+            // keep the previous prompts until the file is fixed
+            Err(e) => {
+                error!("{e:#}");
+                format!("{e:#}")
+            }
+        };
+
+        info!("{notice}");
+
+        // nobody is connected
+        let _ = notices.send(notice);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{interpolate, Prompt, Prompts};
+
+    #[test]
+    fn test_load() -> anyhow::Result<()> {
+        let defaults = Prompts::default();
+        assert!(defaults.get(Prompt::CodeGen).contains("single file"));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("prompts.json");
+
+        std::fs::write(&path, r#"{"codegen": "Write idiomatic Rust."}"#)?;
+        let prompts = Prompts::load(Some(path.clone()))?;
+        assert_eq!(prompts.get(Prompt::CodeGen), "Write idiomatic Rust.");
+        assert_eq!(prompts.get(Prompt::Plan), defaults.get(Prompt::Plan));
+
+        std::fs::write(&path, r#"{"poetry": "Write a poem."}"#)?;
+        assert!(Prompts::load(Some(path)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_dir() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("question.txt"),
+            "Ask about {instruction}.\n",
+        )?;
+        std::fs::write(dir.path().join(".question.txt.swp"), "")?;
+
+        let prompts = Prompts::load(Some(dir.path().to_path_buf()))?;
+        assert_eq!(
+            prompts.render(Prompt::Question, &[("instruction", "a calculator")]),
+            "Ask about a calculator."
+        );
+        assert_eq!(
+            prompts.get(Prompt::Batch),
+            Prompts::default().get(Prompt::Batch)
+        );
+
+        std::fs::write(dir.path().join("poetry.txt"), "Write a poem.")?;
+        assert!(Prompts::load(Some(dir.path().to_path_buf())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let vars = [("path", "src/main.rs"), ("max", "5")];
+        assert_eq!(
+            interpolate("Write {path}, at most {max} lines.", &vars),
+            "Write src/main.rs, at most 5 lines."
+        );
+        // unknown variables and JSON are kept
+        assert_eq!(
+            interpolate(r#"{"path": "{unknown}"} {path"#, &vars),
+            r#"{"path": "{unknown}"} {path"#
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        // the files in a directory are named like the keys of a file
+        for prompt in Prompt::ALL {
+            let name = serde_json::Value::from(prompt.name());
+            assert_eq!(serde_json::from_value::<Prompt>(name).unwrap(), prompt);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("prompts.json");
+        std::fs::write(&path, r#"{"plan": "Plan carefully."}"#)?;
+
+        let prompts = Prompts::load(Some(path.clone()))?;
+        let (tx, mut rx) = tokio::sync::broadcast::channel(4);
+        prompts.watch(tx);
+
+        // make sure the modification time changes on file systems with a coarse resolution
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        std::fs::write(&path, r#"{"plan": "Plan quickly."}"#)?;
+
+        let notice = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await??;
+        assert!(notice.starts_with("Reloaded prompts"));
+        assert_eq!(prompts.get(Prompt::Plan), "Plan quickly.");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+//! Repositories the executor clones itself, so a thin frontend can drive work on the machine the
+//! executor runs on.
+//!
+//! A [`Client::CloneRepo`](protocol::client::Client::CloneRepo) clones a repository into the
+//! empty working directory of the session, which remembers the cloned branch in its git config.
+//! Once a plan was committed there, pushing the commit to a new branch is proposed like a risky
+//! command, with a link to open a pull request on GitHub.
+//!
+//! Credentials for HTTPS remotes are read from `--git-credentials`:
+//!
+//! ```toml
+//! [hosts."github.com"]
+//! # `x-access-token` if omitted, which GitHub expects for tokens
+//! username = "x-access-token"
+//! # the token, or `env = "NAME"` to read it from the environment of the executor
+//! token = "ghp_..."
+//! ```
+//!
+//! They reach git through a credential helper in its environment, so they never appear in a
+//! command line, in the remote of the clone or in the output of a step. SSH remotes use the keys
+//! of the executor.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::commit::{self, git_with};
+
+/// The git config key of the branch a managed clone was cloned at
+const BASE_KEY: &str = "collective.base";
+
+/// The prefix of the branches commits are pushed to
+const BRANCH_PREFIX: &str = "collective/";
+
+/// Prints the credentials of the environment for `git credential fill`
+const HELPER: &str = "!f() { test \"$1\" = get && echo \"username=$COLLECTIVE_GIT_USERNAME\" && \
+                      echo \"password=$COLLECTIVE_GIT_TOKEN\"; }; f";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    hosts: BTreeMap<String, Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    #[serde(default = "default_username")]
+    username: String,
+    token: Option<String>,
+    env: Option<String>,
+}
+
+fn default_username() -> String {
+    "x-access-token".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Credential {
+    username: String,
+    token: String,
+}
+
+/// The credentials of the hosts repositories are cloned from
+#[derive(Debug, Default)]
+pub struct Credentials {
+    hosts: BTreeMap<String, Credential>,
+}
+
+impl Credentials {
+    /// Load the credentials at `path`, none without it.
+    ///
+    /// # Errors
+    /// - The file cannot be read or is not valid
+    /// - A token is read from an environment variable the executor does not have
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the git credentials {}", path.display()))?;
+
+        Self::parse(&content, |name| std::env::var(name).ok())
+            .with_context(|| format!("{} is not valid", path.display()))
+    }
+
+    /// Parse credentials, reading tokens declared with `env` from `var`.
+    fn parse(content: &str, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let file: File = toml::from_str(content)?;
+        let mut hosts = BTreeMap::new();
+
+        for (host, entry) in file.hosts {
+            let token = match (entry.token, entry.env) {
+                (Some(token), None) => token,
+                (None, Some(env)) => var(&env).with_context(|| {
+                    format!("The token of `{host}` is read from ${env}, which is not set")
+                })?,
+                _ => bail!("`{host}` needs either a `token` or an `env`"),
+            };
+            hosts.insert(host, Credential {
+                username: entry.username,
+                token,
+            });
+        }
+
+        Ok(Self { hosts })
+    }
+
+    /// The environment git needs to reach `url`, with the credentials of its host if it is an
+    /// HTTPS remote. Git never prompts for credentials, which would wait forever.
+    fn env(&self, url: &str) -> Vec<(String, String)> {
+        let mut env = vec![("GIT_TERMINAL_PROMPT".to_string(), "0".to_string())];
+
+        let credential = Url::parse(url)
+            .ok()
+            .filter(|url| url.scheme() == "https")
+            .and_then(|url| self.hosts.get(url.host_str()?).cloned());
+        if let Some(Credential { username, token }) = credential {
+            let vars = [
+                ("GIT_CONFIG_COUNT", "2"),
+                // an empty helper drops the helpers configured on the machine
+                ("GIT_CONFIG_KEY_0", "credential.helper"),
+                ("GIT_CONFIG_VALUE_0", ""),
+                ("GIT_CONFIG_KEY_1", "credential.helper"),
+                ("GIT_CONFIG_VALUE_1", HELPER),
+                ("COLLECTIVE_GIT_USERNAME", &username),
+                ("COLLECTIVE_GIT_TOKEN", &token),
+            ];
+            env.extend(
+                vars.into_iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            );
+        }
+
+        env
+    }
+}
+
+/// What was checked out by [`clone`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cloned {
+    pub branch: String,
+    /// the commit `branch` is at
+    pub head: String,
+}
+
+/// Make sure `url` is a remote repository. Local paths and other transports would let a frontend
+/// read any repository on the machine of the executor.
+///
+/// # Errors
+/// If `url` is not an HTTPS or SSH remote.
+pub fn check_url(url: &str) -> anyhow::Result<()> {
+    if let Ok(parsed) = Url::parse(url) {
+        ensure!(
+            matches!(parsed.scheme(), "https" | "ssh") && parsed.host_str().is_some(),
+            "{url} is not an HTTPS or SSH remote"
+        );
+        return Ok(());
+    }
+
+    // scp-like SSH remotes, e.g. `git@github.com:owner/repo.git`
+    let host = url
+        .split_once(':')
+        .and_then(|(user_host, _)| user_host.split_once('@'))
+        .map(|(_, host)| host);
+    ensure!(
+        host.is_some_and(|host| !host.is_empty() && !host.contains('/')),
+        "{url} is not an HTTPS or SSH remote"
+    );
+    Ok(())
+}
+
+/// Clone the repository at `url` into `dir`, which must not exist or be empty, checking out
+/// `branch` or the default branch.
+///
+/// # Errors
+/// - `url` is not a remote repository
+/// - `dir` is not empty
+/// - git failed, e.g. because the repository or the branch does not exist
+pub async fn clone(
+    dir: &Path,
+    url: &str,
+    branch: Option<&str>,
+    credentials: &Credentials,
+) -> anyhow::Result<Cloned> {
+    check_url(url)?;
+    clone_from(dir, url, branch, credentials).await
+}
+
+/// [`clone`] without checking `url`
+async fn clone_from(
+    dir: &Path,
+    url: &str,
+    branch: Option<&str>,
+    credentials: &Credentials,
+) -> anyhow::Result<Cloned> {
+    if let Ok(mut entries) = std::fs::read_dir(dir) {
+        ensure!(
+            entries.next().is_none(),
+            "The working directory is not empty"
+        );
+    }
+    let parent = dir
+        .parent()
+        .context("The working directory has no parent")?;
+    tokio::fs::create_dir_all(parent)
+        .await
+        .context("Failed to create the workspace")?;
+
+    let target = dir.to_string_lossy();
+    let mut args = vec!["clone", "--quiet"];
+    if let Some(branch) = branch {
+        args.extend(["--branch", branch]);
+    }
+    args.extend(["--", url, &target]);
+    git_with(parent, &args, None, &credentials.env(url)).await?;
+
+    let branch = git_with(dir, &["rev-parse", "--abbrev-ref", "HEAD"], None, &[]).await?;
+    let branch = branch.trim().to_string();
+    git_with(dir, &["config", BASE_KEY, &branch], None, &[]).await?;
+    let head = git_with(dir, &["rev-parse", "HEAD"], None, &[]).await?;
+
+    Ok(Cloned {
+        branch,
+        head: head.trim().to_string(),
+    })
+}
+
+/// The branch `dir` was cloned at, `None` if the executor did not clone it.
+pub async fn base(dir: &Path) -> Option<String> {
+    let base = git_with(dir, &["config", "--get", BASE_KEY], None, &[])
+        .await
+        .ok()?;
+    Some(base.trim().to_string()).filter(|base| !base.is_empty())
+}
+
+/// The branch a commit with `subject` is pushed to, e.g. `collective/add-a-verbose-flag`
+pub fn branch(subject: &str) -> String {
+    format!("{BRANCH_PREFIX}{}", commit::slug(subject))
+}
+
+/// The command [`push`] runs, shown to the frontend for approval
+pub fn push_command(branch: &str) -> String {
+    format!("git push origin HEAD:refs/heads/{branch}")
+}
+
+/// Push the commit checked out in `dir` to the new branch `branch` of its origin. Returns where
+/// a pull request into `base` can be opened, if the origin is on GitHub.
+///
+/// # Errors
+/// If git failed, e.g. because the credentials cannot push or `branch` exists.
+pub async fn push(
+    dir: &Path,
+    branch: &str,
+    base: &str,
+    credentials: &Credentials,
+) -> anyhow::Result<Option<String>> {
+    let origin = git_with(dir, &["remote", "get-url", "origin"], None, &[]).await?;
+    let origin = origin.trim();
+
+    let refspec = format!("HEAD:refs/heads/{branch}");
+    git_with(
+        dir,
+        &["push", "--quiet", "origin", &refspec],
+        None,
+        &credentials.env(origin),
+    )
+    .await?;
+
+    Ok(pull_request(origin, base, branch))
+}
+
+/// The page opening a pull request of `branch` into `base`, `None` if `origin` is not on GitHub
+fn pull_request(origin: &str, base: &str, branch: &str) -> Option<String> {
+    let path = match Url::parse(origin) {
+        Ok(url) if url.host_str() == Some("github.com") => url.path().to_string(),
+        Ok(_) => return None,
+        Err(_) => origin.strip_prefix("git@github.com:")?.to_string(),
+    };
+    let repo = path.trim_matches('/').trim_end_matches(".git");
+    let (owner, name) = repo.split_once('/')?;
+    if owner.is_empty() || name.is_empty() || name.contains('/') {
+        return None;
+    }
+
+    Some(format!(
+        "https://github.com/{repo}/compare/{base}...{branch}?expand=1"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{base, branch, check_url, clone_from, pull_request, push, Credentials};
+    use crate::commit::git_with;
+
+    async fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+        git_with(dir, args, None, &[]).await
+    }
+
+    #[test]
+    fn test_check_url() {
+        for url in [
+            "https://github.com/getcollective-ai/collective.git",
+            "ssh://git@github.com/getcollective-ai/collective.git",
+            "git@github.com:getcollective-ai/collective.git",
+        ] {
+            assert!(check_url(url).is_ok(), "{url}");
+        }
+
+        for url in [
+            "/etc",
+            "../other",
+            "file:///home/user/repo",
+            "ext::sh -c touch% /tmp/pwned",
+            "http://github.com/getcollective-ai/collective.git",
+            "C:/repo",
+        ] {
+            assert!(check_url(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_credentials() -> anyhow::Result<()> {
+        let credentials = Credentials::parse(
+            r#"
+            [hosts."github.com"]
+            env = "GITHUB_TOKEN"
+
+            [hosts."gitlab.com"]
+            username = "oauth2"
+            token = "glpat"
+            "#,
+            |name| (name == "GITHUB_TOKEN").then(|| "ghp".to_string()),
+        )?;
+
+        let env = credentials.env("https://github.com/owner/repo.git");
+        let var = |name: &str| {
+            env.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("COLLECTIVE_GIT_USERNAME"), Some("x-access-token"));
+        assert_eq!(var("COLLECTIVE_GIT_TOKEN"), Some("ghp"));
+        assert_eq!(var("GIT_TERMINAL_PROMPT"), Some("0"));
+
+        // other hosts and SSH remotes get no credentials
+        assert_eq!(credentials.env("https://example.com/repo.git").len(), 1);
+        assert_eq!(credentials.env("git@github.com:owner/repo.git").len(), 1);
+
+        assert!(Credentials::parse("[hosts.\"github.com\"]", |_| None).is_err());
+        assert!(Credentials::parse("[hosts.\"github.com\"]\nenv = \"MISSING\"", |_| None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pull_request() {
+        let url = "https://github.com/owner/repo/compare/main...collective/fix?expand=1";
+        assert_eq!(
+            pull_request(
+                "https://github.com/owner/repo.git",
+                "main",
+                "collective/fix"
+            )
+            .as_deref(),
+            Some(url)
+        );
+        assert_eq!(
+            pull_request("git@github.com:owner/repo.git", "main", "collective/fix").as_deref(),
+            Some(url)
+        );
+        assert_eq!(
+            pull_request(
+                "https://gitlab.com/owner/repo.git",
+                "main",
+                "collective/fix"
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clone_and_push() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let origin = dir.path().join("origin");
+        let work = dir.path().join("work");
+        std::fs::create_dir(&origin)?;
+        git(&origin, &["init", "-q", "-b", "trunk"]).await?;
+        git(&origin, &["config", "user.name", "Test"]).await?;
+        git(&origin, &["config", "user.email", "test@example.com"]).await?;
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "init"]).await?;
+        // pushing to the checked out branch of a non-bare repository is refused
+        git(&origin, &["checkout", "-q", "--detach"]).await?;
+
+        let credentials = Credentials::default();
+        let url = origin.to_string_lossy();
+        let cloned = clone_from(&work, &url, None, &credentials).await?;
+        assert_eq!(cloned.branch, "trunk");
+        assert_eq!(cloned.head.len(), 40);
+        assert_eq!(base(&work).await.as_deref(), Some("trunk"));
+        assert_eq!(base(&origin).await, None);
+
+        // the working directory is cloned into once
+        assert!(clone_from(&work, &url, None, &credentials).await.is_err());
+
+        let branch = branch("feat: add a --verbose flag");
+        assert_eq!(branch, "collective/add-a-verbose-flag");
+        assert_eq!(push(&work, &branch, "trunk", &credentials).await?, None);
+        let pushed = git(&origin, &["branch", "--list", &branch]).await?;
+        assert!(pushed.contains(&branch));
+
+        Ok(())
+    }
+}
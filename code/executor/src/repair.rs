@@ -0,0 +1,269 @@
+//! Get JSON the model wrote into shape when it does not parse.
+//!
+//! An answer that does not parse is sent back to the model with what is wrong with it, at most
+//! [`MAX_REPAIRS`] times. If the last answer is still invalid, [`lenient`] keeps the largest prefix
+//! of it that parses, which rescues answers that were cut off or trail off into prose.
+//!
+//! How every structured answer turned out is counted per [`Prompt`] and logged with the `repairs`
+//! target, so templates whose output often needs repairing stand out.
+
+use std::{collections::HashMap, fmt};
+
+use parking_lot::Mutex;
+use tokio_openai::{ChatRequest, Msg};
+use tracing::info;
+
+use crate::{prompts::Prompt, Ctx};
+
+/// How often an invalid answer is sent back to the model to be corrected
+pub const MAX_REPAIRS: usize = 2;
+
+/// How a structured answer turned out
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// it parsed at once
+    Valid,
+    /// it parsed after the model corrected it
+    Repaired,
+    /// only a prefix of it parsed, see [`lenient`]
+    Lenient,
+    /// nothing of it parsed
+    Failed,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Valid => "valid",
+            Self::Repaired => "repaired",
+            Self::Lenient => "partially parsed",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// The outcomes of the structured answers to a prompt
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Counts {
+    pub valid: u64,
+    pub repaired: u64,
+    pub lenient: u64,
+    pub failed: u64,
+}
+
+impl Counts {
+    /// The share of answers that were not valid at once
+    #[allow(clippy::cast_precision_loss)]
+    pub fn repair_rate(&self) -> f64 {
+        let total = self.valid + self.repaired + self.lenient + self.failed;
+        if total == 0 {
+            return 0.0;
+        }
+        (total - self.valid) as f64 / total as f64
+    }
+}
+
+/// The [`Counts`] of every prompt, shared by all sessions
+#[derive(Default)]
+pub struct Repairs {
+    counts: Mutex<HashMap<Prompt, Counts>>,
+}
+
+impl Repairs {
+    /// Count `outcome` for `prompt`, returning the counts so far.
+    pub fn record(&self, prompt: Prompt, outcome: Outcome) -> Counts {
+        let counts = {
+            let mut counts = self.counts.lock();
+            let counts = counts.entry(prompt).or_default();
+            match outcome {
+                Outcome::Valid => counts.valid += 1,
+                Outcome::Repaired => counts.repaired += 1,
+                Outcome::Lenient => counts.lenient += 1,
+                Outcome::Failed => counts.failed += 1,
+            }
+            *counts
+        };
+
+        info!(
+            target: "repairs",
+            prompt = prompt.name(),
+            %outcome,
+            valid = counts.valid,
+            repaired = counts.repaired,
+            lenient = counts.lenient,
+            failed = counts.failed,
+            "Structured output {outcome}, {:.0}% needed repairing",
+            counts.repair_rate() * 100.0
+        );
+        counts
+    }
+
+    /// The counts of every prompt with a structured answer so far, by the name of the prompt
+    pub fn counts(&self) -> Vec<(Prompt, Counts)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .lock()
+            .iter()
+            .map(|(prompt, counts)| (*prompt, *counts))
+            .collect();
+        counts.sort_by_key(|(prompt, _)| prompt.name());
+        counts
+    }
+}
+
+/// What to tell the model about its invalid answer
+pub fn correction(error: &anyhow::Error, format: &str) -> Msg {
+    Msg::user(format!(
+        "The answer is invalid: {error:#}. Answer with only the corrected {format}."
+    ))
+}
+
+/// Send `request` for the template `prompt` and parse the answer with `parse`, repairing it if it
+/// does not.
+///
+/// # Errors
+/// - The model could not be reached
+/// - No prefix of the answer parsed after [`MAX_REPAIRS`], with why the last answer did not
+pub async fn chat<T>(
+    ctx: &Ctx,
+    prompt: Prompt,
+    mut request: ChatRequest,
+    parse: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut repairs = 0;
+
+    loop {
+        let text = ctx.chat(request.clone()).await?;
+
+        let e = match parse(&text) {
+            Ok(parsed) => {
+                let outcome = if repairs == 0 {
+                    Outcome::Valid
+                } else {
+                    Outcome::Repaired
+                };
+                ctx.repairs.record(prompt, outcome);
+                return Ok(parsed);
+            }
+            Err(e) => e,
+        };
+
+        if repairs == MAX_REPAIRS {
+            return match lenient(&text, &parse) {
+                Some(parsed) => {
+                    ctx.repairs.record(prompt, Outcome::Lenient);
+                    Ok(parsed)
+                }
+                None => {
+                    ctx.repairs.record(prompt, Outcome::Failed);
+                    Err(e)
+                }
+            };
+        }
+
+        info!(target: "repairs", prompt = prompt.name(), output = text, "Repairing: {e:#}");
+        request.messages.push(Msg::assistant(text));
+        request.messages.push(correction(&e, "JSON"));
+        repairs += 1;
+    }
+}
+
+/// Parse the largest prefix of the JSON in `text` that `parse` accepts. Prefixes end before a
+/// comma of the outermost array or object, which is closed after them, so they only drop whole
+/// elements or fields. Text around the JSON is ignored.
+pub fn lenient<T>(text: &str, parse: impl Fn(&str) -> anyhow::Result<T>) -> Option<T> {
+    let text = utils::markdown::strip_fence(text);
+    let start = text.find(['[', '{'])?;
+    let json = &text[start..];
+
+    // where prefixes can end, with what closes them
+    let mut cuts: Vec<(usize, &str)> = Vec::new();
+    let mut open = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in json.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '[' => open.push(']'),
+            '{' => open.push('}'),
+            ']' | '}' => {
+                if open.pop() != Some(c) {
+                    break;
+                }
+                if open.is_empty() {
+                    cuts.push((index + c.len_utf8(), ""));
+                    break;
+                }
+            }
+            ',' if open == [']'] => cuts.push((index, "]")),
+            ',' if open == ['}'] => cuts.push((index, "}")),
+            _ => {}
+        }
+    }
+
+    cuts.into_iter()
+        .rev()
+        .find_map(|(end, closing)| parse(&format!("{}{closing}", &json[..end])).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lenient, Counts, Outcome, Repairs};
+    use crate::{plan::Plan, prompts::Prompt};
+
+    const STEP: &str =
+        r#"{"title": "List", "description": "", "command": {"type": "bash", "input": "ls"}}"#;
+
+    #[test]
+    fn test_lenient() {
+        let steps = |text: &str| lenient(text, Plan::parse).map(|plan| plan.steps.len());
+
+        // cut off in the middle of the third step
+        let truncated = format!(r#"[{STEP}, {STEP}, {{"title": "Bu"#);
+        assert_eq!(steps(&truncated), Some(2));
+
+        // prose after the plan, and a comma in a string
+        let chatty = format!(
+            r#"Here is the plan: [{STEP}, {{"title": "a, b", "description": "", "command": {{"type": "bash", "input": "echo \"]\""}}}}] Good luck!"#
+        );
+        assert_eq!(steps(&chatty), Some(2));
+
+        // the second step depends on a step after it
+        let invalid = format!(
+            r#"[{STEP}, {{"title": "Run", "description": "", "command": {{"type": "bash", "input": "ls"}}, "dependencies": [2]}}, {STEP}]"#
+        );
+        assert_eq!(steps(&invalid), Some(1));
+
+        assert_eq!(steps(r#"[{"title": "Li"#), None);
+        assert_eq!(steps("no plan"), None);
+    }
+
+    #[test]
+    fn test_repairs() {
+        let repairs = Repairs::default();
+        repairs.record(Prompt::Commit, Outcome::Valid);
+        repairs.record(Prompt::Diagnosis, Outcome::Failed);
+        repairs.record(Prompt::Commit, Outcome::Repaired);
+        let counts = repairs.record(Prompt::Commit, Outcome::Lenient);
+
+        assert_eq!(counts, Counts {
+            valid: 1,
+            repaired: 1,
+            lenient: 1,
+            failed: 0,
+        });
+        assert!((counts.repair_rate() - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert!(Counts::default().repair_rate().abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,305 @@
+//! Keep the responses of the provider on disk, so running the same interview or plan again, e.g.
+//! while working on a prompt, neither costs tokens nor waits for the model.
+//!
+//! Every response is a JSON file named after the SHA-256 of what was requested. Responses older
+//! than the TTL are not used and deleted. Once the responses take more than the capacity, the
+//! oldest are deleted.
+
+use std::{
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+/// What a response is kept under: the hex SHA-256 of the kind and the serialized request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key(String);
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Responses kept in a directory, shared by every session
+#[derive(Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    /// how long a response is used after it was written
+    ttl: Duration,
+    /// bytes the responses take at most
+    capacity: u64,
+    /// bytes the responses take, shared by the clones
+    size: Arc<AtomicU64>,
+}
+
+/// A response file
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl ResponseCache {
+    /// Keep responses for `ttl` in `dir`, creating it if needed, deleting the oldest beyond
+    /// `capacity` bytes.
+    ///
+    /// # Errors
+    /// If `dir` cannot be created or read
+    pub fn open(dir: PathBuf, ttl: Duration, capacity: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the cache {}", dir.display()))?;
+        let size = entries(&dir)?.iter().map(|entry| entry.size).sum();
+
+        Ok(Self {
+            dir,
+            ttl,
+            capacity,
+            size: Arc::new(AtomicU64::new(size)),
+        })
+    }
+
+    /// The key of `request`. `kind` tells requests apart that serialize the same but are answered
+    /// differently, e.g. by different endpoints.
+    ///
+    /// # Errors
+    /// If `request` cannot be serialized
+    pub fn key(kind: &str, request: &impl Serialize) -> anyhow::Result<Key> {
+        let mut hasher = Sha256::new();
+        hasher.update(kind.as_bytes());
+        hasher.update([0]);
+        serde_json::to_writer(&mut hasher, request).context("Failed to serialize the request")?;
+        Ok(Key(format!("{:x}", hasher.finalize())))
+    }
+
+    fn path(&self, key: &Key) -> PathBuf {
+        self.dir.join(format!("{}.json", key.0))
+    }
+
+    /// The response kept under `key`, `None` if there is none or it expired
+    pub fn get<T: DeserializeOwned>(&self, key: &Key) -> Option<T> {
+        let path = self.path(key);
+        let metadata = fs::metadata(&path).ok()?;
+
+        // a clock set back leaves responses fresh
+        let age = metadata.modified().ok()?.elapsed().unwrap_or_default();
+        if age > self.ttl {
+            debug!("Response {} expired", key.0);
+            self.remove(&path, metadata.len());
+            return None;
+        }
+
+        let response = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_slice(&json)?));
+        match response {
+            Ok(response) => {
+                debug!("Response {} cached", key.0);
+                Some(response)
+            }
+            Err(e) => {
+                warn!("Dropping cached response {}: {e:#}", path.display());
+                self.remove(&path, metadata.len());
+                None
+            }
+        }
+    }
+
+    /// Keep `response` under `key`, replacing what was kept before.
+    ///
+    /// # Errors
+    /// If the response cannot be written
+    pub fn put(&self, key: &Key, response: &impl Serialize) -> anyhow::Result<()> {
+        let json = serde_json::to_vec(response).context("Failed to serialize the response")?;
+        let path = self.path(key);
+        let replaced = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+
+        // written completely before it can be read
+        let mut file = tempfile::NamedTempFile::new_in(&self.dir)
+            .with_context(|| format!("Failed to create a file in {}", self.dir.display()))?;
+        file.write_all(&json)?;
+        file.persist(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        self.shrink(replaced);
+        let size = self.size.fetch_add(json.len() as u64, Ordering::Relaxed) + json.len() as u64;
+        if size > self.capacity {
+            self.evict()?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the oldest responses until they fit into the capacity.
+    fn evict(&self) -> anyhow::Result<()> {
+        let mut entries = entries(&self.dir)?;
+        entries.sort_by_key(|entry| entry.modified);
+
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut evicted = 0;
+        for entry in entries {
+            if size <= self.capacity {
+                break;
+            }
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn!("Failed to evict {}: {e}", entry.path.display());
+                    continue;
+                }
+            }
+            size -= entry.size;
+            evicted += 1;
+        }
+
+        debug!("Evicted {evicted} cached responses");
+        self.size.store(size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path, size: u64) {
+        if fs::remove_file(path).is_ok() {
+            self.shrink(size);
+        }
+    }
+
+    fn shrink(&self, size: u64) {
+        let _ = self
+            .size
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                Some(total.saturating_sub(size))
+            });
+    }
+}
+
+/// The responses in `dir`, skipping files that are still being written
+fn entries(dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let read = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    let mut entries = Vec::new();
+    for entry in read {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        // deleted meanwhile
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        entries.push(Entry {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Key, ResponseCache};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Make the response under `key` look written `age` ago
+    fn age(cache: &ResponseCache, key: &Key, age: Duration) {
+        let file = File::options().write(true).open(cache.path(key)).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn test_key() -> anyhow::Result<()> {
+        let key = ResponseCache::key("chat", &["hello"])?;
+        assert_eq!(key, ResponseCache::key("chat", &["hello"])?);
+        assert_ne!(key, ResponseCache::key("chat", &["hello!"])?);
+        assert_ne!(key, ResponseCache::key("embed", &["hello"])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_put() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ResponseCache::open(dir.path().join("responses"), DAY, 1 << 20)?;
+
+        let key = ResponseCache::key("chat", &"question")?;
+        assert_eq!(cache.get::<String>(&key), None);
+
+        cache.put(&key, &"answer")?;
+        assert_eq!(cache.get::<String>(&key).as_deref(), Some("answer"));
+
+        // replaced
+        cache.put(&key, &"other answer")?;
+        assert_eq!(cache.get::<String>(&key).as_deref(), Some("other answer"));
+
+        // kept across executors
+        let reopened = ResponseCache::open(dir.path().join("responses"), DAY, 1 << 20)?;
+        assert_eq!(
+            reopened.get::<String>(&key).as_deref(),
+            Some("other answer")
+        );
+
+        // a response of another type is dropped
+        assert_eq!(cache.get::<Vec<f32>>(&key), None);
+        assert_eq!(cache.get::<String>(&key), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ResponseCache::open(dir.path().to_path_buf(), DAY, 1 << 20)?;
+
+        let key = ResponseCache::key("chat", &"question")?;
+        cache.put(&key, &"answer")?;
+        age(&cache, &key, DAY / 2);
+        assert!(cache.get::<String>(&key).is_some());
+
+        age(&cache, &key, DAY * 2);
+        assert_eq!(cache.get::<String>(&key), None);
+        assert!(!cache.path(&key).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        // `"answer n"` takes 10 bytes, room for two of them
+        let cache = ResponseCache::open(dir.path().to_path_buf(), DAY, 25)?;
+
+        let keys: Vec<_> = (0..3)
+            .map(|n| ResponseCache::key("chat", &n))
+            .collect::<anyhow::Result<_>>()?;
+
+        cache.put(&keys[0], &"answer 0")?;
+        age(&cache, &keys[0], Duration::from_secs(20));
+        cache.put(&keys[1], &"answer 1")?;
+        age(&cache, &keys[1], Duration::from_secs(10));
+        cache.put(&keys[2], &"answer 2")?;
+
+        assert_eq!(cache.get::<String>(&keys[0]), None);
+        assert_eq!(cache.get::<String>(&keys[1]).as_deref(), Some("answer 1"));
+        assert_eq!(cache.get::<String>(&keys[2]).as_deref(), Some("answer 2"));
+        assert_eq!(cache.size.load(std::sync::atomic::Ordering::Relaxed), 20);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Scratch space for the commands of a step.
+//!
+//! Every step gets a directory of its own in the scratch directory of its session, see
+//! [`SessionManager::scratch`](crate::SessionManager::scratch). The processes it starts get it as
+//! [`ENV`] and as `TMPDIR`, so temporary files neither end up in the working directory nor linger
+//! in the one of the system. The directory is deleted once the step finished, unless the step
+//! failed and failed steps keep theirs for debugging, see [`Settings`](crate::Settings). What is
+//! left is deleted when the session ends.
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tempfile::TempDir;
+
+/// The variable commands find their scratch directory in
+pub const ENV: &str = "COLLECTIVE_SCRATCH";
+
+tokio::task_local! {
+    /// The scratch directory of the step that is running, applied by [`apply`]
+    static DIR: PathBuf;
+}
+
+/// Create the scratch directory of step `index` in `root`, which is deleted when it is dropped.
+///
+/// # Errors
+/// If the directory could not be created
+pub fn create(root: &Path, index: usize) -> anyhow::Result<TempDir> {
+    std::fs::create_dir_all(root)
+        .and_then(|()| {
+            tempfile::Builder::new()
+                .prefix(&format!("step-{index}-"))
+                .tempdir_in(root)
+        })
+        .with_context(|| format!("Failed to create a scratch directory in {}", root.display()))
+}
+
+/// Keep `dir` instead of deleting it when it is dropped, returning where it is.
+pub fn keep(dir: TempDir) -> PathBuf {
+    let path = dir.path().to_path_buf();
+    // like `TempDir::into_path`, which later versions of tempfile deprecate
+    std::mem::forget(dir);
+    path
+}
+
+/// Run `f` with the scratch directory `dir`, every process a command starts in it gets it.
+pub async fn scope<F: Future>(dir: Option<PathBuf>, f: F) -> F::Output {
+    match dir {
+        Some(dir) => DIR.scope(dir, f).await,
+        None => f.await,
+    }
+}
+
+/// Pass the scratch directory of the running step to `cmd`, if any.
+pub fn apply(cmd: &mut tokio::process::Command) {
+    let _ = DIR.try_with(|dir| {
+        cmd.env(ENV, dir).env("TMPDIR", dir);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        command::{collect, Bash, Command},
+        ctx,
+    };
+
+    #[tokio::test]
+    async fn test_scope() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let dir = super::create(root.path(), 3)?;
+        let path = dir.path().to_path_buf();
+        assert!(path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("step-3-")));
+
+        let input = "echo $COLLECTIVE_SCRATCH; mktemp";
+        let ctx = ctx()?;
+        let output = super::scope(Some(path.clone()), async {
+            collect(Bash.execute(ctx.clone(), Path::new("."), input)).await
+        })
+        .await?;
+        let lines: Vec<_> = output.lines().map(Path::new).collect();
+        assert_eq!(lines[0], path);
+        assert!(lines[1].starts_with(&path));
+
+        // without a scope, commands get the environment of the executor
+        let output = collect(Bash.execute(ctx, Path::new("."), input)).await?;
+        assert!(!output.contains(&*path.to_string_lossy()));
+
+        drop(dir);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+}
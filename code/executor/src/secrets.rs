@@ -0,0 +1,371 @@
+//! Secrets that commands of a plan may use, e.g. a token to publish a crate.
+//!
+//! The workspace declares them in `.collective/secrets.toml`, which is kept out of git:
+//!
+//! ```toml
+//! [secrets.CARGO_REGISTRY_TOKEN]
+//! # the value, or `env = "NAME"` to read it from the environment of the executor
+//! value = "cio_..."
+//! # the commands that get it as an environment variable
+//! commands = ["cargo"]
+//! # only steps whose title contains one of these, any step if omitted
+//! steps = ["publish"]
+//! ```
+//!
+//! A step only gets the secrets that allow both its command and its title. The variables secrets
+//! are read from are removed from every step, which would inherit them otherwise. The values are
+//! redacted from the output of every step before it reaches the frontend or the model.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
+
+use crate::{command::Cmd, plan::Step};
+
+/// The directory of the manifest, relative to the working directory
+pub const DIR: &str = ".collective";
+
+/// The name of the manifest in [`DIR`]
+pub const MANIFEST: &str = "secrets.toml";
+
+/// The commands that start processes, the only ones that can use a secret
+const COMMANDS: [Cmd; 3] = [Cmd::Bash, Cmd::Zsh, Cmd::Cargo];
+
+tokio::task_local! {
+    /// The environment of the step that is running, applied by [`Env::apply`]
+    static ENV: Env;
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    secrets: BTreeMap<String, Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    value: Option<String>,
+    env: Option<String>,
+    commands: Vec<Cmd>,
+    #[serde(default)]
+    steps: Vec<String>,
+}
+
+struct Secret {
+    /// the environment variable it is passed as
+    name: String,
+    value: String,
+    /// the variable of the executor it is read from, which processes would inherit
+    source: Option<String>,
+    commands: Vec<Cmd>,
+    /// lowercase parts of the titles of the steps that may use it
+    steps: Vec<String>,
+}
+
+impl Secret {
+    fn allows(&self, step: &Step) -> bool {
+        let title = step.title.to_lowercase();
+        self.commands.contains(&step.command.cmd)
+            && (self.steps.is_empty() || self.steps.iter().any(|part| title.contains(part)))
+    }
+}
+
+#[derive(Default)]
+pub struct Secrets {
+    /// sorted by the length of their value, longest first, so a value containing another is
+    /// redacted as a whole
+    secrets: Vec<Secret>,
+}
+
+/// The environment variables a step runs with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Env {
+    set: Vec<(String, String)>,
+    removed: Vec<String>,
+}
+
+impl Secrets {
+    /// The path of the manifest of the working directory `dir`
+    pub fn manifest(dir: &Path) -> PathBuf {
+        dir.join(DIR).join(MANIFEST)
+    }
+
+    /// Load the secrets of the working directory `dir`, none if it has no manifest.
+    ///
+    /// # Errors
+    /// - The manifest is not valid
+    /// - A secret is read from an environment variable the executor does not have
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::manifest(dir);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).context("Failed to read the secrets manifest"),
+        };
+
+        ignore(dir)?;
+
+        Self::parse(&content, |name| std::env::var(name).ok())
+            .with_context(|| format!("{} is not valid", path.display()))
+    }
+
+    /// Parse a manifest, reading secrets declared with `env` from `var`.
+    fn parse(content: &str, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let manifest: Manifest = toml::from_str(content)?;
+        let mut secrets = Vec::new();
+
+        for (name, entry) in manifest.secrets {
+            let Entry {
+                value,
+                env,
+                commands,
+                steps,
+            } = entry;
+
+            if let Some(cmd) = commands.iter().find(|cmd| !COMMANDS.contains(cmd)) {
+                let names: Vec<_> = COMMANDS.iter().map(|cmd| cmd.header()).collect();
+                bail!(
+                    "`{name}` is given to {}, only {} can use secrets",
+                    cmd.header(),
+                    names.join(", ")
+                );
+            }
+
+            let (value, source) = match (value, env) {
+                (Some(value), None) => (value, None),
+                (None, Some(env)) => {
+                    let value = var(&env).with_context(|| {
+                        format!("`{name}` is read from ${env}, which is not set")
+                    })?;
+                    (value, Some(env))
+                }
+                _ => bail!("`{name}` needs either a `value` or an `env`"),
+            };
+            ensure!(!value.is_empty(), "`{name}` is empty");
+
+            secrets.push(Secret {
+                name,
+                value,
+                source,
+                commands,
+                steps: steps.iter().map(|part| part.to_lowercase()).collect(),
+            });
+        }
+
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.value.len()));
+        Ok(Self { secrets })
+    }
+
+    /// The environment `step` runs with: the secrets it may use are set, the variables of the
+    /// executor they are read from are removed.
+    #[must_use]
+    pub fn env(&self, step: &Step) -> Env {
+        let mut env = Env::default();
+        for secret in &self.secrets {
+            env.removed.extend(secret.source.clone());
+            if secret.allows(step) {
+                env.set.push((secret.name.clone(), secret.value.clone()));
+            }
+        }
+        env
+    }
+
+    /// Pass the secret `value` as `name` to every command that can use secrets, e.g. the answer to
+    /// a secret question.
+    pub fn add(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.secrets.push(Secret {
+            name: name.into(),
+            value: value.into(),
+            source: None,
+            commands: COMMANDS.to_vec(),
+            steps: Vec::new(),
+        });
+        self.secrets
+            .sort_by_key(|secret| std::cmp::Reverse(secret.value.len()));
+    }
+
+    /// `text` with the value of every secret replaced by its name.
+    #[must_use]
+    pub fn redact(&self, text: String) -> String {
+        self.secrets.iter().fold(text, |text, secret| {
+            if text.contains(&secret.value) {
+                text.replace(&secret.value, &format!("[secret {}]", secret.name))
+            } else {
+                text
+            }
+        })
+    }
+}
+
+impl Env {
+    /// The names of the secrets that are set
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.set.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Run `f` with this environment, every process a command starts in it gets it.
+    pub async fn scope<F: std::future::Future>(self, f: F) -> F::Output {
+        ENV.scope(self, f).await
+    }
+
+    /// Set the environment of the running step on `cmd`, if any.
+    pub fn apply(cmd: &mut tokio::process::Command) {
+        let _ = ENV.try_with(|env| {
+            for name in &env.removed {
+                cmd.env_remove(name);
+            }
+            cmd.envs(env.set.iter().map(|(name, value)| (name, value)));
+        });
+    }
+}
+
+/// Keep the manifest out of git with a `.gitignore` next to it.
+fn ignore(dir: &Path) -> anyhow::Result<()> {
+    let gitignore = dir.join(DIR).join(".gitignore");
+    let content = std::fs::read_to_string(&gitignore).unwrap_or_default();
+    if content.lines().any(|line| line.trim() == MANIFEST) {
+        return Ok(());
+    }
+
+    let separator = if content.is_empty() || content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    std::fs::write(&gitignore, format!("{content}{separator}{MANIFEST}\n"))
+        .context("Failed to ignore the secrets manifest")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Env, Secrets};
+    use crate::plan::Plan;
+
+    const MANIFEST: &str = r#"
+        [secrets.CARGO_REGISTRY_TOKEN]
+        value = "cio_token"
+        commands = ["cargo"]
+        steps = ["Publish"]
+
+        [secrets.DEPLOY_KEY]
+        env = "EXECUTOR_DEPLOY_KEY"
+        commands = ["bash", "zsh"]
+    "#;
+
+    fn secrets() -> anyhow::Result<Secrets> {
+        Secrets::parse(MANIFEST, |name| {
+            (name == "EXECUTOR_DEPLOY_KEY").then(|| "deploy-key".to_string())
+        })
+    }
+
+    #[test]
+    fn test_env() -> anyhow::Result<()> {
+        let secrets = secrets()?;
+        let plan = Plan::parse(
+            r#"[
+                {"title": "Publish the crate", "description": "", "command": {"type": "cargo", "input": "publish"}},
+                {"title": "Build the crate", "description": "", "command": {"type": "cargo", "input": "build"}},
+                {"title": "Deploy", "description": "", "command": {"type": "bash", "input": "./deploy.sh"}}
+            ]"#,
+        )?;
+
+        let publish = secrets.env(&plan.steps[0]);
+        assert_eq!(publish.names().collect::<Vec<_>>(), [
+            "CARGO_REGISTRY_TOKEN"
+        ]);
+        assert_eq!(publish.removed, ["EXECUTOR_DEPLOY_KEY"]);
+
+        let build = secrets.env(&plan.steps[1]);
+        assert_eq!(build.names().count(), 0);
+
+        let deploy = secrets.env(&plan.steps[2]);
+        assert_eq!(deploy.set, [(
+            "DEPLOY_KEY".to_string(),
+            "deploy-key".to_string()
+        )]);
+        assert_eq!(deploy.removed, ["EXECUTOR_DEPLOY_KEY"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redact() -> anyhow::Result<()> {
+        let secrets = secrets()?;
+
+        assert_eq!(
+            secrets.redact("Uploading with cio_token and deploy-key".to_string()),
+            "Uploading with [secret CARGO_REGISTRY_TOKEN] and [secret DEPLOY_KEY]"
+        );
+        assert_eq!(
+            secrets.redact("nothing secret".to_string()),
+            "nothing secret"
+        );
+
+        // answers are redacted like declared secrets
+        let mut secrets = secrets;
+        secrets.add("SECRET_ANSWER_1", "hunter2");
+        assert_eq!(
+            secrets.redact("logged in with hunter2".to_string()),
+            "logged in with [secret SECRET_ANSWER_1]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        let parse = |manifest| Secrets::parse(manifest, |_| None);
+
+        assert!(parse("[secrets.A]\ncommands = [\"bash\"]").is_err());
+        assert!(parse("[secrets.A]\nvalue = \"a\"\nenv = \"A\"\ncommands = []").is_err());
+        assert!(parse("[secrets.A]\nvalue = \"a\"\ncommands = [\"fetch\"]").is_err());
+        assert!(parse("[secrets.A]\nenv = \"MISSING\"\ncommands = [\"bash\"]").is_err());
+        assert!(parse("").is_ok_and(|secrets| secrets.secrets.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_apply() -> anyhow::Result<()> {
+        let secrets = secrets()?;
+        let plan = Plan::parse(
+            r#"[{"title": "Deploy", "description": "", "command": {"type": "bash", "input": "echo $DEPLOY_KEY"}}]"#,
+        )?;
+
+        let output = secrets
+            .env(&plan.steps[0])
+            .scope(async {
+                let mut cmd = tokio::process::Command::new("bash");
+                cmd.arg("-c").arg("echo $DEPLOY_KEY");
+                Env::apply(&mut cmd);
+                cmd.output().await
+            })
+            .await?;
+
+        assert_eq!(String::from_utf8(output.stdout)?, "deploy-key\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert!(Secrets::load(dir.path())?.secrets.is_empty());
+
+        std::fs::create_dir(dir.path().join(super::DIR))?;
+        std::fs::write(
+            Secrets::manifest(dir.path()),
+            "[secrets.A]\nvalue = \"a\"\ncommands = [\"bash\"]",
+        )?;
+        assert_eq!(Secrets::load(dir.path())?.secrets.len(), 1);
+
+        let gitignore = std::fs::read_to_string(dir.path().join(super::DIR).join(".gitignore"))?;
+        assert_eq!(gitignore, "secrets.toml\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+//! Tracks the sessions of all connected frontends.
+//!
+//! Every connection gets its own session. When a connection drops, its session is kept around
+//! (detached) for a while so the frontend can reconnect and resume it with a
+//! [`protocol::client::Resume`] packet. Resuming takes the key the session was created with, so a
+//! connection that learned the id of a session cannot take it over.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use protocol::{delivery::Delivered, PacketId, SessionId};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{auth, process::question::QAndA, usage::Usage, Executor};
+
+/// How long a detached session can be resumed
+const DETACHED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long running executions can finish after [`SessionManager::shutdown`] by default
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(30);
+
+/// The state of a session that outlives the executor, see [`SessionManager::checkpoint`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub id: SessionId,
+    pub instruction: String,
+    pub questions: Vec<String>,
+    pub answers: Vec<String>,
+    /// the directory of a [`protocol::client::SetWorkspace`], if the session chose one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<PathBuf>,
+    /// needed to resume the session, a new one nobody knows if it was not saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Checkpoint {
+    /// The sessions saved to `path`, none if there is no checkpoint
+    pub fn load(path: &Path) -> anyhow::Result<Vec<Self>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sessions from {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse sessions in {}", path.display()))
+    }
+
+    pub fn save(path: &Path, checkpoints: &[Self]) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(checkpoints)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write sessions to {}", path.display()))
+    }
+}
+
+struct Session {
+    /// whether a connection is currently driving this session
+    attached: bool,
+    /// the state of a detached session
+    q_and_a: Option<QAndA>,
+    /// when the session was last attached or detached
+    updated: Instant,
+    /// the packets of the frontend that were handled, across its connections
+    delivered: Delivered,
+    /// the directory of a [`protocol::client::SetWorkspace`], instead of one in the workspace
+    workdir: Option<PathBuf>,
+    /// needed to resume the session, only sent to the connection driving it
+    key: String,
+    /// what its requests to the provider cost, across its connections
+    usage: Usage,
+}
+
+/// A key to resume a session with, random enough not to be guessed
+fn key() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// A summary of a session, for listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub attached: bool,
+}
+
+#[derive(Clone)]
+pub struct SessionManager {
+    sessions: Arc<Mutex<HashMap<SessionId, Session>>>,
+    shutdown: CancellationToken,
+    /// how long running executions can finish after a shutdown
+    grace: Duration,
+    /// contains the working directory of every session
+    workspace: PathBuf,
+    /// contains the scratch directory of every session, see [`crate::scratch`]
+    scratch: PathBuf,
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self {
+            sessions: Arc::default(),
+            shutdown: CancellationToken::new(),
+            grace: DEFAULT_GRACE,
+            workspace: std::env::temp_dir().join("collective"),
+            scratch: std::env::temp_dir().join("collective-scratch"),
+        }
+    }
+}
+
+impl SessionManager {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_grace(self, grace: Duration) -> Self {
+        Self { grace, ..self }
+    }
+
+    /// How long running executions can finish after [`SessionManager::shutdown`]
+    #[must_use]
+    pub const fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// The directory commands of the session run in, the one set with
+    /// [`SessionManager::set_workdir`] or else one of its own. It is not created.
+    #[must_use]
+    pub fn workdir(&self, id: SessionId) -> PathBuf {
+        self.sessions
+            .lock()
+            .get(&id)
+            .and_then(|session| session.workdir.clone())
+            .unwrap_or_else(|| self.workspace.join(id.to_string()))
+    }
+
+    /// The key session `id` is resumed with, if it exists
+    #[must_use]
+    pub fn key(&self, id: SessionId) -> Option<String> {
+        self.sessions
+            .lock()
+            .get(&id)
+            .map(|session| session.key.clone())
+    }
+
+    /// What the requests of session `id` to the provider cost, nothing is counted if it does not
+    /// exist
+    #[must_use]
+    pub(crate) fn usage(&self, id: SessionId) -> Usage {
+        self.sessions
+            .lock()
+            .get(&id)
+            .map(|session| session.usage.clone())
+            .unwrap_or_default()
+    }
+
+    /// Run the commands of the session in `dir` from now on.
+    pub fn set_workdir(&self, id: SessionId, dir: PathBuf) {
+        if let Some(session) = self.sessions.lock().get_mut(&id) {
+            session.workdir = Some(dir);
+        }
+    }
+
+    /// The directory the scratch directories of the steps of the session are created in. It is
+    /// deleted when the session ends.
+    #[must_use]
+    pub fn scratch(&self, id: SessionId) -> PathBuf {
+        self.scratch.join(id.to_string())
+    }
+
+    /// Delete the scratch directories of sessions that ended.
+    fn clean(&self, ended: impl IntoIterator<Item = SessionId>) {
+        for id in ended {
+            let dir = self.scratch(id);
+            match std::fs::remove_dir_all(&dir) {
+                Ok(()) => info!("Deleted the scratch directory {}", dir.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => error!("Failed to delete {}: {e}", dir.display()),
+            }
+        }
+    }
+
+    /// Create a new attached session.
+    #[must_use]
+    pub fn create(&self) -> SessionId {
+        let id = Uuid::new_v4();
+
+        let mut sessions = self.sessions.lock();
+
+        // drop sessions nobody resumed in time
+        let mut ended = Vec::new();
+        sessions.retain(|&id, session| {
+            let keep = session.attached || session.updated.elapsed() < DETACHED_TTL;
+            if !keep {
+                ended.push(id);
+            }
+            keep
+        });
+
+        sessions.insert(id, Session {
+            attached: true,
+            q_and_a: None,
+            updated: Instant::now(),
+            delivered: Delivered::default(),
+            workdir: None,
+            key: key(),
+            usage: Usage::default(),
+        });
+        drop(sessions);
+
+        self.clean(ended);
+        id
+    }
+
+    /// Attach to a detached session with its `key`, taking its state.
+    ///
+    /// Returns `None` if there is no such session, the key is wrong or it is still attached to
+    /// another connection.
+    #[must_use]
+    pub fn attach(&self, id: SessionId, key: &str) -> Option<Option<QAndA>> {
+        let mut sessions = self.sessions.lock();
+        let session = sessions.get_mut(&id)?;
+
+        if !auth::equal(key.as_bytes(), session.key.as_bytes()) {
+            warn!("Refused to resume session {id} with a wrong key");
+            return None;
+        }
+        if session.attached {
+            return None;
+        }
+
+        session.attached = true;
+        session.updated = Instant::now();
+
+        Some(session.q_and_a.take())
+    }
+
+    /// Detach a session whose connection ended, keeping its state so it can be resumed.
+    pub fn detach(&self, id: SessionId, q_and_a: Option<QAndA>) {
+        let mut sessions = self.sessions.lock();
+        let Some(session) = sessions.get_mut(&id) else {
+            return;
+        };
+
+        session.attached = false;
+        session.q_and_a = q_and_a;
+        session.updated = Instant::now();
+    }
+
+    /// Record that the packet `packet` of session `id` is handled. Returns `false` if it was
+    /// handled before, a packet the frontend sent again as sending it seemed to fail.
+    #[must_use]
+    pub fn deliver(&self, id: SessionId, packet: PacketId) -> bool {
+        self.sessions
+            .lock()
+            .get_mut(&id)
+            .is_none_or(|session| session.delivered.first(packet))
+    }
+
+    pub fn remove(&self, id: SessionId) {
+        self.sessions.lock().remove(&id);
+        self.clean([id]);
+    }
+
+    #[must_use]
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut list: Vec<_> = self
+            .sessions
+            .lock()
+            .iter()
+            .map(|(id, session)| SessionInfo {
+                id: *id,
+                attached: session.attached,
+            })
+            .collect();
+
+        list.sort_by_key(|info| info.id);
+        list
+    }
+
+    /// Number of sessions with a connection
+    #[must_use]
+    pub fn active(&self) -> usize {
+        self.sessions
+            .lock()
+            .values()
+            .filter(|session| session.attached)
+            .count()
+    }
+
+    /// Ask all sessions to stop after the packet they are currently processing.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Completes once [`SessionManager::shutdown`] was called.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.cancelled().await;
+    }
+
+    /// Save the detached sessions with an instruction to `path`, so they can be resumed after a
+    /// restart. Returns the number of saved sessions.
+    ///
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn checkpoint(&self, path: &Path) -> anyhow::Result<usize> {
+        let checkpoints: Vec<_> = self
+            .sessions
+            .lock()
+            .iter()
+            .filter(|(_, session)| !session.attached)
+            .filter_map(|(id, session)| {
+                let q_and_a = session.q_and_a.as_ref()?;
+                Some(Checkpoint {
+                    id: *id,
+                    instruction: q_and_a.instruction().to_string(),
+                    questions: q_and_a.questions().to_vec(),
+                    answers: q_and_a.answers().to_vec(),
+                    workdir: session.workdir.clone(),
+                    key: Some(session.key.clone()),
+                })
+            })
+            .collect();
+
+        Checkpoint::save(path, &checkpoints)?;
+        Ok(checkpoints.len())
+    }
+
+    /// Restore the sessions saved with [`SessionManager::checkpoint`] as detached sessions.
+    /// Returns the number of restored sessions, 0 if there is no checkpoint.
+    ///
+    /// # Errors
+    /// If the checkpoint cannot be read.
+    pub fn restore(&self, path: &Path, executor: &Executor) -> anyhow::Result<usize> {
+        let checkpoints = Checkpoint::load(path)?;
+
+        let mut sessions = self.sessions.lock();
+        for checkpoint in &checkpoints {
+            let q_and_a = QAndA::resume(
+                executor.clone(),
+                checkpoint.instruction.clone(),
+                checkpoint.questions.clone(),
+                checkpoint.answers.clone(),
+            );
+
+            sessions.insert(checkpoint.id, Session {
+                attached: false,
+                q_and_a: Some(q_and_a),
+                updated: Instant::now(),
+                delivered: Delivered::default(),
+                workdir: checkpoint.workdir.clone(),
+                key: checkpoint.key.clone().unwrap_or_else(key),
+                usage: Usage::default(),
+            });
+        }
+
+        Ok(checkpoints.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionInfo, SessionManager};
+    use crate::{process::question::QAndA, Executor, Settings};
+
+    #[test]
+    fn test_attach_detach() {
+        let sessions = SessionManager::new();
+
+        let id = sessions.create();
+        let key = sessions.key(id).unwrap();
+        assert_eq!(sessions.active(), 1);
+
+        // still attached to the first connection
+        assert!(sessions.attach(id, &key).is_none());
+
+        sessions.detach(id, None);
+        assert_eq!(sessions.active(), 0);
+        assert_eq!(sessions.list(), vec![SessionInfo {
+            id,
+            attached: false
+        }]);
+
+        // another connection that only knows the id
+        assert!(sessions.attach(id, "").is_none());
+        assert!(sessions.attach(id, &key[1..]).is_none());
+        assert_eq!(sessions.active(), 0);
+
+        assert!(sessions.attach(id, &key).is_some());
+        assert_eq!(sessions.active(), 1);
+
+        sessions.remove(id);
+        assert!(sessions.list().is_empty());
+    }
+
+    #[test]
+    fn test_scratch() -> anyhow::Result<()> {
+        let sessions = SessionManager::new();
+        let id = sessions.create();
+
+        let dir = sessions.scratch(id).join("step-0");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("leftover"), "")?;
+
+        sessions.detach(id, None);
+        assert!(dir.exists());
+
+        sessions.remove(id);
+        assert!(!sessions.scratch(id).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deliver() {
+        let sessions = SessionManager::new();
+        let id = sessions.create();
+        let key = sessions.key(id).unwrap();
+        let packet = uuid::Uuid::new_v4();
+
+        assert!(sessions.deliver(id, packet));
+        // sent again after reconnecting
+        sessions.detach(id, None);
+        assert!(sessions.attach(id, &key).is_some());
+        assert!(!sessions.deliver(id, packet));
+        assert!(sessions.deliver(id, uuid::Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_unknown_session() {
+        let sessions = SessionManager::new();
+        assert!(sessions.attach(uuid::Uuid::new_v4(), "").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let sessions = SessionManager::new();
+        assert!(!sessions.is_shutting_down());
+
+        sessions.clone().shutdown();
+
+        sessions.shutdown_requested().await;
+        assert!(sessions.is_shutting_down());
+    }
+
+    #[test]
+    fn test_checkpoint() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sessions.json");
+        let executor = Executor::new(Settings::default())?;
+
+        let sessions = SessionManager::new();
+        let id = sessions.create();
+        let key = sessions.key(id).unwrap();
+        let q_and_a = QAndA::resume(
+            executor.clone(),
+            "Create a calculator",
+            vec!["Which language?".to_string()],
+            vec!["Rust".to_string()],
+        );
+        sessions.set_workdir(id, dir.path().join("calculator"));
+        sessions.detach(id, Some(q_and_a));
+        // attached sessions and sessions without an instruction are not saved
+        let _ = sessions.create();
+
+        assert_eq!(sessions.checkpoint(&path)?, 1);
+
+        let restored = SessionManager::new();
+        assert_eq!(restored.restore(&path, &executor)?, 1);
+
+        let q_and_a = restored
+            .attach(id, &key)
+            .flatten()
+            .expect("the session was restored");
+        assert_eq!(q_and_a.instruction(), "Create a calculator");
+        assert_eq!(q_and_a.answers(), ["Rust"]);
+        assert_eq!(restored.workdir(id), dir.path().join("calculator"));
+
+        assert_eq!(
+            restored.restore(&dir.path().join("missing.json"), &executor)?,
+            0
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Previews of the external material steps read, so the user sees what the plan draws on.
+//!
+//! A step that reads a crate on lib.rs or docs.rs or fetches a page is a source. Its preview is
+//! the title, the first paragraphs and the link of what it read, sent as a
+//! [`server::SourcePreview`].
+
+use html_to_md::HtmlToMd;
+use protocol::server;
+
+use crate::command::Cmd;
+
+/// How many paragraphs a preview shows
+const SUMMARY_PARAGRAPHS: usize = 2;
+
+/// Previews are cut to this many characters
+const MAX_SUMMARY: usize = 500;
+
+/// The link to what `cmd` reads with `input`, `None` if it does not read a source
+#[must_use]
+pub fn url(cmd: Cmd, input: &str) -> Option<String> {
+    let input = input.trim();
+    match cmd {
+        Cmd::LibRs => Some(format!("https://lib.rs/crates/{input}")),
+        Cmd::DocsRs => {
+            let (krate, item) = input.split_once("::").unwrap_or((input, ""));
+            let base = format!(
+                "https://docs.rs/{krate}/latest/{}/",
+                krate.replace('-', "_")
+            );
+            Some(match item.rsplit("::").next() {
+                Some(name) if !name.is_empty() => format!("{base}?search={name}"),
+                _ => base,
+            })
+        }
+        Cmd::Fetch => Some(input.to_string()),
+        _ => None,
+    }
+}
+
+/// The preview of the source `cmd` read with `input`, `output` being what it read
+#[must_use]
+pub fn preview(id: u64, cmd: Cmd, input: &str, output: &str) -> Option<server::SourcePreview> {
+    let url = url(cmd, input)?;
+
+    // lib.rs readmes are HTML
+    let markdown = match cmd {
+        Cmd::LibRs => HtmlToMd::new(output)
+            .run()
+            .unwrap_or_else(|_| output.to_string()),
+        _ => output.to_string(),
+    };
+
+    Some(server::SourcePreview {
+        id,
+        title: title(&markdown).unwrap_or_else(|| input.trim().to_string()),
+        summary: summary(&markdown),
+        url,
+    })
+}
+
+/// The first heading of `markdown`
+fn title(markdown: &str) -> Option<String> {
+    markdown
+        .lines()
+        .find_map(|line| line.strip_prefix('#'))
+        .map(|heading| heading.trim_start_matches('#').trim().to_string())
+        .filter(|heading| !heading.is_empty())
+}
+
+/// The first paragraphs of prose in `markdown`, without headings, code and badges
+fn summary(markdown: &str) -> String {
+    let mut in_code = false;
+    let paragraphs: Vec<_> = markdown
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| {
+            let fence = paragraph.matches("```").count() % 2 == 1;
+            let skipped = in_code || paragraph.starts_with("```");
+            in_code ^= fence;
+            !skipped
+        })
+        .filter(|paragraph| {
+            !paragraph.is_empty()
+                && !paragraph.starts_with('#')
+                && !paragraph.starts_with("[![")
+                && !paragraph.starts_with("![")
+        })
+        .take(SUMMARY_PARAGRAPHS)
+        .collect();
+
+    let summary = paragraphs.join("\n\n");
+    match summary.char_indices().nth(MAX_SUMMARY) {
+        Some((end, _)) => format!("{}…", &summary[..end]),
+        None => summary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{preview, url};
+    use crate::command::Cmd;
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            url(Cmd::LibRs, "serde"),
+            Some("https://lib.rs/crates/serde".to_string())
+        );
+        assert_eq!(
+            url(Cmd::DocsRs, "tokio-util::sync::CancellationToken"),
+            Some(
+                "https://docs.rs/tokio-util/latest/tokio_util/?search=CancellationToken"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            url(Cmd::DocsRs, "tokio"),
+            Some("https://docs.rs/tokio/latest/tokio/".to_string())
+        );
+        assert_eq!(url(Cmd::Bash, "ls"), None);
+    }
+
+    #[test]
+    fn test_preview() {
+        let docs = "[![crates.io](badge.svg)](https://crates.io)\n\n# Serde\n\nSerde is a \
+                    framework for serializing.\n\n```rust\nlet x = 1;\n\nlet y = 2;\n```\n\nIt is \
+                    fast.\n\nAnd generic.";
+
+        let docs = preview(3, Cmd::DocsRs, "serde", docs).unwrap();
+        assert_eq!(docs.id, 3);
+        assert_eq!(docs.title, "Serde");
+        assert_eq!(
+            docs.summary,
+            "Serde is a framework for serializing.\n\nIt is fast."
+        );
+
+        // without a heading the title is what was read
+        let page = preview(1, Cmd::Fetch, "https://example.com", "Just text.").unwrap();
+        assert_eq!(page.title, "https://example.com");
+        assert_eq!(page.summary, "Just text.");
+    }
+}
@@ -0,0 +1,222 @@
+//! Record the traffic with the provider to a fixture and replay it, so tests that talk to the model
+//! run offline and get the same answers every time.
+//!
+//! With [`RECORD`] set to a file, every request and its response is appended to it as a line of
+//! JSON. With [`REPLAY`] set to such a file, requests are answered from it instead of the provider
+//! and fail if they were not recorded. Recorded answers to a request that is sent repeatedly are
+//! replayed in order, the last one for the requests after them.
+//!
+//! ```sh
+//! COLLECTIVE_RECORD=fixtures/questions.jsonl cargo test -p executor test_get_question
+//! COLLECTIVE_REPLAY=fixtures/questions.jsonl cargo test -p executor test_get_question
+//! ```
+
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// The variable naming the fixture to record to
+pub const RECORD: &str = "COLLECTIVE_RECORD";
+
+/// The variable naming the fixture to replay
+pub const REPLAY: &str = "COLLECTIVE_REPLAY";
+
+/// A request and its response, a line of a fixture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// which endpoint answered, e.g. `chat`
+    kind: String,
+    request: Value,
+    response: Value,
+}
+
+pub enum Tape {
+    /// appends to the fixture
+    Record(Mutex<File>),
+    /// the exchanges of the fixture and whether each was replayed
+    Replay(Mutex<Vec<(Exchange, bool)>>),
+}
+
+impl Tape {
+    /// The tape selected by [`RECORD`] or [`REPLAY`], `None` if neither is set.
+    ///
+    /// # Errors
+    /// - Both are set
+    /// - The fixture cannot be opened or is not valid
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match (std::env::var_os(RECORD), std::env::var_os(REPLAY)) {
+            (None, None) => Ok(None),
+            (Some(path), None) => Self::record(Path::new(&path)).map(Some),
+            (None, Some(path)) => Self::replay(Path::new(&path)).map(Some),
+            (Some(_), Some(_)) => bail!("only one of ${RECORD} and ${REPLAY} can be set"),
+        }
+    }
+
+    /// Append the exchanges to `path`, creating it if needed.
+    ///
+    /// # Errors
+    /// If the fixture cannot be opened
+    pub fn record(path: &Path) -> anyhow::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the fixture {}", path.display()))?;
+        Ok(Self::Record(Mutex::new(file)))
+    }
+
+    /// Answer requests with the exchanges recorded in `path`.
+    ///
+    /// # Errors
+    /// If the fixture cannot be read or a line is not an exchange
+    pub fn replay(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open the fixture {}", path.display()))?;
+
+        let mut exchanges = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange = serde_json::from_str(&line).with_context(|| {
+                format!("Line {} of {} is not valid", number + 1, path.display())
+            })?;
+            exchanges.push((exchange, false));
+        }
+
+        Ok(Self::Replay(Mutex::new(exchanges)))
+    }
+
+    #[must_use]
+    pub fn is_replaying(&self) -> bool {
+        matches!(self, Self::Replay(_))
+    }
+
+    /// The recorded response to `request` of `kind`.
+    ///
+    /// # Errors
+    /// - Not replaying
+    /// - The request was not recorded, or the response does not fit `T`
+    pub fn replayed<T: DeserializeOwned>(&self, kind: &str, request: &Value) -> anyhow::Result<T> {
+        let Self::Replay(exchanges) = self else {
+            bail!("the tape is recording");
+        };
+        let mut exchanges = exchanges.lock();
+
+        let matching: Vec<usize> = (0..exchanges.len())
+            .filter(|&index| {
+                let exchange = &exchanges[index].0;
+                exchange.kind == kind && exchange.request == *request
+            })
+            .collect();
+        let Some(&last) = matching.last() else {
+            bail!("no {kind} response was recorded for {request}");
+        };
+
+        let index = matching
+            .into_iter()
+            .find(|&index| !exchanges[index].1)
+            .unwrap_or(last);
+        exchanges[index].1 = true;
+
+        serde_json::from_value(exchanges[index].0.response.clone())
+            .context("The recorded response is not valid")
+    }
+
+    /// Append `response` to `request` of `kind` to the fixture, if recording.
+    ///
+    /// # Errors
+    /// If the fixture cannot be written
+    pub fn recorded(
+        &self,
+        kind: &str,
+        request: Value,
+        response: &impl Serialize,
+    ) -> anyhow::Result<()> {
+        let Self::Record(file) = self else {
+            return Ok(());
+        };
+
+        let exchange = Exchange {
+            kind: kind.to_string(),
+            request,
+            response: serde_json::to_value(response)?,
+        };
+        let mut line = serde_json::to_vec(&exchange)?;
+        line.push(b'\n');
+        // a line at once, so concurrent requests do not interleave
+        file.lock()
+            .write_all(&line)
+            .context("Failed to write the fixture")
+    }
+
+    /// Answer `request` of `kind` from the fixture when replaying. Otherwise `fetch` the response
+    /// and record it.
+    ///
+    /// # Errors
+    /// If the response could not be replayed, fetched or recorded
+    pub async fn play<T: Serialize + DeserializeOwned>(
+        &self,
+        kind: &str,
+        request: Value,
+        fetch: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        if self.is_replaying() {
+            return self.replayed(kind, &request);
+        }
+
+        let response = fetch.await?;
+        self.recorded(kind, request, &response)?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Tape;
+
+    #[tokio::test]
+    async fn test_record_replay() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fixture = dir.path().join("fixture.jsonl");
+
+        let tape = Tape::record(&fixture)?;
+        assert!(!tape.is_replaying());
+        let answer = tape
+            .play("chat", json!({"q": 1}), async { Ok("first".to_string()) })
+            .await?;
+        assert_eq!(answer, "first");
+        tape.recorded("chat", json!({"q": 1}), &"second")?;
+        tape.recorded("embed", json!("text"), &[0.5, 1.0])?;
+
+        let tape = Tape::replay(&fixture)?;
+        assert!(tape.is_replaying());
+        let fetch = async { anyhow::bail!("replaying does not fetch") };
+        let answer: String = tape.play("chat", json!({"q": 1}), fetch).await?;
+        assert_eq!(answer, "first");
+        assert_eq!(tape.replayed::<String>("chat", &json!({"q": 1}))?, "second");
+        // the last answer to a request is repeated
+        assert_eq!(tape.replayed::<String>("chat", &json!({"q": 1}))?, "second");
+        assert_eq!(tape.replayed::<Vec<f32>>("embed", &json!("text"))?, [
+            0.5, 1.0
+        ]);
+
+        assert!(tape.replayed::<String>("chat", &json!({"q": 2})).is_err());
+        assert!(tape
+            .replayed::<String>("raw_chat", &json!({"q": 1}))
+            .is_err());
+
+        Ok(())
+    }
+}
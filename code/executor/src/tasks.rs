@@ -0,0 +1,58 @@
+//! Spawn the long-lived tasks of the executor with names, so `tokio-console` can tell them apart.
+//!
+//! With the `console` feature the executor serves [tokio-console] on `127.0.0.1:6669`, or the
+//! address of `TOKIO_CONSOLE_BIND`, to diagnose leaked, starving or blocked tasks. It needs
+//! `--cfg tokio_unstable`, which `.cargo/config.toml` sets. Without the feature the tasks are
+//! spawned as usual and their names are dropped.
+//!
+//! [tokio-console]: https://github.com/tokio-rs/console
+
+use std::future::Future;
+
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+
+/// Spawn `future` as the task `name`, e.g. `config watcher`.
+#[cfg(feature = "console")]
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("spawning only fails outside of a runtime, like tokio::spawn")
+}
+
+/// Spawn `future` as the task `name`, e.g. `config watcher`.
+#[cfg(not(feature = "console"))]
+pub fn spawn<F>(_name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future)
+}
+
+/// Spawn `future` in `set` as the task `name`.
+#[cfg(feature = "console")]
+pub fn spawn_in<T, F>(set: &mut JoinSet<T>, name: &str, future: F) -> AbortHandle
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    set.build_task()
+        .name(name)
+        .spawn(future)
+        .expect("spawning only fails outside of a runtime, like JoinSet::spawn")
+}
+
+/// Spawn `future` in `set` as the task `name`.
+#[cfg(not(feature = "console"))]
+pub fn spawn_in<T, F>(set: &mut JoinSet<T>, _name: &str, future: F) -> AbortHandle
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    set.spawn(future)
+}
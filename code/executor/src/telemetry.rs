@@ -0,0 +1,224 @@
+//! Counters and histograms of what the executor does across all sessions, served by `/metrics`
+//! of the [`http`](crate::http) endpoints in the Prometheus text format.
+//!
+//! The provider does not report the tokens it used to the client, they are estimated from the
+//! length of the requests and responses like the budget of a conversation.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use utils::discretize::CHARS_PER_TOKEN;
+
+/// Upper bounds of the buckets of the histograms, in seconds
+const BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Durations observed in [`BUCKETS`]
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// observations per bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    /// The lines of the histogram `name` with `labels`, e.g. `kind="chat"`
+    fn render(&self, metrics: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                metrics,
+                "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let count = self.count;
+        let _ = writeln!(metrics, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(metrics, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(metrics, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    /// packets received from frontends, by type
+    packets: BTreeMap<&'static str, u64>,
+    /// requests to the provider by kind, e.g. `chat`
+    requests: BTreeMap<&'static str, Histogram>,
+    request_failures: BTreeMap<&'static str, u64>,
+    /// estimated tokens sent to and received from the provider
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    /// commands run by steps, by their name
+    commands: BTreeMap<String, Histogram>,
+    command_failures: BTreeMap<String, u64>,
+}
+
+/// Shared by every session
+#[derive(Debug, Default, Clone)]
+pub struct Telemetry(Arc<Mutex<Recorded>>);
+
+impl Telemetry {
+    /// A packet of `kind` was received.
+    pub fn packet(&self, kind: &'static str) {
+        *self.0.lock().packets.entry(kind).or_default() += 1;
+    }
+
+    /// The provider answered a request of `kind` after `took`, or failed to.
+    pub fn request(&self, kind: &'static str, took: Duration, succeeded: bool) {
+        let mut recorded = self.0.lock();
+        recorded.requests.entry(kind).or_default().observe(took);
+        if !succeeded {
+            *recorded.request_failures.entry(kind).or_default() += 1;
+        }
+    }
+
+    /// `text` was sent to the provider.
+    pub fn prompt(&self, text: &str) {
+        self.0.lock().prompt_tokens += tokens(text);
+    }
+
+    /// `text` was received from the provider, a whole response or a part of it.
+    pub fn completion(&self, text: &str) {
+        self.0.lock().completion_tokens += tokens(text);
+    }
+
+    /// The command `name` ran for `took` and exited with 0, or did not.
+    pub fn command(&self, name: &str, took: Duration, succeeded: bool) {
+        let mut recorded = self.0.lock();
+        recorded
+            .commands
+            .entry(name.to_string())
+            .or_default()
+            .observe(took);
+        if !succeeded {
+            *recorded
+                .command_failures
+                .entry(name.to_string())
+                .or_default() += 1;
+        }
+    }
+
+    /// Append the metrics to `metrics` in the Prometheus text format.
+    pub fn render(&self, metrics: &mut String) {
+        let recorded = self.0.lock();
+
+        let name = "collective_packets_total";
+        header(
+            metrics,
+            name,
+            "counter",
+            "Packets received from frontends by type",
+        );
+        for (kind, count) in &recorded.packets {
+            let _ = writeln!(metrics, "{name}{{type=\"{kind}\"}} {count}");
+        }
+
+        let name = "collective_provider_request_seconds";
+        let help = "Time the provider took to answer, until the first chunk when streamed";
+        header(metrics, name, "histogram", help);
+        for (kind, histogram) in &recorded.requests {
+            histogram.render(metrics, name, &format!("kind=\"{kind}\""));
+        }
+
+        let name = "collective_provider_request_failures_total";
+        header(
+            metrics,
+            name,
+            "counter",
+            "Requests the provider failed to answer",
+        );
+        for (kind, count) in &recorded.request_failures {
+            let _ = writeln!(metrics, "{name}{{kind=\"{kind}\"}} {count}");
+        }
+
+        let name = "collective_provider_tokens_total";
+        let help = "Tokens sent to and received from the provider, estimated from their length";
+        header(metrics, name, "counter", help);
+        let prompt = recorded.prompt_tokens;
+        let _ = writeln!(metrics, "{name}{{direction=\"prompt\"}} {prompt}");
+        let completion = recorded.completion_tokens;
+        let _ = writeln!(metrics, "{name}{{direction=\"completion\"}} {completion}");
+
+        let name = "collective_command_seconds";
+        header(metrics, name, "histogram", "Time the commands of steps ran");
+        for (command, histogram) in &recorded.commands {
+            histogram.render(metrics, name, &format!("command=\"{command}\""));
+        }
+
+        let name = "collective_command_failures_total";
+        let help = "Commands of steps that failed or exited with another code than 0";
+        header(metrics, name, "counter", help);
+        for (command, count) in &recorded.command_failures {
+            let _ = writeln!(metrics, "{name}{{command=\"{command}\"}} {count}");
+        }
+    }
+}
+
+/// Rough number of tokens of `text`
+pub fn tokens(text: &str) -> u64 {
+    (text.len() / CHARS_PER_TOKEN) as u64
+}
+
+/// Describe the metric `name` of the Prometheus type `kind`
+pub fn header(metrics: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(metrics, "# HELP {name} {help}");
+    let _ = writeln!(metrics, "# TYPE {name} {kind}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use utils::discretize::CHARS_PER_TOKEN;
+
+    use super::Telemetry;
+
+    #[test]
+    fn test_render() {
+        let telemetry = Telemetry::default();
+        telemetry.packet("Answer");
+        telemetry.packet("Answer");
+        telemetry.request("chat", Duration::from_millis(300), true);
+        telemetry.request("chat", Duration::from_secs(3), false);
+        telemetry.prompt(&"a".repeat(120 * CHARS_PER_TOKEN));
+        telemetry.completion(&"a".repeat(30 * CHARS_PER_TOKEN));
+        telemetry.command("bash", Duration::from_secs(1000), false);
+
+        let mut metrics = String::new();
+        telemetry.render(&mut metrics);
+
+        for line in [
+            "collective_packets_total{type=\"Answer\"} 2",
+            "collective_provider_request_seconds_bucket{kind=\"chat\",le=\"0.25\"} 0",
+            "collective_provider_request_seconds_bucket{kind=\"chat\",le=\"0.5\"} 1",
+            "collective_provider_request_seconds_bucket{kind=\"chat\",le=\"5\"} 2",
+            "collective_provider_request_seconds_bucket{kind=\"chat\",le=\"+Inf\"} 2",
+            "collective_provider_request_seconds_sum{kind=\"chat\"} 3.3",
+            "collective_provider_request_seconds_count{kind=\"chat\"} 2",
+            "collective_provider_request_failures_total{kind=\"chat\"} 1",
+            "collective_provider_tokens_total{direction=\"prompt\"} 120",
+            "collective_provider_tokens_total{direction=\"completion\"} 30",
+            // longer than every bucket
+            "collective_command_seconds_bucket{command=\"bash\",le=\"300\"} 0",
+            "collective_command_seconds_bucket{command=\"bash\",le=\"+Inf\"} 1",
+            "collective_command_failures_total{command=\"bash\"} 1",
+        ] {
+            assert!(
+                metrics.contains(&format!("{line}\n")),
+                "{line} in {metrics}"
+            );
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! What the requests of a session to the provider cost, by what they were for, e.g. a step of the
+//! plan or a question of the interview. Sent to the frontend before every
+//! [`server::ExecutionFinished`](protocol::server::Server::ExecutionFinished) as a
+//! [`server::Usage`](protocol::server::Server::Usage) and recorded in the audit log, so it is in
+//! the exported archives as well.
+//!
+//! Like the [`Telemetry`](crate::telemetry::Telemetry), the tokens are estimated from the length
+//! of the requests and responses, the provider does not report them. The cost follows from the
+//! list prices of the model.
+//!
+//! A request counts for the [`Purpose`] it is sent within, see [`purpose`], in the [`Usage`] of
+//! the session whose packet is being handled, see [`scope`]. Requests outside of a purpose count
+//! as [`Purpose::Other`], requests outside of a session are not counted.
+//!
+//! With [`Settings::cost_cap`](crate::Settings::cost_cap), a session that spent
+//! [`DOWNSHIFT_PERCENT`] of the cap sends its low-stakes requests, questions and summaries, to
+//! [`CHEAP_MODEL`] from then on, see [`downshift`]. Plans and code are still written by the model
+//! they ask for.
+
+use std::{collections::BTreeMap, fmt, future::Future, sync::Arc};
+
+use parking_lot::Mutex;
+use protocol::UsageStats;
+use tokio_openai::{ChatModel, ChatRequest};
+
+use crate::telemetry::tokens;
+
+/// The model low-stakes requests are sent to once the budget runs low
+const CHEAP_MODEL: ChatModel = ChatModel::Turbo;
+
+/// How much of the cap a session spends before its low-stakes requests are sent to
+/// [`CHEAP_MODEL`], in percent
+pub const DOWNSHIFT_PERCENT: u64 = 80;
+
+tokio::task_local! {
+    /// The usage of the session whose packet is being handled, see [`scope`]
+    static USAGE: Usage;
+    /// What the requests sent now are for, see [`purpose`]
+    static PURPOSE: Purpose;
+}
+
+/// What a request to the provider is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Purpose {
+    /// several questions asked at once
+    Questions,
+    /// generating question `n`, or reviewing its answer, counting from 1
+    Question(usize),
+    /// summarizing the interview
+    Summary,
+    /// writing the plan and judging it
+    Plan,
+    /// running step `index` of the plan and diagnosing its failure, counting from 0 like the
+    /// protocol
+    Step(usize),
+    /// anything else, e.g. learning preferences or writing a commit message
+    Other,
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Questions => f.write_str("questions"),
+            Self::Question(n) => write!(f, "question {n}"),
+            Self::Summary => f.write_str("summary"),
+            Self::Plan => f.write_str("plan"),
+            // frontends show steps counting from 1
+            Self::Step(index) => write!(f, "step {}", index + 1),
+            Self::Other => f.write_str("other"),
+        }
+    }
+}
+
+impl Purpose {
+    /// Whether a cheaper model is good enough for it when the budget runs low, unlike for plans
+    /// and code
+    const fn low_stakes(self) -> bool {
+        matches!(self, Self::Questions | Self::Question(_) | Self::Summary)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Spent {
+    requests: usize,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/// Whether low-stakes requests are sent to [`CHEAP_MODEL`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Downshift {
+    #[default]
+    No,
+    /// since the session spent these millionths of a dollar, not reported yet
+    Since(u64),
+    Reported,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    spent: BTreeMap<(Purpose, &'static str), Spent>,
+    downshift: Downshift,
+}
+
+/// What the requests of one session cost, by purpose and model
+#[derive(Debug, Default, Clone)]
+pub struct Usage(Arc<Mutex<Recorded>>);
+
+impl Usage {
+    /// What was spent so far, in the order of [`Purpose`]
+    pub fn stats(&self) -> Vec<UsageStats> {
+        self.0
+            .lock()
+            .spent
+            .iter()
+            .map(|(&(purpose, model), spent)| UsageStats {
+                purpose: purpose.to_string(),
+                model: model.to_string(),
+                requests: spent.requests,
+                prompt_tokens: spent.prompt_tokens,
+                completion_tokens: spent.completion_tokens,
+                micro_usd: cost(model, spent),
+            })
+            .collect()
+    }
+
+    /// What the session spent since its low-stakes requests are sent to [`CHEAP_MODEL`], once
+    /// after the [`downshift`].
+    pub fn downshifted(&self) -> Option<u64> {
+        let mut recorded = self.0.lock();
+        let Downshift::Since(spent) = recorded.downshift else {
+            return None;
+        };
+        recorded.downshift = Downshift::Reported;
+        drop(recorded);
+        Some(spent)
+    }
+}
+
+/// Millionths of a dollar spent on `spent` of every purpose and model
+fn total(spent: &BTreeMap<(Purpose, &'static str), Spent>) -> u64 {
+    spent
+        .iter()
+        .map(|(&(_, model), spent)| cost(model, spent))
+        .sum()
+}
+
+/// Send `request` to [`CHEAP_MODEL`] instead if it is for a low-stakes purpose and the session
+/// spent [`DOWNSHIFT_PERCENT`] of `cap`, in cents. Without a cap, or outside of a session, it
+/// stays as it is.
+pub fn downshift(mut request: ChatRequest, cap: Option<u64>) -> ChatRequest {
+    let purpose = PURPOSE
+        .try_with(|purpose| *purpose)
+        .unwrap_or(Purpose::Other);
+    let Some(cap) = cap.filter(|_| purpose.low_stakes() && request.model == ChatModel::Gpt4) else {
+        return request;
+    };
+
+    let _ = USAGE.try_with(|usage| {
+        let mut recorded = usage.0.lock();
+        let spent = total(&recorded.spent);
+        // cents are ten thousand millionths of a dollar
+        if spent * 100 < cap * 10_000 * DOWNSHIFT_PERCENT {
+            return;
+        }
+        if recorded.downshift == Downshift::No {
+            recorded.downshift = Downshift::Since(spent);
+        }
+        drop(recorded);
+        request.model = CHEAP_MODEL;
+    });
+    request
+}
+
+/// `micro_usd` as dollars and cents, e.g. `$0.24`
+pub fn dollars(micro_usd: u64) -> String {
+    let cents = micro_usd / 10_000;
+    format!("${}.{:02}", cents / 100, cents % 100)
+}
+
+/// The name of the model low-stakes requests are sent to once the budget runs low
+pub const fn cheap_model() -> &'static str {
+    name(CHEAP_MODEL)
+}
+
+/// Run `f` for a session with `usage`, the requests it sends count in it.
+pub async fn scope<F: Future>(usage: Usage, f: F) -> F::Output {
+    USAGE.scope(usage, f).await
+}
+
+/// Run `f` for `purpose`, the requests it sends count for it.
+pub async fn purpose<F: Future>(purpose: Purpose, f: F) -> F::Output {
+    PURPOSE.scope(purpose, f).await
+}
+
+/// Counts a request and its response for the purpose it is sent for
+pub struct Meter {
+    usage: Usage,
+    key: (Purpose, &'static str),
+}
+
+impl Meter {
+    /// Count `request`, `None` if it is not sent for a session.
+    pub fn sent(request: &ChatRequest) -> Option<Self> {
+        let usage = USAGE.try_with(Usage::clone).ok()?;
+        let purpose = PURPOSE
+            .try_with(|purpose| *purpose)
+            .unwrap_or(Purpose::Other);
+        let meter = Self {
+            usage,
+            key: (purpose, name(request.model)),
+        };
+
+        let prompt = request
+            .messages
+            .iter()
+            .map(|message| tokens(&message.content))
+            .sum::<u64>();
+        let mut recorded = meter.usage.0.lock();
+        let spent = recorded.spent.entry(meter.key).or_default();
+        spent.requests += 1;
+        spent.prompt_tokens += prompt;
+        drop(recorded);
+
+        Some(meter)
+    }
+
+    /// `text` was received, a whole response or a part of it.
+    pub fn received(&self, text: &str) {
+        self.usage
+            .0
+            .lock()
+            .spent
+            .entry(self.key)
+            .or_default()
+            .completion_tokens += tokens(text);
+    }
+}
+
+/// The name of `model` the provider knows it by
+const fn name(model: ChatModel) -> &'static str {
+    match model {
+        ChatModel::Gpt4 => "gpt-4",
+        ChatModel::Turbo => "gpt-3.5-turbo",
+        ChatModel::Turbo0301 => "gpt-3.5-turbo-0301",
+    }
+}
+
+/// The cost of `spent` with the model `name` in millionths of a dollar, at its list prices in
+/// thousandths of a millionth of a dollar per prompt and completion token
+fn cost(name: &str, spent: &Spent) -> u64 {
+    let (prompt, completion) = match name {
+        "gpt-4" => (30_000, 60_000),
+        _ => (1_500, 2_000),
+    };
+    (spent.prompt_tokens * prompt + spent.completion_tokens * completion) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_openai::{ChatModel, ChatRequest};
+    use utils::discretize::CHARS_PER_TOKEN;
+
+    use super::{downshift, Meter, Purpose, Usage};
+
+    #[tokio::test]
+    async fn test_usage() {
+        let request = |model| {
+            ChatRequest::new()
+                .model(model)
+                .user_msg("a".repeat(1000 * CHARS_PER_TOKEN))
+        };
+        let completion = "a".repeat(500 * CHARS_PER_TOKEN);
+
+        // outside of a session nothing is counted
+        assert!(Meter::sent(&request(ChatModel::Gpt4)).is_none());
+
+        let usage = Usage::default();
+        super::scope(usage.clone(), async {
+            let meter = Meter::sent(&request(ChatModel::Gpt4)).unwrap();
+            meter.received(&completion);
+
+            super::purpose(Purpose::Step(2), async {
+                Meter::sent(&request(ChatModel::Gpt4)).unwrap();
+                Meter::sent(&request(ChatModel::Gpt4))
+                    .unwrap()
+                    .received(&completion);
+            })
+            .await;
+
+            super::purpose(Purpose::Question(1), async {
+                Meter::sent(&request(ChatModel::Turbo))
+                    .unwrap()
+                    .received(&completion);
+            })
+            .await;
+        })
+        .await;
+
+        let stats: Vec<_> = usage.stats().iter().map(ToString::to_string).collect();
+        assert_eq!(stats, [
+            "question 1 cost 1k tokens ($0.00) on gpt-3.5-turbo",
+            "step 3 cost 2k tokens ($0.09) on gpt-4",
+            "other cost 1k tokens ($0.06) on gpt-4",
+        ]);
+        let step = &usage.stats()[1];
+        assert_eq!(
+            (step.requests, step.prompt_tokens, step.completion_tokens),
+            (2, 2000, 500)
+        );
+        assert_eq!(step.micro_usd, 90_000);
+    }
+
+    #[tokio::test]
+    async fn test_downshift() {
+        let request = || ChatRequest::new().user_msg("a".repeat(10_000 * CHARS_PER_TOKEN));
+        let question = |cap| {
+            super::purpose(Purpose::Question(2), async move {
+                downshift(request(), cap).model
+            })
+        };
+
+        // outside of a session nothing was spent
+        assert_eq!(question(Some(0)).await, ChatModel::Gpt4);
+
+        let usage = Usage::default();
+        super::scope(usage.clone(), async {
+            // 30 cents
+            Meter::sent(&request()).unwrap();
+
+            assert_eq!(question(None).await, ChatModel::Gpt4);
+            // less than 80% of 40 cents
+            assert_eq!(question(Some(40)).await, ChatModel::Gpt4);
+            assert_eq!(usage.downshifted(), None);
+
+            assert_eq!(question(Some(37)).await, ChatModel::Turbo);
+            let plan = super::purpose(Purpose::Plan, async { downshift(request(), Some(37)) });
+            assert_eq!(plan.await.model, ChatModel::Gpt4);
+        })
+        .await;
+
+        // reported once
+        assert_eq!(usage.downshifted(), Some(300_000));
+        assert_eq!(usage.downshifted(), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Keep the connection to the provider open.
+//!
+//! The provider client shares its connection pool with [`Warmer`]. Pinging the provider while
+//! the user is thinking about an answer keeps a pooled connection alive, so the next question
+//! does not wait for a new TCP and TLS handshake before its first token.
+
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use protocol::{trace::Distribution, LatencyStats};
+use tracing::debug;
+
+/// A cheap endpoint of the provider, any response keeps the connection open
+pub const PROVIDER_URL: &str = "https://api.openai.com/v1/models";
+
+/// The name of the ping latencies in the `/latency` report
+const PING: &str = "executor → provider ping";
+
+#[derive(Clone)]
+pub struct Warmer {
+    req: reqwest::Client,
+    url: String,
+    pings: Arc<Mutex<Distribution>>,
+}
+
+impl Warmer {
+    pub fn new(req: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            req,
+            url: url.into(),
+            pings: Arc::default(),
+        }
+    }
+
+    /// Ping the provider every `interval` until the warmer is dropped.
+    pub fn keep_warm(&self, interval: Duration) {
+        let req = self.req.clone();
+        let url = self.url.clone();
+        let pings = Arc::downgrade(&self.pings);
+
+        crate::tasks::spawn("warmer", async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if !ping(&req, &url, &pings).await {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Ping the provider in the background because a request is likely soon, e.g. the first
+    /// question of a session.
+    pub fn prewarm(&self) {
+        let req = self.req.clone();
+        let url = self.url.clone();
+        let pings = Arc::downgrade(&self.pings);
+
+        tokio::spawn(async move { ping(&req, &url, &pings).await });
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> Option<LatencyStats> {
+        self.pings.lock().stats(PING)
+    }
+}
+
+/// Send a request over the pooled connection. Returns whether the warmer still exists.
+async fn ping(req: &reqwest::Client, url: &str, pings: &Weak<Mutex<Distribution>>) -> bool {
+    if pings.strong_count() == 0 {
+        return false;
+    }
+
+    let start = Instant::now();
+    let result = req.head(url).send().await;
+    let latency = start.elapsed();
+
+    let Some(pings) = pings.upgrade() else {
+        return false;
+    };
+
+    // the status does not matter, only that the provider answered
+    match result {
+        Ok(_) => pings.lock().record(latency),
+        Err(e) => debug!("Failed to ping the provider: {e}"),
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::Warmer;
+
+    #[tokio::test]
+    async fn test_ping() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/v1/models", listener.local_addr()?);
+
+        // answers every request on one connection, as the provider does
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            let mut buf = [0; 1024];
+            while socket.read(&mut buf).await? > 0 {
+                socket
+                    .write_all(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n")
+                    .await?;
+            }
+            anyhow::Ok(())
+        });
+
+        let warmer = Warmer::new(reqwest::Client::new(), url);
+        assert!(warmer.stats().is_none());
+
+        warmer.keep_warm(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stats = warmer.stats().expect("pinged");
+        assert!(stats.count > 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! Keep commands inside the working directory of the session.
+//!
+//! Every path a command reads or writes is resolved with [`resolve`], which canonicalizes it and
+//! rejects it if it is not inside the workspace root. Symlinks pointing out of the workspace are
+//! rejected as well, because canonicalizing follows them, and so are dangling symlinks, whose
+//! target cannot be canonicalized but would be created by writing through them.
+
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+
+pub mod snapshot;
+pub mod stats;
+
+/// Resolve `path` relative to `root`, making sure it stays inside `root`.
+///
+/// `path` does not have to exist, so it can be used for files that are about to be created.
+///
+/// # Errors
+/// - `root` does not exist
+/// - `path` is outside of `root`
+/// - `path` goes through a symlink whose target does not exist
+pub fn resolve(root: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve the workspace {}", root.display()))?;
+
+    ensure!(!path.trim().is_empty(), "The path is empty");
+
+    let joined = root.join(path);
+    let components: Vec<_> = joined.components().collect();
+
+    // the file and some of its directories may not exist yet, canonicalize what does exist
+    for split in (1..=components.len()).rev() {
+        let ancestor: PathBuf = components[..split].iter().collect();
+        let rest: PathBuf = components[split..].iter().collect();
+
+        let existing = match ancestor.canonicalize() {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                // the link exists even if its target does not
+                if ancestor.symlink_metadata().is_ok() {
+                    bail!("{path} goes through a symlink that points nowhere");
+                }
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to resolve {}", ancestor.display()))
+            }
+        };
+
+        // `..` in a part that does not exist cannot be resolved
+        if rest
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            bail!("{path} is not a valid path");
+        }
+
+        // joining an empty path would add a trailing slash
+        let resolved = if rest.as_os_str().is_empty() {
+            existing
+        } else {
+            existing.join(rest)
+        };
+        ensure!(
+            resolved.starts_with(&root),
+            "{path} is outside of the workspace"
+        );
+
+        return Ok(resolved);
+    }
+
+    bail!("{path} is outside of the workspace")
+}
+
+/// Prepare the directory at `path` a frontend chose to work in, creating it if it does not
+/// exist. Returns where it is, with symlinks followed.
+///
+/// # Errors
+/// - `path` is not absolute
+/// - `path` cannot be created or is not a directory
+/// - `path` is the root of the file system
+pub fn prepare(path: &str) -> anyhow::Result<PathBuf> {
+    let path = Path::new(path.trim());
+    ensure!(
+        path.is_absolute(),
+        "{} is not an absolute path",
+        path.display()
+    );
+
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("Failed to create the workspace {}", path.display()))?;
+    let dir = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve the workspace {}", path.display()))?;
+    ensure!(dir.is_dir(), "{} is not a directory", dir.display());
+    ensure!(
+        dir.parent().is_some(),
+        "The root of the file system cannot be the workspace"
+    );
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prepare, resolve};
+
+    #[test]
+    fn test_resolve() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+        std::fs::create_dir(root.join("src"))?;
+
+        assert_eq!(resolve(&root, "src")?, root.join("src"));
+        assert_eq!(resolve(&root, "src/main.rs")?, root.join("src/main.rs"));
+        assert_eq!(
+            resolve(&root, "./src/../Cargo.toml")?,
+            root.join("Cargo.toml")
+        );
+        assert_eq!(
+            resolve(&root, "new/dir/lib.rs")?,
+            root.join("new/dir/lib.rs")
+        );
+
+        assert!(resolve(&root, "../outside").is_err());
+        assert!(resolve(&root, "/etc/passwd").is_err());
+        assert!(resolve(&root, "new/../../outside").is_err());
+        assert!(resolve(&root, "").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+
+        let created = root.join("new/calculator");
+        assert_eq!(prepare(&created.to_string_lossy())?, created);
+        assert!(created.is_dir());
+        // an existing directory is kept
+        std::fs::write(created.join("main.rs"), "fn main() {}")?;
+        assert_eq!(prepare(&created.to_string_lossy())?, created);
+        assert!(created.join("main.rs").exists());
+
+        assert!(prepare("calculator").is_err());
+        assert!(prepare(&created.join("main.rs").to_string_lossy()).is_err());
+        #[cfg(unix)]
+        assert!(prepare("/").is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link"))?;
+
+        assert!(resolve(dir.path(), "link/file").is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_symlink() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        std::os::unix::fs::symlink(outside.path().join("new"), dir.path().join("link"))?;
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("inside"))?;
+
+        assert!(resolve(dir.path(), "link").is_err());
+        assert!(resolve(dir.path(), "link/file").is_err());
+        // even if it would point into the workspace once its target exists
+        assert!(resolve(dir.path(), "inside").is_err());
+        assert!(!outside.path().join("new").exists());
+
+        Ok(())
+    }
+}
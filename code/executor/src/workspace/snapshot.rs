@@ -0,0 +1,164 @@
+//! What the working directory contained before a plan ran, so it can be restored.
+//!
+//! Files git would ignore are neither kept nor restored, e.g. `target`, and neither is `.git`. A
+//! commit the plan made is kept; only the files go back.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context};
+
+/// The most bytes a snapshot keeps, larger working directories are not snapshotted
+const MAX_BYTES: u64 = 64 << 20;
+
+/// The content of every file of a working directory, by path relative to it
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// What [`Snapshot::restore`] changed, paths relative to the working directory
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Restored {
+    /// files written back as they were
+    pub restored: Vec<String>,
+    /// files that did not exist before
+    pub deleted: Vec<String>,
+}
+
+impl Restored {
+    /// What was restored, for the user
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let list = |paths: &[String]| paths.join(", ");
+        match (self.restored.is_empty(), self.deleted.is_empty()) {
+            (true, true) => "the plan changed no files".to_string(),
+            (false, true) => format!("restored {}", list(&self.restored)),
+            (true, false) => format!("deleted {}", list(&self.deleted)),
+            (false, false) => format!(
+                "restored {} and deleted {}",
+                list(&self.restored),
+                list(&self.deleted)
+            ),
+        }
+    }
+}
+
+/// The files of `root` a snapshot covers, relative to it
+fn walk(root: &Path) -> Vec<PathBuf> {
+    let walk = ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    walk.flatten()
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .filter_map(|entry| Some(entry.path().strip_prefix(root).ok()?.to_path_buf()))
+        .collect()
+}
+
+impl Snapshot {
+    /// Keep the files of the working directory `root`.
+    ///
+    /// # Errors
+    /// - The files take more than [`MAX_BYTES`]
+    /// - A file could not be read
+    pub fn take(root: &Path) -> anyhow::Result<Self> {
+        let mut size = 0;
+        let mut files = BTreeMap::new();
+
+        for path in walk(root) {
+            let content = std::fs::read(root.join(&path))
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            size += content.len() as u64;
+            ensure!(
+                size <= MAX_BYTES,
+                "the working directory is larger than {} MiB",
+                MAX_BYTES >> 20
+            );
+            files.insert(path, content);
+        }
+
+        Ok(Self { files })
+    }
+
+    /// Put the files of `root` back as they were, deleting those that were created since.
+    ///
+    /// # Errors
+    /// If a file could not be written or deleted. The files before it are restored.
+    pub fn restore(&self, root: &Path) -> anyhow::Result<Restored> {
+        let mut restored = Restored::default();
+
+        for path in walk(root) {
+            if !self.files.contains_key(&path) {
+                std::fs::remove_file(root.join(&path))
+                    .with_context(|| format!("Failed to delete {}", path.display()))?;
+                restored.deleted.push(path.display().to_string());
+            }
+        }
+
+        for (path, content) in &self.files {
+            let absolute = root.join(path);
+            if std::fs::read(&absolute).ok().as_ref() == Some(content) {
+                continue;
+            }
+
+            if let Some(parent) = absolute.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&absolute, content)
+                .with_context(|| format!("Failed to restore {}", path.display()))?;
+            restored.restored.push(path.display().to_string());
+        }
+
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Restored, Snapshot};
+
+    #[test]
+    fn test_restore() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        std::fs::write(root.join(".gitignore"), "target\n")?;
+        std::fs::create_dir(root.join("src"))?;
+        std::fs::write(root.join("src/main.rs"), "fn main() {}\n")?;
+        std::fs::write(root.join("README.md"), "# Calculator\n")?;
+
+        let snapshot = Snapshot::take(root)?;
+        assert_eq!(snapshot.restore(root)?, Restored::default());
+
+        std::fs::write(root.join("src/main.rs"), "fn main() { panic!() }\n")?;
+        std::fs::remove_file(root.join("README.md"))?;
+        std::fs::write(root.join("src/lib.rs"), "")?;
+        std::fs::create_dir(root.join("target"))?;
+        std::fs::write(root.join("target/build"), "")?;
+
+        let restored = snapshot.restore(root)?;
+        assert_eq!(restored, Restored {
+            restored: vec!["README.md".to_string(), "src/main.rs".to_string()],
+            deleted: vec!["src/lib.rs".to_string()],
+        });
+        assert_eq!(
+            restored.summary(),
+            "restored README.md, src/main.rs and deleted src/lib.rs"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("src/main.rs"))?,
+            "fn main() {}\n"
+        );
+        // ignored files are left alone
+        assert!(root.join("target/build").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! What a workspace is made of: files by language, frameworks and build tools.
+//!
+//! Included in planning prompts so plans use the toolchain the workspace already has, e.g. no
+//! `npm install` in a pure Cargo workspace.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use protocol::{server, LanguageStats};
+
+/// Directories with generated or vendored files
+const SKIPPED_DIRS: &[&str] = &[
+    "target",
+    "node_modules",
+    "dist",
+    "build",
+    "venv",
+    "__pycache__",
+];
+
+/// Stop counting in huge workspaces, the proportions are clear by then
+const MAX_FILES: usize = 10_000;
+
+const LANGUAGES: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("toml", "TOML"),
+    ("py", "Python"),
+    ("js", "JavaScript"),
+    ("jsx", "JavaScript"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("go", "Go"),
+    ("c", "C"),
+    ("h", "C"),
+    ("cc", "C++"),
+    ("cpp", "C++"),
+    ("hpp", "C++"),
+    ("java", "Java"),
+    ("rb", "Ruby"),
+    ("sh", "Shell"),
+    ("html", "HTML"),
+    ("css", "CSS"),
+    ("json", "JSON"),
+    ("yml", "YAML"),
+    ("yaml", "YAML"),
+    ("md", "Markdown"),
+];
+
+/// Manifests and the build tool they imply
+const BUILD_TOOLS: &[(&str, &str)] = &[
+    ("Cargo.toml", "cargo"),
+    ("package.json", "npm"),
+    ("yarn.lock", "yarn"),
+    ("pnpm-lock.yaml", "pnpm"),
+    ("requirements.txt", "pip"),
+    ("pyproject.toml", "pip"),
+    ("go.mod", "go"),
+    ("Makefile", "make"),
+    ("CMakeLists.txt", "cmake"),
+    ("Dockerfile", "docker"),
+];
+
+/// Dependencies worth telling the model about, by manifest
+const FRAMEWORKS: &[(&str, &[&str])] = &[
+    ("Cargo.toml", &[
+        "tokio",
+        "axum",
+        "actix-web",
+        "rocket",
+        "warp",
+        "bevy",
+        "leptos",
+        "yew",
+        "tauri",
+        "clap",
+        "diesel",
+        "sqlx",
+    ]),
+    ("package.json", &[
+        "react", "next", "vue", "svelte", "angular", "express", "vite",
+    ]),
+    ("requirements.txt", &[
+        "django", "flask", "fastapi", "numpy", "torch",
+    ]),
+    ("pyproject.toml", &[
+        "django", "flask", "fastapi", "numpy", "torch",
+    ]),
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub languages: BTreeMap<&'static str, usize>,
+    pub frameworks: BTreeSet<&'static str>,
+    pub build_tools: BTreeSet<&'static str>,
+}
+
+/// Analyze the files in `root`. A missing `root` is an empty workspace.
+#[must_use]
+pub fn analyze(root: &Path) -> Stats {
+    let mut stats = Stats::default();
+    let mut dirs = vec![root.to_path_buf()];
+    let mut files = 0;
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            if file_type.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()) {
+                    dirs.push(path);
+                }
+                continue;
+            }
+
+            files += 1;
+            if files > MAX_FILES {
+                return stats;
+            }
+
+            stats.add(&name, &path);
+        }
+    }
+
+    stats
+}
+
+/// Whether the manifest `content` depends on `dependency`
+fn depends_on(content: &str, dependency: &str) -> bool {
+    content.lines().any(|line| {
+        let line = line.trim().trim_matches('"').to_lowercase();
+        line.strip_prefix(dependency).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with([' ', '=', '"', '<', '>', '~', '[', ':'])
+        })
+    })
+}
+
+impl Stats {
+    fn add(&mut self, name: &str, path: &Path) {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if let Some(&(_, language)) = LANGUAGES.iter().find(|(ext, _)| Some(*ext) == extension) {
+            *self.languages.entry(language).or_default() += 1;
+        }
+
+        if let Some(&(_, tool)) = BUILD_TOOLS.iter().find(|(manifest, _)| *manifest == name) {
+            self.build_tools.insert(tool);
+        }
+
+        if let Some(&(_, frameworks)) = FRAMEWORKS.iter().find(|(manifest, _)| *manifest == name) {
+            let Ok(content) = std::fs::read_to_string(path) else {
+                return;
+            };
+            self.frameworks.extend(
+                frameworks
+                    .iter()
+                    .filter(|framework| depends_on(&content, framework)),
+            );
+        }
+    }
+
+    /// Languages with the most files first
+    fn languages(&self) -> Vec<(&'static str, usize)> {
+        let mut languages: Vec<_> = self.languages.iter().map(|(l, n)| (*l, *n)).collect();
+        languages.sort_by_key(|(_, files)| std::cmp::Reverse(*files));
+        languages
+    }
+
+    /// A description of the workspace for the model
+    #[must_use]
+    pub fn summary(&self) -> String {
+        if self.languages.is_empty() && self.build_tools.is_empty() {
+            return "The workspace is empty.".to_string();
+        }
+
+        let languages = self
+            .languages()
+            .iter()
+            .map(|(language, files)| format!("{files} {language}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut summary = format!("The workspace contains {languages} files.");
+
+        if !self.build_tools.is_empty() {
+            let tools = self.build_tools.iter().copied().collect::<Vec<_>>();
+            summary.push_str(&format!(
+                " It is built with {}, do not use other build tools.",
+                tools.join(", ")
+            ));
+        }
+
+        if !self.frameworks.is_empty() {
+            let frameworks = self.frameworks.iter().copied().collect::<Vec<_>>();
+            summary.push_str(&format!(" It uses {}.", frameworks.join(", ")));
+        }
+
+        summary
+    }
+
+    #[must_use]
+    pub fn info(&self) -> server::WorkspaceInfo {
+        server::WorkspaceInfo {
+            languages: self
+                .languages()
+                .into_iter()
+                .map(|(language, files)| LanguageStats {
+                    language: language.to_string(),
+                    files,
+                })
+                .collect(),
+            frameworks: self.frameworks.iter().map(ToString::to_string).collect(),
+            build_tools: self.build_tools.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyze;
+
+    #[test]
+    fn test_analyze() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+
+        assert_eq!(
+            analyze(&root.join("missing")).summary(),
+            "The workspace is empty."
+        );
+
+        std::fs::create_dir_all(root.join("src/bin"))?;
+        std::fs::create_dir_all(root.join("target/debug"))?;
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\n\n[dependencies]\ntokio = { version = \"1\" \
+             }\ntokio-stream = \"0.1\"\n",
+        )?;
+        std::fs::write(root.join("src/main.rs"), "fn main() {}")?;
+        std::fs::write(root.join("src/bin/tool.rs"), "fn main() {}")?;
+        std::fs::write(root.join("target/debug/build.rs"), "")?;
+
+        let stats = analyze(root);
+        assert_eq!(stats.languages.get("Rust"), Some(&2));
+        assert_eq!(stats.languages.get("TOML"), Some(&1));
+        assert!(stats.build_tools.contains("cargo"));
+        assert!(!stats.build_tools.contains("npm"));
+        assert_eq!(stats.frameworks.iter().copied().collect::<Vec<_>>(), [
+            "tokio"
+        ]);
+
+        assert_eq!(
+            stats.summary(),
+            "The workspace contains 2 Rust, 1 TOML files. It is built with cargo, do not use \
+             other build tools. It uses tokio."
+        );
+
+        let info = stats.info();
+        assert_eq!(info.languages[0].language, "Rust");
+
+        Ok(())
+    }
+}
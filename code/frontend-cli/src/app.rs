@@ -1,29 +1,87 @@
-use std::{pin::pin, time::Duration};
+use std::{
+    path::PathBuf,
+    pin::pin,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
-use crossterm::event::{poll, KeyCode};
+use collective_client::{files::Files, streams::Streams};
+use crossterm::event::{poll, KeyCode, KeyModifiers};
 use futures::{future, future::Either};
-use protocol::{client, server::Server};
+use protocol::{
+    client,
+    server::{Server, SourcePreview},
+    trace::{Distribution, Tracer},
+    LatencyStats, Phase, Resolution, Validator,
+};
 use tracing::debug;
 use tui::{backend::Backend, Terminal};
 
-use crate::{ui::Ui, Event, CANCEL_TOKEN};
+use crate::{
+    batch::Batch,
+    commands::{self, Palette},
+    health::StreamHealth,
+    history::{History, Search},
+    keys::{Binding, Keymap},
+    pane::View,
+    perf::{Pending, Perf},
+    sources::Sources,
+    steps::Steps,
+    ui::Ui,
+    undo::{Edit, UndoStack},
+    Event, CANCEL_TOKEN,
+};
+
+/// How often [`Event::Tick`] is emitted
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Shortest time between two draws, the events arriving in between are drawn together
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
 
 pub struct App {
     tx: tokio::sync::mpsc::UnboundedSender<protocol::ClientPacket>,
     rx: tokio::sync::mpsc::UnboundedReceiver<protocol::ServerPacket>,
     instruction: Option<String>,
+
+    /// number of questions that were streamed completely
+    questions: usize,
+
+    /// after how long without a delta the stream is considered stalled
+    stall_timeout: Duration,
+
+    /// instructions and answers of all sessions
+    history: History,
+
+    /// the log that is written to, shown by `/logs`
+    log: PathBuf,
+
+    /// screen reader friendly output, see [`Ui::new`]
+    accessible: bool,
+
+    /// the keys of the shortcuts
+    keymap: Keymap,
 }
 
 impl App {
     pub fn new(
         tx: tokio::sync::mpsc::UnboundedSender<protocol::ClientPacket>,
         rx: tokio::sync::mpsc::UnboundedReceiver<protocol::ServerPacket>,
+        stall_timeout: Duration,
+        history: History,
+        log: PathBuf,
+        accessible: bool,
+        keymap: Keymap,
     ) -> Self {
         Self {
             tx,
             rx,
             instruction: None,
+            questions: 0,
+            stall_timeout,
+            history,
+            log,
+            accessible,
+            keymap,
         }
     }
 
@@ -33,13 +91,16 @@ impl App {
         mut self,
         terminal: &mut Terminal<B>,
     ) -> anyhow::Result<()> {
-        let mut ui = Ui::new();
+        let mut ui = Ui::new(self.accessible);
 
         // channel that handles Events
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        // the events sent on it and not handled yet, shown by the performance overlay
+        let pending = Pending::default();
 
         std::thread::spawn({
             let tx = tx.clone();
+            let pending = pending.clone();
             move || {
                 loop {
                     if CANCEL_TOKEN.is_cancelled() {
@@ -55,7 +116,9 @@ impl App {
                             continue;
                         };
 
-                        if let Err(e) = tx.send(Event::Terminal(event)) {
+                        let event = Event::Terminal(event);
+                        pending.sent(&event);
+                        if let Err(e) = tx.send(event) {
                             debug!("Cannot send event to terminal -> shutting down: {e:?}");
                             CANCEL_TOKEN.cancel();
                             return;
@@ -65,9 +128,72 @@ impl App {
             }
         });
 
+        // emit ticks so the stream health indicator is updated even without packets
+        tokio::spawn({
+            let tx = tx.clone();
+            let pending = pending.clone();
+            async move {
+                let mut interval = tokio::time::interval(TICK_INTERVAL);
+                loop {
+                    tokio::select! {
+                        () = CANCEL_TOKEN.cancelled() => return,
+                        _ = interval.tick() => {}
+                    }
+                    pending.sent(&Event::Tick);
+                    if tx.send(Event::Tick).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
         let mut waiting_for_question = false;
+        let mut health = StreamHealth::default();
+        let mut files = Files::default();
+        let mut undo = UndoStack::default();
+        // a risky command is waiting for y/n
+        let mut confirming = false;
+        // a file with conflicts is waiting for k/o/r
+        let mut resolving = false;
+        // the Ctrl+R history search, if open
+        let mut search: Option<Search> = None;
+        // the commands the `/` being typed could become
+        let mut palette = Palette::default();
+        // progress of the file that is currently being streamed
+        let mut file_status = None;
+        // latencies of the packets to and from the executor
+        let mut tracer = Tracer::default();
+        // the questions being streamed
+        let mut questions = Streams::default();
+        // the session of this connection, marked in `/session list`
+        let mut session = None;
+        // questions asked at once that are not all answered yet
+        let mut batch: Option<Batch> = None;
+        // the format the answer to the current question must have
+        let mut validator: Option<Validator> = None;
+        // why the answer that was typed is not sent
+        let mut invalid: Option<String> = None;
+        // the answer to a secret question as it is typed, only shown masked
+        let mut secret: Option<String> = None;
+        // what the executor is doing, and the plan it is writing
+        let mut phase: Option<Phase> = None;
+        let mut plan = String::new();
+        // the steps of the plan, shown in their own pane
+        let mut steps = Steps::default();
+        // the external material the plan draws on, shown in their own pane
+        let mut sources = Sources::default();
+        // from a packet arriving to it being drawn
+        let mut render = Distribution::default();
+        let mut received: Option<Instant> = None;
+        // the status last announced in the conversation, in accessible mode
+        let mut announced: Option<String> = None;
+        // when the screen may be drawn again
+        let mut next_frame = Instant::now();
+        // how the frontend keeps up, shown by its shortcut
+        let mut perf = Perf::default();
 
         // receive a Packet<Server> and emit an Event::Packet(packet<server>)
+        let sent = pending.clone();
         tokio::spawn(async move {
             let mut reader = self.rx;
             loop {
@@ -89,7 +215,9 @@ impl App {
 
                 // grab the packet, wrap it into an Event.
                 // send to the loop below
-                if let Err(e) = tx.send(Event::Packet(packet)) {
+                let event = Event::Packet(packet);
+                sent.sent(&event);
+                if let Err(e) = tx.send(event) {
                     debug!("Cannot send packet to terminal -> shutting down: {e:?}");
                     CANCEL_TOKEN.cancel();
                     return;
@@ -100,24 +228,360 @@ impl App {
         // handle all events, including events received from above
         // and send a Packet<Client> to the executor `fn process_packet`?
         loop {
-            terminal.draw(|frame| ui.run(frame))?;
+            // nothing changes on every tick in accessible mode
+            let status = if self.accessible {
+                health.plain_status(self.stall_timeout, Instant::now())
+            } else {
+                health.status(self.stall_timeout, Instant::now())
+            };
+            let format = validator
+                .as_ref()
+                .map(|validator| format!("expects {validator}"));
+            let activity = match phase {
+                Some(Phase::Planning) if !plan.is_empty() && !self.accessible => {
+                    Some(format!("planning · {} lines", plan.lines().count()))
+                }
+                Some(Phase::Waiting) | None => None,
+                Some(phase) => Some(format!("{phase}…")),
+            };
+            let lost = questions
+                .missing()
+                .first()
+                .map(|(_, frames)| format!("! frames {frames:?} of the question were lost"));
+            let progress = batch.as_ref().map(|batch| {
+                format!(
+                    "{} · `2: answer` answers the second question",
+                    batch.progress()
+                )
+            });
+            let status = status
+                .or(lost)
+                .or_else(|| file_status.clone())
+                .or_else(|| invalid.clone())
+                .or(progress)
+                .or(format)
+                .or(activity);
+            // screen readers do not notice the status line change, it is announced once nothing
+            // is being typed
+            if self.accessible && status != announced && ui.line().is_empty() {
+                if let Some(status) = &status {
+                    ui.push_text(&format!("* {status}"));
+                    ui.new_line();
+                }
+                announced = status.clone();
+            }
+            ui.set_status(status);
+            ui.set_plan(&steps);
+            ui.set_sources(&sources);
+            let overlay = match &search {
+                Some(search) => search.render(&self.history.search(&search.query)),
+                None if !waiting_for_question && !confirming && !resolving => {
+                    palette.render(&commands::candidates(ui.line()))
+                }
+                None => Vec::new(),
+            };
+            ui.set_overlay(overlay);
+
+            // nothing is drawn while nothing changed, and at most once per frame
+            let now = Instant::now();
+            if ui.needs_draw() && now >= next_frame {
+                terminal.draw(|frame| ui.run(frame))?;
+                perf.drawn(now.elapsed());
+                next_frame = now + FRAME_INTERVAL;
+                if let Some(at) = received.take() {
+                    render.record(at.elapsed());
+                }
+            }
 
-            let event = rx.recv().await.context("Failed to receive event")?;
+            let event = if ui.needs_draw() {
+                tokio::select! {
+                    event = rx.recv() => event,
+                    () = tokio::time::sleep_until(next_frame.into()) => continue,
+                }
+            } else {
+                rx.recv().await
+            };
+            let event = event.context("Failed to receive event")?;
+            pending.received(&event);
+            if let Event::Packet(packet) = &event {
+                perf.packet(Instant::now());
+                tracer.received(packet);
+                // the latency of a frame is that of the oldest packet it draws
+                received.get_or_insert_with(Instant::now);
+            }
+            if let Event::Terminal(crossterm::event::Event::Resize(..)) = event {
+                ui.invalidate();
+            }
 
             use crossterm::event::Event::Key as CrossKey;
 
+            // the overlay is refreshed on every tick, as refreshing it on every frame would draw
+            // frames just to show how long they take
+            let toggled = matches!(&event, Event::Terminal(CrossKey(key))
+                if self.keymap.binding(key) == Some(Binding::Perf));
+            if toggled {
+                perf.toggle();
+            }
+            if toggled || matches!(event, Event::Tick) {
+                let lost = questions
+                    .missing()
+                    .iter()
+                    .map(|(_, frames)| frames.end - frames.start)
+                    .sum();
+                ui.set_perf(perf.lines(&pending, lost, Instant::now()));
+            }
+            if toggled {
+                continue;
+            }
+
+            // the split view is controlled whatever else is going on, but Tab completes commands
+            // while the palette is open
+            let completing = search.is_none() && !commands::candidates(ui.line()).is_empty();
+            let view = match &event {
+                Event::Terminal(CrossKey(key)) if completing && key.code == KeyCode::Tab => None,
+                Event::Terminal(CrossKey(key)) => self.keymap.view(key),
+                Event::Terminal(crossterm::event::Event::Mouse(mouse)) => View::from_mouse(mouse),
+                _ => None,
+            };
+            if let Some(view) = view {
+                ui.view(view);
+                continue;
+            }
+
             match event {
-                Event::Terminal(CrossKey(key)) if key.code == KeyCode::Esc => {
+                Event::Terminal(CrossKey(key)) if search.is_some() => {
+                    let Some(open) = search.as_mut() else {
+                        continue;
+                    };
+                    let matches = self.history.search(&open.query);
+                    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+                    if self.keymap.binding(&key) == Some(Binding::Search) {
+                        open.next(matches.len());
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Esc => search = None,
+                        KeyCode::Enter => {
+                            if let Some(entry) = matches.get(open.selected) {
+                                undo.clear();
+                                *ui.current_line() = (*entry).to_string();
+                            }
+                            search = None;
+                        }
+                        KeyCode::Up => open.previous(matches.len()),
+                        KeyCode::Down => open.next(matches.len()),
+                        KeyCode::Backspace => {
+                            open.query.pop();
+                            open.selected = 0;
+                        }
+                        KeyCode::Char(c) if !control => {
+                            open.query.push(c);
+                            open.selected = 0;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Terminal(CrossKey(key))
+                    if self.keymap.binding(&key) == Some(Binding::Quit) =>
+                {
                     return Ok(());
                 }
+                // the stream stalled, let the user regenerate or cancel the question
+                Event::Terminal(CrossKey(key))
+                    if waiting_for_question
+                        && health.is_stalled(self.stall_timeout, Instant::now()) =>
+                {
+                    let packet = match key.code {
+                        KeyCode::Char('r') => {
+                            health.start(Instant::now());
+                            protocol::Packet::client(client::Regenerate)
+                        }
+                        KeyCode::Char('c') => protocol::Packet::client(client::Cancel),
+                        _ => continue,
+                    };
+                    self.tx.send(tracer.stamp(packet))?;
+                }
+                Event::Terminal(CrossKey(key)) if confirming => {
+                    let approved = match key.code {
+                        KeyCode::Char('y') => true,
+                        KeyCode::Char('n') => false,
+                        _ => continue,
+                    };
+                    confirming = false;
+                    ui.current_line()
+                        .push_str(if approved { "yes" } else { "no" });
+                    ui.new_line();
+                    let packet = protocol::Packet::client(client::Confirm { approved });
+                    self.tx.send(tracer.stamp(packet))?;
+                }
+                Event::Terminal(CrossKey(key)) if resolving => {
+                    let (resolution, answer) = match key.code {
+                        KeyCode::Char('k') => (Resolution::Keep, "keep"),
+                        KeyCode::Char('o') => (Resolution::Overwrite, "overwrite"),
+                        KeyCode::Char('r') => (Resolution::Resolved, "resolved"),
+                        _ => continue,
+                    };
+                    resolving = false;
+                    ui.current_line().push_str(answer);
+                    ui.new_line();
+                    let packet = protocol::Packet::client(client::ResolveConflict { resolution });
+                    self.tx.send(tracer.stamp(packet))?;
+                }
                 Event::Terminal(CrossKey(key)) if !waiting_for_question => match key.code {
-                    KeyCode::Backspace => {
+                    // the masked line cannot be edited like a visible one
+                    KeyCode::Char(c)
+                        if secret.is_some() && !key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        secret.get_or_insert_with(String::new).push(c);
+                        ui.current_line().push('*');
+                    }
+                    KeyCode::Backspace if secret.as_mut().and_then(String::pop).is_some() => {
                         ui.current_line().pop();
                     }
+                    // nothing of the masked line is left to delete
+                    KeyCode::Backspace if secret.is_some() => {}
+                    _ if secret.is_some()
+                        && matches!(
+                            self.keymap.binding(&key),
+                            Some(Binding::Search | Binding::Undo | Binding::Redo)
+                        ) => {}
+                    _ if self.keymap.binding(&key) == Some(Binding::Search) => {
+                        search = Some(Search::default());
+                    }
+                    _ if self.keymap.binding(&key) == Some(Binding::Undo) => {
+                        undo.undo(ui.current_line());
+                    }
+                    _ if self.keymap.binding(&key) == Some(Binding::Redo) => {
+                        undo.redo(ui.current_line());
+                    }
+                    // control characters are shortcuts, they are never typed
+                    KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => {}
+                    // the palette is open while a command is typed
+                    KeyCode::Tab => {
+                        if let Some(line) = palette.complete(&commands::candidates(ui.line())) {
+                            undo.clear();
+                            *ui.current_line() = line;
+                        }
+                    }
+                    KeyCode::Up => palette.previous(commands::candidates(ui.line()).len()),
+                    KeyCode::Down => palette.next(commands::candidates(ui.line()).len()),
+                    KeyCode::Backspace => {
+                        invalid = None;
+                        palette = Palette::default();
+                        if !ui.current_line().is_empty() {
+                            undo.record(Edit::Delete, ui.current_line());
+                            ui.current_line().pop();
+                        }
+                    }
                     KeyCode::Enter => {
                         if ui.current_line().trim().is_empty() {
                             continue;
                         }
+
+                        // secrets stay out of the history
+                        if let Some(answer) = secret.take() {
+                            validator = None;
+                            waiting_for_question = true;
+                            health.start(Instant::now());
+
+                            ui.new_line();
+                            let packet = protocol::Packet::client(client::Answer { answer });
+                            self.tx.send(tracer.stamp(packet))?;
+                            continue;
+                        }
+
+                        undo.clear();
+                        self.history.add(ui.current_line());
+
+                        palette = Palette::default();
+                        match commands::parse(ui.current_line()) {
+                            Some(commands::Action::Send(packet)) => {
+                                if let client::Client::VetoSource { id } = packet.data {
+                                    sources.veto(id);
+                                }
+                                if let client::Client::ExecuteSteps { steps: selected } =
+                                    &packet.data
+                                {
+                                    steps.rerun(selected);
+                                }
+                                // the question is streamed again
+                                if matches!(packet.data, client::Client::Regenerate) {
+                                    waiting_for_question = true;
+                                    health.start(Instant::now());
+                                }
+                                ui.new_line();
+                                self.tx.send(tracer.stamp(packet))?;
+                                continue;
+                            }
+                            Some(commands::Action::Plan) => {
+                                ui.new_line();
+                                let lines = match steps.progress() {
+                                    Some(progress) => {
+                                        [vec![format!("plan · {progress}")], steps.lines()].concat()
+                                    }
+                                    None => {
+                                        vec!["no plan yet, /execute writes one and runs it"
+                                            .to_string()]
+                                    }
+                                };
+                                for line in lines {
+                                    ui.current_line().push_str(&line);
+                                    ui.new_line();
+                                }
+                                continue;
+                            }
+                            Some(commands::Action::Logs) => {
+                                ui.new_line();
+                                ui.current_line()
+                                    .push_str(&format!("logging to {}", self.log.display()));
+                                ui.new_line();
+                                continue;
+                            }
+                            Some(commands::Action::Quit) => return Ok(()),
+                            None => {}
+                        }
+                        if let Some(open) = &mut batch {
+                            let Some((item, answer)) = open.target(ui.current_line()) else {
+                                continue;
+                            };
+                            if let Some(Err(e)) = item
+                                .validator
+                                .as_ref()
+                                .map(|validator| validator.check(answer))
+                            {
+                                invalid = Some(format!("! {e}"));
+                                continue;
+                            }
+
+                            let id = item.id;
+                            let packet = protocol::Packet::client(client::AnswerTo {
+                                id,
+                                answer: answer.to_string(),
+                            });
+                            // the next question is generated once every question is answered
+                            if open.answer(id) {
+                                batch = None;
+                                waiting_for_question = true;
+                                health.start(Instant::now());
+                            }
+
+                            ui.new_line();
+                            self.tx.send(tracer.stamp(packet))?;
+                            continue;
+                        }
+                        // answers are checked before they are sent, the executor would reject them
+                        if self.instruction.is_some() {
+                            if let Some(Err(e)) = validator
+                                .as_ref()
+                                .map(|validator| validator.check(ui.current_line()))
+                            {
+                                invalid = Some(format!("! {e}"));
+                                continue;
+                            }
+                        }
+                        validator = None;
+
                         let packet = match self.instruction {
                             // instruction will only be None
                             // on the very first prompt of the user on the terminal
@@ -134,11 +598,15 @@ impl App {
                         };
 
                         waiting_for_question = true;
+                        health.start(Instant::now());
 
                         ui.new_line();
-                        self.tx.send(packet)?;
+                        self.tx.send(tracer.stamp(packet))?;
                     }
                     KeyCode::Char(c) => {
+                        invalid = None;
+                        palette = Palette::default();
+                        undo.record(Edit::Insert(c), ui.current_line());
                         ui.current_line().push(c);
                     }
                     _ => {}
@@ -146,31 +614,396 @@ impl App {
                 // answers sent from GPT to the frontend
                 // are handled here
                 Event::Packet(packet) => match packet.data {
-                    Server::Question {
-                        question,
-                        is_first_word,
-                        is_last_word,
-                    } => {
-                        if is_first_word || is_last_word {
-                            ui.new_line();
-                        }
-                        // is first word, meaning this is the
-                        // beggining of a new question
+                    Server::Question { frame } => {
+                        let delivered = match questions.frame(frame) {
+                            Ok(delivered) => delivered,
+                            Err(e) => {
+                                debug!("Ignored a frame: {e}");
+                                perf.dropped();
+                                continue;
+                            }
+                        };
+                        perf.chunk();
+                        let question = delivered.text;
+                        let is_first_word = delivered.started;
+                        let is_last_word = delivered.complete.is_some();
+
+                        health.delta(&question, Instant::now());
                         if is_first_word {
-                            ui.current_line().push_str(&format!("> {question}"));
+                            validator = None;
+                            secret = None;
                         }
-                        // is not first word, meaning the next words
-                        // are the contiunation of the previous question
-                        if !is_first_word {
-                            ui.current_line().push_str(question.as_str());
+                        // screen readers read the question once, when it is complete
+                        if self.accessible {
+                            if let Some(complete) = &delivered.complete {
+                                ui.new_line();
+                                ui.push_text(&format!("> {complete}"));
+                                ui.new_line();
+                            }
+                        } else {
+                            if is_first_word || is_last_word {
+                                ui.new_line();
+                            }
+                            // is first word, meaning this is the
+                            // beggining of a new question
+                            if is_first_word {
+                                ui.push_text(&format!("> {question}"));
+                            }
+                            // is not first word, meaning the next words
+                            // are the contiunation of the previous question
+                            if !is_first_word {
+                                ui.push_text(&question);
+                            }
                         }
                         if is_last_word {
                             waiting_for_question = false;
+                            health.finish();
+                            self.questions += 1;
+                        }
+                    }
+                    // the packet that triggered the question was reverted
+                    Server::Cancelled => {
+                        questions.abandon();
+                        waiting_for_question = false;
+                        validator = None;
+                        secret = None;
+                        health.finish();
+                        ui.current_line().push_str(" (cancelled)");
+                        ui.new_line();
+                        if self.questions == 0 {
+                            self.instruction = None;
+                        }
+                    }
+                    Server::Rejected { reason } => {
+                        waiting_for_question = false;
+                        health.finish();
+                        ui.current_line().push_str(&format!("! {reason}"));
+                        ui.new_line();
+                    }
+                    Server::ConfirmCommand { command, risk } => {
+                        ui.current_line().push_str(&format!("? {risk} command:"));
+                        ui.new_line();
+                        for line in command.lines() {
+                            ui.current_line().push_str(&format!("    {line}"));
+                            ui.new_line();
+                        }
+                        ui.current_line().push_str("  run it? [y/n] ");
+                        confirming = true;
+                    }
+                    Server::SourcePreview {
+                        id,
+                        title,
+                        summary,
+                        url,
+                    } => {
+                        ui.current_line()
+                            .push_str(&format!("read {title}, /veto {id} if it is irrelevant"));
+                        ui.new_line();
+                        sources.add(SourcePreview {
+                            id,
+                            title,
+                            summary,
+                            url,
+                        });
+                    }
+                    Server::FileConflict { path, conflicts } => {
+                        ui.current_line().push_str(&format!(
+                            "! {path} was changed while it was generated, {conflicts} conflicts \
+                             are marked in it"
+                        ));
+                        ui.new_line();
+                        ui.current_line().push_str(
+                            "  [k]eep your changes, [o]verwrite them or mark as [r]esolved? ",
+                        );
+                        resolving = true;
+                    }
+                    Server::WorkspaceInfo {
+                        languages,
+                        frameworks,
+                        build_tools,
+                    } => {
+                        if languages.is_empty() {
+                            continue;
+                        }
+                        let languages = languages
+                            .iter()
+                            .map(|stats| format!("{} ({})", stats.language, stats.files))
+                            .collect::<Vec<_>>();
+                        let line = [languages, build_tools, frameworks]
+                            .iter()
+                            .filter(|part| !part.is_empty())
+                            .map(|part| part.join(", "))
+                            .collect::<Vec<_>>()
+                            .join(" · ");
+                        ui.current_line().push_str(&format!("* workspace: {line}"));
+                        ui.new_line();
+                    }
+                    Server::Notice { message } => {
+                        ui.current_line().push_str(&format!("* {message}"));
+                        ui.new_line();
+                    }
+                    Server::ShuttingDown { grace_secs } => {
+                        ui.current_line().push_str(&format!(
+                            "! the executor is shutting down in {grace_secs}s, the session is \
+                             saved"
+                        ));
+                        ui.new_line();
+                    }
+                    Server::FileChunk { path, seq, content } => {
+                        let received = files.chunk(&path, seq, &content);
+                        let lines = received.lines().count();
+                        file_status = Some(if self.accessible {
+                            format!("writing {path}")
+                        } else {
+                            format!("writing {path} · {lines} lines")
+                        });
+                    }
+                    Server::FileWritten { path, checksum } => {
+                        file_status = None;
+                        let line = match files.written(&path, &checksum) {
+                            Ok(file) => {
+                                format!(
+                                    "wrote {} ({} lines)",
+                                    file.path,
+                                    file.content.lines().count()
+                                )
+                            }
+                            Err(e) => format!("! {path}: {e}"),
+                        };
+                        ui.current_line().push_str(&line);
+                        ui.new_line();
+                    }
+                    Server::FileFailed { path, reason } => {
+                        file_status = None;
+                        files.failed(&path);
+                        ui.current_line()
+                            .push_str(&format!("! failed to write {path}: {reason}"));
+                        ui.new_line();
+                    }
+                    Server::StepStarted { index, title } => {
+                        ui.pane_line(format!("[{}] {title}", index + 1));
+                        steps.started(index, title);
+                    }
+                    Server::StepOutput { output, .. } => {
+                        for line in output.lines() {
+                            ui.output(line);
+                        }
+                    }
+                    Server::StepDiagnosed {
+                        kind,
+                        explanation,
+                        remediation,
+                        ..
+                    } => {
+                        ui.current_line()
+                            .push_str(&format!("  cause ({kind}): {explanation}"));
+                        ui.new_line();
+                        ui.current_line().push_str(&format!("  fix: {remediation}"));
+                        ui.new_line();
+                    }
+                    Server::StepFinished { index, success } => {
+                        steps.finished(index, success);
+                        if !success {
+                            ui.current_line()
+                                .push_str(&format!("! step {} failed", index + 1));
+                            ui.new_line();
+                        }
+                    }
+                    Server::ExecutionFinished { success } => {
+                        let line = if success {
+                            "done"
+                        } else {
+                            "! execution stopped, /execute to retry"
+                        };
+                        ui.current_line().push_str(line);
+                        ui.new_line();
+                    }
+                    Server::Memory { enabled, entries } => {
+                        let line = match (enabled, entries.is_empty()) {
+                            (false, _) => "memory is disabled, start with --memory <FILE>",
+                            (true, true) => "nothing remembered",
+                            (true, false) => "remembered preferences (/forget <id> to remove):",
+                        };
+                        ui.current_line().push_str(line);
+                        ui.new_line();
+
+                        for entry in entries {
+                            ui.current_line()
+                                .push_str(&format!("  {}: {}", entry.id, entry.preference));
+                            ui.new_line();
+                        }
+                    }
+                    Server::Latency { latencies } => {
+                        let measured = [
+                            tracer.one_way.stats("executor → client"),
+                            tracer.round_trip.stats("client ↔ executor round trip"),
+                            render.stats("render"),
+                        ];
+                        ui.current_line()
+                            .push_str("latency: p50 / p90 / p99 / max (samples)");
+                        ui.new_line();
+
+                        for stats in latencies.iter().chain(measured.iter().flatten()) {
+                            ui.current_line().push_str(&format!("  {}", latency(stats)));
+                            ui.new_line();
+                        }
+                    }
+                    Server::AnswerFormat { validator: format } => validator = Some(format),
+                    Server::SecretAnswer => secret = Some(String::new()),
+                    Server::DocsFetched {
+                        name,
+                        fetched,
+                        total,
+                        success,
+                    } => {
+                        let outcome = if success {
+                            "fetched"
+                        } else {
+                            "failed to fetch"
+                        };
+                        ui.current_line()
+                            .push_str(&format!("{outcome} the docs of {name} ({fetched}/{total})"));
+                        ui.new_line();
+                    }
+                    Server::Reverted {
+                        instruction,
+                        summary,
+                    } => {
+                        ui.current_line().push_str(&format!("reverted: {summary}"));
+                        ui.new_line();
+                        // the terminal only ever sends one instruction, the next line is a new one
+                        if instruction {
+                            self.instruction = None;
+                            self.questions = 0;
+                            validator = None;
+                            secret = None;
+                        }
+                    }
+                    Server::FollowUp => {
+                        ui.current_line().push_str(
+                            "? the last answer was unclear or contradicts an earlier one",
+                        );
+                        ui.new_line();
+                    }
+                    Server::QuestionAlternatives { alternatives } => {
+                        for alternative in alternatives {
+                            ui.current_line()
+                                .push_str(&format!("  also considered: {alternative}"));
+                            ui.new_line();
+                        }
+                    }
+                    Server::Questions { items } => {
+                        waiting_for_question = false;
+                        health.finish();
+                        let open = Batch::new(items);
+                        for line in open.lines() {
+                            ui.new_line();
+                            ui.push_text(&line);
+                        }
+                        ui.new_line();
+                        self.questions += open.len();
+                        batch = Some(open);
+                    }
+                    Server::PlanChunk { content } => {
+                        plan.push_str(&content);
+                        steps.chunk(&content);
+                    }
+                    Server::Status { phase: next } => {
+                        if next == Phase::Planning {
+                            plan.clear();
+                            steps.clear();
+                            sources.clear();
+                        }
+                        phase = Some(next);
+                    }
+                    Server::Error {
+                        message,
+                        recoverable,
+                    } => {
+                        health.finish();
+                        let line = if !recoverable {
+                            format!("! {message}, the session was closed")
+                        } else if waiting_for_question {
+                            format!("! {message}, send it again to retry")
+                        } else {
+                            format!("! {message}")
+                        };
+                        // the executor went back to the previous question, as after a cancel
+                        if waiting_for_question && self.questions == 0 {
+                            self.instruction = None;
+                        }
+                        waiting_for_question = false;
+                        ui.current_line().push_str(&line);
+                        ui.new_line();
+                    }
+                    // stream ids start over with every connection
+                    Server::SessionCreated { id, .. } => {
+                        session = Some(id);
+                        questions = Streams::default();
+                    }
+                    Server::Sessions { sessions } => {
+                        ui.current_line().push_str("sessions:");
+                        ui.new_line();
+                        for summary in sessions {
+                            let state = if Some(summary.id) == session {
+                                "this session"
+                            } else if summary.attached {
+                                "attached"
+                            } else {
+                                "detached, can be resumed"
+                            };
+                            ui.current_line()
+                                .push_str(&format!("  {} ({state})", summary.id));
+                            ui.new_line();
                         }
                     }
+                    Server::Cloned { url, branch, head } => {
+                        let short = head.get(..7).unwrap_or(&head);
+                        ui.current_line()
+                            .push_str(&format!("cloned {url} at {branch} ({short})"));
+                        ui.new_line();
+                    }
+                    Server::CompileChecked {
+                        path,
+                        iteration,
+                        iterations,
+                        errors,
+                        ..
+                    } => {
+                        let outcome = match errors {
+                            0 => "compiles".to_string(),
+                            _ if iteration < iterations => {
+                                format!("has {errors} errors, generating it again")
+                            }
+                            _ => format!("still has {errors} errors"),
+                        };
+                        ui.current_line()
+                            .push_str(&format!("{path} {outcome} ({iteration}/{iterations})"));
+                        ui.new_line();
+                    }
+                    Server::WorkspaceReady { path } => {
+                        ui.current_line().push_str(&format!("working in {path}"));
+                        ui.new_line();
+                    }
+                    Server::Usage { usage } => {
+                        for stats in usage {
+                            ui.current_line().push_str(&format!("usage: {stats}"));
+                            ui.new_line();
+                        }
+                    }
+                    // keepalive packets are handled by `comms`
+                    Server::Pong => {}
                 },
-                Event::Terminal(_) => {}
+                Event::Terminal(_) | Event::Tick => {}
             }
         }
     }
 }
+
+/// One line of the `/latency` report
+fn latency(stats: &LatencyStats) -> String {
+    format!(
+        "{}: {}ms / {}ms / {}ms / {}ms ({})",
+        stats.name, stats.p50_ms, stats.p90_ms, stats.p99_ms, stats.max_ms, stats.count
+    )
+}
@@ -0,0 +1,109 @@
+use protocol::QuestionItem;
+
+/// Questions the executor asked at once, answered in any order.
+///
+/// A line like `2: 8080` answers the second question, any other line the first open one.
+/// Answered questions can be answered again until all are answered.
+pub struct Batch {
+    items: Vec<QuestionItem>,
+    answered: Vec<bool>,
+}
+
+impl Batch {
+    pub fn new(items: Vec<QuestionItem>) -> Self {
+        let answered = vec![false; items.len()];
+        Self { items, answered }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The questions as they are shown, numbered from 1
+    pub fn lines(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| format!("> {}: {}", index + 1, item.question))
+            .collect()
+    }
+
+    /// The question `line` answers and the answer, `None` if it names no question.
+    pub fn target<'a>(&self, line: &'a str) -> Option<(&QuestionItem, &'a str)> {
+        let numbered = line.split_once(':').and_then(|(number, answer)| {
+            let index = number.trim().parse::<usize>().ok()?.checked_sub(1)?;
+            Some((self.items.get(index)?, answer.trim()))
+        });
+
+        numbered.or_else(|| {
+            let open = self.answered.iter().position(|answered| !answered)?;
+            Some((&self.items[open], line.trim()))
+        })
+    }
+
+    /// The question `id` was answered. Returns whether every question is answered.
+    pub fn answer(&mut self, id: u64) -> bool {
+        if let Some(index) = self.items.iter().position(|item| item.id == id) {
+            self.answered[index] = true;
+        }
+        self.answered.iter().all(|answered| *answered)
+    }
+
+    /// e.g. `2 of 5 answered`
+    pub fn progress(&self) -> String {
+        let answered = self.answered.iter().filter(|answered| **answered).count();
+        format!("{answered} of {} answered", self.items.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::QuestionItem;
+
+    use super::Batch;
+
+    fn batch() -> Batch {
+        let item = |id, question: &str| QuestionItem {
+            id,
+            question: question.to_string(),
+            validator: None,
+        };
+        Batch::new(vec![
+            item(4, "Which language?"),
+            item(5, "Which port?"),
+            item(6, "Static files?"),
+        ])
+    }
+
+    #[test]
+    fn test_target() {
+        let batch = batch();
+
+        let (item, answer) = batch.target("2: 8080").unwrap();
+        assert_eq!((item.id, answer), (5, "8080"));
+
+        let (item, answer) = batch.target(" Rust ").unwrap();
+        assert_eq!((item.id, answer), (4, "Rust"));
+
+        // not a question number, the whole line is the answer
+        let (item, answer) = batch.target("7: no").unwrap();
+        assert_eq!((item.id, answer), (4, "7: no"));
+        let (_, answer) = batch.target("note: use axum").unwrap();
+        assert_eq!(answer, "note: use axum");
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut batch = batch();
+        assert_eq!(batch.lines()[1], "> 2: Which port?");
+
+        assert!(!batch.answer(6));
+        assert!(!batch.answer(6));
+        assert_eq!(batch.progress(), "1 of 3 answered");
+
+        let (item, _) = batch.target("Rust").unwrap();
+        assert_eq!(item.id, 4);
+        assert!(!batch.answer(4));
+        assert!(batch.answer(5));
+    }
+}
@@ -1,17 +1,243 @@
-use tracing_appender::{
-    non_blocking::WorkerGuard,
-    rolling::{RollingFileAppender, Rotation},
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
 };
 
-/// Setup tracing to write to a file.
-pub fn setup_tracing() -> WorkerGuard {
-    let rotation = Rotation::DAILY;
-    let file_appender = RollingFileAppender::new(rotation, "logs", "trace.log");
+use anyhow::Context;
+use tracing::{error, Level};
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
 
-    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+use crate::config::Config;
+
+/// The name of the log that is written to, rotated logs get a number appended, e.g. `trace.log.1`
+const FILE: &str = "trace.log";
+
+/// Size in bytes after which the log is rotated
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated logs that are kept, older ones are deleted
+const MAX_FILES: u32 = 5;
+
+/// The log of this run. Buffered lines are written when it is dropped, so it has to live until
+/// the end of `main`.
+pub struct Logs {
+    path: PathBuf,
+    _guard: WorkerGuard,
+}
+
+impl Logs {
+    /// The log that is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Read the config at `path`, or at [`Config::default_path`] without one. Only a missing default
+/// config is no error, it is the empty config.
+pub fn load_config(path: Option<&Path>) -> anyhow::Result<Config> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match Config::default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            Config::parse(&content).with_context(|| format!("Failed to read {}", path.display()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Setup tracing to write events up to `level` to a log in `dir`, which is rotated by size.
+///
+/// Panics are logged too, as the terminal is in raw mode and would swallow them.
+pub fn setup_tracing(dir: &Path, level: Level) -> anyhow::Result<Logs> {
+    let file = RollingFile::open(dir, MAX_SIZE, MAX_FILES)
+        .with_context(|| format!("Failed to open the log in {}", dir.display()))?;
+    let path = file.path();
+
+    let (non_blocking, guard) = NonBlockingBuilder::default().lossy(false).finish(file);
     tracing_subscriber::fmt::Subscriber::builder()
         .with_writer(non_blocking)
+        .with_max_level(level)
+        .with_ansi(false)
         .init();
 
-    guard
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        error!("{info}");
+        default_hook(info);
+    }));
+
+    Ok(Logs {
+        path,
+        _guard: guard,
+    })
+}
+
+/// A log file that is moved to `trace.log.1` once it grows past `max_size`, shifting the
+/// rotated logs before it up by one.
+///
+/// Lines are written to the file directly, so a crash only loses lines that were not handed to
+/// the writer yet.
+struct RollingFile {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RollingFile {
+    fn open(dir: &Path, max_size: u64, max_files: u32) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        clean(dir, max_files)?;
+
+        let path = dir.join(FILE);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(FILE)
+    }
+
+    fn rotated(&self, number: u32) -> PathBuf {
+        self.dir.join(format!("{FILE}.{number}"))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        for number in (1..=self.max_files).rev() {
+            let from = if number == 1 {
+                self.path()
+            } else {
+                self.rotated(number - 1)
+            };
+            match std::fs::rename(from, self.rotated(number)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.max_files == 0 {
+            std::fs::remove_file(self.path())?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Delete the logs in `dir` that are not kept, e.g. rotated logs beyond `max_files` or the daily
+/// logs of older versions.
+fn clean(dir: &Path, max_files: u32) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(suffix) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(FILE))
+            .and_then(|rest| rest.strip_prefix('.'))
+        else {
+            continue;
+        };
+
+        let kept = suffix
+            .parse::<u32>()
+            .is_ok_and(|number| (1..=max_files).contains(&number));
+        if !kept {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{RollingFile, FILE};
+
+    fn read(dir: &std::path::Path, name: &str) -> Option<String> {
+        std::fs::read_to_string(dir.join(name)).ok()
+    }
+
+    #[test]
+    fn test_rotate() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut file = RollingFile::open(dir.path(), 8, 2)?;
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+
+        assert_eq!(read(dir.path(), FILE).as_deref(), Some("fourth\n"));
+        assert_eq!(read(dir.path(), "trace.log.1").as_deref(), Some("third\n"));
+        assert_eq!(read(dir.path(), "trace.log.2").as_deref(), Some("second\n"));
+        assert_eq!(read(dir.path(), "trace.log.3"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in [
+            "trace.log.2023-05-01",
+            "trace.log.1",
+            "trace.log.4",
+            "other.log",
+        ] {
+            std::fs::write(dir.path().join(name), "old\n")?;
+        }
+        std::fs::write(dir.path().join(FILE), "before a crash\n")?;
+
+        let mut file = RollingFile::open(dir.path(), 1024, 3)?;
+        file.write_all(b"after\n")?;
+
+        assert_eq!(
+            read(dir.path(), FILE).as_deref(),
+            Some("before a crash\nafter\n")
+        );
+        assert!(read(dir.path(), "trace.log.1").is_some());
+        assert!(read(dir.path(), "trace.log.4").is_none());
+        assert!(read(dir.path(), "trace.log.2023-05-01").is_none());
+        assert!(read(dir.path(), "other.log").is_some());
+
+        Ok(())
+    }
 }
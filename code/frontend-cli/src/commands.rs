@@ -0,0 +1,328 @@
+//! Slash commands typed in the input box, and the palette completing them while they are typed.
+
+use protocol::client;
+
+/// A command the palette offers
+pub struct Command {
+    pub name: &'static str,
+    /// what follows the name, e.g. `<id>`, empty if nothing does
+    pub args: &'static str,
+    pub about: &'static str,
+}
+
+impl Command {
+    const fn new(name: &'static str, args: &'static str, about: &'static str) -> Self {
+        Self { name, args, about }
+    }
+}
+
+pub const COMMANDS: [Command; 16] = [
+    Command::new("/plan", "", "show the steps of the plan and their progress"),
+    Command::new(
+        "/execute",
+        "",
+        "write a plan for the instruction and run it",
+    ),
+    Command::new(
+        "/run",
+        "<steps>",
+        "run only some steps of the last plan, e.g. 2-4 or 1,3",
+    ),
+    Command::new(
+        "/cancel",
+        "",
+        "stop the question or execution that is running",
+    ),
+    Command::new("/retry", "", "ask the last question again"),
+    Command::new("/session list", "", "list the sessions of the executor"),
+    Command::new(
+        "/clone",
+        "<url> [branch]",
+        "work on a clone of a repository, before the instruction",
+    ),
+    Command::new(
+        "/workspace",
+        "<path>",
+        "generate the code in a directory of the executor, before the instruction",
+    ),
+    Command::new("/memory", "", "list the remembered preferences"),
+    Command::new(
+        "/remember",
+        "<preference>",
+        "remember a preference in every session",
+    ),
+    Command::new("/forget", "<id>", "forget a remembered preference"),
+    Command::new(
+        "/veto",
+        "<id>",
+        "keep a source the plan read out of the next steps",
+    ),
+    Command::new(
+        "/revert",
+        "",
+        "undo the instruction or the changes of the last execution",
+    ),
+    Command::new("/latency", "", "show the latencies of the session"),
+    Command::new("/logs", "", "show where the log is written"),
+    Command::new("/quit", "", "close the frontend"),
+];
+
+/// What a slash command does
+pub enum Action {
+    /// send a packet to the executor
+    Send(protocol::ClientPacket),
+    /// show the steps of the plan in the conversation
+    Plan,
+    /// show where the log is written
+    Logs,
+    Quit,
+}
+
+/// Parse a slash command, `None` if `line` is not one or its arguments are invalid.
+pub fn parse(line: &str) -> Option<Action> {
+    let (command, arg) = match line.trim().split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line.trim(), ""),
+    };
+
+    let packet = match command {
+        "/plan" => return Some(Action::Plan),
+        "/logs" => return Some(Action::Logs),
+        "/quit" => return Some(Action::Quit),
+        "/execute" => protocol::Packet::client(client::Execute),
+        "/cancel" => protocol::Packet::client(client::Cancel),
+        "/retry" => protocol::Packet::client(client::Regenerate),
+        "/revert" => protocol::Packet::client(client::RevertLast),
+        "/session" if arg == "list" => protocol::Packet::client(client::ListSessions),
+        "/memory" => protocol::Packet::client(client::ListMemory),
+        "/latency" => protocol::Packet::client(client::Latency),
+        "/remember" if !arg.is_empty() => protocol::Packet::client(client::Remember {
+            preference: arg.to_string(),
+        }),
+        "/forget" => protocol::Packet::client(client::Forget {
+            id: arg.parse().ok()?,
+        }),
+        "/veto" => protocol::Packet::client(client::VetoSource {
+            id: arg.parse().ok()?,
+        }),
+        "/run" => protocol::Packet::client(client::ExecuteSteps { steps: steps(arg)? }),
+        "/clone" if !arg.is_empty() => {
+            let mut words = arg.split_whitespace();
+            let url = words.next()?.to_string();
+            let branch = words.next().map(str::to_string);
+            if words.next().is_some() {
+                return None;
+            }
+            protocol::Packet::client(client::CloneRepo { url, branch })
+        }
+        "/workspace" if !arg.is_empty() => protocol::Packet::client(client::SetWorkspace {
+            path: arg.to_string(),
+        }),
+        _ => return None,
+    };
+
+    Some(Action::Send(packet))
+}
+
+/// The steps of the plan in `arg`, numbered from 1 like in the plan pane, e.g. `2-4` or `1, 3`.
+/// Numbered from 0 like the executor does.
+fn steps(arg: &str) -> Option<Vec<usize>> {
+    let mut steps = Vec::new();
+    for part in arg.split([',', ' ']).filter(|part| !part.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.parse::<usize>().ok()?, last.parse().ok()?),
+            None => {
+                let step = part.parse().ok()?;
+                (step, step)
+            }
+        };
+        if first == 0 || first > last {
+            return None;
+        }
+        steps.extend(first - 1..last);
+    }
+    (!steps.is_empty()).then_some(steps)
+}
+
+/// The commands `line` is the start of, or the command whose arguments are being typed. Empty
+/// if `line` is not a command.
+pub fn candidates(line: &str) -> Vec<&'static Command> {
+    if !line.starts_with('/') {
+        return Vec::new();
+    }
+
+    COMMANDS
+        .iter()
+        .filter(|command| {
+            command.name.starts_with(line)
+                || line
+                    .strip_prefix(command.name)
+                    .is_some_and(|rest| rest.starts_with(' ') && !command.args.is_empty())
+        })
+        .collect()
+}
+
+/// The popup listing the [`candidates`] of the line being typed, Tab completes the selected one
+#[derive(Default)]
+pub struct Palette {
+    selected: usize,
+}
+
+impl Palette {
+    /// The lines of the popup, the selected command marked with `>`. Empty without candidates.
+    pub fn render(&self, candidates: &[&Command]) -> Vec<String> {
+        let width = candidates
+            .iter()
+            .map(|command| usage(command).len())
+            .max()
+            .unwrap_or_default();
+
+        candidates
+            .iter()
+            .enumerate()
+            .map(|(i, command)| {
+                let marker = if i == self.selected(candidates.len()) {
+                    '>'
+                } else {
+                    ' '
+                };
+                format!("{marker} {:width$}  {}", usage(command), command.about)
+            })
+            .collect()
+    }
+
+    /// The line once the selected command is completed, followed by a space if it takes
+    /// arguments
+    pub fn complete(&self, candidates: &[&Command]) -> Option<String> {
+        let command = candidates.get(self.selected(candidates.len()))?;
+        Some(if command.args.is_empty() {
+            command.name.to_string()
+        } else {
+            format!("{} ", command.name)
+        })
+    }
+
+    /// Select the next command, wrapping around
+    pub fn next(&mut self, candidates: usize) {
+        self.selected = (self.selected(candidates) + 1) % candidates.max(1);
+    }
+
+    /// Select the previous command, wrapping around
+    pub fn previous(&mut self, candidates: usize) {
+        self.selected = self
+            .selected(candidates)
+            .checked_sub(1)
+            .unwrap_or(candidates.max(1) - 1);
+    }
+
+    /// The selected command, the candidates shrink while the line is typed
+    fn selected(&self, candidates: usize) -> usize {
+        self.selected.min(candidates.saturating_sub(1))
+    }
+}
+
+/// e.g. `/forget <id>`
+fn usage(command: &Command) -> String {
+    if command.args.is_empty() {
+        command.name.to_string()
+    } else {
+        format!("{} {}", command.name, command.args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::client::Client;
+
+    use super::{candidates, parse, Action, Palette};
+
+    #[test]
+    fn test_parse() {
+        let packet = |line| match parse(line) {
+            Some(Action::Send(packet)) => Some(packet.data),
+            _ => None,
+        };
+
+        assert!(matches!(packet("/execute"), Some(Client::Execute)));
+        assert!(matches!(packet("/cancel"), Some(Client::Cancel)));
+        assert!(matches!(packet("/retry"), Some(Client::Regenerate)));
+        assert!(matches!(packet("/revert"), Some(Client::RevertLast)));
+        assert!(matches!(
+            packet("/session list"),
+            Some(Client::ListSessions)
+        ));
+        assert!(matches!(packet("/memory"), Some(Client::ListMemory)));
+        assert!(matches!(packet("/latency"), Some(Client::Latency)));
+        assert!(matches!(
+            packet("/remember always uses tokio"),
+            Some(Client::Remember { preference }) if preference == "always uses tokio"
+        ));
+        assert!(matches!(
+            packet("/forget 3"),
+            Some(Client::Forget { id: 3 })
+        ));
+        assert!(matches!(
+            packet("/veto 2"),
+            Some(Client::VetoSource { id: 2 })
+        ));
+        assert!(matches!(
+            packet("/run 2-4, 6"),
+            Some(Client::ExecuteSteps { steps }) if steps == [1, 2, 3, 5]
+        ));
+        assert!(matches!(
+            packet("/clone https://github.com/owner/repo.git dev"),
+            Some(Client::CloneRepo { url, branch: Some(branch) })
+                if url == "https://github.com/owner/repo.git" && branch == "dev"
+        ));
+        assert!(matches!(
+            packet("/workspace /home/user/my project"),
+            Some(Client::SetWorkspace { path }) if path == "/home/user/my project"
+        ));
+        assert!(matches!(parse("/plan"), Some(Action::Plan)));
+        assert!(matches!(parse(" /quit "), Some(Action::Quit)));
+
+        assert!(packet("/session").is_none());
+        assert!(packet("/forget three").is_none());
+        assert!(packet("/remember").is_none());
+        assert!(packet("/run").is_none());
+        assert!(packet("/run 0").is_none());
+        assert!(packet("/run 4-2").is_none());
+        assert!(packet("/clone").is_none());
+        assert!(packet("/clone url branch extra").is_none());
+        assert!(packet("/workspace").is_none());
+        assert!(packet("Create a calculator").is_none());
+    }
+
+    #[test]
+    fn test_palette() {
+        let names = |line| {
+            candidates(line)
+                .iter()
+                .map(|command| command.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("/re"), ["/retry", "/remember", "/revert"]);
+        assert_eq!(names("/remember tokio"), ["/remember"]);
+        assert!(names("/retry now").is_empty());
+        assert!(names("retry").is_empty());
+        assert_eq!(names("/").len(), 16);
+
+        let mut palette = Palette::default();
+        let open = candidates("/re");
+        assert_eq!(palette.render(&open), [
+            "> /retry                  ask the last question again",
+            "  /remember <preference>  remember a preference in every session",
+            "  /revert                 undo the instruction or the changes of the last execution",
+        ]);
+        palette.next(open.len());
+        assert_eq!(palette.complete(&open).as_deref(), Some("/remember "));
+
+        // the selection follows the candidates as they shrink
+        assert_eq!(
+            palette.complete(&candidates("/ret")).as_deref(),
+            Some("/retry")
+        );
+        palette.previous(1);
+        assert_eq!(palette.complete(&[]), None);
+    }
+}
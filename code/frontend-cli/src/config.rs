@@ -0,0 +1,117 @@
+//! `config.toml`, read at startup from `$XDG_CONFIG_HOME/collective` or `~/.config/collective`.
+//! Flags override the values of the file.
+//!
+//! ```toml
+//! ip = "10.0.0.2"
+//! port = 8080
+//! remote = true
+//! theme = "Solarized (dark)"
+//! log_level = "debug"
+//!
+//! [keys]
+//! toggle_output = "ctrl+t"
+//! redo = ["ctrl+y", "ctrl+shift+z"]
+//! ```
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::keys::Keymap;
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    /// connect to the executor at `ip` and `port` instead of launching one
+    pub remote: bool,
+    /// the theme of code blocks, one of the themes syntect ships with
+    pub theme: Option<String>,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: Option<String>,
+    /// the keys of shortcuts, replacing their default keys
+    pub keys: BTreeMap<String, Keys>,
+}
+
+/// One key or several keys bound to a shortcut
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Config {
+    /// The file that is read without `--config`, `None` if neither `XDG_CONFIG_HOME` nor `HOME`
+    /// is set
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(dir.join("collective").join("config.toml"))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        toml::from_str(content).context("The config is not valid")
+    }
+
+    /// The default keys with the keys of the file
+    pub fn keymap(&self) -> anyhow::Result<Keymap> {
+        let mut keymap = Keymap::default();
+        for (name, keys) in &self.keys {
+            let keys = match keys {
+                Keys::One(key) => std::slice::from_ref(key),
+                Keys::Many(keys) => keys.as_slice(),
+            };
+            keymap
+                .bind(name, keys)
+                .with_context(|| format!("Invalid keys for `{name}`"))?;
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Config, Keys};
+    use crate::pane::View;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let config = Config::parse(
+            r#"
+            port = 9000
+            log_level = "debug"
+
+            [keys]
+            toggle_output = "ctrl+t"
+            redo = ["ctrl+y", "ctrl+shift+z"]
+            "#,
+        )?;
+        assert_eq!(config.port, Some(9000));
+        assert_eq!(config.ip, None);
+        assert!(!config.remote);
+        assert_eq!(
+            config.keys.get("redo"),
+            Some(&Keys::Many(vec![
+                "ctrl+y".to_string(),
+                "ctrl+shift+z".to_string()
+            ]))
+        );
+
+        let keymap = config.keymap()?;
+        let toggle = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.view(&toggle), Some(View::Toggle));
+
+        assert_eq!(Config::parse("")?, Config::default());
+        assert!(Config::parse("prot = 9000").is_err());
+        assert!(Config::parse("[keys]\nquit = \"ctrl+\"")?.keymap().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! `--headless`: the session on plain stdin, stdout and stderr, for shell scripts and CI.
+//!
+//! Questions and progress are written to stderr, answers are read line by line from stdin or
+//! from `--answers`, and stdout only gets the plan and the files that were written once the plan
+//! was executed. An empty line or the end of the answers ends the interview and executes the
+//! plan. A file that was changed while it was generated keeps the changes on disk unless the
+//! answer to its conflict starts with `o` (overwrite) or `r` (resolved).
+
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+use collective_client::{files::ReceivedFile, Client, Event};
+use protocol::{client, server::Server, Phase, Resolution};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+
+use crate::{comms, Args, CANCEL_TOKEN};
+
+/// Run the session of `args.instruction`, or of the first line of stdin without one, in the
+/// directory `args.workspace` and on a clone of `args.repo` if they are set. Returns whether the
+/// plan was executed successfully.
+///
+/// # Errors
+/// If there is no instruction, the answers cannot be read or the session failed before the plan
+/// was executed.
+pub async fn run(args: &Args) -> anyhow::Result<bool> {
+    let channels = comms::setup_comms(args).await?;
+    let client = Client::from_channels(channels.0, channels.1);
+
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let instruction = match &args.instruction {
+        Some(instruction) => instruction.clone(),
+        None => stdin
+            .next_line()
+            .await?
+            .filter(|line| !line.trim().is_empty())
+            .context("No instruction, pass it as an argument or on the first line of stdin")?,
+    };
+
+    let answers: Box<dyn AsyncBufRead + Unpin + Send> = match args.answers.as_deref() {
+        Some(path) => Box::new(BufReader::new(open(path).await?)),
+        None => Box::new(stdin.into_inner()),
+    };
+
+    if let Some(path) = &args.workspace {
+        client.send(client::SetWorkspace { path: path.clone() })?;
+    }
+    if let Some(url) = &args.repo {
+        client.send(client::CloneRepo {
+            url: url.clone(),
+            branch: args.branch.clone(),
+        })?;
+    }
+
+    tokio::select! {
+        executed = session(client, instruction, answers.lines(), std::io::stdout()) => executed,
+        () = CANCEL_TOKEN.cancelled() => bail!("Interrupted"),
+    }
+}
+
+async fn open(path: &Path) -> anyhow::Result<tokio::fs::File> {
+    tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Could not open the answers at {}", path.display()))
+}
+
+/// The next answer, `None` at an empty line or the end of the answers
+async fn answer<R: AsyncBufRead + Unpin>(answers: &mut Lines<R>) -> anyhow::Result<Option<String>> {
+    let line = answers
+        .next_line()
+        .await
+        .context("Could not read the answer")?;
+    Ok(line.filter(|line| !line.trim().is_empty()))
+}
+
+/// Send `instruction`, answer the questions from `answers` and write the plan and the files to
+/// `out` once the plan was executed.
+async fn session<R: AsyncBufRead + Unpin>(
+    mut client: Client,
+    instruction: String,
+    mut answers: Lines<R>,
+    mut out: impl Write,
+) -> anyhow::Result<bool> {
+    client.instruct(instruction)?;
+
+    let mut plan = String::new();
+    let mut files: Vec<ReceivedFile> = Vec::new();
+    // answering stops once the answers ran out
+    let mut executing = false;
+
+    while let Some(event) = client.next_event().await {
+        match event {
+            Event::Question(question) => {
+                eprintln!("{question}");
+                match answer(&mut answers).await? {
+                    Some(answer) => client.answer(answer)?,
+                    None => {
+                        executing = true;
+                        client.send(client::Execute)?;
+                    }
+                }
+            }
+            Event::Packet(Server::Questions { items }) => {
+                for item in items {
+                    eprintln!("{}", item.question);
+                    let Some(answer) = answer(&mut answers).await? else {
+                        executing = true;
+                        client.send(client::Execute)?;
+                        break;
+                    };
+                    client.answer_to(item.id, answer)?;
+                }
+            }
+            Event::Packet(Server::ConfirmCommand { command, risk }) => {
+                eprintln!("run `{command}` ({risk})? [y/n]");
+                let approved = answer(&mut answers)
+                    .await?
+                    .is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y"));
+                client.send(client::Confirm { approved })?;
+            }
+            Event::Packet(Server::FileConflict { path, conflicts }) => {
+                eprintln!(
+                    "! {path} was changed while it was generated, {conflicts} conflicts are \
+                     marked in it. [k]eep, [o]verwrite or [r]esolved?"
+                );
+                let answer = answer(&mut answers).await?.unwrap_or_default();
+                let resolution = match answer.trim().chars().next() {
+                    Some('o' | 'O') => Resolution::Overwrite,
+                    Some('r' | 'R') => Resolution::Resolved,
+                    _ => Resolution::Keep,
+                };
+                client.send(client::ResolveConflict { resolution })?;
+            }
+            Event::File(file) => {
+                files.retain(|written| written.path != file.path);
+                files.push(file);
+            }
+            Event::FileError { path, error } => {
+                bail!("{path} was not received completely: {error}")
+            }
+            Event::Packet(Server::PlanChunk { content }) => plan.push_str(&content),
+            // the plan is written again if it was invalid
+            Event::Packet(Server::Status {
+                phase: Phase::Planning,
+            }) => plan.clear(),
+            Event::Packet(Server::ExecutionFinished { success }) => {
+                write!(out, "{}", output(&plan, &files))?;
+                out.flush()?;
+                return Ok(success);
+            }
+            Event::Packet(Server::Rejected { reason }) => bail!("The executor rejected: {reason}"),
+            Event::Packet(Server::Error { message, .. }) => bail!("{message}"),
+            Event::Packet(Server::Cancelled) => bail!("The question was cancelled"),
+            Event::Packet(Server::ShuttingDown { .. }) if !executing => {
+                bail!("The executor is shutting down")
+            }
+            Event::QuestionDelta(_) | Event::StreamError(_) => {}
+            Event::Packet(packet) => show(packet),
+        }
+    }
+
+    bail!("The executor closed the connection")
+}
+
+/// Write the progress `packet` reports to stderr, if it reports any.
+fn show(packet: Server) {
+    match packet {
+        Server::StepStarted { index, title } => eprintln!("step {}: {title}", index + 1),
+        Server::StepOutput { output, .. } => eprintln!("{output}"),
+        Server::StepDiagnosed { explanation, .. } => eprintln!("! {explanation}"),
+        Server::StepFinished {
+            index,
+            success: false,
+        } => eprintln!("! step {} failed", index + 1),
+        Server::FileFailed { path, reason } => eprintln!("! could not write {path}: {reason}"),
+        Server::Notice { message } => eprintln!("{message}"),
+        Server::SourcePreview { title, url, .. } => eprintln!("read {title} ({url})"),
+        Server::Cloned { url, branch, .. } => eprintln!("cloned {url} at {branch}"),
+        Server::WorkspaceReady { path } => eprintln!("working in {path}"),
+        Server::CompileChecked {
+            path,
+            iteration,
+            iterations,
+            errors,
+            ..
+        } if errors > 0 => eprintln!("{path} has {errors} errors ({iteration}/{iterations})"),
+        Server::Usage { usage } => {
+            for stats in usage {
+                eprintln!("{stats}");
+            }
+        }
+        // handled by `session`
+        Server::Questions { .. }
+        | Server::ConfirmCommand { .. }
+        | Server::FileConflict { .. }
+        | Server::PlanChunk { .. }
+        | Server::ExecutionFinished { .. }
+        | Server::Rejected { .. }
+        | Server::Error { .. }
+        | Server::Cancelled => {}
+        Server::SessionCreated { .. }
+        | Server::Question { .. }
+        | Server::FileChunk { .. }
+        | Server::FileWritten { .. }
+        | Server::Pong
+        | Server::StepFinished { .. }
+        | Server::Memory { .. }
+        | Server::WorkspaceInfo { .. }
+        | Server::ShuttingDown { .. }
+        | Server::Latency { .. }
+        | Server::AnswerFormat { .. }
+        | Server::SecretAnswer
+        | Server::QuestionAlternatives { .. }
+        | Server::FollowUp
+        | Server::DocsFetched { .. }
+        | Server::Reverted { .. }
+        | Server::CompileChecked { .. }
+        | Server::Status { .. }
+        | Server::Sessions { .. } => {}
+    }
+}
+
+/// The plan followed by every file under a `==> path <==` header
+fn output(plan: &str, files: &[ReceivedFile]) -> String {
+    let mut output = String::new();
+    if !plan.trim().is_empty() {
+        output.push_str(plan.trim_end());
+        output.push('\n');
+    }
+    for file in files {
+        output.push_str(&format!("==> {} <==\n", file.path));
+        output.push_str(&file.content);
+        if !file.content.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use collective_client::Client;
+    use protocol::{
+        client::Client as ClientPacket, server::Server, Packet, Phase, ServerPacket, StreamFrame,
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        sync::mpsc::UnboundedSender,
+    };
+
+    use super::session;
+
+    fn question(id: u64, payload: &str) -> Server {
+        Server::Question {
+            frame: StreamFrame {
+                id,
+                seq: 0,
+                payload: payload.to_string(),
+                end: true,
+            },
+        }
+    }
+
+    /// Run a session against an executor that sends `packets`, returning the outcome, what was
+    /// written to stdout and the packets that were sent to the executor
+    async fn run(
+        packets: Vec<Server>,
+        answers: &str,
+    ) -> (anyhow::Result<bool>, String, Vec<ClientPacket>) {
+        let (tx, mut executor_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (executor_tx, rx): (UnboundedSender<ServerPacket>, _) =
+            tokio::sync::mpsc::unbounded_channel();
+        for packet in packets {
+            executor_tx.send(Packet::new(packet)).unwrap();
+        }
+        drop(executor_tx);
+
+        let mut out = Vec::new();
+        let answers = BufReader::new(answers.as_bytes()).lines();
+        let executed = session(
+            Client::from_channels(tx, rx),
+            "Create a calculator".to_string(),
+            answers,
+            &mut out,
+        )
+        .await;
+
+        let mut sent = Vec::new();
+        while let Ok(packet) = executor_rx.try_recv() {
+            sent.push(packet.data);
+        }
+        (executed, String::from_utf8(out).unwrap(), sent)
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let content = "fn main() {}\n";
+        let packets = vec![
+            question(1, "What language?"),
+            question(2, "Anything else?"),
+            Server::Status {
+                phase: Phase::Planning,
+            },
+            Server::PlanChunk {
+                content: "1. write main.rs".to_string(),
+            },
+            Server::FileChunk {
+                path: "main.rs".to_string(),
+                seq: 0,
+                content: content.to_string(),
+            },
+            Server::FileWritten {
+                path: "main.rs".to_string(),
+                checksum: protocol::checksum(content),
+            },
+            Server::ExecutionFinished { success: true },
+        ];
+
+        let (executed, out, sent) = run(packets, "Rust\n").await;
+        assert!(executed.unwrap());
+        assert_eq!(out, "1. write main.rs\n==> main.rs <==\nfn main() {}\n");
+        assert!(matches!(&sent[..], [
+            ClientPacket::Instruction { instruction },
+            ClientPacket::Answer { answer },
+            ClientPacket::Execute,
+        ] if instruction == "Create a calculator" && answer == "Rust"));
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let (executed, ..) = run(vec![Server::ExecutionFinished { success: false }], "").await;
+        assert!(!executed.unwrap());
+
+        let error = Server::Error {
+            message: "The provider is down".to_string(),
+            recoverable: true,
+        };
+        let (executed, out, _) = run(vec![error], "").await;
+        assert!(executed.is_err());
+        assert!(out.is_empty());
+
+        // the connection closed before the plan was executed
+        let (executed, ..) = run(vec![question(1, "What language?")], "Rust\n").await;
+        assert!(executed.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_confirm() {
+        let command = |command: &str| Server::ConfirmCommand {
+            command: command.to_string(),
+            risk: protocol::Risk::Destructive,
+        };
+        let packets = vec![
+            command("rm -rf target"),
+            command("git push --force"),
+            Server::ExecutionFinished { success: true },
+        ];
+
+        let (_, _, sent) = run(packets, "y\n").await;
+        assert!(matches!(&sent[..], [
+            ClientPacket::Instruction { .. },
+            ClientPacket::Confirm { approved: true },
+            ClientPacket::Confirm { approved: false },
+        ]));
+    }
+}
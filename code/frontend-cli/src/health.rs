@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+/// Rough number of characters per token, used to estimate tokens/sec from the streamed
+/// characters.
+const CHARS_PER_TOKEN: f64 = 4.0;
+
+const STALLED: &str = "stream stalled — retry? [r] regenerate [c] cancel";
+
+/// Tracks the health of the stream that is currently being received.
+#[derive(Default)]
+pub struct StreamHealth {
+    /// when the request was sent
+    requested: Option<Instant>,
+    /// when the first delta arrived
+    first_delta: Option<Instant>,
+    /// when the last delta arrived
+    last_delta: Option<Instant>,
+    /// number of characters received
+    chars: usize,
+}
+
+impl StreamHealth {
+    /// A request was sent and we are now waiting for a stream.
+    pub fn start(&mut self, now: Instant) {
+        *self = Self {
+            requested: Some(now),
+            ..Self::default()
+        };
+    }
+
+    /// A delta arrived.
+    pub fn delta(&mut self, delta: &str, now: Instant) {
+        self.first_delta.get_or_insert(now);
+        self.last_delta = Some(now);
+        self.chars += delta.chars().count();
+    }
+
+    /// The stream ended (or was cancelled).
+    pub fn finish(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.requested.is_some()
+    }
+
+    /// Estimated tokens/sec since the first delta.
+    pub fn tokens_per_second(&self, now: Instant) -> Option<f64> {
+        let first_delta = self.first_delta?;
+        let elapsed = now.duration_since(first_delta).as_secs_f64();
+
+        if elapsed == 0.0 {
+            return None;
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let tokens = self.chars as f64 / CHARS_PER_TOKEN;
+
+        Some(tokens / elapsed)
+    }
+
+    /// Time since the last delta, or since the request if no delta arrived yet.
+    pub fn since_last_delta(&self, now: Instant) -> Option<Duration> {
+        let last = self.last_delta.or(self.requested)?;
+        Some(now.duration_since(last))
+    }
+
+    pub fn is_stalled(&self, timeout: Duration, now: Instant) -> bool {
+        self.since_last_delta(now)
+            .is_some_and(|elapsed| elapsed >= timeout)
+    }
+
+    /// The indicator shown while streaming.
+    pub fn status(&self, timeout: Duration, now: Instant) -> Option<String> {
+        if !self.is_active() {
+            return None;
+        }
+
+        if self.is_stalled(timeout, now) {
+            return Some(STALLED.to_string());
+        }
+
+        let since_last_delta = self.since_last_delta(now)?.as_secs_f64();
+
+        let status = match self.tokens_per_second(now) {
+            Some(tps) => format!("streaming · {tps:.1} tok/s · last delta {since_last_delta:.1}s"),
+            None => format!("waiting for first token · {since_last_delta:.1}s"),
+        };
+
+        Some(status)
+    }
+
+    /// The indicator without the numbers that change while streaming, so it only changes when
+    /// the stream starts, stalls or ends.
+    pub fn plain_status(&self, timeout: Duration, now: Instant) -> Option<String> {
+        if !self.is_active() {
+            return None;
+        }
+
+        let status = if self.is_stalled(timeout, now) {
+            STALLED
+        } else {
+            "waiting for the model"
+        };
+        Some(status.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::StreamHealth;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_inactive() {
+        let health = StreamHealth::default();
+        let now = Instant::now();
+
+        assert!(!health.is_stalled(TIMEOUT, now));
+        assert_eq!(health.status(TIMEOUT, now), None);
+    }
+
+    #[test]
+    fn test_tokens_per_second() {
+        let mut health = StreamHealth::default();
+        let start = Instant::now();
+
+        health.start(start);
+        health.delta("abcd", start);
+        health.delta("efgh", start + Duration::from_secs(1));
+
+        let tps = health
+            .tokens_per_second(start + Duration::from_secs(2))
+            .unwrap();
+        assert!((tps - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_stalled() {
+        let mut health = StreamHealth::default();
+        let start = Instant::now();
+
+        health.start(start);
+        assert!(!health.is_stalled(TIMEOUT, start + Duration::from_secs(5)));
+        assert!(health.is_stalled(TIMEOUT, start + TIMEOUT));
+
+        health.delta("a", start + TIMEOUT);
+        assert!(!health.is_stalled(TIMEOUT, start + TIMEOUT));
+
+        let status = health.status(TIMEOUT, start + TIMEOUT * 2).unwrap();
+        assert!(status.contains("stalled"));
+        assert_eq!(
+            health.plain_status(TIMEOUT, start + TIMEOUT * 2),
+            Some(status)
+        );
+        assert_eq!(
+            health.plain_status(TIMEOUT, start + TIMEOUT).as_deref(),
+            Some("waiting for the model")
+        );
+
+        health.finish();
+        assert!(!health.is_stalled(TIMEOUT, start + TIMEOUT * 3));
+    }
+}
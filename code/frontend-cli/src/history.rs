@@ -0,0 +1,210 @@
+//! Instructions and answers typed in earlier sessions, searchable with Ctrl+R.
+//!
+//! The history is a file with one entry per line, the most recent last.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use tracing::error;
+
+/// How many entries are kept
+const MAX_ENTRIES: usize = 1000;
+
+/// How many matches the search overlay shows
+pub const MAX_MATCHES: usize = 5;
+
+#[derive(Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    /// Load the history from `path`, which is created once something is added. Without a path
+    /// the history only lasts for this session.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_deref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|content| content.lines().map(ToString::to_string).collect())
+            .unwrap_or_default();
+
+        Self { path, entries }
+    }
+
+    /// Add `entry` as the most recent entry, removing an earlier identical one.
+    pub fn add(&mut self, entry: &str) {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return;
+        }
+
+        let duplicate = self.entries.iter().position(|e| e == entry);
+        if let Some(index) = duplicate {
+            self.entries.remove(index);
+        }
+        self.entries.push(entry.to_string());
+
+        let overflow = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..overflow);
+
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // rewriting keeps the file free of duplicates, appending is enough otherwise
+        let res = if duplicate.is_some() || overflow > 0 {
+            std::fs::write(path, self.entries.join("\n") + "\n")
+        } else {
+            append(path, entry)
+        };
+
+        if let Err(e) = res {
+            error!("Failed to save the history to {}: {e}", path.display());
+        }
+    }
+
+    /// The entries matching `query`, best first. Equally good matches are ordered by recency.
+    pub fn search(&self, query: &str) -> Vec<&str> {
+        let mut matches: Vec<_> = self
+            .entries
+            .iter()
+            .rev()
+            .filter_map(|entry| Some((score(query, entry)?, entry.as_str())))
+            .collect();
+
+        // stable, so recency breaks ties
+        matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        matches
+            .into_iter()
+            .take(MAX_MATCHES)
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+}
+
+fn append(path: &Path, entry: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{entry}")
+}
+
+/// How well `candidate` matches `query`, or `None` if it does not contain all characters of the
+/// query in order.
+///
+/// Matches are case-insensitive. Consecutive characters and characters at the start of a word
+/// score higher, gaps lower.
+fn score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let matches = |c: &char, q: char| c.to_lowercase().eq(q.to_lowercase());
+
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.chars() {
+        let index = position + candidate[position..].iter().position(|c| matches(c, q))?;
+        let word_start = index == 0 || !candidate[index - 1].is_alphanumeric();
+
+        score += 1 + match previous {
+            Some(previous) if index == previous + 1 => 8,
+            _ if word_start => 6,
+            Some(previous) => -i64::try_from(index - previous).unwrap_or(5).min(5),
+            None => 0,
+        };
+
+        previous = Some(index);
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+/// The Ctrl+R overlay: the query typed so far and which match is selected
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    pub selected: usize,
+}
+
+impl Search {
+    /// The lines of the overlay, the selected match marked with `>`
+    pub fn render(&self, matches: &[&str]) -> Vec<String> {
+        let mut lines = vec![format!("(history) {}", self.query)];
+
+        if matches.is_empty() {
+            lines.push("  no matches".to_string());
+        }
+
+        lines.extend(matches.iter().enumerate().map(|(i, entry)| {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            format!("{marker} {entry}")
+        }));
+
+        lines
+    }
+
+    /// Select the next match, wrapping around
+    pub fn next(&mut self, matches: usize) {
+        self.selected = (self.selected + 1) % matches.max(1);
+    }
+
+    /// Select the previous match, wrapping around
+    pub fn previous(&mut self, matches: usize) {
+        self.selected = self.selected.checked_sub(1).unwrap_or(matches.max(1) - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{score, History};
+
+    #[test]
+    fn test_score() {
+        assert!(score("clp", "add clippy CI").is_some());
+        assert!(score("xyz", "add clippy CI").is_none());
+        assert!(score("CI", "add clippy ci").is_some());
+
+        // consecutive characters at the start of a word beat scattered ones
+        assert!(score("clippy", "add clippy CI") > score("clippy", "cargo clean lib ppy"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut history = History::default();
+        history.add("add clippy CI to this repo");
+        history.add("Rust");
+        history.add("create a calculator");
+        history.add("Rust");
+
+        assert_eq!(history.search("clippy"), ["add clippy CI to this repo"]);
+        // the empty query matches everything, most recent first
+        assert_eq!(history.search(""), [
+            "Rust",
+            "create a calculator",
+            "add clippy CI to this repo"
+        ]);
+    }
+
+    #[test]
+    fn test_persist() {
+        let dir = std::env::temp_dir().join(format!("collective-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+
+        let mut history = History::load(Some(path.clone()));
+        history.add("first");
+        history.add("second");
+        history.add("first");
+
+        let history = History::load(Some(path));
+        assert_eq!(history.search(""), ["first", "second"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
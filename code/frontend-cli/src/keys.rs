@@ -0,0 +1,340 @@
+//! The keys bound to the shortcuts of the frontend, which `config.toml` can remap.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::pane::View;
+
+/// A key and the modifiers held with it, e.g. `ctrl+o`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Letters are lowercase, with shift if they were uppercase. Shift is ignored for other
+    /// characters, which are typed with it or not depending on the layout.
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::SHIFT | KeyModifiers::ALT);
+        match code {
+            KeyCode::Char(c) if c.is_uppercase() => Self {
+                code: KeyCode::Char(c.to_ascii_lowercase()),
+                modifiers: modifiers | KeyModifiers::SHIFT,
+            },
+            KeyCode::Char(c) if !c.is_alphabetic() => Self {
+                code,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            code => Self { code, modifiers },
+        }
+    }
+
+    pub fn matches(self, key: &KeyEvent) -> bool {
+        Self::new(key.code, key.modifiers) == self
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// Modifiers joined to the key by `+`, e.g. `ctrl+shift+z`, `pageup` or `f5`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts: Vec<_> = s.split('+').collect();
+        let name = parts
+            .pop()
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("`{s}` has no key"))?;
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in parts {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "shift" => KeyModifiers::SHIFT,
+                "alt" => KeyModifiers::ALT,
+                other => bail!("`{other}` in `{s}` is not a modifier, use ctrl, shift or alt"),
+            };
+        }
+
+        let lowercase = name.to_lowercase();
+        let code = match lowercase.as_str() {
+            "tab" => KeyCode::Tab,
+            "enter" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            function if function.len() > 1 && function.starts_with('f') => KeyCode::F(
+                function[1..]
+                    .parse()
+                    .with_context(|| format!("`{name}` in `{s}` is not a key"))?,
+            ),
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => bail!("`{name}` in `{s}` is not a key"),
+                }
+            }
+        };
+
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+/// A shortcut that can be bound to keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    ToggleOutput,
+    Focus,
+    PageUp,
+    PageDown,
+    Bottom,
+    Follow,
+    ExpandSources,
+    Search,
+    Undo,
+    Redo,
+    Perf,
+    Quit,
+}
+
+impl Binding {
+    const ALL: [Self; 12] = [
+        Self::ToggleOutput,
+        Self::Focus,
+        Self::PageUp,
+        Self::PageDown,
+        Self::Bottom,
+        Self::Follow,
+        Self::ExpandSources,
+        Self::Search,
+        Self::Undo,
+        Self::Redo,
+        Self::Perf,
+        Self::Quit,
+    ];
+
+    /// The name in `config.toml`
+    const fn name(self) -> &'static str {
+        match self {
+            Self::ToggleOutput => "toggle_output",
+            Self::Focus => "focus",
+            Self::PageUp => "page_up",
+            Self::PageDown => "page_down",
+            Self::Bottom => "bottom",
+            Self::Follow => "follow",
+            Self::ExpandSources => "expand_sources",
+            Self::Search => "search",
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+            Self::Perf => "perf",
+            Self::Quit => "quit",
+        }
+    }
+
+    const fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Self::ToggleOutput => &["ctrl+o"],
+            Self::Focus => &["tab"],
+            Self::PageUp => &["pageup"],
+            Self::PageDown => &["pagedown"],
+            Self::Bottom => &["ctrl+end"],
+            Self::Follow => &["ctrl+f"],
+            Self::ExpandSources => &["ctrl+e"],
+            Self::Search => &["ctrl+r"],
+            Self::Undo => &["ctrl+z"],
+            Self::Redo => &["ctrl+y", "ctrl+shift+z"],
+            Self::Perf => &["f12"],
+            Self::Quit => &["esc"],
+        }
+    }
+
+    /// The split view shortcut, if it is one
+    const fn view(self) -> Option<View> {
+        match self {
+            Self::ToggleOutput => Some(View::Toggle),
+            Self::Focus => Some(View::Focus),
+            Self::PageUp => Some(View::PageUp),
+            Self::PageDown => Some(View::PageDown),
+            Self::Bottom => Some(View::Bottom),
+            Self::Follow => Some(View::Follow),
+            Self::ExpandSources => Some(View::Sources),
+            Self::Search | Self::Undo | Self::Redo | Self::Perf | Self::Quit => None,
+        }
+    }
+}
+
+/// The keys bound to every [`Binding`]
+pub struct Keymap {
+    keys: Vec<(Key, Binding)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let keys = Binding::ALL
+            .iter()
+            .flat_map(|binding| {
+                binding.default_keys().iter().map(|key| {
+                    let key = key.parse().expect("the default keys are valid");
+                    (key, *binding)
+                })
+            })
+            .collect();
+        Self { keys }
+    }
+}
+
+impl Keymap {
+    /// Bind the shortcut `name` to `keys` instead of its default keys.
+    ///
+    /// # Errors
+    /// If there is no such shortcut or a key is invalid.
+    pub fn bind(&mut self, name: &str, keys: &[String]) -> anyhow::Result<()> {
+        let Some(binding) = Binding::ALL
+            .into_iter()
+            .find(|binding| binding.name() == name)
+        else {
+            let names: Vec<_> = Binding::ALL.iter().map(|binding| binding.name()).collect();
+            bail!(
+                "`{name}` is not a shortcut, the shortcuts are {}",
+                names.join(", ")
+            );
+        };
+
+        let keys = keys
+            .iter()
+            .map(|key| key.parse())
+            .collect::<anyhow::Result<Vec<Key>>>()?;
+        self.keys.retain(|(_, bound)| *bound != binding);
+        self.keys.extend(keys.into_iter().map(|key| (key, binding)));
+        Ok(())
+    }
+
+    /// The shortcut `key` is bound to
+    pub fn binding(&self, key: &KeyEvent) -> Option<Binding> {
+        self.keys
+            .iter()
+            .find(|(bound, _)| bound.matches(key))
+            .map(|(_, binding)| *binding)
+    }
+
+    /// The split view shortcut `key` is bound to
+    pub fn view(&self, key: &KeyEvent) -> Option<View> {
+        self.binding(key).and_then(Binding::view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Binding, Key, Keymap};
+    use crate::pane::View;
+
+    fn event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let key: Key = "ctrl+shift+z".parse()?;
+        assert!(key.matches(&event(
+            KeyCode::Char('Z'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        )));
+        assert!(key.matches(&event(KeyCode::Char('Z'), KeyModifiers::CONTROL)));
+        assert!(!key.matches(&event(KeyCode::Char('z'), KeyModifiers::CONTROL)));
+
+        let key: Key = "?".parse()?;
+        assert!(key.matches(&event(KeyCode::Char('?'), KeyModifiers::SHIFT)));
+        assert_eq!(
+            "F5".parse::<Key>()?,
+            Key::new(KeyCode::F(5), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            "Ctrl+PageUp".parse::<Key>()?,
+            Key::new(KeyCode::PageUp, KeyModifiers::CONTROL)
+        );
+
+        for invalid in ["", "ctrl+", "hyper+x", "fx", "enterr"] {
+            assert!(invalid.parse::<Key>().is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let keymap = Keymap::default();
+        let view = |code, modifiers| keymap.view(&event(code, modifiers));
+        let binding = |c, modifiers| keymap.binding(&event(KeyCode::Char(c), modifiers));
+
+        assert_eq!(
+            view(KeyCode::Char('o'), KeyModifiers::CONTROL),
+            Some(View::Toggle)
+        );
+        assert_eq!(view(KeyCode::Char('o'), KeyModifiers::NONE), None);
+        assert_eq!(view(KeyCode::Tab, KeyModifiers::NONE), Some(View::Focus));
+        assert_eq!(
+            view(KeyCode::PageUp, KeyModifiers::NONE),
+            Some(View::PageUp)
+        );
+        assert_eq!(
+            view(KeyCode::Char('f'), KeyModifiers::CONTROL),
+            Some(View::Follow)
+        );
+        assert_eq!(
+            view(KeyCode::End, KeyModifiers::CONTROL),
+            Some(View::Bottom)
+        );
+        assert_eq!(view(KeyCode::End, KeyModifiers::NONE), None);
+
+        assert_eq!(binding('z', KeyModifiers::CONTROL), Some(Binding::Undo));
+        assert_eq!(
+            binding('Z', KeyModifiers::CONTROL | KeyModifiers::SHIFT),
+            Some(Binding::Redo)
+        );
+        assert_eq!(binding('y', KeyModifiers::CONTROL), Some(Binding::Redo));
+        assert_eq!(binding('z', KeyModifiers::NONE), None);
+        assert_eq!(binding('r', KeyModifiers::CONTROL), Some(Binding::Search));
+        assert_eq!(
+            keymap.binding(&event(KeyCode::F(12), KeyModifiers::NONE)),
+            Some(Binding::Perf)
+        );
+    }
+
+    #[test]
+    fn test_bind() -> anyhow::Result<()> {
+        let mut keymap = Keymap::default();
+        keymap.bind("toggle_output", &["ctrl+t".to_string(), "f2".to_string()])?;
+
+        let toggle = |code, modifiers| keymap.view(&event(code, modifiers));
+        assert_eq!(
+            toggle(KeyCode::Char('t'), KeyModifiers::CONTROL),
+            Some(View::Toggle)
+        );
+        assert_eq!(
+            toggle(KeyCode::F(2), KeyModifiers::NONE),
+            Some(View::Toggle)
+        );
+        assert_eq!(toggle(KeyCode::Char('o'), KeyModifiers::CONTROL), None);
+
+        assert!(keymap.bind("explode", &["ctrl+x".to_string()]).is_err());
+        assert!(keymap.bind("quit", &["ctrl+".to_string()]).is_err());
+
+        Ok(())
+    }
+}
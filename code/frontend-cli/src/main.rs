@@ -1,40 +1,173 @@
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use anyhow::Context;
 use clap::Parser;
 use once_cell::sync::Lazy;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
+use tracing::{error, info, Level};
 
-use crate::app::App;
+use crate::{app::App, config::Config, history::History, keys::Keymap};
 
 mod app;
+mod batch;
 mod bootstrap;
+mod commands;
 mod comms;
+mod config;
+mod headless;
+mod health;
+mod history;
+mod keys;
+mod markdown;
+mod pane;
+mod perf;
+mod sources;
+mod steps;
 mod terminal;
 mod ui;
+mod undo;
 mod widget;
 
 static CANCEL_TOKEN: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
 
 #[derive(Parser, Clone)]
 pub struct Args {
-    #[clap(short, long, default_value = "127.0.0.1")]
-    ip: String,
-    #[clap(short, long, default_value = "8080")]
-    port: u16,
+    /// Address of the executor with --remote. Defaults to 127.0.0.1
+    #[clap(short, long)]
+    ip: Option<String>,
+    /// Defaults to 8080
+    #[clap(short, long)]
+    port: Option<u16>,
 
     #[clap(long, default_value = "false")]
     remote: bool,
+
+    /// With --remote, the token the executor was started with
+    #[clap(long, env = "COLLECTIVE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Seconds without a streamed delta before offering to regenerate or cancel
+    #[clap(long, default_value = "10")]
+    stall_timeout: u64,
+
+    /// File the instructions and answers are saved to for the Ctrl+R search. Defaults to
+    /// ~/.collective_history
+    #[clap(long)]
+    history: Option<PathBuf>,
+
+    /// Directory the log is written to. It is rotated once it reaches 10 MiB and the last 5
+    /// rotated logs are kept
+    #[clap(long, default_value = "logs")]
+    log_dir: PathBuf,
+
+    /// error, warn, info, debug or trace. Defaults to info
+    #[clap(long)]
+    log_level: Option<String>,
+
+    /// Theme of code blocks, one of the themes syntect ships with
+    #[clap(long)]
+    theme: Option<String>,
+
+    /// The config, whose values the flags override. Defaults to
+    /// $XDG_CONFIG_HOME/collective/config.toml or ~/.config/collective/config.toml
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Screen reader friendly output: questions are shown once they are complete, status changes
+    /// are announced as lines of the conversation, nothing is animated and nothing is only told
+    /// by color or style
+    #[clap(long)]
+    accessible: bool,
+
+    /// Run without the terminal interface: questions are asked on stderr and answered on stdin,
+    /// and the plan and the written files are printed to stdout. Exits with 0 once the plan was
+    /// executed, 1 if a step failed and 2 if the session failed
+    #[clap(long)]
+    headless: bool,
+
+    /// With --headless, the instruction. Read from the first line of stdin if it is missing
+    #[clap(requires = "headless")]
+    instruction: Option<String>,
+
+    /// With --headless, a file with one answer per line, read instead of stdin. The interview
+    /// ends at an empty line or at the end of the file
+    #[clap(long, requires = "headless")]
+    answers: Option<PathBuf>,
+
+    /// With --headless, a git repository the executor clones and works on, like /clone
+    #[clap(long, requires = "headless")]
+    repo: Option<String>,
+
+    /// The branch of --repo to check out. Defaults to its default branch
+    #[clap(long, requires = "repo")]
+    branch: Option<String>,
+
+    /// With --headless, the absolute path of the directory on the machine of the executor to
+    /// generate the code in, like /workspace. It is created if it does not exist
+    #[clap(long, requires = "headless")]
+    workspace: Option<String>,
+
+    /// How the local executor behaves
+    #[clap(flatten)]
+    settings: executor::Settings,
+}
+
+/// Fill in the values the flags left out from the config and apply them, returning the keys of
+/// the shortcuts and the log level.
+fn configure(args: &mut Args) -> anyhow::Result<(Keymap, Level)> {
+    let config = bootstrap::load_config(args.config.as_deref())?;
+    let keymap = config.keymap()?;
+    let Config {
+        ip,
+        port,
+        remote,
+        theme,
+        log_level,
+        ..
+    } = config;
+
+    args.ip = args.ip.take().or(ip);
+    args.port = args.port.or(port);
+    args.remote |= remote;
+    args.theme = args.theme.take().or(theme);
+    args.log_level = args.log_level.take().or(log_level);
+
+    if let Some(theme) = &args.theme {
+        markdown::set_theme(theme)?;
+    }
+    let level = match &args.log_level {
+        Some(level) => level.parse().with_context(|| {
+            format!("`{level}` is not a log level, use error, warn, info, debug or trace")
+        })?,
+        None => Level::INFO,
+    };
+
+    Ok((keymap, level))
 }
 
-async fn run(args: Args) -> anyhow::Result<()> {
+async fn run(args: Args, log: PathBuf, keymap: Keymap) -> anyhow::Result<()> {
     info!("Starting frontend-cli");
 
     let (tx, rx) = comms::setup_comms(&args).await?;
 
     // setup terminal
-    let mut terminal = terminal::setup().await?;
+    let mut terminal = terminal::setup(args.accessible).await?;
 
     // create app and run it
-    let app = App::new(tx, rx);
+    let history_path = args.history.clone().or_else(|| {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".collective_history"))
+    });
+    let history = History::load(history_path);
+
+    let app = App::new(
+        tx,
+        rx,
+        Duration::from_secs(args.stall_timeout),
+        history,
+        log,
+        args.accessible,
+        keymap,
+    );
     let res = app.run(&mut terminal).await;
 
     // cleanup
@@ -44,25 +177,57 @@ async fn run(args: Args) -> anyhow::Result<()> {
 }
 
 #[tokio::main]
-async fn main() {
-    // when this guard is dropped, the file we are writing to
+async fn main() -> ExitCode {
+    let mut args = Args::parse();
+    let (keymap, level) = match configure(&mut args) {
+        Ok(configured) => configured,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // when this is dropped, the file we are writing to
     // will be flushed and closed.
-    let _guard = bootstrap::setup_tracing();
-
-    let args = Args::parse();
+    let logs = match bootstrap::setup_tracing(&args.log_dir, level) {
+        Ok(logs) => logs,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
 
     ctrlc::set_handler(move || {
         CANCEL_TOKEN.cancel();
     })
     .expect("Error setting Ctrl-C handler");
 
-    if let Err(err) = run(args).await {
+    if args.headless {
+        let code = match headless::run(&args).await {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(err) => {
+                error!("{err:?}");
+                eprintln!("{err:#}");
+                2
+            }
+        };
+        drop(logs);
+        // stdin may still be read by a blocking thread, which the runtime would wait for
+        std::process::exit(code);
+    }
+
+    if let Err(err) = run(args, logs.path().to_path_buf(), keymap).await {
         error!("{err:?}");
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
 
 #[derive(Debug)]
 enum Event {
     Terminal(crossterm::event::Event),
     Packet(protocol::ServerPacket),
+    /// Emitted periodically so time-based state (like the stream health) is redrawn
+    Tick,
 }
@@ -0,0 +1,320 @@
+//! Markdown in the conversation: emphasis, inline code, headings, bullets and fenced code blocks,
+//! which are highlighted by their language.
+//!
+//! Lines are rendered once they are finished, the line that is still being typed or streamed is
+//! shown as it is.
+
+use anyhow::Context;
+use once_cell::sync::{Lazy, OnceCell};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{self, HighlightState, Highlighter, Theme, ThemeSet},
+    parsing::{ParseState, ScopeStack, SyntaxSet},
+};
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+use utils::markdown::Fence;
+
+/// The theme of code blocks unless another one is set, one of the themes syntect ships with
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+static HIGHLIGHT: OnceCell<Theme> = OnceCell::new();
+
+/// Highlight code blocks with the syntect theme `name`. Has no effect once a code block was
+/// highlighted.
+pub fn set_theme(name: &str) -> anyhow::Result<()> {
+    let mut themes = ThemeSet::load_defaults().themes;
+    let names = themes.keys().cloned().collect::<Vec<_>>().join(", ");
+    let theme = themes
+        .remove(name)
+        .with_context(|| format!("`{name}` is not a theme, the themes are {names}"))?;
+    // the theme is only set once, at startup
+    let _ = HIGHLIGHT.set(theme);
+    Ok(())
+}
+
+fn theme() -> &'static Theme {
+    HIGHLIGHT.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(DEFAULT_THEME)
+            .unwrap_or_default()
+    })
+}
+
+const FENCE: Style = Style {
+    fg: Some(Color::DarkGray),
+    bg: None,
+    add_modifier: Modifier::empty(),
+    sub_modifier: Modifier::empty(),
+};
+
+const CODE: Style = Style {
+    fg: Some(Color::Yellow),
+    bg: None,
+    add_modifier: Modifier::empty(),
+    sub_modifier: Modifier::empty(),
+};
+
+/// The block the next line is in
+#[derive(Default)]
+enum Block {
+    #[default]
+    Text,
+    Code {
+        /// the line that opened the block
+        fence: String,
+        /// `None` if the language is unknown
+        highlight: Option<(HighlightState, ParseState)>,
+    },
+}
+
+impl Block {
+    /// Render `line`, which follows the lines rendered before.
+    fn line(&mut self, line: &str) -> Spans<'static> {
+        match self {
+            Self::Text => {
+                let Some(fence) = Fence::parse(line) else {
+                    return inline(line);
+                };
+                let highlight = fence
+                    .language()
+                    .and_then(|language| SYNTAXES.find_syntax_by_token(language))
+                    .map(|syntax| {
+                        let highlighter = Highlighter::new(theme());
+                        let state = HighlightState::new(&highlighter, ScopeStack::new());
+                        (state, ParseState::new(syntax))
+                    });
+                *self = Self::Code {
+                    fence: line.to_string(),
+                    highlight,
+                };
+                Spans::from(Span::styled(line.to_string(), FENCE))
+            }
+            Self::Code { fence, .. }
+                if Fence::parse(fence).is_some_and(|fence| fence.is_closed_by(line)) =>
+            {
+                *self = Self::Text;
+                Spans::from(Span::styled(line.to_string(), FENCE))
+            }
+            Self::Code {
+                highlight: None, ..
+            } => Spans::from(Span::styled(line.to_string(), CODE)),
+            Self::Code {
+                highlight: Some(state),
+                ..
+            } => highlight(line, state),
+        }
+    }
+}
+
+/// Highlight a line of code, advancing `state` past it.
+fn highlight(line: &str, state: &mut (HighlightState, ParseState)) -> Spans<'static> {
+    let (highlight_state, parse_state) = state.clone();
+    let mut lines = HighlightLines::from_state(theme(), highlight_state, parse_state);
+
+    // the syntaxes expect the newline at the end of every line
+    let text = format!("{line}\n");
+    let Ok(regions) = lines.highlight_line(&text, &SYNTAXES) else {
+        return Spans::from(Span::styled(line.to_string(), CODE));
+    };
+
+    let spans = regions
+        .into_iter()
+        .map(|(style, text)| (style, text.trim_end_matches('\n')))
+        .filter(|(_, text)| !text.is_empty())
+        .map(|(style, text)| Span::styled(text.to_string(), color(style)))
+        .collect::<Vec<_>>();
+    *state = lines.state();
+
+    Spans::from(spans)
+}
+
+fn color(style: highlighting::Style) -> Style {
+    let highlighting::Color { r, g, b, .. } = style.foreground;
+    Style::default().fg(Color::Rgb(r, g, b))
+}
+
+/// A line of text: `#` headings are bold, `-`, `*` and `+` bullets become `•` and inline
+/// markdown is rendered.
+fn inline(line: &str) -> Spans<'static> {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+    if (1..=6).contains(&hashes) {
+        if let Some(heading) = trimmed[hashes..].strip_prefix(' ') {
+            let bold = Style::default().add_modifier(Modifier::BOLD);
+            return Spans::from(vec![
+                Span::raw(indent.to_string()),
+                Span::styled(heading.to_string(), bold),
+            ]);
+        }
+    }
+
+    let mut spans = vec![Span::raw(indent.to_string())];
+    let rest = match ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| trimmed.strip_prefix(bullet))
+    {
+        Some(item) => {
+            spans.push(Span::raw("• "));
+            item
+        }
+        None => trimmed,
+    };
+    spans.extend(emphasis(rest));
+    spans.retain(|span| !span.content.is_empty());
+
+    Spans::from(spans)
+}
+
+/// `**bold**`, `*italic*`, `_italic_` and `` `code` ``. Markers that are not closed are kept.
+fn emphasis(text: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let (marker, style) = match c {
+            '`' => ("`", CODE),
+            '*' if rest.starts_with("**") => ("**", Style::default().add_modifier(Modifier::BOLD)),
+            '*' | '_' => (&rest[..1], Style::default().add_modifier(Modifier::ITALIC)),
+            _ => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+
+        // `snake_case` is not emphasis
+        let inside_word = c == '_' && plain.chars().last().is_some_and(char::is_alphanumeric);
+        let after = &rest[marker.len()..];
+        let closed = after.find(marker).filter(|&end| {
+            let inner = &after[..end];
+            end > 0 && (marker == "`" || inner.trim() == inner)
+        });
+
+        match closed {
+            Some(end) if !inside_word => {
+                spans.push(Span::raw(std::mem::take(&mut plain)));
+                spans.push(Span::styled(after[..end].to_string(), style));
+                rest = &after[end + marker.len()..];
+            }
+            _ => {
+                plain.push_str(marker);
+                rest = after;
+            }
+        }
+    }
+
+    spans.push(Span::raw(plain));
+    spans
+}
+
+/// Renders the finished lines of a pane once, in order.
+#[derive(Default)]
+pub struct Markdown {
+    /// every line but the last, which may still change
+    rendered: Vec<Spans<'static>>,
+    /// the block the line after them is in
+    block: Block,
+}
+
+impl Markdown {
+    /// The line `index` of `lines`, whose lines before the last one never change.
+    pub fn line(&mut self, lines: &[String], index: usize) -> Spans<'static> {
+        let finished = lines.len().saturating_sub(1);
+        // the lines were cleared
+        if self.rendered.len() > finished {
+            *self = Self::default();
+        }
+
+        while self.rendered.len() < finished.min(index + 1) {
+            let spans = self.block.line(&lines[self.rendered.len()]);
+            self.rendered.push(spans);
+        }
+
+        match self.rendered.get(index) {
+            Some(spans) => spans.clone(),
+            None => Spans::from(lines.get(index).cloned().unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tui::{style::Modifier, text::Spans};
+
+    use super::{emphasis, inline, Markdown, CODE, FENCE};
+
+    fn text(spans: &Spans) -> String {
+        spans.0.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn test_emphasis() {
+        let spans = emphasis("use **tokio** or *async-std*, see `Cargo.toml`");
+        let contents: Vec<_> = spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(contents, [
+            "use ",
+            "tokio",
+            " or ",
+            "async-std",
+            ", see ",
+            "Cargo.toml",
+            ""
+        ]);
+        assert!(spans[1].style.add_modifier.contains(Modifier::BOLD));
+        assert!(spans[3].style.add_modifier.contains(Modifier::ITALIC));
+        assert_eq!(spans[5].style, CODE);
+
+        // not emphasis
+        for text in ["a * b * c", "snake_case_name", "**unclosed", "2 * 3"] {
+            assert_eq!(emphasis(text).len(), 1, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(text(&inline("  - a **b**")), "  • a b");
+        assert_eq!(text(&inline("## Usage")), "Usage");
+        assert_eq!(text(&inline("#hashtag")), "#hashtag");
+    }
+
+    #[test]
+    fn test_code_block() {
+        let lines: Vec<String> = [
+            "> Is this right?",
+            "```rust",
+            "fn main() { let _ = \"**not bold**\"; }",
+            "```",
+            "- done",
+            "being typed **",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let mut markdown = Markdown::default();
+        let rendered: Vec<_> = (0..lines.len())
+            .map(|index| markdown.line(&lines, index))
+            .collect();
+
+        assert_eq!(rendered[1].0[0].style, FENCE);
+        // highlighted in more than one color, nothing removed
+        assert!(rendered[2].0.len() > 1);
+        assert_eq!(text(&rendered[2]), lines[2]);
+        assert_eq!(rendered[3].0[0].style, FENCE);
+        assert_eq!(text(&rendered[4]), "• done");
+        assert_eq!(text(&rendered[5]), "being typed **");
+
+        // unknown languages are not highlighted
+        let lines: Vec<String> = ["```klingon", "qapla'", ""].map(String::from).to_vec();
+        let mut markdown = Markdown::default();
+        assert_eq!(markdown.line(&lines, 1).0[0].style, CODE);
+    }
+}
@@ -0,0 +1,200 @@
+use crossterm::event::{MouseEvent, MouseEventKind};
+
+/// Lines a turn of the mouse wheel scrolls
+const WHEEL_LINES: usize = 3;
+
+/// Shortcuts of the split view, which work whatever else the user is doing. Their keys are in the
+/// [`Keymap`](crate::keys::Keymap).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    /// Show or hide the output pane
+    Toggle,
+    /// Move the focus to the other pane
+    Focus,
+    /// Scroll the focused pane a page up
+    PageUp,
+    /// Scroll the focused pane a page down, back to following it at the bottom
+    PageDown,
+    /// Scroll the focused pane up by `n` lines, e.g. with the mouse wheel
+    Up(usize),
+    /// Scroll the focused pane down by `n` lines
+    Down(usize),
+    /// Jump to the bottom of the focused pane
+    Bottom,
+    /// Turn following new lines of the focused pane on or off
+    Follow,
+    /// Show the first paragraphs of the sources or only their titles
+    Sources,
+}
+
+impl View {
+    /// The mouse wheel scrolls.
+    pub const fn from_mouse(mouse: &MouseEvent) -> Option<Self> {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => Some(Self::Up(WHEEL_LINES)),
+            MouseEventKind::ScrollDown => Some(Self::Down(WHEEL_LINES)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Focus {
+    #[default]
+    Conversation,
+    Output,
+}
+
+impl Focus {
+    #[must_use]
+    pub const fn other(self) -> Self {
+        match self {
+            Self::Conversation => Self::Output,
+            Self::Output => Self::Conversation,
+        }
+    }
+}
+
+/// How far a pane is scrolled up from its bottom.
+///
+/// At the bottom the pane follows new lines, unless following is turned off. Scrolled up it
+/// stays on the lines that are shown while new ones arrive.
+#[derive(Debug)]
+pub struct Scroll {
+    /// lines hidden below the window
+    back: usize,
+    /// whether new lines are shown when the window is at the bottom
+    follow: bool,
+}
+
+impl Default for Scroll {
+    fn default() -> Self {
+        Self {
+            back: 0,
+            follow: true,
+        }
+    }
+}
+
+impl Scroll {
+    /// Scroll up `lines`, no further than the top of `len` lines shown `height` at a time.
+    pub fn up(&mut self, lines: usize, len: usize, height: usize) {
+        self.back = (self.back + lines).min(len.saturating_sub(height));
+    }
+
+    pub fn down(&mut self, lines: usize) {
+        self.back = self.back.saturating_sub(lines);
+    }
+
+    /// Show the last lines, following new ones if following is on.
+    pub fn bottom(&mut self) {
+        self.back = 0;
+    }
+
+    /// Turn following on, which jumps to the bottom, or off.
+    pub fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        if self.follow {
+            self.back = 0;
+        }
+    }
+
+    /// `lines` were added at the bottom.
+    pub fn grew(&mut self, lines: usize) {
+        if self.back > 0 || !self.follow {
+            self.back += lines;
+        }
+    }
+
+    /// Whether the last line is shown
+    #[must_use]
+    pub const fn is_following(&self) -> bool {
+        self.back == 0
+    }
+
+    /// Whether following is turned on
+    #[must_use]
+    pub const fn follows(&self) -> bool {
+        self.follow
+    }
+
+    /// Lines below the window
+    #[must_use]
+    pub const fn below(&self) -> usize {
+        self.back
+    }
+
+    /// The lines of `len` that are shown `height` at a time.
+    #[must_use]
+    pub fn window(&self, len: usize, height: usize) -> std::ops::Range<usize> {
+        let end = len.saturating_sub(self.back).max(height.min(len));
+        end.saturating_sub(height)..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyModifiers, MouseEvent, MouseEventKind};
+
+    use super::{Scroll, View};
+
+    #[test]
+    fn test_scroll() {
+        let mut scroll = Scroll::default();
+        assert_eq!(scroll.window(3, 10), 0..3);
+        assert_eq!(scroll.window(30, 10), 20..30);
+
+        scroll.up(5, 30, 10);
+        assert_eq!(scroll.window(30, 10), 15..25);
+
+        // new lines do not move what is shown
+        scroll.grew(2);
+        assert_eq!(scroll.window(32, 10), 15..25);
+
+        // no further than the top
+        scroll.up(100, 32, 10);
+        assert_eq!(scroll.window(32, 10), 0..10);
+
+        scroll.down(100);
+        assert!(scroll.is_following());
+        scroll.grew(2);
+        assert_eq!(scroll.window(34, 10), 24..34);
+    }
+
+    #[test]
+    fn test_follow() {
+        let mut scroll = Scroll::default();
+        scroll.toggle_follow();
+        assert!(!scroll.follows());
+
+        // the window stays on the bottom it showed
+        scroll.grew(5);
+        assert_eq!(scroll.window(35, 10), 20..30);
+        assert_eq!(scroll.below(), 5);
+
+        scroll.bottom();
+        assert_eq!(scroll.window(35, 10), 25..35);
+        scroll.grew(1);
+        assert!(!scroll.is_following());
+
+        scroll.toggle_follow();
+        assert!(scroll.is_following());
+        scroll.grew(1);
+        assert_eq!(scroll.window(37, 10), 27..37);
+    }
+
+    #[test]
+    fn test_mouse() {
+        let wheel = |kind| {
+            View::from_mouse(&MouseEvent {
+                kind,
+                column: 0,
+                row: 0,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+        assert_eq!(wheel(MouseEventKind::ScrollUp), Some(View::Up(3)));
+        assert_eq!(wheel(MouseEventKind::ScrollDown), Some(View::Down(3)));
+        assert_eq!(wheel(MouseEventKind::Moved), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! The performance overlay, hidden until its shortcut is pressed, F12 by default.
+//!
+//! It shows how long drawing a frame takes, the events waiting to be handled, how fast packets
+//! arrive and what became of the chunks of streamed questions, to tell whether the frontend keeps
+//! up on a slow terminal or over SSH.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Event;
+
+/// Frames the render time is measured over
+const FRAMES: usize = 120;
+
+/// Time the packet rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The events waiting to be handled are queued by where they come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queue {
+    Input,
+    Packets,
+    Ticks,
+}
+
+impl Queue {
+    pub const fn of(event: &Event) -> Self {
+        match event {
+            Event::Terminal(_) => Self::Input,
+            Event::Packet(_) => Self::Packets,
+            Event::Tick => Self::Ticks,
+        }
+    }
+}
+
+/// Events sent to the event loop that it did not handle yet, by [`Queue`]. Shared by the tasks
+/// sending them.
+#[derive(Debug, Default, Clone)]
+pub struct Pending(Arc<[AtomicUsize; 3]>);
+
+impl Pending {
+    /// Count `event` before it is sent.
+    pub fn sent(&self, event: &Event) {
+        self.0[Queue::of(event) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, event: &Event) {
+        let _ = self.0[Queue::of(event) as usize].fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |pending| Some(pending.saturating_sub(1)),
+        );
+    }
+
+    pub fn get(&self, queue: Queue) -> usize {
+        self.0[queue as usize].load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Perf {
+    shown: bool,
+    /// how long drawing the last frames took
+    frames: VecDeque<Duration>,
+    /// when the packets of the last [`RATE_WINDOW`] arrived
+    packets: VecDeque<Instant>,
+    /// chunks handled since the last frame
+    chunks: usize,
+    /// chunks drawn in the same frame as an earlier one
+    coalesced: u64,
+    /// chunks ignored as duplicates or after the end of their stream
+    dropped: u64,
+}
+
+impl Perf {
+    /// Show or hide the overlay. Returns whether it is shown.
+    pub fn toggle(&mut self) -> bool {
+        self.shown = !self.shown;
+        self.shown
+    }
+
+    /// A frame was drawn in `took`.
+    pub fn drawn(&mut self, took: Duration) {
+        if self.frames.len() == FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(took);
+        self.chunks = 0;
+    }
+
+    /// A packet arrived at `now`.
+    pub fn packet(&mut self, now: Instant) {
+        self.packets.push_back(now);
+        self.expire(now);
+    }
+
+    /// A chunk of a streamed question was shown.
+    pub fn chunk(&mut self) {
+        if self.chunks > 0 {
+            self.coalesced += 1;
+        }
+        self.chunks += 1;
+    }
+
+    /// A chunk of a streamed question was ignored.
+    pub fn dropped(&mut self) {
+        self.dropped += 1;
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .packets
+            .front()
+            .is_some_and(|&at| now.duration_since(at) > RATE_WINDOW)
+        {
+            self.packets.pop_front();
+        }
+    }
+
+    /// The lines of the overlay, empty while it is hidden. `lost` are the chunks that are
+    /// missing from the questions being streamed.
+    pub fn lines(&mut self, pending: &Pending, lost: u64, now: Instant) -> Vec<String> {
+        if !self.shown {
+            return Vec::new();
+        }
+        self.expire(now);
+
+        let frame = match self.frames.iter().max() {
+            Some(max) => {
+                let total: Duration = self.frames.iter().sum();
+                let average = total / u32::try_from(self.frames.len()).unwrap_or(u32::MAX);
+                format!("frame {} avg · {} max", millis(average), millis(*max))
+            }
+            None => "frame -".to_string(),
+        };
+
+        vec![
+            frame,
+            format!(
+                "pending {} input · {} packets · {} ticks",
+                pending.get(Queue::Input),
+                pending.get(Queue::Packets),
+                pending.get(Queue::Ticks)
+            ),
+            format!("packets {}/s", self.packets.len()),
+            format!(
+                "chunks {} coalesced · {} dropped · {lost} lost",
+                self.coalesced, self.dropped
+            ),
+        ]
+    }
+}
+
+/// e.g. `1.25ms`
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Pending, Perf, Queue};
+    use crate::Event;
+
+    #[test]
+    fn test_pending() {
+        let pending = Pending::default();
+        pending.sent(&Event::Tick);
+        pending.sent(&Event::Tick);
+        pending.received(&Event::Tick);
+        assert_eq!(pending.get(Queue::Ticks), 1);
+        assert_eq!(pending.get(Queue::Packets), 0);
+
+        // never below zero
+        pending.clone().received(&Event::Tick);
+        pending.received(&Event::Tick);
+        assert_eq!(pending.get(Queue::Ticks), 0);
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut perf = Perf::default();
+        let now = Instant::now();
+        assert!(perf.lines(&Pending::default(), 0, now).is_empty());
+        assert!(perf.toggle());
+
+        perf.drawn(Duration::from_millis(1));
+        perf.drawn(Duration::from_millis(3));
+        perf.packet(now - Duration::from_secs(2));
+        perf.packet(now);
+        perf.chunk();
+        perf.chunk();
+        perf.chunk();
+        perf.drawn(Duration::from_millis(2));
+        perf.chunk();
+        perf.dropped();
+
+        assert_eq!(perf.lines(&Pending::default(), 4, now), [
+            "frame 2.00ms avg · 3.00ms max",
+            "pending 0 input · 0 packets · 0 ticks",
+            "packets 1/s",
+            "chunks 2 coalesced · 1 dropped · 4 lost",
+        ]);
+
+        assert!(!perf.toggle());
+        assert!(perf.lines(&Pending::default(), 0, now).is_empty());
+    }
+}
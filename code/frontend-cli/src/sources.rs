@@ -0,0 +1,81 @@
+use protocol::server::SourcePreview;
+
+/// The external material the executor read while running the plan, shown in the sources pane.
+#[derive(Default)]
+pub struct Sources {
+    previews: Vec<(SourcePreview, bool)>,
+}
+
+impl Sources {
+    /// A new plan is being written.
+    pub fn clear(&mut self) {
+        self.previews.clear();
+    }
+
+    pub fn add(&mut self, preview: SourcePreview) {
+        self.previews.push((preview, false));
+    }
+
+    /// The user vetoed source `id`, it is kept but marked.
+    pub fn veto(&mut self, id: u64) {
+        for (preview, vetoed) in &mut self.previews {
+            if preview.id == id {
+                *vetoed = true;
+            }
+        }
+    }
+
+    /// The sources as they are shown, with their first paragraphs if `expanded`
+    pub fn lines(&self, expanded: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (preview, vetoed) in &self.previews {
+            let marker = if *vetoed { "✗ " } else { "" };
+            lines.push(format!("{marker}[{}] {}", preview.id, preview.title));
+            lines.push(format!("  {}", preview.url));
+            if expanded {
+                lines.extend(
+                    preview
+                        .summary
+                        .lines()
+                        .map(|line| format!("  {line}").trim_end().to_string()),
+                );
+                lines.push(String::new());
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::server::SourcePreview;
+
+    use super::Sources;
+
+    #[test]
+    fn test_sources() {
+        let mut sources = Sources::default();
+        assert!(sources.lines(false).is_empty());
+
+        sources.add(SourcePreview {
+            id: 1,
+            title: "Serde".to_string(),
+            summary: "Serde is a framework.\n\nIt is fast.".to_string(),
+            url: "https://lib.rs/crates/serde".to_string(),
+        });
+        assert_eq!(sources.lines(false), [
+            "[1] Serde",
+            "  https://lib.rs/crates/serde"
+        ]);
+
+        sources.veto(1);
+        assert_eq!(sources.lines(true), [
+            "✗ [1] Serde",
+            "  https://lib.rs/crates/serde",
+            "  Serde is a framework.",
+            "",
+            "  It is fast.",
+            ""
+        ]);
+    }
+}
@@ -0,0 +1,200 @@
+/// How far a step of the plan got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl State {
+    const fn marker(self) -> char {
+        match self {
+            Self::Pending => ' ',
+            Self::Running => '>',
+            Self::Succeeded => '✓',
+            Self::Failed => '✗',
+        }
+    }
+}
+
+/// The steps of the plan, filled in while it is streamed and updated while it is executed.
+#[derive(Default)]
+pub struct Steps {
+    /// the plan streamed so far, a JSON array of steps
+    plan: String,
+    steps: Vec<(String, State)>,
+}
+
+impl Steps {
+    /// A new plan is being written.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// A chunk of the plan arrived. Every step whose title is complete is shown.
+    pub fn chunk(&mut self, content: &str) {
+        self.plan.push_str(content);
+        for (index, title) in titles(&self.plan).into_iter().enumerate() {
+            match self.steps.get_mut(index) {
+                Some((known, _)) => *known = title,
+                None => self.steps.push((title, State::Pending)),
+            }
+        }
+    }
+
+    /// Step `index` started, its title is the one the executor runs.
+    pub fn started(&mut self, index: usize, title: String) {
+        while self.steps.len() <= index {
+            self.steps.push((String::new(), State::Pending));
+        }
+        self.steps[index] = (title, State::Running);
+    }
+
+    pub fn finished(&mut self, index: usize, success: bool) {
+        if let Some((_, state)) = self.steps.get_mut(index) {
+            *state = if success {
+                State::Succeeded
+            } else {
+                State::Failed
+            };
+        }
+    }
+
+    /// The steps at `indices` run again, the others keep how far they got.
+    pub fn rerun(&mut self, indices: &[usize]) {
+        for &index in indices {
+            if let Some((_, state)) = self.steps.get_mut(index) {
+                *state = State::Pending;
+            }
+        }
+    }
+
+    /// e.g. `2 of 5 steps done`, `None` while there is no plan
+    pub fn progress(&self) -> Option<String> {
+        if self.steps.is_empty() {
+            return None;
+        }
+        let done = self
+            .steps
+            .iter()
+            .filter(|(_, state)| *state == State::Succeeded)
+            .count();
+        Some(format!("{done} of {} steps done", self.steps.len()))
+    }
+
+    /// The steps as they are shown, numbered from 1
+    pub fn lines(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(index, (title, state))| format!("{} {}. {title}", state.marker(), index + 1))
+            .collect()
+    }
+
+    /// The line that has to be visible: the running or failed step, the last one otherwise
+    pub fn current(&self) -> usize {
+        self.steps
+            .iter()
+            .position(|(_, state)| matches!(state, State::Running | State::Failed))
+            .unwrap_or_else(|| self.steps.len().saturating_sub(1))
+    }
+}
+
+/// The values of the complete `"title"` fields in `plan`, which may be cut off.
+fn titles(plan: &str) -> Vec<String> {
+    let mut titles = Vec::new();
+    let mut rest = plan;
+
+    while let Some(start) = rest.find("\"title\"") {
+        rest = &rest[start + "\"title\"".len()..];
+        let Some(value) = rest.trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let Some(value) = value.trim_start().strip_prefix('"') else {
+            continue;
+        };
+        let Some((title, end)) = string(value) else {
+            break;
+        };
+        titles.push(title);
+        rest = &value[end..];
+    }
+
+    titles
+}
+
+/// The JSON string at the start of `text`, after its opening quote, and where it ends. `None` if
+/// it is not closed yet.
+fn string(text: &str) -> Option<(String, usize)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, index + 1)),
+            '\\' => match chars.next()?.1 {
+                'n' | 't' => value.push(' '),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32);
+                    value.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{titles, Steps};
+
+    #[test]
+    fn test_titles() {
+        let plan = r#"[{"title": "Create the \"app\"", "command": {}}, {"title" :"Build", "#;
+        assert_eq!(titles(plan), ["Create the \"app\"", "Build"]);
+
+        assert_eq!(titles(r#"[{"title": "Crea"#), Vec::<String>::new());
+        assert_eq!(
+            titles(r#"[{"description": "no title"}]"#),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut steps = Steps::default();
+        assert!(steps.progress().is_none());
+
+        steps.chunk(r#"[{"title": "Create"#);
+        assert!(steps.lines().is_empty());
+        steps.chunk(r#" the crate"}, {"title": "Build it"}]"#);
+        assert_eq!(steps.lines(), ["  1. Create the crate", "  2. Build it"]);
+
+        steps.started(0, "Create the crate".to_string());
+        steps.finished(0, true);
+        steps.started(1, "Build the crate".to_string());
+        assert_eq!(steps.lines(), [
+            "✓ 1. Create the crate",
+            "> 2. Build the crate"
+        ]);
+        assert_eq!(steps.current(), 1);
+
+        steps.finished(1, false);
+        assert_eq!(steps.lines()[1], "✗ 2. Build the crate");
+        assert_eq!(steps.progress().as_deref(), Some("1 of 2 steps done"));
+
+        steps.rerun(&[1, 7]);
+        assert_eq!(steps.lines(), [
+            "✓ 1. Create the crate",
+            "  2. Build the crate"
+        ]);
+
+        steps.clear();
+        assert!(steps.lines().is_empty());
+    }
+}
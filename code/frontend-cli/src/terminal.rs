@@ -13,7 +13,8 @@ use crate::CANCEL_TOKEN;
 
 pub type Terminal = tui::Terminal<CrosstermBackend<Stdout>>;
 
-fn setup_blocking() -> anyhow::Result<Terminal> {
+/// Setup the terminal, with a cursor that does not blink if `steady`.
+fn setup_blocking(steady: bool) -> anyhow::Result<Terminal> {
     // setup terminal
     info!("Setting up terminal");
     enable_raw_mode()?;
@@ -22,11 +23,12 @@ fn setup_blocking() -> anyhow::Result<Terminal> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    execute!(
-        terminal.backend_mut(),
-        cursor::Show,
+    let style = if steady {
+        cursor::SetCursorStyle::SteadyBar
+    } else {
         cursor::SetCursorStyle::BlinkingBar
-    )?;
+    };
+    execute!(terminal.backend_mut(), cursor::Show, style)?;
 
     Ok(terminal)
 }
@@ -56,8 +58,8 @@ fn stop_blocking(mut terminal: Terminal) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn setup() -> anyhow::Result<Terminal> {
-    tokio::task::spawn_blocking(setup_blocking).await?
+pub async fn setup(steady: bool) -> anyhow::Result<Terminal> {
+    tokio::task::spawn_blocking(move || setup_blocking(steady)).await?
 }
 
 pub async fn stop(terminal: Terminal) -> anyhow::Result<()> {
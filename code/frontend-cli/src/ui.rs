@@ -1,15 +1,102 @@
-use tui::{backend::Backend, Frame};
+use tui::{
+    backend::Backend,
+    buffer::Buffer,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, Clear, Paragraph, Widget, Wrap},
+    Frame,
+};
 
-use crate::widget::Label;
+use crate::{
+    markdown::Markdown,
+    pane::{Focus, Scroll, View},
+    sources::Sources,
+    steps::Steps,
+    widget::{Cache, Label},
+};
 
+/// Part of the screen the plan pane takes at most, the rest is left for the conversation
+const PLAN_SHARE: u16 = 3;
+
+/// Percent of the width the sources pane takes, with only their titles and expanded
+const SOURCES_WIDTH: u16 = 30;
+const EXPANDED_SOURCES_WIDTH: u16 = 50;
+
+/// The screen, drawn from what the event handlers put in it.
+///
+/// Every pane is rendered once and drawn from its [`Cache`] until something shown in it changes,
+/// so a streamed token only renders the pane it arrived in.
 pub struct Ui {
     input: Vec<String>,
+    /// the conversation rendered as markdown
+    markdown: Markdown,
+    status: Option<String>,
+    /// lines shown above the status line, e.g. the history search
+    overlay: Vec<String>,
+    /// the performance overlay in the top right corner, empty to hide it
+    perf: Vec<String>,
+
+    /// the steps of the plan and their progress, empty to hide the plan pane
+    plan: Vec<String>,
+    plan_progress: Option<String>,
+    /// the step that has to be visible
+    plan_current: usize,
+
+    /// the sources the executor read, empty to hide the sources pane
+    sources: Vec<String>,
+    /// whether the sources show their first paragraphs
+    sources_expanded: bool,
+
+    /// output of the commands the executor runs
+    output: Vec<String>,
+    /// whether the output is shown in a pane right of the conversation
+    split: bool,
+    focus: Focus,
+    conversation_scroll: Scroll,
+    output_scroll: Scroll,
+    /// lines the panes showed when last drawn, a page to scroll
+    conversation_height: usize,
+    output_height: usize,
+
+    conversation_cache: Cache,
+    plan_cache: Cache,
+    output_cache: Cache,
+    sources_cache: Cache,
+    /// the status line or overlay changed since the last draw
+    damaged: bool,
+
+    /// for screen readers: lines are shown as they are, without styling, and the cursor is
+    /// where the reading position is
+    accessible: bool,
 }
 
 impl Ui {
-    pub fn new() -> Self {
+    pub fn new(accessible: bool) -> Self {
         Self {
             input: vec![String::new()],
+            markdown: Markdown::default(),
+            status: None,
+            overlay: Vec::new(),
+            perf: Vec::new(),
+            plan: Vec::new(),
+            plan_progress: None,
+            plan_current: 0,
+            sources: Vec::new(),
+            sources_expanded: false,
+            output: Vec::new(),
+            split: false,
+            focus: Focus::default(),
+            conversation_scroll: Scroll::default(),
+            output_scroll: Scroll::default(),
+            conversation_height: 0,
+            output_height: 0,
+            conversation_cache: Cache::default(),
+            plan_cache: Cache::default(),
+            output_cache: Cache::default(),
+            sources_cache: Cache::default(),
+            damaged: true,
+            accessible,
         }
     }
 
@@ -17,29 +104,530 @@ impl Ui {
     pub fn reset(&mut self) {
         self.input.clear();
         self.input.push(String::new());
+        self.conversation_cache.damage();
     }
 
+    /// The line being typed or streamed
+    pub fn line(&self) -> &str {
+        self.input.last().unwrap()
+    }
+
+    /// The line being typed or streamed, the conversation is redrawn as it may be changed.
     pub fn current_line(&mut self) -> &mut String {
+        self.conversation_cache.damage();
         self.input.last_mut().unwrap()
     }
 
     pub fn new_line(&mut self) {
         self.input.push(String::new());
+        self.conversation_scroll.grew(1);
+        self.conversation_cache.damage();
+    }
+
+    /// Add `text` to the current line, starting a new line at every newline in it.
+    pub fn push_text(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.current_line().push_str(first);
+        }
+        for line in lines {
+            self.new_line();
+            self.current_line().push_str(line);
+        }
+    }
+
+    /// Set the status line shown at the bottom of the screen.
+    pub fn set_status(&mut self, status: Option<String>) {
+        if self.status != status {
+            self.status = status;
+            self.damaged = true;
+        }
+    }
+
+    /// Set the lines shown above the status line, empty to hide them.
+    pub fn set_overlay(&mut self, overlay: Vec<String>) {
+        if self.overlay != overlay {
+            self.overlay = overlay;
+            self.damaged = true;
+        }
+    }
+
+    /// Set the lines of the performance overlay, empty to hide it.
+    pub fn set_perf(&mut self, perf: Vec<String>) {
+        if self.perf != perf {
+            self.perf = perf;
+            self.damaged = true;
+        }
+    }
+
+    /// Show `steps` in the plan pane above the conversation.
+    pub fn set_plan(&mut self, steps: &Steps) {
+        let (plan, progress, current) = (steps.lines(), steps.progress(), steps.current());
+        if (&plan, &progress, current) != (&self.plan, &self.plan_progress, self.plan_current) {
+            self.plan = plan;
+            self.plan_progress = progress;
+            self.plan_current = current;
+            self.plan_cache.damage();
+        }
+    }
+
+    /// Show `sources` in the sources pane right of the conversation.
+    pub fn set_sources(&mut self, sources: &Sources) {
+        let sources = sources.lines(self.sources_expanded);
+        if sources != self.sources {
+            // the sources pane appears or disappears
+            if sources.is_empty() != self.sources.is_empty() {
+                self.invalidate();
+            }
+            self.sources = sources;
+            self.sources_cache.damage();
+        }
     }
 
-    pub fn run<B: Backend>(&self, f: &mut Frame<B>) {
+    /// A line only shown in the output pane, e.g. the title of a step heading its output.
+    pub fn pane_line(&mut self, line: String) {
+        self.output.push(line);
+        self.output_scroll.grew(1);
+        self.output_cache.damage();
+    }
+
+    /// A line of command output. It is only shown in the conversation while the output pane
+    /// is hidden.
+    pub fn output(&mut self, line: &str) {
+        self.output.push(format!("  {line}"));
+        self.output_scroll.grew(1);
+        self.output_cache.damage();
+
+        if !self.split {
+            self.current_line().push_str(&format!("  {line}"));
+            self.new_line();
+        }
+    }
+
+    pub fn view(&mut self, view: View) {
+        // the output pane shows which pane is focused, the layout changes with the split and the
+        // width of the sources
+        if matches!(view, View::Toggle | View::Focus | View::Sources) {
+            self.invalidate();
+        }
+        match self.focus {
+            Focus::Conversation => self.conversation_cache.damage(),
+            Focus::Output => self.output_cache.damage(),
+        }
+
+        match view {
+            View::Toggle => {
+                self.split = !self.split;
+                self.focus = Focus::Conversation;
+            }
+            View::Focus if self.split => self.focus = self.focus.other(),
+            View::Focus => {}
+            View::PageUp => {
+                let (scroll, len, height) = self.focused();
+                scroll.up(height.max(1), len, height);
+            }
+            View::PageDown => {
+                let (scroll, _, height) = self.focused();
+                scroll.down(height.max(1));
+            }
+            View::Up(lines) => {
+                let (scroll, len, height) = self.focused();
+                scroll.up(lines, len, height);
+            }
+            View::Down(lines) => self.focused().0.down(lines),
+            View::Bottom => self.focused().0.bottom(),
+            View::Follow => self.focused().0.toggle_follow(),
+            // the lines are updated by `set_sources`
+            View::Sources => self.sources_expanded = !self.sources_expanded,
+        }
+    }
+
+    /// Draw everything again, e.g. after the terminal was resized.
+    pub fn invalidate(&mut self) {
+        self.conversation_cache.damage();
+        self.plan_cache.damage();
+        self.output_cache.damage();
+        self.sources_cache.damage();
+        self.damaged = true;
+    }
+
+    /// Whether anything changed since the last draw
+    pub const fn needs_draw(&self) -> bool {
+        self.damaged
+            || self.conversation_cache.is_damaged()
+            || self.plan_cache.is_damaged()
+            || self.output_cache.is_damaged()
+            || self.sources_cache.is_damaged()
+    }
+
+    /// The scroll state, number of lines and page height of the focused pane
+    fn focused(&mut self) -> (&mut Scroll, usize, usize) {
+        match self.focus {
+            Focus::Conversation => (
+                &mut self.conversation_scroll,
+                self.input.len(),
+                self.conversation_height,
+            ),
+            Focus::Output => (
+                &mut self.output_scroll,
+                self.output.len(),
+                self.output_height,
+            ),
+        }
+    }
+
+    pub fn run<B: Backend>(&mut self, f: &mut Frame<B>) {
         let size = f.size();
 
-        let mut render_loc = size;
+        // hidden panes are rendered again once they are shown
+        let main = self.run_plan(f, size);
+        let main = self.run_sources(f, main);
+
+        let (conversation, output) = if self.split {
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(main);
+            (panes[0], Some(panes[1]))
+        } else {
+            (main, None)
+        };
+
+        // the status and overlay are drawn over the bottom of the conversation
+        let overlay_height = u16::try_from(self.overlay.len()).unwrap_or(u16::MAX);
+        let mut conversation_loc = conversation;
+        conversation_loc.height = conversation
+            .height
+            .saturating_sub(overlay_height + u16::from(self.status.is_some()));
+        if self.conversation_cache.is_stale(conversation_loc) {
+            let mut buffer = Buffer::empty(conversation_loc);
+            self.render_conversation(&mut buffer, conversation_loc);
+            self.conversation_cache.store(buffer);
+        }
+        f.render_widget(&self.conversation_cache, conversation_loc);
+
+        if let Some(area) = output {
+            if self.output_cache.is_stale(area) {
+                let mut buffer = Buffer::empty(area);
+                self.render_output(&mut buffer, area);
+                self.output_cache.store(buffer);
+            }
+            f.render_widget(&self.output_cache, area);
+        } else {
+            self.output_cache = Cache::default();
+        }
+
+        let mut overlay_loc = size;
+        overlay_loc.y = size.bottom().saturating_sub(1 + overlay_height);
+        overlay_loc.height = 1;
+        for line in &self.overlay {
+            f.render_widget(Label::default().text(line.as_str()), overlay_loc);
+            overlay_loc.y += 1;
+        }
+
+        self.run_perf(f, size);
 
-        for i in 0..self.input.len() {
-            let label = Label::default().text(&self.input[i]);
-            f.render_widget(label, render_loc);
-            render_loc.y += 1;
+        if let Some(status) = &self.status {
+            let mut status_loc = size;
+            status_loc.y = size.bottom().saturating_sub(1);
+            status_loc.height = 1;
+            f.render_widget(Label::default().text(status.as_str()), status_loc);
         }
-        f.set_cursor(
-            render_loc.x + u16::try_from(self.input.last().unwrap().len()).unwrap(),
-            render_loc.y - 1,
+        self.damaged = false;
+
+        // the cursor is on the line being typed, if it is shown
+        if self.focus == Focus::Conversation && self.conversation_scroll.is_following() {
+            let shown = self
+                .conversation_scroll
+                .window(self.input.len(), self.conversation_height)
+                .len();
+            f.set_cursor(
+                conversation_loc.x + u16::try_from(self.input.last().unwrap().len()).unwrap(),
+                conversation_loc.y + u16::try_from(shown).unwrap().saturating_sub(1),
+            );
+        } else if self.accessible && self.focus == Focus::Conversation {
+            // screen readers read from the cursor, which is at the first line scrolled to
+            f.set_cursor(conversation_loc.x, conversation_loc.y);
+        }
+    }
+
+    /// Render the plan at the top of `size`, if there is one, returning what is left of it.
+    fn run_plan<B: Backend>(&mut self, f: &mut Frame<B>, size: Rect) -> Rect {
+        if self.plan.is_empty() {
+            self.plan_cache = Cache::default();
+            size
+        } else {
+            // the steps, their header and the border below them
+            let height = u16::try_from(self.plan.len() + 2)
+                .unwrap_or(u16::MAX)
+                .min(size.height / PLAN_SHARE);
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(height), Constraint::Min(0)])
+                .split(size);
+            if self.plan_cache.is_stale(areas[0]) {
+                let mut buffer = Buffer::empty(areas[0]);
+                self.render_plan(&mut buffer, areas[0]);
+                self.plan_cache.store(buffer);
+            }
+            f.render_widget(&self.plan_cache, areas[0]);
+            areas[1]
+        }
+    }
+
+    /// Render the sources at the right of `area`, if there are any, returning what is left
+    /// of it.
+    fn run_sources<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) -> Rect {
+        if self.sources.is_empty() {
+            self.sources_cache = Cache::default();
+            area
+        } else {
+            let width = if self.sources_expanded {
+                EXPANDED_SOURCES_WIDTH
+            } else {
+                SOURCES_WIDTH
+            };
+            let areas = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(100 - width),
+                    Constraint::Percentage(width),
+                ])
+                .split(area);
+            if self.sources_cache.is_stale(areas[1]) {
+                let mut buffer = Buffer::empty(areas[1]);
+                self.render_sources(&mut buffer, areas[1]);
+                self.sources_cache.store(buffer);
+            }
+            f.render_widget(&self.sources_cache, areas[1]);
+            areas[0]
+        }
+    }
+
+    /// Render the performance overlay at the top right of `size`, if it is shown.
+    fn run_perf<B: Backend>(&self, f: &mut Frame<B>, size: Rect) {
+        if !self.perf.is_empty() {
+            // drawn over the panes, which draw their cache again once it is hidden
+            let width = self.perf.iter().map(|line| line.chars().count()).max();
+            let width = u16::try_from(width.unwrap_or(0) + 2).unwrap_or(u16::MAX);
+            let height = u16::try_from(self.perf.len() + 2).unwrap_or(u16::MAX);
+            let area = Rect {
+                x: size.right().saturating_sub(width),
+                y: size.y,
+                width: width.min(size.width),
+                height: height.min(size.height),
+            };
+            let lines: Vec<_> = self
+                .perf
+                .iter()
+                .map(|line| Spans::from(line.as_str()))
+                .collect();
+            f.render_widget(Clear, area);
+            f.render_widget(
+                Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("perf")),
+                area,
+            );
+        }
+    }
+
+    fn render_conversation(&mut self, buf: &mut Buffer, area: Rect) {
+        let scrolled = scrolled(&self.conversation_scroll);
+        let mut lines_loc = area;
+        lines_loc.height = area.height.saturating_sub(u16::from(scrolled.is_some()));
+        self.conversation_height = usize::from(lines_loc.height);
+
+        let window = self
+            .conversation_scroll
+            .window(self.input.len(), self.conversation_height);
+        let mut line_loc = lines_loc;
+        line_loc.height = 1;
+        for index in window {
+            let line = if self.accessible {
+                Spans::from(self.input[index].as_str())
+            } else {
+                self.markdown.line(&self.input, index)
+            };
+            Label::default().spans(line).render(line_loc, buf);
+            line_loc.y += 1;
+        }
+
+        if let Some(scrolled) = &scrolled {
+            let mut scrolled_loc = lines_loc;
+            scrolled_loc.y = lines_loc.bottom();
+            scrolled_loc.height = 1;
+            Label::default()
+                .text(scrolled.as_str())
+                .render(scrolled_loc, buf);
+        }
+    }
+
+    fn render_plan(&self, buf: &mut Buffer, area: Rect) {
+        let block = Block::default().borders(Borders::BOTTOM);
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if inner.height == 0 {
+            return;
+        }
+
+        let header = self.plan_progress.as_ref().map_or_else(
+            || "plan".to_string(),
+            |progress| format!("plan · {progress}"),
+        );
+        let mut line_loc = inner;
+        line_loc.height = 1;
+        Paragraph::new(Span::styled(
+            header,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+        .render(line_loc, buf);
+
+        // the current step is kept in view, shown as low as possible
+        let height = usize::from(inner.height - 1);
+        let end = (self.plan_current + 1).max(height).min(self.plan.len());
+        for line in &self.plan[end.saturating_sub(height)..end] {
+            line_loc.y += 1;
+            Label::default().text(line.as_str()).render(line_loc, buf);
+        }
+    }
+
+    fn render_sources(&self, buf: &mut Buffer, area: Rect) {
+        let title = if self.sources_expanded {
+            "sources · /veto <id>"
+        } else {
+            "sources · Ctrl+E to expand"
+        };
+        let block = Block::default().borders(Borders::LEFT).title(Span::styled(
+            title,
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let text = Text::from(self.sources.join("\n"));
+        Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .render(inner, buf);
+    }
+
+    fn render_output(&mut self, buf: &mut Buffer, area: Rect) {
+        let mut title = scrolled(&self.output_scroll).map_or_else(
+            || "output".to_string(),
+            |scrolled| format!("output {scrolled}"),
         );
+        // the focus is only shown in bold otherwise
+        if self.accessible && self.focus == Focus::Output {
+            title.push_str(" (focused)");
+        }
+        let style = if self.focus == Focus::Output {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let block = Block::default()
+            .borders(Borders::LEFT)
+            .title(Span::styled(title, style));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let mut line_loc = inner;
+        line_loc.height = 1;
+        self.output_height = usize::from(inner.height);
+
+        let window = self
+            .output_scroll
+            .window(self.output.len(), self.output_height);
+        for line in &self.output[window] {
+            Label::default().text(line.as_str()).render(line_loc, buf);
+            line_loc.y += 1;
+        }
+    }
+}
+
+/// What a pane shows while it does not follow new lines, `None` while it does.
+fn scrolled(scroll: &Scroll) -> Option<String> {
+    // following is resumed at the bottom, if it is on
+    let follow = if scroll.follows() {
+        "Ctrl+End"
+    } else {
+        "Ctrl+F"
+    };
+    match scroll.below() {
+        0 if scroll.follows() => None,
+        0 => Some(format!("(not following, {follow} to follow)")),
+        below => Some(format!("({below} lines below, {follow} to follow)")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tui::{backend::TestBackend, buffer::Buffer, Terminal};
+
+    use super::Ui;
+    use crate::pane::View;
+
+    fn row(buffer: &Buffer, y: u16) -> String {
+        (0..buffer.area.width)
+            .map(|x| buffer.get(x, y).symbol.as_str())
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn test_damage() -> anyhow::Result<()> {
+        let mut terminal = Terminal::new(TestBackend::new(30, 6))?;
+        let mut ui = Ui::new(false);
+        assert!(ui.needs_draw());
+
+        ui.push_text("> first\nsecond");
+        terminal.draw(|frame| ui.run(frame))?;
+        assert!(!ui.needs_draw());
+
+        // only what changed is damaged
+        ui.set_status(None);
+        ui.pane_line("  built".to_string());
+        assert!(ui.needs_draw());
+        assert!(!ui.conversation_cache.is_damaged());
+
+        // the conversation is drawn from its cache, above the new status line
+        ui.set_status(Some("planning…".to_string()));
+        ui.view(View::Toggle);
+        let buffer = terminal.draw(|frame| ui.run(frame))?.buffer.clone();
+        assert!(!ui.needs_draw());
+        assert_eq!(row(&buffer, 0), "> first        │output");
+        assert_eq!(row(&buffer, 1), "second         │  built");
+        assert!(row(&buffer, 5).starts_with("planning…"));
+
+        // a resized terminal is drawn again completely
+        terminal.backend_mut().resize(20, 4);
+        terminal.resize(tui::layout::Rect::new(0, 0, 20, 4))?;
+        ui.invalidate();
+        let buffer = terminal.draw(|frame| ui.run(frame))?.buffer.clone();
+        assert_eq!(row(&buffer, 1), "second    │  built");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accessible() -> anyhow::Result<()> {
+        let mut terminal = Terminal::new(TestBackend::new(30, 3))?;
+
+        // markdown is shown as it is
+        let mut ui = Ui::new(true);
+        ui.push_text("one\ntwo\nrun `cargo test`\n");
+        let buffer = terminal.draw(|frame| ui.run(frame))?.buffer.clone();
+        assert_eq!(row(&buffer, 1), "run `cargo test`");
+
+        let mut styled = Ui::new(false);
+        styled.push_text("one\ntwo\nrun `cargo test`\n");
+        let buffer = terminal.draw(|frame| styled.run(frame))?.buffer.clone();
+        assert_eq!(row(&buffer, 1), "run cargo test");
+
+        // the cursor stays at the reading position while scrolled back
+        ui.view(View::Up(1));
+        terminal.draw(|frame| ui.run(frame))?;
+        assert_eq!(terminal.get_cursor()?, (0, 0));
+
+        Ok(())
     }
 }
@@ -0,0 +1,139 @@
+/// How many edits can be undone
+const MAX_UNDO: usize = 200;
+
+/// What an edit of the input did, edits of the same kind are undone together.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// a character was typed
+    Insert(char),
+    /// a character was deleted
+    Delete,
+}
+
+/// Undo and redo for the line being composed.
+///
+/// Snapshots of the line are taken before edits. Typing is undone a word at a time and
+/// consecutive deletions are undone together.
+#[derive(Default)]
+pub struct UndoStack {
+    undo: Vec<String>,
+    redo: Vec<String>,
+    /// the previous edit, to decide whether the next one is merged with it
+    last: Option<Edit>,
+}
+
+impl UndoStack {
+    /// Call before `edit` is applied to `line`.
+    pub fn record(&mut self, edit: Edit, line: &str) {
+        let merge = match (self.last, edit) {
+            // a word ends with the whitespace typed after it
+            (Some(Edit::Insert(previous)), Edit::Insert(c)) => {
+                !c.is_whitespace() || previous.is_whitespace()
+            }
+            (Some(Edit::Delete), Edit::Delete) => true,
+            _ => false,
+        };
+
+        self.last = Some(edit);
+        self.redo.clear();
+
+        if merge {
+            return;
+        }
+
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(line.to_string());
+    }
+
+    /// Restore `line` to before the last edit. Returns false if there is nothing to undo.
+    pub fn undo(&mut self, line: &mut String) -> bool {
+        let Some(previous) = self.undo.pop() else {
+            return false;
+        };
+
+        self.redo.push(std::mem::replace(line, previous));
+        self.last = None;
+        true
+    }
+
+    /// Reapply the last undone edit. Returns false if there is nothing to redo.
+    pub fn redo(&mut self, line: &mut String) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+
+        self.undo.push(std::mem::replace(line, next));
+        self.last = None;
+        true
+    }
+
+    /// Forget everything, e.g. because the line was sent.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Edit, UndoStack};
+
+    fn type_str(stack: &mut UndoStack, line: &mut String, text: &str) {
+        for c in text.chars() {
+            stack.record(Edit::Insert(c), line);
+            line.push(c);
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        let mut stack = UndoStack::default();
+        let mut line = String::new();
+
+        type_str(&mut stack, &mut line, "use tokio please");
+
+        assert!(stack.undo(&mut line));
+        assert_eq!(line, "use tokio");
+        assert!(stack.undo(&mut line));
+        assert_eq!(line, "use");
+        assert!(stack.undo(&mut line));
+        assert_eq!(line, "");
+        assert!(!stack.undo(&mut line));
+
+        assert!(stack.redo(&mut line));
+        assert!(stack.redo(&mut line));
+        assert_eq!(line, "use tokio");
+    }
+
+    #[test]
+    fn test_deletions() {
+        let mut stack = UndoStack::default();
+        let mut line = String::new();
+
+        type_str(&mut stack, &mut line, "careful");
+        for _ in 0..4 {
+            stack.record(Edit::Delete, &line);
+            line.pop();
+        }
+        assert_eq!(line, "car");
+
+        // all deletions are undone at once
+        assert!(stack.undo(&mut line));
+        assert_eq!(line, "careful");
+    }
+
+    #[test]
+    fn test_edit_clears_redo() {
+        let mut stack = UndoStack::default();
+        let mut line = String::new();
+
+        type_str(&mut stack, &mut line, "one");
+        stack.undo(&mut line);
+
+        type_str(&mut stack, &mut line, "two");
+
+        assert!(!stack.redo(&mut line));
+        assert_eq!(line, "two");
+    }
+}
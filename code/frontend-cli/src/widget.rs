@@ -1,21 +1,78 @@
 use std::borrow::Cow;
 
-use tui::{buffer::Buffer, layout::Rect, style::Style, widgets::Widget};
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    text::{Span, Spans},
+    widgets::Widget,
+};
 
 #[derive(Default)]
 pub struct Label<'a> {
-    text: Cow<'a, str>,
+    text: Spans<'a>,
 }
 
 impl<'a> Widget for Label<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        buf.set_string(area.left(), area.top(), self.text, Style::default());
+        buf.set_spans(area.left(), area.top(), &self.text, area.width);
     }
 }
 
 impl<'a> Label<'a> {
     pub(crate) fn text(mut self, text: impl Into<Cow<'a, str>>) -> Label<'a> {
-        self.text = text.into();
+        self.text = Spans::from(Span::raw(text));
         self
     }
+
+    /// Styled text, e.g. rendered markdown
+    pub(crate) fn spans(mut self, spans: Spans<'a>) -> Label<'a> {
+        self.text = spans;
+        self
+    }
+}
+
+/// The last rendering of a pane, drawn again as it is while nothing shown in the pane changed.
+#[derive(Default)]
+pub struct Cache {
+    buffer: Option<Buffer>,
+    /// something shown in the pane changed since it was rendered
+    damaged: bool,
+}
+
+impl Cache {
+    pub(crate) fn damage(&mut self) {
+        self.damaged = true;
+    }
+
+    pub(crate) const fn is_damaged(&self) -> bool {
+        self.damaged
+    }
+
+    /// Whether the pane has to be rendered again to be drawn at `area`
+    pub(crate) fn is_stale(&self, area: Rect) -> bool {
+        self.damaged
+            || self
+                .buffer
+                .as_ref()
+                .is_none_or(|buffer| buffer.area != area)
+    }
+
+    pub(crate) fn store(&mut self, buffer: Buffer) {
+        self.buffer = Some(buffer);
+        self.damaged = false;
+    }
+}
+
+impl Widget for &Cache {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let Some(cached) = &self.buffer else {
+            return;
+        };
+        let area = area.intersection(cached.area).intersection(buf.area);
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                *buf.get_mut(x, y) = cached.get(x, y).clone();
+            }
+        }
+    }
 }
@@ -1,8 +1,20 @@
-use anyhow::Context;
+use anyhow::{bail, ensure, Context};
 use derive_build::Build;
 use once_cell::sync::Lazy;
 use regex::Regex;
-use tl::{Node, Parser, ParserOptions};
+use tl::{HTMLTag, Node, Parser, ParserOptions};
+
+mod selector;
+
+/// Which of the elements matching a [`HtmlToMd::selector`] are converted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Matches {
+    /// The first one in the document
+    #[default]
+    First,
+    /// All of them in document order, separated by blank lines
+    All,
+}
 
 #[derive(Build)]
 pub struct HtmlToMd<'a> {
@@ -10,6 +22,11 @@ pub struct HtmlToMd<'a> {
     html: &'a str,
 
     id: Option<&'a str>,
+
+    /// a CSS selector, e.g. `div.content` or `#docs > h2`
+    selector: Option<&'a str>,
+
+    matches: Matches,
 }
 
 impl HtmlToMd<'_> {
@@ -17,27 +34,52 @@ impl HtmlToMd<'_> {
     /// - Failed to parse html
     /// - Failed to find find id
     /// - Failed to parse #{id}
+    /// - Both an id and a selector are given
+    /// - The selector is invalid or matches nothing
     /// - Failed to parse node
     pub fn run(self) -> anyhow::Result<String> {
         let dom = tl::parse(self.html, ParserOptions::default()).context("Failed to parse html")?;
         let parser = dom.parser();
 
         let mut s = String::new();
+        let mut converter = Converter::new(parser);
+
+        ensure!(
+            self.id.is_none() || self.selector.is_none(),
+            "Only one of an id and a selector can be given"
+        );
 
-        match self.id {
-            None => {
+        match (self.id, self.selector) {
+            (None, None) => {
                 for node in dom.children() {
                     let node = node.get(parser).context("Failed to parse node")?;
-                    node_to_md(&mut s, node, parser)?;
+                    converter.node(&mut s, node)?;
                 }
             }
-            Some(id) => {
+            (Some(id), _) => {
                 let parent = dom
                     .get_element_by_id(id)
                     .context("Failed to find find id")?
                     .get(parser)
                     .context("Failed to parse #{id}")?;
-                node_to_md(&mut s, parent, parser)?;
+                converter.node(&mut s, parent)?;
+            }
+            (None, Some(selector)) => {
+                let mut matches = selector::select(&dom, selector)
+                    .with_context(|| format!("`{selector}` is not a valid selector"))?;
+
+                if matches.is_empty() {
+                    bail!("Nothing matches `{selector}`");
+                }
+                if self.matches == Matches::First {
+                    matches.truncate(1);
+                }
+
+                for node in matches {
+                    let node = node.get(parser).context("Failed to parse node")?;
+                    converter.node(&mut s, node)?;
+                    s.push_str("\n\n");
+                }
             }
         }
 
@@ -50,73 +92,265 @@ impl HtmlToMd<'_> {
 
         Ok(s.to_string())
     }
+
+    /// The markdown in chunks of about `max_tokens` tokens, split as by
+    /// [`utils::discretize::markdown`] so code blocks stay whole where possible.
+    ///
+    /// # Errors
+    /// The same as [`HtmlToMd::run`]
+    pub fn chunks(self, max_tokens: usize) -> anyhow::Result<Vec<String>> {
+        Ok(utils::discretize::markdown(&self.run()?, max_tokens))
+    }
 }
 
-fn node_to_md(s: &mut String, node: &Node, parser: &Parser) -> anyhow::Result<()> {
-    match node {
-        Node::Tag(tag) => {
-            tag_to_md(s, tag, parser)?;
-        }
-        Node::Raw(raw) => {
-            raw_to_md(s, raw);
-        }
-        Node::Comment(_) => {}
+/// Start a new line unless `s` is empty or already at the start of one. Trailing spaces, e.g.
+/// the indentation of the HTML, are removed.
+fn new_line(s: &mut String) {
+    s.truncate(s.trim_end_matches([' ', '\t']).len());
+
+    if !s.is_empty() && !s.ends_with('\n') {
+        s.push('\n');
     }
-    Ok(())
 }
 
-fn raw_to_md(s: &mut String, raw: &tl::Bytes) {
-    let raw = raw.as_utf8_str();
-    let raw = raw.as_ref();
-    let raw = raw.replace("&amp;", "&");
-    s.push_str(&raw);
+fn attribute(tag: &HTMLTag, name: &str) -> Option<String> {
+    Some(tag.attributes().get(name)??.as_utf8_str().into_owned())
 }
 
-/// # E
-fn tag_to_md(s: &mut String, tag: &tl::HTMLTag, parser: &Parser) -> anyhow::Result<()> {
-    let name = tag.name().as_utf8_str();
-    let name = name.as_ref();
+/// The language of a code block from a `language-*` or `lang-*` class, as used by highlighters
+fn language(tag: &HTMLTag) -> Option<String> {
+    let mut classes = tag.attributes().class_iter()?;
+    classes
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+        })
+        .map(ToString::to_string)
+}
+
+/// Converts nodes to markdown, keeping track of the lists and code blocks they are in
+struct Converter<'p, 'a> {
+    parser: &'p Parser<'a>,
+    /// the lists the current node is in, innermost last, with the number of the next item of
+    /// ordered lists
+    lists: Vec<Option<usize>>,
+    /// the indentation of the content of the current list item
+    indent: usize,
+    /// inside `<pre>`, where text is kept as is
+    in_code: bool,
+}
+
+impl<'p, 'a> Converter<'p, 'a> {
+    const fn new(parser: &'p Parser<'a>) -> Self {
+        Self {
+            parser,
+            lists: Vec::new(),
+            indent: 0,
+            in_code: false,
+        }
+    }
+
+    fn node(&mut self, s: &mut String, node: &Node) -> anyhow::Result<()> {
+        match node {
+            Node::Tag(tag) => {
+                self.tag(s, tag)?;
+            }
+            Node::Raw(raw) => {
+                raw_to_md(s, raw);
+            }
+            Node::Comment(_) => {}
+        }
+        Ok(())
+    }
+
+    fn children(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        for node in tag.children().top().iter() {
+            let node = node.get(self.parser).context("Failed to parse node")?;
+            self.node(s, node)?;
+        }
+        Ok(())
+    }
+
+    /// The child tags of `tag`, without the text between them
+    fn child_tags(&self, tag: &HTMLTag) -> Vec<&'p HTMLTag<'a>> {
+        tag.children()
+            .top()
+            .iter()
+            .filter_map(|node| node.get(self.parser)?.as_tag())
+            .collect()
+    }
+
+    fn tag(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        let name = tag.name().as_utf8_str();
+        let name = name.as_ref();
+
+        match name {
+            "script" | "style" | "link" | "img" | "meta" | "noscript" | "svg" => return Ok(()),
+            // navigation and page chrome, not content
+            "nav" | "aside" | "footer" => return Ok(()),
+            "a" if !self.in_code => return self.link(s, tag),
+            "ul" => return self.list(s, tag, None),
+            "ol" => {
+                let start = attribute(tag, "start").and_then(|start| start.parse().ok());
+                return self.list(s, tag, Some(start.unwrap_or(1)));
+            }
+            "li" => return self.list_item(s, tag),
+            "pre" => return self.code_block(s, tag),
+            "table" => return self.table(s, tag),
+            _ => {}
+        }
+
+        let prefix = match name {
+            "h1" => "# ",
+            "h2" => "## ",
+            "h3" => "### ",
+            "h4" => "#### ",
+            "h5" => "##### ",
+            _ => "",
+        };
+
+        s.push_str(prefix);
+        self.children(s, tag)
+    }
+
+    /// `[text](href)`, or only the text without a href
+    fn link(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        let mut text = String::new();
+        self.children(&mut text, tag)?;
+
+        match attribute(tag, "href") {
+            Some(href) if !text.trim().is_empty() => {
+                s.push_str(&format!("[{}]({href})", text.trim()));
+            }
+            _ => s.push_str(&text),
+        }
+
+        Ok(())
+    }
 
-    match name {
-        "script" | "style" | "link" | "img" | "meta" => return Ok(()),
-        _ => {}
+    fn list(&mut self, s: &mut String, tag: &HTMLTag, start: Option<usize>) -> anyhow::Result<()> {
+        self.lists.push(start);
+
+        // the whitespace between items would end up in the items
+        let items = self.child_tags(tag);
+        let result = items.into_iter().try_for_each(|item| self.tag(s, item));
+
+        self.lists.pop();
+        new_line(s);
+
+        result
     }
 
-    let prefix = match name {
-        "h1" => "# ",
-        "h2" => "## ",
-        "h3" => "### ",
-        "h4" => "#### ",
-        "h5" => "##### ",
-        "li" | "ol" => "- ",
-        // "tt" if is_rust => "`",
-        "pre" => "```\n",
-        _ => "",
-    };
+    /// `- item` or `1. item`, indented below the item of the enclosing list
+    fn list_item(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        let marker = match self.lists.last_mut() {
+            Some(Some(number)) => {
+                *number += 1;
+                format!("{}. ", *number - 1)
+            }
+            _ => "- ".to_string(),
+        };
 
-    let suffix = match name {
-        // "tt" if is_rust => "`",
-        "pre" => "```",
-        _ => "",
-    };
+        new_line(s);
+        s.push_str(&" ".repeat(self.indent));
+        s.push_str(&marker);
 
-    s.push_str(prefix);
+        let mut item = String::new();
+        self.indent += marker.len();
+        let result = self.children(&mut item, tag);
+        self.indent -= marker.len();
 
-    for node in tag.children().top().iter() {
-        let node = node.get(parser).context("Failed to parse node")?;
-        node_to_md(s, node, parser)?;
+        s.push_str(item.trim());
+        result
     }
 
-    s.push_str(suffix);
+    /// A fence with the language of the `<pre>` or its `<code>`, if it has one
+    fn code_block(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        let language = language(tag)
+            .or_else(|| self.child_tags(tag).into_iter().find_map(language))
+            .unwrap_or_default();
+
+        let mut code = String::new();
+        self.in_code = true;
+        let result = self.children(&mut code, tag);
+        self.in_code = false;
+
+        new_line(s);
+        s.push_str(&format!("```{language}\n{code}"));
+        new_line(s);
+        // the closing fence is alone on its line
+        s.push_str("```\n");
+
+        result
+    }
 
-    Ok(())
+    /// A GitHub-style table, the first row being the header
+    fn table(&mut self, s: &mut String, tag: &HTMLTag) -> anyhow::Result<()> {
+        let mut rows = Vec::new();
+        self.rows(tag, &mut rows)?;
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        if columns == 0 {
+            return Ok(());
+        }
+
+        new_line(s);
+        s.push('\n');
+
+        for (i, mut row) in rows.into_iter().enumerate() {
+            row.resize(columns, String::new());
+            s.push_str(&format!("| {} |\n", row.join(" | ")));
+
+            if i == 0 {
+                s.push_str(&format!("|{}\n", " --- |".repeat(columns)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The cells of the rows in `tag`, looking into `<thead>`, `<tbody>` and `<tfoot>`
+    fn rows(&mut self, tag: &HTMLTag, rows: &mut Vec<Vec<String>>) -> anyhow::Result<()> {
+        for child in self.child_tags(tag) {
+            match child.name().as_utf8_str().as_ref() {
+                "thead" | "tbody" | "tfoot" => self.rows(child, rows)?,
+                "tr" => {
+                    let cells: Vec<_> = self
+                        .child_tags(child)
+                        .into_iter()
+                        .filter(|cell| matches!(cell.name().as_utf8_str().as_ref(), "th" | "td"))
+                        .collect();
+
+                    let mut row = Vec::new();
+                    for cell in cells {
+                        let mut text = String::new();
+                        self.children(&mut text, cell)?;
+                        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                        row.push(text.replace('|', "\\|"));
+                    }
+                    rows.push(row);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn raw_to_md(s: &mut String, raw: &tl::Bytes) {
+    let raw = raw.as_utf8_str();
+    let raw = raw.as_ref();
+    let raw = raw.replace("&amp;", "&");
+    s.push_str(&raw);
 }
 
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
 
-    use crate::HtmlToMd;
+    use crate::{HtmlToMd, Matches};
 
     #[test]
     fn test_html_to_md() -> anyhow::Result<()> {
@@ -127,6 +361,106 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_links() -> anyhow::Result<()> {
+        let html =
+            r#"<p>See <a href="https://docs.rs/tokio">the docs</a> or <a name="top">here</a>.</p>"#;
+        let md = HtmlToMd::new(html).run()?;
+        assert_eq!(md, "See [the docs](https://docs.rs/tokio) or here.");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lists() -> anyhow::Result<()> {
+        let html = r#"<ol start="3">
+            <li>three</li>
+            <li>four
+                <ul>
+                    <li>nested</li>
+                    <li>again<ol><li>deep</li></ol></li>
+                </ul>
+            </li>
+        </ol>
+        <ul><li>bullet</li></ul>"#;
+        let md = HtmlToMd::new(html).run()?;
+        assert_eq!(
+            md,
+            "3. three\n4. four\n   - nested\n   - again\n     1. deep\n\n- bullet"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tables() -> anyhow::Result<()> {
+        let html = "<table>
+            <thead><tr><th>Name</th><th>Kind</th></tr></thead>
+            <tbody>
+                <tr><td><a href=\"fn.spawn.html\">spawn</a></td><td>fn | async</td></tr>
+                <tr><td>Runtime</td></tr>
+            </tbody>
+        </table>";
+        let md = HtmlToMd::new(html).run()?;
+        assert_eq!(
+            md,
+            "| Name | Kind |\n| --- | --- |\n| [spawn](fn.spawn.html) | fn \\| async |\n| Runtime \
+             |  |"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_blocks() -> anyhow::Result<()> {
+        let html = r#"<p>Run:</p><pre class="rust"><code class="language-rust">let x = <a href="i32.html">i32</a>::MAX;</code></pre><pre>plain
+</pre>"#;
+        let md = HtmlToMd::new(html).run()?;
+        assert_eq!(md, "Run:\n```rust\nlet x = i32::MAX;\n```\n```\nplain\n```");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_selector() -> anyhow::Result<()> {
+        let html = "<body><div class=\"sidebar\">Menu</div><div \
+                    class=\"docs\"><h2>First</h2></div><div \
+                    class=\"docs\"><h2>Second</h2></div></body>";
+
+        let md = HtmlToMd::new(html).selector("div.docs").run()?;
+        assert_eq!(md, "## First");
+
+        let md = HtmlToMd::new(html)
+            .selector("body > .docs h2")
+            .matches(Matches::All)
+            .run()?;
+        assert_eq!(md, "## First\n\n## Second");
+
+        assert!(HtmlToMd::new(html).selector("table").run().is_err());
+        assert!(HtmlToMd::new(html)
+            .id("main")
+            .selector("div")
+            .run()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunks() -> anyhow::Result<()> {
+        let html = "<h1>Usage</h1><p>Call it like this:</p><pre><code class=\"language-rust\">fn \
+                    main() {\n\n    run();\n}</code></pre><p>That is all.</p>";
+
+        let chunks = HtmlToMd::new(html).chunks(12)?;
+        assert_eq!(chunks, [
+            "# UsageCall it like this:",
+            "```rust\nfn main() {\n\n    run();\n}\n```",
+            "That is all."
+        ]);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_html_to_md_librs() -> anyhow::Result<()> {
         let req = reqwest::Client::new();
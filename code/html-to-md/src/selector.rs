@@ -0,0 +1,92 @@
+//! CSS selectors with combinators.
+//!
+//! tl matches compound selectors like `div.docs` or `[role=main]`, but none of the combinators
+//! between them. The descendant (`a b`) and child (`a > b`) combinators and selector lists
+//! (`a, b`) are applied here.
+
+use tl::{Node, NodeHandle, VDom};
+
+/// The elements matching `selector` in document order, `None` if it is not a valid selector.
+pub fn select(dom: &VDom, selector: &str) -> Option<Vec<NodeHandle>> {
+    let mut handles = Vec::new();
+    for group in selector.split(',') {
+        handles.extend(select_group(dom, group)?);
+    }
+
+    // handles are numbered in document order
+    handles.sort();
+    handles.dedup();
+    Some(handles)
+}
+
+/// The elements matching `selector`, which has no `,`
+fn select_group(dom: &VDom, selector: &str) -> Option<Vec<NodeHandle>> {
+    let parser = dom.parser();
+    let spaced = selector.replace('>', " > ");
+    let mut parts = spaced.split_whitespace();
+
+    let mut handles: Vec<_> = dom.query_selector(parts.next()?)?.collect();
+    let mut child = false;
+
+    for part in parts {
+        if part == ">" {
+            if child {
+                return None;
+            }
+            child = true;
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for handle in &handles {
+            let Some(tag) = handle.get(parser).and_then(Node::as_tag) else {
+                continue;
+            };
+            let children = tag.children();
+            let is_match = |found: &NodeHandle| !child || children.top().iter().any(|c| c == found);
+            next.extend(tag.query_selector(parser, part)?.filter(is_match));
+        }
+
+        handles = next;
+        child = false;
+    }
+
+    // a trailing `>`
+    (!child).then_some(handles)
+}
+
+#[cfg(test)]
+mod tests {
+    use tl::ParserOptions;
+
+    use super::select;
+
+    #[test]
+    fn test_select() -> anyhow::Result<()> {
+        let html = "<main><p id=\"a\"></p><div><p id=\"b\"></p></div></main><p id=\"c\"></p>";
+        let dom = tl::parse(html, ParserOptions::default())?;
+
+        let ids = |selector| {
+            select(&dom, selector).map(|handles| {
+                handles
+                    .iter()
+                    .filter_map(|handle| handle.get(dom.parser())?.as_tag()?.attributes().id())
+                    .map(|id| id.as_utf8_str().into_owned())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(ids("p").unwrap(), ["a", "b", "c"]);
+        assert_eq!(ids("main p").unwrap(), ["a", "b"]);
+        assert_eq!(ids("main > p").unwrap(), ["a"]);
+        assert_eq!(ids("main>div p, #c").unwrap(), ["b", "c"]);
+        assert_eq!(ids("#c, p").unwrap(), ["a", "b", "c"]);
+        assert!(ids("table p").unwrap().is_empty());
+
+        assert!(ids("main >").is_none());
+        assert!(ids("main > > p").is_none());
+        assert!(ids("").is_none());
+
+        Ok(())
+    }
+}
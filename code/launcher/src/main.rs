@@ -1,20 +1,96 @@
 #![allow(unused)]
 
+use std::path::PathBuf;
+
 use anyhow::ensure;
+use clap::Parser;
 use log::{error, info};
 use tokio::process::Command;
 use tokio_openai::{ChatRequest, Msg};
 
+mod supervise;
+
+#[derive(Parser)]
+#[clap(about)]
+struct Args {
+    /// Docker by default
+    #[clap(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(clap::Subcommand)]
+enum Mode {
+    /// Build the Docker image of the executor
+    Docker,
+    /// Run the executor binary as a child process, restarting it when it crashes
+    Native(supervise::Options),
+    /// Write a session of a stopped executor to a `.collective-session` archive
+    Export {
+        /// The checkpoint of the executor
+        #[clap(long)]
+        checkpoint: PathBuf,
+        /// The audit log of the executor, for the events of the session
+        #[clap(long)]
+        audit_log: Option<PathBuf>,
+        session: protocol::SessionId,
+        /// By default `<session>.collective-session`
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Add the session of an archive to the checkpoint of a stopped executor, which restores it
+    /// when it starts
+    Import {
+        /// The checkpoint of the executor, created if needed
+        #[clap(long)]
+        checkpoint: PathBuf,
+        archive: PathBuf,
+    },
+}
+
 #[tokio::main]
 async fn main() {
     if let Err(e) = run().await {
-        error!("{e}");
+        error!("{e:#}");
     }
 }
 
 async fn run() -> anyhow::Result<()> {
     env_logger::init();
-    build().await?;
+
+    match Args::parse().mode.unwrap_or(Mode::Docker) {
+        Mode::Docker => build().await?,
+        Mode::Native(options) => supervise::run(options).await?,
+        Mode::Export {
+            checkpoint,
+            audit_log,
+            session,
+            output,
+        } => {
+            let output = output.unwrap_or_else(|| {
+                PathBuf::from(format!("{session}.{}", executor::archive::EXTENSION))
+            });
+            let sessions = executor::SessionManager::new();
+            executor::archive::export(
+                &checkpoint,
+                session,
+                &sessions,
+                audit_log.as_deref(),
+                &output,
+            )?;
+            info!("📦 exported session {session} to {}", output.display());
+        }
+        Mode::Import {
+            checkpoint,
+            archive,
+        } => {
+            let sessions = executor::SessionManager::new();
+            let session = executor::archive::import(&archive, &checkpoint, &sessions)?;
+            info!(
+                "📦 imported session {session} into {}",
+                checkpoint.display()
+            );
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,376 @@
+//! Run the executor as a child process where Docker is not available.
+//!
+//! The executor is restarted when it crashes, waiting longer after every crash in a row. Its
+//! output goes to a log file that is rotated once it grows too large. SIGTERM and SIGINT are
+//! forwarded to it as SIGTERM so it shuts down gracefully, SIGHUP so it reloads its config.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use log::{error, info, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+};
+
+/// How long to wait after the first crash
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long to wait at most between crashes
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long the executor has to run for a crash to not count as one in a row
+const STABLE: Duration = Duration::from_secs(60);
+
+/// The name of the log file in the log directory
+const LOG_FILE: &str = "executor.log";
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// The executor binary, by default the one next to the launcher or on the PATH
+    #[clap(long)]
+    executor: Option<PathBuf>,
+
+    /// Directory the output of the executor is logged to
+    #[clap(long, default_value = "logs")]
+    log_dir: PathBuf,
+
+    /// Bytes a log file grows to before it is rotated
+    #[clap(long, default_value = "10485760")]
+    max_log_size: u64,
+
+    /// How many rotated log files are kept
+    #[clap(long, default_value = "5")]
+    max_logs: usize,
+
+    /// Arguments for the executor, after `--`
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+/// How long to wait before restarting the executor
+#[derive(Debug)]
+struct Backoff {
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    /// The executor crashed after running for `ran`, the time to wait until restarting it
+    fn crashed(&mut self, ran: Duration) -> Duration {
+        if ran >= STABLE {
+            self.delay = INITIAL_BACKOFF;
+        }
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+/// A log file that is moved to `executor.log.1` once it is full, the older ones one further
+struct Log {
+    dir: PathBuf,
+    max_size: u64,
+    max_logs: usize,
+    file: File,
+    size: u64,
+}
+
+impl Log {
+    fn open(dir: &Path, max_size: u64, max_logs: usize) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_size,
+            max_logs,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = |index: usize| match index {
+            0 => self.dir.join(LOG_FILE),
+            index => self.dir.join(format!("{LOG_FILE}.{index}")),
+        };
+
+        // the oldest is overwritten
+        for index in (0..self.max_logs).rev() {
+            match std::fs::rename(path(index), path(index + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.max_logs == 0 {
+            std::fs::remove_file(path(0))?;
+        }
+
+        self.file = File::create(path(0))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Run the executor until the launcher is asked to stop or the executor exits successfully,
+/// restarting it when it crashes.
+///
+/// # Errors
+/// If the log cannot be opened, the executor cannot be started or signals cannot be listened for
+pub async fn run(options: Options) -> anyhow::Result<()> {
+    let program = options.executor.clone().unwrap_or_else(executor);
+    let log = Log::open(&options.log_dir, options.max_log_size, options.max_logs)
+        .with_context(|| format!("Failed to open the log in {}", options.log_dir.display()))?;
+    let log = Arc::new(Mutex::new(log));
+    let mut signals = Signals::new()?;
+    let mut backoff = Backoff::default();
+
+    loop {
+        let mut child = Command::new(&program)
+            .args(&options.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", program.display()))?;
+        info!(
+            "🚀 started the executor (pid {})",
+            child.id().unwrap_or_default()
+        );
+
+        let started = Instant::now();
+        let pumps = [
+            child
+                .stdout
+                .take()
+                .map(|out| tokio::spawn(pump(out, log.clone()))),
+            child
+                .stderr
+                .take()
+                .map(|out| tokio::spawn(pump(out, log.clone()))),
+        ];
+
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status?,
+                signal = signals.recv() => match signal {
+                    Signal::Stop => return stop(&mut child).await,
+                    Signal::Reload => forward(&child, Signal::Reload),
+                },
+            }
+        };
+        // the rest of its output
+        for pump in pumps.into_iter().flatten() {
+            let _ = pump.await;
+        }
+
+        if status.success() {
+            info!("the executor exited");
+            return Ok(());
+        }
+
+        let delay = backoff.crashed(started.elapsed());
+        error!("the executor failed with {status}, restarting it in {delay:?}");
+        let restart = tokio::time::sleep(delay);
+        tokio::pin!(restart);
+        loop {
+            tokio::select! {
+                () = &mut restart => break,
+                signal = signals.recv() => if signal == Signal::Stop {
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+/// The executor next to the launcher, else the one on the PATH
+fn executor() -> PathBuf {
+    let name = format!("executor{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .map(|launcher| launcher.with_file_name(&name))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Write the lines of `out` to `log`.
+async fn pump(out: impl AsyncRead + Unpin, log: Arc<Mutex<Log>>) {
+    let mut lines = BufReader::new(out).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let written = log
+            .lock()
+            .map_err(|_| io::Error::other("the log was poisoned"))
+            .and_then(|mut log| log.write_line(&line));
+        if let Err(e) = written {
+            warn!("failed to log the output of the executor: {e}");
+        }
+    }
+}
+
+/// Let the executor shut down gracefully and wait for it.
+async fn stop(child: &mut Child) -> anyhow::Result<()> {
+    info!("🛑 stopping the executor");
+    #[cfg(unix)]
+    forward(child, Signal::Stop);
+    // without signals it is killed instead
+    #[cfg(not(unix))]
+    child.start_kill()?;
+    let status = child.wait().await?;
+    info!("the executor stopped: {status}");
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    /// SIGTERM or SIGINT, or Ctrl-C where there are no signals
+    Stop,
+    /// SIGHUP
+    Reload,
+}
+
+#[cfg(unix)]
+struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> anyhow::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.terminate.recv() => Signal::Stop,
+            _ = self.interrupt.recv() => Signal::Stop,
+            _ = self.hangup.recv() => Signal::Reload,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn forward(child: &Child, signal: Signal) {
+    let Some(pid) = child.id().and_then(|pid| i32::try_from(pid).ok()) else {
+        return;
+    };
+    let signal = match signal {
+        Signal::Stop => libc::SIGTERM,
+        Signal::Reload => libc::SIGHUP,
+    };
+    // SAFETY: sending a signal to the child has no memory effects
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        warn!(
+            "failed to signal the executor: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    #[allow(clippy::unnecessary_wraps)]
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> Signal {
+        let _ = tokio::signal::ctrl_c().await;
+        Signal::Stop
+    }
+}
+
+#[cfg(not(unix))]
+fn forward(_child: &Child, _signal: Signal) {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Backoff, Log, LOG_FILE, MAX_BACKOFF};
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let quickly = Duration::from_secs(1);
+
+        assert_eq!(backoff.crashed(quickly), Duration::from_secs(1));
+        assert_eq!(backoff.crashed(quickly), Duration::from_secs(2));
+        assert_eq!(backoff.crashed(quickly), Duration::from_secs(4));
+        for _ in 0..10 {
+            backoff.crashed(quickly);
+        }
+        assert_eq!(backoff.crashed(quickly), MAX_BACKOFF);
+
+        // it ran fine for a while
+        assert_eq!(
+            backoff.crashed(Duration::from_secs(600)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_rotate() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = Log::open(dir.path(), 10, 2)?;
+
+        for line in ["first", "second", "third", "fourth"] {
+            log.write_line(line)?;
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name));
+        assert_eq!(read(LOG_FILE)?, "fourth\n");
+        assert_eq!(read(&format!("{LOG_FILE}.1"))?, "third\n");
+        assert_eq!(read(&format!("{LOG_FILE}.2"))?, "second\n");
+        // older ones are dropped
+        assert!(read(&format!("{LOG_FILE}.3")).is_err());
+
+        // appended to after a restart
+        let mut log = Log::open(dir.path(), 100, 2)?;
+        log.write_line("fifth")?;
+        assert_eq!(read(LOG_FILE)?, "fourth\nfifth\n");
+
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use derive_discriminant::Discriminant;
 use serde::{Deserialize, Serialize};
 
+use crate::{Resolution, SessionId};
+
 #[derive(Discriminant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Client {
@@ -8,6 +10,79 @@ pub enum Client {
     Instruction { instruction: String },
     /// Answer a question.
     Answer { answer: String },
+    /// Stop generating the current question and generate it again.
+    Regenerate,
+    /// Stop generating the current question.
+    ///
+    /// The packet that triggered the question is reverted: a cancelled answer can be given again
+    /// and a cancelled instruction ends the session. The executor responds with a
+    /// [`Server::Cancelled`](crate::server::Server::Cancelled).
+    Cancel,
+    /// Execute the plan for the instruction. Only one plan can be executed at a time.
+    Execute,
+    /// Keepalive. The executor responds with a [`Server::Pong`](crate::server::Server::Pong).
+    Ping,
+    /// Resume a session after the connection dropped.
+    ///
+    /// The frontend replays everything it knows about the session so the executor can rebuild
+    /// the question-answer state without asking the model again. A session the executor still
+    /// knows is only taken over with the `key` of its
+    /// [`Server::SessionCreated`](crate::server::Server::SessionCreated), otherwise the replay is
+    /// rebuilt as a new session.
+    Resume {
+        session: SessionId,
+        key: String,
+        instruction: String,
+        questions: Vec<String>,
+        answers: Vec<String>,
+    },
+    /// List the remembered preferences. The executor responds with a
+    /// [`Server::Memory`](crate::server::Server::Memory).
+    ListMemory,
+    /// Remember a preference for all future sessions.
+    Remember { preference: String },
+    /// Forget a remembered preference.
+    Forget { id: u64 },
+    /// Answer a [`Server::ConfirmCommand`](crate::server::Server::ConfirmCommand). A command
+    /// that is not approved fails its step.
+    Confirm { approved: bool },
+    /// Ask for the latencies of the session. The executor responds with a
+    /// [`Server::Latency`](crate::server::Server::Latency).
+    Latency,
+    /// Answer the question `id` of a [`Server::Questions`](crate::server::Server::Questions).
+    /// Questions can be answered in any order and answered again until all are answered, then
+    /// the executor asks the next question.
+    AnswerTo { id: u64, answer: String },
+    /// List the sessions of the executor. The executor responds with a
+    /// [`Server::Sessions`](crate::server::Server::Sessions).
+    ListSessions,
+    /// Work on a clone of the git repository at `url` instead of an empty working directory,
+    /// checking out `branch` or the default branch. Only before the instruction. The executor
+    /// responds with a [`Server::Cloned`](crate::server::Server::Cloned) followed by the
+    /// [`Server::WorkspaceInfo`](crate::server::Server::WorkspaceInfo) of the clone.
+    CloneRepo { url: String, branch: Option<String> },
+    /// Answer a [`Server::FileConflict`](crate::server::Server::FileConflict). The execution
+    /// continues once the conflict is resolved.
+    ResolveConflict { resolution: Resolution },
+    /// Drop the source of a [`Server::SourcePreview`](crate::server::Server::SourcePreview) from
+    /// what the next steps of the plan see. Only while the plan is executed.
+    VetoSource { id: u64 },
+    /// Undo the last instruction or execution of a plan, whichever came last. The executor
+    /// responds with a [`Server::Reverted`](crate::server::Server::Reverted). Not while a plan is
+    /// executed.
+    RevertLast,
+    /// Run only `steps` of the last plan that was executed, in the order of the plan, e.g. to run
+    /// the steps after a failed one again or to skip a deployment. Steps are numbered from 0, as
+    /// in [`Server::StepStarted`](crate::server::Server::StepStarted). A step whose dependency is
+    /// skipped still runs, after a [`Server::Notice`](crate::server::Server::Notice) warning
+    /// about it.
+    ExecuteSteps { steps: Vec<usize> },
+    /// Generate the code in the directory `path` of the machine of the executor instead of an
+    /// empty working directory, creating it if it does not exist. It has to be absolute. Only
+    /// before the instruction. The executor responds with a
+    /// [`Server::WorkspaceReady`](crate::server::Server::WorkspaceReady) followed by the
+    /// [`Server::WorkspaceInfo`](crate::server::Server::WorkspaceInfo) of the directory.
+    SetWorkspace { path: String },
 }
 
 impl From<Instruction> for String {
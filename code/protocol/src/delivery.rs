@@ -0,0 +1,89 @@
+//! At-least-once delivery of packets across failed sends and reconnects.
+//!
+//! A packet whose sending failed is sent again with the same [`PacketId`], on the same connection
+//! if the failure was transient and after reconnecting otherwise. The id is its idempotency key:
+//! receivers remember the ids they handled in [`Delivered`] and drop a packet they already
+//! handled, so sending it again never duplicates its effects, e.g. an answer or an approval.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+    time::Duration,
+};
+
+use crate::PacketId;
+
+/// How often a packet is sent on the same connection before the connection is given up
+pub const SEND_ATTEMPTS: u32 = 3;
+
+/// The delay before sending a packet again, multiplied by the number of failed attempts
+pub const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How many ids of handled packets are remembered
+const WINDOW: usize = 1024;
+
+/// Whether sending failed with an IO error the connection can recover from, so the packet can
+/// be sent again on it
+#[must_use]
+pub fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+    )
+}
+
+/// The ids of the packets handled last
+#[derive(Debug, Default, Clone)]
+pub struct Delivered {
+    order: VecDeque<PacketId>,
+    ids: HashSet<PacketId>,
+}
+
+impl Delivered {
+    /// Remember that the packet `id` is handled. Returns `false` if it was handled before.
+    pub fn first(&mut self, id: PacketId) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > WINDOW {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use uuid::Uuid;
+
+    use super::{is_transient, Delivered, WINDOW};
+
+    #[test]
+    fn test_delivered() {
+        let mut delivered = Delivered::default();
+        let id = Uuid::new_v4();
+        assert!(delivered.first(id));
+        assert!(!delivered.first(id));
+        assert!(delivered.first(Uuid::new_v4()));
+
+        // the oldest ids are forgotten
+        for _ in 0..WINDOW {
+            delivered.first(Uuid::new_v4());
+        }
+        assert!(delivered.first(id));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(io::ErrorKind::WouldBlock));
+        assert!(is_transient(io::ErrorKind::TimedOut));
+        assert!(!is_transient(io::ErrorKind::ConnectionReset));
+        assert!(!is_transient(io::ErrorKind::BrokenPipe));
+    }
+}
@@ -1,19 +1,295 @@
 #![feature(unsize)]
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::{client::Client, server::Server};
 
 pub mod client;
+pub mod delivery;
 pub mod server;
+pub mod trace;
+pub mod vectors;
+
+/// The version of the packet format. Bumped whenever the serialized form of an existing packet
+/// changes, see [`vectors`].
+pub const PROTOCOL_VERSION: u32 = 4;
 
 pub type PacketId = Uuid;
 
+/// Identifies a question-answer session across reconnects.
+pub type SessionId = Uuid;
+
+/// A part of a text streamed in several packets, e.g. a question as the model writes it.
+///
+/// Frames of one stream share an `id` and are numbered by `seq`, so a receiver can tell
+/// interleaved streams apart, put frames back in order and notice lost ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamFrame {
+    /// unique among the streams of a connection
+    pub id: u64,
+    /// the position of the frame in its stream, starting at 0
+    pub seq: u64,
+    pub payload: String,
+    /// whether this is the last frame of the stream
+    pub end: bool,
+}
+
+/// One of several questions asked at once, see [`Server::Questions`](server::Server::Questions)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuestionItem {
+    /// unique within the session, answers refer to it
+    pub id: u64,
+    pub question: String,
+    /// the format the answer must have, if any
+    pub validator: Option<Validator>,
+}
+
+/// A session the executor knows, see [`server::Server::Sessions`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub id: SessionId,
+    /// whether a connection is driving the session, detached sessions can be resumed
+    pub attached: bool,
+}
+
+/// A durable preference of the user, remembered across sessions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemoryEntry {
+    pub id: u64,
+    pub preference: String,
+}
+
+/// How many files of a language are in the workspace
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LanguageStats {
+    pub language: String,
+    pub files: usize,
+}
+
+/// Percentiles of a latency, in milliseconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// what was measured, e.g. `executor → provider`
+    pub name: String,
+    pub count: usize,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+/// What the requests to the provider for one purpose cost, with one model. Tokens are estimated
+/// from the length of the text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UsageStats {
+    /// what the requests were for, e.g. `step 3` or `question 2`
+    pub purpose: String,
+    /// the model they were sent to, e.g. `gpt-4`
+    pub model: String,
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// in millionths of a US dollar, at the list prices of the model
+    pub micro_usd: u64,
+}
+
+impl std::fmt::Display for UsageStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tokens = self.prompt_tokens + self.completion_tokens;
+        let cents = self.micro_usd / 10_000;
+        write!(f, "{} cost ", self.purpose)?;
+        if tokens < 1000 {
+            write!(f, "{tokens}")?;
+        } else {
+            write!(f, "{}k", tokens / 1000)?;
+        }
+        write!(
+            f,
+            " tokens (${}.{:02}) on {}",
+            cents / 100,
+            cents % 100,
+            self.model
+        )
+    }
+}
+
+/// Why a command failed, as classified by the model
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// a crate, package or program is not installed
+    MissingDependency,
+    /// the code or script does not parse or compile
+    Syntax,
+    Permissions,
+    /// likely to succeed when retried, e.g. a network error
+    Flaky,
+    Other,
+}
+
+impl std::fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingDependency => "missing dependency",
+            Self::Syntax => "syntax error",
+            Self::Permissions => "permissions",
+            Self::Flaky => "flaky",
+            Self::Other => "other",
+        })
+    }
+}
+
+/// How the user resolved a [`server::FileConflict`]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// keep the file as the user changed it, dropping what was generated
+    Keep,
+    /// replace the changes of the user with what was generated
+    Overwrite,
+    /// the user removed the conflict markers from the file, which is kept as it is
+    Resolved,
+}
+
+/// Why a command needs to be confirmed before it runs
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Risk {
+    /// deletes or overwrites data, e.g. `rm -rf`
+    Destructive,
+    /// runs with elevated privileges, e.g. `sudo`
+    Privileged,
+    /// talks to the network, e.g. `curl`
+    Network,
+    /// records the changes in version control, e.g. `git commit`
+    Commit,
+}
+
+impl std::fmt::Display for Risk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Destructive => "destructive",
+            Self::Privileged => "privileged",
+            Self::Network => "network",
+            Self::Commit => "commit",
+        })
+    }
+}
+
+/// What the executor is doing in a session
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// generating the next question
+    Asking,
+    /// waiting for an instruction, an answer or a command from the user
+    Waiting,
+    /// generating the plan
+    Planning,
+    /// running the steps of the plan
+    Executing,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Asking => "asking",
+            Self::Waiting => "waiting",
+            Self::Planning => "planning",
+            Self::Executing => "executing",
+        })
+    }
+}
+
+/// What a valid answer to a question looks like. The frontend checks answers before sending them
+/// and the executor checks them again when they arrive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Validator {
+    /// The whole answer matches `pattern`, e.g. a crate name
+    Regex {
+        pattern: String,
+        /// what the pattern matches, shown to the user
+        description: String,
+    },
+    /// One of `options`, ignoring case
+    Enum { options: Vec<String> },
+    /// An integer from `min` to `max`, inclusive
+    Range { min: i64, max: i64 },
+}
+
+/// An answer rejected by a [`Validator`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidAnswer {
+    pub answer: String,
+    /// what was expected, the [`Display`](std::fmt::Display) of the validator
+    pub expected: String,
+}
+
+impl std::fmt::Display for InvalidAnswer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is not {}", self.answer, self.expected)
+    }
+}
+
+impl std::error::Error for InvalidAnswer {}
+
+impl std::fmt::Display for Validator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Regex { description, .. } => f.write_str(description),
+            Self::Enum { options } => write!(f, "one of {}", options.join(", ")),
+            Self::Range { min, max } => write!(f, "a number from {min} to {max}"),
+        }
+    }
+}
+
+impl Validator {
+    /// Check `answer`, ignoring surrounding whitespace. A pattern that is not a valid regex
+    /// accepts every answer, the user should not be stuck because of it.
+    ///
+    /// # Errors
+    /// If the answer does not have the expected format.
+    pub fn check(&self, answer: &str) -> Result<(), InvalidAnswer> {
+        let answer = answer.trim();
+
+        let valid = match self {
+            Self::Regex { pattern, .. } => regex::Regex::new(&format!("^(?:{pattern})$"))
+                .map_or(true, |regex| regex.is_match(answer)),
+            Self::Enum { options } => options
+                .iter()
+                .any(|option| option.eq_ignore_ascii_case(answer)),
+            Self::Range { min, max } => answer
+                .parse::<i64>()
+                .is_ok_and(|number| (*min..=*max).contains(&number)),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(InvalidAnswer {
+                answer: answer.to_string(),
+                expected: self.to_string(),
+            })
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Packet<T> {
     pub id: PacketId,
     pub data: T,
+    /// timestamps for measuring latency, see [`trace`]
+    #[serde(default)]
+    pub trace: Option<trace::Trace>,
+}
+
+/// The checksum used to validate streamed content: the hex encoded SHA-256 of `content`.
+#[must_use]
+pub fn checksum(content: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(content))
 }
 
 pub type ClientPacket = Packet<Client>;
@@ -24,10 +300,43 @@ impl<T> Packet<T> {
         Self {
             id: Uuid::new_v4(),
             data,
+            trace: None,
         }
     }
 }
 
+impl<T: Serialize> Packet<T> {
+    /// The packet as compact JSON, which is what is sent over the websocket.
+    ///
+    /// # Errors
+    /// If the packet cannot be serialized.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// The packet as bincode with the default options.
+    ///
+    /// # Errors
+    /// If the packet cannot be serialized.
+    pub fn to_binary(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+}
+
+impl<T: DeserializeOwned> Packet<T> {
+    /// # Errors
+    /// If `json` is not a packet.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// # Errors
+    /// If `bytes` is not a packet.
+    pub fn from_binary(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
+
 impl Packet<Server> {
     pub fn server(data: impl Into<Server>) -> Self {
         Self::new(data.into())
@@ -39,3 +348,57 @@ impl Packet<Client> {
         Self::new(data.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{UsageStats, Validator};
+
+    #[test]
+    fn test_validator() {
+        let port = Validator::Range { min: 1, max: 65535 };
+        assert!(port.check(" 8080 ").is_ok());
+        assert!(port.check("0").is_err());
+        assert_eq!(
+            port.check("eighty").unwrap_err().to_string(),
+            "`eighty` is not a number from 1 to 65535"
+        );
+
+        let answer = Validator::Enum {
+            options: vec!["yes".to_string(), "no".to_string()],
+        };
+        assert!(answer.check("Yes").is_ok());
+        assert!(answer.check("maybe").is_err());
+
+        let name = Validator::Regex {
+            pattern: "[a-z]+|[A-Z]+".to_string(),
+            description: "a name".to_string(),
+        };
+        assert!(name.check("serde").is_ok());
+        // the whole answer has to match every alternative
+        assert!(name.check("serde JSON").is_err());
+
+        let broken = Validator::Regex {
+            pattern: "(".to_string(),
+            description: "anything".to_string(),
+        };
+        assert!(broken.check("whatever").is_ok());
+    }
+
+    #[test]
+    fn test_usage() {
+        let mut usage = UsageStats {
+            purpose: "step 3".to_string(),
+            model: "gpt-4".to_string(),
+            requests: 2,
+            prompt_tokens: 9000,
+            completion_tokens: 3400,
+            micro_usd: 474_000,
+        };
+        assert_eq!(usage.to_string(), "step 3 cost 12k tokens ($0.47) on gpt-4");
+
+        usage.prompt_tokens = 500;
+        usage.completion_tokens = 20;
+        usage.micro_usd = 16_200;
+        assert_eq!(usage.to_string(), "step 3 cost 520 tokens ($0.01) on gpt-4");
+    }
+}
@@ -1,12 +1,216 @@
 use derive_discriminant::Discriminant;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    FailureKind, LanguageStats, LatencyStats, MemoryEntry, Phase, QuestionItem, Risk, SessionId,
+    SessionSummary, StreamFrame, UsageStats, Validator,
+};
+
 #[derive(Discriminant)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Server {
+    /// The session driven by the connection. Sent as the first packet of every connection and
+    /// again after a [`Client::Resume`](crate::client::Client::Resume), which may switch to the
+    /// resumed session. Only the connection driving the session gets its `key`, which resuming it
+    /// takes.
+    SessionCreated {
+        id: SessionId,
+        key: String,
+    },
+    /// A frame of the next question. Every question is its own stream.
     Question {
-        question: String,
-        is_first_word: bool,
-        is_last_word: bool,
+        frame: StreamFrame,
+    },
+    /// Complete lines of a file that is being written. Chunks are numbered from 0 so the
+    /// frontend can render the file before it is written completely.
+    FileChunk {
+        path: String,
+        seq: u64,
+        content: String,
+    },
+    /// A file was written completely. `checksum` is the [`checksum`](crate::checksum) of the whole
+    /// content.
+    FileWritten {
+        path: String,
+        checksum: String,
+    },
+    /// Writing a file failed, the file was not changed.
+    FileFailed {
+        path: String,
+        reason: String,
+    },
+    /// The current question was cancelled by a
+    /// [`Client::Cancel`](crate::client::Client::Cancel).
+    Cancelled,
+    /// A packet from the frontend was not processed because it is not allowed in the current
+    /// state of the session, e.g. an answer before any instruction.
+    Rejected {
+        reason: String,
+    },
+    /// Response to a [`Client::Ping`](crate::client::Client::Ping).
+    Pong,
+    /// A step of the plan started. Steps are numbered from 0.
+    StepStarted {
+        index: usize,
+        title: String,
+    },
+    /// Output of a step as it is produced, e.g. a line the script wrote to stdout or stderr.
+    StepOutput {
+        index: usize,
+        output: String,
+    },
+    /// Why a step failed and what would fix it. Sent before the [`Server::StepFinished`] of a
+    /// failed step, the frontend can retry with a
+    /// [`Client::Execute`](crate::client::Client::Execute).
+    StepDiagnosed {
+        index: usize,
+        kind: FailureKind,
+        explanation: String,
+        remediation: String,
+    },
+    StepFinished {
+        index: usize,
+        success: bool,
+    },
+    /// The plan was executed, stopped at a failed step or was cancelled.
+    ExecutionFinished {
+        success: bool,
+    },
+    /// The remembered preferences, sent when they were listed or changed. `enabled` is false if
+    /// the executor runs without memory.
+    Memory {
+        enabled: bool,
+        entries: Vec<MemoryEntry>,
+    },
+    /// A message for the user outside the conversation, e.g. that the prompts were reloaded.
+    Notice {
+        message: String,
+    },
+    /// A step wants to run a risky command. It only runs after a
+    /// [`Client::Confirm`](crate::client::Client::Confirm) approving it.
+    ConfirmCommand {
+        command: String,
+        risk: Risk,
+    },
+    /// What the working directory of the session contains, sent after
+    /// [`Server::SessionCreated`]. Languages are sorted by their number of files.
+    WorkspaceInfo {
+        languages: Vec<LanguageStats>,
+        frameworks: Vec<String>,
+        build_tools: Vec<String>,
+    },
+    /// The executor is shutting down. Running executions get `grace_secs` seconds to finish,
+    /// after which the connection is closed. The session is saved, so it can be resumed once the
+    /// executor is back.
+    ShuttingDown {
+        grace_secs: u64,
+    },
+    /// The latencies the executor measured in this session
+    Latency {
+        latencies: Vec<LatencyStats>,
+    },
+    /// The format the answer to the question that was just asked must have. Sent after the
+    /// last word of the question, only for questions expecting a specific format.
+    AnswerFormat {
+        validator: Validator,
+    },
+    /// Part of the plan as the model writes it, sent before its steps run. The plan is written
+    /// again if it was invalid.
+    PlanChunk {
+        content: String,
+    },
+    /// The session entered `phase`.
+    Status {
+        phase: Phase,
+    },
+    /// Something went wrong that the frontend should show. If it is `recoverable`, the session
+    /// continues and the packet that caused it can be sent again, otherwise the connection is
+    /// closed.
+    Error {
+        message: String,
+        recoverable: bool,
+    },
+    /// Questions that can be answered independently of each other, asked at once instead of one
+    /// by one. They are answered with [`Client::AnswerTo`](crate::client::Client::AnswerTo).
+    Questions {
+        items: Vec<QuestionItem>,
+    },
+    /// The sessions of the executor, sorted by id, sent when they were listed
+    Sessions {
+        sessions: Vec<SessionSummary>,
+    },
+    /// The repository of a [`Client::CloneRepo`](crate::client::Client::CloneRepo) was cloned
+    /// into the working directory, with `branch` checked out at the commit `head`.
+    Cloned {
+        url: String,
+        branch: String,
+        head: String,
+    },
+    /// A file was changed on disk while the executor generated it, and the changes could not be
+    /// merged. The file now has `conflicts` conflicts between git style markers, and the
+    /// execution waits for a [`Client::ResolveConflict`](crate::client::Client::ResolveConflict).
+    FileConflict {
+        path: String,
+        conflicts: usize,
+    },
+    /// A step read external material the next steps draw on, e.g. the documentation of a crate.
+    /// It can be dropped from what they see with a
+    /// [`Client::VetoSource`](crate::client::Client::VetoSource).
+    SourcePreview {
+        id: u64,
+        title: String,
+        /// the first paragraphs
+        summary: String,
+        url: String,
+    },
+    /// The answer to the last question is a secret, e.g. a token. The frontend masks it while it
+    /// is typed and keeps it out of its history. The executor only passes it to commands.
+    SecretAnswer,
+    /// The other questions the model considered for the last [`Server::Question`], less
+    /// informative than the one asked. Frontends may show them or ignore them.
+    QuestionAlternatives {
+        alternatives: Vec<String>,
+    },
+    /// The next [`Server::Question`] clarifies the last answer, which was ambiguous or
+    /// contradicted an earlier one.
+    FollowUp,
+    /// The documentation of `name`, a crate or an item, was fetched before the step reading it
+    /// runs, as the `fetched`th of the `total` the plan reads.
+    DocsFetched {
+        name: String,
+        fetched: usize,
+        total: usize,
+        success: bool,
+    },
+    /// The last instruction or execution was undone after a [`Client::RevertLast`].
+    /// - `instruction`: whether the interview about the last instruction was dropped, the session
+    ///   is back at the one before it. Otherwise the files the plan changed were put back.
+    /// - `summary`: what was undone, for the user
+    ///
+    /// [`Client::RevertLast`]: crate::client::Client::RevertLast
+    Reverted {
+        instruction: bool,
+        summary: String,
+    },
+    /// The directory of a [`Client::SetWorkspace`](crate::client::Client::SetWorkspace) is the
+    /// working directory of the session from now on. `path` is where it resolved to, with
+    /// symlinks followed.
+    WorkspaceReady {
+        path: String,
+    },
+    /// The file step `index` generated at `path` was checked with `cargo check` after it was
+    /// generated the `iteration`th of at most `iterations` times, counting from 1. While it has
+    /// `errors`, it is generated again with them until the last iteration.
+    CompileChecked {
+        index: usize,
+        path: String,
+        iteration: usize,
+        iterations: usize,
+        errors: usize,
+    },
+    /// What the requests of the session to the provider cost so far, by what they were for and
+    /// the model they were sent to. Sent before every [`Server::ExecutionFinished`].
+    Usage {
+        usage: Vec<UsageStats>,
     },
 }
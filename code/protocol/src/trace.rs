@@ -0,0 +1,191 @@
+//! Timing annotations for measuring latency end to end.
+//!
+//! Both sides stamp every packet they send with a [`Trace`] and echo the time they received the
+//! last packet of the other side. From the echo, the original sender gets the round trip without
+//! the time the other side held the packet, which does not depend on the clocks agreeing. The
+//! one-way latency compares the clocks of both sides and is only exact on one machine.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LatencyStats, Packet, PacketId};
+
+/// How many samples a [`Distribution`] keeps
+const MAX_SAMPLES: usize = 1000;
+
+/// When a packet was sent, in milliseconds since the unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub sent_ms: u64,
+    /// the last packet received from the other side, if it was not echoed yet
+    pub echo: Option<Echo>,
+}
+
+/// A received packet and its timestamps
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Echo {
+    pub id: PacketId,
+    /// by the clock of the side that sent it
+    pub sent_ms: u64,
+    /// by the clock of the side that received it
+    pub received_ms: u64,
+}
+
+#[must_use]
+pub fn now_ms() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// The most recent samples of a latency
+#[derive(Debug, Default, Clone)]
+pub struct Distribution {
+    samples: VecDeque<u64>,
+}
+
+impl Distribution {
+    pub fn record(&mut self, latency: Duration) {
+        self.record_ms(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX));
+    }
+
+    pub fn record_ms(&mut self, ms: u64) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    /// The percentiles of the samples, `None` without samples
+    #[must_use]
+    pub fn stats(&self, name: &str) -> Option<LatencyStats> {
+        let mut samples: Vec<_> = self.samples.iter().copied().collect();
+        samples.sort_unstable();
+        let max_ms = *samples.last()?;
+
+        let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+
+        Some(LatencyStats {
+            name: name.to_string(),
+            count: samples.len(),
+            p50_ms: percentile(50),
+            p90_ms: percentile(90),
+            p99_ms: percentile(99),
+            max_ms,
+        })
+    }
+}
+
+/// Stamps the packets one side sends and measures the packets it receives
+#[derive(Debug, Default)]
+pub struct Tracer {
+    /// the last packet received, echoed with the next packet sent
+    last: Option<Echo>,
+    /// from the other side sending a packet to it arriving, by the clocks of both sides
+    pub one_way: Distribution,
+    /// from sending a packet to its echo arriving, without the time the other side held it
+    pub round_trip: Distribution,
+}
+
+impl Tracer {
+    /// Stamp `packet` before it is sent.
+    #[must_use]
+    pub fn stamp<T>(&mut self, mut packet: Packet<T>) -> Packet<T> {
+        packet.trace = Some(Trace {
+            sent_ms: now_ms(),
+            echo: self.last.take(),
+        });
+        packet
+    }
+
+    /// Measure `packet` once it arrived. Packets without a trace are ignored.
+    pub fn received<T>(&mut self, packet: &Packet<T>) {
+        self.received_at(packet, now_ms());
+    }
+
+    fn received_at<T>(&mut self, packet: &Packet<T>, now: u64) {
+        let Some(trace) = &packet.trace else {
+            return;
+        };
+
+        self.one_way.record_ms(now.saturating_sub(trace.sent_ms));
+
+        if let Some(echo) = &trace.echo {
+            let total = now.saturating_sub(echo.sent_ms);
+            let held = trace.sent_ms.saturating_sub(echo.received_ms);
+            self.round_trip.record_ms(total.saturating_sub(held));
+        }
+
+        self.last = Some(Echo {
+            id: packet.id,
+            sent_ms: trace.sent_ms,
+            received_ms: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Distribution, Echo, Trace, Tracer};
+    use crate::{client, server, Packet};
+
+    #[test]
+    fn test_distribution() {
+        let mut distribution = Distribution::default();
+        assert!(distribution.stats("empty").is_none());
+
+        for ms in 1..=100 {
+            distribution.record_ms(ms);
+        }
+
+        let stats = distribution.stats("rtt").unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50_ms, 50);
+        assert_eq!(stats.p90_ms, 90);
+        assert_eq!(stats.max_ms, 100);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut tracer = Tracer::default();
+
+        // the client sent a ping at 1000 by its clock, which runs 500ms behind ours
+        let mut ping = Packet::client(client::Ping);
+        ping.trace = Some(Trace {
+            sent_ms: 1000,
+            echo: None,
+        });
+        tracer.received_at(&ping, 1520);
+        assert_eq!(tracer.one_way.stats("").unwrap().max_ms, 520);
+
+        let pong = tracer.stamp(Packet::server(server::Pong));
+        let echo = pong.trace.unwrap().echo.unwrap();
+        assert_eq!(echo, Echo {
+            id: ping.id,
+            sent_ms: 1000,
+            received_ms: 1520,
+        });
+
+        // echoed once
+        let next = tracer.stamp(Packet::server(server::Pong));
+        assert!(next.trace.unwrap().echo.is_none());
+
+        // the client held our packet for 30ms and the echo took 40ms in total
+        let mut answer = Packet::client(client::Ping);
+        answer.trace = Some(Trace {
+            sent_ms: 1050,
+            echo: Some(Echo {
+                id: pong.id,
+                sent_ms: 2000,
+                received_ms: 1020,
+            }),
+        });
+        tracer.received_at(&answer, 2040);
+        assert_eq!(tracer.round_trip.stats("").unwrap().max_ms, 10);
+    }
+}
@@ -0,0 +1,501 @@
+//! Canonical serialized packets, so other implementations of the protocol (web, editor) can check
+//! that they read and write exactly the bytes this crate does.
+//!
+//! The vectors of every [`PROTOCOL_VERSION`] are stored in `protocol/vectors/v{version}`, one
+//! `{name}.json` ([`Packet::to_json`]) and one `{name}.bin` ([`Packet::to_binary`]) per packet.
+//! The tests fail if a packet no longer serializes to its vector. After an intentional change,
+//! bump [`PROTOCOL_VERSION`] and run the tests with `UPDATE_VECTORS=1` to write the new vectors.
+
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::{
+    client::{self, Client},
+    server::{self, Server},
+    trace::{Echo, Trace},
+    FailureKind, LanguageStats, LatencyStats, MemoryEntry, Packet, Phase, QuestionItem, Resolution,
+    Risk, SessionSummary, StreamFrame, UsageStats, Validator, PROTOCOL_VERSION,
+};
+
+/// Where the vectors of [`PROTOCOL_VERSION`] are stored
+#[must_use]
+pub fn dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("vectors")
+        .join(format!("v{PROTOCOL_VERSION}"))
+}
+
+/// Packet ids are random, the vectors use fixed ones
+fn packet<T>(id: u128, data: impl Into<T>) -> Packet<T> {
+    Packet {
+        id: Uuid::from_u128(id),
+        data: data.into(),
+        trace: None,
+    }
+}
+
+fn traced<T>(packet: Packet<T>, sent_ms: u64, echo: Option<Echo>) -> Packet<T> {
+    Packet {
+        trace: Some(Trace { sent_ms, echo }),
+        ..packet
+    }
+}
+
+const SESSION: Uuid = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+const KEY: &str = "fedcba9876543210fedcba9876543210";
+
+/// One packet of every kind the frontend sends
+#[must_use]
+pub fn client() -> Vec<(&'static str, Packet<Client>)> {
+    vec![
+        (
+            "client_instruction",
+            packet(1, client::Instruction {
+                instruction: "Create a calculator".to_string(),
+            }),
+        ),
+        (
+            "client_answer",
+            packet(2, client::Answer {
+                answer: "Rust, with a CLI".to_string(),
+            }),
+        ),
+        ("client_regenerate", packet(3, client::Regenerate)),
+        ("client_cancel", packet(4, client::Cancel)),
+        ("client_execute", packet(5, client::Execute)),
+        ("client_ping", packet(6, client::Ping)),
+        (
+            "client_resume",
+            packet(7, client::Resume {
+                session: SESSION,
+                key: KEY.to_string(),
+                instruction: "Create a calculator".to_string(),
+                questions: vec!["Which language?".to_string()],
+                answers: vec!["Rust".to_string()],
+            }),
+        ),
+        ("client_list_memory", packet(8, client::ListMemory)),
+        (
+            "client_remember",
+            packet(9, client::Remember {
+                preference: "prefers tokio".to_string(),
+            }),
+        ),
+        ("client_forget", packet(10, client::Forget { id: 3 })),
+        (
+            "client_confirm",
+            packet(11, client::Confirm { approved: true }),
+        ),
+        (
+            "client_latency",
+            traced(packet(12, client::Latency), 1_700_000_000_000, None),
+        ),
+        (
+            "client_answer_to",
+            packet(13, client::AnswerTo {
+                id: 2,
+                answer: "8080".to_string(),
+            }),
+        ),
+        ("client_list_sessions", packet(14, client::ListSessions)),
+        (
+            "client_clone_repo",
+            packet(15, client::CloneRepo {
+                url: "https://github.com/getcollective-ai/collective.git".to_string(),
+                branch: Some("main".to_string()),
+            }),
+        ),
+        (
+            "client_resolve_conflict",
+            packet(16, client::ResolveConflict {
+                resolution: Resolution::Resolved,
+            }),
+        ),
+        (
+            "client_veto_source",
+            packet(17, client::VetoSource { id: 1 }),
+        ),
+        ("client_revert_last", packet(18, client::RevertLast)),
+        (
+            "client_execute_steps",
+            packet(19, client::ExecuteSteps {
+                steps: vec![1, 2, 3],
+            }),
+        ),
+        (
+            "client_set_workspace",
+            packet(20, client::SetWorkspace {
+                path: "/home/user/calculator".to_string(),
+            }),
+        ),
+    ]
+}
+
+/// One packet of every kind the executor sends
+#[must_use]
+pub fn server() -> Vec<(&'static str, Packet<Server>)> {
+    [
+        server_connection(),
+        server_execution(),
+        server_interview(),
+        server_steps(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Packets of the connection, the questions and the files
+fn server_connection() -> Vec<(&'static str, Packet<Server>)> {
+    vec![
+        (
+            "server_session_created",
+            packet(101, server::SessionCreated {
+                id: SESSION,
+                key: KEY.to_string(),
+            }),
+        ),
+        (
+            "server_question",
+            packet(102, server::Question {
+                frame: StreamFrame {
+                    id: 1,
+                    seq: 0,
+                    payload: "Which language?".to_string(),
+                    end: false,
+                },
+            }),
+        ),
+        (
+            "server_file_chunk",
+            packet(103, server::FileChunk {
+                path: "src/main.rs".to_string(),
+                seq: 0,
+                content: "fn main() {}\n".to_string(),
+            }),
+        ),
+        (
+            "server_file_written",
+            packet(104, server::FileWritten {
+                path: "src/main.rs".to_string(),
+                checksum: crate::checksum("fn main() {}\n"),
+            }),
+        ),
+        (
+            "server_file_failed",
+            packet(105, server::FileFailed {
+                path: "src/main.rs".to_string(),
+                reason: "permission denied".to_string(),
+            }),
+        ),
+        ("server_cancelled", packet(106, server::Cancelled)),
+        (
+            "server_rejected",
+            packet(107, server::Rejected {
+                reason: "a plan is already being executed".to_string(),
+            }),
+        ),
+        ("server_pong", packet(108, server::Pong)),
+    ]
+}
+
+/// Packets of the steps and the state of the executor
+fn server_execution() -> Vec<(&'static str, Packet<Server>)> {
+    vec![
+        (
+            "server_step_started",
+            packet(109, server::StepStarted {
+                index: 0,
+                title: "Create the project".to_string(),
+            }),
+        ),
+        (
+            "server_step_output",
+            packet(110, server::StepOutput {
+                index: 0,
+                output: "Created binary (application) `calculator` package".to_string(),
+            }),
+        ),
+        (
+            "server_step_diagnosed",
+            packet(111, server::StepDiagnosed {
+                index: 1,
+                kind: FailureKind::MissingDependency,
+                explanation: "serde is not a dependency".to_string(),
+                remediation: "cargo add serde".to_string(),
+            }),
+        ),
+        (
+            "server_step_finished",
+            packet(112, server::StepFinished {
+                index: 1,
+                success: false,
+            }),
+        ),
+        (
+            "server_execution_finished",
+            packet(113, server::ExecutionFinished { success: false }),
+        ),
+        (
+            "server_memory",
+            packet(114, server::Memory {
+                enabled: true,
+                entries: vec![MemoryEntry {
+                    id: 1,
+                    preference: "prefers tokio".to_string(),
+                }],
+            }),
+        ),
+        (
+            "server_notice",
+            packet(115, server::Notice {
+                message: "Reloaded prompts from prompts.json".to_string(),
+            }),
+        ),
+        (
+            "server_confirm_command",
+            packet(116, server::ConfirmCommand {
+                command: "rm -rf target".to_string(),
+                risk: Risk::Destructive,
+            }),
+        ),
+        (
+            "server_workspace_info",
+            packet(117, server::WorkspaceInfo {
+                languages: vec![
+                    LanguageStats {
+                        language: "Rust".to_string(),
+                        files: 12,
+                    },
+                    LanguageStats {
+                        language: "TOML".to_string(),
+                        files: 2,
+                    },
+                ],
+                frameworks: vec!["tokio".to_string()],
+                build_tools: vec!["cargo".to_string()],
+            }),
+        ),
+        (
+            "server_shutting_down",
+            packet(118, server::ShuttingDown { grace_secs: 30 }),
+        ),
+    ]
+}
+
+/// Packets of the latencies, the answers, the phases and the workspace of a session
+fn server_interview() -> Vec<(&'static str, Packet<Server>)> {
+    vec![
+        (
+            "server_latency",
+            traced(
+                packet(119, server::Latency {
+                    latencies: vec![LatencyStats {
+                        name: "provider → first token".to_string(),
+                        count: 3,
+                        p50_ms: 420,
+                        p90_ms: 610,
+                        p99_ms: 610,
+                        max_ms: 610,
+                    }],
+                }),
+                1_700_000_000_060,
+                Some(Echo {
+                    id: Uuid::from_u128(12),
+                    sent_ms: 1_700_000_000_000,
+                    received_ms: 1_700_000_000_020,
+                }),
+            ),
+        ),
+        (
+            "server_answer_format",
+            packet(120, server::AnswerFormat {
+                validator: Validator::Range { min: 1, max: 65535 },
+            }),
+        ),
+        (
+            "server_plan_chunk",
+            packet(121, server::PlanChunk {
+                content: "[{\"title\": \"Create the project\", ".to_string(),
+            }),
+        ),
+        (
+            "server_status",
+            packet(122, server::Status {
+                phase: Phase::Planning,
+            }),
+        ),
+        (
+            "server_error",
+            packet(123, server::Error {
+                message: "The model did not write a valid plan".to_string(),
+                recoverable: true,
+            }),
+        ),
+        (
+            "server_questions",
+            packet(124, server::Questions {
+                items: vec![
+                    QuestionItem {
+                        id: 1,
+                        question: "Which language should it be written in?".to_string(),
+                        validator: None,
+                    },
+                    QuestionItem {
+                        id: 2,
+                        question: "Which port should it listen on?".to_string(),
+                        validator: Some(Validator::Range { min: 1, max: 65535 }),
+                    },
+                ],
+            }),
+        ),
+        (
+            "server_sessions",
+            packet(125, server::Sessions {
+                sessions: vec![SessionSummary {
+                    id: SESSION,
+                    attached: false,
+                }],
+            }),
+        ),
+        (
+            "server_cloned",
+            packet(126, server::Cloned {
+                url: "https://github.com/getcollective-ai/collective.git".to_string(),
+                branch: "main".to_string(),
+                head: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            }),
+        ),
+        (
+            "server_file_conflict",
+            packet(127, server::FileConflict {
+                path: "src/main.rs".to_string(),
+                conflicts: 2,
+            }),
+        ),
+        (
+            "server_source_preview",
+            packet(128, server::SourcePreview {
+                id: 1,
+                title: "tokio".to_string(),
+                summary: "A runtime for writing reliable asynchronous applications with Rust."
+                    .to_string(),
+                url: "https://lib.rs/crates/tokio".to_string(),
+            }),
+        ),
+    ]
+}
+
+/// Packets of secret answers, follow-ups, reverts and what the steps checked and cost
+fn server_steps() -> Vec<(&'static str, Packet<Server>)> {
+    vec![
+        ("server_secret_answer", packet(129, server::SecretAnswer)),
+        (
+            "server_question_alternatives",
+            packet(130, server::QuestionAlternatives {
+                alternatives: vec!["Which database should it use?".to_string()],
+            }),
+        ),
+        ("server_follow_up", packet(131, server::FollowUp)),
+        (
+            "server_docs_fetched",
+            packet(132, server::DocsFetched {
+                name: "tokio".to_string(),
+                fetched: 1,
+                total: 3,
+                success: true,
+            }),
+        ),
+        (
+            "server_reverted",
+            packet(133, server::Reverted {
+                instruction: false,
+                summary: "restored src/main.rs and deleted src/lib.rs".to_string(),
+            }),
+        ),
+        (
+            "server_workspace_ready",
+            packet(134, server::WorkspaceReady {
+                path: "/home/user/calculator".to_string(),
+            }),
+        ),
+        (
+            "server_compile_checked",
+            packet(135, server::CompileChecked {
+                index: 2,
+                path: "src/main.rs".to_string(),
+                iteration: 1,
+                iterations: 3,
+                errors: 4,
+            }),
+        ),
+        (
+            "server_usage",
+            packet(136, server::Usage {
+                usage: vec![UsageStats {
+                    purpose: "step 3".to_string(),
+                    model: "gpt-4".to_string(),
+                    requests: 2,
+                    prompt_tokens: 9000,
+                    completion_tokens: 3000,
+                    micro_usd: 450_000,
+                }],
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::{client, dir, server};
+    use crate::Packet;
+
+    /// Compare `packets` with their vectors and round-trip the vectors, or write the vectors if
+    /// `UPDATE_VECTORS` is set.
+    fn check<T: Serialize + DeserializeOwned>(packets: Vec<(&str, Packet<T>)>) {
+        let dir = dir();
+        let update = std::env::var_os("UPDATE_VECTORS").is_some();
+
+        for (name, packet) in packets {
+            let json = packet.to_json().unwrap();
+            let binary = packet.to_binary().unwrap();
+
+            let json_path = dir.join(format!("{name}.json"));
+            let binary_path = dir.join(format!("{name}.bin"));
+
+            if update {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&json_path, &json).unwrap();
+                std::fs::write(&binary_path, &binary).unwrap();
+                continue;
+            }
+
+            let expected_json = std::fs::read_to_string(&json_path)
+                .unwrap_or_else(|e| panic!("{}: {e}", json_path.display()));
+            let expected_binary = std::fs::read(&binary_path)
+                .unwrap_or_else(|e| panic!("{}: {e}", binary_path.display()));
+
+            assert_eq!(json, expected_json, "{name}.json changed");
+            assert_eq!(binary, expected_binary, "{name}.bin changed");
+
+            let decoded = Packet::<T>::from_json(&expected_json).unwrap();
+            assert_eq!(decoded.to_json().unwrap(), expected_json, "{name}.json");
+
+            let decoded = Packet::<T>::from_binary(&expected_binary).unwrap();
+            assert_eq!(decoded.to_binary().unwrap(), expected_binary, "{name}.bin");
+        }
+    }
+
+    #[test]
+    fn test_client_vectors() {
+        check(client());
+    }
+
+    #[test]
+    fn test_server_vectors() {
+        check(server());
+    }
+}
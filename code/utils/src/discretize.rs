@@ -1,49 +1,381 @@
-use once_cell::sync::Lazy;
-use regex::Regex;
+/// Rough number of characters per token
+pub const CHARS_PER_TOKEN: usize = 4;
 
-pub fn string(input: &str) -> Vec<&str> {
-    let mut result = Vec::new();
+/// What the sizes of a [`Chunker`] count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unit {
+    #[default]
+    Chars,
+    /// estimated with [`CHARS_PER_TOKEN`]
+    Tokens,
+}
+
+/// Splits text into chunks of at most `max` characters or tokens.
+///
+/// Chunks end after a paragraph where possible, then after a sentence and then after a word, as
+/// long as that keeps them at least half as long as they could be. Only a word longer than `max`
+/// is cut. Without overlap the chunks are consecutive slices of the input, so together they are
+/// exactly the input. With overlap every chunk after the first starts with about the last
+/// `overlap` characters or tokens of the one before it, from the start of a word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    max: usize,
+    overlap: usize,
+    unit: Unit,
+}
+
+impl Chunker {
+    /// Chunks of at most `max` characters, at least 1
+    #[must_use]
+    pub const fn new(max: usize) -> Self {
+        Self {
+            max,
+            overlap: 0,
+            unit: Unit::Chars,
+        }
+    }
+
+    /// Repeat about the last `overlap` characters or tokens of a chunk at the start of the next,
+    /// at most half of `max`.
+    #[must_use]
+    pub const fn overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Count `max` and the overlap in tokens instead of characters.
+    #[must_use]
+    pub const fn tokens(mut self) -> Self {
+        self.unit = Unit::Tokens;
+        self
+    }
+
+    /// The chunks of `input`, none if it is empty
+    #[must_use]
+    pub fn split<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        let chars = |size: usize| match self.unit {
+            Unit::Chars => size,
+            Unit::Tokens => size * CHARS_PER_TOKEN,
+        };
+        let max = chars(self.max).max(1);
+        let overlap = chars(self.overlap).min(max / 2);
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < input.len() {
+            let rest = &input[start..];
+            let Some((limit, _)) = rest.char_indices().nth(max) else {
+                chunks.push(rest);
+                break;
+            };
+
+            let end = start + boundary(&rest[..limit]);
+            chunks.push(&input[start..end]);
+
+            start = match overlap {
+                0 => end,
+                overlap => overlapping(input, start, end, overlap),
+            };
+        }
+        chunks
+    }
+}
+
+/// Where to end a chunk of `window`, the longest it can be: after its last paragraph, sentence
+/// or word in the second half, else after its last word, else at its end
+fn boundary(window: &str) -> usize {
+    let half = window.len() / 2;
+    let after = |index: Option<usize>, len: usize| index.map(|index| index + len);
+
+    let paragraph = after(window.rfind("\n\n"), 2);
+    let sentence = [". ", "? ", "! ", ".\n", "?\n", "!\n"]
+        .iter()
+        .filter_map(|end| after(window.rfind(end), end.len()))
+        .max();
+    let word = window
+        .char_indices()
+        .rfind(|(_, c)| c.is_whitespace())
+        .map(|(index, c)| index + c.len_utf8());
+
+    [paragraph, sentence]
+        .into_iter()
+        .flatten()
+        .find(|&end| end > half)
+        .or(word)
+        .unwrap_or(window.len())
+}
+
+/// Where the chunk after `input[start..end]` starts to repeat about `overlap` characters of it:
+/// at the start of a word, but after `start` so every chunk makes progress
+fn overlapping(input: &str, start: usize, end: usize, overlap: usize) -> usize {
+    let chunk = &input[start..end];
+    let Some((back, _)) = chunk.char_indices().rev().nth(overlap - 1) else {
+        return end;
+    };
+    let word = chunk[back..]
+        .char_indices()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(index, c)| back + index + c.len_utf8())
+        .filter(|&word| word < chunk.len())
+        .unwrap_or(back);
+
+    match start + word {
+        next if next > start => next,
+        _ => end,
+    }
+}
+
+/// A paragraph or a fenced code block
+struct Block<'a> {
+    lines: Vec<&'a str>,
+    /// the fence of a code block, e.g. "```"
+    fence: Option<&'a str>,
+}
+
+/// Split markdown into chunks of about `max_tokens` tokens each.
+///
+/// Chunks end between paragraphs where possible, then between lines and then between words.
+/// Code blocks are only split if they alone exceed the budget, and then between lines with every
+/// part in its own fences, so each chunk is valid markdown. The same input is always split the
+/// same way.
+#[must_use]
+pub fn markdown(input: &str, max_tokens: usize) -> Vec<String> {
+    let max = (max_tokens * CHARS_PER_TOKEN).max(1);
+
+    let pieces = blocks(input)
+        .into_iter()
+        .flat_map(|block| match block.fence {
+            _ if block.lines.join("\n").len() <= max => vec![block.lines.join("\n")],
+            None => pack(lines(&block.lines, max), max),
+            Some(fence) => {
+                let open = block.lines[0];
+                let closed = block.lines.len() > 1
+                    && block.lines[block.lines.len() - 1]
+                        .trim_start()
+                        .starts_with(fence);
+                let inner = &block.lines[1..block.lines.len() - usize::from(closed)];
+
+                // the fences take room too
+                let budget = max.saturating_sub(open.len() + fence.len() + 2).max(1);
+                pack(lines(inner, budget), budget)
+                    .into_iter()
+                    .map(|code| format!("{open}\n{code}\n{fence}"))
+                    .collect()
+            }
+        });
+
+    pack(pieces.map(|piece| ("\n\n", piece)), max)
+}
+
+/// The paragraphs and code blocks of `input`, without the blank lines between them
+fn blocks(input: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut current = Block {
+        lines: Vec::new(),
+        fence: None,
+    };
+
+    for line in input.lines() {
+        let trimmed = line.trim_start();
+
+        match current.fence {
+            Some(fence) => {
+                current.lines.push(line);
+                if trimmed.starts_with(fence) && current.lines.len() > 1 {
+                    blocks.push(std::mem::replace(&mut current, Block {
+                        lines: Vec::new(),
+                        fence: None,
+                    }));
+                }
+            }
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                if !current.lines.is_empty() {
+                    blocks.push(current);
+                }
+                current = Block {
+                    lines: vec![line],
+                    fence: Some(&trimmed[..3]),
+                };
+            }
+            None if trimmed.is_empty() => {
+                if !current.lines.is_empty() {
+                    blocks.push(std::mem::replace(&mut current, Block {
+                        lines: Vec::new(),
+                        fence: None,
+                    }));
+                }
+            }
+            None => current.lines.push(line),
+        }
+    }
+
+    if !current.lines.is_empty() {
+        blocks.push(current);
+    }
 
-    static WORD: Lazy<Regex> = Lazy::new(|| Regex::new(r".{1,4000}\s?").unwrap());
+    blocks
+}
 
-    WORD.find_iter(input).for_each(|m| {
-        result.push(m.as_str());
-    });
+/// `line` in pieces of at most `max` bytes, split after whitespace where possible
+fn words(line: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
 
-    if let Some((idx, _)) = input.char_indices().nth(2000) {
-        let input = &input[idx..];
-        WORD.find_iter(input).for_each(|m| {
-            result.push(m.as_str());
+    while rest.len() > max {
+        // the longest prefix that fits and ends on a character boundary
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+
+        let split = rest[..end].rfind(char::is_whitespace).map_or(end, |i| {
+            i + rest[i..].chars().next().map_or(1, char::len_utf8)
         });
+        pieces.push(&rest[..split]);
+        rest = &rest[split..];
+    }
+
+    pieces.push(rest);
+    pieces
+}
+
+/// The pieces of `lines` that fit in `max` bytes, each with the separator before it
+fn lines<'a>(lines: &[&'a str], max: usize) -> Vec<(&'static str, &'a str)> {
+    lines
+        .iter()
+        .flat_map(|line| {
+            // the rest of a long line continues it
+            std::iter::once("\n")
+                .chain(std::iter::repeat(""))
+                .zip(words(line, max))
+        })
+        .collect()
+}
+
+/// Join `pieces` with the separators before them into chunks of at most `max` bytes, unless a
+/// piece alone is larger. The separator before the first piece of a chunk is dropped.
+fn pack<S: AsRef<str>>(
+    pieces: impl IntoIterator<Item = (&'static str, S)>,
+    max: usize,
+) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+
+    for (separator, piece) in pieces {
+        let piece = piece.as_ref();
+        if !chunk.is_empty() && chunk.len() + separator.len() + piece.len() > max {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if !chunk.is_empty() {
+            chunk.push_str(separator);
+        }
+        chunk.push_str(piece);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
     }
 
-    result
+    chunks
 }
 
 #[cfg(test)]
 mod tests {
-    use super::string;
+    use super::{markdown, Chunker, CHARS_PER_TOKEN};
 
     #[test]
-    fn test_discretize_simple() {
-        let input = "Hello, world!";
-        let res = string(input);
-        assert_eq!(vec!["Hello, world!"], res);
+    fn test_chunker() {
+        let chunker = Chunker::new(40);
+        assert_eq!(chunker.split("Hello, world!"), ["Hello, world!"]);
+        assert!(chunker.split("").is_empty());
+
+        // a paragraph, then a sentence, then a word
+        let text =
+            "The first paragraph.\n\nThe second paragraph has two sentences. Here is the other.";
+        assert_eq!(chunker.split(text), [
+            "The first paragraph.\n\n",
+            "The second paragraph has two sentences. ",
+            "Here is the other."
+        ]);
+        assert_eq!(Chunker::new(10).split("unbreakable words"), [
+            "unbreakabl",
+            "e words"
+        ]);
+
+        // nothing is duplicated or lost, whatever the size
+        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Donec auctor, nisl \
+                     eget ultricies lacinia, nisl nisl aliquet nisl, eget aliquet nunc. "
+            .repeat(60);
+        for max in [1, 7, 100, 4000] {
+            let chunks = Chunker::new(max).split(&lorem);
+            assert_eq!(chunks.concat(), lorem);
+            assert!(chunks.iter().all(|chunk| chunk.chars().count() <= max));
+        }
+        let chunks = Chunker::new(1000).tokens().split(&lorem);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.ends_with(". ")));
+
+        // characters, not bytes
+        assert_eq!(Chunker::new(2).split("äöü"), ["äö", "ü"]);
     }
 
     #[test]
-    fn test_discretize_paragraph() {
-        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Donec auctor, nisl \
-                     eget ultricies lacinia, nisl nisl aliquet nisl, eget aliquet nunc";
+    fn test_overlap() {
+        let text = "one two three four five six seven eight nine ten";
+        let chunks = Chunker::new(20).overlap(6).split(text);
+        assert_eq!(chunks, [
+            "one two three four ",
+            "four five six seven ",
+            "seven eight nine ten"
+        ]);
 
-        let base_len = lorem.chars().count();
+        // progress even with an overlap as long as the chunks
+        let chunks = Chunker::new(2).overlap(2).split("abcdef");
+        assert_eq!(chunks, ["ab", "bc", "cd", "de", "ef"]);
+    }
+
+    #[test]
+    fn test_markdown() {
+        let input = "# Title\n\nfirst paragraph\n\nsecond paragraph\nwith two lines";
+        assert_eq!(markdown(input, 100), [input]);
+
+        // 24 characters per chunk
+        let chunks = markdown(input, 24 / CHARS_PER_TOKEN);
+        assert_eq!(chunks, [
+            "# Title\n\nfirst paragraph",
+            "second paragraph",
+            "with two lines"
+        ]);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 24));
 
-        let lorem = std::iter::once(lorem).cycle();
+        let long = "word ".repeat(100);
+        let chunks = markdown(&long, 10);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 40));
+        assert_eq!(chunks.concat(), long);
+    }
+
+    #[test]
+    fn test_markdown_code() {
+        let code = "```rust\nfn main() {\n\n    println!(\"hi\");\n}\n```";
+        let input = format!("Some text before the code\n\n{code}\n\nafter");
 
-        let take = 8000 / base_len;
-        let lorem = lorem.take(take).collect::<Vec<_>>().join(" ");
+        // the blank line in the code block is not a paragraph break
+        let chunks = markdown(&input, 12);
+        assert_eq!(chunks, ["Some text before the code", code, "after"]);
 
-        let res = string(&lorem);
-        assert_eq!(res.len(), 4);
+        // a code block larger than the budget is split between lines, every part fenced
+        let code = format!("```rust\n{}```", "let x = 1;\n".repeat(20));
+        let chunks = markdown(&code, 10);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.len() <= 40, "{chunk}");
+            assert!(chunk.starts_with("```rust\n"));
+            assert!(chunk.ends_with("\n```"));
+        }
+        let lines: usize = chunks.iter().map(|chunk| chunk.lines().count() - 2).sum();
+        assert_eq!(lines, 20);
     }
 }
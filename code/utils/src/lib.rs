@@ -4,8 +4,12 @@ use tokio::task::JoinSet;
 use tokio_stream::wrappers::ReceiverStream;
 
 pub mod discretize;
+pub mod markdown;
+pub mod retry;
 pub mod str;
 
+pub use retry::{retry, with_timeout, Policy, TimedOut};
+
 // pub type SyncBoxStream<'a, T> = Pin<Box<dyn futures_util::Stream<Item = T> + Send + Sync + 'a>>;
 pub type Stream<T> = futures_util::stream::BoxStream<'static, T>;
 
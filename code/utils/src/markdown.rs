@@ -0,0 +1,177 @@
+//! Fenced code blocks in markdown, e.g. in the answers of a model.
+//!
+//! Fences follow CommonMark: a line of at least three backticks or tildes, indented by at most
+//! three spaces, optionally followed by the language. A block is closed by a line of the same
+//! character that is at least as long as its opening fence. Unlike in CommonMark, closing fences
+//! may be indented any amount, as models indent them inconsistently.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeBlock<'a> {
+    /// the first word after the opening fence, e.g. `rust`
+    pub language: Option<&'a str>,
+    /// the lines between the fences, without the final newline
+    pub code: &'a str,
+}
+
+/// An opening or closing fence
+pub struct Fence<'a> {
+    char: char,
+    len: usize,
+    /// what follows the fence on its line
+    info: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    /// The fence `line` is, `None` if it is not one.
+    #[must_use]
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            return None;
+        }
+
+        let line = &line[indent..];
+        let char = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+        let len = line.len() - line.trim_start_matches(char).len();
+        if len < 3 {
+            return None;
+        }
+
+        let info = line[len..].trim();
+        // backticks in the info string would make it inline code
+        if char == '`' && info.contains('`') {
+            return None;
+        }
+
+        Some(Self { char, len, info })
+    }
+
+    /// Whether `line` closes a block opened by this fence
+    #[must_use]
+    pub fn is_closed_by(&self, line: &str) -> bool {
+        Fence::parse(line.trim_start()).is_some_and(|close| {
+            close.char == self.char && close.len >= self.len && close.info.is_empty()
+        })
+    }
+
+    /// The first word after an opening fence, e.g. `rust`
+    #[must_use]
+    pub fn language(&self) -> Option<&'a str> {
+        self.info.split_whitespace().next()
+    }
+}
+
+/// The fenced code blocks of `text` in order. A block that is never closed ends with the text.
+#[must_use]
+pub fn code_blocks(text: &str) -> Vec<CodeBlock<'_>> {
+    let mut blocks = Vec::new();
+    // the opening fence and where the code after it starts
+    let mut open: Option<(Fence, usize)> = None;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        match &open {
+            Some((fence, code)) if fence.is_closed_by(line) => {
+                blocks.push(CodeBlock {
+                    language: fence.language(),
+                    code: trim_newline(&text[*code..start]),
+                });
+                open = None;
+            }
+            Some(_) => {}
+            None => open = Fence::parse(line).map(|fence| (fence, offset)),
+        }
+    }
+
+    if let Some((fence, code)) = open {
+        blocks.push(CodeBlock {
+            language: fence.language(),
+            code: trim_newline(&text[code.min(text.len())..]),
+        });
+    }
+
+    blocks
+}
+
+/// The content of `text` without the fences around it, if it is a single code block, `text`
+/// trimmed otherwise.
+///
+/// Only the outermost fences are removed, fences inside the block, e.g. in a string or a markdown
+/// file, are kept.
+#[must_use]
+pub fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+
+    let (first, rest) = text.split_once('\n').unwrap_or((text, ""));
+    let Some(fence) = Fence::parse(first) else {
+        return text;
+    };
+
+    let (inner, last) = rest.rsplit_once('\n').unwrap_or(("", rest));
+    if fence.is_closed_by(last) {
+        trim_newline(inner)
+    } else {
+        // not closed, e.g. a cut off answer
+        trim_newline(rest)
+    }
+}
+
+fn trim_newline(text: &str) -> &str {
+    text.strip_suffix('\n')
+        .map_or(text, |text| text.strip_suffix('\r').unwrap_or(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code_blocks, strip_fence, CodeBlock};
+
+    #[test]
+    fn test_code_blocks() {
+        let text = "Here you go:\n\n```rust\nfn main() {}\n```\n\nand the config:\n\n~~~~ toml \
+                    extra\n[package]\n```\nnot a fence for ~~~~\n~~~~\n\n```\nunclosed\n";
+
+        assert_eq!(code_blocks(text), [
+            CodeBlock {
+                language: Some("rust"),
+                code: "fn main() {}",
+            },
+            CodeBlock {
+                language: Some("toml"),
+                code: "[package]\n```\nnot a fence for ~~~~",
+            },
+            CodeBlock {
+                language: None,
+                code: "unclosed",
+            },
+        ]);
+
+        assert!(code_blocks("no code, only ``inline`` code").is_empty());
+        assert!(code_blocks("    ```\n    indented code\n    ```").is_empty());
+    }
+
+    #[test]
+    fn test_strip_fence() {
+        assert_eq!(strip_fence("```json\n[1, 2]\n```"), "[1, 2]");
+        assert_eq!(strip_fence("  [1, 2]\n"), "[1, 2]");
+        assert_eq!(strip_fence("```\n```"), "");
+        assert_eq!(strip_fence("```json\n[1,\n"), "[1,");
+
+        // only the outermost fences
+        let readme = "```markdown\n# Usage\n\n```sh\ncargo run\n```\n```";
+        assert_eq!(strip_fence(readme), "# Usage\n\n```sh\ncargo run\n```");
+
+        let code = "```rust\nlet fence = \"```\";\nlet end = \"```\";\n```";
+        assert_eq!(
+            strip_fence(code),
+            "let fence = \"```\";\nlet end = \"```\";"
+        );
+
+        // text around a block is kept
+        let answer = "Sure:\n```json\n[]\n```";
+        assert_eq!(strip_fence(answer), answer);
+    }
+}
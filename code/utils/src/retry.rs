@@ -0,0 +1,205 @@
+//! Retry fallible async operations with backoff, and bound how long they may take.
+//!
+//! [`retry`] runs an operation again while it fails with an error its [`Policy`] deems
+//! transient, waiting exponentially longer between the attempts. The delays are jittered so
+//! clients failing at the same time do not retry at the same time either.
+
+use std::{fmt, future::Future, time::Duration};
+
+use tracing::{debug, warn};
+
+/// How often and how patiently to retry
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// how often the operation is run at most, including the first time
+    attempts: u32,
+    /// the delay before the second attempt
+    initial: Duration,
+    /// the longest delay between two attempts
+    max: Duration,
+    /// the share of each delay that is random, from 0 to 1
+    jitter: f64,
+    /// whether an error may go away when retrying
+    transient: fn(&anyhow::Error) -> bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl Policy {
+    /// Run an operation up to `attempts` times, retrying any error after 250ms, doubling up to 8s
+    #[must_use]
+    pub const fn new(attempts: u32) -> Self {
+        Self {
+            attempts,
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(8),
+            jitter: 0.5,
+            transient: |_| true,
+        }
+    }
+
+    /// Wait `initial` before the second attempt, doubling for each further one up to `max`
+    #[must_use]
+    pub const fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial = initial;
+        self.max = max;
+        self
+    }
+
+    /// Randomize up to `jitter` of each delay, from 0 for fixed delays to 1
+    #[must_use]
+    pub const fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Only retry the errors `transient` returns true for
+    #[must_use]
+    pub const fn when(mut self, transient: fn(&anyhow::Error) -> bool) -> Self {
+        self.transient = transient;
+        self
+    }
+
+    /// The delay after the failed `attempt`, counting from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max);
+        let jitter = self.jitter.clamp(0.0, 1.0) * fastrand::f64();
+        exponential.mul_f64(1.0 - jitter)
+    }
+}
+
+/// Run `operation` until it succeeds, fails with an error that is not transient or `policy` runs
+/// out of attempts.
+///
+/// # Errors
+/// The error of the last attempt
+pub async fn retry<T, F, Fut>(policy: Policy, mut operation: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.attempts && (policy.transient)(&e) => {
+                let delay = policy.delay(attempt);
+                warn!(attempt, ?delay, "Retrying after {e:#}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                if attempt > 1 {
+                    debug!(attempt, "Giving up after {e:#}");
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// An operation did not complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    /// how long it was given
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out after {:?}", self.after)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Wait for `future` at most `duration`, dropping it if it takes longer.
+///
+/// # Errors
+/// If `future` did not complete within `duration`
+pub async fn with_timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, TimedOut> {
+    tokio::time::timeout(duration, future).await.map_err(|_| {
+        debug!(?duration, "Timed out");
+        TimedOut { after: duration }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+
+    use super::{retry, with_timeout, Policy, TimedOut};
+
+    #[test]
+    fn test_delay() {
+        let policy = Policy::new(10)
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .jitter(0.0);
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(9), Duration::from_secs(1));
+
+        let jittered = Policy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = Policy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let calls = AtomicU32::new(0);
+        let flaky = || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(anyhow::anyhow!("flaky")),
+                n => Ok(n),
+            }
+        };
+        assert_eq!(retry(policy, flaky).await.unwrap(), 2);
+
+        // out of attempts
+        calls.store(0, Ordering::SeqCst);
+        assert!(retry(
+            Policy {
+                attempts: 2,
+                ..policy
+            },
+            flaky
+        )
+        .await
+        .is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // not transient
+        calls.store(0, Ordering::SeqCst);
+        assert!(retry(policy.when(|_| false), flaky).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        assert_eq!(
+            with_timeout(Duration::from_secs(1), async { 1 }).await,
+            Ok(1)
+        );
+
+        let slow = tokio::time::sleep(Duration::from_secs(10));
+        assert_eq!(
+            with_timeout(Duration::from_millis(10), slow).await,
+            Err(TimedOut {
+                after: Duration::from_millis(10)
+            })
+        );
+    }
+}